@@ -15,7 +15,6 @@ use crate::action::Action;
 use crate::channels::{ActionChannel, EventChannel};
 use crate::event::Event;
 use crate::ui::ui_task;
-use core::fmt::Write;
 use cyw43::JoinOptions;
 use cyw43_pio::{PioSpi, DEFAULT_CLOCK_DIVIDER};
 use defmt::*;
@@ -32,6 +31,7 @@ use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::pubsub::PubSubChannel;
 use embassy_time::{Duration, Timer};
 use heapless::String;
+use mountain_mqtt::client_id::client_id_from_unique_id;
 use rand::RngCore;
 use static_cell::StaticCell;
 use {defmt_rtt as _, panic_probe as _};
@@ -51,7 +51,6 @@ const MQTT_HOST: &str = env!("MQTT_HOST");
 const MQTT_PORT: &str = env!("MQTT_PORT");
 
 static UID: StaticCell<String<64>> = StaticCell::new();
-static CHIP_ID: StaticCell<String<64>> = StaticCell::new();
 static EVENT_CHANNEL: StaticCell<EventChannel> = StaticCell::new();
 static ACTION_CHANNEL: StaticCell<ActionChannel> = StaticCell::new();
 
@@ -77,24 +76,7 @@ async fn main(spawner: Spawner) {
     let mut flash = embassy_rp::flash::Flash::<_, Async, FLASH_SIZE>::new(p.FLASH, p.DMA_CH1);
     let mut uid = [0; 8];
     flash.blocking_unique_id(&mut uid).unwrap();
-    let chip_id_handle = CHIP_ID.init(String::new());
-    let uid_handle = UID.init(String::new());
-
-    core::write!(
-        chip_id_handle,
-        "{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
-        uid[0],
-        uid[1],
-        uid[2],
-        uid[3],
-        uid[4],
-        uid[5],
-        uid[6],
-        uid[7]
-    )
-    .unwrap();
-
-    core::write!(uid_handle, "embassy-example-{}", chip_id_handle).unwrap();
+    let uid_handle = UID.init(client_id_from_unique_id("embassy-example-", &uid).unwrap());
 
     let mut rng = RoscRng;
 
@@ -38,10 +38,11 @@ impl<'a, const P: usize> SyncEventHandler<P> for QueueEventHandler<'a, P> {
                     warn!("Overflow in event channel, dropping event {:?}", e)
                 }
             }
-            mountain_mqtt::client::ClientReceivedEvent::Ack => info!("Received Ack"),
+            mountain_mqtt::client::ClientReceivedEvent::Ack(_) => info!("Received Ack"),
             mountain_mqtt::client::ClientReceivedEvent::SubscriptionGrantedBelowMaximumQos {
                 granted_qos,
                 maximum_qos,
+                ..
             } => warn!(
                 "SubscriptionGrantedBelowMaximumQos, requested {:?}, received {:?}",
                 maximum_qos, granted_qos
@@ -49,9 +50,25 @@ impl<'a, const P: usize> SyncEventHandler<P> for QueueEventHandler<'a, P> {
             mountain_mqtt::client::ClientReceivedEvent::PublishedMessageHadNoMatchingSubscribers => {
                 warn!("PublishedMessageHadNoMatchingSubscribers")
             }
+            mountain_mqtt::client::ClientReceivedEvent::SubscriptionsPartiallyRejected {
+                reason_codes,
+            } => warn!(
+                "SubscriptionsPartiallyRejected, reason codes {:?}",
+                reason_codes
+            ),
             mountain_mqtt::client::ClientReceivedEvent::NoSubscriptionExisted => {
                 warn!("NoSubscriptionExisted")
             }
+            mountain_mqtt::client::ClientReceivedEvent::UnsubscriptionsPartiallyRejected {
+                reason_codes,
+            } => warn!(
+                "UnsubscriptionsPartiallyRejected, reason codes {:?}",
+                reason_codes
+            ),
+            mountain_mqtt::client::ClientReceivedEvent::Reauthenticated => info!("Reauthenticated"),
+            mountain_mqtt::client::ClientReceivedEvent::Disconnected { reason_code, .. } => {
+                warn!("Disconnected by server, reason {:?}", reason_code)
+            }
         }
         Ok(())
     }
@@ -42,10 +42,12 @@ pub async fn send_action(client: &mut Client<'_>, action: &Action) -> Result<(),
 
 pub async fn receive_event(
     client: &mut Client<'_>,
-    packet_bin: PacketBin<1024>,
+    packet_bin: &PacketBin<1024>,
     event_pub: &mut EventPub,
 ) -> Result<(), ClientError> {
-    let event = client.process(&packet_bin).await?;
+    let Some(event) = client.process(packet_bin).await? else {
+        return Ok(());
+    };
     match event {
         mountain_mqtt::client::ClientReceivedEvent::ApplicationMessage(message) => {
             let event = Event::from_application_message(&message)?;
@@ -54,10 +56,11 @@ pub async fn receive_event(
                 warn!("Overflow in event channel, dropping event {:?}", e)
             }
         }
-        mountain_mqtt::client::ClientReceivedEvent::Ack => info!("Received Ack"),
+        mountain_mqtt::client::ClientReceivedEvent::Ack(_) => info!("Received Ack"),
         mountain_mqtt::client::ClientReceivedEvent::SubscriptionGrantedBelowMaximumQos {
             granted_qos,
             maximum_qos,
+            ..
         } => warn!(
             "SubscriptionGrantedBelowMaximumQos, requested {:?}, received {:?}",
             maximum_qos, granted_qos
@@ -65,9 +68,25 @@ pub async fn receive_event(
         mountain_mqtt::client::ClientReceivedEvent::PublishedMessageHadNoMatchingSubscribers => {
             warn!("PublishedMessageHadNoMatchingSubscribers")
         }
+        mountain_mqtt::client::ClientReceivedEvent::SubscriptionsPartiallyRejected {
+            reason_codes,
+        } => warn!(
+            "SubscriptionsPartiallyRejected, reason codes {:?}",
+            reason_codes
+        ),
         mountain_mqtt::client::ClientReceivedEvent::NoSubscriptionExisted => {
             warn!("NoSubscriptionExisted")
         }
+        mountain_mqtt::client::ClientReceivedEvent::UnsubscriptionsPartiallyRejected {
+            reason_codes,
+        } => warn!(
+            "UnsubscriptionsPartiallyRejected, reason codes {:?}",
+            reason_codes
+        ),
+        mountain_mqtt::client::ClientReceivedEvent::Reauthenticated => info!("Reauthenticated"),
+        mountain_mqtt::client::ClientReceivedEvent::Disconnected { reason_code, .. } => {
+            warn!("Disconnected by server, reason {:?}", reason_code)
+        }
     }
     Ok(())
 }
@@ -78,7 +97,7 @@ pub async fn wait_for_responses(
 ) -> Result<(), ClientError> {
     while client.waiting_for_responses() {
         let packet_bin = client.receive().await?;
-        receive_event(client, packet_bin, event_pub).await?;
+        receive_event(client, &packet_bin, event_pub).await?;
     }
     Ok(())
 }
@@ -119,7 +138,7 @@ pub async fn demo_poll_result(
             }
             Either::Second(packet_bin) => {
                 let packet_bin = packet_bin?;
-                receive_event(&mut client, packet_bin, event_pub).await?;
+                receive_event(&mut client, &packet_bin, event_pub).await?;
             }
         }
     }
@@ -3,11 +3,18 @@ use heapless::Vec;
 use mountain_mqtt::{
     client::{Client, ClientError, ClientReceivedEvent, ConnectionSettings, EventHandlerError},
     client_state::ClientState,
-    data::{property::PublishProperty, quality_of_service::QualityOfService},
-    packets::connect::Will,
+    data::{
+        property::{PublishProperty, SubscribeProperty},
+        quality_of_service::QualityOfService,
+        subscription_options::SubscriptionOptions,
+    },
+    packets::connect::{Connect, Will},
 };
 
-use crate::{packet_bin::PacketBin, poll_client::PollClient};
+use crate::{
+    packet_bin::PooledPacketBin,
+    poll_client::{Health, PollClient},
+};
 
 pub trait SyncEventHandler<const P: usize> {
     fn handle_event(&mut self, event: ClientReceivedEvent<P>) -> Result<(), EventHandlerError>;
@@ -20,26 +27,35 @@ pub trait SyncEventHandler<const P: usize> {
 /// perform async operations in response to events, consider using a channel
 /// to queue them, with [`embassy_sync::channel::Channel::try_send`] to
 /// send the events onwards.
-pub struct HandlerClient<'a, S, M, F, const N: usize, const P: usize>
-where
+pub struct HandlerClient<
+    'a,
+    S,
+    M,
+    F,
+    const N: usize,
+    const P: usize,
+    const RX: usize = 1,
+    const TX: usize = 1,
+> where
     M: RawMutex,
     S: ClientState,
     F: SyncEventHandler<P>,
 {
-    poll_client: PollClient<'a, S, M, N, P>,
+    poll_client: PollClient<'a, S, M, N, P, RX, TX>,
     handler: F,
     // The pending packet, if any. This is a packet that has been received,
     // but not yet processed.
-    pending_packet: Option<PacketBin<N>>,
+    pending_packet: Option<PooledPacketBin<'a, M, N>>,
 }
 
-impl<'a, S, M, F, const N: usize, const P: usize> HandlerClient<'a, S, M, F, N, P>
+impl<'a, S, M, F, const N: usize, const P: usize, const RX: usize, const TX: usize>
+    HandlerClient<'a, S, M, F, N, P, RX, TX>
 where
     M: RawMutex,
     S: ClientState,
     F: SyncEventHandler<P>,
 {
-    pub fn new(poll_client: PollClient<'a, S, M, N, P>, handler: F) -> Self {
+    pub fn new(poll_client: PollClient<'a, S, M, N, P, RX, TX>, handler: F) -> Self {
         Self {
             poll_client,
             handler,
@@ -48,10 +64,15 @@ where
     }
 
     /// Consume this [`HandlerClient`] and return the underlying [`PollClient`]
-    pub fn to_poll_client(self) -> PollClient<'a, S, M, N, P> {
+    pub fn to_poll_client(self) -> PollClient<'a, S, M, N, P, RX, TX> {
         self.poll_client
     }
 
+    /// See [`PollClient::health`]
+    pub fn health(&self) -> Health {
+        self.poll_client.health()
+    }
+
     /// Receive packets until all pending responses are received (e.g. acknowledgement of subscribe,
     /// unsubscribe, publish)
     /// Cancel-safe: Note that if this is cancelled, it shold be called again until successful,
@@ -99,10 +120,11 @@ where
             // Note we don't return any error immediately with "?" - we need to make sure we clear
             // the pending packet before exiting this method. We can't clear the pending packet
             // before calling handler since the event lifetime is linked to it.
-            let handler_result = self
-                .handler
-                .handle_event(event)
-                .map_err(ClientError::EventHandler);
+            let handler_result = event.map_or(Ok(()), |event| {
+                self.handler
+                    .handle_event(event)
+                    .map_err(ClientError::EventHandler)
+            });
 
             // Pending packet has been processed and passed to handler, we can clear it
             self.pending_packet = None;
@@ -143,10 +165,11 @@ where
         // Note we don't return any error immediately with "?" - we need to make sure we clear
         // the pending packet before exiting this method. We can't clear the pending packet
         // before calling handler since the event lifetime is linked to it.
-        let handler_result = self
-            .handler
-            .handle_event(event)
-            .map_err(ClientError::EventHandler);
+        let handler_result = event.map_or(Ok(()), |event| {
+            self.handler
+                .handle_event(event)
+                .map_err(ClientError::EventHandler)
+        });
 
         // Pending packet has been processed and passed to handler, we can clear it
         self.pending_packet = None;
@@ -173,7 +196,8 @@ where
     }
 }
 
-impl<'a, S, M, F, const N: usize, const P: usize> Client<'a> for HandlerClient<'a, S, M, F, N, P>
+impl<'a, S, M, F, const N: usize, const P: usize, const RX: usize, const TX: usize> Client<'a>
+    for HandlerClient<'a, S, M, F, N, P, RX, TX>
 where
     M: RawMutex,
     S: ClientState,
@@ -192,15 +216,39 @@ where
         Ok(())
     }
 
+    async fn connect_with_packet<const PP: usize, const W: usize>(
+        &mut self,
+        packet: Connect<'_, PP, W>,
+    ) -> Result<(), ClientError> {
+        self.poll_client.connect_with_packet(packet).await
+    }
+
     async fn disconnect(&mut self) -> Result<(), ClientError> {
         self.poll_client.disconnect().await
     }
 
+    async fn disconnect_with_session_expiry_interval(
+        &mut self,
+        session_expiry_interval: u32,
+    ) -> Result<(), ClientError> {
+        self.poll_client
+            .disconnect_with_session_expiry_interval(session_expiry_interval)
+            .await
+    }
+
     async fn send_ping(&mut self) -> Result<(), ClientError> {
         // This is a no-op since we already handle sending pings as required
         Ok(())
     }
 
+    fn pending_ping_count(&self) -> u32 {
+        self.poll_client.pending_ping_count()
+    }
+
+    fn keep_alive(&self) -> Option<u16> {
+        self.poll_client.keep_alive()
+    }
+
     async fn poll(&mut self, wait: bool) -> Result<bool, ClientError> {
         if wait {
             self.receive().await?;
@@ -226,6 +274,38 @@ where
         Ok(())
     }
 
+    /// Request a subscription with [`SubscriptionOptions`], e.g. to set
+    /// no local or retain handling.
+    /// This will then wait for any required response from the server.
+    /// NOT CANCEL-SAFE: As for [`HandlerClient::subscribe`]
+    async fn subscribe_with_options<'b>(
+        &'b mut self,
+        topic_name: &'b str,
+        options: SubscriptionOptions,
+    ) -> Result<(), ClientError> {
+        self.poll_client
+            .subscribe_with_options(topic_name, options)
+            .await?;
+        self.wait_for_responses().await?;
+        Ok(())
+    }
+
+    /// Request a subscription, with properties.
+    /// This will then wait for any required response from the server.
+    /// NOT CANCEL-SAFE: As for [`HandlerClient::subscribe`]
+    async fn subscribe_with_properties<'b, const PP: usize>(
+        &'b mut self,
+        topic_name: &'b str,
+        maximum_qos: QualityOfService,
+        properties: Vec<SubscribeProperty<'b>, PP>,
+    ) -> Result<(), ClientError> {
+        self.poll_client
+            .subscribe_with_properties(topic_name, maximum_qos, properties)
+            .await?;
+        self.wait_for_responses().await?;
+        Ok(())
+    }
+
     /// Request to unsubscribe from a topic
     /// This will then wait for any required response from the server.
     /// NOT CANCEL-SAFE: If cancelled, there may be pending responses from the server, and this may result
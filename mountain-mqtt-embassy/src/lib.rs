@@ -5,3 +5,4 @@ pub mod mqtt_manager;
 pub mod packet_bin;
 pub mod packet_bin_client;
 pub mod poll_client;
+pub mod time;
@@ -1,18 +1,25 @@
 use core::cell::RefCell;
 use embassy_net::{tcp::TcpSocket, Ipv4Address, Stack};
-use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::blocking_mutex::raw::{NoopRawMutex, RawMutex};
 use embassy_sync::channel::{Receiver, Sender};
+use embassy_sync::watch::Watch;
 use embassy_time::{Delay, Duration, Instant, Timer};
+use heapless::{String, Vec};
 use mountain_mqtt::client::{
-    Client, ClientError, ClientNoQueue, ClientReceivedEvent, ConnectionSettings, EventHandler,
-    EventHandlerError,
+    Client, ClientError, ClientNoQueue, ClientNoQueueOptions, ClientReceivedEvent,
+    ConnectionSettings, EventHandler, EventHandlerError,
 };
+use mountain_mqtt::client_state::{Acknowledgement, MAX_SUBSCRIPTION_REQUESTS};
+use mountain_mqtt::data::property::{MessageExpiryInterval, PublishProperty};
 use mountain_mqtt::data::quality_of_service::QualityOfService;
+use mountain_mqtt::data::reason_code::{SubscribeReasonCode, UnsubscribeReasonCode};
 use mountain_mqtt::embedded_hal_async::DelayEmbedded;
 use mountain_mqtt::embedded_io_async::ConnectionEmbedded;
 use mountain_mqtt::mqtt_manager::{ConnectionId, MqttOperations};
 use mountain_mqtt::packets::publish::ApplicationMessage;
 
+use crate::time::EmbassyTime;
+
 /// Convert an [ApplicationMessage] to an application-specific event type
 /// This is a specific trait rather than [TryFrom] so it can use a specific
 /// error type, and include the expected number of properties in the
@@ -47,6 +54,233 @@ impl defmt::Format for Error {
     }
 }
 
+/// A request to publish a message, holding owned copies of the topic name and payload,
+/// so that it can be queued on a channel and performed later by the manager.
+/// `T` is the maximum topic name length, and `L` is the maximum payload length.
+/// Requests are performed on whichever connection is current, and requests with a
+/// quality of service of 1 or more are retried after a reconnection, see
+/// [MqttOperations::perform].
+/// If the request has a message expiry interval, it is dropped rather than
+/// published if the interval has passed since it was created, e.g. while waiting
+/// to reconnect, and otherwise it is published with the remaining interval.
+#[derive(Debug, Clone)]
+pub struct PublishRequest<const T: usize, const L: usize> {
+    pub topic_name: String<T>,
+    pub payload: Vec<u8, L>,
+    pub qos: QualityOfService,
+    pub retain: bool,
+    /// The lifetime of the message in seconds, see [MessageExpiryInterval]
+    pub message_expiry_interval: Option<u32>,
+    /// The time the request was created, used to find the remaining message
+    /// expiry interval
+    pub created: Instant,
+}
+
+impl<const T: usize, const L: usize> PublishRequest<T, L> {
+    /// Create a new [PublishRequest], copying the topic name and payload.
+    /// Produces an error if either is too long for the request.
+    pub fn new(
+        topic_name: &str,
+        payload: &[u8],
+        qos: QualityOfService,
+        retain: bool,
+    ) -> Result<Self, PublisherError> {
+        let topic_name =
+            String::try_from(topic_name).map_err(|_| PublisherError::TopicNameTooLong)?;
+        let payload = Vec::from_slice(payload).map_err(|_| PublisherError::PayloadTooLong)?;
+        Ok(Self {
+            topic_name,
+            payload,
+            qos,
+            retain,
+            message_expiry_interval: None,
+            created: Instant::now(),
+        })
+    }
+
+    /// Set the lifetime of the message in seconds, from when the request was created
+    pub fn with_message_expiry_interval(mut self, message_expiry_interval: u32) -> Self {
+        self.message_expiry_interval = Some(message_expiry_interval);
+        self
+    }
+
+    /// The message expiry interval remaining at `now`, reduced by the whole
+    /// seconds since the request was created [MQTT-3.3.2-6], or None if the
+    /// request has no message expiry interval
+    pub fn remaining_expiry_interval(&self, now: Instant) -> Option<u32> {
+        let elapsed = now.saturating_duration_since(self.created).as_secs();
+        self.message_expiry_interval
+            .map(|interval| interval.saturating_sub(elapsed.min(u32::MAX as u64) as u32))
+    }
+
+    /// True if the request has a message expiry interval that has passed at `now`,
+    /// so the message should no longer be published
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.remaining_expiry_interval(now) == Some(0)
+    }
+
+    /// Perform the request as for [MqttOperations::perform], at time `now`
+    async fn perform_at<'a, C>(
+        &mut self,
+        client: &mut C,
+        is_retry: bool,
+        now: Instant,
+    ) -> Result<(), ClientError>
+    where
+        C: Client<'a>,
+    {
+        // Messages at qos 0 are "at most once", so are not repeated
+        if is_retry && self.qos == QualityOfService::Qos0 {
+            return Ok(());
+        }
+
+        if self.is_expired(now) {
+            #[cfg(feature = "defmt")]
+            defmt::debug!("Dropping expired message for {}", self.topic_name.as_str());
+            #[cfg(feature = "log")]
+            log::debug!("Dropping expired message for {}", self.topic_name.as_str());
+            return Ok(());
+        }
+
+        let mut properties: Vec<PublishProperty<'_>, 1> = Vec::new();
+        if let Some(remaining) = self.remaining_expiry_interval(now) {
+            properties
+                .push(PublishProperty::MessageExpiryInterval(
+                    MessageExpiryInterval::new(remaining),
+                ))
+                .unwrap();
+        }
+        client
+            .publish_with_properties(
+                &self.topic_name,
+                &self.payload,
+                self.qos,
+                self.retain,
+                properties,
+            )
+            .await
+    }
+}
+
+/// Requests are equal if they would publish the same message, regardless of
+/// when they were created
+impl<const T: usize, const L: usize> PartialEq for PublishRequest<T, L> {
+    fn eq(&self, other: &Self) -> bool {
+        self.topic_name == other.topic_name
+            && self.payload == other.payload
+            && self.qos == other.qos
+            && self.retain == other.retain
+            && self.message_expiry_interval == other.message_expiry_interval
+    }
+}
+
+impl<const T: usize, const L: usize> MqttOperations for PublishRequest<T, L> {
+    async fn perform<'a, 'b, C>(
+        &'b mut self,
+        client: &mut C,
+        _client_id: &'a str,
+        _connection_id: ConnectionId,
+        is_retry: bool,
+    ) -> Result<(), ClientError>
+    where
+        C: Client<'a>,
+    {
+        self.perform_at(client, is_retry, Instant::now()).await
+    }
+}
+
+/// Error produced when a [Publisher] cannot queue a [PublishRequest]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PublisherError {
+    /// The topic name is too long to fit in the [PublishRequest]
+    TopicNameTooLong,
+    /// The payload is too long to fit in the [PublishRequest]
+    PayloadTooLong,
+    /// The channel to the manager is full (only produced by [Publisher::try_publish])
+    ChannelFull,
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for PublisherError {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::TopicNameTooLong => defmt::write!(f, "TopicNameTooLong"),
+            Self::PayloadTooLong => defmt::write!(f, "PayloadTooLong"),
+            Self::ChannelFull => defmt::write!(f, "ChannelFull"),
+        }
+    }
+}
+
+/// A handle for queueing publishes to the manager. This is [Copy], so it can
+/// be given to any number of tasks, which can then publish messages without
+/// needing to route them through a single task.
+///
+/// The handle sends to the same channel as the `action_receiver` passed to [run],
+/// so the action type `A` must be convertible from a [PublishRequest] - this may
+/// just be [PublishRequest] itself, or an application-specific action type
+/// with a variant wrapping a [PublishRequest].
+pub struct Publisher<'a, A, const T: usize, const L: usize, const Q: usize> {
+    sender: Sender<'a, NoopRawMutex, A, Q>,
+}
+
+impl<A, const T: usize, const L: usize, const Q: usize> Clone for Publisher<'_, A, T, L, Q> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<A, const T: usize, const L: usize, const Q: usize> Copy for Publisher<'_, A, T, L, Q> {}
+
+impl<'a, A, const T: usize, const L: usize, const Q: usize> Publisher<'a, A, T, L, Q>
+where
+    A: From<PublishRequest<T, L>>,
+{
+    /// Create a [Publisher] sending to the manager's action channel
+    pub fn new(sender: Sender<'a, NoopRawMutex, A, Q>) -> Self {
+        Self { sender }
+    }
+
+    /// Queue a message to be published, waiting for space in the channel if needed
+    pub async fn publish(
+        &self,
+        topic_name: &str,
+        payload: &[u8],
+        qos: QualityOfService,
+        retain: bool,
+    ) -> Result<(), PublisherError> {
+        let request = PublishRequest::new(topic_name, payload, qos, retain)?;
+        self.sender.send(request.into()).await;
+        Ok(())
+    }
+
+    /// Queue a message to be published, producing [PublisherError::ChannelFull]
+    /// immediately if there is no space in the channel
+    pub fn try_publish(
+        &self,
+        topic_name: &str,
+        payload: &[u8],
+        qos: QualityOfService,
+        retain: bool,
+    ) -> Result<(), PublisherError> {
+        let request = PublishRequest::new(topic_name, payload, qos, retain)?;
+        self.try_publish_request(request)
+    }
+
+    /// Queue a [PublishRequest], e.g. with a message expiry interval, waiting for
+    /// space in the channel if needed
+    pub async fn publish_request(&self, request: PublishRequest<T, L>) {
+        self.sender.send(request.into()).await;
+    }
+
+    /// Queue a [PublishRequest], producing [PublisherError::ChannelFull]
+    /// immediately if there is no space in the channel
+    pub fn try_publish_request(&self, request: PublishRequest<T, L>) -> Result<(), PublisherError> {
+        self.sender
+            .try_send(request.into())
+            .map_err(|_| PublisherError::ChannelFull)
+    }
+}
+
 /// Settings for the manager, including the address and port of the server,
 /// and the various timeouts and intervals used to manage sending pings,
 /// monitoring whether connections are responsive, and when to report that
@@ -88,6 +322,12 @@ pub struct Settings {
     /// server and receive back a response.
     pub response_timeout: Duration,
 
+    /// The maximum time we will wait for a Connack after sending a Connect
+    /// packet to the server. This is separate from `response_timeout` since
+    /// a broker under load may take noticeably longer to accept a connection
+    /// than to acknowledge other packets.
+    pub connect_timeout: Duration,
+
     /// [Handler::on_connection_stable] after the connection has been
     /// maintained for this long. This can be used for example to check
     /// that any expected retained messages have been received, and if they
@@ -106,6 +346,7 @@ impl Settings {
             reconnection_delay: Duration::from_millis(2000),
             poll_interval: Duration::from_millis(10),
             response_timeout: Duration::from_millis(5000),
+            connect_timeout: Duration::from_millis(10000),
             stabilisation_interval: Duration::from_millis(5000),
         }
     }
@@ -161,6 +402,14 @@ pub enum MqttEvent<E> {
         maximum_qos: QualityOfService,
     },
 
+    /// A subscription request with multiple topic filters was acknowledged, but some
+    /// (not all) of the subscriptions were rejected. `reason_codes` has the reason code
+    /// for each subscription, in the order requested.
+    SubscriptionsPartiallyRejected {
+        connection_id: ConnectionId,
+        reason_codes: Vec<SubscribeReasonCode, MAX_SUBSCRIPTION_REQUESTS>,
+    },
+
     /// A published message was received at the server, but had no matching subscribers and
     /// so did not reach any receivers
     /// This may or may not require action depending on client requirements / expectations
@@ -175,6 +424,14 @@ pub enum MqttEvent<E> {
     /// an error, and the user of the client might try reconnecting to the server to set
     /// up subscriptions again.
     NoSubscriptionExisted { connection_id: ConnectionId },
+
+    /// An unsubscribe request with multiple topic filters was acknowledged, but some
+    /// (not all) of the unsubscriptions were rejected. `reason_codes` has the reason code
+    /// for each topic filter, in the order requested.
+    UnsubscriptionsPartiallyRejected {
+        connection_id: ConnectionId,
+        reason_codes: Vec<UnsubscribeReasonCode, MAX_SUBSCRIPTION_REQUESTS>,
+    },
 }
 
 #[cfg(feature = "defmt")]
@@ -204,6 +461,15 @@ impl<E> defmt::Format for MqttEvent<E> {
                 granted_qos,
                 maximum_qos
             ),
+            Self::SubscriptionsPartiallyRejected {
+                connection_id,
+                reason_codes,
+            } => defmt::write!(
+                f,
+                "SubscriptionsPartiallyRejected({}, {})",
+                connection_id,
+                reason_codes.as_slice()
+            ),
             Self::PublishedMessageHadNoMatchingSubscribers { connection_id } => {
                 defmt::write!(
                     f,
@@ -214,10 +480,49 @@ impl<E> defmt::Format for MqttEvent<E> {
             Self::NoSubscriptionExisted { connection_id } => {
                 defmt::write!(f, "NoSubscriptionExisted({})", connection_id)
             }
+            Self::UnsubscriptionsPartiallyRejected {
+                connection_id,
+                reason_codes,
+            } => defmt::write!(
+                f,
+                "UnsubscriptionsPartiallyRejected({}, {})",
+                connection_id,
+                reason_codes.as_slice()
+            ),
         }
     }
 }
 
+/// The status of the connection run by [run_with_status], published on a
+/// [Watch] so that tasks (e.g. showing the link state on an LED) can wait
+/// for changes rather than polling.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConnectionStatus {
+    /// Connecting the socket, and then the client, to the server
+    Connecting,
+
+    /// The server accepted the connection, `session_present` is from its Connack
+    Connected { session_present: bool },
+
+    /// A connection to the server ended with `reason`
+    Disconnected { reason: Error },
+
+    /// Waiting for [Settings::reconnection_delay] before connecting again,
+    /// after a connection ended or the socket failed to connect
+    Reconnecting,
+}
+
+/// Publish `s` on `status` if present
+fn send_status<M: RawMutex, const N: usize>(
+    status: Option<&Watch<M, ConnectionStatus, N>>,
+    s: ConnectionStatus,
+) {
+    if let Some(status) = status {
+        status.sender().send(s);
+    }
+}
+
 struct State<A> {
     /// The instant when the most recent connection event occurred, indicating
     /// the connection was live. Set when state is created
@@ -227,6 +532,9 @@ struct State<A> {
 
     /// A failed action that needs to be retried
     pub pending_action: Option<A>,
+
+    /// Whether the server had a session for us, from the Connack
+    session_present: bool,
 }
 
 impl<A> State<A> {
@@ -234,6 +542,7 @@ impl<A> State<A> {
         Self {
             last_connection_event: Instant::now(),
             pending_action: None,
+            session_present: false,
         }
     }
     fn record_connection_event(&mut self) {
@@ -268,12 +577,17 @@ where
                     })
                     .await;
             }
-            ClientReceivedEvent::Ack => {
-                self.state.borrow_mut().record_connection_event();
+            ClientReceivedEvent::Ack(acknowledgement) => {
+                let mut state = self.state.borrow_mut();
+                state.record_connection_event();
+                if let Acknowledgement::Connack { session_present } = acknowledgement {
+                    state.session_present = session_present;
+                }
             }
             ClientReceivedEvent::SubscriptionGrantedBelowMaximumQos {
                 granted_qos,
                 maximum_qos,
+                ..
             } => {
                 self.event_sender
                     .send(MqttEvent::SubscriptionGrantedBelowMaximumQos {
@@ -283,6 +597,14 @@ where
                     })
                     .await
             }
+            ClientReceivedEvent::SubscriptionsPartiallyRejected { reason_codes } => {
+                self.event_sender
+                    .send(MqttEvent::SubscriptionsPartiallyRejected {
+                        connection_id: self.connection_id,
+                        reason_codes,
+                    })
+                    .await
+            }
             ClientReceivedEvent::PublishedMessageHadNoMatchingSubscribers => {
                 self.event_sender
                     .send(MqttEvent::PublishedMessageHadNoMatchingSubscribers {
@@ -297,6 +619,19 @@ where
                     })
                     .await
             }
+            ClientReceivedEvent::UnsubscriptionsPartiallyRejected { reason_codes } => {
+                self.event_sender
+                    .send(MqttEvent::UnsubscriptionsPartiallyRejected {
+                        connection_id: self.connection_id,
+                        reason_codes,
+                    })
+                    .await
+            }
+            // The manager doesn't re-authenticate, so this is not expected
+            ClientReceivedEvent::Reauthenticated => {}
+            // The client will produce an error after this event, leading to
+            // an MqttEvent::Disconnected
+            ClientReceivedEvent::Disconnected { .. } => {}
         }
         Ok(())
     }
@@ -330,7 +665,8 @@ where
 }
 
 /// Handle messages until we encounter an error
-async fn handle_messages<'a, A, C, E, const Q: usize>(
+#[allow(clippy::too_many_arguments)]
+async fn handle_messages<'a, A, C, E, M, const Q: usize, const N: usize>(
     current_connection_id: ConnectionId,
     client: &mut C,
     state: &RefCell<State<A>>,
@@ -338,14 +674,19 @@ async fn handle_messages<'a, A, C, E, const Q: usize>(
     event_sender: &Sender<'static, NoopRawMutex, MqttEvent<E>, Q>,
     action_receiver: &mut Receiver<'static, NoopRawMutex, A, Q>,
     settings: &Settings,
+    status: Option<&Watch<M, ConnectionStatus, N>>,
 ) -> Result<(), Error>
 where
     C: Client<'a>,
     A: MqttOperations + Clone,
     E: Clone,
+    M: RawMutex,
 {
     client.connect(connection_settings).await?;
 
+    let session_present = state.borrow().session_present;
+    send_status(status, ConnectionStatus::Connected { session_present });
+
     event_sender
         .send(MqttEvent::Connected {
             connection_id: current_connection_id,
@@ -422,7 +763,9 @@ where
 
 /// Run MQTT activities using the provided stack and settings.
 /// This uses [Client], but via [embassy_sync::channel::Channel]s for use
-/// from embassy.
+/// from embassy. The client is a [ClientNoQueue], i.e. a
+/// [mountain_mqtt::managed_client::ManagedClient] using a [ConnectionEmbedded]
+/// over the socket, a [DelayEmbedded] and [EmbassyTime].
 ///
 /// It implements the following additional features compared to [Client]:
 ///
@@ -458,6 +801,7 @@ where
 /// 1. Set up your network stack, define [Settings] and [ConnectionSettings], and create [embassy_sync::channel::Channel]s for events and actions.
 /// 2. Make a new task to call this function, and start the task.
 /// 3. Start feeding actions to the action channel, and receiving events on the event channel.
+///    To publish messages from multiple tasks, give each task a [Publisher] for the action channel.
 ///
 /// In most cases, you will want to respond to an [MqttEvent::Connected] event
 /// by sending actions to subscribe to relevant topics (if any), including the
@@ -487,15 +831,51 @@ where
 /// module.
 ///
 pub async fn run<A, E, const P: usize, const B: usize, const Q: usize>(
+    stack: Stack<'static>,
+    connection_settings: ConnectionSettings<'static>,
+    settings: Settings,
+    event_sender: Sender<'static, NoopRawMutex, MqttEvent<E>, Q>,
+    action_receiver: Receiver<'static, NoopRawMutex, A, Q>,
+) -> !
+where
+    E: FromApplicationMessage<P> + Clone,
+    A: MqttOperations + Clone,
+{
+    run_with_status::<A, E, NoopRawMutex, P, B, Q, 1>(
+        stack,
+        connection_settings,
+        settings,
+        event_sender,
+        action_receiver,
+        None,
+    )
+    .await
+}
+
+/// As for [run], but if `status` is provided, each change in [ConnectionStatus]
+/// is published on it, so that other tasks can use a [Watch] receiver to wait
+/// for the connection to change state, e.g. to show it on an LED, without
+/// polling the client.
+pub async fn run_with_status<
+    A,
+    E,
+    M,
+    const P: usize,
+    const B: usize,
+    const Q: usize,
+    const N: usize,
+>(
     stack: Stack<'static>,
     connection_settings: ConnectionSettings<'static>,
     settings: Settings,
     event_sender: Sender<'static, NoopRawMutex, MqttEvent<E>, Q>,
     mut action_receiver: Receiver<'static, NoopRawMutex, A, Q>,
+    status: Option<&Watch<M, ConnectionStatus, N>>,
 ) -> !
 where
     E: FromApplicationMessage<P> + Clone,
     A: MqttOperations + Clone,
+    M: RawMutex,
 {
     let mut rx_buffer = [0; B];
     let mut tx_buffer = [0; B];
@@ -504,6 +884,8 @@ where
     let mut connection_index = 0u32;
 
     loop {
+        send_status(status, ConnectionStatus::Connecting);
+
         let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
 
         socket.set_timeout(None);
@@ -520,6 +902,7 @@ where
             #[cfg(feature = "log")]
             log::warn!("MQTT socket connect error, will retry: {:?}", e);
             // Wait a while to try reconnecting
+            send_status(status, ConnectionStatus::Reconnecting);
             Timer::after(settings.reconnection_delay).await;
             continue;
         }
@@ -531,7 +914,10 @@ where
 
         let connection = ConnectionEmbedded::new(socket);
         let delay = DelayEmbedded::new(Delay);
-        let timeout_millis = settings.response_timeout.as_millis() as u32;
+        let options = ClientNoQueueOptions::new(settings.response_timeout.as_millis() as u32)
+            .with_connect_timeout(settings.connect_timeout.as_millis() as u32)
+            .with_receive_timeout(settings.connection_event_max_interval.as_millis() as u32)
+            .with_ping_interval(settings.ping_interval.as_millis() as u32);
 
         let state: RefCell<State<A>> = RefCell::new(State::new());
 
@@ -548,7 +934,8 @@ where
             connection,
             &mut mqtt_buffer,
             delay,
-            timeout_millis,
+            EmbassyTime,
+            options,
             event_handler,
         );
 
@@ -560,6 +947,7 @@ where
             &event_sender,
             &mut action_receiver,
             &settings,
+            status,
         )
         .await
         {
@@ -567,6 +955,7 @@ where
             defmt::warn!("MQTT handle_messages errored: {:?}", error);
             #[cfg(feature = "log")]
             log::warn!("MQTT handle_messages errored: {:?}", error);
+            send_status(status, ConnectionStatus::Disconnected { reason: error });
             event_sender
                 .send(MqttEvent::Disconnected {
                     connection_id,
@@ -576,6 +965,231 @@ where
         }
 
         // Wait a while to try reconnecting
+        send_status(status, ConnectionStatus::Reconnecting);
         Timer::after(settings.reconnection_delay).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embassy_sync::channel::Channel;
+    use mountain_mqtt::data::property::{PropertiesExt, Property, SubscribeProperty};
+    use mountain_mqtt::data::subscription_options::SubscriptionOptions;
+    use mountain_mqtt::packets::connect::{Connect, Will};
+
+    use super::*;
+
+    /// A [Client] that records the topic name and message expiry interval of
+    /// each message published
+    #[derive(Default)]
+    struct PublishRecorder {
+        published: Vec<(String<16>, Option<u32>), 4>,
+    }
+
+    impl<'a> Client<'a> for PublishRecorder {
+        async fn connect(&mut self, _settings: &ConnectionSettings<'_>) -> Result<(), ClientError> {
+            unimplemented!()
+        }
+
+        async fn connect_with_will<const W: usize>(
+            &mut self,
+            _settings: &ConnectionSettings<'_>,
+            _will: Option<Will<'_, W>>,
+        ) -> Result<(), ClientError> {
+            unimplemented!()
+        }
+
+        async fn connect_with_packet<const PP: usize, const W: usize>(
+            &mut self,
+            _packet: Connect<'_, PP, W>,
+        ) -> Result<(), ClientError> {
+            unimplemented!()
+        }
+
+        async fn disconnect(&mut self) -> Result<(), ClientError> {
+            unimplemented!()
+        }
+
+        async fn disconnect_with_session_expiry_interval(
+            &mut self,
+            _session_expiry_interval: u32,
+        ) -> Result<(), ClientError> {
+            unimplemented!()
+        }
+
+        async fn send_ping(&mut self) -> Result<(), ClientError> {
+            unimplemented!()
+        }
+
+        fn pending_ping_count(&self) -> u32 {
+            0
+        }
+
+        fn keep_alive(&self) -> Option<u16> {
+            None
+        }
+
+        async fn poll(&mut self, _wait: bool) -> Result<bool, ClientError> {
+            unimplemented!()
+        }
+
+        async fn subscribe_with_options<'b>(
+            &'b mut self,
+            _topic_name: &'b str,
+            _options: SubscriptionOptions,
+        ) -> Result<(), ClientError> {
+            unimplemented!()
+        }
+
+        async fn subscribe_with_properties<'b, const P: usize>(
+            &'b mut self,
+            _topic_name: &'b str,
+            _maximum_qos: QualityOfService,
+            _properties: Vec<SubscribeProperty<'b>, P>,
+        ) -> Result<(), ClientError> {
+            unimplemented!()
+        }
+
+        async fn unsubscribe<'b>(&'b mut self, _topic_name: &'b str) -> Result<(), ClientError> {
+            unimplemented!()
+        }
+
+        async fn publish_with_properties<'b, const P: usize>(
+            &'b mut self,
+            topic_name: &'b str,
+            _payload: &'b [u8],
+            _qos: QualityOfService,
+            _retain: bool,
+            properties: Vec<PublishProperty<'b>, P>,
+        ) -> Result<(), ClientError> {
+            let expiry = properties
+                .get_property::<MessageExpiryInterval>()
+                .map(|p| p.value());
+            self.published
+                .push((topic_name.try_into().unwrap(), expiry))
+                .unwrap();
+            Ok(())
+        }
+    }
+
+    /// A request with a message expiry interval, created at 100 seconds
+    fn request(topic_name: &str, message_expiry_interval: u32) -> PublishRequest<16, 16> {
+        let mut request = PublishRequest::new(topic_name, b"p", QualityOfService::Qos1, false)
+            .unwrap()
+            .with_message_expiry_interval(message_expiry_interval);
+        request.created = Instant::from_secs(100);
+        request
+    }
+
+    #[test]
+    fn perform_drops_expired_message() {
+        let mut client = PublishRecorder::default();
+        let mut request = request("a", 10);
+
+        block_on(request.perform_at(&mut client, false, Instant::from_secs(110))).unwrap();
+        assert!(client.published.is_empty());
+    }
+
+    #[test]
+    fn perform_publishes_fresh_message_with_remaining_interval() {
+        let mut client = PublishRecorder::default();
+        let mut request = request("a", 10);
+
+        block_on(request.perform_at(&mut client, false, Instant::from_secs(100))).unwrap();
+        block_on(request.perform_at(&mut client, true, Instant::from_secs(103))).unwrap();
+        let a: String<16> = "a".try_into().unwrap();
+        assert_eq!(
+            client.published.as_slice(),
+            &[(a.clone(), Some(10)), (a, Some(7))]
+        );
+    }
+
+    #[test]
+    fn requests_are_equal_regardless_of_creation_time() {
+        let mut later = request("a", 10);
+        later.created = Instant::from_secs(200);
+        assert_eq!(request("a", 10), later);
+        assert_ne!(request("a", 10), request("a", 20));
+        assert_ne!(request("a", 10), request("b", 10));
+    }
+
+    #[test]
+    fn new_request_rejects_topic_name_or_payload_too_long() {
+        assert_eq!(
+            PublishRequest::<4, 4>::new("abcde", b"p", QualityOfService::Qos0, false),
+            Err(PublisherError::TopicNameTooLong)
+        );
+        assert_eq!(
+            PublishRequest::<4, 4>::new("a", b"abcde", QualityOfService::Qos0, false),
+            Err(PublisherError::PayloadTooLong)
+        );
+        assert!(
+            PublishRequest::<4, 4>::new("abcd", b"abcd", QualityOfService::Qos0, false).is_ok()
+        );
+    }
+
+    #[test]
+    fn publisher_rejects_message_too_long_without_queueing() {
+        let channel: Channel<NoopRawMutex, PublishRequest<4, 4>, 2> = Channel::new();
+        let publisher: Publisher<'_, _, 4, 4, 2> = Publisher::new(channel.sender());
+
+        assert_eq!(
+            block_on(publisher.publish("abcde", b"p", QualityOfService::Qos0, false)),
+            Err(PublisherError::TopicNameTooLong)
+        );
+        assert_eq!(
+            publisher.try_publish("a", b"abcde", QualityOfService::Qos0, false),
+            Err(PublisherError::PayloadTooLong)
+        );
+        assert!(channel.is_empty());
+    }
+
+    #[test]
+    fn publisher_fails_when_channel_is_full() {
+        let channel: Channel<NoopRawMutex, PublishRequest<16, 16>, 1> = Channel::new();
+        let publisher: Publisher<'_, _, 16, 16, 1> = Publisher::new(channel.sender());
+
+        publisher
+            .try_publish("a", b"p", QualityOfService::Qos0, false)
+            .unwrap();
+        assert_eq!(
+            publisher.try_publish("b", b"p", QualityOfService::Qos0, false),
+            Err(PublisherError::ChannelFull)
+        );
+    }
+
+    #[test]
+    fn publishes_from_publisher_are_performed_by_manager() {
+        let channel: Channel<NoopRawMutex, PublishRequest<16, 16>, 2> = Channel::new();
+        let publisher: Publisher<'_, _, 16, 16, 2> = Publisher::new(channel.sender());
+
+        // Copies of the handle all send to the manager's channel
+        let other = publisher;
+        block_on(publisher.publish("a", b"p", QualityOfService::Qos1, false)).unwrap();
+        let request = PublishRequest::new("b", b"p", QualityOfService::Qos1, false)
+            .unwrap()
+            .with_message_expiry_interval(10);
+        block_on(other.publish_request(request));
+
+        let mut client = PublishRecorder::default();
+        let state = RefCell::new(State::new());
+        let settings = ConnectionSettings::unauthenticated("client");
+        while let Ok(action) = channel.try_receive() {
+            block_on(try_action(
+                ConnectionId::new(0),
+                &mut client,
+                &state,
+                &settings,
+                action,
+                false,
+            ))
+            .unwrap();
+        }
+
+        let a: String<16> = "a".try_into().unwrap();
+        let b: String<16> = "b".try_into().unwrap();
+        assert_eq!(client.published.as_slice(), &[(a, None), (b, Some(10))]);
+        assert!(state.borrow().pending_action.is_none());
+    }
+}
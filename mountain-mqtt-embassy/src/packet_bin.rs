@@ -1,12 +1,32 @@
-use embedded_io_async::Read;
+use core::{
+    cell::Cell,
+    ops::{Deref, DerefMut},
+};
+
+use embassy_sync::{
+    blocking_mutex::{raw::RawMutex, Mutex as BlockingMutex},
+    channel::{Channel, DynamicSender},
+    mutex::{Mutex, MutexGuard},
+};
+use embedded_io_async::{Read, ReadExactError};
 use mountain_mqtt::{
     client::ClientError,
-    codec::mqtt_reader::{MqttBufReader, MqttReader},
+    codec::{
+        mqtt_reader::{MqttBufReader, MqttReader},
+        read::Read as MqttRead,
+    },
     data::packet_type::PacketType,
-    error::PacketReadError,
-    packets::packet_generic::PacketGeneric,
+    error::{PacketReadError, TransportErrorKind},
+    packets::{
+        auth::Auth, connack::Connack, disconnect::Disconnect, pingresp::Pingresp, puback::Puback,
+        pubcomp::Pubcomp, publish::Publish, pubrec::Pubrec, pubrel::Pubrel,
+        server_packet::ServerPacket, suback::Suback, unsuback::Unsuback,
+    },
 };
 
+#[cfg(feature = "server-codec")]
+use mountain_mqtt::packets::packet_generic::PacketGeneric;
+
 /// A binary packet, this is just a fixed maximum size buffer and a length
 /// for the data actually used within the buffer. Allows variable-sized packets
 /// to be stored as a sized struct.
@@ -39,6 +59,8 @@ impl<const N: usize> PacketBin<N> {
 
     /// Parse the message data as a [`PacketGeneric`]
     /// Produces a [`PacketReadError`] if the data is not a valid packet.
+    /// Requires the `server-codec` feature.
+    #[cfg(feature = "server-codec")]
     pub fn as_packet_generic<const P: usize, const W: usize, const S: usize>(
         &self,
     ) -> Result<PacketGeneric<'_, P, W, S>, PacketReadError> {
@@ -46,6 +68,101 @@ impl<const N: usize> PacketBin<N> {
         packet_reader.get()
     }
 
+    /// Parse the message data as a [`ServerPacket`], as received by a client.
+    /// Produces a [`PacketReadError`] if the data is not a valid packet, or is
+    /// a packet that only a server may receive.
+    pub fn as_server_packet<const P: usize, const S: usize>(
+        &self,
+    ) -> Result<ServerPacket<'_, P, S>, PacketReadError> {
+        let mut packet_reader = MqttBufReader::new(self.msg_data());
+        packet_reader.get()
+    }
+
+    /// The type of the packet, read from the first header byte without
+    /// decoding the rest of the packet. None if the buffer is empty or the
+    /// first header byte is not valid.
+    pub fn packet_type(&self) -> Option<PacketType> {
+        self.msg_data()
+            .first()
+            .and_then(|b| PacketType::from_first_header_byte(*b).ok())
+    }
+
+    /// Parse the message data as a `T`, if it has the given packet type.
+    /// None if the packet has a different type or is not valid.
+    fn as_packet_of_type<'a, T: MqttRead<'a>>(&'a self, packet_type: PacketType) -> Option<T> {
+        if self.packet_type() != Some(packet_type) {
+            return None;
+        }
+        let mut packet_reader = MqttBufReader::new(self.msg_data());
+        packet_reader.get().ok()
+    }
+
+    /// Parse the message data as a [`Connack`], if it is one.
+    /// None if the packet has a different type or is not valid.
+    pub fn as_connack<const P: usize>(&self) -> Option<Connack<'_, P>> {
+        self.as_packet_of_type(PacketType::Connack)
+    }
+
+    /// Parse the message data as a [`Publish`], if it is one.
+    /// None if the packet has a different type or is not valid.
+    pub fn as_publish<const P: usize>(&self) -> Option<Publish<'_, P>> {
+        self.as_packet_of_type(PacketType::Publish)
+    }
+
+    /// Parse the message data as a [`Puback`], if it is one.
+    /// None if the packet has a different type or is not valid.
+    pub fn as_puback<const P: usize>(&self) -> Option<Puback<'_, P>> {
+        self.as_packet_of_type(PacketType::Puback)
+    }
+
+    /// Parse the message data as a [`Pubrec`], if it is one.
+    /// None if the packet has a different type or is not valid.
+    pub fn as_pubrec<const P: usize>(&self) -> Option<Pubrec<'_, P>> {
+        self.as_packet_of_type(PacketType::Pubrec)
+    }
+
+    /// Parse the message data as a [`Pubrel`], if it is one.
+    /// None if the packet has a different type or is not valid.
+    pub fn as_pubrel<const P: usize>(&self) -> Option<Pubrel<'_, P>> {
+        self.as_packet_of_type(PacketType::Pubrel)
+    }
+
+    /// Parse the message data as a [`Pubcomp`], if it is one.
+    /// None if the packet has a different type or is not valid.
+    pub fn as_pubcomp<const P: usize>(&self) -> Option<Pubcomp<'_, P>> {
+        self.as_packet_of_type(PacketType::Pubcomp)
+    }
+
+    /// Parse the message data as a [`Suback`], if it is one.
+    /// None if the packet has a different type or is not valid.
+    pub fn as_suback<const P: usize, const S: usize>(&self) -> Option<Suback<'_, P, S>> {
+        self.as_packet_of_type(PacketType::Suback)
+    }
+
+    /// Parse the message data as an [`Unsuback`], if it is one.
+    /// None if the packet has a different type or is not valid.
+    pub fn as_unsuback<const P: usize, const S: usize>(&self) -> Option<Unsuback<'_, P, S>> {
+        self.as_packet_of_type(PacketType::Unsuback)
+    }
+
+    /// Parse the message data as a [`Pingresp`], if it is one.
+    /// None if the packet has a different type or is not valid.
+    pub fn as_pingresp(&self) -> Option<Pingresp> {
+        self.as_packet_of_type(PacketType::Pingresp)
+    }
+
+    /// Parse the message data as a [`Disconnect`], if it is one.
+    /// None if the packet has a different type or is not valid.
+    pub fn as_disconnect<const P: usize>(&self) -> Option<Disconnect<'_, P>> {
+        self.as_packet_of_type(PacketType::Disconnect)
+    }
+
+    /// Parse the message data as an [`Auth`], if it is one.
+    /// None if the packet has a different type or is not valid.
+    pub fn as_auth<const P: usize>(&self) -> Option<Auth<'_, P>> {
+        self.as_packet_of_type(PacketType::Auth)
+    }
+
     /// Create a new empty buffer (length 0)
     pub fn empty() -> Self {
         let buf = [0; N];
@@ -53,6 +170,177 @@ impl<const N: usize> PacketBin<N> {
     }
 }
 
+/// A pool of `B` reusable [`PacketBin`]s, with a queue of those holding received packets.
+/// Packets are read into a free [`PacketBin`] with [`PacketBinPool::read_from`], and
+/// then handed to the receiver as a [`PooledPacketBin`] by [`PacketBinPool::receive`],
+/// so the buffers themselves are never moved or copied, and only `B` buffers of
+/// size `N` are needed in total. Each [`PacketBin`] is returned to the pool when
+/// its [`PooledPacketBin`] is dropped.
+pub struct PacketBinPool<M, const N: usize, const B: usize>
+where
+    M: RawMutex,
+{
+    bins: [Mutex<M, PacketBin<N>>; B],
+    free: Channel<M, usize, B>,
+    received: Channel<M, usize, B>,
+    /// A received index that [`PacketBinPool::try_receive`] couldn't lock, which
+    /// is received before anything still in `received`
+    pending: BlockingMutex<M, Cell<Option<usize>>>,
+}
+
+impl<M, const N: usize, const B: usize> PacketBinPool<M, N, B>
+where
+    M: RawMutex,
+{
+    /// Create a new pool, with all [`PacketBin`]s empty and free
+    pub fn new() -> Self {
+        let free = Channel::new();
+        for index in 0..B {
+            // Channel has capacity for every index, so this can't fail
+            let _ = free.try_send(index);
+        }
+        Self {
+            bins: core::array::from_fn(|_| Mutex::new(PacketBin::empty())),
+            free,
+            received: Channel::new(),
+            pending: BlockingMutex::new(Cell::new(None)),
+        }
+    }
+
+    /// Only the holder of an index locks its bin, and the lock is released before
+    /// the index is passed on, so this completes immediately
+    async fn bin(&self, index: usize) -> PooledPacketBin<'_, M, N> {
+        PooledPacketBin {
+            bin: self.bins[index].lock().await,
+            slot: PoolSlot {
+                index,
+                free: self.free.dyn_sender(),
+            },
+        }
+    }
+
+    /// Take a free [`PacketBin`] from the pool, waiting until one is available.
+    /// The [`PacketBin`] may contain data from a previous packet.
+    pub async fn take(&self) -> PooledPacketBin<'_, M, N> {
+        let index = self.free.receive().await;
+        self.bin(index).await
+    }
+
+    /// Read a packet from a [`Read`] into a free [`PacketBin`], waiting until
+    /// one is available, and queue it to be received with [`PacketBinPool::receive`].
+    /// If this fails or is dropped, the [`PacketBin`] is returned to the pool.
+    pub async fn read_from<R: Read>(&self, read: &mut R) -> Result<(), ClientError> {
+        let mut packet_bin = self.take().await;
+        receive_packet_bin_into(read, &mut packet_bin).await?;
+        let index = packet_bin.into_index();
+        // Channel has capacity for every index, so this can't fail
+        let _ = self.received.try_send(index);
+        Ok(())
+    }
+
+    /// Receive the next packet read by [`PacketBinPool::read_from`]
+    /// Cancel-safe: This just receives from a [`Channel`]
+    pub async fn receive(&self) -> PooledPacketBin<'_, M, N> {
+        let index = match self.pending.lock(Cell::take) {
+            Some(index) => index,
+            None => self.received.receive().await,
+        };
+        self.bin(index).await
+    }
+
+    /// Receive the next packet read by [`PacketBinPool::read_from`], if one is ready
+    pub fn try_receive(&self) -> Option<PooledPacketBin<'_, M, N>> {
+        let index = match self.pending.lock(Cell::take) {
+            Some(index) => index,
+            None => self.received.try_receive().ok()?,
+        };
+        match self.bins[index].try_lock() {
+            Ok(bin) => Some(PooledPacketBin {
+                bin,
+                slot: PoolSlot {
+                    index,
+                    free: self.free.dyn_sender(),
+                },
+            }),
+            // The lock is released before an index is received, so this shouldn't
+            // happen, but if it does the packet is kept to be received next
+            Err(_) => {
+                self.pending.lock(|pending| pending.set(Some(index)));
+                None
+            }
+        }
+    }
+}
+
+impl<M, const N: usize, const B: usize> Default for PacketBinPool<M, N, B>
+where
+    M: RawMutex,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns an index to the free list of a [`PacketBinPool`] when dropped
+struct PoolSlot<'a> {
+    index: usize,
+    free: DynamicSender<'a, usize>,
+}
+
+impl Drop for PoolSlot<'_> {
+    fn drop(&mut self) {
+        // The free list has capacity for every index, so this can't fail
+        let _ = self.free.try_send(self.index);
+    }
+}
+
+/// A [`PacketBin`] from a [`PacketBinPool`], see [`PacketBinPool::receive`].
+/// This dereferences to the [`PacketBin`], so can be passed to e.g.
+/// [`PollClient::process`](crate::poll_client::PollClient::process) as `&packet_bin`.
+/// The [`PacketBin`] is returned to the pool when this is dropped.
+pub struct PooledPacketBin<'a, M, const N: usize>
+where
+    M: RawMutex,
+{
+    // Fields are dropped in order, so the bin is unlocked before the slot is freed
+    bin: MutexGuard<'a, M, PacketBin<N>>,
+    slot: PoolSlot<'a>,
+}
+
+impl<M, const N: usize> PooledPacketBin<'_, M, N>
+where
+    M: RawMutex,
+{
+    /// Unlock the [`PacketBin`] and produce its index, without returning it to the pool
+    fn into_index(self) -> usize {
+        let Self { bin, slot } = self;
+        drop(bin);
+        let index = slot.index;
+        core::mem::forget(slot);
+        index
+    }
+}
+
+impl<M, const N: usize> Deref for PooledPacketBin<'_, M, N>
+where
+    M: RawMutex,
+{
+    type Target = PacketBin<N>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.bin
+    }
+}
+
+impl<M, const N: usize> DerefMut for PooledPacketBin<'_, M, N>
+where
+    M: RawMutex,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.bin
+    }
+}
+
 /// Receive a packet from a [`Read`] and return as a [`PacketBin`]
 /// using MQTT packet encoding to find the size of the packet
 pub async fn receive_packet_bin<R, const N: usize>(
@@ -61,9 +349,34 @@ pub async fn receive_packet_bin<R, const N: usize>(
 where
     R: Read,
 {
-    let mut buf = [0; N];
-    let n = receive_packet_buf(read, &mut buf).await?;
-    Ok(PacketBin::new(&buf[0..n])?)
+    let mut packet_bin = PacketBin::empty();
+    receive_packet_bin_into(read, &mut packet_bin).await?;
+    Ok(packet_bin)
+}
+
+/// Receive a packet from a [`Read`] directly into an existing [`PacketBin`],
+/// using MQTT packet encoding to find the size of the packet.
+/// The data is read straight into the [`PacketBin`]'s buffer without an
+/// intermediate copy, so a single [`PacketBin`] can be reused for each packet.
+/// If this fails, the [`PacketBin`] is left empty (length 0).
+pub async fn receive_packet_bin_into<R, const N: usize>(
+    read: &mut R,
+    packet_bin: &mut PacketBin<N>,
+) -> Result<(), ClientError>
+where
+    R: Read,
+{
+    packet_bin.len = 0;
+    packet_bin.len = receive_packet_buf(read, &mut packet_bin.buf).await?;
+    Ok(())
+}
+
+/// Convert a failure to read from the connection to a [`ClientError::Transport`]
+fn read_exact_error<E: embedded_io_async::Error>(error: ReadExactError<E>) -> ClientError {
+    ClientError::Transport(match error {
+        ReadExactError::UnexpectedEof => TransportErrorKind::Closed,
+        ReadExactError::Other(e) => TransportErrorKind::from_embedded_io(&e),
+    })
 }
 
 /// Receive a packet from a [`Read`] to a binary buffer,
@@ -79,7 +392,7 @@ where
 
     read.read_exact(&mut buf[0..1])
         .await
-        .map_err(|_| ClientError::PacketRead(PacketReadError::ConnectionReceive))?;
+        .map_err(read_exact_error)?;
     position += 1;
 
     // Check first header byte is valid, if not we can error early without
@@ -93,7 +406,7 @@ where
     // First byte always exists
     read.read_exact(&mut buf[position..position + 1])
         .await
-        .map_err(|_| ClientError::PacketRead(PacketReadError::ConnectionReceive))?;
+        .map_err(read_exact_error)?;
     position += 1;
 
     // Read up to 3 more bytes looking for the end of the encoded length
@@ -103,7 +416,7 @@ where
         } else {
             read.read_exact(&mut buf[position..position + 1])
                 .await
-                .map_err(|_| ClientError::PacketRead(PacketReadError::ConnectionReceive))?;
+                .map_err(read_exact_error)?;
             position += 1;
         }
     }
@@ -131,8 +444,109 @@ where
     // Read the rest of the packet
     read.read_exact(&mut buf[position..position + remaining_length])
         .await
-        .map_err(|_| ClientError::PacketRead(PacketReadError::ConnectionReceive))?;
+        .map_err(read_exact_error)?;
     position += remaining_length;
 
     Ok(position)
 }
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+
+    const PINGRESP: [u8; 2] = [0xd0, 0x00];
+    const PUBLISH: [u8; 7] = [0x30, 0x05, 0x00, 0x01, b'a', 0x00, b'b'];
+
+    #[test]
+    fn typed_accessors_parse_packet_of_matching_type() {
+        let packet_bin = PacketBin::<16>::new(&PUBLISH).unwrap();
+        assert_eq!(packet_bin.packet_type(), Some(PacketType::Publish));
+
+        let publish: Publish<'_, 0> = packet_bin.as_publish().unwrap();
+        assert_eq!(publish.topic_name(), "a");
+        assert_eq!(publish.payload(), b"b");
+
+        let packet_bin = PacketBin::<16>::new(&PINGRESP).unwrap();
+        assert_eq!(packet_bin.packet_type(), Some(PacketType::Pingresp));
+        assert_eq!(packet_bin.as_pingresp(), Some(Pingresp::default()));
+    }
+
+    #[test]
+    fn typed_accessors_produce_none_for_other_packet_types() {
+        let packet_bin = PacketBin::<16>::new(&PUBLISH).unwrap();
+        assert!(packet_bin.as_connack::<0>().is_none());
+        assert!(packet_bin.as_puback::<0>().is_none());
+        assert!(packet_bin.as_pingresp().is_none());
+
+        // An empty bin, or an invalid first header byte, has no packet type
+        assert_eq!(PacketBin::<16>::empty().packet_type(), None);
+        let packet_bin = PacketBin::<16>::new(&[0x00, 0x00]).unwrap();
+        assert_eq!(packet_bin.packet_type(), None);
+        assert!(packet_bin.as_publish::<0>().is_none());
+    }
+
+    #[test]
+    fn pool_reuses_bins_once_dropped() {
+        let pool: PacketBinPool<NoopRawMutex, 16, 2> = PacketBinPool::new();
+        let data = [PINGRESP.as_slice(), &PUBLISH, &PINGRESP].concat();
+        let mut read = data.as_slice();
+
+        block_on(pool.read_from(&mut read)).unwrap();
+        block_on(pool.read_from(&mut read)).unwrap();
+        assert!(pool.free.is_empty());
+
+        let first = pool.try_receive().unwrap();
+        let second = pool.try_receive().unwrap();
+        assert!(pool.try_receive().is_none());
+        assert_eq!(first.msg_data(), PINGRESP);
+        assert_eq!(second.msg_data(), PUBLISH);
+
+        // Dropping a received packet frees its bin for the next packet
+        drop(first);
+        assert_eq!(pool.free.len(), 1);
+        block_on(pool.read_from(&mut read)).unwrap();
+        assert_eq!(pool.try_receive().unwrap().msg_data(), PINGRESP);
+        assert_eq!(pool.free.len(), 1);
+
+        drop(second);
+        assert_eq!(pool.free.len(), 2);
+    }
+
+    #[test]
+    fn try_receive_keeps_packet_if_bin_is_locked() {
+        let pool: PacketBinPool<NoopRawMutex, 16, 2> = PacketBinPool::new();
+        let data = [PUBLISH.as_slice(), &PINGRESP].concat();
+        let mut read = data.as_slice();
+        block_on(pool.read_from(&mut read)).unwrap();
+        block_on(pool.read_from(&mut read)).unwrap();
+
+        // Hold the lock on the first packet's bin, so it can't be received yet
+        let guard = pool.bins[0].try_lock().unwrap();
+        assert!(pool.try_receive().is_none());
+        assert!(pool.free.is_empty());
+        drop(guard);
+
+        // The packet is still received, and in order
+        assert_eq!(pool.try_receive().unwrap().msg_data(), PUBLISH);
+        assert_eq!(pool.try_receive().unwrap().msg_data(), PINGRESP);
+        assert!(pool.try_receive().is_none());
+    }
+
+    #[test]
+    fn pool_frees_bin_on_read_error() {
+        let pool: PacketBinPool<NoopRawMutex, 4, 1> = PacketBinPool::new();
+        let mut read = PUBLISH.as_slice();
+
+        assert_eq!(
+            block_on(pool.read_from(&mut read)),
+            Err(ClientError::PacketRead(
+                PacketReadError::PacketTooLargeForBuffer
+            ))
+        );
+        assert!(pool.try_receive().is_none());
+        assert_eq!(pool.free.len(), 1);
+    }
+}
@@ -1,6 +1,6 @@
 use embassy_sync::{
     blocking_mutex::raw::RawMutex,
-    channel::{Receiver, Sender},
+    channel::{Sender, TrySendError},
 };
 use embassy_time::{Duration, WithTimeout};
 use mountain_mqtt::{
@@ -9,45 +9,108 @@ use mountain_mqtt::{
         mqtt_writer::{MqttBufWriter, MqttWriter},
         write,
     },
-    error::PacketWriteError,
+    error::TransportErrorKind,
     packets::packet::Packet,
 };
 
-use crate::packet_bin::PacketBin;
+use crate::packet_bin::{PacketBin, PacketBinPool, PooledPacketBin};
 
-pub struct PacketBinClient<'a, M, const N: usize>
+/// The lane used to send a packet to the server. Packets sent with
+/// [`Priority::High`] are written to the network before any packets waiting
+/// with [`Priority::Normal`], but only if the client has a priority sender,
+/// see [`PacketBinClient::with_priority_sender`]. Otherwise all packets use
+/// the normal lane.
+/// Packets are never interrupted once writing has started, and packets in
+/// the same lane are always written in the order they were sent.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Priority {
+    #[default]
+    Normal,
+    High,
+}
+
+/// Sends [`PacketBin`]s via channels, and receives them from a [`PacketBinPool`].
+/// `RX` is the number of [`PacketBin`]s in the pool for packets received from the server, and
+/// `TX` is the depth of the channel (or channels) for packets to send to the server.
+pub struct PacketBinClient<'a, M, const N: usize, const RX: usize = 1, const TX: usize = 1>
 where
     M: RawMutex,
 {
-    sender: Sender<'a, M, PacketBin<N>, 1>,
-    receiver: Receiver<'a, M, PacketBin<N>, 1>,
+    sender: Sender<'a, M, PacketBin<N>, TX>,
+    priority_sender: Option<Sender<'a, M, PacketBin<N>, TX>>,
+    rx_pool: &'a PacketBinPool<M, N, RX>,
 }
 
-impl<'a, M, const N: usize> PacketBinClient<'a, M, N>
+impl<'a, M, const N: usize, const RX: usize, const TX: usize> PacketBinClient<'a, M, N, RX, TX>
 where
     M: RawMutex,
 {
     pub fn new(
-        sender: Sender<'a, M, PacketBin<N>, 1>,
-        receiver: Receiver<'a, M, PacketBin<N>, 1>,
+        sender: Sender<'a, M, PacketBin<N>, TX>,
+        rx_pool: &'a PacketBinPool<M, N, RX>,
+    ) -> Self {
+        Self {
+            sender,
+            priority_sender: None,
+            rx_pool,
+        }
+    }
+
+    /// Use a separate [`Sender`] for packets sent with [`Priority::High`],
+    /// whose receiver must be drained before the normal lane, as done by
+    /// [`run_mqtt_connection_with_priority_lane`](crate::poll_client::run_mqtt_connection_with_priority_lane)
+    pub fn with_priority_sender(
+        mut self,
+        priority_sender: Sender<'a, M, PacketBin<N>, TX>,
     ) -> Self {
-        Self { sender, receiver }
+        self.priority_sender = Some(priority_sender);
+        self
     }
 
     /// Send a packet
     /// Cancel-safe: This just sends to a [`Sender`]
     pub async fn send(&mut self, message: PacketBin<N>) {
-        self.sender.send(message).await
+        self.send_with_priority(message, Priority::Normal).await
+    }
+
+    /// Send a packet in the lane for `priority`
+    /// Cancel-safe: This just sends to a [`Sender`]
+    pub async fn send_with_priority(&mut self, message: PacketBin<N>, priority: Priority) {
+        match (priority, &self.priority_sender) {
+            (Priority::High, Some(priority_sender)) => priority_sender.send(message).await,
+            _ => self.sender.send(message).await,
+        }
+    }
+
+    /// Try to send a packet without waiting, failing with
+    /// [`ClientError::WouldBlock`] if the channel is full
+    pub fn try_send(&mut self, message: PacketBin<N>) -> Result<(), ClientError> {
+        self.try_send_with_priority(message, Priority::Normal)
+    }
+
+    /// Try to send a packet in the lane for `priority` without waiting,
+    /// failing with [`ClientError::WouldBlock`] if the channel is full
+    pub fn try_send_with_priority(
+        &mut self,
+        message: PacketBin<N>,
+        priority: Priority,
+    ) -> Result<(), ClientError> {
+        let result = match (priority, &self.priority_sender) {
+            (Priority::High, Some(priority_sender)) => priority_sender.try_send(message),
+            _ => self.sender.try_send(message),
+        };
+        result.map_err(|TrySendError::Full(_)| ClientError::WouldBlock)
     }
 
     /// Receive a packet
-    /// Cancel-safe: This just receives from a [`Receiver`]
-    pub async fn receive(&mut self) -> PacketBin<N> {
-        self.receiver.receive().await
+    /// Cancel-safe: This just receives from a [`PacketBinPool`]
+    pub async fn receive(&mut self) -> PooledPacketBin<'a, M, N> {
+        self.rx_pool.receive().await
     }
 
-    pub fn try_receive(&mut self) -> Option<PacketBin<N>> {
-        self.receiver.try_receive().ok()
+    pub fn try_receive(&mut self) -> Option<PooledPacketBin<'a, M, N>> {
+        self.rx_pool.try_receive()
     }
 
     /// Encode a [`Packet`] as [`PacketBin`], and send via [`Self::send`].
@@ -57,18 +120,51 @@ where
     where
         P: Packet + write::Write,
     {
-        let mut buf = [0; N];
-        let len = {
-            let mut r = MqttBufWriter::new(&mut buf);
-            r.put(packet)?;
-            r.position()
-        };
-        let packet = PacketBin { buf, len };
-        buf[0] = 1;
-        self.send(packet).await;
+        self.send_packet_with_priority(packet, Priority::Normal)
+            .await
+    }
+
+    /// Encode a [`Packet`] as [`PacketBin`], and send via [`Self::send_with_priority`].
+    /// Cancel-safe: As for [`Self::send_packet`]
+    pub async fn send_packet_with_priority<P>(
+        &mut self,
+        packet: &P,
+        priority: Priority,
+    ) -> Result<(), ClientError>
+    where
+        P: Packet + write::Write,
+    {
+        let packet = encode_packet(packet)?;
+        self.send_with_priority(packet, priority).await;
         Ok(())
     }
 
+    /// Encode a [`Packet`] as [`PacketBin`], and send via [`Self::try_send`].
+    /// This doesn't wait if the channel is full, failing with
+    /// [`ClientError::WouldBlock`] instead, so the caller can decide whether to
+    /// retry later or drop the packet, e.g. for QoS 0 telemetry.
+    pub fn try_send_packet<P>(&mut self, packet: &P) -> Result<(), ClientError>
+    where
+        P: Packet + write::Write,
+    {
+        self.try_send_packet_with_priority(packet, Priority::Normal)
+    }
+
+    /// Encode a [`Packet`] as [`PacketBin`], and send via [`Self::try_send_with_priority`].
+    /// As for [`Self::try_send_packet`], this fails with [`ClientError::WouldBlock`]
+    /// if the channel is full.
+    pub fn try_send_packet_with_priority<P>(
+        &mut self,
+        packet: &P,
+        priority: Priority,
+    ) -> Result<(), ClientError>
+    where
+        P: Packet + write::Write,
+    {
+        let packet = encode_packet(packet)?;
+        self.try_send_with_priority(packet, priority)
+    }
+
     pub async fn send_packet_timeout<PP>(
         &mut self,
         packet: &PP,
@@ -77,10 +173,23 @@ where
     where
         PP: Packet + write::Write,
     {
-        self.send_packet(packet)
+        self.send_packet_timeout_with_priority(packet, duration, Priority::Normal)
+            .await
+    }
+
+    pub async fn send_packet_timeout_with_priority<PP>(
+        &mut self,
+        packet: &PP,
+        duration: Duration,
+        priority: Priority,
+    ) -> Result<(), ClientError>
+    where
+        PP: Packet + write::Write,
+    {
+        self.send_packet_with_priority(packet, priority)
             .with_timeout(duration)
             .await
-            .map_err(|_| ClientError::PacketWrite(PacketWriteError::ConnectionSend))??;
+            .map_err(|_| ClientError::Transport(TransportErrorKind::TimedOut))??;
         Ok(())
     }
 
@@ -92,7 +201,83 @@ where
         self.send(message)
             .with_timeout(duration)
             .await
-            .map_err(|_| ClientError::PacketWrite(PacketWriteError::ConnectionSend))?;
+            .map_err(|_| ClientError::Transport(TransportErrorKind::TimedOut))?;
         Ok(())
     }
+
+    /// Request that all previously sent packets are flushed to the network.
+    /// This is sent as an empty [`PacketBin`], which the connection flushes
+    /// rather than writing, once all earlier packets have been written.
+    pub async fn flush_timeout(&mut self, duration: Duration) -> Result<(), ClientError> {
+        self.send_timeout(PacketBin::empty(), duration).await
+    }
+}
+
+/// Encode a [`Packet`] as a [`PacketBin`]
+fn encode_packet<P, const N: usize>(packet: &P) -> Result<PacketBin<N>, ClientError>
+where
+    P: Packet + write::Write,
+{
+    let mut buf = [0; N];
+    let len = {
+        let mut r = MqttBufWriter::new(&mut buf);
+        r.put(packet)?;
+        r.position()
+    };
+    Ok(PacketBin { buf, len })
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+    use mountain_mqtt::packets::pingreq::Pingreq;
+
+    use super::*;
+
+    const PINGREQ: [u8; 2] = [0xc0, 0x00];
+
+    #[test]
+    fn try_send_fails_with_would_block_when_channel_is_full() {
+        let channel: Channel<NoopRawMutex, PacketBin<16>, 1> = Channel::new();
+        let pool: PacketBinPool<NoopRawMutex, 16, 1> = PacketBinPool::new();
+        let mut client = PacketBinClient::new(channel.sender(), &pool);
+
+        client.try_send_packet(&Pingreq::default()).unwrap();
+        assert_eq!(
+            client.try_send_packet(&Pingreq::default()),
+            Err(ClientError::WouldBlock)
+        );
+        assert_eq!(
+            client.try_send(PacketBin::empty()),
+            Err(ClientError::WouldBlock)
+        );
+
+        // Only the first packet was sent, and once it is received there is space again
+        assert_eq!(channel.try_receive().unwrap().msg_data(), PINGREQ);
+        assert!(channel.try_receive().is_err());
+        client.try_send(PacketBin::empty()).unwrap();
+        assert_eq!(channel.try_receive().unwrap().len, 0);
+    }
+
+    #[test]
+    fn try_send_with_priority_uses_priority_lane() {
+        let channel: Channel<NoopRawMutex, PacketBin<16>, 1> = Channel::new();
+        let priority_channel: Channel<NoopRawMutex, PacketBin<16>, 1> = Channel::new();
+        let pool: PacketBinPool<NoopRawMutex, 16, 1> = PacketBinPool::new();
+        let mut client = PacketBinClient::new(channel.sender(), &pool)
+            .with_priority_sender(priority_channel.sender());
+
+        client.try_send_packet(&Pingreq::default()).unwrap();
+
+        // The normal lane is full, but the priority lane is not
+        client
+            .try_send_packet_with_priority(&Pingreq::default(), Priority::High)
+            .unwrap();
+        assert_eq!(
+            client.try_send_packet_with_priority(&Pingreq::default(), Priority::High),
+            Err(ClientError::WouldBlock)
+        );
+        assert_eq!(priority_channel.try_receive().unwrap().msg_data(), PINGREQ);
+        assert_eq!(channel.try_receive().unwrap().msg_data(), PINGREQ);
+    }
 }
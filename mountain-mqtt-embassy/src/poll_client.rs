@@ -2,46 +2,82 @@ use core::{future, net::Ipv4Addr};
 
 use crate::{
     handler_client::{HandlerClient, SyncEventHandler},
-    packet_bin::{self, PacketBin},
-    packet_bin_client::PacketBinClient,
+    packet_bin::{PacketBin, PacketBinPool, PooledPacketBin},
+    packet_bin_client::{PacketBinClient, Priority},
+    time::EmbassyTime,
 };
 #[cfg(feature = "defmt")]
 use defmt::{debug, trace, warn};
-use embassy_futures::select::{select3, Either3};
+use embassy_futures::select::{select, select3, Either, Either3};
 use embassy_net::{
+    dns::{self, DnsQueryType},
     tcp::{ConnectError, TcpSocket},
-    Stack,
+    IpAddress, IpEndpoint, Stack,
 };
 use embassy_sync::{
     blocking_mutex::raw::RawMutex,
-    channel::{Channel, Receiver, Sender},
+    channel::{Channel, Sender},
+    watch::Watch,
 };
 use embassy_time::{Duration, Instant, Timer};
 use embedded_io_async::Write;
 use heapless::Vec;
 use mountain_mqtt::{
     client::{ClientError, ClientReceivedEvent, ConnectionSettings},
-    client_state::{ClientState, ClientStateError, ClientStateReceiveEvent},
+    client_state::{
+        Acknowledgement, ClientState, ClientStateError, ClientStateReceiveEvent,
+        ClientStateSubscriptions, PendingRequest, MAX_SUBSCRIPTION_REQUESTS,
+    },
     data::{
         packet_type::PacketType,
-        property::{ConnectProperty, PublishProperty},
+        property::{PublishProperty, SubscribeProperty},
         quality_of_service::QualityOfService,
+        subscription_options::SubscriptionOptions,
     },
-    error::PacketWriteError,
+    error::TransportErrorKind,
+    managed_client::{self, EventFilter},
     packets::{
         connect::{Connect, Will},
         disconnect::Disconnect,
         packet::Packet,
-        packet_generic::PacketGeneric,
         pingreq::Pingreq,
+        server_packet::ServerPacket,
+        subscribe::SubscriptionRequest,
     },
+    timing::{Clock, ConnectionTimer, ConnectionTimerSettings, Timer as _},
+    watchdog::Watchdog,
 };
 
+/// A snapshot of the health of a [`PollClient`] connection, see [`PollClient::health`]
+pub use mountain_mqtt::timing::Health;
+
+/// The maximum number of additional subscription requests (after the first) we decode
+/// in received packets, allowing for subscribing to and unsubscribing from multiple topics
+const ADDITIONAL_REQUESTS: usize = MAX_SUBSCRIPTION_REQUESTS - 1;
+
+/// The host of an MQTT server
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Host {
+    /// An IP address - note that IPv6 addresses require the `ipv6` feature
+    Address(IpAddress),
+
+    /// A list of IP addresses, tried in order until a connection succeeds,
+    /// e.g. for a server with both IPv4 and IPv6 addresses.
+    Addresses(&'static [IpAddress]),
+
+    /// A hostname, resolved using the DNS resolver of the [`Stack`]
+    /// each time a connection is made, so that changes in the address
+    /// of the server are picked up on reconnection. Each resolved address
+    /// is tried in order. With the `ipv6` feature, IPv6 addresses are tried
+    /// if no IPv4 address can be connected.
+    Name(&'static str),
+}
+
 #[derive(Debug, Copy, Clone)]
 /// Settings for a [`PollClient`]
 pub struct Settings {
-    /// The address of the MQTT server
-    pub address: Ipv4Addr,
+    /// The host of the MQTT server
+    pub host: Host,
 
     /// The port of the MQTT server
     pub port: u16,
@@ -65,23 +101,180 @@ pub struct Settings {
 
     /// The timeout for sending a packet
     send_packet_timeout: Duration,
+
+    /// The delay before the first reconnection attempt by
+    /// [`run_mqtt_connection_forever`]. This doubles after each consecutive
+    /// failed connection, up to `reconnect_delay_max`.
+    reconnect_delay: Duration,
+
+    /// The maximum delay between reconnection attempts by [`run_mqtt_connection_forever`]
+    reconnect_delay_max: Duration,
+
+    /// If true, received application messages with a payload format indicator
+    /// showing UTF-8 data are checked for valid UTF-8, producing
+    /// [`ClientError::PayloadFormatInvalid`] if invalid
+    validate_payload_format: bool,
+
+    /// If true, pings are postponed while other packets are being sent,
+    /// see [`ConnectionTimer::with_adaptive_keep_alive`]
+    adaptive_keep_alive: bool,
+
+    /// If true, a subscription granted with a lower qos than requested produces
+    /// [`ClientReceivedEvent::Ack`], see [`managed_client::accept_downgraded_qos`]
+    accept_downgraded_qos: bool,
+
+    /// If true, redelivered qos 1 application messages are acknowledged but
+    /// not passed on, see [`EventFilter::message`]
+    detect_duplicates: bool,
+
+    /// If true, [`PollClient::subscribe`] sets the NoLocal option, see
+    /// [`managed_client::subscription_request`]
+    no_local: bool,
+
+    /// If true, received application messages that look like echoes of our
+    /// own publishes are acknowledged but not passed on, see [`EventFilter::message`]
+    suppress_local_echo: bool,
 }
 
 impl Settings {
+    /// Create [`Settings`] to connect to a server at an address
     pub fn new(address: Ipv4Addr, port: u16) -> Self {
+        Self::with_host(Host::Address(IpAddress::Ipv4(address)), port)
+    }
+
+    /// Create [`Settings`] to connect to a server at an [`IpEndpoint`], which
+    /// may be IPv4 or IPv6
+    pub fn with_endpoint(endpoint: IpEndpoint) -> Self {
+        Self::with_host(Host::Address(endpoint.addr), endpoint.port)
+    }
+
+    /// Create [`Settings`] to connect to a server by hostname, see [`Host::Name`]
+    pub fn with_hostname(hostname: &'static str, port: u16) -> Self {
+        Self::with_host(Host::Name(hostname), port)
+    }
+
+    /// Create [`Settings`] to connect to a server at a [`Host`]
+    pub fn with_host(host: Host, port: u16) -> Self {
         Self {
-            address,
+            host,
             port,
             receive_timeout: Duration::from_secs(10),
             ping_interval: Duration::from_secs(2),
             ping_retry_delay: Duration::from_millis(100),
             send_packet_timeout: Duration::from_secs(5),
+            reconnect_delay: Duration::from_secs(2),
+            reconnect_delay_max: Duration::from_secs(60),
+            validate_payload_format: false,
+            adaptive_keep_alive: false,
+            accept_downgraded_qos: false,
+            detect_duplicates: false,
+            no_local: false,
+            suppress_local_echo: false,
+        }
+    }
+
+    /// Set the initial and maximum delays between reconnection attempts,
+    /// see [`run_mqtt_connection_forever`]
+    pub fn with_reconnect_delay(mut self, initial: Duration, maximum: Duration) -> Self {
+        self.reconnect_delay = initial;
+        self.reconnect_delay_max = maximum;
+        self
+    }
+
+    /// The settings for the [`ConnectionTimer`] used by a [`PollClient`]
+    pub fn timer_settings(&self) -> ConnectionTimerSettings {
+        ConnectionTimerSettings {
+            receive_timeout_millis: self.receive_timeout.as_millis(),
+            ping_interval_millis: self.ping_interval.as_millis(),
+            ping_retry_delay_millis: self.ping_retry_delay.as_millis(),
         }
     }
+
+    /// Set whether received application messages with a payload format indicator
+    /// showing UTF-8 data are checked for valid UTF-8, producing
+    /// [`ClientError::PayloadFormatInvalid`] if invalid. Disabled by default.
+    pub fn with_payload_format_validation(mut self, validate_payload_format: bool) -> Self {
+        self.validate_payload_format = validate_payload_format;
+        self
+    }
+
+    /// Set whether pings are postponed while other packets are being sent, e.g.
+    /// to reduce radio wakeups when publishing regularly anyway, see
+    /// [`ConnectionTimer::with_adaptive_keep_alive`]. Disabled by default.
+    pub fn with_adaptive_keep_alive(mut self, adaptive_keep_alive: bool) -> Self {
+        self.adaptive_keep_alive = adaptive_keep_alive;
+        self
+    }
+
+    /// Set whether a subscription granted with a lower qos than requested is
+    /// treated as successful, producing [`ClientReceivedEvent::Ack`] rather than
+    /// [`ClientReceivedEvent::SubscriptionGrantedBelowMaximumQos`]. The granted
+    /// qos is still recorded in the client state's subscription registry.
+    /// Disabled by default.
+    pub fn with_accept_downgraded_qos(mut self, accept_downgraded_qos: bool) -> Self {
+        self.accept_downgraded_qos = accept_downgraded_qos;
+        self
+    }
+
+    /// Set whether redelivered qos 1 application messages are suppressed, i.e.
+    /// a message with the DUP flag set and the packet identifier of a recently
+    /// acknowledged message is acknowledged again, but [`PollClient::process`]
+    /// produces no event for it. Disabled by default.
+    pub fn with_duplicate_detection(mut self, detect_duplicates: bool) -> Self {
+        self.detect_duplicates = detect_duplicates;
+        self
+    }
+
+    /// Set whether subscriptions made by [`PollClient::subscribe`] and
+    /// [`PollClient::subscribe_with_properties`] have the NoLocal option set,
+    /// so the server doesn't send messages published by this client back to it.
+    /// NoLocal is never set for a shared subscription. Disabled by default.
+    pub fn with_no_local(mut self, no_local: bool) -> Self {
+        self.no_local = no_local;
+        self
+    }
+
+    /// Set whether received application messages that look like echoes of our
+    /// own publishes are suppressed, for servers that don't support NoLocal.
+    /// A received message with the same topic name and payload as a recent
+    /// message published by this client is acknowledged, but [`PollClient::process`]
+    /// produces no event for it. Note that an identical message published by
+    /// another client may be suppressed. Disabled by default.
+    pub fn with_local_echo_suppression(mut self, suppress_local_echo: bool) -> Self {
+        self.suppress_local_echo = suppress_local_echo;
+        self
+    }
+
+    /// The [`EventFilter`] used by a [`PollClient`] to handle received events
+    pub fn event_filter(&self) -> EventFilter {
+        EventFilter::default()
+            .with_payload_format_validation(self.validate_payload_format)
+            .with_accept_downgraded_qos(self.accept_downgraded_qos)
+            .with_duplicate_detection(self.detect_duplicates)
+            .with_no_local(self.no_local)
+            .with_local_echo_suppression(self.suppress_local_echo)
+    }
+}
+
+/// The status of a connection run by [`run_mqtt_connection_forever`]
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConnectionStatus {
+    /// Connecting the TCP socket to the server
+    Connecting,
+
+    /// The socket is connected, and the client function is running
+    Running,
+
+    /// The connection has ended, and we are waiting for `delay` before
+    /// reconnecting. `failures` is the number of consecutive connections that have failed.
+    Reconnecting { failures: u32, delay: Duration },
 }
 
 #[cfg_attr(feature = "log", derive(Debug))]
 pub enum MqttConnectionError {
+    /// The hostname of the server could not be resolved
+    DnsError(dns::Error),
     ConnectError(ConnectError),
     ClientError(ClientError),
     TcpWriteError(embassy_net::tcp::Error),
@@ -91,6 +284,7 @@ pub enum MqttConnectionError {
 impl defmt::Format for MqttConnectionError {
     fn format(&self, f: defmt::Formatter) {
         match self {
+            Self::DnsError(e) => defmt::write!(f, "DnsError({})", e),
             Self::ConnectError(e) => defmt::write!(f, "ConnectError({})", e),
             Self::ClientError(e) => defmt::write!(f, "ClientError({})", e),
             Self::TcpWriteError(e) => defmt::write!(f, "TcpWriteError({})", e),
@@ -104,16 +298,104 @@ impl From<ConnectError> for MqttConnectionError {
     }
 }
 
+impl From<dns::Error> for MqttConnectionError {
+    fn from(value: dns::Error) -> Self {
+        MqttConnectionError::DnsError(value)
+    }
+}
+
 impl From<ClientError> for MqttConnectionError {
     fn from(value: ClientError) -> Self {
         MqttConnectionError::ClientError(value)
     }
 }
 
-pub async fn run_mqtt_connection<S, M, const N: usize, const P: usize>(
+/// Try connecting the socket to each address in turn, returning when the first
+/// connection succeeds, or the error from the last address if all fail.
+async fn connect_to_any(
+    socket: &mut TcpSocket<'_>,
+    addresses: &[IpAddress],
+    port: u16,
+) -> Result<(), MqttConnectionError> {
+    let mut result = Err(MqttConnectionError::ConnectError(ConnectError::NoRoute));
+    for address in addresses {
+        #[cfg(feature = "defmt")]
+        debug!("MQTT socket connecting to {:?}:{}...", address, port);
+        result = socket.connect((*address, port)).await.map_err(|e| e.into());
+        if result.is_ok() {
+            break;
+        }
+        #[cfg(feature = "defmt")]
+        debug!("MQTT socket failed to connect to {:?}", address);
+        socket.abort();
+    }
+    result
+}
+
+/// Connect the socket to a [`Host`], using DNS if needed, and trying each
+/// address for the host in turn.
+async fn connect_to_host(
+    socket: &mut TcpSocket<'_>,
+    stack: Stack<'static>,
+    host: Host,
+    port: u16,
+) -> Result<(), MqttConnectionError> {
+    match host {
+        Host::Address(address) => connect_to_any(socket, &[address], port).await,
+        Host::Addresses(addresses) => connect_to_any(socket, addresses, port).await,
+        Host::Name(name) => {
+            #[cfg(feature = "defmt")]
+            debug!("MQTT resolving host {}...", name);
+
+            #[cfg(feature = "ipv6")]
+            let query_types = [DnsQueryType::A, DnsQueryType::Aaaa];
+            #[cfg(not(feature = "ipv6"))]
+            let query_types = [DnsQueryType::A];
+
+            let mut result = Err(MqttConnectionError::DnsError(dns::Error::Failed));
+            for query_type in query_types {
+                result = match stack.dns_query(name, query_type).await {
+                    Ok(addresses) if !addresses.is_empty() => {
+                        connect_to_any(socket, &addresses, port).await
+                    }
+                    Ok(_) => Err(MqttConnectionError::DnsError(dns::Error::Failed)),
+                    Err(e) => Err(e.into()),
+                };
+                if result.is_ok() {
+                    break;
+                }
+            }
+            result
+        }
+    }
+}
+
+/// Connect a TCP socket to the server in `settings`, and then run `client_function` with a
+/// [`PollClient`] using that socket, until either the function completes or the network
+/// connection fails.
+///
+/// - `N` is the size of the socket rx/tx buffers, and of each [`PacketBin`].
+///   To use socket buffers of a different size to the maximum packet size, see
+///   [`run_mqtt_connection_with_buffers`].
+/// - `P` is the maximum number of properties in received packets.
+/// - `RX` is the number of [`PacketBin`]s used for packets received from the server, see
+///   [`PacketBinPool`]. Each received packet holds one of these until it is dropped, including
+///   the packet being received from the socket, those queued for the [`PollClient`], and any
+///   the application is still holding. Increasing this allows the TCP reader to keep
+///   receiving while the client is busy, e.g. for high-throughput subscribers.
+/// - `TX` is the depth of the channel holding packets sent by the [`PollClient`] and not yet
+///   written to the socket.
+pub async fn run_mqtt_connection<
+    S,
+    M,
+    const N: usize,
+    const P: usize,
+    const RX: usize,
+    const TX: usize,
+>(
     settings: Settings,
     stack: Stack<'static>,
-    client_function: impl AsyncFnOnce(PollClient<S, M, N, P>) -> Result<(), ClientError>,
+    client_function: impl AsyncFnOnce(PollClient<S, M, N, P, RX, TX>) -> Result<(), ClientError>,
 ) -> Result<(), MqttConnectionError>
 where
     M: RawMutex,
@@ -121,54 +403,166 @@ where
 {
     let mut rx_buffer = [0; N];
     let mut tx_buffer = [0; N];
+    run_mqtt_connection_with_buffers(
+        settings,
+        stack,
+        &mut rx_buffer,
+        &mut tx_buffer,
+        client_function,
+    )
+    .await
+}
 
-    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+/// As for [`run_mqtt_connection`], but using the provided socket rx/tx buffers.
+/// `N` is then only the maximum MQTT packet size, i.e. the size of each [`PacketBin`],
+/// and is independent of the socket buffer sizes. This allows for example a large
+/// socket buffer for throughput, without every queued [`PacketBin`] being that large,
+/// or for the buffers to be placed in static memory.
+pub async fn run_mqtt_connection_with_buffers<
+    S,
+    M,
+    const N: usize,
+    const P: usize,
+    const RX: usize,
+    const TX: usize,
+>(
+    settings: Settings,
+    stack: Stack<'static>,
+    rx_buffer: &mut [u8],
+    tx_buffer: &mut [u8],
+    client_function: impl AsyncFnOnce(PollClient<S, M, N, P, RX, TX>) -> Result<(), ClientError>,
+) -> Result<(), MqttConnectionError>
+where
+    M: RawMutex,
+    S: ClientState + Default,
+{
+    run_mqtt_connection_with_lanes(settings, stack, rx_buffer, tx_buffer, None, client_function)
+        .await
+}
+
+/// As for [`run_mqtt_connection`], but with a second channel of depth `TX` for
+/// packets sent with [`Priority::High`], e.g. small control or heartbeat messages
+/// published with [`PollClient::publish_with_priority`]. Whenever the socket is
+/// ready for the next packet, any packet waiting in the priority lane is written
+/// before those in the normal lane, so these can overtake queued bulk telemetry
+/// on slow links.
+/// Note that this needs memory for an additional `TX` [`PacketBin`]s, and that
+/// messages in different lanes may reach the server in a different order to
+/// the order they were published.
+pub async fn run_mqtt_connection_with_priority_lane<
+    S,
+    M,
+    const N: usize,
+    const P: usize,
+    const RX: usize,
+    const TX: usize,
+>(
+    settings: Settings,
+    stack: Stack<'static>,
+    client_function: impl AsyncFnOnce(PollClient<S, M, N, P, RX, TX>) -> Result<(), ClientError>,
+) -> Result<(), MqttConnectionError>
+where
+    M: RawMutex,
+    S: ClientState + Default,
+{
+    let mut rx_buffer = [0; N];
+    let mut tx_buffer = [0; N];
+    let priority_tx_channel: Channel<M, PacketBin<N>, TX> = Channel::new();
+    run_mqtt_connection_with_lanes(
+        settings,
+        stack,
+        &mut rx_buffer,
+        &mut tx_buffer,
+        Some(&priority_tx_channel),
+        client_function,
+    )
+    .await
+}
+
+async fn run_mqtt_connection_with_lanes<
+    S,
+    M,
+    const N: usize,
+    const P: usize,
+    const RX: usize,
+    const TX: usize,
+>(
+    settings: Settings,
+    stack: Stack<'static>,
+    rx_buffer: &mut [u8],
+    tx_buffer: &mut [u8],
+    priority_tx_channel: Option<&Channel<M, PacketBin<N>, TX>>,
+    client_function: impl AsyncFnOnce(PollClient<S, M, N, P, RX, TX>) -> Result<(), ClientError>,
+) -> Result<(), MqttConnectionError>
+where
+    M: RawMutex,
+    S: ClientState + Default,
+{
+    let mut socket = TcpSocket::new(stack, rx_buffer, tx_buffer);
 
     socket.set_timeout(None);
 
-    let remote_endpoint = (settings.address, settings.port);
-    #[cfg(feature = "defmt")]
-    debug!("MQTT socket connecting to {:?}...", remote_endpoint);
-    socket.connect(remote_endpoint).await?;
+    connect_to_host(&mut socket, stack, settings.host, settings.port).await?;
     #[cfg(feature = "defmt")]
     debug!("MQTT socket connected!");
 
-    let rx_channel: Channel<M, PacketBin<N>, 1> = Channel::new();
-    let rx_channel_sender = rx_channel.sender();
+    // Received packets are read straight into the pool, and handed to the client
+    // from there, so they are never copied
+    let rx_pool: PacketBinPool<M, N, RX> = PacketBinPool::new();
 
-    let tx_channel: Channel<M, PacketBin<N>, 1> = Channel::new();
+    let tx_channel: Channel<M, PacketBin<N>, TX> = Channel::new();
     let tx_channel_receiver = tx_channel.receiver();
 
     let (mut rx, mut tx) = socket.split();
 
     let rx_fut = async {
         loop {
-            match packet_bin::receive_packet_bin(&mut rx).await {
-                Ok(packet_bin) => rx_channel_sender.send(packet_bin).await,
-                Err(e) => return e,
+            if let Err(e) = rx_pool.read_from(&mut rx).await {
+                return e;
             }
         }
     };
 
     let tx_fut = async {
         loop {
-            let write = tx_channel_receiver.receive().await;
-            // Ignore packets with length 0 - we can use these as a way to flush
-            // the buffer.
-            if write.len > 0 {
-                if let Err(e) = tx.write_all(write.msg_data()).await {
-                    return e;
+            // Packets waiting in the priority lane are written first
+            // Only mutated to wipe credentials
+            #[cfg_attr(not(feature = "zeroize"), allow(unused_mut))]
+            let mut write = match priority_tx_channel {
+                Some(priority_tx_channel) => {
+                    match select(priority_tx_channel.receive(), tx_channel_receiver.receive()).await
+                    {
+                        Either::First(write) | Either::Second(write) => write,
+                    }
                 }
+                None => tx_channel_receiver.receive().await,
+            };
+            // Packets with length 0 are used to request a flush, see
+            // [`PacketBinClient::flush_timeout`]
+            let result = if write.len > 0 {
+                tx.write_all(write.msg_data()).await
+            } else {
+                tx.flush().await
+            };
+            // Connect and Auth packets may contain a password or authentication data,
+            // so wipe the bin after writing them, whether or not this succeeds
+            #[cfg(feature = "zeroize")]
+            if matches!(
+                write.packet_type(),
+                Some(PacketType::Connect | PacketType::Auth)
+            ) {
+                zeroize::Zeroize::zeroize(&mut write.buf);
+            }
+            if let Err(e) = result {
+                return e;
             }
         }
     };
 
-    let client = PollClient::new(
-        tx_channel.sender(),
-        rx_channel.receiver(),
-        settings,
-        S::default(),
-    );
+    let mut client = PollClient::new(tx_channel.sender(), &rx_pool, settings, S::default());
+    if let Some(priority_tx_channel) = priority_tx_channel {
+        client = client.with_priority_sender(priority_tx_channel.sender());
+    }
 
     match select3(rx_fut, tx_fut, client_function(client)).await {
         Either3::First(e) => {
@@ -179,8 +573,8 @@ where
         Either3::Second(e) => {
             #[cfg(feature = "defmt")]
             warn!("Finished network comms with write error {:?}", e);
-            Err(MqttConnectionError::ClientError(ClientError::PacketWrite(
-                PacketWriteError::ConnectionSend,
+            Err(MqttConnectionError::ClientError(ClientError::Transport(
+                TransportErrorKind::from_embedded_io(&e),
             )))
         }
         Either3::Third(r) => {
@@ -192,10 +586,129 @@ where
     }
 }
 
+/// Run an MQTT connection with [`run_mqtt_connection`], and whenever the connection ends,
+/// wait for a backoff delay and then reconnect, calling `client_function` again with a fresh
+/// [`PollClient`]. This never returns - `client_function` should return `Ok(())` if it
+/// wishes to finish a connection cleanly, and this will also lead to a reconnection.
+///
+/// The delay before reconnecting starts at the reconnect delay in [`Settings`], and doubles
+/// for each consecutive failure up to the maximum delay. A connection that finishes cleanly,
+/// or stays up for at least the maximum delay, resets the backoff.
+///
+/// If `status` is provided, each [`ConnectionStatus`] transition is published on it, so that
+/// other tasks (e.g. UI) can use a [`Watch`] receiver to follow the state of the connection,
+/// as for [`crate::mqtt_manager::run_with_status`].
+pub async fn run_mqtt_connection_forever<
+    S,
+    M,
+    const N: usize,
+    const P: usize,
+    const RX: usize,
+    const TX: usize,
+    const W: usize,
+>(
+    settings: Settings,
+    stack: Stack<'static>,
+    status: Option<&Watch<M, ConnectionStatus, W>>,
+    mut client_function: impl AsyncFnMut(PollClient<S, M, N, P, RX, TX>) -> Result<(), ClientError>,
+) -> !
+where
+    M: RawMutex,
+    S: ClientState + Default,
+{
+    let send_status = |s: ConnectionStatus| {
+        if let Some(status) = status {
+            status.sender().send(s);
+        }
+    };
+
+    let mut backoff = Backoff::new(&settings);
+
+    loop {
+        send_status(ConnectionStatus::Connecting);
+
+        let mut running_since = None;
+        let result = run_mqtt_connection(settings, stack, async |client| {
+            running_since = Some(Instant::now());
+            send_status(ConnectionStatus::Running);
+            client_function(client).await
+        })
+        .await;
+
+        if let Err(_e) = &result {
+            #[cfg(feature = "defmt")]
+            warn!("MQTT connection failed with {:?}, will reconnect", _e);
+        }
+
+        let delay = backoff.next(result.is_ok(), running_since.map(|since| since.elapsed()));
+        send_status(ConnectionStatus::Reconnecting {
+            failures: backoff.failures,
+            delay,
+        });
+        Timer::after(delay).await;
+    }
+}
+
+/// Tracks consecutive failed connections for [`run_mqtt_connection_forever`], to
+/// produce the delay before each reconnection attempt
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Backoff {
+    initial: Duration,
+    maximum: Duration,
+    failures: u32,
+    delay: Duration,
+}
+
+impl Backoff {
+    fn new(settings: &Settings) -> Self {
+        Self {
+            initial: settings.reconnect_delay,
+            maximum: settings.reconnect_delay_max,
+            failures: 0,
+            delay: settings.reconnect_delay,
+        }
+    }
+
+    /// Record the end of a connection, which `succeeded` if it finished cleanly,
+    /// and was running for `running_for` if it connected at all, and produce the
+    /// delay before reconnecting. A successful connection, or one that ran for at
+    /// least the maximum delay, resets the backoff, and the delay doubles after each
+    /// consecutive failure.
+    fn next(&mut self, succeeded: bool, running_for: Option<Duration>) -> Duration {
+        if succeeded || running_for.is_some_and(|r| r >= self.maximum) {
+            self.failures = 0;
+            self.delay = self.initial;
+        }
+        if !succeeded {
+            self.failures = self.failures.saturating_add(1);
+        }
+        let delay = self.delay;
+        if self.failures > 0 {
+            self.delay = (self.delay * 2).min(self.maximum);
+        }
+        delay
+    }
+}
+
 /// An MQTT client that works by regularly polling for new received messages,
 /// rather than using a stream of events.
-pub struct PollClient<'a, S, M, const N: usize, const P: usize>
-where
+/// This is not built on a [`managed_client::ManagedClient`], since packets are sent
+/// and received as [`PacketBin`]s by a separate connection task, and each operation
+/// must be cancel-safe. Instead it shares the protocol handling of a
+/// [`managed_client::ManagedClient`]: received events are handled by an [`EventFilter`],
+/// and pings, the receive timeout and [`Health`] use a [`ConnectionTimer`].
+/// For a [`managed_client::ManagedClient`] running directly on an embassy socket, see
+/// [`crate::mqtt_manager`].
+/// See [`run_mqtt_connection`] for the meaning of the const parameters.
+pub struct PollClient<
+    'a,
+    S,
+    M,
+    const N: usize,
+    const P: usize,
+    const RX: usize = 1,
+    const TX: usize = 1,
+> where
     M: RawMutex,
     S: ClientState,
 {
@@ -205,62 +718,74 @@ where
 
     /// Used to send and receive [`PacketBin`] instances, each containing
     /// and MQTT packet in binary format.
-    raw_client: PacketBinClient<'a, M, N>,
-
-    /// The end of the timeout for received packets (specifically connack and pingresp)
-    /// from the server.
-    /// This is initially None. It is initialised when a connection is started (by
-    /// sending a connect packet), and then updated whenever a  relevant packet is
-    /// received.
-    /// If we ever reach this timeout instant, then the server has not
-    /// replied for too long, and is unresponsive leading to an error being reported.
-    /// Note that the receive timeout is only active when this is Some.
-    receive_timeout_at: Option<Instant>,
-
-    /// The scheduled time for sending the next ping.
-    /// This is initially None. It is initialised when a connection is
-    /// made, and then updated whenever we attempt to send a ping. Note that
-    /// we only send one ping at a time, if ping_time occurs while there is still
-    /// a ping that has not been responded to, we will delay before attempting
-    /// to send another ping.
-    /// Note that pings are only active when this is Some.
-    ping_at: Option<Instant>,
-
-    /// When we sent the [`Connect`] packet to start connection
-    connection_start: Option<Instant>,
+    raw_client: PacketBinClient<'a, M, N, RX, TX>,
+
+    /// Tracks ping scheduling and the receive timeout, see [`ConnectionTimer`]
+    timer: ConnectionTimer,
+
+    /// Source of time for the timer
+    time: EmbassyTime,
 
     /// Client settings
     settings: Settings,
+
+    /// Handles received events as for a [`managed_client::ManagedClient`],
+    /// e.g. suppressing duplicates, see [`Settings::event_filter`]
+    filter: EventFilter,
+
+    /// Fed each time a received packet is committed, see [`PollClient::with_watchdog`]
+    watchdog: Option<&'a mut dyn Watchdog>,
 }
 
 /// Implements a relatively low-level but flexible client that is operated
 /// based on regularly polling for new messages.
-impl<'a, S, M, const N: usize, const P: usize> PollClient<'a, S, M, N, P>
+impl<'a, S, M, const N: usize, const P: usize, const RX: usize, const TX: usize>
+    PollClient<'a, S, M, N, P, RX, TX>
 where
     M: RawMutex,
     S: ClientState,
 {
-    /// Create a PollClient using [`Sender`] and [`Receiver`] to
-    /// send/receive MQTT packets as [`PacketBin`].
-    /// The sender and receiver must be already connected at the TCP/IP layer,
+    /// Create a PollClient using a [`Sender`] to send MQTT packets as [`PacketBin`],
+    /// and a [`PacketBinPool`] to receive them.
+    /// The sender and pool must be already connected at the TCP/IP layer,
     /// with no data yet sent or received.
     pub fn new(
-        sender: Sender<'a, M, PacketBin<N>, 1>,
-        receiver: Receiver<'a, M, PacketBin<N>, 1>,
+        sender: Sender<'a, M, PacketBin<N>, TX>,
+        rx_pool: &'a PacketBinPool<M, N, RX>,
         settings: Settings,
         client_state: S,
     ) -> Self {
         Self {
-            receive_timeout_at: None,
-            ping_at: None,
-            connection_start: None,
+            timer: ConnectionTimer::new(settings.timer_settings())
+                .with_adaptive_keep_alive(settings.adaptive_keep_alive),
+            time: EmbassyTime,
             client_state,
-            raw_client: PacketBinClient::new(sender, receiver),
+            raw_client: PacketBinClient::new(sender, rx_pool),
+            filter: settings.event_filter(),
             settings,
+            watchdog: None,
         }
     }
 
-    pub fn to_handler_client<F>(self, handler: F) -> HandlerClient<'a, S, M, F, N, P>
+    /// Send packets with [`Priority::High`] using `priority_sender`, see
+    /// [`PacketBinClient::with_priority_sender`]. Pings are also sent with high
+    /// priority, so that they are not delayed by queued publishes.
+    pub fn with_priority_sender(
+        mut self,
+        priority_sender: Sender<'a, M, PacketBin<N>, TX>,
+    ) -> Self {
+        self.raw_client = self.raw_client.with_priority_sender(priority_sender);
+        self
+    }
+
+    /// Set a [`Watchdog`] to be fed each time a received packet is successfully
+    /// processed, i.e. on each successful [`PollClient::commit`]
+    pub fn with_watchdog(mut self, watchdog: &'a mut dyn Watchdog) -> Self {
+        self.watchdog = Some(watchdog);
+        self
+    }
+
+    pub fn to_handler_client<F>(self, handler: F) -> HandlerClient<'a, S, M, F, N, P, RX, TX>
     where
         F: SyncEventHandler<P>,
     {
@@ -282,26 +807,9 @@ where
         settings: &ConnectionSettings<'_>,
         will: Option<Will<'_, W>>,
     ) -> Result<(), ClientError> {
-        let mut properties = Vec::new();
-        // By setting maximum topic alias to 0, we prevent the server
-        // trying to use aliases, which we don't support. They are optional
-        // and only provide for reduced packet size, but would require storing
-        // topic names from the server for the length of the connection,
-        // which might be awkward without alloc.
-        properties
-            .push(ConnectProperty::TopicAliasMaximum(0.into()))
-            .unwrap();
-        let packet: Connect<'_, 1, W> = Connect::new(
-            settings.keep_alive(),
-            *settings.username(),
-            *settings.password(),
-            settings.client_id(),
-            true,
-            will,
-            properties,
-        );
-
-        self.connect_with_packet(packet).await
+        settings.check_client_id()?;
+        self.connect_with_packet(managed_client::connect_packet(settings, will))
+            .await
     }
 
     /// Connect to the server - this sends a [`Connect`] packet and  waits for
@@ -312,17 +820,14 @@ where
         packet: Connect<'_, PP, W>,
     ) -> Result<(), ClientError> {
         self.client_state.connect(&packet)?;
+        self.filter.connecting(packet.clean_start());
         self.raw_client
             .send_packet_timeout(&packet, self.settings.send_packet_timeout)
             .await?;
 
-        // Sending packet is the start of our connection
-        self.connection_start = Some(Instant::now());
-
-        // We are expecting a server reply (the connack), so we can start the receive timeout
-        // We don't start the ping interval yet since we shouldn't ping until
-        // we are connected
-        self.receive_timeout_at = Some(Instant::now() + self.settings.receive_timeout);
+        // Sending packet is the start of our connection, and we are expecting
+        // a server reply (the connack), so this starts the receive timeout
+        self.timer.connect_sent(self.time.now_millis());
 
         // We now just wait for an ack
         self.wait_for_connected().await?;
@@ -333,16 +838,28 @@ where
     async fn wait_for_connected(&mut self) -> Result<(), ClientError> {
         while self.client_state.waiting_for_responses() {
             let packet_bin = self.receive().await?;
-            let packet: mountain_mqtt::packets::packet_generic::PacketGeneric<'_, P, 0, 0> =
-                packet_bin.as_packet_generic()?;
+
+            // Only a Connack or Auth is expected, so check the type before decoding
+            if !matches!(
+                packet_bin.packet_type(),
+                Some(PacketType::Connack | PacketType::Auth) | None
+            ) {
+                return Err(ClientError::ClientState(
+                    ClientStateError::ReceivedPacketOtherThanConnackOrAuthWhenConnecting,
+                ));
+            }
+            let packet: ServerPacket<'_, P, 0> = packet_bin.as_server_packet()?;
+            self.timer
+                .packet_received(self.time.now_millis(), packet.packet_type());
             let event = self.client_state.receive(packet)?;
             match event {
-                ClientStateReceiveEvent::Ack => {
+                ClientStateReceiveEvent::Ack(acknowledgement) => {
+                    if let Acknowledgement::Connack { session_present } = acknowledgement {
+                        self.filter.connected(session_present);
+                    }
                     // We should now start sending pings - start from when we started connection,
                     // since this is the last time we sent a packet
-                    self.ping_at = self
-                        .connection_start
-                        .map(|s| s + self.settings.ping_interval);
+                    self.timer.connected();
                     #[cfg(feature = "defmt")]
                     debug!("Client connected");
                 }
@@ -365,7 +882,7 @@ where
         if self.client_state.pending_ping_count() > 0 {
             #[cfg(feature = "defmt")]
             trace!("...Ping pending, will delay and retry");
-            self.ping_at = Some(Instant::now() + self.settings.ping_retry_delay);
+            self.timer.ping_deferred(self.time.now_millis());
         } else {
             #[cfg(feature = "defmt")]
             trace!("...Pinging");
@@ -380,10 +897,14 @@ where
             // but if the actual sending fails then the client will error and should not
             // be used further.
             self.raw_client
-                .send_packet_timeout(&Pingreq::default(), self.settings.send_packet_timeout)
+                .send_packet_timeout_with_priority(
+                    &Pingreq::default(),
+                    self.settings.send_packet_timeout,
+                    Priority::High,
+                )
                 .await?;
             self.client_state.send_ping()?;
-            self.ping_at = Some(Instant::now() + self.settings.ping_interval);
+            self.timer.ping_sent(self.time.now_millis());
         }
         Ok(())
     }
@@ -393,27 +914,38 @@ where
     /// Returns true if a ping was sent.
     /// Cancel-safe: Just calls through to cancel-safe [`Self::ping`] if needed.
     pub async fn ping_if_needed(&mut self) -> Result<bool, ClientError> {
-        if let Some(ping_at) = self.ping_at {
-            if ping_at < Instant::now() {
-                self.ping().await?;
-            }
+        if self.timer.ping_due(self.time.now_millis()) {
+            self.ping().await?;
             Ok(true)
         } else {
             Ok(false)
         }
     }
 
-    fn check_receive_timeout(&self) -> Result<(), ClientError> {
-        if let Some(receive_timeout_at) = self.receive_timeout_at {
-            if receive_timeout_at < Instant::now() {
-                return Err(ClientError::ReceiveTimeoutServerUnresponsive);
-            }
+    /// Send a ping now if one would otherwise be due before `at`, e.g. to send
+    /// the ping while awake to publish, rather than waking again to send it.
+    /// Returns true if a ping was sent.
+    /// Cancel-safe: Just calls through to cancel-safe [`Self::ping`] if needed.
+    pub async fn ping_if_due_before(&mut self, at: Instant) -> Result<bool, ClientError> {
+        if self.timer.ping_due_before(at.as_millis()) {
+            self.ping().await?;
+            Ok(true)
+        } else {
+            Ok(false)
         }
-        Ok(())
     }
 
-    fn reset_receive_timeout(&mut self) {
-        self.receive_timeout_at = Some(Instant::now() + self.settings.receive_timeout);
+    /// The next time the client needs to act, i.e. when a ping is due or the
+    /// server would be considered unresponsive, or None if neither is scheduled.
+    /// Power-managed firmware can sleep until the earlier of this and its own
+    /// next wakeup, then call [`PollClient::try_receive`] (or [`PollClient::ping_if_needed`]).
+    /// This may move earlier when a packet is sent, or later when one is received.
+    pub fn next_wakeup(&self) -> Option<Instant> {
+        self.timer.next_wakeup().map(Instant::from_millis)
+    }
+
+    fn check_receive_timeout(&self) -> Result<(), ClientError> {
+        self.timer.check_receive_timeout(self.time.now_millis())
     }
 
     /// Check whether a new [`PacketBin`] is available immediately, and if so
@@ -426,7 +958,7 @@ where
     /// appear unresponsive).
     /// This will handle sending pings as needed, and check if the server is unresponsive.
     /// Cancel-safe
-    pub async fn try_receive(&mut self) -> Result<Option<PacketBin<N>>, ClientError> {
+    pub async fn try_receive(&mut self) -> Result<Option<PooledPacketBin<'a, M, N>>, ClientError> {
         self.check_receive_timeout()?;
         self.ping_if_needed().await?;
 
@@ -442,6 +974,26 @@ where
         self.disconnect_with_packet(Disconnect::default()).await
     }
 
+    /// Disconnect the client, setting a new session expiry interval, e.g. 0 to end
+    /// the session. A non-zero interval is only allowed if the connection was
+    /// made with a non-zero interval.
+    /// NOT CANCEL-SAFE
+    pub async fn disconnect_with_session_expiry_interval(
+        &mut self,
+        session_expiry_interval: u32,
+    ) -> Result<(), ClientError> {
+        let packet = self
+            .client_state
+            .disconnect_with_session_expiry_interval(session_expiry_interval)?;
+        self.raw_client
+            .send_packet_timeout(&packet, self.settings.send_packet_timeout)
+            .await?;
+        self.raw_client
+            .flush_timeout(self.settings.send_packet_timeout)
+            .await?;
+        Ok(())
+    }
+
     /// Disconnect the client with a provided packet (e.g. for reason code)
     /// NOT CANCEL-SAFE
     pub async fn disconnect_with_packet<'b, const PP: usize>(
@@ -451,18 +1003,17 @@ where
         self.raw_client
             .send_packet_timeout(&packet, self.settings.send_packet_timeout)
             .await?;
-        // Send an empty packet to flush send queue so we know the
-        // disconnect packet has actually made it to the network
+        // Flush so we know the disconnect packet has actually made it to the network
         self.raw_client
-            .send_timeout(PacketBin::empty(), self.settings.send_packet_timeout)
+            .flush_timeout(self.settings.send_packet_timeout)
             .await?;
         self.client_state.disconnect()?;
         Ok(())
     }
 
-    async fn wait_for_time(time: Option<Instant>) {
-        if let Some(time) = time {
-            Timer::at(time).await
+    async fn wait_for_time(mut time: EmbassyTime, at_millis: Option<u64>) {
+        if let Some(at_millis) = at_millis {
+            time.sleep_until_millis(at_millis).await
         } else {
             // When time is None, check is not enabled, so never complete
             future::pending().await
@@ -478,7 +1029,7 @@ where
     /// example you may wish to select between [`PollClient::receive_bin`], and having
     /// an outgoing message you wish to publish, for example by receiving one on a channel
     /// from the rest of your application.
-    pub async fn receive(&mut self) -> Result<PacketBin<N>, ClientError> {
+    pub async fn receive(&mut self) -> Result<PooledPacketBin<'a, M, N>, ClientError> {
         // Loop until we error or return a packet
         loop {
             // We need to deal with the next event - this is either the ping interval
@@ -488,8 +1039,8 @@ where
             // `process`, we want to stop waiting for a packet immediately if this timeout occurs,
             // rather than waiting until we receive and process it.
             let r = select3(
-                Self::wait_for_time(self.ping_at),
-                Self::wait_for_time(self.receive_timeout_at),
+                Self::wait_for_time(self.time, self.timer.ping_at()),
+                Self::wait_for_time(self.time, self.timer.receive_timeout_at()),
                 self.raw_client.receive(),
             )
             .await;
@@ -503,7 +1054,25 @@ where
         }
     }
 
-    /// Request a subscription.
+    /// Wait up to `timeout` to receive a new [`PacketBin`], returning `Ok(None)`
+    /// if no packet arrives in time.
+    /// As for [`PollClient::receive`], this will handle sending pings as needed, and
+    /// will return an error if the server is unresponsive. This allows a loop to
+    /// perform periodic work between packets without needing its own select.
+    ///
+    /// Cancel-safe: As for [`PollClient::receive`]
+    pub async fn receive_with_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<PooledPacketBin<'a, M, N>>, ClientError> {
+        match select(self.receive(), Timer::after(timeout)).await {
+            Either::First(result) => result.map(Some),
+            Either::Second(()) => Ok(None),
+        }
+    }
+
+    /// Request a subscription, with the NoLocal option if enabled, see
+    /// [`Settings::with_no_local`].
     /// This may require a response from the server, so after calling this, you must receive messages until
     /// [`PollClient::waiting_for_responses`] returns false, before calling any other methods that may
     /// require a response from the server.
@@ -512,13 +1081,82 @@ where
         &mut self,
         topic_name: &str,
         maximum_qos: QualityOfService,
+    ) -> Result<(), ClientError> {
+        let packet = self.client_state.subscribe_requests_packet::<0>(
+            self.filter.subscription_request(topic_name, maximum_qos),
+            Vec::new(),
+        )?;
+        self.raw_client
+            .send_packet_timeout(&packet, self.settings.send_packet_timeout)
+            .await?;
+        self.timer.packet_sent(self.time.now_millis());
+        self.client_state.subscribe_update(&packet)?;
+        Ok(())
+    }
+
+    /// Request a subscription, with properties (e.g. a subscription identifier).
+    /// This may require a response from the server, so after calling this, you must receive messages until
+    /// [`PollClient::waiting_for_responses`] returns false, before calling any other methods that may
+    /// require a response from the server.
+    /// Cancel-safe: Unless subscribe packet is sent, client state won't be updated
+    pub async fn subscribe_with_properties<const PP: usize>(
+        &mut self,
+        topic_name: &str,
+        maximum_qos: QualityOfService,
+        properties: Vec<SubscribeProperty<'_>, PP>,
+    ) -> Result<(), ClientError> {
+        let packet = self
+            .client_state
+            .subscribe_with_properties_packet::<PP, 0>(
+                self.filter.subscription_request(topic_name, maximum_qos),
+                Vec::new(),
+                properties,
+            )?;
+        self.raw_client
+            .send_packet_timeout(&packet, self.settings.send_packet_timeout)
+            .await?;
+        self.timer.packet_sent(self.time.now_millis());
+        self.client_state.subscribe_update(&packet)?;
+        Ok(())
+    }
+
+    /// Request a subscription with [`SubscriptionOptions`], e.g. to set
+    /// no local or retain handling.
+    /// This may require a response from the server, so after calling this, you must receive messages until
+    /// [`PollClient::waiting_for_responses`] returns false, before calling any other methods that may
+    /// require a response from the server.
+    /// Cancel-safe: Unless subscribe packet is sent, client state won't be updated
+    pub async fn subscribe_with_options(
+        &mut self,
+        topic_name: &str,
+        options: SubscriptionOptions,
+    ) -> Result<(), ClientError> {
+        let request = SubscriptionRequest {
+            topic_name,
+            options,
+        };
+        self.subscribe_many::<0>(request, Vec::new()).await
+    }
+
+    /// Request subscriptions to multiple topics in one packet, each with their
+    /// own [`SubscriptionOptions`].
+    /// At most [`MAX_SUBSCRIPTION_REQUESTS`] requests can be made in one packet.
+    /// This may require a response from the server, so after calling this, you must receive messages until
+    /// [`PollClient::waiting_for_responses`] returns false, before calling any other methods that may
+    /// require a response from the server.
+    /// Cancel-safe: Unless subscribe packet is sent, client state won't be updated
+    pub async fn subscribe_many<const R: usize>(
+        &mut self,
+        first_request: SubscriptionRequest<'_>,
+        other_requests: Vec<SubscriptionRequest<'_>, R>,
     ) -> Result<(), ClientError> {
         let packet = self
             .client_state
-            .subscribe_packet(topic_name, maximum_qos)?;
+            .subscribe_requests_packet(first_request, other_requests)?;
         self.raw_client
             .send_packet_timeout(&packet, self.settings.send_packet_timeout)
             .await?;
+        self.timer.packet_sent(self.time.now_millis());
         self.client_state.subscribe_update(&packet)?;
         Ok(())
     }
@@ -533,10 +1171,75 @@ where
         self.raw_client
             .send_packet_timeout(&packet, self.settings.send_packet_timeout)
             .await?;
+        self.timer.packet_sent(self.time.now_millis());
         self.client_state.unsubscribe_update(&packet)?;
         Ok(())
     }
 
+    /// Request to unsubscribe from multiple topics in one packet.
+    /// At most [`MAX_SUBSCRIPTION_REQUESTS`] topics can be included in one packet.
+    /// This may require a response from the server, so after calling this, you must receive messages until
+    /// [`PollClient::waiting_for_responses`] returns false, before calling any other methods that may
+    /// require a response from the server.
+    /// Cancel-safe: Unless unsubscribe packet is sent, client state won't be updated
+    pub async fn unsubscribe_many<const R: usize>(
+        &mut self,
+        first_topic_name: &str,
+        other_topic_names: Vec<&str, R>,
+    ) -> Result<(), ClientError> {
+        let packet = self
+            .client_state
+            .unsubscribe_topics_packet(first_topic_name, other_topic_names)?;
+        self.raw_client
+            .send_packet_timeout(&packet, self.settings.send_packet_timeout)
+            .await?;
+        self.timer.packet_sent(self.time.now_millis());
+        self.client_state.unsubscribe_update(&packet)?;
+        Ok(())
+    }
+
+    /// Produce a [`Health`] snapshot, with the time since the last packet was
+    /// received from the server, the time left before the server is considered
+    /// unresponsive, and counts of requests still awaiting a response.
+    /// This can be used to distinguish a connection that is idle but healthy
+    /// from one that is about to time out.
+    pub fn health(&self) -> Health {
+        self.timer
+            .health(self.time.now_millis(), self.client_state.pending_counts())
+    }
+
+    /// If connected, the number of pings sent that have not been responded to,
+    /// see [`ClientState::pending_ping_count`]
+    pub fn pending_ping_count(&self) -> u32 {
+        self.client_state.pending_ping_count()
+    }
+
+    /// If connected, the keep alive interval in seconds for the connection,
+    /// see [`ClientState::keep_alive`]
+    pub fn keep_alive(&self) -> Option<u16> {
+        self.client_state.keep_alive()
+    }
+
+    /// The requests sent to the server that are still awaiting a response,
+    /// with their packet identifiers, e.g. to log "still waiting for Suback id 7"
+    /// on a timeout, see [`ClientState::pending_requests`]
+    pub fn pending_requests(&self) -> impl Iterator<Item = PendingRequest> + '_ {
+        self.client_state.pending_requests()
+    }
+
+    /// The subscriptions the client believes are active, or None if not connected,
+    /// see [`ClientState::subscriptions`]
+    pub fn subscriptions(&self) -> Option<&ClientStateSubscriptions> {
+        self.client_state.subscriptions()
+    }
+
+    /// True if the client believes there is an active subscription with exactly
+    /// the given topic filter
+    pub fn is_subscribed(&self, topic_filter: &str) -> bool {
+        self.subscriptions()
+            .is_some_and(|s| s.is_subscribed(topic_filter))
+    }
+
     /// True if client is waiting for a response from the server - if this is true, then you must receive and
     /// handle packets until it becomes false, before attempting to send any more packets.
     /// This is done by calling [`PollClient::receive`] or [`PollClient::try_receive`]
@@ -574,99 +1277,316 @@ where
         qos: QualityOfService,
         retain: bool,
         properties: Vec<PublishProperty<'b>, PP>,
+    ) -> Result<(), ClientError> {
+        self.publish_with_properties_and_priority(
+            topic_name,
+            payload,
+            qos,
+            retain,
+            properties,
+            Priority::Normal,
+        )
+        .await
+    }
+
+    /// Publish a message with given payload to a given topic, with no properties,
+    /// in the lane for `priority`, see [`Priority`]. Otherwise as for [`PollClient::publish`].
+    /// Cancel-safe: Unless publish packet is sent, client state won't be updated
+    pub async fn publish_with_priority<'b>(
+        &'b mut self,
+        topic_name: &'b str,
+        payload: &'b [u8],
+        qos: QualityOfService,
+        retain: bool,
+        priority: Priority,
+    ) -> Result<(), ClientError> {
+        self.publish_with_properties_and_priority::<0>(
+            topic_name,
+            payload,
+            qos,
+            retain,
+            Vec::new(),
+            priority,
+        )
+        .await
+    }
+
+    /// Publish a message with given payload to a given topic, with given properties,
+    /// in the lane for `priority`, see [`Priority`].
+    /// Otherwise as for [`PollClient::publish_with_properties`].
+    /// Cancel-safe: Unless publish packet is sent, client state won't be updated
+    pub async fn publish_with_properties_and_priority<'b, const PP: usize>(
+        &'b mut self,
+        topic_name: &'b str,
+        payload: &'b [u8],
+        qos: QualityOfService,
+        retain: bool,
+        properties: Vec<PublishProperty<'b>, PP>,
+        priority: Priority,
     ) -> Result<(), ClientError> {
         let packet = self
             .client_state
             .publish_with_properties_packet(topic_name, payload, qos, retain, properties)?;
         self.raw_client
-            .send_packet_timeout(&packet, self.settings.send_packet_timeout)
+            .send_packet_timeout_with_priority(&packet, self.settings.send_packet_timeout, priority)
             .await?;
+        self.timer.packet_sent(self.time.now_millis());
         self.client_state.publish_update(&packet)?;
+        self.filter.published(topic_name, payload);
         Ok(())
     }
 
-    /// Handle a [`PacketBin`], parsing it as a [`PacketGeneric`], then updating client state,
+    /// Publish a message with given payload to a given topic, with no properties,
+    /// without waiting for space to send it. This fails with [`ClientError::WouldBlock`]
+    /// if the channel of packets to send is full, in which case client state is not
+    /// updated, and the caller may retry later or drop the message, e.g. for QoS 0
+    /// telemetry. Otherwise as for [`PollClient::publish`].
+    pub fn try_publish<'b>(
+        &'b mut self,
+        topic_name: &'b str,
+        payload: &'b [u8],
+        qos: QualityOfService,
+        retain: bool,
+    ) -> Result<(), ClientError> {
+        let packet = self.client_state.publish_with_properties_packet::<0>(
+            topic_name,
+            payload,
+            qos,
+            retain,
+            Vec::new(),
+        )?;
+        self.raw_client.try_send_packet(&packet)?;
+        self.timer.packet_sent(self.time.now_millis());
+        self.client_state.publish_update(&packet)?;
+        self.filter.published(topic_name, payload);
+        Ok(())
+    }
+
+    /// Handle a [`PacketBin`], parsing it as a [`ServerPacket`], then updating client state,
     /// sending any required response packet, and finally returning any [`ClientReceivedEvent`]
-    /// resulting from the packet.
+    /// resulting from the packet. Produces `Ok(None)` for a packet that is handled but
+    /// should not be passed on to the application, e.g. a suppressed duplicate message.
     /// This be called exactly once with each received [`PacketBin`]
     /// This also handles checking for receive timeouts, so it's best to call immediately after
     /// receiving each packet.
-    /// Cancel-safe with note: This method may await sending a response packet, and will only
-    /// update state if the packet is sent, so state remains consistent. However note that if
-    /// process is interrupted, it must be called again with the same `packet_bin`, since the
-    /// packet will not have been handled.
+    /// This is equivalent to calling [`PollClient::respond`] and then [`PollClient::commit`],
+    /// and if either fails because the server violated the protocol, calling
+    /// [`PollClient::disconnect_on_protocol_violation`].
+    /// Cancel-safe: [`PollClient::respond`] does not update client state, so if this is
+    /// interrupted there, nothing has been committed, and it can be called again with the
+    /// same `packet_bin`, e.g. in the next iteration of a loop using `select`. Note that the
+    /// packet must not be dropped, since it has not been handled. If handling fails, the
+    /// await in [`PollClient::disconnect_on_protocol_violation`] is also cancel-safe, but the
+    /// client should not be used further.
     pub async fn process<'b>(
         &mut self,
         packet_bin: &'b PacketBin<N>,
-    ) -> Result<ClientReceivedEvent<'b, P>, ClientError> {
-        // Cancel-safety - this doesn't modify state, so safe to do before interruptible send
-        let packet: PacketGeneric<'_, P, 0, 0> = packet_bin.as_packet_generic()?;
-
-        // Cancel-safety: This just resets receive timeout and checks for timeout error -
-        // this is safe to do as soon as we know the packet has been received, even if the
-        // following send is interrupted this doesn't make state inconsistent.
-        // We reset the receive timeout on connack and pingresp only.
-        // We use the pings as our means of detecting any issue with packets getting
-        // through on either outgoing or incoming channels. If we included other packet
-        // types then even if pings were not being sent outgoing, we might not timeout
-        // since the server could still be sending publish packets regularly.
-        // Connack is safe to use since we'll only ever get one in response to our connect
-        // packet, which essentially acts as our first ping.
+    ) -> Result<Option<ClientReceivedEvent<'b, P>>, ClientError> {
+        let result = match self.respond(packet_bin).await {
+            Ok(()) => self.commit(packet_bin),
+            Err(e) => Err(e),
+        };
+        match result {
+            Err(e) => Err(self.disconnect_on_protocol_violation(e).await),
+            result => result,
+        }
+    }
+
+    /// If `error` was produced handling a packet from the server that violates the
+    /// protocol (see [`ClientError::disconnect_reason_code`]), and the client is connected,
+    /// make a best-effort attempt to send a Disconnect with the appropriate reason code,
+    /// so the server knows why the connection is closing. The error is returned unaltered,
+    /// even if the Disconnect can't be sent, and the client should not be used further.
+    /// Only needed when calling [`PollClient::respond`] and [`PollClient::commit`] directly,
+    /// since [`PollClient::process`] calls this on error.
+    /// Cancel-safe: Client state is only updated once the Disconnect has been queued, so if
+    /// interrupted before then, nothing has changed and this can be called again. If
+    /// interrupted while flushing, the client is already disconnected.
+    pub async fn disconnect_on_protocol_violation(&mut self, error: ClientError) -> ClientError {
+        if let Some(reason_code) = error.disconnect_reason_code() {
+            // Only connected clients have a keep alive
+            if self.client_state.keep_alive().is_some() {
+                #[cfg(feature = "defmt")]
+                warn!("Disconnecting on protocol violation: {:?}", error);
+                // CANCEL-SAFETY: As for `ping`, we send first, then update the client state
+                // as a sync operation, ignoring the packet it produces. Sending is cancel-safe,
+                // so either the Disconnect is not queued and the state is unchanged, or it is
+                // queued and we are disconnected, even if the flush is then interrupted.
+                let packet: Disconnect<'_, 0> = Disconnect::new(reason_code, Vec::new());
+                let sent = self
+                    .raw_client
+                    .send_packet_timeout(&packet, self.settings.send_packet_timeout)
+                    .await;
+                let _ = self.client_state.disconnect();
+                if sent.is_ok() {
+                    let _ = self
+                        .raw_client
+                        .flush_timeout(self.settings.send_packet_timeout)
+                        .await;
+                }
+            }
+        }
+        error
+    }
+
+    /// The first stage of processing a received [`PacketBin`] - this produces any response
+    /// packet required (e.g. a puback) based on the current client state, without updating
+    /// that state, and then sends it.
+    /// Call [`PollClient::commit`] with the same packet after this succeeds.
+    /// Cancel-safe: This does not update client state, so if interrupted it can simply be
+    /// called again.
+    pub async fn respond(&mut self, packet_bin: &PacketBin<N>) -> Result<(), ClientError> {
+        let packet: ServerPacket<'_, P, ADDITIONAL_REQUESTS> = packet_bin.as_server_packet()?;
+
+        // Cancel-safety: This just records the receive time, resets receive timeout
+        // if appropriate (see [`ConnectionTimer::packet_received`]) and checks for timeout
+        // error - this is safe to do as soon as we know the packet has been received, even
+        // if the following send is interrupted this doesn't make state inconsistent.
         // We check here rather than on receive because we need to have decoded to
         // know the packet type
-        match packet.packet_type() {
-            PacketType::Connack | PacketType::Pingresp => self.reset_receive_timeout(),
-            _ => {}
-        }
+        self.timer
+            .packet_received(self.time.now_millis(), packet.packet_type());
         self.check_receive_timeout()?;
 
-        // Note that send may be interrupted, if so the client state will not be updated.
-        // However note that if interrupted, this process method must be called again on
-        // the packet until it completes (i.e. response is set and client state is updated).
+        // Cancel-safety: Producing the response does not update state, so the send may be
+        // interrupted, and we can just produce and send the response again next time.
         if let Some(response) = self.client_state.receive_produce_response(&packet)? {
             self.raw_client
                 .send_packet_timeout(&response, self.settings.send_packet_timeout)
                 .await?;
+            self.timer.packet_sent(self.time.now_millis());
         }
 
-        // Remaining operations are sync, and update client state now that send has succeeded
+        Ok(())
+    }
+
+    /// The second stage of processing a received [`PacketBin`] - this updates the client state,
+    /// and returns any resulting [`ClientReceivedEvent`], handled by the client's
+    /// [`EventFilter`] as for a [`managed_client::ManagedClient`].
+    /// This must be called exactly once for each received packet, after
+    /// [`PollClient::respond`] has succeeded for that packet.
+    /// This is sync, and so cannot be interrupted.
+    pub fn commit<'b>(
+        &mut self,
+        packet_bin: &'b PacketBin<N>,
+    ) -> Result<Option<ClientReceivedEvent<'b, P>>, ClientError> {
+        let packet: ServerPacket<'_, P, ADDITIONAL_REQUESTS> = packet_bin.as_server_packet()?;
         let event = self.client_state.receive(packet)?;
+        let event = self.filter.receive(event)?;
+        if let Some(watchdog) = self.watchdog.as_deref_mut() {
+            watchdog.feed();
+        }
+        Ok(event)
+    }
+}
 
-        match event {
-            ClientStateReceiveEvent::Ack => Ok(ClientReceivedEvent::Ack),
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use mountain_mqtt::client_state::{ClientStateNoQueue, PendingCounts};
+
+    use super::*;
+
+    const CONNACK: [u8; 5] = [0x20, 0x03, 0x00, 0x00, 0x00];
+
+    /// A client state that has connected to the server
+    fn connected_state() -> ClientStateNoQueue {
+        let mut client_state = ClientStateNoQueue::default();
+        let connect: Connect<'_, 0, 0> = Connect::new(60, None, None, "a", true, None, Vec::new());
+        client_state.connect(&connect).unwrap();
+        let connack = PacketBin::<16>::new(&CONNACK).unwrap();
+        client_state
+            .receive(ServerPacket::<'_, 0, 0>::Connack(
+                connack.as_connack().unwrap(),
+            ))
+            .unwrap();
+        client_state
+    }
 
-            ClientStateReceiveEvent::Publish { publish } => {
-                if publish.topic_name().is_empty() {
-                    return Err(ClientError::EmptyTopicNameWithAliasesDisabled);
-                }
-                Ok(publish.into())
-            }
+    #[test]
+    fn try_publish_fails_with_would_block_without_updating_state() {
+        let channel: Channel<NoopRawMutex, PacketBin<64>, 1> = Channel::new();
+        let pool: PacketBinPool<NoopRawMutex, 64, 1> = PacketBinPool::new();
+        let settings = Settings::new(Ipv4Addr::LOCALHOST, 1883).with_local_echo_suppression(true);
+        let mut client: PollClient<'_, _, _, 64, 16> =
+            PollClient::new(channel.sender(), &pool, settings, connected_state());
 
-            ClientStateReceiveEvent::PublishAndPuback { publish, puback: _ } => {
-                if publish.topic_name().is_empty() {
-                    return Err(ClientError::EmptyTopicNameWithAliasesDisabled);
-                }
-                Ok(publish.into())
-            }
+        client
+            .try_publish("t", b"p", QualityOfService::Qos0, false)
+            .unwrap();
+        assert_eq!(
+            client.try_publish("t", b"q", QualityOfService::Qos0, false),
+            Err(ClientError::WouldBlock)
+        );
 
-            ClientStateReceiveEvent::SubscriptionGrantedBelowMaximumQos {
-                granted_qos,
-                maximum_qos,
-            } => Ok(ClientReceivedEvent::SubscriptionGrantedBelowMaximumQos {
-                granted_qos,
-                maximum_qos,
-            }),
+        let sent = channel.try_receive().unwrap();
+        assert_eq!(sent.msg_data(), [0x30, 0x05, 0x00, 0x01, b't', 0x00, b'p']);
+        assert!(channel.try_receive().is_err());
+
+        // The echo of the sent message is suppressed, but the message that
+        // could not be sent was not recorded as published
+        assert!(matches!(client.commit(&sent), Ok(None)));
+        let unsent = PacketBin::<64>::new(&[0x30, 0x05, 0x00, 0x01, b't', 0x00, b'q']).unwrap();
+        assert!(matches!(
+            client.commit(&unsent),
+            Ok(Some(ClientReceivedEvent::ApplicationMessage(_)))
+        ));
+    }
 
-            ClientStateReceiveEvent::PublishedMessageHadNoMatchingSubscribers => {
-                Ok(ClientReceivedEvent::PublishedMessageHadNoMatchingSubscribers)
-            }
-            ClientStateReceiveEvent::NoSubscriptionExisted => {
-                Ok(ClientReceivedEvent::NoSubscriptionExisted)
-            }
+    #[test]
+    fn health_shows_received_packets_and_pending_requests() {
+        let channel: Channel<NoopRawMutex, PacketBin<64>, 1> = Channel::new();
+        let pool: PacketBinPool<NoopRawMutex, 64, 1> = PacketBinPool::new();
+        let settings = Settings::new(Ipv4Addr::LOCALHOST, 1883);
+        let mut client: PollClient<'_, _, _, 64, 16> =
+            PollClient::new(channel.sender(), &pool, settings, connected_state());
 
-            ClientStateReceiveEvent::Disconnect { disconnect } => {
-                Err(ClientError::Disconnected(*disconnect.reason_code()))
+        client
+            .try_publish("t", b"p", QualityOfService::Qos1, false)
+            .unwrap();
+        let health = client.health();
+        assert_eq!(health.since_last_received, None);
+        assert_eq!(health.until_receive_timeout, None);
+        assert_eq!(
+            health.pending,
+            PendingCounts {
+                publishes: 1,
+                ..Default::default()
             }
-        }
+        );
+
+        // Puback
+        let puback = PacketBin::<64>::new(&[0x40, 0x02, 0x00, 0x01]).unwrap();
+        embassy_futures::block_on(client.respond(&puback)).unwrap();
+        assert!(client.health().since_last_received.is_some());
+        client.commit(&puback).unwrap();
+        assert!(client.health().pending.is_empty());
+    }
+
+    #[test]
+    fn backoff_doubles_on_failure_and_resets_on_success() {
+        let settings = Settings::new(Ipv4Addr::LOCALHOST, 1883)
+            .with_reconnect_delay(Duration::from_secs(1), Duration::from_secs(4));
+        let mut backoff = Backoff::new(&settings);
+        let secs = |s| Duration::from_secs(s);
+
+        // Consecutive failures double the delay, up to the maximum
+        assert_eq!(backoff.next(false, None), secs(1));
+        assert_eq!(backoff.next(false, Some(secs(1))), secs(2));
+        assert_eq!(backoff.next(false, None), secs(4));
+        assert_eq!(backoff.next(false, None), secs(4));
+        assert_eq!(backoff.failures, 4);
+
+        // A clean finish resets the backoff, and doesn't grow the delay
+        assert_eq!(backoff.next(true, Some(secs(1))), secs(1));
+        assert_eq!(backoff.failures, 0);
+        assert_eq!(backoff.next(true, Some(secs(1))), secs(1));
+        assert_eq!(backoff.next(false, None), secs(1));
+        assert_eq!(backoff.next(false, None), secs(2));
+
+        // A failure after running for the maximum delay starts again
+        assert_eq!(backoff.next(false, Some(secs(4))), secs(1));
+        assert_eq!(backoff.failures, 1);
+        assert_eq!(backoff.next(false, None), secs(2));
     }
 }
@@ -0,0 +1,20 @@
+use embassy_time::{Instant, Timer as EmbassyTimer};
+use mountain_mqtt::timing::{Clock, Timer};
+
+/// A [`Clock`] and [`Timer`] using embassy time, with times measured
+/// from embassy's [`Instant`] zero point
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EmbassyTime;
+
+impl Clock for EmbassyTime {
+    fn now_millis(&self) -> u64 {
+        Instant::now().as_millis()
+    }
+}
+
+impl Timer for EmbassyTime {
+    async fn sleep_until_millis(&mut self, at_millis: u64) {
+        EmbassyTimer::at(Instant::from_millis(at_millis)).await
+    }
+}
@@ -0,0 +1,407 @@
+use core::{future::poll_fn, task::Poll};
+
+use futures_util::{SinkExt, Stream, StreamExt};
+use gloo_net::websocket::{futures::WebSocket, Message, WebSocketError};
+use gloo_timers::future::TimeoutFuture;
+use mountain_mqtt::{
+    client::{ClientNoQueue, ClientNoQueueOptions, Delay, EventHandler},
+    error::TransportErrorKind,
+    packet_client::Connection,
+    timing::Clock,
+};
+
+pub use gloo_utils::errors::JsError;
+
+/// The WebSocket subprotocol for MQTT, required by MQTT over WebSockets
+pub const MQTT_SUBPROTOCOL: &str = "mqtt";
+
+/// A [Delay] using browser timers. Browser timers have a resolution of
+/// milliseconds, so delays are rounded up to a whole number of milliseconds.
+#[derive(Clone)]
+pub struct WasmDelay;
+
+impl Delay for WasmDelay {
+    async fn delay_us(&mut self, us: u32) {
+        TimeoutFuture::new(us.div_ceil(1000)).await;
+    }
+}
+
+/// A [Clock] using the browser's `performance.now()`, which is monotonic,
+/// with a resolution of at most a millisecond
+#[derive(Clone)]
+pub struct WasmClock {
+    performance: web_sys::Performance,
+}
+
+impl WasmClock {
+    /// Create a clock, failing if there is no `window.performance`,
+    /// e.g. outside a browser window
+    pub fn new() -> Result<Self, JsError> {
+        let performance = web_sys::window()
+            .and_then(|window| window.performance())
+            .ok_or_else(|| js_sys::Error::new("window.performance is not available"))?;
+        Ok(Self { performance })
+    }
+}
+
+impl Clock for WasmClock {
+    fn now_millis(&self) -> u64 {
+        self.performance.now() as u64
+    }
+}
+
+/// Data from received WebSocket messages, read as a stream of bytes. This is
+/// separate from the [WebSocket] so it can be used with any [Stream] of messages.
+#[derive(Default)]
+struct MessageBuffer {
+    /// Data from the last received message that has not yet been read
+    received: Vec<u8>,
+
+    /// The position of the first unread byte in `received`
+    position: usize,
+}
+
+impl MessageBuffer {
+    /// The unread data from the last received message
+    fn unread(&self) -> &[u8] {
+        &self.received[self.position..]
+    }
+
+    /// Copy as much unread data as fits into `buf`, returning the number of bytes copied
+    fn read_unread(&mut self, buf: &mut [u8]) -> usize {
+        let len = self.unread().len().min(buf.len());
+        buf[0..len].copy_from_slice(&self.unread()[..len]);
+        self.position += len;
+        len
+    }
+
+    /// Handle a message from the WebSocket, or None if it has closed
+    fn message_received(
+        &mut self,
+        message: Option<Result<Message, WebSocketError>>,
+    ) -> Result<(), TransportErrorKind> {
+        match message {
+            Some(Ok(Message::Bytes(data))) => {
+                self.received = data;
+                self.position = 0;
+                Ok(())
+            }
+            Some(Err(WebSocketError::ConnectionClose(_))) | None => Err(TransportErrorKind::Closed),
+            // MQTT must be sent in binary messages
+            Some(Ok(Message::Text(_))) | Some(Err(_)) => Err(TransportErrorKind::Other),
+        }
+    }
+
+    /// Poll `messages` once if there is no unread data, returning false
+    /// if there is still no unread data
+    async fn poll_message<S>(&mut self, messages: &mut S) -> Result<bool, TransportErrorKind>
+    where
+        S: Stream<Item = Result<Message, WebSocketError>> + Unpin,
+    {
+        if self.unread().is_empty() {
+            match poll_fn(|cx| Poll::Ready(messages.poll_next_unpin(cx))).await {
+                Poll::Pending => return Ok(false),
+                Poll::Ready(message) => self.message_received(message)?,
+            }
+        }
+        Ok(true)
+    }
+
+    /// See [Connection::receive]
+    async fn receive<S>(
+        &mut self,
+        messages: &mut S,
+        buf: &mut [u8],
+    ) -> Result<(), TransportErrorKind>
+    where
+        S: Stream<Item = Result<Message, WebSocketError>> + Unpin,
+    {
+        let mut filled = 0;
+        while filled < buf.len() {
+            if self.unread().is_empty() {
+                let message = messages.next().await;
+                self.message_received(message)?;
+            }
+            filled += self.read_unread(&mut buf[filled..]);
+        }
+        Ok(())
+    }
+
+    /// See [Connection::receive_if_ready]
+    async fn receive_if_ready<S>(
+        &mut self,
+        messages: &mut S,
+        buf: &mut [u8],
+    ) -> Result<bool, TransportErrorKind>
+    where
+        S: Stream<Item = Result<Message, WebSocketError>> + Unpin,
+    {
+        if !self.poll_message(messages).await? {
+            return Ok(false);
+        }
+        self.receive(messages, buf).await?;
+        Ok(true)
+    }
+
+    /// See [Connection::receive_ready]
+    async fn receive_ready<S>(
+        &mut self,
+        messages: &mut S,
+        buf: &mut [u8],
+    ) -> Result<usize, TransportErrorKind>
+    where
+        S: Stream<Item = Result<Message, WebSocketError>> + Unpin,
+    {
+        if !self.poll_message(messages).await? {
+            return Ok(0);
+        }
+        Ok(self.read_unread(buf))
+    }
+
+    /// See [Connection::receive_available]
+    async fn receive_available<S>(
+        &mut self,
+        messages: &mut S,
+        buf: &mut [u8],
+    ) -> Result<usize, TransportErrorKind>
+    where
+        S: Stream<Item = Result<Message, WebSocketError>> + Unpin,
+    {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // Wait for a message with data, so at least one byte is received
+        while self.unread().is_empty() {
+            let message = messages.next().await;
+            self.message_received(message)?;
+        }
+        Ok(self.read_unread(buf))
+    }
+}
+
+/// A [Connection] over a browser [WebSocket], for MQTT over WebSockets.
+/// Each packet is sent as a binary message, and received binary messages
+/// are treated as a stream of bytes, so packets may be split across messages
+/// or share a message, as allowed by the MQTT specification.
+pub struct ConnectionWebSocket {
+    inner: WebSocket,
+    buffer: MessageBuffer,
+}
+
+impl ConnectionWebSocket {
+    /// Create a new adapter, the [WebSocket] should have been opened with
+    /// the [MQTT_SUBPROTOCOL]
+    pub fn new(inner: WebSocket) -> Self {
+        ConnectionWebSocket {
+            inner,
+            buffer: MessageBuffer::default(),
+        }
+    }
+
+    /// Open a WebSocket to `url` (e.g. "wss://example.com:8884/mqtt"), using the
+    /// [MQTT_SUBPROTOCOL]
+    pub fn open(url: &str) -> Result<Self, JsError> {
+        let inner = WebSocket::open_with_protocol(url, MQTT_SUBPROTOCOL)?;
+        Ok(Self::new(inner))
+    }
+
+    /// Consume the adapter, returning the inner object.
+    pub fn into_inner(self) -> WebSocket {
+        self.inner
+    }
+
+    /// Borrow the inner object.
+    pub fn inner(&self) -> &WebSocket {
+        &self.inner
+    }
+
+    /// Mutably borrow the inner object.
+    pub fn inner_mut(&mut self) -> &mut WebSocket {
+        &mut self.inner
+    }
+}
+
+impl Connection for ConnectionWebSocket {
+    type Error = TransportErrorKind;
+
+    async fn send(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.inner
+            .feed(Message::Bytes(buf.to_vec()))
+            .await
+            .map_err(|_| TransportErrorKind::Other)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        SinkExt::flush(&mut self.inner)
+            .await
+            .map_err(|_| TransportErrorKind::Other)
+    }
+
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.buffer.receive(&mut self.inner, buf).await
+    }
+
+    async fn receive_if_ready(&mut self, buf: &mut [u8]) -> Result<bool, Self::Error> {
+        self.buffer.receive_if_ready(&mut self.inner, buf).await
+    }
+
+    async fn receive_ready(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.buffer.receive_ready(&mut self.inner, buf).await
+    }
+
+    async fn receive_available(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.buffer.receive_available(&mut self.inner, buf).await
+    }
+}
+
+/// Open a WebSocket to `url`, and create a [ClientNoQueue] using it
+pub fn client_websocket<'a, F, const P: usize>(
+    url: &str,
+    options: ClientNoQueueOptions,
+    buf: &'a mut [u8],
+    event_handler: F,
+) -> Result<ClientNoQueue<'a, ConnectionWebSocket, WasmDelay, WasmClock, F, P>, JsError>
+where
+    F: EventHandler<P>,
+{
+    let clock = WasmClock::new()?;
+    let connection = ConnectionWebSocket::open(url)?;
+    Ok(ClientNoQueue::new(
+        connection,
+        buf,
+        WasmDelay,
+        clock,
+        options,
+        event_handler,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::stream;
+    use gloo_net::websocket::events::CloseEvent;
+
+    use super::*;
+
+    /// A stream of messages that become ready in turn, then the WebSocket
+    /// either closes or waits forever with no message ready
+    fn messages(
+        messages: Vec<Result<Message, WebSocketError>>,
+        closed: bool,
+    ) -> impl Stream<Item = Result<Message, WebSocketError>> + Unpin {
+        let end = if closed {
+            stream::empty().left_stream()
+        } else {
+            stream::pending().right_stream()
+        };
+        stream::iter(messages).chain(end)
+    }
+
+    fn bytes(data: &[u8]) -> Result<Message, WebSocketError> {
+        Ok(Message::Bytes(data.to_vec()))
+    }
+
+    #[tokio::test]
+    async fn packet_is_received_from_partial_messages() {
+        let mut messages = messages(vec![bytes(&[1, 2]), bytes(&[3]), bytes(&[4, 5])], false);
+        let mut buffer = MessageBuffer::default();
+
+        let mut buf = [0; 4];
+        buffer.receive(&mut messages, &mut buf).await.unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+
+        // The rest of the last message is still available
+        assert!(buffer
+            .receive_if_ready(&mut messages, &mut buf[0..1])
+            .await
+            .unwrap());
+        assert_eq!(buf[0], 5);
+        assert!(!buffer
+            .receive_if_ready(&mut messages, &mut buf)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn packets_are_received_from_one_message() {
+        let mut messages = messages(vec![bytes(&[1, 2, 3, 4, 5])], false);
+        let mut buffer = MessageBuffer::default();
+
+        let mut buf = [0; 2];
+        buffer.receive(&mut messages, &mut buf).await.unwrap();
+        assert_eq!(buf, [1, 2]);
+
+        // Only unread data from the current message is returned
+        let mut buf = [0; 8];
+        assert_eq!(
+            buffer.receive_ready(&mut messages, &mut buf[0..1]).await,
+            Ok(1)
+        );
+        assert_eq!(
+            buffer.receive_available(&mut messages, &mut buf).await,
+            Ok(2)
+        );
+        assert_eq!(buf[0..2], [4, 5]);
+
+        // No message is ready
+        assert_eq!(buffer.receive_ready(&mut messages, &mut buf).await, Ok(0));
+    }
+
+    #[tokio::test]
+    async fn receive_available_waits_for_a_message_with_data() {
+        let mut messages = messages(vec![bytes(&[]), bytes(&[1, 2, 3])], false);
+        let mut buffer = MessageBuffer::default();
+
+        let mut buf = [0; 8];
+        assert_eq!(
+            buffer.receive_available(&mut messages, &mut []).await,
+            Ok(0)
+        );
+        assert_eq!(
+            buffer.receive_available(&mut messages, &mut buf).await,
+            Ok(3)
+        );
+        assert_eq!(buf[0..3], [1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn closed_socket_produces_closed_error() {
+        let mut buf = [0; 4];
+
+        // Closed part way through a packet
+        let mut ended = messages(vec![bytes(&[1, 2])], true);
+        let mut buffer = MessageBuffer::default();
+        assert_eq!(
+            buffer.receive(&mut ended, &mut buf).await,
+            Err(TransportErrorKind::Closed)
+        );
+        assert_eq!(
+            buffer.receive_available(&mut ended, &mut buf).await,
+            Err(TransportErrorKind::Closed)
+        );
+
+        let close = WebSocketError::ConnectionClose(CloseEvent {
+            code: 1000,
+            reason: String::new(),
+            was_clean: true,
+        });
+        let mut closed = messages(vec![Err(close)], false);
+        let mut buffer = MessageBuffer::default();
+        assert_eq!(
+            buffer.receive_ready(&mut closed, &mut buf).await,
+            Err(TransportErrorKind::Closed)
+        );
+    }
+
+    #[tokio::test]
+    async fn text_message_is_an_error() {
+        let mut messages = messages(vec![Ok(Message::Text("a".into()))], false);
+        let mut buffer = MessageBuffer::default();
+
+        let mut buf = [0; 1];
+        assert_eq!(
+            buffer.receive_if_ready(&mut messages, &mut buf).await,
+            Err(TransportErrorKind::Other)
+        );
+    }
+}
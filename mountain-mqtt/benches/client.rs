@@ -0,0 +1,171 @@
+//! Publishing and receiving messages with a [ClientNoQueue], run with `cargo bench`.
+//! The client uses an in-memory connection, so this measures the time spent
+//! in the client itself (state tracking, encoding, decoding and dispatching
+//! events to a handler) rather than in the network stack.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use heapless::Vec;
+use mountain_mqtt::{
+    client::{
+        Client, ClientNoQueue, ClientNoQueueOptions, ClientReceivedEvent, ConnectionSettings,
+        Delay, EventHandler, EventHandlerError,
+    },
+    codec::mqtt_writer::{MqttBufWriter, MqttWriter},
+    data::{packet_identifier::PublishPacketIdentifier, quality_of_service::QualityOfService},
+    error::TransportErrorKind,
+    packet_client::Connection,
+    packets::publish::Publish,
+    timing::Clock,
+};
+use tokio::runtime::Runtime;
+
+/// Maximum properties in received packets
+const P: usize = 16;
+
+/// Connack with success reason code and no properties
+const CONNACK: [u8; 5] = [0x20, 0x03, 0x00, 0x00, 0x00];
+
+/// Discards sent data, and receives a Connack followed by the same
+/// packet repeated indefinitely
+struct LoopbackConnection {
+    received: std::vec::Vec<u8>,
+    repeated: std::vec::Vec<u8>,
+    position: usize,
+}
+
+impl LoopbackConnection {
+    fn new(repeated: &[u8]) -> Self {
+        Self {
+            received: CONNACK.to_vec(),
+            repeated: repeated.to_vec(),
+            position: 0,
+        }
+    }
+}
+
+impl Connection for LoopbackConnection {
+    type Error = TransportErrorKind;
+
+    async fn send(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        black_box(buf);
+        Ok(())
+    }
+
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            if self.position == self.received.len() {
+                self.received.clone_from(&self.repeated);
+                self.position = 0;
+            }
+            let len = (buf.len() - filled).min(self.received.len() - self.position);
+            buf[filled..filled + len]
+                .copy_from_slice(&self.received[self.position..self.position + len]);
+            filled += len;
+            self.position += len;
+        }
+        Ok(())
+    }
+
+    async fn receive_if_ready(&mut self, buf: &mut [u8]) -> Result<bool, Self::Error> {
+        self.receive(buf).await?;
+        Ok(true)
+    }
+}
+
+struct NoDelay;
+
+impl Delay for NoDelay {
+    async fn delay_us(&mut self, _us: u32) {}
+}
+
+/// A [Clock] that stays at 0, so that no pings or timeouts are due while
+/// benchmarking, since the loopback connection never responds to pings
+struct NoTime;
+
+impl Clock for NoTime {
+    fn now_millis(&self) -> u64 {
+        0
+    }
+}
+
+struct IgnoreEvents;
+
+impl EventHandler<P> for IgnoreEvents {
+    async fn handle_event(
+        &mut self,
+        event: ClientReceivedEvent<'_, P>,
+    ) -> Result<(), EventHandlerError> {
+        black_box(event);
+        Ok(())
+    }
+}
+
+fn encode_publish(topic_name: &str, payload: &[u8]) -> std::vec::Vec<u8> {
+    let packet: Publish<'_, 0> = Publish::new(
+        false,
+        false,
+        topic_name,
+        PublishPacketIdentifier::None,
+        payload,
+        Vec::new(),
+    );
+    let mut buf = std::vec![0u8; payload.len() + 1024];
+    let mut writer = MqttBufWriter::new(&mut buf);
+    writer.put(&packet).unwrap();
+    let len = writer.position();
+    buf.truncate(len);
+    buf
+}
+
+/// Benchmark publishing, and receiving, a qos 0 message with `payload`
+fn bench_message(c: &mut Criterion, runtime: &Runtime, name: &str, payload: &[u8]) {
+    const TOPIC_NAME: &str = "sensors/temperature";
+
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Bytes(payload.len() as u64));
+
+    let encoded = encode_publish(TOPIC_NAME, payload);
+    let mut buf = [0u8; 2048];
+    let mut client: ClientNoQueue<'_, _, _, _, _, P> = ClientNoQueue::new(
+        LoopbackConnection::new(&encoded),
+        &mut buf,
+        NoDelay,
+        NoTime,
+        ClientNoQueueOptions::new(5000),
+        IgnoreEvents,
+    );
+    runtime
+        .block_on(client.connect(&ConnectionSettings::unauthenticated("bench")))
+        .unwrap();
+
+    group.bench_function("publish", |b| {
+        b.iter(|| {
+            runtime
+                .block_on(client.publish(
+                    TOPIC_NAME,
+                    black_box(payload),
+                    QualityOfService::Qos0,
+                    false,
+                ))
+                .unwrap()
+        })
+    });
+
+    group.bench_function("receive", |b| {
+        b.iter(|| assert!(runtime.block_on(client.poll(false)).unwrap()))
+    });
+
+    group.finish();
+}
+
+fn messages(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    bench_message(c, &runtime, "client_qos0_small", b"21.5");
+    bench_message(c, &runtime, "client_qos0_1kb", &[0x5au8; 1024]);
+}
+
+criterion_group!(benches, messages);
+criterion_main!(benches);
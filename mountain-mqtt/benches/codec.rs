@@ -0,0 +1,209 @@
+//! Encode/decode throughput for representative packets, run with `cargo bench`.
+//! Pass a filter argument to run only benchmarks whose names contain it,
+//! e.g. `cargo bench --bench codec -- decode`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use heapless::Vec;
+use mountain_mqtt::{
+    codec::{
+        mqtt_reader::{MqttBufReader, MqttReader},
+        mqtt_writer::{MqttBufWriter, MqttWriter},
+        write::Write,
+    },
+    data::{
+        packet_identifier::{PacketIdentifier, PublishPacketIdentifier},
+        property::{
+            AssignedClientIdentifier, ConnackProperty, ConnectProperty, ContentType,
+            MaximumPacketSize, MaximumQos, MessageExpiryInterval, PayloadFormatIndicator,
+            PublishProperty, ReasonString, ReceiveMaximum, RetainAvailable, ServerKeepAlive,
+            SessionExpiryInterval, SharedSubscriptionAvailable, SubscriptionIdentifierAvailable,
+            TopicAliasMaximum, UserProperty, WildcardSubscriptionAvailable, WillDelayInterval,
+            WillProperty,
+        },
+        quality_of_service::QualityOfService,
+        reason_code::ConnectReasonCode,
+        string_pair::StringPair,
+    },
+    packets::{
+        connack::Connack,
+        connect::{Connect, Will},
+        packet::Packet,
+        packet_generic::PacketGeneric,
+        publish::Publish,
+    },
+};
+
+/// Maximum properties in decoded packets
+const P: usize = 16;
+
+fn encode<W: Packet + Write>(packet: &W, buf: &mut [u8]) -> usize {
+    let mut writer = MqttBufWriter::new(buf);
+    writer.put(packet).unwrap();
+    writer.position()
+}
+
+fn decode(encoded: &[u8]) -> PacketGeneric<'_, P, P, 0> {
+    let mut reader = MqttBufReader::new(encoded);
+    reader.get().unwrap()
+}
+
+/// Benchmark encoding `packet`, and decoding the result
+fn bench_packet<W: Packet + Write>(c: &mut Criterion, name: &str, packet: &W) {
+    let mut buf = [0u8; 4096];
+    let len = encode(packet, &mut buf);
+
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Bytes(len as u64));
+
+    group.bench_function("encode", |b| {
+        let mut buf = [0u8; 4096];
+        b.iter(|| encode(black_box(packet), &mut buf))
+    });
+
+    let encoded = &buf[..len];
+    group.bench_function("decode", |b| b.iter(|| decode(black_box(encoded))));
+
+    group.finish();
+}
+
+fn packets(c: &mut Criterion) {
+    let small_publish: Publish<'_, 0> = Publish::new(
+        false,
+        false,
+        "sensors/temperature",
+        PublishPacketIdentifier::None,
+        b"21.5",
+        Vec::new(),
+    );
+    bench_packet(c, "publish_qos0_small", &small_publish);
+
+    let payload = [0x5au8; 1024];
+    let mut properties = Vec::new();
+    properties
+        .push(PublishProperty::PayloadFormatIndicator(
+            PayloadFormatIndicator::new(PayloadFormatIndicator::UNSPECIFIED),
+        ))
+        .unwrap();
+    properties
+        .push(PublishProperty::MessageExpiryInterval(
+            MessageExpiryInterval::new(60),
+        ))
+        .unwrap();
+    properties
+        .push(PublishProperty::ContentType(ContentType::new(
+            "application/octet-stream",
+        )))
+        .unwrap();
+    let large_publish: Publish<'_, 3> = Publish::new(
+        false,
+        false,
+        "devices/abc/telemetry",
+        PublishPacketIdentifier::Qos1(PacketIdentifier(42)),
+        &payload,
+        properties,
+    );
+    bench_packet(c, "publish_qos1_1kb", &large_publish);
+
+    let mut will_properties: Vec<WillProperty<'_>, 1> = Vec::new();
+    will_properties
+        .push(WillProperty::WillDelayInterval(WillDelayInterval::new(30)))
+        .unwrap();
+    let will = Will::new(
+        QualityOfService::Qos1,
+        true,
+        "devices/abc/status",
+        b"offline",
+        will_properties,
+    );
+    let mut connect_properties = Vec::new();
+    connect_properties
+        .push(ConnectProperty::SessionExpiryInterval(
+            SessionExpiryInterval::new(3600),
+        ))
+        .unwrap();
+    connect_properties
+        .push(ConnectProperty::TopicAliasMaximum(TopicAliasMaximum::new(
+            0,
+        )))
+        .unwrap();
+    let connect: Connect<'_, 2, 1> = Connect::new(
+        60,
+        Some("user"),
+        Some(b"password"),
+        "device-abc",
+        true,
+        Some(will),
+        connect_properties,
+    );
+    bench_packet(c, "connect_with_will", &connect);
+
+    let mut connack_properties: Vec<ConnackProperty<'_>, P> = Vec::new();
+    for property in [
+        ConnackProperty::SessionExpiryInterval(SessionExpiryInterval::new(3600)),
+        ConnackProperty::ReceiveMaximum(ReceiveMaximum::new(100)),
+        ConnackProperty::MaximumQos(MaximumQos::new(1)),
+        ConnackProperty::RetainAvailable(RetainAvailable::new(1)),
+        ConnackProperty::MaximumPacketSize(MaximumPacketSize::new(65536)),
+        ConnackProperty::AssignedClientIdentifier(AssignedClientIdentifier::new("auto-7f3a9c2e")),
+        ConnackProperty::TopicAliasMaximum(TopicAliasMaximum::new(10)),
+        ConnackProperty::ReasonString(ReasonString::new("connection accepted")),
+        ConnackProperty::UserProperty(UserProperty::new(StringPair::new("region", "eu-west"))),
+        ConnackProperty::WildcardSubscriptionAvailable(WildcardSubscriptionAvailable::new(1)),
+        ConnackProperty::SubscriptionIdentifierAvailable(SubscriptionIdentifierAvailable::new(1)),
+        ConnackProperty::SharedSubscriptionAvailable(SharedSubscriptionAvailable::new(1)),
+        ConnackProperty::ServerKeepAlive(ServerKeepAlive::new(120)),
+    ] {
+        connack_properties.push(property).unwrap();
+    }
+    let connack = Connack::new(false, ConnectReasonCode::Success, connack_properties);
+    bench_packet(c, "connack_many_properties", &connack);
+}
+
+/// Encode publish packets with a range of payload sizes, with and without
+/// backpatching of lengths, see [MqttBufWriter::with_backpatching]
+fn backpatching(c: &mut Criterion) {
+    let mut group = c.benchmark_group("publish_backpatching");
+
+    for payload_len in [16, 1024, 16 * 1024, 256 * 1024] {
+        let payload = std::vec![0xaau8; payload_len];
+        let mut buf = std::vec![0u8; payload_len + 1024];
+
+        let mut properties: Vec<PublishProperty<'_>, 4> = Vec::new();
+        for name in ["a", "b", "c", "d"] {
+            properties
+                .push(PublishProperty::UserProperty(UserProperty::new(
+                    StringPair::new(name, "value"),
+                )))
+                .unwrap();
+        }
+        let packet = Publish::new(
+            false,
+            false,
+            "a/topic/for/benchmarking",
+            PublishPacketIdentifier::Qos1(PacketIdentifier(1)),
+            &payload,
+            properties,
+        );
+
+        group.throughput(Throughput::Bytes(encode(&packet, &mut buf) as u64));
+        for backpatch in [false, true] {
+            let id = BenchmarkId::new(
+                if backpatch { "backpatch" } else { "two_pass" },
+                payload_len,
+            );
+            group.bench_function(id, |b| {
+                b.iter(|| {
+                    let mut writer = MqttBufWriter::new(&mut buf).with_backpatching(backpatch);
+                    writer.put(black_box(&packet)).unwrap();
+                    writer.position()
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, packets, backpatching);
+criterion_main!(benches);
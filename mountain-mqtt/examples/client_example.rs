@@ -1,7 +1,7 @@
 use mountain_mqtt::{
     client::{
-        Client, ClientError, ClientReceivedEvent, ConnectionSettings, EventHandler,
-        EventHandlerError,
+        Client, ClientError, ClientNoQueueOptions, ClientReceivedEvent, ConnectionSettings,
+        EventHandler, EventHandlerError,
     },
     data::quality_of_service::QualityOfService,
     tokio::client_tcp,
@@ -36,7 +36,7 @@ impl<const P: usize> EventHandler<P> for SenderEventHandler {
 async fn main() -> Result<(), ClientError> {
     let ip = core::net::Ipv4Addr::new(127, 0, 0, 1);
     let port = 1883;
-    let timeout_millis = 5000;
+    let options = ClientNoQueueOptions::new(5000);
     let mut buf = [0; 1024];
 
     // We'll use a channel to handle incoming messages, this would allow us to receive
@@ -50,7 +50,7 @@ async fn main() -> Result<(), ClientError> {
     // published application message is received.
     // This sends copies of the message contents to our channel for later processing.
     let mut client =
-        client_tcp::<SenderEventHandler, 16>(ip, port, timeout_millis, &mut buf, handler).await;
+        client_tcp::<SenderEventHandler, 16>(ip, port, options, &mut buf, handler).await;
 
     // Send a Connect packet to connect to the server.
     // `unauthenticated` uses default settings and no username/password, see `Connect::new` for
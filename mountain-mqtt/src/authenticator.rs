@@ -0,0 +1,72 @@
+#[cfg(feature = "display")]
+use core::fmt::{Display, Formatter};
+
+/// Errors produced by an [Authenticator] when it can't continue an
+/// authentication exchange. These propagate to the user of the client.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AuthenticatorError {
+    /// Authentication data from the server was missing, or invalid for
+    /// the authentication method, e.g. a server signature didn't verify
+    InvalidServerData,
+
+    /// The authenticator could not produce a response to the server, e.g.
+    /// because credentials are not available
+    ResponseUnavailable,
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for AuthenticatorError {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::InvalidServerData => defmt::write!(f, "InvalidServerData"),
+            Self::ResponseUnavailable => defmt::write!(f, "ResponseUnavailable"),
+        }
+    }
+}
+
+#[cfg(feature = "display")]
+impl Display for AuthenticatorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InvalidServerData => write!(f, "InvalidServerData"),
+            Self::ResponseUnavailable => write!(f, "ResponseUnavailable"),
+        }
+    }
+}
+
+/// Handles the client side of an MQTT 5 enhanced authentication exchange,
+/// used by [ClientNoQueue::reauthenticate](crate::client::ClientNoQueue::reauthenticate)
+/// to respond to the server while re-authenticating.
+///
+/// Each time the server sends an Auth packet with
+/// [AuthReasonCode::ContinueAuthentication](crate::data::reason_code::AuthReasonCode::ContinueAuthentication),
+/// [Authenticator::continue_authentication] is called with the server's
+/// authentication data, and the client then responds with [Authenticator::method]
+/// and [Authenticator::response_data]. When the server completes authentication,
+/// [Authenticator::authenticated] is called with any final authentication data.
+pub trait Authenticator {
+    /// The authentication method, which must be the same as the
+    /// [AuthenticationMethod](crate::data::property::AuthenticationMethod)
+    /// property used to connect, e.g. "SCRAM-SHA-256"
+    fn method(&self) -> &str;
+
+    /// Handle authentication data from the server (if any), preparing the
+    /// response data to be returned by [Authenticator::response_data]
+    fn continue_authentication(
+        &mut self,
+        server_data: Option<&[u8]>,
+    ) -> Result<(), AuthenticatorError>;
+
+    /// The authentication data to send in response to the server, prepared
+    /// by the most recent call to [Authenticator::continue_authentication]
+    fn response_data(&self) -> &[u8];
+
+    /// Handle the server completing authentication successfully, with final
+    /// authentication data from the server (if any), e.g. to verify a server
+    /// signature. Returning an error will fail the authentication exchange,
+    /// although the server will consider the client to be authenticated.
+    fn authenticated(&mut self, server_data: Option<&[u8]>) -> Result<(), AuthenticatorError> {
+        let _ = server_data;
+        Ok(())
+    }
+}
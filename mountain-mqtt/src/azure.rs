@@ -0,0 +1,453 @@
+//! Support for connecting devices to Azure IoT Hub, which authenticates
+//! using a shared access signature (SAS) token with an expiry time, and uses
+//! fixed topic names for device messages.
+//!
+//! A SAS token is sent as the password when connecting, so it only needs
+//! to be valid when each connection is made - before each connection or
+//! reconnection, call [AzureIotHubCredentials::refresh_if_expiring] to
+//! regenerate the token if it has expired or is close to expiring.
+//! Note that IoT Hub disconnects the client when the token expires, so the
+//! token lifetime should be longer than connections are expected to last,
+//! or the client should reconnect with a new token before expiry.
+
+#[cfg(feature = "display")]
+use core::fmt::{Display, Formatter};
+
+use heapless::String;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{
+    client::ConnectionSettings,
+    data::topic_buf::{TopicBuf, TopicBufError},
+};
+
+/// The IoT Hub API version requested in the username
+pub const AZURE_API_VERSION: &str = "2021-04-12";
+
+/// The maximum length of a decoded shared access key, IoT Hub uses 32 or
+/// 64 byte keys
+const KEY_MAX_LEN: usize = 64;
+
+/// Length of a base64 encoded SHA-256 HMAC
+const SIGNATURE_LEN: usize = 44;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE64_PAD: u8 = b'=';
+
+/// Errors producing Azure IoT Hub credentials or topics
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AzureError {
+    /// The shared access key is not valid base64, or is too long
+    InvalidKey,
+
+    /// A username, token or topic would not fit in the provided capacity
+    Overflow,
+
+    /// A topic name could not be built, e.g. because the device id is invalid
+    Topic(TopicBufError),
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for AzureError {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::InvalidKey => defmt::write!(f, "InvalidKey"),
+            Self::Overflow => defmt::write!(f, "Overflow"),
+            Self::Topic(e) => defmt::write!(f, "Topic({})", e),
+        }
+    }
+}
+
+#[cfg(feature = "display")]
+impl Display for AzureError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InvalidKey => write!(f, "InvalidKey"),
+            Self::Overflow => write!(f, "Overflow"),
+            Self::Topic(e) => write!(f, "Topic({})", e),
+        }
+    }
+}
+
+impl From<TopicBufError> for AzureError {
+    fn from(value: TopicBufError) -> Self {
+        match value {
+            TopicBufError::Overflow => AzureError::Overflow,
+            e => AzureError::Topic(e),
+        }
+    }
+}
+
+fn push_str<const N: usize>(s: &mut String<N>, text: &str) -> Result<(), AzureError> {
+    s.push_str(text).map_err(|_| AzureError::Overflow)
+}
+
+/// Append text, percent encoding all characters except the unreserved
+/// characters `A-Z a-z 0-9 - _ . ~`
+fn push_percent_encoded<const N: usize>(s: &mut String<N>, text: &str) -> Result<(), AzureError> {
+    const HEX_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+    for b in text.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            s.push(b as char).map_err(|_| AzureError::Overflow)?;
+        } else {
+            for c in [
+                b'%',
+                HEX_DIGITS[(b >> 4) as usize],
+                HEX_DIGITS[(b & 0x0F) as usize],
+            ] {
+                s.push(c as char).map_err(|_| AzureError::Overflow)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn base64_value(c: u8) -> Result<u32, AzureError> {
+    BASE64_ALPHABET
+        .iter()
+        .position(|a| *a == c)
+        .map(|p| p as u32)
+        .ok_or(AzureError::InvalidKey)
+}
+
+/// Decode standard base64 with padding, returning the decoded length
+fn base64_decode(encoded: &str, out: &mut [u8]) -> Result<usize, AzureError> {
+    let encoded = encoded.as_bytes();
+    // `usize::is_multiple_of` needs Rust 1.87, so avoid it to support older toolchains
+    #[allow(clippy::manual_is_multiple_of)]
+    if encoded.is_empty() || encoded.len() % 4 != 0 {
+        return Err(AzureError::InvalidKey);
+    }
+    let mut len = 0;
+    for chunk in encoded.chunks(4) {
+        let padding = chunk.iter().rev().take_while(|c| **c == BASE64_PAD).count();
+        if padding > 2 || (padding > 0 && len + 3 < encoded.len() / 4 * 3) {
+            return Err(AzureError::InvalidKey);
+        }
+        let mut n = 0u32;
+        for c in chunk.iter().take(4 - padding) {
+            n = (n << 6) | base64_value(*c)?;
+        }
+        n <<= 6 * padding as u32;
+        let bytes = n.to_be_bytes();
+        for b in &bytes[1..4 - padding] {
+            *out.get_mut(len).ok_or(AzureError::InvalidKey)? = *b;
+            len += 1;
+        }
+    }
+    Ok(len)
+}
+
+/// Encode as standard base64 with padding
+fn base64_encode(data: &[u8], out: &mut [u8]) -> usize {
+    let mut len = 0;
+    for chunk in data.chunks(3) {
+        let mut bytes = [0u8; 4];
+        bytes[1..1 + chunk.len()].copy_from_slice(chunk);
+        let n = u32::from_be_bytes(bytes);
+        for i in 0..4 {
+            out[len] = if i <= chunk.len() {
+                BASE64_ALPHABET[((n >> (18 - 6 * i)) & 0x3F) as usize]
+            } else {
+                BASE64_PAD
+            };
+            len += 1;
+        }
+    }
+    len
+}
+
+/// Produce the username used to connect a device to IoT Hub,
+/// `{host_name}/{device_id}/?api-version={AZURE_API_VERSION}`
+pub fn azure_username<const N: usize>(
+    host_name: &str,
+    device_id: &str,
+) -> Result<String<N>, AzureError> {
+    let mut username = String::new();
+    push_str(&mut username, host_name)?;
+    push_str(&mut username, "/")?;
+    push_str(&mut username, device_id)?;
+    push_str(&mut username, "/?api-version=")?;
+    push_str(&mut username, AZURE_API_VERSION)?;
+    Ok(username)
+}
+
+/// Produce a SAS token for a device, valid until `expiry` (in seconds since
+/// the Unix epoch), signed using the device's base64 encoded shared access key.
+/// This has the form
+/// `SharedAccessSignature sr={resource_uri}&sig={signature}&se={expiry}`,
+/// where the resource URI is `{host_name}/devices/{device_id}`.
+pub fn sas_token<const N: usize>(
+    host_name: &str,
+    device_id: &str,
+    key: &str,
+    expiry: u64,
+) -> Result<String<N>, AzureError> {
+    let mut key_bytes = [0u8; KEY_MAX_LEN];
+    let mac = base64_decode(key, &mut key_bytes).and_then(|key_len| {
+        Hmac::<Sha256>::new_from_slice(&key_bytes[0..key_len]).map_err(|_| AzureError::InvalidKey)
+    });
+    // The decoded key is secret, so wipe it as soon as the mac holds it,
+    // whether or not the key is valid
+    #[cfg(feature = "zeroize")]
+    zeroize::Zeroize::zeroize(&mut key_bytes);
+    let mut mac = mac?;
+
+    let mut token = String::new();
+    push_str(&mut token, "SharedAccessSignature sr=")?;
+    let resource_uri_start = token.len();
+    push_percent_encoded(&mut token, host_name)?;
+    push_percent_encoded(&mut token, "/devices/")?;
+    push_percent_encoded(&mut token, device_id)?;
+    let resource_uri_end = token.len();
+
+    let mut expiry_digits = [0u8; 20];
+    let expiry_digits = format_decimal(expiry, &mut expiry_digits);
+
+    // Sign the encoded resource URI and the expiry, separated by a newline
+    mac.update(token[resource_uri_start..resource_uri_end].as_bytes());
+    mac.update(b"\n");
+    mac.update(expiry_digits.as_bytes());
+    let signature = mac.finalize().into_bytes();
+
+    let mut signature_base64 = [0u8; SIGNATURE_LEN];
+    let len = base64_encode(&signature, &mut signature_base64);
+    // Base64 output is always ASCII
+    let signature_base64 = core::str::from_utf8(&signature_base64[0..len]).unwrap();
+
+    push_str(&mut token, "&sig=")?;
+    push_percent_encoded(&mut token, signature_base64)?;
+    push_str(&mut token, "&se=")?;
+    push_str(&mut token, expiry_digits)?;
+    Ok(token)
+}
+
+fn format_decimal(mut value: u64, buf: &mut [u8; 20]) -> &str {
+    let mut start = buf.len();
+    loop {
+        start -= 1;
+        buf[start] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+    // Decimal digits are always ASCII
+    core::str::from_utf8(&buf[start..]).unwrap()
+}
+
+/// The topic name a device publishes telemetry to,
+/// `devices/{device_id}/messages/events/`
+pub fn device_to_cloud_topic<const N: usize>(device_id: &str) -> Result<TopicBuf<N>, AzureError> {
+    let mut topic = TopicBuf::new();
+    topic.push_segment("devices")?;
+    topic.push_segment(device_id)?;
+    topic.push_str("/messages/events/")?;
+    Ok(topic)
+}
+
+/// The topic filter a device subscribes to, to receive cloud to device
+/// messages, `devices/{device_id}/messages/devicebound/#`
+pub fn cloud_to_device_topic_filter<const N: usize>(
+    device_id: &str,
+) -> Result<String<N>, AzureError> {
+    let mut topic = TopicBuf::<N>::new();
+    topic.push_segment("devices")?;
+    topic.push_segment(device_id)?;
+    topic.push_str("/messages/devicebound/")?;
+    let mut filter = String::new();
+    push_str(&mut filter, topic.as_str())?;
+    push_str(&mut filter, "#")?;
+    Ok(filter)
+}
+
+/// Credentials for connecting a device to IoT Hub, holding a username of up
+/// to `U` bytes and a SAS token of up to `T` bytes.
+/// The token is produced by [AzureIotHubCredentials::refresh], and should be
+/// regenerated before each connection using
+/// [AzureIotHubCredentials::refresh_if_expiring].
+/// Times are in seconds since the Unix epoch, and must be provided by the
+/// caller (e.g. from an RTC or SNTP).
+pub struct AzureIotHubCredentials<'a, const U: usize, const T: usize> {
+    host_name: &'a str,
+    device_id: &'a str,
+    key: &'a str,
+    token_lifetime: u64,
+    username: String<U>,
+    token: String<T>,
+    expiry: u64,
+}
+
+impl<'a, const U: usize, const T: usize> AzureIotHubCredentials<'a, U, T> {
+    /// Create credentials for a device with IoT Hub host name (e.g.
+    /// "myhub.azure-devices.net"), device id and base64 encoded shared access
+    /// key, producing tokens valid for `token_lifetime` seconds.
+    /// No token is produced until [AzureIotHubCredentials::refresh] or
+    /// [AzureIotHubCredentials::refresh_if_expiring] is called.
+    pub fn new(
+        host_name: &'a str,
+        device_id: &'a str,
+        key: &'a str,
+        token_lifetime: u64,
+    ) -> Result<Self, AzureError> {
+        Ok(Self {
+            host_name,
+            device_id,
+            key,
+            token_lifetime,
+            username: azure_username(host_name, device_id)?,
+            token: String::new(),
+            expiry: 0,
+        })
+    }
+
+    /// Produce a new token, valid from `now` for the token lifetime
+    pub fn refresh(&mut self, now: u64) -> Result<(), AzureError> {
+        let expiry = now.saturating_add(self.token_lifetime);
+        self.token = sas_token(self.host_name, self.device_id, self.key, expiry)?;
+        self.expiry = expiry;
+        Ok(())
+    }
+
+    /// Produce a new token if there is no token, or the current token expires
+    /// within `margin` seconds of `now`. Call this before each connection.
+    /// On success, returns true if the token was regenerated.
+    pub fn refresh_if_expiring(&mut self, now: u64, margin: u64) -> Result<bool, AzureError> {
+        if self.token.is_empty() || now.saturating_add(margin) >= self.expiry {
+            self.refresh(now)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// The expiry time of the current token
+    pub fn expiry(&self) -> u64 {
+        self.expiry
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// The current token, empty if no token has been produced
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Settings to connect using the device id as client id, and the current token
+    pub fn connection_settings(&self) -> ConnectionSettings<'_> {
+        ConnectionSettings::authenticated(self.device_id, &self.username, self.token.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bytes 0 to 31
+    const KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+    const HOST_NAME: &str = "myhub.azure-devices.net";
+
+    #[test]
+    fn base64_round_trips() {
+        let mut decoded = [0u8; 8];
+        for (text, encoded) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+        ] {
+            let mut buf = [0u8; 8];
+            let len = base64_encode(text.as_bytes(), &mut buf);
+            assert_eq!(&buf[0..len], encoded.as_bytes());
+            if !text.is_empty() {
+                let len = base64_decode(encoded, &mut decoded).unwrap();
+                assert_eq!(&decoded[0..len], text.as_bytes());
+            }
+        }
+        assert_eq!(
+            base64_decode("Zg=", &mut decoded),
+            Err(AzureError::InvalidKey)
+        );
+        assert_eq!(
+            base64_decode("Z===", &mut decoded),
+            Err(AzureError::InvalidKey)
+        );
+        assert_eq!(
+            base64_decode("Zg==Zm8=", &mut decoded),
+            Err(AzureError::InvalidKey)
+        );
+        assert_eq!(
+            base64_decode("Z!9v", &mut decoded),
+            Err(AzureError::InvalidKey)
+        );
+        assert_eq!(
+            base64_decode("Zm9vYmFyYmF6", &mut decoded),
+            Err(AzureError::InvalidKey)
+        );
+    }
+
+    #[test]
+    fn sas_token_is_signed() {
+        let token: String<256> = sas_token(HOST_NAME, "sensor01", KEY, 1_700_000_000).unwrap();
+        assert_eq!(
+            token,
+            "SharedAccessSignature sr=myhub.azure-devices.net%2Fdevices%2Fsensor01\
+            &sig=b4%2BmBklaD%2F378PCgv4RG%2FuSXadeCXgl03%2B9A3sotihY%3D&se=1700000000"
+        );
+        assert_eq!(
+            sas_token::<32>(HOST_NAME, "sensor01", KEY, 1_700_000_000),
+            Err(AzureError::Overflow)
+        );
+        assert_eq!(
+            sas_token::<256>(HOST_NAME, "sensor01", "not a key", 1_700_000_000),
+            Err(AzureError::InvalidKey)
+        );
+    }
+
+    #[test]
+    fn credentials_refresh_before_expiry() {
+        let mut credentials =
+            AzureIotHubCredentials::<'_, 64, 256>::new(HOST_NAME, "sensor01", KEY, 3600).unwrap();
+        assert_eq!(
+            credentials.username(),
+            "myhub.azure-devices.net/sensor01/?api-version=2021-04-12"
+        );
+        assert_eq!(credentials.token(), "");
+
+        assert_eq!(
+            credentials.refresh_if_expiring(1_699_996_400, 300),
+            Ok(true)
+        );
+        assert_eq!(credentials.expiry(), 1_700_000_000);
+        assert!(credentials.token().ends_with("&se=1700000000"));
+        assert_eq!(
+            credentials.refresh_if_expiring(1_699_999_000, 300),
+            Ok(false)
+        );
+        assert_eq!(
+            credentials.refresh_if_expiring(1_699_999_700, 300),
+            Ok(true)
+        );
+        assert_eq!(credentials.expiry(), 1_700_003_300);
+
+        let settings = credentials.connection_settings();
+        assert_eq!(settings.client_id(), "sensor01");
+    }
+
+    #[test]
+    fn topics_include_device_id() {
+        let topic: TopicBuf<64> = device_to_cloud_topic("sensor01").unwrap();
+        assert_eq!(topic.as_str(), "devices/sensor01/messages/events/");
+        let filter: String<64> = cloud_to_device_topic_filter("sensor01").unwrap();
+        assert_eq!(filter, "devices/sensor01/messages/devicebound/#");
+        assert_eq!(
+            device_to_cloud_topic::<64>("a/b"),
+            Err(AzureError::Topic(TopicBufError::SeparatorInSegment))
+        );
+    }
+}
@@ -0,0 +1,446 @@
+use core::{
+    future::Future,
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use heapless::Vec;
+
+use crate::{
+    client::{Client, ClientError, ClientNoQueueOptions, ConnectionSettings, Delay, EventHandler},
+    client_state::{ClientState, ClientStateNoQueue, ClientStateSubscriptions, PendingCounts},
+    data::{
+        property::PublishProperty, quality_of_service::QualityOfService,
+        subscription_options::SubscriptionOptions,
+    },
+    managed_client::ManagedClient,
+    packet_client::Connection,
+    packets::connect::{Connect, Will},
+    timing::Clock,
+};
+
+/// Run a future to completion on the current thread.
+/// The futures used by [BlockingClient] perform blocking IO and so never
+/// return [Poll::Pending], and are complete after the first poll. If a
+/// future does return pending (e.g. an [EventHandler] awaiting something
+/// else), this yields the thread and polls again until it is ready.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        std::thread::yield_now();
+    }
+}
+
+/// A [Delay] that blocks the current thread
+#[derive(Clone)]
+pub struct BlockingDelay;
+
+impl Delay for BlockingDelay {
+    async fn delay_us(&mut self, us: u32) {
+        std::thread::sleep(Duration::from_micros(us as u64));
+    }
+}
+
+/// A [Clock] using [Instant], with times measured from when this was created
+#[derive(Clone)]
+pub struct BlockingClock {
+    start: Instant,
+}
+
+impl BlockingClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for BlockingClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for BlockingClock {
+    fn now_millis(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+}
+
+/// A [Connection] using blocking reads and writes on a [TcpStream]
+pub struct ConnectionBlockingTcpStream {
+    inner: TcpStream,
+}
+
+impl ConnectionBlockingTcpStream {
+    /// Create a new adapter
+    pub fn new(inner: TcpStream) -> Self {
+        ConnectionBlockingTcpStream { inner }
+    }
+
+    /// Connect to the given address, with an optional timeout for connecting,
+    /// and for each read and write once connected. If a read or write times out,
+    /// the client will produce an error.
+    pub fn connect(addr: impl ToSocketAddrs, timeout: Option<Duration>) -> io::Result<Self> {
+        let mut result = Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "no addresses to connect to",
+        ));
+        for addr in addr.to_socket_addrs()? {
+            result = match timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+            if result.is_ok() {
+                break;
+            }
+        }
+        let inner = result?;
+        inner.set_read_timeout(timeout)?;
+        inner.set_write_timeout(timeout)?;
+        Ok(Self::new(inner))
+    }
+
+    /// Consume the adapter, returning the inner object.
+    pub fn into_inner(self) -> TcpStream {
+        self.inner
+    }
+
+    /// Borrow the inner object.
+    pub fn inner(&self) -> &TcpStream {
+        &self.inner
+    }
+
+    /// Mutably borrow the inner object.
+    pub fn inner_mut(&mut self) -> &mut TcpStream {
+        &mut self.inner
+    }
+}
+
+impl Connection for ConnectionBlockingTcpStream {
+    type Error = io::Error;
+
+    async fn send(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.inner.write_all(buf)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush()
+    }
+
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.inner.read_exact(buf)
+    }
+
+    async fn receive_if_ready(&mut self, buf: &mut [u8]) -> Result<bool, Self::Error> {
+        // Read without blocking to see whether any data is available
+        self.inner.set_nonblocking(true)?;
+        let result = self.inner.read(buf);
+        self.inner.set_nonblocking(false)?;
+
+        match result {
+            // If length is 0, the stream's read half is closed, this is a read error
+            // since no data will be read in future
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+
+            // We have read some bytes, block to read the rest
+            Ok(n) => {
+                if n < buf.len() {
+                    self.receive(&mut buf[n..]).await?;
+                }
+                Ok(true)
+            }
+
+            // There is no data at present - not an error, just return false
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+
+            // Any other error is a real receive error
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn receive_ready(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.inner.set_nonblocking(true)?;
+        let result = self.inner.read(buf);
+        self.inner.set_nonblocking(false)?;
+
+        match result {
+            // The stream's read half is closed, no data will be read in future
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(n) => Ok(n),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn receive_available(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.inner.read(buf) {
+            // The stream's read half is closed, no data will be read in future
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            result => result,
+        }
+    }
+}
+
+/// A synchronous MQTT client, for use without an async runtime, e.g. in
+/// command line tools, tests and simple services.
+/// This drives a [ManagedClient] with blocking reads and writes on a
+/// [TcpStream], so behaves in the same way as [ClientNoQueue](crate::client::ClientNoQueue),
+/// with each method returning once any response from the server has been received.
+/// Events are passed to an [EventHandler] - since the client does not use an
+/// async runtime, the handler should not await anything other than immediately
+/// ready futures.
+pub struct BlockingClient<'a, F, const P: usize, S = ClientStateNoQueue>
+where
+    F: EventHandler<P>,
+    S: ClientState,
+{
+    client: ManagedClient<'a, ConnectionBlockingTcpStream, BlockingDelay, BlockingClock, S, F, P>,
+}
+
+impl<'a, F, const P: usize, S> BlockingClient<'a, F, P, S>
+where
+    F: EventHandler<P>,
+    S: ClientState + Default,
+{
+    /// Create a client using a connected [ConnectionBlockingTcpStream], using
+    /// `buf` to encode and decode packets
+    pub fn new(
+        connection: ConnectionBlockingTcpStream,
+        buf: &'a mut [u8],
+        options: ClientNoQueueOptions,
+        event_handler: F,
+    ) -> Self {
+        Self {
+            client: ManagedClient::new(
+                connection,
+                buf,
+                BlockingDelay,
+                BlockingClock::new(),
+                options,
+                event_handler,
+            ),
+        }
+    }
+}
+
+impl<'a, F, const P: usize, S> BlockingClient<'a, F, P, S>
+where
+    F: EventHandler<P>,
+    S: ClientState,
+{
+    /// Wrap a [ManagedClient], e.g. one configured using its builder methods
+    pub fn from_managed_client(
+        client: ManagedClient<
+            'a,
+            ConnectionBlockingTcpStream,
+            BlockingDelay,
+            BlockingClock,
+            S,
+            F,
+            P,
+        >,
+    ) -> Self {
+        Self { client }
+    }
+
+    /// Consume this [BlockingClient] and return the underlying [ManagedClient]
+    pub fn into_managed_client(
+        self,
+    ) -> ManagedClient<'a, ConnectionBlockingTcpStream, BlockingDelay, BlockingClock, S, F, P> {
+        self.client
+    }
+
+    /// See [Client::connect]
+    pub fn connect(&mut self, settings: &ConnectionSettings<'_>) -> Result<(), ClientError> {
+        block_on(self.client.connect(settings))
+    }
+
+    /// See [Client::connect_with_will]
+    pub fn connect_with_will<const W: usize>(
+        &mut self,
+        settings: &ConnectionSettings<'_>,
+        will: Option<Will<'_, W>>,
+    ) -> Result<(), ClientError> {
+        block_on(self.client.connect_with_will(settings, will))
+    }
+
+    /// See [Client::connect_with_packet]
+    pub fn connect_with_packet<const PP: usize, const W: usize>(
+        &mut self,
+        packet: Connect<'_, PP, W>,
+    ) -> Result<(), ClientError> {
+        block_on(self.client.connect_with_packet(packet))
+    }
+
+    /// See [Client::disconnect]
+    pub fn disconnect(&mut self) -> Result<(), ClientError> {
+        block_on(self.client.disconnect())
+    }
+
+    /// See [Client::send_ping]
+    pub fn send_ping(&mut self) -> Result<(), ClientError> {
+        block_on(self.client.send_ping())
+    }
+
+    /// See [Client::poll] - if `wait` is true, this blocks until a packet is
+    /// received, or the read timeout of the connection elapses
+    pub fn poll(&mut self, wait: bool) -> Result<bool, ClientError> {
+        block_on(self.client.poll(wait))
+    }
+
+    /// See [Client::subscribe]
+    pub fn subscribe(
+        &mut self,
+        topic_name: &str,
+        maximum_qos: QualityOfService,
+    ) -> Result<(), ClientError> {
+        block_on(self.client.subscribe(topic_name, maximum_qos))
+    }
+
+    /// See [Client::subscribe_with_options]
+    pub fn subscribe_with_options(
+        &mut self,
+        topic_name: &str,
+        options: SubscriptionOptions,
+    ) -> Result<(), ClientError> {
+        block_on(self.client.subscribe_with_options(topic_name, options))
+    }
+
+    /// See [Client::unsubscribe]
+    pub fn unsubscribe(&mut self, topic_name: &str) -> Result<(), ClientError> {
+        block_on(self.client.unsubscribe(topic_name))
+    }
+
+    /// See [Client::publish]
+    pub fn publish(
+        &mut self,
+        topic_name: &str,
+        payload: &[u8],
+        qos: QualityOfService,
+        retain: bool,
+    ) -> Result<(), ClientError> {
+        block_on(self.client.publish(topic_name, payload, qos, retain))
+    }
+
+    /// See [Client::publish_with_properties]
+    pub fn publish_with_properties<'b, const PP: usize>(
+        &'b mut self,
+        topic_name: &'b str,
+        payload: &'b [u8],
+        qos: QualityOfService,
+        retain: bool,
+        properties: Vec<PublishProperty<'b>, PP>,
+    ) -> Result<(), ClientError> {
+        block_on(
+            self.client
+                .publish_with_properties(topic_name, payload, qos, retain, properties),
+        )
+    }
+
+    /// See [ManagedClient::pending_counts]
+    pub fn pending_counts(&self) -> PendingCounts {
+        self.client.pending_counts()
+    }
+
+    /// See [ManagedClient::subscriptions]
+    pub fn subscriptions(&self) -> Option<&ClientStateSubscriptions> {
+        self.client.subscriptions()
+    }
+
+    /// Borrow the event handler, e.g. to inspect events it has stored
+    pub fn event_handler(&self) -> &F {
+        self.client.event_handler()
+    }
+
+    /// Mutably borrow the event handler, e.g. to take events it has stored
+    pub fn event_handler_mut(&mut self) -> &mut F {
+        self.client.event_handler_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::client::{ClientReceivedEvent, EventHandlerError};
+
+    struct CountAcks(usize);
+
+    impl EventHandler<16> for CountAcks {
+        async fn handle_event(
+            &mut self,
+            event: ClientReceivedEvent<'_, 16>,
+        ) -> Result<(), EventHandlerError> {
+            if matches!(event, ClientReceivedEvent::Ack(_)) {
+                self.0 += 1;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn blocking_client_connects_and_publishes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = [0u8; 2];
+
+            // Connect, respond with Connack
+            stream.read_exact(&mut header).unwrap();
+            assert_eq!(header[0], 0x10);
+            let mut rest = std::vec![0u8; header[1] as usize];
+            stream.read_exact(&mut rest).unwrap();
+            stream.write_all(&[0x20, 0x03, 0x00, 0x00, 0x00]).unwrap();
+
+            // Qos 0 publish to "a" with payload "b"
+            let mut publish = [0u8; 7];
+            stream.read_exact(&mut publish).unwrap();
+            assert_eq!(publish, [0x30, 0x05, 0x00, 0x01, b'a', 0x00, b'b']);
+
+            // Disconnect
+            stream.read_exact(&mut header).unwrap();
+            assert_eq!(header, [0xe0, 0x00]);
+        });
+
+        let connection =
+            ConnectionBlockingTcpStream::connect(addr, Some(Duration::from_secs(5))).unwrap();
+        let mut buf = [0; 1024];
+        let mut client: BlockingClient<'_, CountAcks, 16> = BlockingClient::new(
+            connection,
+            &mut buf,
+            ClientNoQueueOptions::new(5000),
+            CountAcks(0),
+        );
+
+        client
+            .connect(&ConnectionSettings::unauthenticated("blocking"))
+            .unwrap();
+        assert_eq!(client.event_handler().0, 1);
+        client
+            .publish("a", b"b", QualityOfService::Qos0, false)
+            .unwrap();
+        assert!(!client.poll(false).unwrap());
+        client.disconnect().unwrap();
+
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,867 @@
+#[cfg(feature = "display")]
+use core::fmt::{Display, Formatter};
+
+use heapless::{Deque, String, Vec};
+
+use crate::{
+    client::{Client, ClientError, ClientReceivedEvent, EventHandler, EventHandlerError},
+    data::{
+        quality_of_service::QualityOfService,
+        topic_filter::{
+            topic_filter_capture, topic_filter_matches, MULTI_LEVEL_WILDCARD,
+            SINGLE_LEVEL_WILDCARD, TOPIC_LEVEL_SEPARATOR,
+        },
+    },
+};
+#[cfg(feature = "client-codec")]
+use crate::{
+    client::{ClientNoQueue, Delay},
+    packet_client::Connection,
+    timing::Clock,
+};
+
+/// One of the two clients connected by a [Bridge]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BridgeSide {
+    Local,
+    Remote,
+}
+
+impl BridgeSide {
+    /// The opposite side of the bridge
+    pub fn other(&self) -> BridgeSide {
+        match self {
+            BridgeSide::Local => BridgeSide::Remote,
+            BridgeSide::Remote => BridgeSide::Local,
+        }
+    }
+}
+
+#[cfg(feature = "display")]
+impl Display for BridgeSide {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Local => write!(f, "Local"),
+            Self::Remote => write!(f, "Remote"),
+        }
+    }
+}
+
+/// [Bridge] error
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BridgeError {
+    /// The client on the given side produced an error, e.g. it may need
+    /// to be reconnected
+    Client(BridgeSide, ClientError),
+
+    /// A message could not be forwarded since its remapped topic name is
+    /// too long for the bridge's topic name capacity
+    TopicNameTooLong,
+
+    /// A message could not be forwarded since its topic name could not be
+    /// remapped by a [TopicRemap::Pattern]
+    InvalidTopicRemap,
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for BridgeError {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Client(side, e) => defmt::write!(f, "Client({}, {})", side, e),
+            Self::TopicNameTooLong => defmt::write!(f, "TopicNameTooLong"),
+            Self::InvalidTopicRemap => defmt::write!(f, "InvalidTopicRemap"),
+        }
+    }
+}
+
+#[cfg(feature = "display")]
+impl Display for BridgeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Client(side, e) => write!(f, "Client({}, {})", side, e),
+            Self::TopicNameTooLong => write!(f, "TopicNameTooLong"),
+            Self::InvalidTopicRemap => write!(f, "InvalidTopicRemap"),
+        }
+    }
+}
+
+/// The quality of service used to republish a message on the other side
+/// of a [Bridge]
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum QosMapping {
+    /// Use the quality of service the message was received with
+    Preserve,
+    /// Always use the given quality of service
+    Fixed(QualityOfService),
+    /// Use the quality of service the message was received with, but
+    /// no higher than the given quality of service
+    Maximum(QualityOfService),
+}
+
+impl QosMapping {
+    pub fn map(&self, qos: QualityOfService) -> QualityOfService {
+        match self {
+            QosMapping::Preserve => qos,
+            QosMapping::Fixed(fixed) => *fixed,
+            QosMapping::Maximum(maximum) if qos > *maximum => *maximum,
+            QosMapping::Maximum(_) => qos,
+        }
+    }
+}
+
+/// Remaps the topic names of messages forwarded by a [Bridge], e.g. to
+/// translate a local topic namespace to the one used by a remote server
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TopicRemap<'a> {
+    /// Replace a prefix, e.g. with `from_prefix` "sensors/" and `to_prefix`
+    /// "site-1/sensors/", topic "sensors/temperature" becomes
+    /// "site-1/sensors/temperature". Topic names without the prefix have just
+    /// `to_prefix` added.
+    Prefix {
+        from_prefix: &'a str,
+        to_prefix: &'a str,
+    },
+
+    /// Capture the levels of the topic name matched by the wildcards of
+    /// `from_filter`, and substitute them in order for the wildcards of
+    /// `to_template`, e.g. with `from_filter` "local/+/temp" and `to_template`
+    /// "site42/+/temperature", topic "local/kitchen/temp" becomes
+    /// "site42/kitchen/temperature", and with "local/#" and "site42/#",
+    /// topic "local/a/b" becomes "site42/a/b". Only topic names matching
+    /// `from_filter` are remapped, see [TopicRemap::applies_to].
+    Pattern {
+        from_filter: &'a str,
+        to_template: &'a str,
+    },
+}
+
+impl<'a> TopicRemap<'a> {
+    /// Replace `from_prefix` with `to_prefix`, see [TopicRemap::Prefix]
+    pub fn new(from_prefix: &'a str, to_prefix: &'a str) -> Self {
+        Self::Prefix {
+            from_prefix,
+            to_prefix,
+        }
+    }
+
+    /// Remove `prefix` from topic names that start with it
+    pub fn strip_prefix(prefix: &'a str) -> Self {
+        Self::new(prefix, "")
+    }
+
+    /// Add `prefix` to all topic names
+    pub fn add_prefix(prefix: &'a str) -> Self {
+        Self::new("", prefix)
+    }
+
+    /// Substitute wildcard captures, see [TopicRemap::Pattern]
+    pub fn pattern(from_filter: &'a str, to_template: &'a str) -> Self {
+        Self::Pattern {
+            from_filter,
+            to_template,
+        }
+    }
+
+    /// True if the topic name can be remapped. A [TopicRemap::Prefix] applies
+    /// to all topic names, a [TopicRemap::Pattern] to those matching its filter.
+    pub fn applies_to(&self, topic_name: &str) -> bool {
+        match self {
+            Self::Prefix { .. } => true,
+            Self::Pattern { from_filter, .. } => topic_filter_matches(from_filter, topic_name),
+        }
+    }
+
+    /// Remap the topic name, producing [BridgeError::TopicNameTooLong] if the
+    /// result won't fit in `T` bytes. A [TopicRemap::Pattern] produces
+    /// [BridgeError::InvalidTopicRemap] if it doesn't apply to the topic name,
+    /// or `to_template` has more wildcards than `from_filter`.
+    pub fn remap<const T: usize>(&self, topic_name: &str) -> Result<String<T>, BridgeError> {
+        let mut remapped = String::new();
+        match self {
+            Self::Prefix {
+                from_prefix,
+                to_prefix,
+            } => {
+                let rest = topic_name.strip_prefix(from_prefix).unwrap_or(topic_name);
+                remapped
+                    .push_str(to_prefix)
+                    .and_then(|_| remapped.push_str(rest))
+                    .map_err(|_| BridgeError::TopicNameTooLong)?;
+            }
+            Self::Pattern {
+                from_filter,
+                to_template,
+            } => {
+                let mut index = 0;
+                for (i, level) in to_template.split(TOPIC_LEVEL_SEPARATOR).enumerate() {
+                    if i > 0 {
+                        remapped
+                            .push(TOPIC_LEVEL_SEPARATOR)
+                            .map_err(|_| BridgeError::TopicNameTooLong)?;
+                    }
+                    let level = if level == SINGLE_LEVEL_WILDCARD || level == MULTI_LEVEL_WILDCARD {
+                        let captured = topic_filter_capture(from_filter, topic_name, index)
+                            .ok_or(BridgeError::InvalidTopicRemap)?;
+                        index += 1;
+                        // A `#` matching just the parent level leaves no trailing separator
+                        if captured.is_empty() && level == MULTI_LEVEL_WILDCARD && i > 0 {
+                            remapped.pop();
+                        }
+                        captured
+                    } else {
+                        level
+                    };
+                    remapped
+                        .push_str(level)
+                        .map_err(|_| BridgeError::TopicNameTooLong)?;
+                }
+            }
+        }
+        Ok(remapped)
+    }
+}
+
+/// A rule for a [Bridge], mirroring messages matching a topic filter
+/// from the source side of the bridge to the other side.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BridgeRule<'a> {
+    source: BridgeSide,
+    topic_filter: &'a str,
+    maximum_qos: QualityOfService,
+    remap: Option<TopicRemap<'a>>,
+    qos_mapping: QosMapping,
+}
+
+impl<'a> BridgeRule<'a> {
+    /// Mirror messages from `source` matching `topic_filter`, subscribing
+    /// with `maximum_qos`. By default messages are republished with the same
+    /// topic name and quality of service.
+    pub fn new(source: BridgeSide, topic_filter: &'a str, maximum_qos: QualityOfService) -> Self {
+        Self {
+            source,
+            topic_filter,
+            maximum_qos,
+            remap: None,
+            qos_mapping: QosMapping::Preserve,
+        }
+    }
+
+    /// Remap topic names of republished messages by replacing a prefix,
+    /// see [TopicRemap::Prefix]
+    pub fn with_remap(mut self, from_prefix: &'a str, to_prefix: &'a str) -> Self {
+        self.remap = Some(TopicRemap::new(from_prefix, to_prefix));
+        self
+    }
+
+    /// Remap topic names of republished messages, see [TopicRemap]
+    pub fn with_topic_remap(mut self, remap: TopicRemap<'a>) -> Self {
+        self.remap = Some(remap);
+        self
+    }
+
+    /// Set the quality of service of republished messages
+    pub fn with_qos_mapping(mut self, qos_mapping: QosMapping) -> Self {
+        self.qos_mapping = qos_mapping;
+        self
+    }
+
+    pub fn source(&self) -> BridgeSide {
+        self.source
+    }
+    pub fn topic_filter(&self) -> &'a str {
+        self.topic_filter
+    }
+    pub fn maximum_qos(&self) -> QualityOfService {
+        self.maximum_qos
+    }
+    pub fn remap(&self) -> Option<TopicRemap<'a>> {
+        self.remap
+    }
+    pub fn qos_mapping(&self) -> QosMapping {
+        self.qos_mapping
+    }
+
+    /// True if this rule applies to a message received on the given side,
+    /// i.e. the topic name matches the rule's topic filter, and can be
+    /// remapped by the rule's [TopicRemap] if any
+    pub fn matches(&self, side: BridgeSide, topic_name: &str) -> bool {
+        self.source == side
+            && topic_filter_matches(self.topic_filter, topic_name)
+            && self.remap.is_none_or(|remap| remap.applies_to(topic_name))
+    }
+}
+
+/// An application message held by a [BridgeQueue] until it can be republished,
+/// with a topic name of up to `T` bytes and payload of up to `N` bytes.
+/// Properties of the message are not retained.
+#[derive(Debug, PartialEq, Clone)]
+pub struct BridgeMessage<const T: usize, const N: usize> {
+    pub topic_name: String<T>,
+    pub payload: Vec<u8, N>,
+    pub qos: QualityOfService,
+    pub retain: bool,
+}
+
+/// An [EventHandler] for a client used by a [Bridge], queueing up to `Q` received
+/// application messages until they are republished. Other events are ignored.
+/// A message that can't be queued, since it is too large or the queue is full,
+/// produces [EventHandlerError::Overflow].
+#[derive(Debug, Default)]
+pub struct BridgeQueue<const T: usize, const N: usize, const Q: usize> {
+    messages: Deque<BridgeMessage<T, N>, Q>,
+}
+
+impl<const T: usize, const N: usize, const Q: usize> BridgeQueue<T, N, Q> {
+    pub fn new() -> Self {
+        Self {
+            messages: Deque::new(),
+        }
+    }
+
+    /// Take the oldest queued message, if any
+    pub fn pop(&mut self) -> Option<BridgeMessage<T, N>> {
+        self.messages.pop_front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+}
+
+impl<const T: usize, const N: usize, const Q: usize, const P: usize> EventHandler<P>
+    for BridgeQueue<T, N, Q>
+{
+    async fn handle_event(
+        &mut self,
+        event: ClientReceivedEvent<'_, P>,
+    ) -> Result<(), EventHandlerError> {
+        if let ClientReceivedEvent::ApplicationMessage(message) = event {
+            let message = BridgeMessage {
+                topic_name: message
+                    .topic_name
+                    .try_into()
+                    .map_err(|_| EventHandlerError::Overflow)?,
+                payload: Vec::from_slice(message.payload)
+                    .map_err(|_| EventHandlerError::Overflow)?,
+                qos: message.qos,
+                retain: message.retain,
+            };
+            self.messages
+                .push_back(message)
+                .map_err(|_| EventHandlerError::Overflow)?;
+        }
+        Ok(())
+    }
+}
+
+/// A [Client] that can be used on one side of a [Bridge], providing the
+/// messages it has received
+pub trait BridgeClient<const T: usize, const N: usize> {
+    /// Take the oldest message received by the client, if any
+    fn pop_message(&mut self) -> Option<BridgeMessage<T, N>>;
+}
+
+#[cfg(feature = "client-codec")]
+impl<'a, C, D, K, const P: usize, const T: usize, const N: usize, const Q: usize> BridgeClient<T, N>
+    for ClientNoQueue<'a, C, D, K, BridgeQueue<T, N, Q>, P>
+where
+    C: Connection,
+    D: Delay,
+    K: Clock,
+{
+    fn pop_message(&mut self) -> Option<BridgeMessage<T, N>> {
+        self.event_handler_mut().pop()
+    }
+}
+
+/// Mirrors messages between two clients, normally connected to different
+/// servers, e.g. from a local broker on an edge gateway to a remote broker.
+///
+/// Each [BridgeRule] subscribes to a topic filter on its source side, and
+/// messages received on that side matching the filter are republished on the
+/// other side, with optional topic name remapping and quality of service mapping.
+/// A message is forwarded using the first matching rule, if any.
+///
+/// The bridge uses the clients to subscribe and publish, but the application
+/// is responsible for connecting (and reconnecting) the clients, which can be
+/// accessed using [Bridge::local_mut] and [Bridge::remote_mut]. Note that rules
+/// in both directions with overlapping topics will forward messages back and forth.
+pub struct Bridge<'r, L, R, const T: usize, const N: usize, const RULES: usize> {
+    local: L,
+    remote: R,
+    rules: Vec<BridgeRule<'r>, RULES>,
+}
+
+impl<'a, 'r, L, R, const T: usize, const N: usize, const RULES: usize> Bridge<'r, L, R, T, N, RULES>
+where
+    L: Client<'a> + BridgeClient<T, N>,
+    R: Client<'a> + BridgeClient<T, N>,
+{
+    pub fn new(local: L, remote: R, rules: Vec<BridgeRule<'r>, RULES>) -> Self {
+        Self {
+            local,
+            remote,
+            rules,
+        }
+    }
+
+    pub fn local(&self) -> &L {
+        &self.local
+    }
+    pub fn local_mut(&mut self) -> &mut L {
+        &mut self.local
+    }
+    pub fn remote(&self) -> &R {
+        &self.remote
+    }
+    pub fn remote_mut(&mut self) -> &mut R {
+        &mut self.remote
+    }
+    pub fn rules(&self) -> &[BridgeRule<'r>] {
+        &self.rules
+    }
+
+    /// Consume the bridge, returning the local and remote clients
+    pub fn into_inner(self) -> (L, R) {
+        (self.local, self.remote)
+    }
+
+    /// Subscribe to the topic filter of each rule on its source side. This
+    /// should be called whenever the clients are connected, unless
+    /// subscriptions are retained by the server's session.
+    pub async fn subscribe(&mut self) -> Result<(), BridgeError> {
+        for rule in self.rules.iter() {
+            let result = match rule.source {
+                BridgeSide::Local => {
+                    self.local
+                        .subscribe(rule.topic_filter, rule.maximum_qos)
+                        .await
+                }
+                BridgeSide::Remote => {
+                    self.remote
+                        .subscribe(rule.topic_filter, rule.maximum_qos)
+                        .await
+                }
+            };
+            result.map_err(|e| BridgeError::Client(rule.source, e))?;
+        }
+        Ok(())
+    }
+
+    /// Poll both clients for received packets without waiting, then forward
+    /// all received messages to the other side.
+    /// On success, returns the number of messages forwarded.
+    pub async fn poll(&mut self) -> Result<usize, BridgeError> {
+        self.local
+            .poll(false)
+            .await
+            .map_err(|e| BridgeError::Client(BridgeSide::Local, e))?;
+        self.remote
+            .poll(false)
+            .await
+            .map_err(|e| BridgeError::Client(BridgeSide::Remote, e))?;
+
+        let mut forwarded = 0;
+        for side in [BridgeSide::Local, BridgeSide::Remote] {
+            while let Some(message) = self.pop_message(side) {
+                if self.forward(side, &message).await? {
+                    forwarded += 1;
+                }
+            }
+        }
+        Ok(forwarded)
+    }
+
+    /// Forward a message received on the `source` side to the other side,
+    /// using the first matching rule. On success, returns true if the
+    /// message was forwarded, false if no rule matched.
+    pub async fn forward(
+        &mut self,
+        source: BridgeSide,
+        message: &BridgeMessage<T, N>,
+    ) -> Result<bool, BridgeError> {
+        let Some(rule) = self
+            .rules
+            .iter()
+            .find(|rule| rule.matches(source, &message.topic_name))
+        else {
+            return Ok(false);
+        };
+
+        let topic_name: String<T> = match rule.remap {
+            Some(remap) => remap.remap(&message.topic_name)?,
+            None => message.topic_name.clone(),
+        };
+        let qos = rule.qos_mapping.map(message.qos);
+
+        let target = source.other();
+        let result = match target {
+            BridgeSide::Local => {
+                self.local
+                    .publish(&topic_name, &message.payload, qos, message.retain)
+                    .await
+            }
+            BridgeSide::Remote => {
+                self.remote
+                    .publish(&topic_name, &message.payload, qos, message.retain)
+                    .await
+            }
+        };
+        result.map_err(|e| BridgeError::Client(target, e))?;
+        Ok(true)
+    }
+
+    fn pop_message(&mut self, side: BridgeSide) -> Option<BridgeMessage<T, N>> {
+        match side {
+            BridgeSide::Local => self.local.pop_message(),
+            BridgeSide::Remote => self.remote.pop_message(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::ConnectionSettings,
+        client_state::Acknowledgement,
+        data::{
+            property::{PublishProperty, SubscribeProperty},
+            subscription_options::SubscriptionOptions,
+        },
+        packets::connect::{Connect, Will},
+    };
+
+    type Message = BridgeMessage<32, 16>;
+
+    /// A [Client] that records subscriptions and publishes, and
+    /// provides queued messages as if they had been received
+    #[derive(Default)]
+    struct RecordingClient {
+        received: std::vec::Vec<Message>,
+        subscribed: std::vec::Vec<(std::string::String, QualityOfService)>,
+        published: std::vec::Vec<Message>,
+    }
+
+    impl BridgeClient<32, 16> for RecordingClient {
+        fn pop_message(&mut self) -> Option<Message> {
+            if self.received.is_empty() {
+                None
+            } else {
+                Some(self.received.remove(0))
+            }
+        }
+    }
+
+    impl<'a> Client<'a> for RecordingClient {
+        async fn connect(&mut self, _settings: &ConnectionSettings<'_>) -> Result<(), ClientError> {
+            Ok(())
+        }
+
+        async fn connect_with_will<const W: usize>(
+            &mut self,
+            _settings: &ConnectionSettings<'_>,
+            _will: Option<Will<'_, W>>,
+        ) -> Result<(), ClientError> {
+            Ok(())
+        }
+
+        async fn connect_with_packet<const PP: usize, const W: usize>(
+            &mut self,
+            _packet: Connect<'_, PP, W>,
+        ) -> Result<(), ClientError> {
+            Ok(())
+        }
+
+        async fn disconnect(&mut self) -> Result<(), ClientError> {
+            Ok(())
+        }
+
+        async fn disconnect_with_session_expiry_interval(
+            &mut self,
+            _session_expiry_interval: u32,
+        ) -> Result<(), ClientError> {
+            Ok(())
+        }
+
+        async fn send_ping(&mut self) -> Result<(), ClientError> {
+            Ok(())
+        }
+
+        fn pending_ping_count(&self) -> u32 {
+            0
+        }
+
+        fn keep_alive(&self) -> Option<u16> {
+            None
+        }
+
+        async fn poll(&mut self, _wait: bool) -> Result<bool, ClientError> {
+            Ok(false)
+        }
+
+        async fn subscribe_with_options<'b>(
+            &'b mut self,
+            topic_name: &'b str,
+            options: SubscriptionOptions,
+        ) -> Result<(), ClientError> {
+            self.subscribed
+                .push((topic_name.into(), options.maximum_qos));
+            Ok(())
+        }
+
+        async fn subscribe_with_properties<'b, const P: usize>(
+            &'b mut self,
+            topic_name: &'b str,
+            maximum_qos: QualityOfService,
+            _properties: Vec<SubscribeProperty<'b>, P>,
+        ) -> Result<(), ClientError> {
+            self.subscribed.push((topic_name.into(), maximum_qos));
+            Ok(())
+        }
+
+        async fn unsubscribe<'b>(&'b mut self, _topic_name: &'b str) -> Result<(), ClientError> {
+            Ok(())
+        }
+
+        async fn publish_with_properties<'b, const P: usize>(
+            &'b mut self,
+            topic_name: &'b str,
+            payload: &'b [u8],
+            qos: QualityOfService,
+            retain: bool,
+            _properties: Vec<PublishProperty<'b>, P>,
+        ) -> Result<(), ClientError> {
+            self.published
+                .push(message(topic_name, payload, qos, retain));
+            Ok(())
+        }
+    }
+
+    fn message(topic_name: &str, payload: &[u8], qos: QualityOfService, retain: bool) -> Message {
+        BridgeMessage {
+            topic_name: topic_name.try_into().unwrap(),
+            payload: Vec::from_slice(payload).unwrap(),
+            qos,
+            retain,
+        }
+    }
+
+    #[test]
+    fn qos_mapping() {
+        use QualityOfService::*;
+        assert_eq!(QosMapping::Preserve.map(Qos1), Qos1);
+        assert_eq!(QosMapping::Fixed(Qos0).map(Qos1), Qos0);
+        assert_eq!(QosMapping::Fixed(Qos1).map(Qos0), Qos1);
+        assert_eq!(QosMapping::Maximum(Qos0).map(Qos1), Qos0);
+        assert_eq!(QosMapping::Maximum(Qos1).map(Qos0), Qos0);
+    }
+
+    #[test]
+    fn topic_remap() {
+        let remap = TopicRemap::new("sensors/", "site-1/sensors/");
+        assert_eq!(
+            remap.remap::<32>("sensors/temperature").unwrap(),
+            "site-1/sensors/temperature"
+        );
+        assert_eq!(remap.remap::<32>("other").unwrap(), "site-1/sensors/other");
+        assert_eq!(
+            remap.remap::<16>("sensors/temperature"),
+            Err(BridgeError::TopicNameTooLong)
+        );
+    }
+
+    #[test]
+    fn topic_remap_prefixes() {
+        assert_eq!(
+            TopicRemap::strip_prefix("local/")
+                .remap::<32>("local/sensors/temperature")
+                .unwrap(),
+            "sensors/temperature"
+        );
+        assert_eq!(
+            TopicRemap::add_prefix("site42/")
+                .remap::<32>("sensors/temperature")
+                .unwrap(),
+            "site42/sensors/temperature"
+        );
+    }
+
+    #[test]
+    fn topic_remap_pattern() {
+        let cases = [
+            ("local/#", "site42/#", "local/a/b", "site42/a/b"),
+            ("local/#", "site42/#", "local", "site42"),
+            ("local/#", "#", "local/a/b", "a/b"),
+            (
+                "local/+/temp",
+                "site42/+/temperature",
+                "local/kitchen/temp",
+                "site42/kitchen/temperature",
+            ),
+            ("+/+/#", "site42/+/+/#", "a/b/c/d", "site42/a/b/c/d"),
+            ("local/+/#", "site42/+", "local/a/b", "site42/a"),
+        ];
+        for (from_filter, to_template, topic_name, expected) in cases {
+            let remap = TopicRemap::pattern(from_filter, to_template);
+            assert!(remap.applies_to(topic_name));
+            assert_eq!(remap.remap::<32>(topic_name).unwrap(), expected);
+        }
+
+        let remap = TopicRemap::pattern("local/+", "site42/+/+");
+        assert_eq!(
+            remap.remap::<32>("local/a"),
+            Err(BridgeError::InvalidTopicRemap)
+        );
+        assert!(!remap.applies_to("other/a"));
+        assert_eq!(
+            remap.remap::<32>("other/a"),
+            Err(BridgeError::InvalidTopicRemap)
+        );
+        assert_eq!(
+            TopicRemap::pattern("local/#", "site42/#").remap::<8>("local/a/b"),
+            Err(BridgeError::TopicNameTooLong)
+        );
+    }
+
+    #[test]
+    fn rule_matches_only_topics_its_remap_applies_to() {
+        let rule = BridgeRule::new(BridgeSide::Local, "#", QualityOfService::Qos0)
+            .with_topic_remap(TopicRemap::pattern("local/#", "site42/#"));
+        assert!(rule.matches(BridgeSide::Local, "local/a"));
+        assert!(!rule.matches(BridgeSide::Local, "other/a"));
+        assert!(!rule.matches(BridgeSide::Remote, "local/a"));
+    }
+
+    #[tokio::test]
+    async fn bridge_subscribes_and_forwards_with_first_matching_rule() {
+        let mut rules = Vec::<BridgeRule<'_>, 3>::new();
+        rules
+            .push(
+                BridgeRule::new(BridgeSide::Local, "sensors/#", QualityOfService::Qos1)
+                    .with_remap("sensors/", "site-1/sensors/")
+                    .with_qos_mapping(QosMapping::Fixed(QualityOfService::Qos0)),
+            )
+            .unwrap();
+        rules
+            .push(BridgeRule::new(
+                BridgeSide::Local,
+                "sensors/+",
+                QualityOfService::Qos0,
+            ))
+            .unwrap();
+        rules
+            .push(BridgeRule::new(
+                BridgeSide::Remote,
+                "commands/+",
+                QualityOfService::Qos1,
+            ))
+            .unwrap();
+
+        let mut local = RecordingClient::default();
+        local.received.push(message(
+            "sensors/temperature",
+            b"21.5",
+            QualityOfService::Qos1,
+            true,
+        ));
+        local
+            .received
+            .push(message("unmatched", b"", QualityOfService::Qos0, false));
+        let mut remote = RecordingClient::default();
+        remote.received.push(message(
+            "commands/reset",
+            b"now",
+            QualityOfService::Qos1,
+            false,
+        ));
+
+        let mut bridge = Bridge::new(local, remote, rules);
+        bridge.subscribe().await.unwrap();
+        assert_eq!(bridge.poll().await.unwrap(), 2);
+
+        let (local, remote) = bridge.into_inner();
+        assert_eq!(
+            local.subscribed,
+            [
+                ("sensors/#".into(), QualityOfService::Qos1),
+                ("sensors/+".into(), QualityOfService::Qos0)
+            ]
+        );
+        assert_eq!(
+            remote.subscribed,
+            [("commands/+".into(), QualityOfService::Qos1)]
+        );
+        assert_eq!(
+            remote.published,
+            [message(
+                "site-1/sensors/temperature",
+                b"21.5",
+                QualityOfService::Qos0,
+                true
+            )]
+        );
+        assert_eq!(
+            local.published,
+            [message(
+                "commands/reset",
+                b"now",
+                QualityOfService::Qos1,
+                false
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn bridge_queue_queues_application_messages() {
+        let mut queue = BridgeQueue::<32, 16, 1>::new();
+        let event: ClientReceivedEvent<'_, 0> =
+            ClientReceivedEvent::ApplicationMessage(crate::packets::publish::ApplicationMessage {
+                topic_name: "a/topic",
+                payload: b"payload",
+                qos: QualityOfService::Qos1,
+                retain: false,
+                properties: Vec::new(),
+                encoded_properties: &[],
+            });
+        queue.handle_event(event).await.unwrap();
+        queue
+            .handle_event(ClientReceivedEvent::<'_, 0>::Ack(Acknowledgement::Pingresp))
+            .await
+            .unwrap();
+        assert_eq!(queue.len(), 1);
+
+        let event: ClientReceivedEvent<'_, 0> =
+            ClientReceivedEvent::ApplicationMessage(crate::packets::publish::ApplicationMessage {
+                topic_name: "b/topic",
+                payload: b"",
+                qos: QualityOfService::Qos0,
+                retain: false,
+                properties: Vec::new(),
+                encoded_properties: &[],
+            });
+        assert_eq!(
+            queue.handle_event(event).await,
+            Err(EventHandlerError::Overflow)
+        );
+
+        assert_eq!(
+            queue.pop(),
+            Some(message(
+                "a/topic",
+                b"payload",
+                QualityOfService::Qos1,
+                false
+            ))
+        );
+        assert!(queue.is_empty());
+    }
+}
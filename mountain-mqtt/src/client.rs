@@ -1,27 +1,32 @@
-use core::{
-    fmt::{Display, Formatter},
-    str::Utf8Error,
-};
+#[cfg(feature = "display")]
+use core::fmt::{Display, Formatter};
+use core::num::NonZeroU16;
+use core::str::Utf8Error;
 
 use heapless::Vec;
 
 use crate::{
-    client_state::{ClientState, ClientStateError, ClientStateNoQueue, ClientStateReceiveEvent},
-    codec::write,
+    authenticator::AuthenticatorError,
+    client_id::{validate_connect_client_id, ClientIdError},
+    client_state::{Acknowledgement, ClientStateError, MAX_SUBSCRIPTION_REQUESTS},
     data::{
-        property::{ConnectProperty, PublishProperty},
+        packet_identifier::PacketIdentifier,
+        property::{DisconnectProperty, PropertyOverflow, PublishProperty, SubscribeProperty},
         quality_of_service::QualityOfService,
-        reason_code::DisconnectReasonCode,
+        reason_code::{DisconnectReasonCode, SubscribeReasonCode, UnsubscribeReasonCode},
+        subscription_options::SubscriptionOptions,
     },
-    error::{PacketReadError, PacketWriteError},
-    packet_client::{Connection, PacketClient},
+    error::{PacketReadError, PacketWriteError, TransportError, TransportErrorKind},
     packets::{
         connect::{Connect, Will},
-        packet::{Packet, KEEP_ALIVE_DEFAULT},
-        packet_generic::PacketGeneric,
+        packet::KEEP_ALIVE_DEFAULT,
         publish::{ApplicationMessage, Publish},
+        PROPERTIES_DEFAULT,
     },
+    timing::ConnectionTimerSettings,
 };
+#[cfg(feature = "client-codec")]
+use crate::{client_state::ClientStateNoQueue, managed_client::ManagedClient};
 
 /// Errors produced when a [ClientNoQueue] event handler cannot handle
 /// a [ClientReceivedEvent]. These errors propagate to the user of the
@@ -111,6 +116,7 @@ impl From<Utf8Error> for EventHandlerError {
     }
 }
 
+#[cfg(feature = "display")]
 impl Display for EventHandlerError {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match self {
@@ -138,9 +144,12 @@ impl Display for EventHandlerError {
     }
 }
 
-/// [Client] error
+/// [Client] error. `E` is the error carried by [ClientError::Transport], by
+/// default just the [TransportErrorKind]. An error carrying the error produced
+/// by the [Connection](crate::packet_client::Connection) itself is available
+/// from e.g. [ManagedClient::full_error](crate::managed_client::ManagedClient::full_error).
 #[derive(Debug, PartialEq, Clone, Copy)]
-pub enum ClientError {
+pub enum ClientError<E = TransportErrorKind> {
     PacketWrite(PacketWriteError),
     PacketRead(PacketReadError),
     ClientState(ClientStateError),
@@ -157,10 +166,37 @@ pub enum ClientError {
     /// a Disconnect with [DisconnectReasonCode::TopicAliasInvalid], on the assumption
     /// that the packet also had some topic alias specified.
     EmptyTopicNameWithAliasesDisabled,
+    /// Client received an application message with a payload format indicator
+    /// showing UTF-8 data, but the payload is not valid UTF-8, and the client
+    /// is validating payload formats. Client should disconnect, it may send
+    /// a Disconnect with [DisconnectReasonCode::PayloadFormatInvalid].
+    PayloadFormatInvalid,
+    /// Client received an Auth packet requiring a response, or was asked to
+    /// re-authenticate, but has no [Authenticator](crate::authenticator::Authenticator)
+    NoAuthenticator,
+    /// The [Authenticator](crate::authenticator::Authenticator) could not continue the authentication exchange
+    Authenticator(AuthenticatorError),
+    /// The client identifier failed validation before connecting, see
+    /// [ConnectionSettings::with_validate_client_id]
+    InvalidClientId(ClientIdError),
+    /// A request was made using a handle to a client run by another task,
+    /// but that task has stopped running the client
+    ClientStopped,
+    /// A topic name or filter could not be used since it is too long for the
+    /// client's topic name capacity, e.g. after adding a namespace prefix, see
+    /// [NamespacedClient](crate::namespace::NamespacedClient)
+    TopicNameTooLong,
+    /// The transport underlying the client's connection failed, e.g. the
+    /// server closed the connection
+    Transport(E),
+    /// A packet could not be sent without waiting, since the queue of packets
+    /// to send is full. The caller may retry later, or drop the packet (e.g.
+    /// for QoS 0 telemetry that will soon be replaced).
+    WouldBlock,
 }
 
 #[cfg(feature = "defmt")]
-impl defmt::Format for ClientError {
+impl<E: defmt::Format> defmt::Format for ClientError<E> {
     fn format(&self, f: defmt::Formatter) {
         match self {
             Self::PacketWrite(e) => defmt::write!(f, "PacketWrite({})", e),
@@ -175,6 +211,14 @@ impl defmt::Format for ClientError {
             Self::EmptyTopicNameWithAliasesDisabled => {
                 defmt::write!(f, "EmptyTopicNameWithAliasesDisabled")
             }
+            Self::PayloadFormatInvalid => defmt::write!(f, "PayloadFormatInvalid"),
+            Self::NoAuthenticator => defmt::write!(f, "NoAuthenticator"),
+            Self::Authenticator(e) => defmt::write!(f, "Authenticator({})", e),
+            Self::InvalidClientId(e) => defmt::write!(f, "InvalidClientId({})", e),
+            Self::ClientStopped => defmt::write!(f, "ClientStopped"),
+            Self::TopicNameTooLong => defmt::write!(f, "TopicNameTooLong"),
+            Self::Transport(e) => defmt::write!(f, "Transport({})", e),
+            Self::WouldBlock => defmt::write!(f, "WouldBlock"),
         }
     }
 }
@@ -187,13 +231,19 @@ impl From<ClientStateError> for ClientError {
 
 impl From<PacketWriteError> for ClientError {
     fn from(value: PacketWriteError) -> Self {
-        ClientError::PacketWrite(value)
+        match value {
+            PacketWriteError::Transport(kind) => ClientError::Transport(kind),
+            value => ClientError::PacketWrite(value),
+        }
     }
 }
 
 impl From<PacketReadError> for ClientError {
     fn from(value: PacketReadError) -> Self {
-        ClientError::PacketRead(value)
+        match value {
+            PacketReadError::Transport(kind) => ClientError::Transport(kind),
+            value => ClientError::PacketRead(value),
+        }
     }
 }
 
@@ -203,7 +253,20 @@ impl From<EventHandlerError> for ClientError {
     }
 }
 
-impl Display for ClientError {
+impl From<AuthenticatorError> for ClientError {
+    fn from(value: AuthenticatorError) -> Self {
+        ClientError::Authenticator(value)
+    }
+}
+
+impl From<ClientIdError> for ClientError {
+    fn from(value: ClientIdError) -> Self {
+        ClientError::InvalidClientId(value)
+    }
+}
+
+#[cfg(feature = "display")]
+impl<E: Display> Display for ClientError<E> {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::PacketWrite(e) => write!(f, "PacketWrite({})", e),
@@ -214,6 +277,129 @@ impl Display for ClientError {
             Self::Disconnected(e) => write!(f, "Disconnected({})", e),
             Self::EventHandler(e) => write!(f, "EventHandler({})", e),
             Self::EmptyTopicNameWithAliasesDisabled => write!(f, "EmptyTopicWithAliasesDisabled"),
+            Self::PayloadFormatInvalid => write!(f, "PayloadFormatInvalid"),
+            Self::NoAuthenticator => write!(f, "NoAuthenticator"),
+            Self::Authenticator(e) => write!(f, "Authenticator({})", e),
+            Self::InvalidClientId(e) => write!(f, "InvalidClientId({})", e),
+            Self::ClientStopped => write!(f, "ClientStopped"),
+            Self::TopicNameTooLong => write!(f, "TopicNameTooLong"),
+            Self::Transport(e) => write!(f, "Transport({})", e),
+            Self::WouldBlock => write!(f, "WouldBlock"),
+        }
+    }
+}
+
+impl ClientError {
+    /// Produce an error carrying `error`, the error from the connection that
+    /// caused this error, if it is a [ClientError::Transport]. If `error` is
+    /// not available, a [ClientError::Transport] is instead reported as
+    /// [PacketReadError::Transport], which has only the [TransportErrorKind].
+    pub fn with_transport_error<E>(self, error: Option<E>) -> ClientError<E> {
+        match self {
+            Self::Transport(kind) => match error {
+                Some(error) => ClientError::Transport(error),
+                None => ClientError::PacketRead(PacketReadError::Transport(kind)),
+            },
+            Self::PacketWrite(e) => ClientError::PacketWrite(e),
+            Self::PacketRead(e) => ClientError::PacketRead(e),
+            Self::ClientState(e) => ClientError::ClientState(e),
+            Self::TimeoutOnResponsePacket => ClientError::TimeoutOnResponsePacket,
+            Self::ReceiveTimeoutServerUnresponsive => ClientError::ReceiveTimeoutServerUnresponsive,
+            Self::Disconnected(r) => ClientError::Disconnected(r),
+            Self::EventHandler(e) => ClientError::EventHandler(e),
+            Self::EmptyTopicNameWithAliasesDisabled => {
+                ClientError::EmptyTopicNameWithAliasesDisabled
+            }
+            Self::PayloadFormatInvalid => ClientError::PayloadFormatInvalid,
+            Self::NoAuthenticator => ClientError::NoAuthenticator,
+            Self::Authenticator(e) => ClientError::Authenticator(e),
+            Self::InvalidClientId(e) => ClientError::InvalidClientId(e),
+            Self::ClientStopped => ClientError::ClientStopped,
+            Self::TopicNameTooLong => ClientError::TopicNameTooLong,
+            Self::WouldBlock => ClientError::WouldBlock,
+        }
+    }
+}
+
+impl<E: TransportError> ClientError<E> {
+    /// Produce an error carrying only the [TransportErrorKind] of the error
+    /// from the connection, e.g. to store or copy the error
+    pub fn into_kind(self) -> ClientError {
+        match self {
+            Self::Transport(e) => ClientError::Transport(e.kind()),
+            Self::PacketWrite(e) => ClientError::PacketWrite(e),
+            Self::PacketRead(e) => ClientError::PacketRead(e),
+            Self::ClientState(e) => ClientError::ClientState(e),
+            Self::TimeoutOnResponsePacket => ClientError::TimeoutOnResponsePacket,
+            Self::ReceiveTimeoutServerUnresponsive => ClientError::ReceiveTimeoutServerUnresponsive,
+            Self::Disconnected(r) => ClientError::Disconnected(r),
+            Self::EventHandler(e) => ClientError::EventHandler(e),
+            Self::EmptyTopicNameWithAliasesDisabled => {
+                ClientError::EmptyTopicNameWithAliasesDisabled
+            }
+            Self::PayloadFormatInvalid => ClientError::PayloadFormatInvalid,
+            Self::NoAuthenticator => ClientError::NoAuthenticator,
+            Self::Authenticator(e) => ClientError::Authenticator(e),
+            Self::InvalidClientId(e) => ClientError::InvalidClientId(e),
+            Self::ClientStopped => ClientError::ClientStopped,
+            Self::TopicNameTooLong => ClientError::TopicNameTooLong,
+            Self::WouldBlock => ClientError::WouldBlock,
+        }
+    }
+}
+
+impl<E> ClientError<E> {
+    /// True if the error is transient, so that retrying (e.g. reconnecting) may
+    /// succeed, for example timeouts, failures to send or receive, or the server
+    /// disconnecting with [DisconnectReasonCode::ServerBusy]. False if the error
+    /// would be expected to recur, for example malformed packets, or the server
+    /// rejecting the connection with a reason like
+    /// [ConnectReasonCode::NotAuthorized](crate::data::reason_code::ConnectReasonCode::NotAuthorized).
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::PacketWrite(e) => e.is_retryable(),
+            Self::PacketRead(e) => e.is_retryable(),
+            Self::ClientState(e) => e.is_retryable(),
+            Self::TimeoutOnResponsePacket
+            | Self::ReceiveTimeoutServerUnresponsive
+            | Self::Transport(_)
+            | Self::WouldBlock => true,
+            Self::Disconnected(r) => r.is_retryable(),
+            Self::EventHandler(e) => matches!(e, EventHandlerError::Overflow),
+            Self::EmptyTopicNameWithAliasesDisabled
+            | Self::PayloadFormatInvalid
+            | Self::NoAuthenticator
+            | Self::Authenticator(_)
+            | Self::InvalidClientId(_)
+            | Self::ClientStopped
+            | Self::TopicNameTooLong => false,
+        }
+    }
+
+    /// The reason code to use in a Disconnect packet sent to the server when
+    /// this error occurs handling received data, or None if the error is not
+    /// caused by the server violating the protocol, in which case the client
+    /// does not send a Disconnect.
+    pub fn disconnect_reason_code(&self) -> Option<DisconnectReasonCode> {
+        match self {
+            Self::PacketRead(e) => e.disconnect_reason_code(),
+            Self::ClientState(e) => e.disconnect_reason_code(),
+            Self::EmptyTopicNameWithAliasesDisabled => {
+                Some(DisconnectReasonCode::TopicAliasInvalid)
+            }
+            Self::PayloadFormatInvalid => Some(DisconnectReasonCode::PayloadFormatInvalid),
+            Self::PacketWrite(_)
+            | Self::TimeoutOnResponsePacket
+            | Self::ReceiveTimeoutServerUnresponsive
+            | Self::Disconnected(_)
+            | Self::EventHandler(_)
+            | Self::NoAuthenticator
+            | Self::Authenticator(_)
+            | Self::InvalidClientId(_)
+            | Self::ClientStopped
+            | Self::TopicNameTooLong
+            | Self::Transport(_)
+            | Self::WouldBlock => None,
         }
     }
 }
@@ -231,14 +417,41 @@ pub trait Client<'a> {
         will: Option<Will<'_, W>>,
     ) -> Result<(), ClientError>;
 
+    /// Connect to server with a provided [Connect] packet, allowing full control
+    /// of the connection, e.g. to set a will and connect properties
+    async fn connect_with_packet<const PP: usize, const W: usize>(
+        &mut self,
+        packet: Connect<'_, PP, W>,
+    ) -> Result<(), ClientError>;
+
     /// Disconnect from server
     async fn disconnect(&mut self) -> Result<(), ClientError>;
 
+    /// Disconnect from server, setting a new session expiry interval in seconds,
+    /// e.g. 0 to end the session, or a longer interval to keep it for
+    /// longer. A non-zero interval is only allowed if the connection was made
+    /// with a non-zero interval, see [ConnectionSettings::with_session_expiry_interval].
+    async fn disconnect_with_session_expiry_interval(
+        &mut self,
+        session_expiry_interval: u32,
+    ) -> Result<(), ClientError>;
+
     /// Optionally send a ping message to broker - note that if the implementation
     /// already handles sending pings this may be a no-op, otherwise it can be used
     /// to determine when to send pings to keep connection alive.
     async fn send_ping(&mut self) -> Result<(), ClientError>;
 
+    /// If connected, the number of pings that have been sent, but not responded
+    /// to, otherwise 0. This can be used to implement a liveness policy, e.g.
+    /// reconnecting after several pings go unanswered, see
+    /// [ClientState::pending_ping_count](crate::client_state::ClientState::pending_ping_count)
+    fn pending_ping_count(&self) -> u32;
+
+    /// If connected, the keep alive interval in seconds for the connection,
+    /// otherwise None, see
+    /// [ClientState::keep_alive](crate::client_state::ClientState::keep_alive)
+    fn keep_alive(&self) -> Option<u16>;
+
     /// Poll for and handle at most one event
     /// This updates the state of the client, and calls the event_handler if
     /// a message is received
@@ -252,11 +465,31 @@ pub trait Client<'a> {
     /// disconnected.
     async fn poll(&mut self, wait: bool) -> Result<bool, ClientError>;
 
-    /// Subscribe to a topic
+    /// Subscribe to a topic, with no properties
     async fn subscribe<'b>(
         &'b mut self,
         topic_name: &'b str,
         maximum_qos: QualityOfService,
+    ) -> Result<(), ClientError> {
+        self.subscribe_with_properties::<0>(topic_name, maximum_qos, Vec::new())
+            .await
+    }
+
+    /// Subscribe to a topic with [SubscriptionOptions], e.g. to use
+    /// [RetainHandling::DoNotSend](crate::data::subscription_options::RetainHandling::DoNotSend)
+    /// to skip retained messages
+    async fn subscribe_with_options<'b>(
+        &'b mut self,
+        topic_name: &'b str,
+        options: SubscriptionOptions,
+    ) -> Result<(), ClientError>;
+
+    /// Subscribe to a topic, with properties
+    async fn subscribe_with_properties<'b, const P: usize>(
+        &'b mut self,
+        topic_name: &'b str,
+        maximum_qos: QualityOfService,
+        properties: Vec<SubscribeProperty<'b>, P>,
     ) -> Result<(), ClientError>;
 
     /// Unsubscribe from a topic
@@ -352,6 +585,11 @@ pub struct ConnectionSettings<'a> {
     username: Option<&'a str>,
     password: Option<&'a [u8]>,
     client_id: &'a str,
+    session_expiry_interval: u32,
+    receive_maximum: NonZeroU16,
+    request_problem_information: bool,
+    validate_client_id: bool,
+    clean_start: bool,
 }
 
 impl<'a> ConnectionSettings<'a> {
@@ -361,6 +599,11 @@ impl<'a> ConnectionSettings<'a> {
             username: None,
             password: None,
             client_id,
+            session_expiry_interval: 0,
+            receive_maximum: NonZeroU16::MAX,
+            request_problem_information: true,
+            validate_client_id: false,
+            clean_start: true,
         }
     }
 
@@ -374,6 +617,11 @@ impl<'a> ConnectionSettings<'a> {
             username,
             password,
             client_id,
+            session_expiry_interval: 0,
+            receive_maximum: NonZeroU16::MAX,
+            request_problem_information: true,
+            validate_client_id: false,
+            clean_start: true,
         }
     }
 
@@ -397,10 +645,80 @@ impl<'a> ConnectionSettings<'a> {
     pub fn password(&self) -> &Option<&'a [u8]> {
         &self.password
     }
+    pub fn session_expiry_interval(&self) -> u32 {
+        self.session_expiry_interval
+    }
+    pub fn receive_maximum(&self) -> NonZeroU16 {
+        self.receive_maximum
+    }
+    pub fn request_problem_information(&self) -> bool {
+        self.request_problem_information
+    }
+    pub fn clean_start(&self) -> bool {
+        self.clean_start
+    }
+
+    /// Set the session expiry interval in seconds, for which the server keeps
+    /// the session state after the connection closes. Defaults to 0, ending the
+    /// session when the connection closes. `u32::MAX` means the session never
+    /// expires.
+    pub fn with_session_expiry_interval(mut self, session_expiry_interval: u32) -> Self {
+        self.session_expiry_interval = session_expiry_interval;
+        self
+    }
+
+    /// Set the maximum number of qos 1 and qos 2 publishes the server may send
+    /// to the client before receiving acknowledgements. This is non-zero, since
+    /// a receive maximum of 0 is a protocol error.
+    /// Defaults to `u16::MAX`, the protocol default.
+    pub fn with_receive_maximum(mut self, receive_maximum: NonZeroU16) -> Self {
+        self.receive_maximum = receive_maximum;
+        self
+    }
+
+    /// Set whether the server may send a reason string or user properties
+    /// in packets other than Publish, Connack and Disconnect. Defaults to
+    /// true, the protocol default.
+    pub fn with_request_problem_information(mut self, request_problem_information: bool) -> Self {
+        self.request_problem_information = request_problem_information;
+        self
+    }
+
+    /// Set whether the client identifier is checked before connecting, so that
+    /// an identifier some servers would reject produces
+    /// [ClientError::InvalidClientId] locally, rather than a Connack with
+    /// [ConnectReasonCode::ClientIdentifierNotValid](crate::data::reason_code::ConnectReasonCode::ClientIdentifierNotValid).
+    /// See [validate_connect_client_id] for the checks. Defaults to false, since
+    /// many servers accept client identifiers that fail these checks.
+    pub fn with_validate_client_id(mut self, validate_client_id: bool) -> Self {
+        self.validate_client_id = validate_client_id;
+        self
+    }
+
+    /// Set whether the connection starts a new session, discarding any existing
+    /// session on the server. Defaults to true. If false, the server resumes an
+    /// existing session if it has one (see [ConnectionSettings::with_session_expiry_interval]),
+    /// shown by `session_present` in [Acknowledgement::Connack](crate::client_state::Acknowledgement::Connack),
+    /// and may redeliver unacknowledged qos 1 messages.
+    pub fn with_clean_start(mut self, clean_start: bool) -> Self {
+        self.clean_start = clean_start;
+        self
+    }
+
+    /// Check the client identifier, if enabled with
+    /// [ConnectionSettings::with_validate_client_id]. An empty client identifier
+    /// is only valid with clean start.
+    pub fn check_client_id(&self) -> Result<(), ClientIdError> {
+        if self.validate_client_id {
+            validate_connect_client_id(self.client_id, self.clean_start)
+        } else {
+            Ok(())
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
-pub enum ClientReceivedEvent<'a, const P: usize> {
+pub enum ClientReceivedEvent<'a, const P: usize = PROPERTIES_DEFAULT> {
     /// Client received an application message published to a subscribed topic
     ApplicationMessage(ApplicationMessage<'a, P>),
 
@@ -414,7 +732,9 @@ pub enum ClientReceivedEvent<'a, const P: usize> {
     /// how long a latency/interruption can be tolerated. To tolerate loss
     /// of ping request/response packets, N should be increased so that T+N
     /// is a multiple of T.
-    Ack,
+    /// The [Acknowledgement] shows which packet was acknowledged, e.g. to
+    /// match a Puback to a publish when several are in flight.
+    Ack(Acknowledgement),
 
     /// A subscription was granted but was at lower qos than the maximum requested
     /// This may or may not require action depending on client requirements -
@@ -423,11 +743,22 @@ pub enum ClientReceivedEvent<'a, const P: usize> {
     /// then the client could respond by showing an error to the user stating the
     /// server is incompatible, or possibly trying to unsubscribe and resubscribe,
     /// assuming this is expected to make any difference with the server(s) in use.
+    /// `packet_identifier` is that of the subscribe packet.
     SubscriptionGrantedBelowMaximumQos {
+        packet_identifier: PacketIdentifier,
         granted_qos: QualityOfService,
         maximum_qos: QualityOfService,
     },
 
+    /// A subscription request with multiple topic filters was acknowledged, but some
+    /// (not all) of the subscriptions were rejected. `reason_codes` has the reason code
+    /// for each subscription, in the order requested, so the client can see exactly
+    /// which topic filters were granted (and at what qos) and which were rejected.
+    /// If all subscriptions are rejected, the client instead produces an error.
+    SubscriptionsPartiallyRejected {
+        reason_codes: Vec<SubscribeReasonCode, MAX_SUBSCRIPTION_REQUESTS>,
+    },
+
     /// A published message was received at the server, but had no matching subscribers and
     /// so did not reach any receivers
     /// This may or may not require action depending on client requirements / expectations
@@ -442,6 +773,28 @@ pub enum ClientReceivedEvent<'a, const P: usize> {
     /// an error, and the user of the client might try reconnecting to the server to set
     /// up subscriptions again.
     NoSubscriptionExisted,
+
+    /// An unsubscribe request with multiple topic filters was acknowledged, but some
+    /// (not all) of the unsubscriptions were rejected. `reason_codes` has the reason code
+    /// for each topic filter, in the order requested.
+    /// If all unsubscriptions are rejected, the client instead produces an error.
+    UnsubscriptionsPartiallyRejected {
+        reason_codes: Vec<UnsubscribeReasonCode, MAX_SUBSCRIPTION_REQUESTS>,
+    },
+
+    /// The server completed re-authentication started by the client, see
+    /// [ManagedClient::reauthenticate](crate::managed_client::ManagedClient::reauthenticate).
+    /// Any authentication data from the server has already been passed to the
+    /// [Authenticator](crate::authenticator::Authenticator).
+    Reauthenticated,
+
+    /// The server sent a Disconnect packet. This is followed by the client producing
+    /// [ClientError::Disconnected], but allows the application to see the properties
+    /// of the packet, e.g. a reason string, server reference or session expiry interval.
+    Disconnected {
+        reason_code: DisconnectReasonCode,
+        properties: Vec<DisconnectProperty<'a>, P>,
+    },
 }
 
 impl<'a, const P: usize> From<Publish<'a, P>> for ClientReceivedEvent<'a, P> {
@@ -457,274 +810,330 @@ impl<'a, const P: usize> defmt::Format for ClientReceivedEvent<'a, P> {
             ClientReceivedEvent::ApplicationMessage(application_message) => {
                 defmt::write!(f, "ApplicationMessage({})", application_message)
             }
-            ClientReceivedEvent::Ack => defmt::write!(f, "Ack"),
+            ClientReceivedEvent::Ack(acknowledgement) => {
+                defmt::write!(f, "Ack({})", acknowledgement)
+            }
             ClientReceivedEvent::SubscriptionGrantedBelowMaximumQos {
+                packet_identifier,
                 granted_qos,
                 maximum_qos,
             } => defmt::write!(
                 f,
-                "SubscriptionGrantedBelowMaximumQos({},{})",
+                "SubscriptionGrantedBelowMaximumQos({},{},{})",
+                packet_identifier,
                 granted_qos,
                 maximum_qos
             ),
+            ClientReceivedEvent::SubscriptionsPartiallyRejected { reason_codes } => {
+                defmt::write!(
+                    f,
+                    "SubscriptionsPartiallyRejected({})",
+                    reason_codes.as_slice()
+                )
+            }
             ClientReceivedEvent::PublishedMessageHadNoMatchingSubscribers => {
                 defmt::write!(f, "PublishedMessageHadNoMatchingSubscribers")
             }
             ClientReceivedEvent::NoSubscriptionExisted => defmt::write!(f, "NoSubscriptionExisted"),
+            ClientReceivedEvent::UnsubscriptionsPartiallyRejected { reason_codes } => {
+                defmt::write!(
+                    f,
+                    "UnsubscriptionsPartiallyRejected({})",
+                    reason_codes.as_slice()
+                )
+            }
+            ClientReceivedEvent::Reauthenticated => defmt::write!(f, "Reauthenticated"),
+            ClientReceivedEvent::Disconnected {
+                reason_code,
+                properties,
+            } => defmt::write!(
+                f,
+                "Disconnected({}, {} properties)",
+                reason_code,
+                properties.len()
+            ),
         }
     }
 }
 
 #[allow(async_fn_in_trait)]
-pub trait EventHandler<const P: usize> {
+pub trait EventHandler<const P: usize = PROPERTIES_DEFAULT> {
     async fn handle_event(
         &mut self,
         event: ClientReceivedEvent<P>,
     ) -> Result<(), EventHandlerError>;
 }
 
-pub struct ClientNoQueue<'a, C, D, F, const P: usize>
-where
-    C: Connection,
-    D: Delay,
-    F: EventHandler<P>,
-{
-    packet_client: PacketClient<'a, C>,
-    client_state: ClientStateNoQueue,
-    delay: D,
-    timeout_millis: u32,
-    event_handler: F,
-}
-
-impl<'a, C, D, F, const P: usize> ClientNoQueue<'a, C, D, F, P>
-where
-    C: Connection,
-    D: Delay,
-    F: EventHandler<P>,
-{
-    pub fn new(
-        connection: C,
-        buf: &'a mut [u8],
-        delay: D,
-        timeout_millis: u32,
-        event_handler: F,
-    ) -> Self {
-        let packet_client = PacketClient::new(connection, buf);
-        let client_state = ClientStateNoQueue::default();
+/// The default timeout used for each operation by [ClientNoQueueOptions]
+pub const CLIENT_NO_QUEUE_TIMEOUT_MILLIS_DEFAULT: u32 = 5000;
+
+/// The default interval between polls for responses used by [ClientNoQueueOptions]
+pub const CLIENT_NO_QUEUE_POLL_INTERVAL_MICROS_DEFAULT: u32 = 1000;
+
+/// The number of recently handled qos 1 messages checked for redelivery, see
+/// [ClientNoQueue::with_duplicate_detection]
+pub const DUPLICATE_DETECTION_WINDOW: usize = 8;
+
+/// The number of recently published messages checked for local echoes, see
+/// [ClientNoQueue::with_local_echo_suppression]
+pub const LOCAL_ECHO_WINDOW: usize = 8;
+
+/// Timeouts used by [ClientNoQueue] while waiting for responses from the server,
+/// and the interval between pings used to check the server is still responsive.
+/// Time is measured with the client's [Clock](crate::timing::Clock), see
+/// [ConnectionTimer](crate::timing::ConnectionTimer).
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ClientNoQueueOptions {
+    /// The maximum time to wait for the server to acknowledge a connection
+    connect_timeout_millis: u32,
+
+    /// The maximum time to wait for the server to respond to a packet that
+    /// requires a response, e.g. subscribe, unsubscribe, publish with qos 1 or higher
+    ack_timeout_millis: u32,
+
+    /// The maximum time to go without a response to a connect or ping from the
+    /// server, before the server is considered unresponsive.
+    /// This can be used with a longer ack timeout to allow for slow responses
+    /// from a server that is still responding to pings.
+    receive_timeout_millis: u32,
+
+    /// The time between pings sent to the server while connected, or None
+    /// to ping at half the receive timeout
+    ping_interval_millis: Option<u32>,
+
+    /// The delay between polls for a response from the server
+    poll_interval_micros: u32,
+}
+
+impl ClientNoQueueOptions {
+    /// Create options with all timeouts set to `timeout_millis`, pings
+    /// at half the receive timeout, and the default poll interval
+    pub fn new(timeout_millis: u32) -> Self {
         Self {
-            packet_client,
-            client_state,
-            delay,
-            timeout_millis,
-            event_handler,
+            connect_timeout_millis: timeout_millis,
+            ack_timeout_millis: timeout_millis,
+            receive_timeout_millis: timeout_millis,
+            ping_interval_millis: None,
+            poll_interval_micros: CLIENT_NO_QUEUE_POLL_INTERVAL_MICROS_DEFAULT,
         }
     }
 
-    async fn wait_for_responses(&mut self, timeout_millis: u32) -> Result<(), ClientError> {
-        let mut elapsed = 0;
-        let mut waiting = self.client_state.waiting_for_responses();
-        while waiting && elapsed <= timeout_millis {
-            self.poll(false).await?;
-            waiting = self.client_state.waiting_for_responses();
-            elapsed += 1;
-            self.delay.delay_us(1000).await;
-        }
+    pub fn with_connect_timeout(mut self, connect_timeout_millis: u32) -> Self {
+        self.connect_timeout_millis = connect_timeout_millis;
+        self
+    }
 
-        if waiting {
-            Err(ClientError::TimeoutOnResponsePacket)
-        } else {
-            Ok(())
-        }
+    pub fn with_ack_timeout(mut self, ack_timeout_millis: u32) -> Self {
+        self.ack_timeout_millis = ack_timeout_millis;
+        self
     }
 
-    async fn send_wait_for_responses<PW>(&mut self, packet: PW) -> Result<(), ClientError>
-    where
-        PW: Packet + write::Write,
-    {
-        match self.packet_client.send(packet).await {
-            Ok(()) => {
-                self.wait_for_responses(self.timeout_millis).await?;
-                Ok(())
-            }
-            Err(e) => {
-                self.client_state.error();
-                Err(e.into())
-            }
-        }
+    pub fn with_receive_timeout(mut self, receive_timeout_millis: u32) -> Self {
+        self.receive_timeout_millis = receive_timeout_millis;
+        self
+    }
+
+    /// Set the time between pings, this should be less than the receive timeout
+    /// so that a response to each ping can arrive before the server is
+    /// considered unresponsive
+    pub fn with_ping_interval(mut self, ping_interval_millis: u32) -> Self {
+        self.ping_interval_millis = Some(ping_interval_millis);
+        self
     }
 
-    async fn send<PW>(&mut self, packet: PW) -> Result<(), ClientError>
-    where
-        PW: Packet + write::Write,
-    {
-        let r = self.packet_client.send(packet).await;
-        if r.is_err() {
-            self.client_state.error();
+    /// Set the delay between polls, this is limited to at least 1 microsecond
+    pub fn with_poll_interval(mut self, poll_interval_micros: u32) -> Self {
+        self.poll_interval_micros = poll_interval_micros.max(1);
+        self
+    }
+
+    pub fn connect_timeout_millis(&self) -> u32 {
+        self.connect_timeout_millis
+    }
+
+    pub fn ack_timeout_millis(&self) -> u32 {
+        self.ack_timeout_millis
+    }
+
+    pub fn receive_timeout_millis(&self) -> u32 {
+        self.receive_timeout_millis
+    }
+
+    pub fn ping_interval_millis(&self) -> u32 {
+        self.ping_interval_millis
+            .unwrap_or(self.receive_timeout_millis / 2)
+    }
+
+    pub fn poll_interval_micros(&self) -> u32 {
+        self.poll_interval_micros
+    }
+
+    /// The [ConnectionTimerSettings] for these options, retrying a deferred
+    /// ping after the poll interval
+    pub fn connection_timer_settings(&self) -> ConnectionTimerSettings {
+        ConnectionTimerSettings {
+            receive_timeout_millis: self.receive_timeout_millis as u64,
+            ping_interval_millis: self.ping_interval_millis() as u64,
+            ping_retry_delay_millis: (self.poll_interval_micros as u64).div_ceil(1000),
         }
-        r?;
-        Ok(())
     }
 }
 
-impl<'a, C, D, F, const P: usize> Client<'a> for ClientNoQueue<'a, C, D, F, P>
-where
-    C: Connection,
-    D: Delay,
-    F: EventHandler<P>,
-{
-    async fn connect_with_will<const W: usize>(
-        &mut self,
-        settings: &ConnectionSettings<'_>,
-        will: Option<Will<'_, W>>,
-    ) -> Result<(), ClientError> {
-        let mut properties = Vec::new();
-        // By setting maximum topic alias to 0, we prevent the server
-        // trying to use aliases, which we don't support. They are optional
-        // and only provide for reduced packet size, but would require storing
-        // topic names from the server for the length of the connection,
-        // which might be awkward without alloc.
-        properties
-            .push(ConnectProperty::TopicAliasMaximum(0.into()))
-            .unwrap();
-        let packet: Connect<'_, 1, W> = Connect::new(
-            settings.keep_alive,
-            settings.username,
-            settings.password,
-            settings.client_id,
-            true,
-            will,
-            properties,
-        );
-        self.client_state.connect(&packet)?;
-        self.send_wait_for_responses(packet).await
-    }
-    async fn connect(&mut self, settings: &ConnectionSettings<'_>) -> Result<(), ClientError> {
-        self.connect_with_will::<0>(settings, None).await
-    }
-
-    async fn disconnect(&mut self) -> Result<(), ClientError> {
-        let packet = self.client_state.disconnect()?;
-        self.send(packet).await
-    }
-
-    async fn publish_with_properties<'b, const PP: usize>(
-        &'b mut self,
-        topic_name: &'b str,
-        payload: &'b [u8],
-        qos: QualityOfService,
-        retain: bool,
-        properties: Vec<PublishProperty<'b>, PP>,
-    ) -> Result<(), ClientError> {
-        let packet = self
-            .client_state
-            .publish_with_properties(topic_name, payload, qos, retain, properties)?;
-        self.send_wait_for_responses(packet).await
+impl Default for ClientNoQueueOptions {
+    fn default() -> Self {
+        Self::new(CLIENT_NO_QUEUE_TIMEOUT_MILLIS_DEFAULT)
     }
+}
 
-    async fn subscribe<'b>(
-        &'b mut self,
-        topic_name: &'b str,
-        maximum_qos: QualityOfService,
-    ) -> Result<(), ClientError> {
-        let packet = self.client_state.subscribe(topic_name, maximum_qos)?;
-        self.send_wait_for_responses(packet).await
-    }
-
-    async fn unsubscribe<'b>(&'b mut self, topic_name: &'b str) -> Result<(), ClientError> {
-        let packet = self.client_state.unsubscribe(topic_name)?;
-        self.send_wait_for_responses(packet).await
-    }
-
-    async fn send_ping(&mut self) -> Result<(), ClientError> {
-        let packet = self.client_state.send_ping()?;
-        self.send(packet).await
-    }
-
-    async fn poll(&mut self, wait: bool) -> Result<bool, ClientError> {
-        // We need to wrap up like this so we can drop the mutable reference to
-        // self.packet_client needed to receive data - this reference needs to live
-        // as long as the returned data from the client, so we need to drop everything
-        // but the packet we need to send, in order to be able to mutably borrow
-        // packet_client again to actually do the send.
-        // Note we allow 0 will properties and additional subscriptions, since we
-        // shouldn't receive any messages using these, since we are a client.
-        let to_send = {
-            let packet: Option<PacketGeneric<'_, P, 0, 0>> = if wait {
-                Some(self.packet_client.receive().await?)
-            } else {
-                self.packet_client.receive_if_ready().await?
-            };
-
-            if let Some(packet) = packet {
-                let event = self.client_state.receive(packet)?;
-
-                match event {
-                    ClientStateReceiveEvent::Ack => {
-                        self.event_handler
-                            .handle_event(ClientReceivedEvent::Ack)
-                            .await?;
-                        None
-                    }
-
-                    ClientStateReceiveEvent::Publish { publish } => {
-                        if publish.topic_name().is_empty() {
-                            return Err(ClientError::EmptyTopicNameWithAliasesDisabled);
-                        }
-                        self.event_handler.handle_event(publish.into()).await?;
-                        None
-                    }
-
-                    ClientStateReceiveEvent::PublishAndPuback { publish, puback } => {
-                        if publish.topic_name().is_empty() {
-                            return Err(ClientError::EmptyTopicNameWithAliasesDisabled);
-                        }
-                        self.event_handler.handle_event(publish.into()).await?;
-                        Some(puback)
-                    }
-
-                    ClientStateReceiveEvent::SubscriptionGrantedBelowMaximumQos {
-                        granted_qos,
-                        maximum_qos,
-                    } => {
-                        self.event_handler
-                            .handle_event(ClientReceivedEvent::SubscriptionGrantedBelowMaximumQos {
-                                granted_qos,
-                                maximum_qos,
-                            })
-                            .await?;
-                        None
-                    }
-
-                    ClientStateReceiveEvent::PublishedMessageHadNoMatchingSubscribers => {
-                        self.event_handler
-                            .handle_event(
-                                ClientReceivedEvent::PublishedMessageHadNoMatchingSubscribers,
-                            )
-                            .await?;
-                        None
-                    }
-
-                    ClientStateReceiveEvent::NoSubscriptionExisted => {
-                        self.event_handler
-                            .handle_event(ClientReceivedEvent::NoSubscriptionExisted)
-                            .await?;
-                        None
-                    }
-
-                    ClientStateReceiveEvent::Disconnect { disconnect } => {
-                        return Err(ClientError::Disconnected(*disconnect.reason_code()));
-                    }
-                }
-            } else {
-                return Ok(false);
-            }
-        };
+/// All options used to create a [ClientNoQueue], combining the
+/// [ClientNoQueueOptions] timeouts with the client's optional behaviours,
+/// so that new options can be added without changing constructor signatures.
+/// Each behaviour is disabled by default, and corresponds to a `with_*` method
+/// on [ClientNoQueue] that can also be used to set it after creation.
+/// A [ClientNoQueueOptions] converts into [ClientOptions] with default behaviours.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ClientOptions {
+    timeouts: ClientNoQueueOptions,
+    validate_payload_format: bool,
+    accept_downgraded_qos: bool,
+    detect_duplicates: bool,
+    no_local: bool,
+    suppress_local_echo: bool,
+    discard_oversized_packets: bool,
+    skip_unknown_properties: bool,
+    property_overflow: PropertyOverflow,
+}
 
-        // Send any resulting packet, no need to wait for responses
-        if let Some(packet) = to_send {
-            self.send(packet).await?;
+impl ClientOptions {
+    /// Create options with the specified timeouts, and default behaviours
+    pub fn new(timeouts: ClientNoQueueOptions) -> Self {
+        Self {
+            timeouts,
+            ..Default::default()
         }
+    }
+
+    pub fn with_timeouts(mut self, timeouts: ClientNoQueueOptions) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// See [ClientNoQueue::with_payload_format_validation]
+    pub fn with_payload_format_validation(mut self, validate_payload_format: bool) -> Self {
+        self.validate_payload_format = validate_payload_format;
+        self
+    }
+
+    /// See [ClientNoQueue::with_accept_downgraded_qos]
+    pub fn with_accept_downgraded_qos(mut self, accept_downgraded_qos: bool) -> Self {
+        self.accept_downgraded_qos = accept_downgraded_qos;
+        self
+    }
+
+    /// See [ClientNoQueue::with_duplicate_detection]
+    pub fn with_duplicate_detection(mut self, detect_duplicates: bool) -> Self {
+        self.detect_duplicates = detect_duplicates;
+        self
+    }
+
+    /// See [ClientNoQueue::with_no_local]
+    pub fn with_no_local(mut self, no_local: bool) -> Self {
+        self.no_local = no_local;
+        self
+    }
+
+    /// See [ClientNoQueue::with_local_echo_suppression]
+    pub fn with_local_echo_suppression(mut self, suppress_local_echo: bool) -> Self {
+        self.suppress_local_echo = suppress_local_echo;
+        self
+    }
+
+    /// See [ClientNoQueue::with_discard_oversized_packets]
+    pub fn with_discard_oversized_packets(mut self, discard_oversized_packets: bool) -> Self {
+        self.discard_oversized_packets = discard_oversized_packets;
+        self
+    }
+
+    /// See [ClientNoQueue::with_skip_unknown_properties]
+    pub fn with_skip_unknown_properties(mut self, skip_unknown_properties: bool) -> Self {
+        self.skip_unknown_properties = skip_unknown_properties;
+        self
+    }
+
+    /// See [ClientNoQueue::with_property_overflow]
+    pub fn with_property_overflow(mut self, property_overflow: PropertyOverflow) -> Self {
+        self.property_overflow = property_overflow;
+        self
+    }
+
+    pub fn timeouts(&self) -> &ClientNoQueueOptions {
+        &self.timeouts
+    }
+
+    pub fn validate_payload_format(&self) -> bool {
+        self.validate_payload_format
+    }
+
+    pub fn accept_downgraded_qos(&self) -> bool {
+        self.accept_downgraded_qos
+    }
 
-        Ok(true)
+    pub fn detect_duplicates(&self) -> bool {
+        self.detect_duplicates
     }
+
+    pub fn no_local(&self) -> bool {
+        self.no_local
+    }
+
+    pub fn suppress_local_echo(&self) -> bool {
+        self.suppress_local_echo
+    }
+
+    pub fn discard_oversized_packets(&self) -> bool {
+        self.discard_oversized_packets
+    }
+
+    pub fn skip_unknown_properties(&self) -> bool {
+        self.skip_unknown_properties
+    }
+
+    pub fn property_overflow(&self) -> PropertyOverflow {
+        self.property_overflow
+    }
+}
+
+impl From<ClientNoQueueOptions> for ClientOptions {
+    fn from(timeouts: ClientNoQueueOptions) -> Self {
+        Self::new(timeouts)
+    }
+}
+
+/// A quality of service 0 message to publish as part of a batch,
+/// see [ClientNoQueue::publish_batch]
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BatchMessage<'a> {
+    pub topic_name: &'a str,
+    pub payload: &'a [u8],
+    pub retain: bool,
 }
+
+impl<'a> BatchMessage<'a> {
+    pub fn new(topic_name: &'a str, payload: &'a [u8], retain: bool) -> Self {
+        Self {
+            topic_name,
+            payload,
+            retain,
+        }
+    }
+}
+
+/// A [ManagedClient] using [ClientStateNoQueue], which supports only one request
+/// awaiting a response from the server at a time, waiting for each response
+/// before returning.
+#[cfg(feature = "client-codec")]
+pub type ClientNoQueue<'a, C, D, T, F, const P: usize = PROPERTIES_DEFAULT> =
+    ManagedClient<'a, C, D, T, ClientStateNoQueue, F, P>;
@@ -0,0 +1,235 @@
+//! Helpers to generate and validate client identifiers, e.g. to derive a
+//! stable client id from a chip's unique id, or add a random suffix.
+
+#[cfg(feature = "display")]
+use core::fmt::{Display, Formatter};
+
+use heapless::String;
+
+/// The maximum length of client identifier that every server must accept
+/// [MQTT-3.1.3-5]. Servers may accept longer client identifiers.
+pub const CLIENT_ID_MAX_LEN: usize = 23;
+
+/// The characters that every server must accept in a client identifier
+/// [MQTT-3.1.3-5]. Servers may accept other characters.
+pub const CLIENT_ID_CHARACTERS: &[u8; 62] =
+    b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+/// Errors producing or validating a client identifier
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ClientIdError {
+    /// Client identifier is empty. This is valid in a Connect packet, where
+    /// it requests that the server assigns a client identifier, but cannot
+    /// be used to identify the client across connections.
+    Empty,
+
+    /// Client identifier is longer than [CLIENT_ID_MAX_LEN]
+    TooLong,
+
+    /// Client identifier contains a character not in [CLIENT_ID_CHARACTERS]
+    InvalidCharacter,
+
+    /// Client identifier does not fit in the provided string capacity
+    Overflow,
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for ClientIdError {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Empty => defmt::write!(f, "Empty"),
+            Self::TooLong => defmt::write!(f, "TooLong"),
+            Self::InvalidCharacter => defmt::write!(f, "InvalidCharacter"),
+            Self::Overflow => defmt::write!(f, "Overflow"),
+        }
+    }
+}
+
+#[cfg(feature = "display")]
+impl Display for ClientIdError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "Empty"),
+            Self::TooLong => write!(f, "TooLong"),
+            Self::InvalidCharacter => write!(f, "InvalidCharacter"),
+            Self::Overflow => write!(f, "Overflow"),
+        }
+    }
+}
+
+/// Check that a client identifier will be accepted by every server, i.e. it
+/// is between 1 and [CLIENT_ID_MAX_LEN] bytes long, and contains only
+/// [CLIENT_ID_CHARACTERS] [MQTT-3.1.3-5].
+/// Many servers accept longer client identifiers using other characters
+/// (e.g. `-` or `_`), so this is only needed where the server is not known.
+pub fn validate_client_id(client_id: &str) -> Result<(), ClientIdError> {
+    if client_id.is_empty() {
+        Err(ClientIdError::Empty)
+    } else if client_id.len() > CLIENT_ID_MAX_LEN {
+        Err(ClientIdError::TooLong)
+    } else if !client_id.bytes().all(|c| CLIENT_ID_CHARACTERS.contains(&c)) {
+        Err(ClientIdError::InvalidCharacter)
+    } else {
+        Ok(())
+    }
+}
+
+/// Check that a client identifier in a Connect packet will be accepted by
+/// every server, as for [validate_client_id], except that an empty client
+/// identifier is also accepted when `clean_start` is true, requesting that
+/// the server assigns a client identifier [MQTT-3.1.3-6]. Without clean start,
+/// servers may reject an empty client identifier [MQTT-3.1.3-8].
+pub fn validate_connect_client_id(client_id: &str, clean_start: bool) -> Result<(), ClientIdError> {
+    if client_id.is_empty() && clean_start {
+        Ok(())
+    } else {
+        validate_client_id(client_id)
+    }
+}
+
+/// Produce a client identifier from a prefix, followed by a unique id (e.g. read
+/// from a microcontroller's flash) as upper case hexadecimal, e.g. prefix
+/// "sensor" and unique id `[0xE6, 0x61, 0x38, 0x52]` gives "sensorE6613852".
+/// The prefix is not validated, see [validate_client_id].
+pub fn client_id_from_unique_id<const N: usize>(
+    prefix: &str,
+    unique_id: &[u8],
+) -> Result<String<N>, ClientIdError> {
+    let mut client_id = String::new();
+    client_id
+        .push_str(prefix)
+        .map_err(|_| ClientIdError::Overflow)?;
+    for b in unique_id {
+        for digit in [b >> 4, b & 0x0F] {
+            client_id
+                .push(HEX_DIGITS[digit as usize] as char)
+                .map_err(|_| ClientIdError::Overflow)?;
+        }
+    }
+    Ok(client_id)
+}
+
+/// Produce a client identifier from a prefix, followed by `suffix_len` random
+/// characters from [CLIENT_ID_CHARACTERS], e.g. "sensor" gives "sensor3kQ9xA"
+/// for a suffix length of 6.
+/// The prefix is not validated, see [validate_client_id].
+#[cfg(feature = "rand_core")]
+pub fn client_id_with_random_suffix<const N: usize, R: rand_core::RngCore>(
+    prefix: &str,
+    suffix_len: usize,
+    rng: &mut R,
+) -> Result<String<N>, ClientIdError> {
+    // Use only random bytes below the largest multiple of the number of characters,
+    // so that each character is equally likely
+    const LIMIT: u8 = (256 / CLIENT_ID_CHARACTERS.len() * CLIENT_ID_CHARACTERS.len()) as u8;
+
+    let mut client_id = String::new();
+    client_id
+        .push_str(prefix)
+        .map_err(|_| ClientIdError::Overflow)?;
+    let mut remaining = suffix_len;
+    while remaining > 0 {
+        let mut bytes = [0u8; 8];
+        rng.fill_bytes(&mut bytes);
+        for b in bytes.iter().filter(|b| **b < LIMIT).take(remaining) {
+            let c = CLIENT_ID_CHARACTERS[*b as usize % CLIENT_ID_CHARACTERS.len()];
+            client_id
+                .push(c as char)
+                .map_err(|_| ClientIdError::Overflow)?;
+            remaining -= 1;
+        }
+    }
+    Ok(client_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_ids_are_validated() {
+        assert_eq!(validate_client_id("sensor01"), Ok(()));
+        assert_eq!(validate_client_id("abcdefghijklmnopqrstuvw"), Ok(()));
+        assert_eq!(validate_client_id(""), Err(ClientIdError::Empty));
+        assert_eq!(
+            validate_client_id("abcdefghijklmnopqrstuvwx"),
+            Err(ClientIdError::TooLong)
+        );
+        assert_eq!(
+            validate_client_id("sensor-01"),
+            Err(ClientIdError::InvalidCharacter)
+        );
+    }
+
+    #[test]
+    fn empty_connect_client_id_needs_clean_start() {
+        assert_eq!(validate_connect_client_id("", true), Ok(()));
+        assert_eq!(
+            validate_connect_client_id("", false),
+            Err(ClientIdError::Empty)
+        );
+        assert_eq!(validate_connect_client_id("sensor01", false), Ok(()));
+        assert_eq!(
+            validate_connect_client_id("sensor-01", true),
+            Err(ClientIdError::InvalidCharacter)
+        );
+    }
+
+    #[test]
+    fn client_id_is_formatted_from_unique_id() {
+        let client_id: String<32> =
+            client_id_from_unique_id("sensor", &[0xE6, 0x61, 0x38, 0x52, 0x0F]).unwrap();
+        assert_eq!(client_id, "sensorE66138520F");
+        assert_eq!(validate_client_id(&client_id), Ok(()));
+        assert_eq!(
+            client_id_from_unique_id::<8>("sensor", &[0xE6, 0x61]),
+            Err(ClientIdError::Overflow)
+        );
+    }
+
+    #[cfg(feature = "rand_core")]
+    #[test]
+    fn client_id_has_random_suffix() {
+        /// Produces bytes counting up from 0, wrapping
+        struct CountingRng(u8);
+
+        impl rand_core::RngCore for CountingRng {
+            fn next_u32(&mut self) -> u32 {
+                rand_core::impls::next_u32_via_fill(self)
+            }
+
+            fn next_u64(&mut self) -> u64 {
+                rand_core::impls::next_u64_via_fill(self)
+            }
+
+            fn fill_bytes(&mut self, dest: &mut [u8]) {
+                for b in dest.iter_mut() {
+                    *b = self.0;
+                    self.0 = self.0.wrapping_add(1);
+                }
+            }
+
+            fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+                self.fill_bytes(dest);
+                Ok(())
+            }
+        }
+
+        let mut rng = CountingRng(0);
+        let client_id: String<32> = client_id_with_random_suffix("sensor", 12, &mut rng).unwrap();
+        assert_eq!(client_id, "sensor0123456789ab");
+
+        // Bytes 248 to 255 are skipped, so 246 and 247 are followed by 0 and 1
+        let mut rng = CountingRng(246);
+        let client_id: String<32> = client_id_with_random_suffix("", 4, &mut rng).unwrap();
+        assert_eq!(client_id, "YZ01");
+        assert_eq!(validate_client_id(&client_id), Ok(()));
+
+        assert_eq!(
+            client_id_with_random_suffix::<8, _>("sensor", 6, &mut rng),
+            Err(ClientIdError::Overflow)
+        );
+    }
+}
@@ -1,3 +1,4 @@
+#[cfg(feature = "display")]
 use core::fmt::{Display, Formatter};
 
 use heapless::Vec;
@@ -5,23 +6,32 @@ use heapless::Vec;
 use crate::{
     data::{
         packet_identifier::{PacketIdentifier, PublishPacketIdentifier},
-        property::{ConnackProperty, Property, PublishProperty},
+        property::{
+            AuthProperty, ConnectProperty, PropertiesExt, Property, PublishProperty,
+            ServerKeepAlive, SessionExpiryInterval, SubscribeProperty,
+        },
         quality_of_service::QualityOfService,
         reason_code::{
-            ConnectReasonCode, PublishReasonCode, SubscribeReasonCode, UnsubscribeReasonCode,
+            AuthReasonCode, ConnectReasonCode, DisconnectReasonCode, PublishReasonCode,
+            SubscribeReasonCode, UnsubscribeReasonCode,
         },
+        subscription_options::SubscriptionOptions,
     },
     error::{PacketReadError, PacketWriteError},
     packets::{
+        auth::Auth,
         connect::Connect,
         disconnect::Disconnect,
-        packet_generic::PacketGeneric,
         pingreq::Pingreq,
         puback::Puback,
         publish::Publish,
+        server_packet::ServerPacket,
+        suback::Suback,
         subscribe::{Subscribe, SubscriptionRequest},
+        unsuback::Unsuback,
         unsubscribe::Unsubscribe,
     },
+    subscriptions::{granted_qos, SubscriptionRegistry},
 };
 
 /// [ClientState] error
@@ -31,8 +41,16 @@ pub enum ClientStateError {
     PacketRead(PacketReadError),
     NotIdle,
     AuthNotSupported,
+    /// Re-authentication was requested, but the connection was not made with
+    /// an authentication method property, so the server will not accept it
+    NoAuthenticationMethod,
+    /// An Auth packet was received when not re-authenticating, or with a
+    /// reason code other than continue authentication or success
+    UnexpectedAuth,
     Qos2NotSupported,
-    MultipleSubscriptionRequestsNotSupported,
+    TooManySubscriptionRequests,
+    UnexpectedSubackReasonCodeCount,
+    UnexpectedUnsubackReasonCodeCount,
     ReceivedQos2PublishNotSupported,
     ClientIsWaitingForResponse,
     NotConnected,
@@ -49,6 +67,10 @@ pub enum ClientStateError {
     ReceivedPacketOtherThanConnackOrAuthWhenConnecting,
     ReceivedConnackWhenNotConnecting,
     UnexpectedSessionPresentForCleanStart,
+    /// A non-zero session expiry interval was requested when disconnecting,
+    /// but the connection was made with a session expiry interval of 0, so the
+    /// server would treat it as a protocol error [MQTT-3.14.2-2]
+    SessionExpiryIntervalWasZero,
     Connect(ConnectReasonCode),
     Subscribe(SubscribeReasonCode),
     Publish(PublishReasonCode),
@@ -63,9 +85,15 @@ impl defmt::Format for ClientStateError {
             Self::PacketRead(e) => defmt::write!(f, "PacketRead({})", e),
             Self::NotIdle => defmt::write!(f, "NotIdle"),
             Self::AuthNotSupported => defmt::write!(f, "AuthNotSupported"),
+            Self::NoAuthenticationMethod => defmt::write!(f, "NoAuthenticationMethod"),
+            Self::UnexpectedAuth => defmt::write!(f, "UnexpectedAuth"),
             Self::Qos2NotSupported => defmt::write!(f, "Qos2NotSupported"),
-            Self::MultipleSubscriptionRequestsNotSupported => {
-                defmt::write!(f, "MultipleSubscriptionRequestsNotSupported")
+            Self::TooManySubscriptionRequests => defmt::write!(f, "TooManySubscriptionRequests"),
+            Self::UnexpectedSubackReasonCodeCount => {
+                defmt::write!(f, "UnexpectedSubackReasonCodeCount")
+            }
+            Self::UnexpectedUnsubackReasonCodeCount => {
+                defmt::write!(f, "UnexpectedUnsubackReasonCodeCount")
             }
             Self::ReceivedQos2PublishNotSupported => {
                 defmt::write!(f, "ReceivedQos2PublishNotSupported")
@@ -99,6 +127,7 @@ impl defmt::Format for ClientStateError {
             Self::UnexpectedSessionPresentForCleanStart => {
                 defmt::write!(f, "UnexpectedSessionPresentForCleanStart")
             }
+            Self::SessionExpiryIntervalWasZero => defmt::write!(f, "SessionExpiryIntervalWasZero"),
             Self::Connect(r) => defmt::write!(f, "Connect({})", r),
             Self::Subscribe(r) => defmt::write!(f, "Subscribe({})", r),
             Self::Publish(r) => defmt::write!(f, "Publish({})", r),
@@ -107,6 +136,7 @@ impl defmt::Format for ClientStateError {
     }
 }
 
+#[cfg(feature = "display")]
 impl Display for ClientStateError {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match self {
@@ -114,9 +144,13 @@ impl Display for ClientStateError {
             Self::PacketRead(e) => write!(f, "PacketRead({})", e),
             Self::NotIdle => write!(f, "NotIdle"),
             Self::AuthNotSupported => write!(f, "AuthNotSupported"),
+            Self::NoAuthenticationMethod => write!(f, "NoAuthenticationMethod"),
+            Self::UnexpectedAuth => write!(f, "UnexpectedAuth"),
             Self::Qos2NotSupported => write!(f, "Qos2NotSupported"),
-            Self::MultipleSubscriptionRequestsNotSupported => {
-                write!(f, "MultipleSubscriptionRequestsNotSupported")
+            Self::TooManySubscriptionRequests => write!(f, "TooManySubscriptionRequests"),
+            Self::UnexpectedSubackReasonCodeCount => write!(f, "UnexpectedSubackReasonCodeCount"),
+            Self::UnexpectedUnsubackReasonCodeCount => {
+                write!(f, "UnexpectedUnsubackReasonCodeCount")
             }
             Self::ReceivedQos2PublishNotSupported => write!(f, "ReceivedQos2PublishNotSupported"),
             Self::ClientIsWaitingForResponse => write!(f, "ClientIsWaitingForResponse"),
@@ -147,8 +181,122 @@ impl Display for ClientStateError {
             Self::UnexpectedSessionPresentForCleanStart => {
                 write!(f, "UnexpectedSessionPresentForCleanStart")
             }
+            Self::SessionExpiryIntervalWasZero => write!(f, "SessionExpiryIntervalWasZero"),
+        }
+    }
+}
+
+impl ClientStateError {
+    /// True if the error is transient, and may not recur with a new connection,
+    /// e.g. a failure to send data, an unexpected packet from the server, or a
+    /// server reason code like [ReasonCode::ServerBusy](crate::data::reason_code::ReasonCode::ServerBusy).
+    /// False if the error would be expected to recur, e.g. use of an unsupported
+    /// feature, or a server reason code like
+    /// [ReasonCode::NotAuthorized](crate::data::reason_code::ReasonCode::NotAuthorized).
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::PacketWrite(e) => e.is_retryable(),
+            Self::PacketRead(e) => e.is_retryable(),
+            Self::AuthNotSupported
+            | Self::NoAuthenticationMethod
+            | Self::Qos2NotSupported
+            | Self::TooManySubscriptionRequests
+            | Self::ReceivedQos2PublishNotSupported
+            | Self::ServerOnlyMessageReceived
+            | Self::SessionExpiryIntervalWasZero => false,
+            Self::NotIdle
+            | Self::UnexpectedSubackReasonCodeCount
+            | Self::UnexpectedUnsubackReasonCodeCount
+            | Self::ClientIsWaitingForResponse
+            | Self::NotConnected
+            | Self::ReceiveWhenNotConnectedOrConnecting
+            | Self::UnexpectedPuback
+            | Self::UnexpectedPubackPacketIdentifier
+            | Self::UnexpectedSuback
+            | Self::UnexpectedSubackPacketIdentifier
+            | Self::UnexpectedUnsuback
+            | Self::UnexpectedUnsubackPacketIdentifier
+            | Self::UnexpectedPingresp
+            | Self::UnexpectedAuth
+            | Self::Disconnect
+            | Self::ReceivedPacketOtherThanConnackOrAuthWhenConnecting
+            | Self::ReceivedConnackWhenNotConnecting
+            | Self::UnexpectedSessionPresentForCleanStart => true,
+            Self::Connect(r) => r.is_retryable(),
+            Self::Subscribe(r) => r.is_retryable(),
+            Self::Publish(r) => r.is_retryable(),
+            Self::Unsubscribe(r) => r.is_retryable(),
         }
     }
+
+    /// The reason code to use in a Disconnect packet sent to the server when
+    /// this error occurs handling a received packet, or None if the error is
+    /// not caused by the server violating the protocol, e.g. the server
+    /// rejecting a request, or the client being used in the wrong state.
+    pub fn disconnect_reason_code(&self) -> Option<DisconnectReasonCode> {
+        match self {
+            Self::PacketRead(e) => e.disconnect_reason_code(),
+            Self::UnexpectedSubackReasonCodeCount
+            | Self::UnexpectedUnsubackReasonCodeCount
+            | Self::UnexpectedPuback
+            | Self::UnexpectedPubackPacketIdentifier
+            | Self::UnexpectedSuback
+            | Self::UnexpectedSubackPacketIdentifier
+            | Self::UnexpectedUnsuback
+            | Self::UnexpectedUnsubackPacketIdentifier
+            | Self::UnexpectedPingresp
+            | Self::UnexpectedAuth
+            | Self::ServerOnlyMessageReceived
+            | Self::ReceivedPacketOtherThanConnackOrAuthWhenConnecting
+            | Self::ReceivedConnackWhenNotConnecting
+            | Self::UnexpectedSessionPresentForCleanStart => {
+                Some(DisconnectReasonCode::ProtocolError)
+            }
+            Self::ReceivedQos2PublishNotSupported => Some(DisconnectReasonCode::QosNotSupported),
+            Self::PacketWrite(_)
+            | Self::NotIdle
+            | Self::AuthNotSupported
+            | Self::NoAuthenticationMethod
+            | Self::Qos2NotSupported
+            | Self::TooManySubscriptionRequests
+            | Self::SessionExpiryIntervalWasZero
+            | Self::ClientIsWaitingForResponse
+            | Self::NotConnected
+            | Self::ReceiveWhenNotConnectedOrConnecting
+            | Self::Disconnect
+            | Self::Connect(_)
+            | Self::Subscribe(_)
+            | Self::Publish(_)
+            | Self::Unsubscribe(_) => None,
+        }
+    }
+}
+
+/// The packet received from the server to acknowledge a packet sent by the
+/// client, with the details needed to tell which packet was acknowledged
+/// when several are in flight
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Acknowledgement {
+    /// The server accepted the connection
+    Connack { session_present: bool },
+
+    /// The server accepted the qos 1 publish with `packet_identifier`
+    Puback {
+        packet_identifier: PacketIdentifier,
+        reason_code: PublishReasonCode,
+    },
+
+    /// The server granted the subscribe with `packet_identifier`. The reason
+    /// code (and so granted qos) of each subscription request is recorded in the
+    /// client state's [SubscriptionRegistry]
+    Suback { packet_identifier: PacketIdentifier },
+
+    /// The server accepted the unsubscribe with `packet_identifier`
+    Unsuback { packet_identifier: PacketIdentifier },
+
+    /// The server responded to a ping
+    Pingresp,
 }
 
 pub enum ClientStateReceiveEvent<'a, 'b, const P: usize> {
@@ -156,7 +304,7 @@ pub enum ClientStateReceiveEvent<'a, 'b, const P: usize> {
     /// to the server (e.g. Connack, Puback, Suback, Unsuback, Pingresp)
     /// These are all handled internally by the state, so do not need an external
     /// response
-    Ack,
+    Ack(Acknowledgement),
 
     /// A published message was received
     Publish { publish: Publish<'a, P> },
@@ -174,11 +322,22 @@ pub enum ClientStateReceiveEvent<'a, 'b, const P: usize> {
     /// then the client could respond by showing an error to the user stating the
     /// server is incompatible, or possibly trying to unsubscribe and resubscribe,
     /// assuming this is expected to make any difference with the server(s) in use.
+    /// `packet_identifier` is that of the subscribe packet.
     SubscriptionGrantedBelowMaximumQos {
+        packet_identifier: PacketIdentifier,
         granted_qos: QualityOfService,
         maximum_qos: QualityOfService,
     },
 
+    /// A subscribe packet with multiple subscription requests was acknowledged,
+    /// but some (not all) of the requests were rejected. `reason_codes` contains
+    /// the reason code for each request, in the order they were requested, showing
+    /// which were granted (and at what qos), and which were rejected.
+    /// If all requests are rejected, this is instead an error.
+    SubscriptionsPartiallyRejected {
+        reason_codes: Vec<SubscribeReasonCode, MAX_SUBSCRIPTION_REQUESTS>,
+    },
+
     /// A published message was received at the server, but had no matching subscribers and
     /// so did not reach any receivers
     /// This may or may not require action depending on client requirements / expectations
@@ -194,10 +353,106 @@ pub enum ClientStateReceiveEvent<'a, 'b, const P: usize> {
     /// up subscriptions again.
     NoSubscriptionExisted,
 
+    /// An unsubscribe packet with multiple topic filters was acknowledged, but some
+    /// (not all) of the unsubscriptions were rejected. `reason_codes` contains the
+    /// reason code for each topic filter, in the order they were requested.
+    /// If all unsubscriptions are rejected, this is instead an error.
+    UnsubscriptionsPartiallyRejected {
+        reason_codes: Vec<UnsubscribeReasonCode, MAX_SUBSCRIPTION_REQUESTS>,
+    },
+
+    /// An [Auth] packet was received while re-authenticating, see
+    /// [ClientState::reauthenticate]. If the reason code is
+    /// [AuthReasonCode::ContinueAuthentication], the server requires a response,
+    /// which should be sent using [ClientState::continue_authentication].
+    /// If the reason code is [AuthReasonCode::Success], re-authentication is
+    /// complete. In both cases the packet may contain
+    /// [AuthenticationData](crate::data::property::AuthenticationData).
+    Auth { auth: Auth<'a, P> },
+
     /// A [Disconnect] packet was received, it should contain a reason for our disconnection
     Disconnect { disconnect: Disconnect<'a, P> },
 }
 
+/// Counts of requests sent to the server that have not yet received
+/// a response, see [ClientState::pending_counts]
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PendingCounts {
+    /// Pingreqs sent without a Pingresp
+    pub pings: u32,
+    /// Publishes (qos 1) sent without a Puback
+    pub publishes: u32,
+    /// Subscribes sent without a Suback
+    pub subscribes: u32,
+    /// Unsubscribes sent without an Unsuback
+    pub unsubscribes: u32,
+}
+
+impl PendingCounts {
+    /// True if there are no pending requests of any kind
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The kind of a request sent to the server that has not yet received
+/// a response, see [ClientState::pending_requests]
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PendingRequestKind {
+    /// Publish (qos 1) waiting for a Puback
+    Publish,
+    /// Subscribe waiting for a Suback
+    Subscribe,
+    /// Unsubscribe waiting for an Unsuback
+    Unsubscribe,
+}
+
+impl PendingRequestKind {
+    /// The name of the packet expected in response to the request
+    pub fn response_name(&self) -> &'static str {
+        match self {
+            PendingRequestKind::Publish => "Puback",
+            PendingRequestKind::Subscribe => "Suback",
+            PendingRequestKind::Unsubscribe => "Unsuback",
+        }
+    }
+}
+
+/// A request sent to the server that has not yet received a response,
+/// see [ClientState::pending_requests].
+/// Displays as the expected response, e.g. "Suback id 7".
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct PendingRequest {
+    pub kind: PendingRequestKind,
+    pub packet_identifier: PacketIdentifier,
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for PendingRequest {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "{} id {}",
+            self.kind.response_name(),
+            self.packet_identifier.0
+        )
+    }
+}
+
+#[cfg(feature = "display")]
+impl Display for PendingRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} id {}",
+            self.kind.response_name(),
+            self.packet_identifier.0
+        )
+    }
+}
+
 impl From<PacketWriteError> for ClientStateError {
     fn from(value: PacketWriteError) -> Self {
         ClientStateError::PacketWrite(value)
@@ -223,6 +478,8 @@ pub trait ClientState {
 
     /// Update state based on a packet used to connect to server
     /// Call this after connect packet has been successfully sent.
+    /// This is possible for a new state, and also after disconnecting or an
+    /// error, to reconnect using the same state.
     fn connect<const P: usize, const W: usize>(
         &mut self,
         connect: &Connect<'_, P, W>,
@@ -231,6 +488,15 @@ pub trait ClientState {
     /// Produce a packet to disconnect from server, update state
     fn disconnect<'b>(&mut self) -> Result<Disconnect<'b, 0>, ClientStateError>;
 
+    /// Produce a packet to disconnect from server with a new session expiry
+    /// interval, update state. Fails with [ClientStateError::SessionExpiryIntervalWasZero]
+    /// if the interval is non-zero, but the connection was made without a
+    /// non-zero session expiry interval.
+    fn disconnect_with_session_expiry_interval<'b>(
+        &mut self,
+        session_expiry_interval: u32,
+    ) -> Result<Disconnect<'b, 1>, ClientStateError>;
+
     /// Produce a packet to ping the server, update state
     fn send_ping(&mut self) -> Result<Pingreq, ClientStateError>;
 
@@ -238,14 +504,65 @@ pub trait ClientState {
     /// to, otherwise 0.
     fn pending_ping_count(&self) -> u32;
 
+    /// If connected, the keep alive interval in seconds for the connection,
+    /// which is the Server Keep Alive from the Connack if present, otherwise
+    /// the value requested in the Connect packet. A value of 0 means keep
+    /// alive is disabled. None if not connected.
+    fn keep_alive(&self) -> Option<u16>;
+
+    /// If connected, the number of each kind of request that has been sent,
+    /// but not responded to, otherwise all counts are 0.
+    fn pending_counts(&self) -> PendingCounts;
+
+    /// If connected, the requests that have been sent but not responded to,
+    /// with their packet identifiers, e.g. to report what a timed out client
+    /// was waiting for. Otherwise empty.
+    /// Pending pings and re-authentication don't use packet identifiers, see
+    /// [ClientState::pending_ping_count] and [ClientState::authenticating].
+    fn pending_requests(&self) -> impl Iterator<Item = PendingRequest> + '_;
+
+    /// If connected, the subscriptions the client believes are active for the
+    /// current session, based on the subscribe and unsubscribe packets sent and
+    /// acknowledged, see [SubscriptionRegistry]. These are kept when reconnecting
+    /// with the session present, see [SubscriptionRegistry::connected]. None if not connected, or if the
+    /// implementation doesn't track subscriptions.
+    fn subscriptions(&self) -> Option<&ClientStateSubscriptions> {
+        None
+    }
+
+    /// Produce an [Auth] packet to start re-authentication with the server,
+    /// using the same authentication method as the [Connect] packet, and
+    /// the given authentication data, update state.
+    /// Re-authentication is only possible if the Connect packet had an
+    /// [AuthenticationMethod](crate::data::property::AuthenticationMethod) property.
+    /// Other requests may still be sent while re-authenticating, but the state
+    /// will be waiting for responses until the server completes re-authentication,
+    /// see [ClientStateReceiveEvent::Auth].
+    fn reauthenticate<'b>(
+        &mut self,
+        method: &'b str,
+        data: &'b [u8],
+    ) -> Result<Auth<'b, 2>, ClientStateError>;
+
+    /// Produce an [Auth] packet to respond to an Auth packet from the server with
+    /// [AuthReasonCode::ContinueAuthentication], while re-authenticating.
+    fn continue_authentication<'b>(
+        &mut self,
+        method: &'b str,
+        data: &'b [u8],
+    ) -> Result<Auth<'b, 2>, ClientStateError>;
+
+    /// True if re-authentication has been started and not yet completed
+    fn authenticating(&self) -> bool;
+
     /// Receive a packet
     /// This updates the client state, and if anything that might require
     /// action by the caller occurs, a [ClientStateReceiveEvent] is returned.
     /// Errors indicate an invalid packet was received, message_target errored,
     /// or the received packet was unexpected based on our state
-    fn receive<'a, 'b, const P: usize, const W: usize, const S: usize>(
+    fn receive<'a, 'b, const P: usize, const S: usize>(
         &mut self,
-        packet: PacketGeneric<'a, P, W, S>,
+        packet: ServerPacket<'a, P, S>,
     ) -> Result<ClientStateReceiveEvent<'a, 'b, P>, ClientStateError>;
 
     /// Receive a packet and produce the corresponding packet to send as
@@ -254,9 +571,9 @@ pub trait ClientState {
     /// do this.
     /// Errors indicate an invalid packet was received,
     /// or the received packet was unexpected based on our state
-    fn receive_produce_response<'a, const P: usize, const W: usize, const S: usize>(
+    fn receive_produce_response<'a, const P: usize, const S: usize>(
         &self,
-        packet: &PacketGeneric<'a, P, W, S>,
+        packet: &ServerPacket<'a, P, S>,
     ) -> Result<Option<Puback<'_, P>>, ClientStateError>;
 
     /// Produce a packet to subscribe to a topic by name, update state
@@ -270,13 +587,71 @@ pub trait ClientState {
         Ok(packet)
     }
 
+    /// Produce a packet to subscribe to a topic by name with [`SubscriptionOptions`]
+    /// (e.g. to control [`RetainHandling`](crate::data::subscription_options::RetainHandling)),
+    /// update state
+    fn subscribe_with_options<'b>(
+        &mut self,
+        topic_name: &'b str,
+        options: SubscriptionOptions,
+    ) -> Result<Subscribe<'b, 0, 0>, ClientStateError> {
+        let packet = self.subscribe_requests_packet(
+            SubscriptionRequest::with_options(topic_name, options),
+            Vec::new(),
+        )?;
+        self.subscribe_update(&packet)?;
+        Ok(packet)
+    }
+
+    /// Produce a packet to subscribe to a topic by name, update state, with properties
+    fn subscribe_with_properties<'b, const P: usize>(
+        &mut self,
+        topic_name: &'b str,
+        maximum_qos: QualityOfService,
+        properties: Vec<SubscribeProperty<'b>, P>,
+    ) -> Result<Subscribe<'b, P, 0>, ClientStateError> {
+        let packet = self.subscribe_with_properties_packet(
+            SubscriptionRequest::new(topic_name, maximum_qos),
+            Vec::new(),
+            properties,
+        )?;
+        self.subscribe_update(&packet)?;
+        Ok(packet)
+    }
+
     /// Produce a packet to subscribe to a topic by name, this does not update
     /// the state - call [`Self::subscribe_update`] after sending the packet.
     fn subscribe_packet<'b>(
         &mut self,
         topic_name: &'b str,
         maximum_qos: QualityOfService,
-    ) -> Result<Subscribe<'b, 0, 0>, ClientStateError>;
+    ) -> Result<Subscribe<'b, 0, 0>, ClientStateError> {
+        self.subscribe_requests_packet(
+            SubscriptionRequest::new(topic_name, maximum_qos),
+            Vec::new(),
+        )
+    }
+
+    /// Produce a packet to subscribe to one or more topics, each with their own
+    /// [`SubscriptionOptions`].
+    /// This does not update the state - call [`Self::subscribe_update`] after sending the packet.
+    fn subscribe_requests_packet<'b, const S: usize>(
+        &mut self,
+        first_request: SubscriptionRequest<'b>,
+        other_requests: Vec<SubscriptionRequest<'b>, S>,
+    ) -> Result<Subscribe<'b, 0, S>, ClientStateError> {
+        self.subscribe_with_properties_packet(first_request, other_requests, Vec::new())
+    }
+
+    /// Produce a packet to subscribe to one or more topics, with properties
+    /// (e.g. a subscription identifier).
+    /// This does not update the state - call [`Self::subscribe_update`] after sending the packet.
+    fn subscribe_with_properties_packet<'b, const P: usize, const S: usize>(
+        &mut self,
+        first_request: SubscriptionRequest<'b>,
+        other_requests: Vec<SubscriptionRequest<'b>, S>,
+        properties: Vec<SubscribeProperty<'b>, P>,
+    ) -> Result<Subscribe<'b, P, S>, ClientStateError>;
 
     /// Update the state of the client after sending a subscribe packet
     fn subscribe_update<'b, const P: usize, const S: usize>(
@@ -299,7 +674,17 @@ pub trait ClientState {
     fn unsubscribe_packet<'b>(
         &mut self,
         topic_name: &'b str,
-    ) -> Result<Unsubscribe<'b, 0, 0>, ClientStateError>;
+    ) -> Result<Unsubscribe<'b, 0, 0>, ClientStateError> {
+        self.unsubscribe_topics_packet(topic_name, Vec::new())
+    }
+
+    /// Produce a packet to unsubscribe from one or more topics by name, this does not update
+    /// the state - call [`Self::unsubscribe_update`] after sending the packet.
+    fn unsubscribe_topics_packet<'b, const S: usize>(
+        &mut self,
+        first_topic_name: &'b str,
+        other_topic_names: Vec<&'b str, S>,
+    ) -> Result<Unsubscribe<'b, 0, S>, ClientStateError>;
 
     /// Update the state of the client after sending an unsubscribe packet
     fn unsubscribe_update<'b, const P: usize, const S: usize>(
@@ -370,8 +755,33 @@ pub trait ClientState {
     fn error(&mut self);
 }
 
+/// The maximum number of subscription requests (topics) in a single subscribe or
+/// unsubscribe packet supported by [ClientStateNoQueue]
+pub const MAX_SUBSCRIPTION_REQUESTS: usize = 8;
+
+/// The maximum number of subscriptions tracked by [ClientStateNoQueue],
+/// see [ClientState::subscriptions]
+pub const MAX_TRACKED_SUBSCRIPTIONS: usize = 8;
+
+/// The maximum length in bytes of topic filters of subscriptions tracked by
+/// [ClientStateNoQueue], see [ClientState::subscriptions]
+pub const MAX_TRACKED_TOPIC_FILTER_LEN: usize = 64;
+
+/// The [SubscriptionRegistry] used by [ClientStateNoQueue]
+pub type ClientStateSubscriptions =
+    SubscriptionRegistry<MAX_TRACKED_SUBSCRIPTIONS, MAX_TRACKED_TOPIC_FILTER_LEN>;
+
+#[derive(PartialEq)]
+pub struct ClientStateNoQueue {
+    phase: Phase,
+
+    /// Kept outside [Phase] so that the subscriptions of a session are kept
+    /// when reconnecting with the session present
+    subscriptions: ClientStateSubscriptions,
+}
+
 #[derive(PartialEq)]
-pub enum ClientStateNoQueue {
+enum Phase {
     Idle,
     Connecting(RequestedConnectionInfo),
     Connected(ConnectionState),
@@ -383,6 +793,8 @@ pub enum ClientStateNoQueue {
 pub struct RequestedConnectionInfo {
     clean_start: bool,
     keep_alive: u16,
+    has_authentication_method: bool,
+    has_session_expiry: bool,
 }
 
 #[derive(PartialEq)]
@@ -390,6 +802,9 @@ pub struct ConnectionInfo {
     pending_ping_count: u32,
     session_present: bool,
     keep_alive: u16,
+    has_authentication_method: bool,
+    has_session_expiry: bool,
+    authenticating: bool,
 }
 
 #[derive(PartialEq)]
@@ -406,10 +821,13 @@ enum Waiting {
     },
     ForSuback {
         id: PacketIdentifier,
-        qos: QualityOfService,
+        /// The maximum qos for each subscription request, in order
+        qos: Vec<QualityOfService, MAX_SUBSCRIPTION_REQUESTS>,
     },
     ForUnsuback {
         id: PacketIdentifier,
+        /// The number of topic filters in the unsubscribe packet
+        count: usize,
     },
 }
 
@@ -419,18 +837,117 @@ impl Waiting {
             Self::None => false,
             Self::ForPuback { id: _ } => true,
             Self::ForSuback { id: _, qos: _ } => true,
-            Self::ForUnsuback { id: _ } => true,
+            Self::ForUnsuback { id: _, count: _ } => true,
         }
     }
 }
 
+/// Produce the event for a suback with the expected number of reason codes,
+/// given the maximum qos of each subscription request.
+/// If all requests were rejected this is an error with the first reason code.
+/// If only some requests were rejected, we produce an event with all reason codes.
+/// Otherwise, we note the first subscription (if any) granted below its maximum qos.
+fn suback_event<'a, 'b, const P: usize, const PP: usize, const S: usize>(
+    suback: &Suback<'_, PP, S>,
+    requested_qos: &[QualityOfService],
+) -> Result<ClientStateReceiveEvent<'a, 'b, P>, ClientStateError> {
+    let mut first_error = None;
+    let mut granted_count = 0;
+    let packet_identifier = *suback.packet_identifier();
+    let mut event = ClientStateReceiveEvent::Ack(Acknowledgement::Suback { packet_identifier });
+    for (reason_code, maximum_qos) in suback.reason_codes().zip(requested_qos.iter()) {
+        match granted_qos(reason_code) {
+            Some(granted_qos) => {
+                granted_count += 1;
+                if granted_qos != *maximum_qos && matches!(event, ClientStateReceiveEvent::Ack(_)) {
+                    event = ClientStateReceiveEvent::SubscriptionGrantedBelowMaximumQos {
+                        packet_identifier,
+                        granted_qos,
+                        maximum_qos: *maximum_qos,
+                    };
+                }
+            }
+            None => {
+                first_error.get_or_insert(*reason_code);
+            }
+        }
+    }
+
+    match first_error {
+        Some(reason_code) if granted_count == 0 => Err(ClientStateError::Subscribe(reason_code)),
+        Some(_) => Ok(ClientStateReceiveEvent::SubscriptionsPartiallyRejected {
+            reason_codes: suback.reason_codes().copied().collect(),
+        }),
+        None => Ok(event),
+    }
+}
+
+/// Produce the event for an unsuback, given the number of topic filters in
+/// the unsubscribe packet.
+/// If all unsubscriptions were rejected this is an error with the first reason code.
+/// If only some were rejected, we produce an event with all reason codes.
+fn unsuback_event<'a, 'b, const P: usize, const PP: usize, const S: usize>(
+    unsuback: &Unsuback<'_, PP, S>,
+    count: usize,
+) -> Result<ClientStateReceiveEvent<'a, 'b, P>, ClientStateError> {
+    if unsuback.other_reason_codes().len() + 1 != count {
+        return Err(ClientStateError::UnexpectedUnsubackReasonCodeCount);
+    }
+
+    let error_count = unsuback.reason_codes().filter(|r| r.is_error()).count();
+    if error_count == count {
+        Err(ClientStateError::Unsubscribe(*unsuback.first_reason_code()))
+    } else if error_count > 0 {
+        Ok(ClientStateReceiveEvent::UnsubscriptionsPartiallyRejected {
+            reason_codes: unsuback.reason_codes().copied().collect(),
+        })
+    } else if unsuback
+        .reason_codes()
+        .any(|r| r == &UnsubscribeReasonCode::NoSubscriptionExisted)
+    {
+        Ok(ClientStateReceiveEvent::NoSubscriptionExisted)
+    } else {
+        Ok(ClientStateReceiveEvent::Ack(Acknowledgement::Unsuback {
+            packet_identifier: *unsuback.packet_identifier(),
+        }))
+    }
+}
+
+/// An [Auth] packet with the given reason code, authentication method and data
+/// The data is optional, so is omitted if empty.
+fn auth_packet<'b>(reason_code: AuthReasonCode, method: &'b str, data: &'b [u8]) -> Auth<'b, 2> {
+    let mut properties = Vec::new();
+    properties
+        .push(AuthProperty::AuthenticationMethod(method.into()))
+        .unwrap();
+    if !data.is_empty() {
+        properties
+            .push(AuthProperty::AuthenticationData(data.into()))
+            .unwrap();
+    }
+    Auth::new(reason_code, properties)
+}
+
+/// True if the reason code is expected in an [Auth] packet from the server
+/// while re-authenticating - [AuthReasonCode::ReAuthenticate] is only sent
+/// by the client
+fn auth_reason_code_expected(reason_code: &AuthReasonCode) -> bool {
+    matches!(
+        reason_code,
+        AuthReasonCode::Success | AuthReasonCode::ContinueAuthentication
+    )
+}
+
 impl ClientStateNoQueue {
     const PUBLISH_PACKET_IDENTIFIER: PacketIdentifier = PacketIdentifier(1);
     const SUBSCRIBE_PACKET_IDENTIFIER: PacketIdentifier = PacketIdentifier(2);
     const UNSUBSCRIBE_PACKET_IDENTIFIER: PacketIdentifier = PacketIdentifier(3);
 
     pub fn new() -> Self {
-        Self::Idle
+        Self {
+            phase: Phase::Idle,
+            subscriptions: SubscriptionRegistry::new(),
+        }
     }
 }
 
@@ -442,12 +959,14 @@ impl Default for ClientStateNoQueue {
 
 impl ClientState for ClientStateNoQueue {
     fn waiting_for_responses(&self) -> bool {
-        match self {
-            Self::Idle => false,
-            Self::Connecting(_) => true,
-            Self::Connected(connection_data) => connection_data.waiting.is_waiting(),
-            Self::Errored => false,
-            Self::Disconnected => false,
+        match &self.phase {
+            Phase::Idle => false,
+            Phase::Connecting(_) => true,
+            Phase::Connected(connection_data) => {
+                connection_data.waiting.is_waiting() || connection_data.info.authenticating
+            }
+            Phase::Errored => false,
+            Phase::Disconnected => false,
         }
     }
 
@@ -455,11 +974,19 @@ impl ClientState for ClientStateNoQueue {
         &mut self,
         connect: &Connect<'_, P, W>,
     ) -> Result<(), ClientStateError> {
-        match self {
-            ClientStateNoQueue::Idle => {
-                *self = Self::Connecting(RequestedConnectionInfo {
+        match &mut self.phase {
+            Phase::Idle | Phase::Disconnected | Phase::Errored => {
+                self.phase = Phase::Connecting(RequestedConnectionInfo {
                     clean_start: connect.clean_start(),
                     keep_alive: connect.keep_alive(),
+                    has_authentication_method: connect
+                        .properties
+                        .iter()
+                        .any(|p| matches!(p, ConnectProperty::AuthenticationMethod(_))),
+                    has_session_expiry: connect
+                        .properties
+                        .get_property::<SessionExpiryInterval>()
+                        .is_some_and(|p| p.value() != 0),
                 });
                 Ok(())
             }
@@ -468,15 +995,34 @@ impl ClientState for ClientStateNoQueue {
     }
 
     fn disconnect<'b>(&mut self) -> Result<Disconnect<'b, 0>, ClientStateError> {
-        match self {
-            ClientStateNoQueue::Connected(_d) => {
-                *self = Self::Disconnected;
+        match &mut self.phase {
+            Phase::Connected(_d) => {
+                self.phase = Phase::Disconnected;
                 Ok(Disconnect::default())
             }
             _ => Err(ClientStateError::NotConnected),
         }
     }
 
+    fn disconnect_with_session_expiry_interval<'b>(
+        &mut self,
+        session_expiry_interval: u32,
+    ) -> Result<Disconnect<'b, 1>, ClientStateError> {
+        match &mut self.phase {
+            Phase::Connected(d) => {
+                if session_expiry_interval != 0 && !d.info.has_session_expiry {
+                    return Err(ClientStateError::SessionExpiryIntervalWasZero);
+                }
+                self.phase = Phase::Disconnected;
+                Ok(Disconnect::with_session_expiry_interval(
+                    DisconnectReasonCode::Success,
+                    session_expiry_interval,
+                ))
+            }
+            _ => Err(ClientStateError::NotConnected),
+        }
+    }
+
     fn publish_with_properties_packet<'b, const P: usize>(
         &mut self,
         topic_name: &'b str,
@@ -485,8 +1031,8 @@ impl ClientState for ClientStateNoQueue {
         retain: bool,
         properties: Vec<PublishProperty<'b>, P>,
     ) -> Result<Publish<'b, P>, ClientStateError> {
-        match self {
-            ClientStateNoQueue::Connected(ConnectionState { info: _, waiting }) => {
+        match &mut self.phase {
+            Phase::Connected(ConnectionState { info: _, waiting }) => {
                 let publish_packet_identifier = match qos {
                     QualityOfService::Qos0 => Ok(PublishPacketIdentifier::None),
                     QualityOfService::Qos1 if waiting.is_waiting() => {
@@ -516,8 +1062,8 @@ impl ClientState for ClientStateNoQueue {
         &mut self,
         packet: &Publish<'b, P>,
     ) -> Result<(), ClientStateError> {
-        match self {
-            ClientStateNoQueue::Connected(ConnectionState { info: _, waiting }) => {
+        match &mut self.phase {
+            Phase::Connected(ConnectionState { info: _, waiting }) => {
                 match packet.publish_packet_identifier() {
                     PublishPacketIdentifier::None => Ok(()),
                     PublishPacketIdentifier::Qos1(packet_identifier) => {
@@ -537,25 +1083,27 @@ impl ClientState for ClientStateNoQueue {
         }
     }
 
-    fn subscribe_packet<'b>(
+    fn subscribe_with_properties_packet<'b, const P: usize, const S: usize>(
         &mut self,
-        topic_name: &'b str,
-        maximum_qos: QualityOfService,
-    ) -> Result<Subscribe<'b, 0, 0>, ClientStateError> {
-        match self {
-            ClientStateNoQueue::Connected(ConnectionState { info: _, waiting }) => {
+        first_request: SubscriptionRequest<'b>,
+        other_requests: Vec<SubscriptionRequest<'b>, S>,
+        properties: Vec<SubscribeProperty<'b>, P>,
+    ) -> Result<Subscribe<'b, P, S>, ClientStateError> {
+        match &mut self.phase {
+            Phase::Connected(ConnectionState { info: _, waiting }) => {
+                let subscribe: Subscribe<'_, P, S> = Subscribe::new(
+                    Self::SUBSCRIBE_PACKET_IDENTIFIER,
+                    first_request,
+                    other_requests,
+                    properties,
+                );
                 if waiting.is_waiting() {
                     Err(ClientStateError::ClientIsWaitingForResponse)
-                } else if maximum_qos == QualityOfService::Qos2 {
+                } else if subscribe.request_count() > MAX_SUBSCRIPTION_REQUESTS {
+                    Err(ClientStateError::TooManySubscriptionRequests)
+                } else if subscribe.request_maximum_qos() == QualityOfService::Qos2 {
                     Err(ClientStateError::Qos2NotSupported)
                 } else {
-                    let first_request = SubscriptionRequest::new(topic_name, maximum_qos);
-                    let subscribe: Subscribe<'_, 0, 0> = Subscribe::new(
-                        Self::SUBSCRIBE_PACKET_IDENTIFIER,
-                        first_request,
-                        Vec::new(),
-                        Vec::new(),
-                    );
                     Ok(subscribe)
                 }
             }
@@ -567,19 +1115,25 @@ impl ClientState for ClientStateNoQueue {
         &mut self,
         packet: &Subscribe<'b, P, S>,
     ) -> Result<(), ClientStateError> {
-        match self {
-            ClientStateNoQueue::Connected(ConnectionState { info: _, waiting }) => {
+        match &mut self.phase {
+            Phase::Connected(ConnectionState { info: _, waiting }) => {
                 if waiting.is_waiting() {
                     Err(ClientStateError::ClientIsWaitingForResponse)
-                } else if packet.request_count() > 1 {
-                    Err(ClientStateError::MultipleSubscriptionRequestsNotSupported)
                 } else if packet.request_maximum_qos() == QualityOfService::Qos2 {
                     Err(ClientStateError::Qos2NotSupported)
                 } else {
+                    let qos = packet
+                        .requests()
+                        .map(|r| r.options.maximum_qos)
+                        .collect::<Vec<_, MAX_SUBSCRIPTION_REQUESTS>>();
+                    if qos.len() < packet.request_count() {
+                        return Err(ClientStateError::TooManySubscriptionRequests);
+                    }
                     *waiting = Waiting::ForSuback {
                         id: *packet.packet_identifier(),
-                        qos: packet.request_maximum_qos(),
+                        qos,
                     };
+                    self.subscriptions.subscribe_sent(packet);
 
                     Ok(())
                 }
@@ -588,19 +1142,22 @@ impl ClientState for ClientStateNoQueue {
         }
     }
 
-    fn unsubscribe_packet<'b>(
+    fn unsubscribe_topics_packet<'b, const S: usize>(
         &mut self,
-        topic_name: &'b str,
-    ) -> Result<Unsubscribe<'b, 0, 0>, ClientStateError> {
-        match self {
-            ClientStateNoQueue::Connected(ConnectionState { info: _, waiting }) => {
+        first_topic_name: &'b str,
+        other_topic_names: Vec<&'b str, S>,
+    ) -> Result<Unsubscribe<'b, 0, S>, ClientStateError> {
+        match &mut self.phase {
+            Phase::Connected(ConnectionState { info: _, waiting }) => {
                 if waiting.is_waiting() {
                     Err(ClientStateError::ClientIsWaitingForResponse)
+                } else if other_topic_names.len() + 1 > MAX_SUBSCRIPTION_REQUESTS {
+                    Err(ClientStateError::TooManySubscriptionRequests)
                 } else {
-                    let unsubscribe: Unsubscribe<'_, 0, 0> = Unsubscribe::new(
+                    let unsubscribe: Unsubscribe<'_, 0, S> = Unsubscribe::new(
                         Self::UNSUBSCRIBE_PACKET_IDENTIFIER,
-                        topic_name,
-                        Vec::new(),
+                        first_topic_name,
+                        other_topic_names,
                         Vec::new(),
                     );
 
@@ -615,14 +1172,20 @@ impl ClientState for ClientStateNoQueue {
         &mut self,
         packet: &Unsubscribe<'b, P, S>,
     ) -> Result<(), ClientStateError> {
-        match self {
-            ClientStateNoQueue::Connected(ConnectionState { info: _, waiting }) => {
+        match &mut self.phase {
+            Phase::Connected(ConnectionState { info: _, waiting }) => {
                 if waiting.is_waiting() {
                     Err(ClientStateError::ClientIsWaitingForResponse)
                 } else {
+                    let count = packet.topic_names().count();
+                    if count > MAX_SUBSCRIPTION_REQUESTS {
+                        return Err(ClientStateError::TooManySubscriptionRequests);
+                    }
                     *waiting = Waiting::ForUnsuback {
                         id: *packet.packet_identifier(),
+                        count,
                     };
+                    self.subscriptions.unsubscribe_sent(packet);
 
                     Ok(())
                 }
@@ -631,9 +1194,57 @@ impl ClientState for ClientStateNoQueue {
         }
     }
 
+    fn reauthenticate<'b>(
+        &mut self,
+        method: &'b str,
+        data: &'b [u8],
+    ) -> Result<Auth<'b, 2>, ClientStateError> {
+        match &mut self.phase {
+            Phase::Connected(ConnectionState { info, .. }) => {
+                if !info.has_authentication_method {
+                    Err(ClientStateError::NoAuthenticationMethod)
+                } else if info.authenticating {
+                    Err(ClientStateError::ClientIsWaitingForResponse)
+                } else {
+                    info.authenticating = true;
+                    Ok(auth_packet(AuthReasonCode::ReAuthenticate, method, data))
+                }
+            }
+            _ => Err(ClientStateError::NotConnected),
+        }
+    }
+
+    fn continue_authentication<'b>(
+        &mut self,
+        method: &'b str,
+        data: &'b [u8],
+    ) -> Result<Auth<'b, 2>, ClientStateError> {
+        match &mut self.phase {
+            Phase::Connected(ConnectionState { info, .. }) => {
+                if info.authenticating {
+                    Ok(auth_packet(
+                        AuthReasonCode::ContinueAuthentication,
+                        method,
+                        data,
+                    ))
+                } else {
+                    Err(ClientStateError::UnexpectedAuth)
+                }
+            }
+            _ => Err(ClientStateError::NotConnected),
+        }
+    }
+
+    fn authenticating(&self) -> bool {
+        match &self.phase {
+            Phase::Connected(connection_state) => connection_state.info.authenticating,
+            _ => false,
+        }
+    }
+
     fn send_ping(&mut self) -> Result<Pingreq, ClientStateError> {
-        match self {
-            ClientStateNoQueue::Connected(ConnectionState { info, waiting: _ }) => {
+        match &mut self.phase {
+            Phase::Connected(ConnectionState { info, waiting: _ }) => {
                 info.pending_ping_count += 1;
                 Ok(Pingreq::default())
             }
@@ -641,19 +1252,21 @@ impl ClientState for ClientStateNoQueue {
         }
     }
 
-    fn receive_produce_response<'a, const P: usize, const W: usize, const S: usize>(
+    fn receive_produce_response<'a, const P: usize, const S: usize>(
         &self,
-        packet: &PacketGeneric<'a, P, W, S>,
+        packet: &ServerPacket<'a, P, S>,
     ) -> Result<Option<Puback<'_, P>>, ClientStateError> {
-        match self {
+        match &self.phase {
             // If we are connecting, we only expect a Connack packet
             // (server cannot disconnect before Connack [MQTT-3.14.0-1])
             // or an Auth packet [MQTT-3.2.0-1]
-            ClientStateNoQueue::Connecting(RequestedConnectionInfo {
+            Phase::Connecting(RequestedConnectionInfo {
                 clean_start,
                 keep_alive: _,
+                has_authentication_method: _,
+                has_session_expiry: _,
             }) => match packet {
-                PacketGeneric::Connack(connack) => match connack.reason_code() {
+                ServerPacket::Connack(connack) => match connack.reason_code() {
                     ConnectReasonCode::Success => {
                         let session_present = connack.session_present();
 
@@ -666,13 +1279,13 @@ impl ClientState for ClientStateNoQueue {
                     }
                     reason_code => Err(ClientStateError::Connect(*reason_code)),
                 },
-                PacketGeneric::Auth(_) => Err(ClientStateError::AuthNotSupported),
+                ServerPacket::Auth(_) => Err(ClientStateError::AuthNotSupported),
                 _ => Err(ClientStateError::ReceivedPacketOtherThanConnackOrAuthWhenConnecting),
             },
 
             // If we are connected, we handle all client packets other than Connack
-            ClientStateNoQueue::Connected(ConnectionState { info, waiting }) => match packet {
-                PacketGeneric::Publish(publish) => match publish.publish_packet_identifier() {
+            Phase::Connected(ConnectionState { info, waiting }) => match packet {
+                ServerPacket::Publish(publish) => match publish.publish_packet_identifier() {
                     PublishPacketIdentifier::None => Ok(None),
                     PublishPacketIdentifier::Qos1(packet_identifier) => {
                         let puback =
@@ -684,7 +1297,7 @@ impl ClientState for ClientStateNoQueue {
                     }
                 },
 
-                PacketGeneric::Puback(puback) => {
+                ServerPacket::Puback(puback) => {
                     let ack_id = puback.packet_identifier();
                     match waiting {
                         Waiting::ForPuback { id } if id == ack_id => {
@@ -702,7 +1315,7 @@ impl ClientState for ClientStateNoQueue {
                     }
                 }
 
-                PacketGeneric::Suback(suback) => {
+                ServerPacket::Suback(suback) => {
                     let ack_id = suback.packet_identifier();
 
                     match waiting {
@@ -713,61 +1326,58 @@ impl ClientState for ClientStateNoQueue {
                         _ => Err(ClientStateError::UnexpectedSuback),
                     }
                 }
-                PacketGeneric::Unsuback(unsuback) => {
+                ServerPacket::Unsuback(unsuback) => {
                     let ack_id = unsuback.packet_identifier();
 
                     match waiting {
-                        Waiting::ForUnsuback { id } if id == ack_id => {
-                            let reason_code = unsuback.first_reason_code();
-                            if reason_code.is_error() {
-                                Err(ClientStateError::Unsubscribe(*reason_code))
-                            } else {
-                                Ok(None)
-                            }
+                        Waiting::ForUnsuback { id, count } if id == ack_id => {
+                            unsuback_event::<P, P, S>(unsuback, *count).map(|_| None)
                         }
-                        Waiting::ForUnsuback { id: _ } => {
+                        Waiting::ForUnsuback { id: _, count: _ } => {
                             Err(ClientStateError::UnexpectedUnsubackPacketIdentifier)
                         }
                         _ => Err(ClientStateError::UnexpectedUnsuback),
                     }
                 }
-                PacketGeneric::Pingresp(_pingresp) => {
+                ServerPacket::Pingresp(_pingresp) => {
                     if info.pending_ping_count > 0 {
                         Ok(None)
                     } else {
                         Err(ClientStateError::UnexpectedPingresp)
                     }
                 }
-                PacketGeneric::Disconnect(_) => Ok(None),
-                PacketGeneric::Connack(_) => {
-                    Err(ClientStateError::ReceivedConnackWhenNotConnecting)
+                ServerPacket::Disconnect(_) => Ok(None),
+                ServerPacket::Connack(_) => Err(ClientStateError::ReceivedConnackWhenNotConnecting),
+                ServerPacket::Auth(auth) => {
+                    if info.authenticating && auth_reason_code_expected(auth.reason_code()) {
+                        Ok(None)
+                    } else {
+                        Err(ClientStateError::UnexpectedAuth)
+                    }
+                }
+                ServerPacket::Pubrec(_) | ServerPacket::Pubrel(_) | ServerPacket::Pubcomp(_) => {
+                    Err(ClientStateError::ServerOnlyMessageReceived)
                 }
-                PacketGeneric::Auth(_auth) => Err(ClientStateError::AuthNotSupported),
-                PacketGeneric::Connect(_)
-                | PacketGeneric::Pubrec(_)
-                | PacketGeneric::Pubrel(_)
-                | PacketGeneric::Pubcomp(_)
-                | PacketGeneric::Subscribe(_)
-                | PacketGeneric::Unsubscribe(_)
-                | PacketGeneric::Pingreq(_) => Err(ClientStateError::ServerOnlyMessageReceived),
             },
             _ => Err(ClientStateError::ReceiveWhenNotConnectedOrConnecting),
         }
     }
 
-    fn receive<'a, 'b, const P: usize, const W: usize, const S: usize>(
+    fn receive<'a, 'b, const P: usize, const S: usize>(
         &mut self,
-        packet: PacketGeneric<'a, P, W, S>,
+        packet: ServerPacket<'a, P, S>,
     ) -> Result<ClientStateReceiveEvent<'a, 'b, P>, ClientStateError> {
-        match self {
+        match &mut self.phase {
             // If we are connecting, we only expect a Connack packet
             // (server cannot disconnect before Connack [MQTT-3.14.0-1])
             // or an Auth packet [MQTT-3.2.0-1]
-            ClientStateNoQueue::Connecting(RequestedConnectionInfo {
+            Phase::Connecting(RequestedConnectionInfo {
                 clean_start,
                 keep_alive,
+                has_authentication_method,
+                has_session_expiry,
             }) => match packet {
-                PacketGeneric::Connack(connack) => match connack.reason_code() {
+                ServerPacket::Connack(connack) => match connack.reason_code() {
                     ConnectReasonCode::Success => {
                         let session_present = connack.session_present();
 
@@ -777,35 +1387,39 @@ impl ClientState for ClientStateNoQueue {
                         }
 
                         // Keep alive is the one we requested, unless server returns a new one as a property
-                        let mut actual_keep_alive = *keep_alive;
-                        for p in connack.properties().iter() {
-                            if let ConnackProperty::ServerKeepAlive(server_keep_alive) = p {
-                                actual_keep_alive = server_keep_alive.value();
-                            }
-                        }
+                        let actual_keep_alive = connack
+                            .properties()
+                            .get_property::<ServerKeepAlive>()
+                            .map_or(*keep_alive, |p| p.value());
 
                         let info = ConnectionInfo {
                             pending_ping_count: 0,
                             session_present,
                             keep_alive: actual_keep_alive,
+                            has_authentication_method: *has_authentication_method,
+                            has_session_expiry: *has_session_expiry,
+                            authenticating: false,
                         };
 
-                        *self = Self::Connected(ConnectionState {
+                        self.phase = Phase::Connected(ConnectionState {
                             info,
                             waiting: Waiting::None,
                         });
+                        self.subscriptions.connected(session_present);
 
-                        Ok(ClientStateReceiveEvent::Ack)
+                        Ok(ClientStateReceiveEvent::Ack(Acknowledgement::Connack {
+                            session_present,
+                        }))
                     }
                     reason_code => Err(ClientStateError::Connect(*reason_code)),
                 },
-                PacketGeneric::Auth(_) => Err(ClientStateError::AuthNotSupported),
+                ServerPacket::Auth(_) => Err(ClientStateError::AuthNotSupported),
                 _ => Err(ClientStateError::ReceivedPacketOtherThanConnackOrAuthWhenConnecting),
             },
 
             // If we are connected, we handle all client packets other than Connack
-            ClientStateNoQueue::Connected(ConnectionState { info, waiting }) => match packet {
-                PacketGeneric::Publish(publish) => match publish.publish_packet_identifier() {
+            Phase::Connected(ConnectionState { info, waiting }) => match packet {
+                ServerPacket::Publish(publish) => match publish.publish_packet_identifier() {
                     PublishPacketIdentifier::None => {
                         Ok(ClientStateReceiveEvent::Publish { publish })
                     }
@@ -819,7 +1433,7 @@ impl ClientState for ClientStateNoQueue {
                     }
                 },
 
-                PacketGeneric::Puback(puback) => {
+                ServerPacket::Puback(puback) => {
                     let ack_id = puback.packet_identifier();
                     match waiting {
                         Waiting::ForPuback { id } if id == ack_id => {
@@ -831,7 +1445,10 @@ impl ClientState for ClientStateNoQueue {
                             } else if reason_code == &PublishReasonCode::NoMatchingSubscribers {
                                 Ok(ClientStateReceiveEvent::PublishedMessageHadNoMatchingSubscribers)
                             } else {
-                                Ok(ClientStateReceiveEvent::Ack)
+                                Ok(ClientStateReceiveEvent::Ack(Acknowledgement::Puback {
+                                    packet_identifier: *ack_id,
+                                    reason_code: *reason_code,
+                                }))
                             }
                         }
                         Waiting::ForPuback { id: _ } => {
@@ -841,32 +1458,20 @@ impl ClientState for ClientStateNoQueue {
                     }
                 }
 
-                PacketGeneric::Suback(suback) => {
+                ServerPacket::Suback(suback) => {
                     let ack_id = suback.packet_identifier();
 
                     match waiting {
                         Waiting::ForSuback { id, qos } if id == ack_id => {
-                            let maximum_qos = *qos;
+                            let requested_qos = core::mem::take(qos);
                             *waiting = Waiting::None;
 
-                            let reason_code = suback.first_reason_code();
-                            let granted_qos = match reason_code {
-                                SubscribeReasonCode::Success => QualityOfService::Qos0,
-                                SubscribeReasonCode::GrantedQos1 => QualityOfService::Qos1,
-                                SubscribeReasonCode::GrantedQos2 => QualityOfService::Qos2,
-                                err => return Err(ClientStateError::Subscribe(*err)),
-                            };
-
-                            if granted_qos != maximum_qos {
-                                Ok(
-                                    ClientStateReceiveEvent::SubscriptionGrantedBelowMaximumQos {
-                                        granted_qos,
-                                        maximum_qos,
-                                    },
-                                )
-                            } else {
-                                Ok(ClientStateReceiveEvent::Ack)
+                            if suback.other_reason_codes().len() + 1 != requested_qos.len() {
+                                return Err(ClientStateError::UnexpectedSubackReasonCodeCount);
                             }
+                            self.subscriptions.suback_received(&suback);
+
+                            suback_event(&suback, &requested_qos)
                         }
                         Waiting::ForSuback { id: _, qos: _ } => {
                             Err(ClientStateError::UnexpectedSubackPacketIdentifier)
@@ -874,65 +1479,115 @@ impl ClientState for ClientStateNoQueue {
                         _ => Err(ClientStateError::UnexpectedSuback),
                     }
                 }
-                PacketGeneric::Unsuback(unsuback) => {
+                ServerPacket::Unsuback(unsuback) => {
                     let ack_id = unsuback.packet_identifier();
 
                     match waiting {
-                        Waiting::ForUnsuback { id } if id == ack_id => {
+                        Waiting::ForUnsuback { id, count } if id == ack_id => {
+                            let count = *count;
                             *waiting = Waiting::None;
-
-                            let reason_code = unsuback.first_reason_code();
-                            if reason_code.is_error() {
-                                Err(ClientStateError::Unsubscribe(*reason_code))
-                            } else if reason_code == &UnsubscribeReasonCode::NoSubscriptionExisted {
-                                Ok(ClientStateReceiveEvent::NoSubscriptionExisted)
-                            } else {
-                                Ok(ClientStateReceiveEvent::Ack)
-                            }
+                            self.subscriptions.unsuback_received(&unsuback);
+                            unsuback_event(&unsuback, count)
                         }
-                        Waiting::ForUnsuback { id: _ } => {
+                        Waiting::ForUnsuback { id: _, count: _ } => {
                             Err(ClientStateError::UnexpectedUnsubackPacketIdentifier)
                         }
                         _ => Err(ClientStateError::UnexpectedUnsuback),
                     }
                 }
-                PacketGeneric::Pingresp(_pingresp) => {
+                ServerPacket::Pingresp(_pingresp) => {
                     if info.pending_ping_count > 0 {
                         info.pending_ping_count -= 1;
-                        Ok(ClientStateReceiveEvent::Ack)
+                        Ok(ClientStateReceiveEvent::Ack(Acknowledgement::Pingresp))
                     } else {
                         Err(ClientStateError::UnexpectedPingresp)
                     }
                 }
-                PacketGeneric::Disconnect(disconnect) => {
+                ServerPacket::Disconnect(disconnect) => {
                     Ok(ClientStateReceiveEvent::Disconnect { disconnect })
                 }
-                PacketGeneric::Connack(_) => {
-                    Err(ClientStateError::ReceivedConnackWhenNotConnecting)
+                ServerPacket::Connack(_) => Err(ClientStateError::ReceivedConnackWhenNotConnecting),
+                ServerPacket::Auth(auth) => {
+                    if info.authenticating && auth_reason_code_expected(auth.reason_code()) {
+                        if auth.reason_code() == &AuthReasonCode::Success {
+                            info.authenticating = false;
+                        }
+                        Ok(ClientStateReceiveEvent::Auth { auth })
+                    } else {
+                        Err(ClientStateError::UnexpectedAuth)
+                    }
+                }
+                ServerPacket::Pubrec(_) | ServerPacket::Pubrel(_) | ServerPacket::Pubcomp(_) => {
+                    Err(ClientStateError::ServerOnlyMessageReceived)
                 }
-                PacketGeneric::Auth(_auth) => Err(ClientStateError::AuthNotSupported),
-                PacketGeneric::Connect(_)
-                | PacketGeneric::Pubrec(_)
-                | PacketGeneric::Pubrel(_)
-                | PacketGeneric::Pubcomp(_)
-                | PacketGeneric::Subscribe(_)
-                | PacketGeneric::Unsubscribe(_)
-                | PacketGeneric::Pingreq(_) => Err(ClientStateError::ServerOnlyMessageReceived),
             },
             _ => Err(ClientStateError::ReceiveWhenNotConnectedOrConnecting),
         }
     }
 
     fn error(&mut self) {
-        *self = Self::Errored;
+        self.phase = Phase::Errored;
     }
 
     fn pending_ping_count(&self) -> u32 {
-        match self {
-            ClientStateNoQueue::Connected(connection_state) => {
-                connection_state.info.pending_ping_count
-            }
+        match &self.phase {
+            Phase::Connected(connection_state) => connection_state.info.pending_ping_count,
             _ => 0,
         }
     }
+
+    fn keep_alive(&self) -> Option<u16> {
+        match &self.phase {
+            Phase::Connected(connection_state) => Some(connection_state.info.keep_alive),
+            _ => None,
+        }
+    }
+
+    fn subscriptions(&self) -> Option<&ClientStateSubscriptions> {
+        match &self.phase {
+            Phase::Connected(_) => Some(&self.subscriptions),
+            _ => None,
+        }
+    }
+
+    fn pending_counts(&self) -> PendingCounts {
+        match &self.phase {
+            Phase::Connected(ConnectionState { info, waiting }) => {
+                let mut counts = PendingCounts {
+                    pings: info.pending_ping_count,
+                    ..Default::default()
+                };
+                match waiting {
+                    Waiting::None => {}
+                    Waiting::ForPuback { id: _ } => counts.publishes = 1,
+                    Waiting::ForSuback { id: _, qos: _ } => counts.subscribes = 1,
+                    Waiting::ForUnsuback { id: _, count: _ } => counts.unsubscribes = 1,
+                }
+                counts
+            }
+            _ => PendingCounts::default(),
+        }
+    }
+
+    fn pending_requests(&self) -> impl Iterator<Item = PendingRequest> + '_ {
+        let request = match &self.phase {
+            Phase::Connected(ConnectionState { waiting, .. }) => match waiting {
+                Waiting::None => None,
+                Waiting::ForPuback { id } => Some(PendingRequest {
+                    kind: PendingRequestKind::Publish,
+                    packet_identifier: *id,
+                }),
+                Waiting::ForSuback { id, qos: _ } => Some(PendingRequest {
+                    kind: PendingRequestKind::Subscribe,
+                    packet_identifier: *id,
+                }),
+                Waiting::ForUnsuback { id, count: _ } => Some(PendingRequest {
+                    kind: PendingRequestKind::Unsubscribe,
+                    packet_identifier: *id,
+                }),
+            },
+            _ => None,
+        };
+        request.into_iter()
+    }
 }
@@ -2,9 +2,13 @@ use heapless::Vec;
 
 use crate::{
     data::{
-        reason_code::ReasonCode, string_pair::StringPair, subscription_options::SubscriptionOptions,
+        packet_type::PacketType,
+        property::{PacketProperty, PropertiesIter, PropertyOverflow},
+        reason_code::ReasonCode,
+        string_pair::StringPair,
+        subscription_options::SubscriptionOptions,
     },
-    error::PacketReadError,
+    error::{PacketReadError, PacketReadErrorContext},
     packets::subscribe::SubscriptionRequest,
 };
 
@@ -12,13 +16,21 @@ use super::read::Read;
 
 pub type Result<A> = core::result::Result<A, PacketReadError>;
 
+/// A position of a reader, that the reader can be reset to, so that data
+/// read after it can be read again, see [MqttBufReader::mark]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mark {
+    position: usize,
+}
+
 /// A reader providing access to an underlying buffer in the form of
 /// MQTT v5 encoded data. Each `get` method retrieves data from the buffer,
 /// advancing the position.
 /// If a method fails (returns an [Err]), partial data may have been read
 /// before the error was detected. Since an error always indicates invalid
 /// data, there's no reason to allow recovering, and the reader should not
-/// be used further.
+/// be used further - unless it was reset to a [Mark] from before the failed
+/// method, see [MqttBufReader::mark].
 pub trait MqttReader<'a>: Sized {
     /// Get the current position of the reader - this is 0 when reader is
     /// created, and increments by the number of bytes read on each successful
@@ -30,6 +42,25 @@ pub trait MqttReader<'a>: Sized {
     /// Can fail with [PacketReadError::InsufficientData]
     fn get_slice(&mut self, len: usize) -> Result<&'a [u8]>;
 
+    /// True if property lists read by this reader skip properties that are not
+    /// expected for the packet type, see [PropertiesIter::with_skip_unknown].
+    /// False by default.
+    fn skip_unknown_properties(&self) -> bool {
+        false
+    }
+
+    /// How property lists read by this reader handle more properties than the
+    /// capacity of the packet, see [PropertyOverflow].
+    /// [PropertyOverflow::Fail] by default.
+    fn property_overflow(&self) -> PropertyOverflow {
+        PropertyOverflow::Fail
+    }
+
+    /// Called when `count` properties are dropped from a property list, when
+    /// [MqttReader::property_overflow] is [PropertyOverflow::Count].
+    /// Does nothing by default.
+    fn properties_dropped(&mut self, _count: usize) {}
+
     // /// Make a view of this reader that is limited to returning at most
     // /// `remaining` bytes of data. If more than this is requested from the,
     // /// new reader, this will result in [PacketReadError::InsufficientData], even
@@ -175,69 +206,39 @@ pub trait MqttReader<'a>: Sized {
         self.get_slice(len as usize)
     }
 
+    /// Get the next byte of data as a [ReasonCode], where a value not defined
+    /// by MQTT v5 gives [ReasonCode::Unknown]
+    /// Advances the position by 1
+    /// Can fail with [PacketReadError::InsufficientData]
     fn get_reason_code(&mut self) -> Result<ReasonCode> {
-        let value = self.get_u8()?;
-        match value {
-            0 => Ok(ReasonCode::Success),
-            1 => Ok(ReasonCode::GrantedQos1),
-            2 => Ok(ReasonCode::GrantedQos2),
-            4 => Ok(ReasonCode::DisconnectWithWillMessage),
-            16 => Ok(ReasonCode::NoMatchingSubscribers),
-            17 => Ok(ReasonCode::NoSubscriptionExisted),
-            24 => Ok(ReasonCode::ContinueAuthentication),
-            25 => Ok(ReasonCode::ReAuthenticate),
-            128 => Ok(ReasonCode::UnspecifiedError),
-            129 => Ok(ReasonCode::MalformedPacket),
-            130 => Ok(ReasonCode::ProtocolError),
-            131 => Ok(ReasonCode::ImplementationSpecificError),
-            132 => Ok(ReasonCode::UnsupportedProtocolVersion),
-            133 => Ok(ReasonCode::ClientIdentifierNotValid),
-            134 => Ok(ReasonCode::BadUserNameOrPassword),
-            135 => Ok(ReasonCode::NotAuthorized),
-            136 => Ok(ReasonCode::ServerUnavailable),
-            137 => Ok(ReasonCode::ServerBusy),
-            138 => Ok(ReasonCode::Banned),
-            139 => Ok(ReasonCode::ServerShuttingDown),
-            140 => Ok(ReasonCode::BadAuthenticationMethod),
-            141 => Ok(ReasonCode::KeepAliveTimeout),
-            142 => Ok(ReasonCode::SessionTakenOver),
-            143 => Ok(ReasonCode::TopicFilterInvalid),
-            144 => Ok(ReasonCode::TopicNameInvalid),
-            145 => Ok(ReasonCode::PacketIdentifierInUse),
-            146 => Ok(ReasonCode::PacketIdentifierNotFound),
-            147 => Ok(ReasonCode::ReceiveMaximumExceeded),
-            148 => Ok(ReasonCode::TopicAliasInvalid),
-            149 => Ok(ReasonCode::PacketTooLarge),
-            150 => Ok(ReasonCode::MessageRateTooHigh),
-            151 => Ok(ReasonCode::QuotaExceeded),
-            152 => Ok(ReasonCode::AdministrativeAction),
-            153 => Ok(ReasonCode::PayloadFormatInvalid),
-            154 => Ok(ReasonCode::RetainNotSupported),
-            155 => Ok(ReasonCode::QosNotSupported),
-            156 => Ok(ReasonCode::UseAnotherServer),
-            157 => Ok(ReasonCode::ServerMoved),
-            158 => Ok(ReasonCode::SharedSubscriptionsNotSupported),
-            159 => Ok(ReasonCode::ConnectionRateExceeded),
-            160 => Ok(ReasonCode::MaximumConnectTime),
-            161 => Ok(ReasonCode::SubscriptionIdentifiersNotSupported),
-            162 => Ok(ReasonCode::WildcardSubscriptionsNotSupported),
-            _ => Err(PacketReadError::UnknownReasonCode),
-        }
+        Ok(self.get_u8()?.into())
     }
 
-    fn get_property_list<T: Read<'a>, const N: usize>(
+    /// Get an encoded property list, prefixed with its length as a
+    /// variable u32, as a [PropertiesIter] to decode the properties lazily.
+    /// The encoded list is read immediately, so this advances the position
+    /// to the end of the list, but properties are not decoded until iterated.
+    fn get_properties_iter<T>(&mut self) -> Result<PropertiesIter<'a, T>> {
+        let properties_len = self.get_variable_u32()? as usize;
+        let encoded = self.get_slice(properties_len)?;
+        Ok(PropertiesIter::new(encoded).with_skip_unknown(self.skip_unknown_properties()))
+    }
+
+    /// Get an encoded property list, prefixed with its length as a
+    /// variable u32, pushing properties to `vec`.
+    /// See [PropertiesIter::collect_into_with_overflow] for handling of capacity,
+    /// using [MqttReader::property_overflow].
+    fn get_property_list<T: Read<'a> + PacketProperty, const N: usize>(
         &mut self,
         vec: &mut Vec<T, N>,
     ) -> Result<()> {
-        let properties_len = self.get_variable_u32()? as usize;
-        let properties_end = self.position() + properties_len;
-
-        while self.position() < properties_end {
-            let item = T::read(self)?;
-            vec.push(item)
-                .map_err(|_e| PacketReadError::TooManyProperties)?;
+        let overflow = self.property_overflow();
+        let dropped = self
+            .get_properties_iter()?
+            .collect_into_with_overflow(vec, overflow)?;
+        if overflow == PropertyOverflow::Count && dropped > 0 {
+            self.properties_dropped(dropped);
         }
-
         Ok(())
     }
 
@@ -260,19 +261,83 @@ pub trait MqttReader<'a>: Sized {
     }
 }
 
+#[derive(Clone)]
 pub struct MqttBufReader<'a> {
     buf: &'a [u8],
     position: usize,
+    skip_unknown_properties: bool,
+    property_overflow: PropertyOverflow,
+    dropped_properties: usize,
 }
 
 impl<'a> MqttBufReader<'a> {
     pub fn new(buf: &'a [u8]) -> Self {
-        Self { buf, position: 0 }
+        Self {
+            buf,
+            position: 0,
+            skip_unknown_properties: false,
+            property_overflow: PropertyOverflow::Fail,
+            dropped_properties: 0,
+        }
+    }
+
+    /// Set whether property lists skip properties that are not expected for
+    /// the packet type, see [MqttReader::skip_unknown_properties]
+    pub fn with_skip_unknown_properties(mut self, skip_unknown_properties: bool) -> Self {
+        self.skip_unknown_properties = skip_unknown_properties;
+        self
+    }
+
+    /// Set how property lists with more properties than the capacity of the
+    /// packet are handled, see [MqttReader::property_overflow]
+    pub fn with_property_overflow(mut self, property_overflow: PropertyOverflow) -> Self {
+        self.property_overflow = property_overflow;
+        self
+    }
+
+    /// Get a packet (or other data) as for [MqttReader::get], but on failure
+    /// produce a [PacketReadErrorContext], with the position where decoding
+    /// stopped and the type of the packet, found from the first byte of data
+    pub fn get_with_context<T: Read<'a>>(
+        &mut self,
+    ) -> core::result::Result<T, PacketReadErrorContext> {
+        let start = self.position;
+        self.get().map_err(|error| PacketReadErrorContext {
+            error,
+            packet_type: self
+                .buf
+                .get(start)
+                .and_then(|first| PacketType::try_from(*first).ok()),
+            position: self.position - start,
+        })
+    }
+
+    /// The total number of properties dropped from property lists read so far,
+    /// when using [PropertyOverflow::Count]
+    pub fn dropped_properties(&self) -> usize {
+        self.dropped_properties
     }
 
     pub fn remaining(&self) -> usize {
         self.buf.len() - self.position
     }
+
+    /// Record the current position, so that the reader can be reset to it
+    /// with [MqttBufReader::reset]. This allows looking ahead in the data,
+    /// e.g. to find a property before deciding how to decode a packet,
+    /// without consuming it. Alternatively, the reader can be cloned to
+    /// look ahead with the clone.
+    pub fn mark(&self) -> Mark {
+        Mark {
+            position: self.position,
+        }
+    }
+
+    /// Reset to a [Mark] produced by [MqttBufReader::mark] on this reader,
+    /// so that data after the mark will be read again
+    pub fn reset(&mut self, mark: Mark) {
+        self.position = mark.position.min(self.buf.len());
+    }
 }
 
 impl<'a> MqttReader<'a> for MqttBufReader<'a> {
@@ -280,6 +345,18 @@ impl<'a> MqttReader<'a> for MqttBufReader<'a> {
         self.position
     }
 
+    fn skip_unknown_properties(&self) -> bool {
+        self.skip_unknown_properties
+    }
+
+    fn property_overflow(&self) -> PropertyOverflow {
+        self.property_overflow
+    }
+
+    fn properties_dropped(&mut self, count: usize) {
+        self.dropped_properties += count;
+    }
+
     fn get_slice(&mut self, len: usize) -> Result<&'a [u8]> {
         let end = self.position + len;
         if end > self.buf.len() {
@@ -370,7 +447,7 @@ mod tests {
 
         // Can still get an empty slice
         let slice_empty = r.get_slice(0)?;
-        assert_eq!(slice_empty, &[]);
+        assert_eq!(slice_empty, &[0u8; 0]);
 
         Ok(())
     }
@@ -677,7 +754,7 @@ mod tests {
         ];
 
         for code in codes.iter() {
-            let buf = [*code as u8];
+            let buf = [code.value()];
             let mut r = MqttBufReader::new(&buf);
             let read_code = r.get_reason_code()?;
             assert_eq!(read_code, *code);
@@ -687,4 +764,74 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn mqtt_buf_reader_handles_property_overflow() {
+        use crate::data::property::ConnackProperty;
+
+        // SessionExpiryInterval, ReceiveMaximum and MaximumQos
+        let buf = [10, 0x11, 0, 0, 0, 60, 0x21, 0, 20, 0x24, 1];
+
+        let mut properties: Vec<ConnackProperty<'_>, 1> = Vec::new();
+        let mut r = MqttBufReader::new(&buf);
+        assert_eq!(
+            r.get_property_list(&mut properties),
+            Err(PacketReadError::TooManyProperties)
+        );
+
+        let mut properties: Vec<ConnackProperty<'_>, 1> = Vec::new();
+        let mut r = MqttBufReader::new(&buf).with_property_overflow(PropertyOverflow::Drop);
+        assert_eq!(r.get_property_list(&mut properties), Ok(()));
+        assert_eq!(properties.len(), 1);
+        assert_eq!(r.dropped_properties(), 0);
+        assert_eq!(r.remaining(), 0);
+
+        let mut properties: Vec<ConnackProperty<'_>, 1> = Vec::new();
+        let mut r = MqttBufReader::new(&buf).with_property_overflow(PropertyOverflow::Count);
+        assert_eq!(r.get_property_list(&mut properties), Ok(()));
+        assert_eq!(
+            properties[0],
+            ConnackProperty::SessionExpiryInterval(60.into())
+        );
+        assert_eq!(r.dropped_properties(), 2);
+    }
+
+    #[cfg(all(feature = "client-codec", feature = "server-codec"))]
+    #[test]
+    fn mqtt_buf_reader_gives_error_context() {
+        use crate::packets::packet_generic::PacketGeneric;
+
+        // Connack with properties length of 3, but only 2 bytes of properties
+        let buf = [0x20, 0x05, 0x00, 0x00, 0x03, 0x21, 0x00];
+        let mut r = MqttBufReader::new(&buf);
+        let packet: core::result::Result<PacketGeneric<'_, 1, 0, 0>, _> = r.get_with_context();
+        let context = packet.unwrap_err();
+        assert_eq!(
+            context,
+            PacketReadErrorContext {
+                error: PacketReadError::InsufficientData,
+                packet_type: Some(PacketType::Connack),
+                position: 5,
+            }
+        );
+    }
+
+    #[test]
+    fn mqtt_buf_reader_can_reset_to_mark() -> Result<()> {
+        let buf = [0, 1, 0, 2];
+        let mut r = MqttBufReader::new(&buf);
+
+        assert_eq!(r.get_u8()?, 0);
+        let mark = r.mark();
+        assert_eq!(r.get_u16()?, 256);
+        assert_eq!(r.get_u16(), Err(PacketReadError::InsufficientData));
+
+        r.reset(mark);
+        assert_eq!(r.position(), 1);
+        assert_eq!(r.get_u8()?, 1);
+        assert_eq!(r.get_u16()?, 2);
+        assert_eq!(r.remaining(), 0);
+
+        Ok(())
+    }
 }
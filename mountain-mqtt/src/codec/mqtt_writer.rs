@@ -2,23 +2,35 @@ use heapless::Vec;
 
 use crate::{
     data::{
-        reason_code::ReasonCode, string_pair::StringPair,
+        property::PacketProperty, reason_code::ReasonCode, string_pair::StringPair,
         subscription_options::SubscriptionOptions, DATA_MAX_LEN, VARIABLE_BYTE_INTEGER_MAX_VALUE,
     },
-    error::PacketWriteError,
-    packets::subscribe::SubscriptionRequest,
+    error::{PacketWriteError, PacketWriteErrorContext},
+    packets::{
+        packet::{Packet, PacketWrite},
+        size::variable_byte_integer_len,
+        subscribe::SubscriptionRequest,
+    },
 };
 
 use super::write::Write;
 
 pub type Result<A> = core::result::Result<A, PacketWriteError>;
 
+/// A position of a writer, that the writer can be rolled back to, discarding
+/// any data written after it, see [MqttBufWriter::checkpoint]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    position: usize,
+}
+
 /// A writer putting data to an underlying buffer in MQTT v5 encodings.
 /// Each `put` method puts encoded data to the buffer, advancing its position.
 /// If a method fails (returns an [Err]), then partial data may have been
 /// written before the error was detected. Since an error always indicates
 /// invalid data, there's no reason to allow recovering, and the writer/data
-/// should not be used further.
+/// should not be used further - unless the writer was rolled back to a
+/// [Checkpoint] from before the failed method, see [MqttBufWriter::checkpoint].
 pub trait MqttWriter<'a>: Sized {
     /// Put the whole of a slice as raw data
     /// This generally should not be used directly - it is
@@ -41,7 +53,7 @@ pub trait MqttWriter<'a>: Sized {
     /// Advances the position by 1
     /// Can fail with [PacketWriteError::Overflow]
     fn put_reason_code(&mut self, c: &ReasonCode) -> Result<()> {
-        self.put_u8(*c as u8)
+        self.put_u8(c.value())
     }
 
     /// Put the next byte of data as a u8
@@ -152,22 +164,26 @@ pub trait MqttWriter<'a>: Sized {
 
     /// Put a list of [Write]able objects, prefixed with their total
     /// encoded length as a variable u32 value
-    fn put_variable_u32_delimited_vec<T: Write, const N: usize>(
+    fn put_variable_u32_delimited_vec<T: Write + PacketProperty, const N: usize>(
         &mut self,
         vec: &Vec<T, N>,
     ) -> Result<()> {
-        let mut lw = MqttLenWriter::new();
-        for p in vec.iter() {
-            p.write(&mut lw)?;
+        #[cfg(feature = "validate-properties")]
+        if crate::data::property::has_duplicate_property(vec) {
+            return Err(PacketWriteError::DuplicateProperty);
         }
-        let properties_len = lw.position();
 
-        self.put_variable_u32(properties_len as u32)?;
-        for p in vec.iter() {
-            p.write(self)?;
-        }
+        put_variable_u32_delimited_vec_two_pass(self, vec)
+    }
 
-        Ok(())
+    /// Put the variable header and payload of a packet, prefixed with their
+    /// total encoded length as a variable u32 value (the remaining length
+    /// from the fixed header).
+    /// By default this finds the length by writing the packet to an
+    /// [MqttLenWriter], then writes the packet again. Writers with access
+    /// to the written data can override this to write in a single pass.
+    fn put_variable_u32_delimited_packet<P: PacketWrite>(&mut self, packet: &P) -> Result<()> {
+        put_variable_u32_delimited_packet_two_pass(self, packet)
     }
 
     // Put subscription options, encoded as a u8
@@ -186,14 +202,120 @@ pub trait MqttWriter<'a>: Sized {
     }
 }
 
+fn put_variable_u32_delimited_vec_two_pass<'a, W, T, const N: usize>(
+    writer: &mut W,
+    vec: &Vec<T, N>,
+) -> Result<()>
+where
+    W: MqttWriter<'a>,
+    T: Write,
+{
+    let mut lw = MqttLenWriter::new();
+    for p in vec.iter() {
+        p.write(&mut lw)?;
+    }
+    let properties_len = lw.position();
+
+    writer.put_variable_u32(properties_len as u32)?;
+    for p in vec.iter() {
+        p.write(writer)?;
+    }
+
+    Ok(())
+}
+
+fn put_variable_u32_delimited_packet_two_pass<'a, W, P>(writer: &mut W, packet: &P) -> Result<()>
+where
+    W: MqttWriter<'a>,
+    P: PacketWrite,
+{
+    let mut lw = MqttLenWriter::new();
+    packet.put_variable_header_and_payload(&mut lw)?;
+    let remaining_length = lw.position();
+
+    writer.put_variable_u32(remaining_length as u32)?;
+    packet.put_variable_header_and_payload(writer)
+}
+
 pub struct MqttBufWriter<'a> {
     buf: &'a mut [u8],
     position: usize,
+    backpatch: bool,
 }
 
 impl<'a> MqttBufWriter<'a> {
+    /// Create a writer for a buffer, with backpatching enabled,
+    /// see [MqttBufWriter::with_backpatching]
     pub fn new(buf: &'a mut [u8]) -> Self {
-        Self { buf, position: 0 }
+        Self {
+            buf,
+            position: 0,
+            backpatch: true,
+        }
+    }
+
+    /// Set whether length-delimited data (packets and property lists) is written
+    /// in a single pass, by reserving space for the longest length encoding that
+    /// could be needed, writing the data, then writing the length and shifting
+    /// the data back over any unused space (at most 3 bytes).
+    /// If disabled, the length is found by first writing data to an [MqttLenWriter],
+    /// then the data is written again, doubling the work of encoding.
+    /// Enabled by default. The encoded data is the same either way.
+    pub fn with_backpatching(mut self, backpatch: bool) -> Self {
+        self.backpatch = backpatch;
+        self
+    }
+
+    /// Put data using `put`, prefixed with its length as a variable u32,
+    /// in a single pass, see [MqttBufWriter::with_backpatching].
+    /// Space is reserved for the largest length that could fit in the remaining
+    /// buffer, so for a buffer not much larger than the data, no shift is needed.
+    /// Returns `Ok(false)` with nothing written if backpatching is disabled,
+    /// or the data plus reserved space would overflow the buffer - in this
+    /// case the caller should write the data in two passes instead, since
+    /// it may still fit if the length needs fewer than the reserved bytes.
+    fn put_backpatched<F>(&mut self, put: F) -> Result<bool>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        if !self.backpatch {
+            return Ok(false);
+        }
+
+        // The data must be shorter than the remaining buffer, so we only need to reserve
+        // enough space to encode that length. Where the buffer is not much larger than the
+        // data, this means that we don't need to shift the data at all.
+        let start = self.position;
+        let reserved = variable_byte_integer_len(self.remaining());
+        let data_start = start + reserved;
+        if data_start > self.buf.len() {
+            return Ok(false);
+        }
+
+        self.position = data_start;
+        match put(self) {
+            Ok(()) => {}
+            Err(PacketWriteError::Overflow) => {
+                self.position = start;
+                return Ok(false);
+            }
+            Err(e) => return Err(e),
+        }
+        let data_end = self.position;
+        let data_len = data_end - data_start;
+
+        let len_len = {
+            let mut len_writer = MqttBufWriter::new(&mut self.buf[start..data_start]);
+            len_writer.put_variable_u32(data_len as u32)?;
+            len_writer.position()
+        };
+
+        if len_len < reserved {
+            self.buf.copy_within(data_start..data_end, start + len_len);
+        }
+        self.position = start + len_len + data_len;
+
+        Ok(true)
     }
 
     pub fn remaining(&self) -> usize {
@@ -203,6 +325,39 @@ impl<'a> MqttBufWriter<'a> {
     pub fn position(&self) -> usize {
         self.position
     }
+
+    /// Record the current position, so that the writer can be rolled back to it
+    /// with [MqttBufWriter::rollback]. This allows attempting to put an optional
+    /// section of data (e.g. properties that may not fit), and cleanly reverting
+    /// if this fails, after which the writer can be used as normal.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            position: self.position,
+        }
+    }
+
+    /// Roll back to a [Checkpoint] produced by [MqttBufWriter::checkpoint] on this
+    /// writer, discarding any data written since. The checkpoint must not be after
+    /// the current position, i.e. the writer can't be rolled forward.
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        debug_assert!(checkpoint.position <= self.position);
+        self.position = checkpoint.position.min(self.position);
+    }
+
+    /// Put a packet as for [MqttWriter::put], but on failure produce a
+    /// [PacketWriteErrorContext], with the position where encoding stopped
+    /// and the type of the packet
+    pub fn put_with_context<P: Packet + Write>(
+        &mut self,
+        packet: &P,
+    ) -> core::result::Result<(), PacketWriteErrorContext> {
+        let start = self.position;
+        self.put(packet).map_err(|error| PacketWriteErrorContext {
+            error,
+            packet_type: packet.packet_type(),
+            position: self.position - start,
+        })
+    }
 }
 
 impl<'a> MqttWriter<'a> for MqttBufWriter<'a> {
@@ -216,6 +371,37 @@ impl<'a> MqttWriter<'a> for MqttBufWriter<'a> {
             Ok(())
         }
     }
+
+    fn put_variable_u32_delimited_vec<T: Write + PacketProperty, const N: usize>(
+        &mut self,
+        vec: &Vec<T, N>,
+    ) -> Result<()> {
+        #[cfg(feature = "validate-properties")]
+        if crate::data::property::has_duplicate_property(vec) {
+            return Err(PacketWriteError::DuplicateProperty);
+        }
+
+        let written = self.put_backpatched(|w| {
+            for p in vec.iter() {
+                p.write(w)?;
+            }
+            Ok(())
+        })?;
+
+        if written {
+            Ok(())
+        } else {
+            put_variable_u32_delimited_vec_two_pass(self, vec)
+        }
+    }
+
+    fn put_variable_u32_delimited_packet<P: PacketWrite>(&mut self, packet: &P) -> Result<()> {
+        if self.put_backpatched(|w| packet.put_variable_header_and_payload(w))? {
+            Ok(())
+        } else {
+            put_variable_u32_delimited_packet_two_pass(self, packet)
+        }
+    }
 }
 
 /// An [MqttWriter] that discards data and just adjusts position,
@@ -239,6 +425,19 @@ impl MqttLenWriter {
     pub fn position(&self) -> usize {
         self.position
     }
+
+    /// Record the current position, see [MqttBufWriter::checkpoint]
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            position: self.position,
+        }
+    }
+
+    /// Roll back to a [Checkpoint], see [MqttBufWriter::rollback]
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        debug_assert!(checkpoint.position <= self.position);
+        self.position = checkpoint.position.min(self.position);
+    }
 }
 impl Default for MqttLenWriter {
     fn default() -> Self {
@@ -310,6 +509,33 @@ mod tests {
         }
     }
 
+    #[test]
+    fn mqtt_writers_can_roll_back_to_checkpoint() -> Result<()> {
+        let mut buf = [0u8; 4];
+        let mut r = MqttBufWriter::new(&mut buf);
+        let mut rl = MqttLenWriter::default();
+
+        r.put_u8(1)?;
+        rl.put_u8(1)?;
+        let checkpoint = r.checkpoint();
+        let len_checkpoint = rl.checkpoint();
+
+        // Optional section doesn't fit, roll back and continue
+        r.put_u16(2)?;
+        rl.put_u16(2)?;
+        assert_eq!(r.put_u16(3), Err(PacketWriteError::Overflow));
+        r.rollback(checkpoint);
+        rl.rollback(len_checkpoint);
+
+        r.put_u16(4)?;
+        rl.put_u16(4)?;
+        assert_eq!(3, r.position());
+        assert_eq!(3, rl.position());
+        r.assert_contents(&[1, 0, 4]);
+
+        Ok(())
+    }
+
     #[test]
     fn mqtt_writers_can_put_slices() -> Result<()> {
         let mut buf = [0u8; 4];
@@ -845,7 +1071,58 @@ mod tests {
                 assert_eq!(1, r.position());
                 assert_eq!(0, r.remaining());
             }
-            assert_eq!(buf[0], *code as u8);
+            assert_eq!(buf[0], code.value());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn backpatched_packets_match_two_pass_packets() -> Result<()> {
+        use crate::{
+            data::{
+                packet_identifier::PublishPacketIdentifier,
+                property::{PayloadFormatIndicator, PublishProperty},
+            },
+            packets::publish::Publish,
+        };
+
+        // Lengths needing 1, 2 and 3 byte remaining length encodings
+        let payload = [0xAAu8; 20_000];
+        for payload_len in [0, 10, 200, 20_000] {
+            let mut properties = Vec::<PublishProperty, 1>::new();
+            properties
+                .push(PublishProperty::PayloadFormatIndicator(
+                    PayloadFormatIndicator::new(0),
+                ))
+                .unwrap();
+            let packet = Publish::new(
+                false,
+                false,
+                "a/topic",
+                PublishPacketIdentifier::None,
+                &payload[0..payload_len],
+                properties,
+            );
+
+            let mut two_pass_buf = [0u8; 20_100];
+            let mut two_pass = MqttBufWriter::new(&mut two_pass_buf).with_backpatching(false);
+            two_pass.put(&packet)?;
+            let len = two_pass.position();
+
+            let mut backpatched_buf = [0u8; 20_100];
+            let mut backpatched = MqttBufWriter::new(&mut backpatched_buf);
+            backpatched.put(&packet)?;
+            assert_eq!(len, backpatched.position());
+            assert_eq!(&two_pass_buf[0..len], &backpatched_buf[0..len]);
+
+            // A buffer exactly fitting the packet has no room for the reserved
+            // length bytes, so this falls back to two passes
+            let mut exact_buf = [0u8; 20_100];
+            let mut exact = MqttBufWriter::new(&mut exact_buf[0..len]);
+            exact.put(&packet)?;
+            assert_eq!(0, exact.remaining());
+            assert_eq!(&two_pass_buf[0..len], &exact_buf[0..len]);
         }
 
         Ok(())
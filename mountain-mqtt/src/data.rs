@@ -1,10 +1,16 @@
 pub const VARIABLE_BYTE_INTEGER_MAX_VALUE: u32 = 268_435_455;
+/// The maximum number of bytes used to encode a variable byte integer
+pub const VARIABLE_BYTE_INTEGER_MAX_LEN: usize = 4;
 pub const DATA_MAX_LEN: usize = 65_535;
 
+pub mod encoded_topic;
 pub mod packet_identifier;
 pub mod packet_type;
 pub mod property;
 pub mod quality_of_service;
 pub mod reason_code;
+pub mod recent_publishes;
 pub mod string_pair;
 pub mod subscription_options;
+pub mod topic_buf;
+pub mod topic_filter;
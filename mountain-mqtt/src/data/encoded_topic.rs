@@ -0,0 +1,81 @@
+use super::topic_buf::{validate_topic_text, TopicBufError};
+
+/// A topic name validated and encoded once, as a two byte length followed by
+/// the UTF-8 bytes, so that it can be used for many publishes without being
+/// checked and encoded again, see [Publish::with_encoded_topic](crate::packets::publish::Publish::with_encoded_topic).
+/// `N` is the capacity for the encoded topic name, so must be at least 2 more
+/// than the length of the topic name.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EncodedTopic<const N: usize> {
+    encoded: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> EncodedTopic<N> {
+    /// Validate and encode a topic name. It must not be empty [MQTT-4.7.3-1],
+    /// or contain wildcard characters [MQTT-3.3.2-2] or null characters [MQTT-4.7.3-2].
+    pub fn new(topic_name: &str) -> Result<Self, TopicBufError> {
+        if topic_name.is_empty() {
+            return Err(TopicBufError::EmptySegment);
+        }
+        validate_topic_text(topic_name)?;
+
+        let len = 2 + topic_name.len();
+        if len > N || topic_name.len() > u16::MAX as usize {
+            return Err(TopicBufError::Overflow);
+        }
+
+        let mut encoded = [0; N];
+        encoded[0..2].copy_from_slice(&(topic_name.len() as u16).to_be_bytes());
+        encoded[2..len].copy_from_slice(topic_name.as_bytes());
+        Ok(Self { encoded, len })
+    }
+
+    pub fn topic_name(&self) -> &str {
+        // The bytes after the length were copied from a str
+        core::str::from_utf8(&self.encoded[2..self.len]).unwrap_or("")
+    }
+
+    /// The encoded topic name, including the two byte length
+    pub fn encoded(&self) -> &[u8] {
+        &self.encoded[0..self.len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::mqtt_writer::{MqttBufWriter, MqttWriter};
+
+    #[test]
+    fn topic_is_encoded_as_str() {
+        let topic = EncodedTopic::<16>::new("sensor/temp").unwrap();
+        assert_eq!(topic.topic_name(), "sensor/temp");
+
+        let mut buf = [0; 16];
+        let mut writer = MqttBufWriter::new(&mut buf);
+        writer.put_str("sensor/temp").unwrap();
+        let len = writer.position();
+        assert_eq!(topic.encoded(), &buf[0..len]);
+    }
+
+    #[test]
+    fn invalid_topics_are_rejected() {
+        assert_eq!(
+            EncodedTopic::<16>::new(""),
+            Err(TopicBufError::EmptySegment)
+        );
+        assert_eq!(
+            EncodedTopic::<16>::new("sensor/+"),
+            Err(TopicBufError::Wildcard)
+        );
+        assert_eq!(
+            EncodedTopic::<16>::new("sensor\0"),
+            Err(TopicBufError::NullCharacter)
+        );
+        assert_eq!(
+            EncodedTopic::<12>::new("sensor/temp"),
+            Err(TopicBufError::Overflow)
+        );
+    }
+}
@@ -1,8 +1,11 @@
+use heapless::Vec;
+
 use crate::data::quality_of_service::QualityOfService;
 
 /// A Packet Identifier as defined in section 2.2.1 of the MQTT v5.0 spec.
 /// Note that annoyingly this can't be 0
 #[derive(Debug, PartialEq, Copy, Clone, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct PacketIdentifier(pub u16);
 
 impl PacketIdentifier {
@@ -22,7 +25,7 @@ impl Default for PacketIdentifier {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum PublishPacketIdentifier {
     None,
     Qos1(PacketIdentifier),
@@ -38,3 +41,63 @@ impl PublishPacketIdentifier {
         }
     }
 }
+
+/// The most recent `N` packet identifiers recorded, e.g. to detect
+/// redelivery of a publish that has already been processed.
+/// Recording an identifier that is already present makes it the most recent,
+/// and when full, recording a new identifier forgets the oldest.
+#[derive(Debug, Clone, Default)]
+pub struct RecentPacketIdentifiers<const N: usize> {
+    /// Identifiers from oldest to most recent
+    ids: Vec<PacketIdentifier, N>,
+}
+
+impl<const N: usize> RecentPacketIdentifiers<N> {
+    pub fn new() -> Self {
+        Self { ids: Vec::new() }
+    }
+
+    pub fn contains(&self, id: PacketIdentifier) -> bool {
+        self.ids.contains(&id)
+    }
+
+    pub fn record(&mut self, id: PacketIdentifier) {
+        if let Some(index) = self.ids.iter().position(|recent| *recent == id) {
+            self.ids.remove(index);
+        } else if self.ids.is_full() && !self.ids.is_empty() {
+            self.ids.remove(0);
+        }
+        let _ = self.ids.push(id);
+    }
+
+    pub fn clear(&mut self) {
+        self.ids.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_packet_identifiers_forget_oldest() {
+        let mut recent = RecentPacketIdentifiers::<2>::new();
+        recent.record(PacketIdentifier(1));
+        recent.record(PacketIdentifier(2));
+        assert!(recent.contains(PacketIdentifier(1)));
+
+        // Recording 1 again makes 2 the oldest
+        recent.record(PacketIdentifier(1));
+        recent.record(PacketIdentifier(3));
+        assert!(recent.contains(PacketIdentifier(1)));
+        assert!(!recent.contains(PacketIdentifier(2)));
+        assert!(recent.contains(PacketIdentifier(3)));
+
+        recent.clear();
+        assert!(!recent.contains(PacketIdentifier(1)));
+
+        let mut none = RecentPacketIdentifiers::<0>::new();
+        none.record(PacketIdentifier(1));
+        assert!(!none.contains(PacketIdentifier(1)));
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{error::PacketReadError, packets::publish::is_valid_publish_first_header_byte};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 #[repr(u8)]
 pub enum PacketType {
     /// Connection request
@@ -56,12 +56,27 @@ impl PacketType {
         if let Ok(packet_type) = PacketType::try_from(encoded) {
             match packet_type {
                 PacketType::Publish => is_valid_publish_first_header_byte(encoded),
-                _ => encoded == packet_type.into(),
+                _ => encoded == u8::from(packet_type),
             }
         } else {
             false
         }
     }
+
+    /// Parse the [PacketType] from the first header byte of a packet, checking
+    /// that the flags in the lower 4 bits have the fixed values the specification
+    /// requires for the packet type [MQTT-2.1.3-1], failing with
+    /// [PacketReadError::InvalidPacketType] if not (Malformed Packet).
+    /// The flags of a [PacketType::Publish] are not fixed, and are checked when
+    /// decoding the packet.
+    pub fn from_first_header_byte(encoded: u8) -> Result<Self, PacketReadError> {
+        let packet_type = PacketType::try_from(encoded)?;
+        if packet_type == PacketType::Publish || encoded == u8::from(packet_type) {
+            Ok(packet_type)
+        } else {
+            Err(PacketReadError::InvalidPacketType)
+        }
+    }
 }
 
 /// Note this only provides the "base" representation, with no
@@ -1,18 +1,219 @@
 use crate::data::string_pair::StringPair;
 
 use crate::codec::{
-    mqtt_reader::{self, MqttReader},
+    mqtt_reader::{self, MqttBufReader, MqttReader},
     mqtt_writer::{self, MqttWriter},
     read::Read,
     write::Write,
 };
 
+use crate::error::PacketReadError;
 use core::marker::PhantomData;
+use heapless::Vec;
 pub trait Property<'a, T> {
     const IDENTIFIER: u32;
     fn value(&self) -> T;
 }
 
+/// Implemented by packet property enums (e.g. [ConnackProperty]) for each
+/// property type they can contain, allowing typed lookup with [PropertiesExt]
+pub trait AsProperty<T> {
+    /// The property as type `T`, or None if it is a different property
+    fn as_property(&self) -> Option<&T>;
+}
+
+/// Implemented by packet property enums (e.g. [ConnackProperty]), giving
+/// the identifier of the contained property
+pub trait PacketProperty {
+    /// The identifier of the contained property
+    fn identifier(&self) -> u32;
+
+    /// True if the contained property may be included more than once in a packet.
+    /// This is the case for [UserProperty] in any packet, and for
+    /// [SubscriptionIdentifier] only in a Publish packet, see [PublishProperty].
+    fn may_repeat(&self) -> bool {
+        self.identifier() == UserProperty::IDENTIFIER
+    }
+}
+
+/// True if `properties` contains more than one instance of a property that
+/// may only be included once in a packet
+pub fn has_duplicate_property<T: PacketProperty>(properties: &[T]) -> bool {
+    properties.iter().enumerate().any(|(i, p)| {
+        !p.may_repeat()
+            && properties[i + 1..]
+                .iter()
+                .any(|other| other.identifier() == p.identifier())
+    })
+}
+
+/// Typed lookup in a list of packet properties, e.g.
+/// `connack.properties().get_property::<ServerKeepAlive>()`
+pub trait PropertiesExt<E> {
+    /// The first property of type `T`, if present
+    fn get_property<T>(&self) -> Option<&T>
+    where
+        E: AsProperty<T>;
+
+    /// All properties of type `T`, in order, e.g. for [UserProperty]
+    fn get_properties<'s, T: 's>(&'s self) -> impl Iterator<Item = &'s T>
+    where
+        E: AsProperty<T>;
+}
+
+impl<E> PropertiesExt<E> for [E] {
+    fn get_property<T>(&self) -> Option<&T>
+    where
+        E: AsProperty<T>,
+    {
+        self.iter().find_map(|p| p.as_property())
+    }
+
+    fn get_properties<'s, T: 's>(&'s self) -> impl Iterator<Item = &'s T>
+    where
+        E: AsProperty<T>,
+    {
+        self.iter().filter_map(|p| p.as_property())
+    }
+}
+
+/// How a property list with more properties than the capacity of a packet is
+/// handled when it is decoded
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PropertyOverflow {
+    /// Fail to decode the packet with [PacketReadError::TooManyProperties]
+    #[default]
+    Fail,
+
+    /// Keep the properties that fit, and silently drop the rest
+    Drop,
+
+    /// Keep the properties that fit, and drop the rest, reporting the number of
+    /// properties dropped, see [MqttReader::properties_dropped]
+    Count,
+}
+
+/// Lazily decodes the properties in an encoded property list (without the
+/// leading length), one at a time. This doesn't need any capacity to store
+/// properties, so it can be used to inspect properties of a packet that was
+/// decoded without storing them (e.g. with a property capacity of 0),
+/// collecting only the properties of interest.
+/// Yields an error if a property can't be decoded, and then finishes.
+#[derive(Debug, Clone)]
+pub struct PropertiesIter<'a, T> {
+    encoded: &'a [u8],
+    remaining: &'a [u8],
+    failed: bool,
+    skip_unknown: bool,
+    _phantom: PhantomData<T>,
+}
+
+impl<'a, T> PropertiesIter<'a, T> {
+    pub fn new(encoded: &'a [u8]) -> Self {
+        Self {
+            encoded,
+            remaining: encoded,
+            failed: false,
+            skip_unknown: false,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Set whether properties that are not expected for the packet type are
+    /// skipped, rather than producing [PacketReadError::UnexpectedPropertyIdentifier].
+    /// This allows decoding packets from servers that send properties we don't
+    /// model for a packet type. Properties defined by MQTT v5 are skipped using
+    /// their encoded length. An identifier not defined by MQTT v5 has no known
+    /// length, so the properties after it can't be decoded, and this produces
+    /// [PacketReadError::MalformedPacket].
+    pub fn with_skip_unknown(mut self, skip_unknown: bool) -> Self {
+        self.skip_unknown = skip_unknown;
+        self
+    }
+
+    /// The encoded property list
+    pub fn encoded(&self) -> &'a [u8] {
+        self.encoded
+    }
+}
+
+impl<'a, T: Read<'a> + PacketProperty> PropertiesIter<'a, T> {
+    /// Decode all remaining properties, pushing them to `vec`.
+    /// If `N` is 0, properties are still decoded (so will produce any decoding
+    /// error) but are skipped rather than stored. Otherwise this fails with
+    /// [PacketReadError::TooManyProperties] if `vec` does not have capacity
+    /// for all properties.
+    pub fn collect_into<const N: usize>(self, vec: &mut Vec<T, N>) -> mqtt_reader::Result<()> {
+        self.collect_into_with_overflow(vec, PropertyOverflow::Fail)
+            .map(|_dropped| ())
+    }
+
+    /// Decode all remaining properties, pushing them to `vec`, as for
+    /// [PropertiesIter::collect_into], but handling properties that don't fit
+    /// in `vec` according to `overflow`. Returns the number of properties dropped.
+    /// All properties are still decoded, so will produce any decoding error.
+    pub fn collect_into_with_overflow<const N: usize>(
+        self,
+        vec: &mut Vec<T, N>,
+        overflow: PropertyOverflow,
+    ) -> mqtt_reader::Result<usize> {
+        let mut dropped = 0;
+        for property in self {
+            let property = property?;
+            if N > 0 && vec.push(property).is_err() {
+                match overflow {
+                    PropertyOverflow::Fail => return Err(PacketReadError::TooManyProperties),
+                    PropertyOverflow::Drop | PropertyOverflow::Count => dropped += 1,
+                }
+            }
+        }
+
+        #[cfg(feature = "validate-properties")]
+        if has_duplicate_property(vec) {
+            return Err(PacketReadError::DuplicateProperty);
+        }
+
+        Ok(dropped)
+    }
+}
+
+impl<'a, T: Read<'a>> Iterator for PropertiesIter<'a, T> {
+    type Item = mqtt_reader::Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.failed && !self.remaining.is_empty() {
+            let mut reader = MqttBufReader::new(self.remaining);
+            let result = T::read(&mut reader);
+
+            if self.skip_unknown
+                && matches!(result, Err(PacketReadError::UnexpectedPropertyIdentifier))
+            {
+                let mut reader = MqttBufReader::new(self.remaining);
+                match AnyProperty::read(&mut reader) {
+                    Ok(_) => self.remaining = &self.remaining[reader.position()..],
+                    Err(e) => {
+                        let e = match e {
+                            PacketReadError::UnexpectedPropertyIdentifier => {
+                                PacketReadError::MalformedPacket
+                            }
+                            e => e,
+                        };
+                        self.failed = true;
+                        return Some(Err(e));
+                    }
+                }
+                continue;
+            }
+
+            self.remaining = &self.remaining[reader.position()..];
+            self.failed = result.is_err();
+            return Some(result);
+        }
+        None
+    }
+}
+
 #[macro_export]
 macro_rules! property_owned {
     ( $n:ident, $t:ty, $c:literal ) => {
@@ -254,7 +455,7 @@ macro_rules! property_binary_data {
 
 #[macro_export]
 macro_rules! packet_properties {
-    ( $n:ident, [ $( $p:ident ),+ ] ) => {
+    ( $n:ident, [ $( $p:ident ),+ ] $(, repeatable: [ $( $r:ident ),+ ] )? ) => {
 
         #[derive(Debug, PartialEq)]
         pub enum $n<'a>{
@@ -294,6 +495,36 @@ macro_rules! packet_properties {
                 }
             }
         }
+
+        impl PacketProperty for $n<'_> {
+            fn identifier(&self) -> u32 {
+                match self {
+                    $(
+                        Self::$p(_) => $p::IDENTIFIER,
+                    )*
+                }
+            }
+
+            $(
+                fn may_repeat(&self) -> bool {
+                    let identifier = self.identifier();
+                    identifier == UserProperty::IDENTIFIER
+                        $( || identifier == $r::IDENTIFIER )+
+                }
+            )?
+        }
+
+        $(
+            impl<'a> AsProperty<$p<'a>> for $n<'a> {
+                fn as_property(&self) -> Option<&$p<'a>> {
+                    match self {
+                        Self::$p(v) => Some(v),
+                        #[allow(unreachable_patterns)]
+                        _ => None,
+                    }
+                }
+            }
+        )*
     };
 }
 
@@ -326,6 +557,53 @@ property_owned!(WildcardSubscriptionAvailable, u8, 0x28);
 property_owned!(SubscriptionIdentifierAvailable, u8, 0x29);
 property_owned!(SharedSubscriptionAvailable, u8, 0x2A);
 
+impl PayloadFormatIndicator<'_> {
+    /// The payload is unspecified bytes
+    pub const UNSPECIFIED: u8 = 0;
+    /// The payload is UTF-8 encoded character data
+    pub const UTF8: u8 = 1;
+
+    /// Indicate that the payload is UTF-8 encoded character data
+    pub fn utf8() -> Self {
+        Self::new(Self::UTF8)
+    }
+}
+
+// Every property defined by MQTT v5, used to skip properties not expected
+// for a packet type, see [PropertiesIter::with_skip_unknown]
+packet_properties!(
+    AnyProperty,
+    [
+        PayloadFormatIndicator,
+        MessageExpiryInterval,
+        ContentType,
+        ResponseTopic,
+        CorrelationData,
+        SubscriptionIdentifier,
+        SessionExpiryInterval,
+        AssignedClientIdentifier,
+        ServerKeepAlive,
+        AuthenticationMethod,
+        AuthenticationData,
+        RequestProblemInformation,
+        WillDelayInterval,
+        RequestResponseInformation,
+        ResponseInformation,
+        ServerReference,
+        ReasonString,
+        ReceiveMaximum,
+        TopicAliasMaximum,
+        TopicAlias,
+        MaximumQos,
+        RetainAvailable,
+        UserProperty,
+        MaximumPacketSize,
+        WildcardSubscriptionAvailable,
+        SubscriptionIdentifierAvailable,
+        SharedSubscriptionAvailable
+    ]
+);
+
 packet_properties!(
     ConnectProperty,
     [
@@ -374,7 +652,8 @@ packet_properties!(
         SubscriptionIdentifier,
         TopicAlias,
         UserProperty
-    ]
+    ],
+    repeatable: [SubscriptionIdentifier]
 );
 packet_properties!(PubackProperty, [ReasonString, UserProperty]);
 packet_properties!(PubrecProperty, [ReasonString, UserProperty]);
@@ -643,4 +922,153 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn properties_can_be_looked_up_by_type() {
+        let mut properties: Vec<PacketAnyProperty<'_>, 4> = Vec::new();
+        properties
+            .push(PacketAnyProperty::PropertyU16(PropertyU16::new(7)))
+            .unwrap();
+        properties
+            .push(PacketAnyProperty::PropertyString("a".into()))
+            .unwrap();
+        properties
+            .push(PacketAnyProperty::PropertyString("b".into()))
+            .unwrap();
+
+        assert_eq!(
+            properties.get_property::<PropertyU16>().map(|p| p.value()),
+            Some(7)
+        );
+        assert_eq!(
+            properties
+                .get_property::<PropertyString>()
+                .map(|p| p.value()),
+            Some("a")
+        );
+        assert_eq!(properties.get_property::<PropertyU8>(), None);
+
+        let strings: Vec<&str, 2> = properties
+            .get_properties::<PropertyString>()
+            .map(|p| p.value())
+            .collect();
+        assert_eq!(strings.as_slice(), &["a", "b"]);
+    }
+
+    #[test]
+    fn duplicate_properties_are_detected_unless_repeatable() {
+        let properties = [
+            PublishProperty::ContentType("a".into()),
+            PublishProperty::UserProperty(StringPair::new("name", "value").into()),
+            PublishProperty::UserProperty(StringPair::new("name", "value").into()),
+            PublishProperty::SubscriptionIdentifier(1.into()),
+            PublishProperty::SubscriptionIdentifier(2.into()),
+        ];
+        assert!(!has_duplicate_property(&properties));
+
+        let properties = [
+            PublishProperty::ContentType("a".into()),
+            PublishProperty::MessageExpiryInterval(1.into()),
+            PublishProperty::ContentType("b".into()),
+        ];
+        assert!(has_duplicate_property(&properties));
+
+        // Subscription identifiers may only be repeated in a Publish packet
+        let properties = [
+            SubscribeProperty::SubscriptionIdentifier(1.into()),
+            SubscribeProperty::SubscriptionIdentifier(2.into()),
+        ];
+        assert!(has_duplicate_property(&properties));
+    }
+
+    #[cfg(feature = "validate-properties")]
+    #[test]
+    fn duplicate_properties_are_rejected_on_write_and_read() {
+        use crate::error::PacketWriteError;
+
+        let mut vec: Vec<PacketAnyProperty<'_>, 2> = Vec::new();
+        vec.extend([
+            PacketAnyProperty::PropertyU8(1.into()),
+            PacketAnyProperty::PropertyU8(2.into()),
+        ]);
+
+        let mut buf = [0xFFu8; 16];
+        let mut r = MqttBufWriter::new(&mut buf);
+        assert_eq!(
+            r.put_variable_u32_delimited_vec(&vec),
+            Err(PacketWriteError::DuplicateProperty)
+        );
+
+        // Properties length 4, then two u8 properties with the same identifier
+        let encoded = [
+            4,
+            PropertyU8::IDENTIFIER as u8,
+            1,
+            PropertyU8::IDENTIFIER as u8,
+            2,
+        ];
+        let mut r = MqttBufReader::new(&encoded);
+        let mut read_vec: Vec<PacketAnyProperty<'_>, 2> = Vec::new();
+        assert_eq!(
+            r.get_property_list(&mut read_vec),
+            Err(PacketReadError::DuplicateProperty)
+        );
+    }
+
+    #[test]
+    fn unexpected_properties_are_skipped_if_enabled() {
+        let mut buf = [0u8; 64];
+        let position = {
+            let mut r = MqttBufWriter::new(&mut buf);
+            ConnackProperty::ServerKeepAlive(30.into())
+                .write(&mut r)
+                .unwrap();
+            PubackProperty::ReasonString("a".into())
+                .write(&mut r)
+                .unwrap();
+            ConnackProperty::AssignedClientIdentifier("b".into())
+                .write(&mut r)
+                .unwrap();
+            PubackProperty::ReasonString("c".into())
+                .write(&mut r)
+                .unwrap();
+            r.position()
+        };
+        let encoded = &buf[0..position];
+
+        let mut strict = PropertiesIter::<PubackProperty>::new(encoded);
+        assert_eq!(
+            strict.next(),
+            Some(Err(PacketReadError::UnexpectedPropertyIdentifier))
+        );
+        assert_eq!(strict.next(), None);
+
+        let lenient: Vec<PubackProperty, 4> = PropertiesIter::new(encoded)
+            .with_skip_unknown(true)
+            .map(|p| p.unwrap())
+            .collect();
+        assert_eq!(
+            lenient.as_slice(),
+            [
+                PubackProperty::ReasonString("a".into()),
+                PubackProperty::ReasonString("c".into())
+            ]
+        );
+
+        // An identifier not defined by MQTT v5 has no known length, so the
+        // properties after it can't be decoded, even with a lenient reader
+        let encoded = [8, 0x1F, 0, 1, b'a', 0x7F, 0x1F, 0, 0];
+        let mut read_vec: Vec<PubackProperty, 4> = Vec::new();
+        let mut r = MqttBufReader::new(&encoded).with_skip_unknown_properties(true);
+        assert_eq!(
+            r.get_property_list(&mut read_vec),
+            Err(PacketReadError::MalformedPacket)
+        );
+
+        let mut r = MqttBufReader::new(&encoded);
+        assert_eq!(
+            r.get_property_list(&mut read_vec),
+            Err(PacketReadError::UnexpectedPropertyIdentifier)
+        );
+    }
 }
@@ -1,3 +1,4 @@
+#[cfg(feature = "display")]
 use core::fmt::{Display, Formatter};
 
 use crate::error::PacketReadError;
@@ -34,6 +35,7 @@ impl defmt::Format for QualityOfService {
     }
 }
 
+#[cfg(feature = "display")]
 impl Display for QualityOfService {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match self {
@@ -1,3 +1,4 @@
+#[cfg(feature = "display")]
 use core::fmt::{Display, Formatter};
 
 use crate::codec::{read::Read, write::Write};
@@ -7,11 +8,13 @@ use crate::error::PacketReadError;
 macro_rules! packet_reason_codes {
     ( $n:ident, [ $( $c:ident ),+ ] ) => {
         #[derive(Debug, PartialEq, Clone, Copy)]
-        #[repr(u8)]
         pub enum $n{
             $(
-                $c = ReasonCode::$c as u8,
+                $c,
             )*
+            /// A received reason code that is not defined for this packet type,
+            /// preserving the encoded value
+            Unknown(u8),
         }
 
         impl From<$n> for ReasonCode {
@@ -20,16 +23,26 @@ macro_rules! packet_reason_codes {
                     $(
                         $n::$c => ReasonCode::$c,
                     )*
+                    $n::Unknown(value) => ReasonCode::from(value),
                 }
             }
         }
 
+        impl From<$n> for u8 {
+            fn from(value: $n) -> Self {
+                value.value()
+            }
+        }
+
+        /// Fails with [PacketReadError::UnknownReasonCode] if the value is not
+        /// defined for this packet type, use [Read] to decode to an `Unknown`
+        /// value instead.
         impl TryFrom<u8> for $n {
             type Error = PacketReadError;
 
             fn try_from(value: u8) -> Result<Self, Self::Error> {
                 $(
-                    if value == ReasonCode::$c as u8 {
+                    if value == ReasonCode::$c.value() {
                         Ok(Self::$c)
                     } else
                 )*
@@ -54,8 +67,28 @@ macro_rules! packet_reason_codes {
         }
 
         impl $n {
+            /// The encoded value of the reason code
+            pub const fn value(&self) -> u8 {
+                match self {
+                    $(
+                        Self::$c => ReasonCode::$c.value(),
+                    )*
+                    Self::Unknown(value) => *value,
+                }
+            }
+
             pub fn is_error(&self) -> bool {
-                (*self as u8) > 128
+                self.value() > 128
+            }
+
+            /// True if this is a reason code that is not defined for this packet type
+            pub fn is_unknown(&self) -> bool {
+                matches!(self, Self::Unknown(_))
+            }
+
+            /// See [ReasonCode::is_retryable]
+            pub fn is_retryable(&self) -> bool {
+                ReasonCode::from(*self).is_retryable()
             }
         }
 
@@ -66,8 +99,10 @@ macro_rules! packet_reason_codes {
             where
                 Self: Sized,
             {
+                // Reason codes not defined for this packet type still decode, so
+                // the application can decide how to handle them
                 let encoded = reader.get_u8()?;
-                encoded.try_into()
+                Ok(encoded.try_into().unwrap_or(Self::Unknown(encoded)))
             }
         }
 
@@ -76,16 +111,18 @@ macro_rules! packet_reason_codes {
                 &self,
                 writer: &mut W,
             ) -> $crate::codec::mqtt_writer::Result<()> {
-                writer.put_u8(*self as u8)
+                writer.put_u8(self.value())
             }
         }
 
+        #[cfg(feature = "display")]
         impl Display for $n {
             fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
                 match self {
                     $(
                         Self::$c => write!(f, "$n($c)"),
                     )*
+                    Self::Unknown(value) => write!(f, "$n(Unknown({}))", value),
                 }
             }
         }
@@ -97,6 +134,7 @@ macro_rules! packet_reason_codes {
                     $(
                         Self::$c => defmt::write!(f, "$n($c)"),
                     )*
+                    Self::Unknown(value) => defmt::write!(f, "$n(Unknown({}))", value),
                 }
             }
         }
@@ -104,12 +142,46 @@ macro_rules! packet_reason_codes {
     };
 }
 
-/// A Reason Code is a one byte unsigned value that indicates the result of an operation. Reason Codes less
-/// than 128 indicate successful completion of an operation. The normal Reason Code for success is 0.
-/// Reason Code values of 128 or greater indicate failure.
-#[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Debug)]
-pub enum ReasonCode {
+macro_rules! reason_codes {
+    ( $( $c:ident = $v:literal ),+ ) => {
+        /// A Reason Code is a one byte unsigned value that indicates the result of an operation. Reason Codes less
+        /// than 128 indicate successful completion of an operation. The normal Reason Code for success is 0.
+        /// Reason Code values of 128 or greater indicate failure.
+        #[derive(Clone, Copy, PartialEq, Debug)]
+        pub enum ReasonCode {
+            $(
+                $c,
+            )*
+            /// A reason code not defined by MQTT v5, preserving the encoded value
+            Unknown(u8),
+        }
+
+        impl ReasonCode {
+            /// The encoded value of the reason code
+            pub const fn value(&self) -> u8 {
+                match self {
+                    $(
+                        Self::$c => $v,
+                    )*
+                    Self::Unknown(value) => *value,
+                }
+            }
+        }
+
+        impl From<u8> for ReasonCode {
+            fn from(value: u8) -> Self {
+                match value {
+                    $(
+                        $v => Self::$c,
+                    )*
+                    _ => Self::Unknown(value),
+                }
+            }
+        }
+    };
+}
+
+reason_codes!(
     Success = 0,
     GrantedQos1 = 1,
     GrantedQos2 = 2,
@@ -152,12 +224,41 @@ pub enum ReasonCode {
     ConnectionRateExceeded = 159,
     MaximumConnectTime = 160,
     SubscriptionIdentifiersNotSupported = 161,
-    WildcardSubscriptionsNotSupported = 162,
-}
+    WildcardSubscriptionsNotSupported = 162
+);
 
 impl ReasonCode {
     pub fn is_error(&self) -> bool {
-        (*self as u8) > 128
+        self.value() > 128
+    }
+
+    /// False if the reason code indicates an error that is expected to recur
+    /// if the request or connection is retried unchanged, e.g. [ReasonCode::NotAuthorized]
+    /// or [ReasonCode::MalformedPacket]. True for transient errors, e.g.
+    /// [ReasonCode::ServerBusy] or [ReasonCode::QuotaExceeded], and for non-error codes.
+    pub fn is_retryable(&self) -> bool {
+        !matches!(
+            self,
+            Self::MalformedPacket
+                | Self::ProtocolError
+                | Self::UnsupportedProtocolVersion
+                | Self::ClientIdentifierNotValid
+                | Self::BadUserNameOrPassword
+                | Self::NotAuthorized
+                | Self::Banned
+                | Self::BadAuthenticationMethod
+                | Self::SessionTakenOver
+                | Self::TopicFilterInvalid
+                | Self::TopicNameInvalid
+                | Self::TopicAliasInvalid
+                | Self::PacketTooLarge
+                | Self::PayloadFormatInvalid
+                | Self::RetainNotSupported
+                | Self::QosNotSupported
+                | Self::SharedSubscriptionsNotSupported
+                | Self::SubscriptionIdentifiersNotSupported
+                | Self::WildcardSubscriptionsNotSupported
+        )
     }
 }
 
@@ -291,18 +392,18 @@ mod tests {
     const DATA: [(ExampleReasonCode, u8); 2] = [
         (
             ExampleReasonCode::UnspecifiedError,
-            ReasonCode::UnspecifiedError as u8,
+            ReasonCode::UnspecifiedError.value(),
         ),
         (
             ExampleReasonCode::MalformedPacket,
-            ReasonCode::MalformedPacket as u8,
+            ReasonCode::MalformedPacket.value(),
         ),
     ];
 
     #[test]
     fn all_expected_codes_exist_with_expected_u8_value() {
         for (code, value) in DATA.iter() {
-            assert_eq!(*code as u8, *value);
+            assert_eq!(code.value(), *value);
         }
     }
 
@@ -351,15 +452,15 @@ mod tests {
     #[test]
     fn can_try_to_get_packet_reason_code_from_u8() {
         assert_eq!(
-            ExampleReasonCode::try_from(ReasonCode::UnspecifiedError as u8),
+            ExampleReasonCode::try_from(ReasonCode::UnspecifiedError.value()),
             Ok(ExampleReasonCode::UnspecifiedError)
         );
         assert_eq!(
-            ExampleReasonCode::try_from(ReasonCode::MalformedPacket as u8),
+            ExampleReasonCode::try_from(ReasonCode::MalformedPacket.value()),
             Ok(ExampleReasonCode::MalformedPacket)
         );
         assert_eq!(
-            ExampleReasonCode::try_from(ReasonCode::AdministrativeAction as u8),
+            ExampleReasonCode::try_from(ReasonCode::AdministrativeAction.value()),
             Err(PacketReadError::UnknownReasonCode)
         );
     }
@@ -375,4 +476,46 @@ mod tests {
             ReasonCode::MalformedPacket
         );
     }
+
+    #[test]
+    fn reason_codes_are_classified_as_retryable_or_not() {
+        assert!(ReasonCode::ServerBusy.is_retryable());
+        assert!(ReasonCode::QuotaExceeded.is_retryable());
+        assert!(!ReasonCode::MalformedPacket.is_retryable());
+        assert!(!ReasonCode::NotAuthorized.is_retryable());
+        assert!(ExampleReasonCode::UnspecifiedError.is_retryable());
+        assert!(!ExampleReasonCode::MalformedPacket.is_retryable());
+        assert!(DisconnectReasonCode::ServerShuttingDown.is_retryable());
+        assert!(!ConnectReasonCode::BadUserNameOrPassword.is_retryable());
+    }
+
+    #[test]
+    fn unknown_codes_are_read_preserving_value() {
+        // Defined by MQTT v5, but not for this packet type
+        let buf = [ReasonCode::AdministrativeAction.value()];
+        let mut r = MqttBufReader::new(&buf);
+        let read_code: ExampleReasonCode = r.get().unwrap();
+        assert_eq!(
+            read_code,
+            ExampleReasonCode::Unknown(ReasonCode::AdministrativeAction.value())
+        );
+        assert!(read_code.is_unknown());
+        assert!(read_code.is_error());
+        assert_eq!(
+            ReasonCode::from(read_code),
+            ReasonCode::AdministrativeAction
+        );
+
+        // Not defined by MQTT v5
+        let buf = [0xAA];
+        let mut r = MqttBufReader::new(&buf);
+        let read_code: DisconnectReasonCode = r.get().unwrap();
+        assert_eq!(read_code, DisconnectReasonCode::Unknown(0xAA));
+        assert_eq!(ReasonCode::from(read_code), ReasonCode::Unknown(0xAA));
+        assert_eq!(u8::from(read_code), 0xAA);
+
+        let mut buf = [0u8];
+        MqttBufWriter::new(&mut buf).put(&read_code).unwrap();
+        assert_eq!(buf, [0xAA]);
+    }
 }
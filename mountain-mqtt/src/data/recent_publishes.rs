@@ -0,0 +1,81 @@
+use heapless::Vec;
+
+/// Fingerprints of the most recent `N` messages published by the client, each
+/// a hash of the topic name and payload, e.g. to recognise our own messages
+/// echoed back by a server that doesn't support the NoLocal subscription option.
+/// When full, recording a new message forgets the oldest. Distinct messages
+/// may occasionally have the same fingerprint.
+#[derive(Debug, Clone, Default)]
+pub struct RecentPublishes<const N: usize> {
+    /// Fingerprints from oldest to most recent
+    fingerprints: Vec<u32, N>,
+}
+
+/// 32 bit FNV-1a hash of the topic name and payload, separated by a null
+/// character, which can't appear in a topic name
+fn fingerprint(topic_name: &str, payload: &[u8]) -> u32 {
+    topic_name
+        .as_bytes()
+        .iter()
+        .chain(&[0])
+        .chain(payload)
+        .fold(0x811c_9dc5, |hash, b| {
+            (hash ^ *b as u32).wrapping_mul(0x0100_0193)
+        })
+}
+
+impl<const N: usize> RecentPublishes<N> {
+    pub fn new() -> Self {
+        Self {
+            fingerprints: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, topic_name: &str, payload: &[u8]) {
+        if self.fingerprints.is_full() && !self.fingerprints.is_empty() {
+            self.fingerprints.remove(0);
+        }
+        let _ = self.fingerprints.push(fingerprint(topic_name, payload));
+    }
+
+    /// If a message with this topic name and payload has been recorded, forget
+    /// it (so that each recorded message is only matched once) and return true
+    pub fn take(&mut self, topic_name: &str, payload: &[u8]) -> bool {
+        let fingerprint = fingerprint(topic_name, payload);
+        match self.fingerprints.iter().position(|f| *f == fingerprint) {
+            Some(index) => {
+                self.fingerprints.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.fingerprints.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_publishes_are_taken_once() {
+        let mut recent = RecentPublishes::<2>::new();
+        recent.record("a", b"1");
+        recent.record("a", b"1");
+        recent.record("b", b"2");
+
+        // The oldest of the two identical messages was forgotten
+        assert!(recent.take("a", b"1"));
+        assert!(!recent.take("a", b"1"));
+        assert!(!recent.take("b", b"1"));
+        assert!(!recent.take("b2", b""));
+        assert!(recent.take("b", b"2"));
+
+        recent.record("a", b"1");
+        recent.clear();
+        assert!(!recent.take("a", b"1"));
+    }
+}
@@ -18,6 +18,43 @@ pub struct SubscriptionOptions {
     pub retain_handling: RetainHandling,
 }
 
+impl SubscriptionOptions {
+    /// Create options with the given maximum qos, and defaults for other
+    /// options - no local false, retain as published false, and
+    /// [RetainHandling::SendOnSubscribe]
+    pub fn new(maximum_qos: QualityOfService) -> Self {
+        Self {
+            maximum_qos,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: RetainHandling::SendOnSubscribe,
+        }
+    }
+
+    /// Set whether retained messages are sent by the server when subscribing.
+    /// For example [RetainHandling::DoNotSend] can be used to receive only
+    /// live messages, skipping retained messages.
+    pub fn with_retain_handling(mut self, retain_handling: RetainHandling) -> Self {
+        self.retain_handling = retain_handling;
+        self
+    }
+
+    /// Set whether messages published by this client are sent back to it
+    pub fn with_no_local(mut self, no_local: bool) -> Self {
+        self.no_local = no_local;
+        self
+    }
+
+    /// Set whether messages forwarded to this client keep the retain flag
+    /// they were published with. When false (the default), the retain flag
+    /// is only set on messages sent because they were retained on the server,
+    /// so it can be used to distinguish retained messages from live ones.
+    pub fn with_retain_as_published(mut self, retain_as_published: bool) -> Self {
+        self.retain_as_published = retain_as_published;
+        self
+    }
+}
+
 const QOS_MASK: u8 = 0x3;
 const NO_LOCAL_BIT: u8 = 1 << 2;
 const RETAIN_AS_PUBLISHED_BIT: u8 = 1 << 3;
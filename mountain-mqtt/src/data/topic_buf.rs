@@ -0,0 +1,240 @@
+use core::fmt::{Display, Formatter, Write};
+
+use heapless::String;
+
+use super::topic_filter::{MULTI_LEVEL_WILDCARD, SINGLE_LEVEL_WILDCARD, TOPIC_LEVEL_SEPARATOR};
+
+/// Errors building a topic name with a [TopicBuf]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TopicBufError {
+    /// The topic name would not fit in the buffer
+    Overflow,
+
+    /// A segment was empty, which would produce an empty topic level
+    EmptySegment,
+
+    /// A segment contained the topic level separator `/`
+    SeparatorInSegment,
+
+    /// A wildcard character `+` or `#` was used, these are only valid in
+    /// topic filters
+    Wildcard,
+
+    /// The null character U+0000 was used [MQTT-4.7.3-2]
+    NullCharacter,
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for TopicBufError {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Overflow => defmt::write!(f, "Overflow"),
+            Self::EmptySegment => defmt::write!(f, "EmptySegment"),
+            Self::SeparatorInSegment => defmt::write!(f, "SeparatorInSegment"),
+            Self::Wildcard => defmt::write!(f, "Wildcard"),
+            Self::NullCharacter => defmt::write!(f, "NullCharacter"),
+        }
+    }
+}
+
+#[cfg(feature = "display")]
+impl Display for TopicBufError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Overflow => write!(f, "Overflow"),
+            Self::EmptySegment => write!(f, "EmptySegment"),
+            Self::SeparatorInSegment => write!(f, "SeparatorInSegment"),
+            Self::Wildcard => write!(f, "Wildcard"),
+            Self::NullCharacter => write!(f, "NullCharacter"),
+        }
+    }
+}
+
+/// Check that text can be used in a topic name, i.e. it contains no wildcard
+/// characters [MQTT-3.3.2-2] and no null characters [MQTT-4.7.3-2]
+pub(crate) fn validate_topic_text(text: &str) -> Result<(), TopicBufError> {
+    for c in text.chars() {
+        if SINGLE_LEVEL_WILDCARD.contains(c) || MULTI_LEVEL_WILDCARD.contains(c) {
+            return Err(TopicBufError::Wildcard);
+        } else if c == '\0' {
+            return Err(TopicBufError::NullCharacter);
+        }
+    }
+    Ok(())
+}
+
+/// Check that text can be used as a single level of a topic name
+fn validate_segment(segment: &str) -> Result<(), TopicBufError> {
+    if segment.is_empty() {
+        Err(TopicBufError::EmptySegment)
+    } else if segment.contains(TOPIC_LEVEL_SEPARATOR) {
+        Err(TopicBufError::SeparatorInSegment)
+    } else {
+        validate_topic_text(segment)
+    }
+}
+
+/// Builds a topic name in a buffer of up to `N` bytes, validating each part
+/// as it is added, e.g. to produce `devices/{id}/telemetry/{channel}`:
+///
+/// ```
+/// # use mountain_mqtt::data::topic_buf::TopicBuf;
+/// let mut topic = TopicBuf::<64>::new();
+/// topic.push_str("devices").unwrap();
+/// topic.push_segment("sensor01").unwrap();
+/// topic.push_segment("telemetry").unwrap();
+/// topic.push_display(3).unwrap();
+/// assert_eq!(topic.as_str(), "devices/sensor01/telemetry/3");
+/// ```
+///
+/// If an error is returned, the buffer is left unchanged.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct TopicBuf<const N: usize> {
+    buf: String<N>,
+}
+
+impl<const N: usize> TopicBuf<N> {
+    pub fn new() -> Self {
+        Self { buf: String::new() }
+    }
+
+    /// Append a single topic level, preceded by a separator unless the
+    /// buffer is empty. The segment must be non-empty, and can't contain
+    /// `/`, `+`, `#` or null characters, so values such as device ids can be
+    /// used without producing unexpected topic levels.
+    pub fn push_segment(&mut self, segment: &str) -> Result<(), TopicBufError> {
+        validate_segment(segment)?;
+        let separator_len = if self.buf.is_empty() { 0 } else { 1 };
+        if self.buf.len() + separator_len + segment.len() > N {
+            return Err(TopicBufError::Overflow);
+        }
+        if separator_len > 0 {
+            self.buf
+                .push(TOPIC_LEVEL_SEPARATOR)
+                .map_err(|_| TopicBufError::Overflow)?;
+        }
+        self.buf
+            .push_str(segment)
+            .map_err(|_| TopicBufError::Overflow)
+    }
+
+    /// Append a value as a single topic level using its [Display]
+    /// implementation, e.g. a numeric channel id, with the same validation
+    /// as [TopicBuf::push_segment].
+    pub fn push_display<T: Display>(&mut self, value: T) -> Result<(), TopicBufError> {
+        let start = self.buf.len();
+        let result = self.write_display(value);
+        if result.is_err() {
+            self.buf.truncate(start);
+        }
+        result
+    }
+
+    fn write_display<T: Display>(&mut self, value: T) -> Result<(), TopicBufError> {
+        if !self.buf.is_empty() {
+            self.buf
+                .push(TOPIC_LEVEL_SEPARATOR)
+                .map_err(|_| TopicBufError::Overflow)?;
+        }
+        let segment_start = self.buf.len();
+        write!(self.buf, "{}", value).map_err(|_| TopicBufError::Overflow)?;
+        validate_segment(&self.buf[segment_start..])
+    }
+
+    /// Append text directly, without adding a separator. The text may
+    /// contain separators, e.g. to add a fixed prefix "devices/" or
+    /// multiple levels at once, but can't contain `+`, `#` or null characters.
+    pub fn push_str(&mut self, text: &str) -> Result<(), TopicBufError> {
+        validate_topic_text(text)?;
+        self.buf.push_str(text).map_err(|_| TopicBufError::Overflow)
+    }
+
+    /// Remove the last topic level and its preceding separator, if any,
+    /// e.g. to reuse a common prefix for several topic names
+    pub fn pop_segment(&mut self) {
+        let len = self.buf.rfind(TOPIC_LEVEL_SEPARATOR).unwrap_or(0);
+        self.buf.truncate(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+
+    /// The topic name. Note that this is empty if nothing has been pushed,
+    /// and an empty topic name is not valid in a Publish packet.
+    pub fn as_str(&self) -> &str {
+        self.buf.as_str()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
+
+impl<const N: usize> AsRef<str> for TopicBuf<N> {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<const N: usize> Display for TopicBuf<N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topic_is_built_from_segments() {
+        let mut topic = TopicBuf::<64>::new();
+        topic.push_str("devices/").unwrap();
+        topic.push_str("sensor01").unwrap();
+        topic.push_segment("telemetry").unwrap();
+        topic.push_display(12u8).unwrap();
+        assert_eq!(topic.as_str(), "devices/sensor01/telemetry/12");
+
+        topic.pop_segment();
+        topic.push_segment("status").unwrap();
+        assert_eq!(topic.as_str(), "devices/sensor01/telemetry/status");
+
+        topic.clear();
+        topic.pop_segment();
+        assert!(topic.is_empty());
+    }
+
+    #[test]
+    fn invalid_parts_are_rejected_leaving_topic_unchanged() {
+        let mut topic = TopicBuf::<16>::new();
+        topic.push_segment("devices").unwrap();
+
+        assert_eq!(topic.push_segment(""), Err(TopicBufError::EmptySegment));
+        assert_eq!(
+            topic.push_segment("a/b"),
+            Err(TopicBufError::SeparatorInSegment)
+        );
+        assert_eq!(topic.push_segment("+"), Err(TopicBufError::Wildcard));
+        assert_eq!(topic.push_str("/#"), Err(TopicBufError::Wildcard));
+        assert_eq!(topic.push_str("a\0"), Err(TopicBufError::NullCharacter));
+        assert_eq!(
+            topic.push_display("a/b"),
+            Err(TopicBufError::SeparatorInSegment)
+        );
+        assert_eq!(topic.push_display(""), Err(TopicBufError::EmptySegment));
+        assert_eq!(
+            topic.push_segment("abcdefghi"),
+            Err(TopicBufError::Overflow)
+        );
+        assert_eq!(topic.push_display(123456789), Err(TopicBufError::Overflow));
+        assert_eq!(topic.as_str(), "devices");
+
+        topic.push_segment("abcdefg").unwrap();
+        assert_eq!(topic.len(), 15);
+    }
+}
@@ -0,0 +1,151 @@
+/// The separator between levels of topic names and topic filters
+pub const TOPIC_LEVEL_SEPARATOR: char = '/';
+
+/// Wildcard matching a single topic level
+pub const SINGLE_LEVEL_WILDCARD: &str = "+";
+
+/// Wildcard matching any number of topic levels, including the parent level
+pub const MULTI_LEVEL_WILDCARD: &str = "#";
+
+/// Prefix of a shared subscription topic filter, `$share/{ShareName}/{filter}`
+pub const SHARED_SUBSCRIPTION_PREFIX: &str = "$share/";
+
+/// True if the topic filter matches the topic name, using the wildcard rules
+/// of the MQTT 5 specification:
+///
+/// - `+` matches exactly one level, e.g. `a/+/c` matches `a/b/c` and `a//c`
+///   but not `a/b/b/c`
+/// - `#` must be the last level, and matches the parent level and any
+///   number of child levels, e.g. `a/#` matches `a`, `a/b` and `a/b/c`
+/// - Filters starting with a wildcard don't match topic names starting with `$`,
+///   e.g. `#` does not match `$SYS/uptime`
+///
+/// A shared subscription filter `$share/{ShareName}/{filter}` matches using
+/// just `{filter}`. The filter is not otherwise validated, so an invalid filter
+/// (e.g. `a/#/c`) will give an unspecified result.
+pub fn topic_filter_matches(topic_filter: &str, topic_name: &str) -> bool {
+    let topic_filter = shared_subscription_filter(topic_filter).unwrap_or(topic_filter);
+
+    if topic_name.starts_with('$')
+        && (topic_filter.starts_with(SINGLE_LEVEL_WILDCARD)
+            || topic_filter.starts_with(MULTI_LEVEL_WILDCARD))
+    {
+        return false;
+    }
+
+    let mut filter_levels = topic_filter.split(TOPIC_LEVEL_SEPARATOR);
+    let mut name_levels = topic_name.split(TOPIC_LEVEL_SEPARATOR);
+
+    loop {
+        match (filter_levels.next(), name_levels.next()) {
+            (Some(MULTI_LEVEL_WILDCARD), _) => return true,
+            (Some(SINGLE_LEVEL_WILDCARD), Some(_)) => {}
+            (Some(filter_level), Some(name_level)) if filter_level == name_level => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// If the topic filter is a shared subscription `$share/{ShareName}/{filter}`,
+/// return the `{filter}` part, otherwise None
+pub fn shared_subscription_filter(topic_filter: &str) -> Option<&str> {
+    topic_filter
+        .strip_prefix(SHARED_SUBSCRIPTION_PREFIX)
+        .and_then(|rest| rest.split_once(TOPIC_LEVEL_SEPARATOR))
+        .map(|(_share_name, filter)| filter)
+}
+
+/// The part of the topic name matched by the wildcard at `index` in the topic
+/// filter, counting `+` and `#` wildcards from zero in order, or None if the
+/// filter doesn't match the topic name or has no such wildcard. A `+` matches
+/// one level, and a `#` matches the remaining levels, which is empty if the `#`
+/// matched just the parent level, e.g. `a/+/#` captures `b` and `c/d` from
+/// `a/b/c/d`, and `a/#` captures an empty string from `a`.
+pub fn topic_filter_capture<'a>(
+    topic_filter: &str,
+    topic_name: &'a str,
+    index: usize,
+) -> Option<&'a str> {
+    if !topic_filter_matches(topic_filter, topic_name) {
+        return None;
+    }
+    let topic_filter = shared_subscription_filter(topic_filter).unwrap_or(topic_filter);
+
+    let mut name_levels = topic_name.split(TOPIC_LEVEL_SEPARATOR);
+    let mut offset = 0;
+    let mut wildcard = 0;
+    for filter_level in topic_filter.split(TOPIC_LEVEL_SEPARATOR) {
+        if filter_level == MULTI_LEVEL_WILDCARD {
+            return (wildcard == index).then(|| topic_name.get(offset..).unwrap_or(""));
+        }
+        let name_level = name_levels.next()?;
+        if filter_level == SINGLE_LEVEL_WILDCARD {
+            if wildcard == index {
+                return Some(name_level);
+            }
+            wildcard += 1;
+        }
+        offset += name_level.len() + 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_match_topic_names() {
+        let cases = [
+            ("a/b/c", "a/b/c", true),
+            ("a/b/c", "a/b", false),
+            ("a/b", "a/b/c", false),
+            ("a/+/c", "a/b/c", true),
+            ("a/+/c", "a//c", true),
+            ("a/+/c", "a/b/b/c", false),
+            ("+", "a", true),
+            ("+", "a/b", false),
+            ("+/+", "/a", true),
+            ("a/#", "a", true),
+            ("a/#", "a/b/c", true),
+            ("a/#", "b/c", false),
+            ("#", "a/b/c", true),
+            ("a/+/#", "a/b", true),
+            ("#", "$SYS/uptime", false),
+            ("+/uptime", "$SYS/uptime", false),
+            ("$SYS/#", "$SYS/uptime", true),
+            ("$share/group/a/+", "a/b", true),
+            ("$share/group/a/+", "group/a/b", false),
+        ];
+        for (filter, name, expected) in cases {
+            assert_eq!(
+                topic_filter_matches(filter, name),
+                expected,
+                "filter {filter}, name {name}"
+            );
+        }
+    }
+
+    #[test]
+    fn wildcards_capture_topic_levels() {
+        let cases = [
+            ("a/+/#", "a/b/c/d", 0, Some("b")),
+            ("a/+/#", "a/b/c/d", 1, Some("c/d")),
+            ("a/+/#", "a/b/c/d", 2, None),
+            ("a/#", "a", 0, Some("")),
+            ("+/+", "/a", 0, Some("")),
+            ("+/+", "/a", 1, Some("a")),
+            ("a/b", "a/b", 0, None),
+            ("a/+", "b/c", 0, None),
+            ("$share/group/a/+", "a/b", 0, Some("b")),
+        ];
+        for (filter, name, index, expected) in cases {
+            assert_eq!(
+                topic_filter_capture(filter, name, index),
+                expected,
+                "filter {filter}, name {name}, index {index}"
+            );
+        }
+    }
+}
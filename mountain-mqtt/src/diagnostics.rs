@@ -0,0 +1,140 @@
+use crate::{
+    data::{
+        packet_identifier::PacketIdentifier,
+        packet_type::PacketType,
+        reason_code::{ConnectReasonCode, SubscribeReasonCode},
+    },
+    packets::{connect::Connect, server_packet::ServerPacket},
+};
+
+/// Structured events describing what a client is doing, for logging and
+/// debugging connections. These are reported to a [Diagnostics] hook, see
+/// for example
+/// [ManagedClient::with_diagnostics](crate::managed_client::ManagedClient::with_diagnostics),
+/// and contain no borrowed data, so they can be stored or sent elsewhere.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClientDiagnosticEvent {
+    /// A Connect packet is being sent to the server
+    ConnectAttempt { clean_start: bool, keep_alive: u16 },
+
+    /// A Connack packet was received. The connection was accepted if
+    /// `reason_code` is [ConnectReasonCode::Success]
+    ConnackResult {
+        reason_code: ConnectReasonCode,
+        session_present: bool,
+    },
+
+    /// A Suback packet was received, this is produced once for the reason
+    /// code of each subscription request
+    SubackResult {
+        packet_identifier: PacketIdentifier,
+        reason_code: SubscribeReasonCode,
+    },
+
+    /// A packet is being sent again, since the server has not yet responded
+    /// to an earlier one, e.g. a Pingreq sent while an earlier Pingreq is
+    /// still awaiting a Pingresp
+    Retransmit { packet_type: PacketType },
+
+    /// A timeout occurred. If `server_unresponsive` is true, no packets at
+    /// all were received from the server for the receive timeout, otherwise
+    /// the server did not respond to a request in time.
+    Timeout { server_unresponsive: bool },
+}
+
+impl ClientDiagnosticEvent {
+    /// The event for sending a Connect packet
+    pub fn connect_attempt<const P: usize, const W: usize>(packet: &Connect<'_, P, W>) -> Self {
+        Self::ConnectAttempt {
+            clean_start: packet.clean_start(),
+            keep_alive: packet.keep_alive(),
+        }
+    }
+
+    /// True if the event shows something has gone wrong, e.g. a rejected
+    /// connection or subscription, or a timeout, so that it may be logged at
+    /// a higher level
+    pub fn is_warning(&self) -> bool {
+        match self {
+            Self::ConnectAttempt { .. } | Self::Retransmit { .. } => false,
+            Self::ConnackResult { reason_code, .. } => *reason_code != ConnectReasonCode::Success,
+            Self::SubackResult { reason_code, .. } => reason_code.is_error(),
+            Self::Timeout { .. } => true,
+        }
+    }
+}
+
+/// Receives [ClientDiagnosticEvent]s from a client.
+/// This is implemented for closures taking a [ClientDiagnosticEvent],
+/// and by `LogDiagnostics` and `DefmtDiagnostics` (with the `log` and `defmt`
+/// features) to log each event.
+pub trait Diagnostics {
+    fn event(&mut self, event: ClientDiagnosticEvent);
+}
+
+impl<F> Diagnostics for F
+where
+    F: FnMut(ClientDiagnosticEvent),
+{
+    fn event(&mut self, event: ClientDiagnosticEvent) {
+        self(event)
+    }
+}
+
+/// Report the [ClientDiagnosticEvent]s for a packet received from the server,
+/// if any, to `diagnostics`
+pub fn report_received<const P: usize, const S: usize>(
+    diagnostics: &mut dyn Diagnostics,
+    packet: &ServerPacket<'_, P, S>,
+) {
+    match packet {
+        ServerPacket::Connack(connack) => diagnostics.event(ClientDiagnosticEvent::ConnackResult {
+            reason_code: *connack.reason_code(),
+            session_present: connack.session_present(),
+        }),
+        ServerPacket::Suback(suback) => {
+            for reason_code in suback.reason_codes() {
+                diagnostics.event(ClientDiagnosticEvent::SubackResult {
+                    packet_identifier: *suback.packet_identifier(),
+                    reason_code: *reason_code,
+                });
+            }
+        }
+        _ => {}
+    }
+}
+
+/// [Diagnostics] logging each event with the `log` crate, at warn level if
+/// [ClientDiagnosticEvent::is_warning], otherwise at info level
+#[cfg(feature = "log")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LogDiagnostics;
+
+#[cfg(feature = "log")]
+impl Diagnostics for LogDiagnostics {
+    fn event(&mut self, event: ClientDiagnosticEvent) {
+        if event.is_warning() {
+            log::warn!("MQTT {:?}", event);
+        } else {
+            log::info!("MQTT {:?}", event);
+        }
+    }
+}
+
+/// [Diagnostics] logging each event with `defmt`, at warn level if
+/// [ClientDiagnosticEvent::is_warning], otherwise at info level
+#[cfg(feature = "defmt")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefmtDiagnostics;
+
+#[cfg(feature = "defmt")]
+impl Diagnostics for DefmtDiagnostics {
+    fn event(&mut self, event: ClientDiagnosticEvent) {
+        if event.is_warning() {
+            defmt::warn!("MQTT {}", event);
+        } else {
+            defmt::info!("MQTT {}", event);
+        }
+    }
+}
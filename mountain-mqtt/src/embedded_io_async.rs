@@ -1,11 +1,42 @@
 use embedded_io::ReadReady;
-use embedded_io_async::{Read, Write};
+use embedded_io_async::{Read, ReadExactError, Write};
 
 use crate::{
-    error::{PacketReadError, PacketWriteError},
+    error::{TransportError, TransportErrorKind},
     packet_client::Connection,
 };
 
+/// An error from a connection using `embedded-io-async`, e.g. [ConnectionEmbedded]
+#[derive(Debug)]
+pub enum EmbeddedIoError<E> {
+    /// The connection was closed, so no data will be received in future
+    Closed,
+
+    /// An error from the underlying connection
+    Io(E),
+}
+
+impl<E> From<ReadExactError<E>> for EmbeddedIoError<E> {
+    fn from(value: ReadExactError<E>) -> Self {
+        match value {
+            ReadExactError::UnexpectedEof => Self::Closed,
+            ReadExactError::Other(e) => Self::Io(e),
+        }
+    }
+}
+
+impl<E> TransportError for EmbeddedIoError<E>
+where
+    E: embedded_io::Error,
+{
+    fn kind(&self) -> TransportErrorKind {
+        match self {
+            Self::Closed => TransportErrorKind::Closed,
+            Self::Io(e) => TransportErrorKind::from_embedded_io(e),
+        }
+    }
+}
+
 /// Contains an instance of T with [Read], [Write] and [ReadReady].
 /// We implement [Connection] for this rather than T directly to avoid
 /// issues with conflicting implementations of external types T that
@@ -46,34 +77,52 @@ impl<T> Connection for ConnectionEmbedded<T>
 where
     T: Read + Write + ReadReady,
 {
-    async fn send(&mut self, buf: &[u8]) -> Result<(), PacketWriteError> {
-        self.inner
-            .write_all(buf)
-            .await
-            .map_err(|_| PacketWriteError::ConnectionSend)?;
-        self.inner
-            .flush()
-            .await
-            .map_err(|_| PacketWriteError::ConnectionSend)
+    type Error = EmbeddedIoError<T::Error>;
+
+    async fn send(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.inner.write_all(buf).await.map_err(EmbeddedIoError::Io)
     }
 
-    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), PacketReadError> {
-        self.inner
-            .read_exact(buf)
-            .await
-            .map_err(|_| PacketReadError::ConnectionReceive)
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush().await.map_err(EmbeddedIoError::Io)
     }
 
-    async fn receive_if_ready(&mut self, buf: &mut [u8]) -> Result<bool, PacketReadError> {
-        if self
-            .inner
-            .read_ready()
-            .map_err(|_| PacketReadError::ConnectionReceive)?
-        {
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.inner.read_exact(buf).await?;
+        Ok(())
+    }
+
+    async fn receive_if_ready(&mut self, buf: &mut [u8]) -> Result<bool, Self::Error> {
+        if self.inner.read_ready().map_err(EmbeddedIoError::Io)? {
             self.receive(buf).await?;
             Ok(true)
         } else {
             Ok(false)
         }
     }
+
+    async fn receive_ready(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() || !self.inner.read_ready().map_err(EmbeddedIoError::Io)? {
+            return Ok(0);
+        }
+        // Data is ready, so this reads it without waiting
+        match self.inner.read(buf).await {
+            // The connection is closed, no data will be read in future
+            Ok(0) => Err(EmbeddedIoError::Closed),
+            Ok(n) => Ok(n),
+            Err(e) => Err(EmbeddedIoError::Io(e)),
+        }
+    }
+
+    async fn receive_available(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.inner.read(buf).await {
+            // The connection is closed, no data will be read in future
+            Ok(0) => Err(EmbeddedIoError::Closed),
+            Ok(n) => Ok(n),
+            Err(e) => Err(EmbeddedIoError::Io(e)),
+        }
+    }
 }
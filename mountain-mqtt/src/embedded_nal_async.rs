@@ -0,0 +1,299 @@
+use core::{
+    future::{poll_fn, Future},
+    net::SocketAddr,
+    pin::pin,
+    task::Poll,
+};
+
+use embedded_io_async::{Read, Write};
+use embedded_nal_async::TcpConnect;
+
+use crate::{embedded_io_async::EmbeddedIoError, packet_client::Connection};
+
+/// Contains a TCP connection made using an [embedded_nal_async::TcpConnect]
+/// network stack (e.g. a W5500 driver or esp-wifi), implementing [Connection].
+/// Unlike [ConnectionEmbedded](crate::embedded_io_async::ConnectionEmbedded), this
+/// doesn't require [embedded_io::ReadReady], which embedded-nal connections don't
+/// provide. Instead, [Connection::receive_if_ready] polls a read once, and drops
+/// it if no data is ready, so the [Read] implementation of the connection must be
+/// cancel-safe, i.e. a dropped read must not lose data.
+pub struct ConnectionNal<T>
+where
+    T: Read + Write,
+{
+    inner: T,
+}
+
+impl<T> ConnectionNal<T>
+where
+    T: Read + Write,
+{
+    /// Create a new adapter
+    pub fn new(inner: T) -> Self {
+        ConnectionNal { inner }
+    }
+
+    /// Consume the adapter, returning the inner object.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Borrow the inner object.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Mutably borrow the inner object.
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+/// Connect to `remote` using a network `stack`, producing a [ConnectionNal]
+pub async fn connect<S>(
+    stack: &S,
+    remote: SocketAddr,
+) -> Result<ConnectionNal<S::Connection<'_>>, S::Error>
+where
+    S: TcpConnect,
+{
+    let inner = stack.connect(remote).await?;
+    Ok(ConnectionNal::new(inner))
+}
+
+impl<T> Connection for ConnectionNal<T>
+where
+    T: Read + Write,
+{
+    type Error = EmbeddedIoError<T::Error>;
+
+    async fn send(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.inner.write_all(buf).await.map_err(EmbeddedIoError::Io)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush().await.map_err(EmbeddedIoError::Io)
+    }
+
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.inner.read_exact(buf).await?;
+        Ok(())
+    }
+
+    async fn receive_if_ready(&mut self, buf: &mut [u8]) -> Result<bool, Self::Error> {
+        if buf.is_empty() {
+            return Ok(true);
+        }
+
+        // Poll a read once, if no data is ready it is dropped
+        let result = {
+            let mut read = pin!(self.inner.read(buf));
+            poll_fn(|cx| Poll::Ready(read.as_mut().poll(cx))).await
+        };
+
+        match result {
+            // There is no data at present - not an error, just return false
+            Poll::Pending => Ok(false),
+
+            // If length is 0, the connection is closed, this is a read error
+            // since no data will be read in future
+            Poll::Ready(Ok(0)) => Err(EmbeddedIoError::Closed),
+
+            // We have read some bytes, we may need to perform more reads to finish
+            Poll::Ready(Ok(n)) => {
+                if n < buf.len() {
+                    self.receive(&mut buf[n..]).await?;
+                }
+                Ok(true)
+            }
+
+            Poll::Ready(Err(e)) => Err(EmbeddedIoError::Io(e)),
+        }
+    }
+
+    async fn receive_ready(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // Poll a read once, if no data is ready it is dropped
+        let result = {
+            let mut read = pin!(self.inner.read(buf));
+            poll_fn(|cx| Poll::Ready(read.as_mut().poll(cx))).await
+        };
+
+        match result {
+            Poll::Pending => Ok(0),
+            // The connection is closed, no data will be read in future
+            Poll::Ready(Ok(0)) => Err(EmbeddedIoError::Closed),
+            Poll::Ready(Ok(n)) => Ok(n),
+            Poll::Ready(Err(e)) => Err(EmbeddedIoError::Io(e)),
+        }
+    }
+
+    async fn receive_available(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.inner.read(buf).await {
+            // The connection is closed, no data will be read in future
+            Ok(0) => Err(EmbeddedIoError::Closed),
+            Ok(n) => Ok(n),
+            Err(e) => Err(EmbeddedIoError::Io(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{
+        cell::Cell,
+        net::{Ipv4Addr, SocketAddrV4},
+    };
+
+    use embedded_io::{ErrorKind, ErrorType};
+    use heapless::Vec;
+
+    use super::*;
+
+    /// A socket that reads from a list of chunks, each of which becomes
+    /// available to a single read, then waits forever unless closed
+    struct MockSocket {
+        chunks: Vec<&'static [u8], 4>,
+        closed: bool,
+        sent: Vec<u8, 16>,
+    }
+
+    impl ErrorType for MockSocket {
+        type Error = ErrorKind;
+    }
+
+    impl Read for MockSocket {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            if self.chunks.is_empty() {
+                if self.closed {
+                    return Ok(0);
+                }
+                core::future::pending::<()>().await;
+            }
+            let chunk = self.chunks[0];
+            let n = chunk.len().min(buf.len());
+            buf[..n].copy_from_slice(&chunk[..n]);
+            if n < chunk.len() {
+                self.chunks[0] = &chunk[n..];
+            } else {
+                self.chunks.remove(0);
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for MockSocket {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.sent
+                .extend_from_slice(buf)
+                .map_err(|_| ErrorKind::OutOfMemory)?;
+            Ok(buf.len())
+        }
+    }
+
+    /// A network stack producing a [MockSocket], recording the remote address
+    /// it was asked to connect to
+    struct MockStack {
+        chunks: &'static [&'static [u8]],
+        closed: bool,
+        remote: Cell<Option<SocketAddr>>,
+    }
+
+    impl MockStack {
+        fn new(chunks: &'static [&'static [u8]], closed: bool) -> Self {
+            Self {
+                chunks,
+                closed,
+                remote: Cell::new(None),
+            }
+        }
+    }
+
+    impl TcpConnect for MockStack {
+        type Error = ErrorKind;
+        type Connection<'a> = MockSocket;
+
+        async fn connect<'a>(
+            &'a self,
+            remote: SocketAddr,
+        ) -> Result<Self::Connection<'a>, Self::Error> {
+            self.remote.set(Some(remote));
+            Ok(MockSocket {
+                chunks: Vec::from_slice(self.chunks).unwrap(),
+                closed: self.closed,
+                sent: Vec::new(),
+            })
+        }
+    }
+
+    fn remote() -> SocketAddr {
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1883))
+    }
+
+    #[tokio::test]
+    async fn connect_uses_stack_to_send_and_receive() {
+        let stack = MockStack::new(&[&[1, 2], &[3]], false);
+        let mut connection = connect(&stack, remote()).await.unwrap();
+        assert_eq!(stack.remote.get(), Some(remote()));
+
+        connection.send(&[4, 5]).await.unwrap();
+        connection.flush().await.unwrap();
+        assert_eq!(connection.inner().sent, [4, 5]);
+
+        // A receive completes using reads of more than one chunk
+        let mut buf = [0; 3];
+        connection.receive(&mut buf).await.unwrap();
+        assert_eq!(buf, [1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn receive_if_ready_returns_false_without_data() {
+        let stack = MockStack::new(&[], false);
+        let mut connection = connect(&stack, remote()).await.unwrap();
+
+        let mut buf = [0; 2];
+        assert!(!connection.receive_if_ready(&mut buf).await.unwrap());
+        assert_eq!(connection.receive_ready(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn receive_if_ready_completes_partial_read() {
+        let stack = MockStack::new(&[&[1], &[2, 3]], false);
+        let mut connection = connect(&stack, remote()).await.unwrap();
+
+        let mut buf = [0; 2];
+        assert!(connection.receive_if_ready(&mut buf).await.unwrap());
+        assert_eq!(buf, [1, 2]);
+
+        // Only the data ready in a single read is returned
+        assert_eq!(connection.receive_ready(&mut buf).await.unwrap(), 1);
+        assert_eq!(buf[0], 3);
+        assert!(!connection.receive_if_ready(&mut buf).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn closed_socket_produces_closed_error() {
+        let stack = MockStack::new(&[&[1]], true);
+        let mut connection = connect(&stack, remote()).await.unwrap();
+
+        let mut buf = [0; 2];
+        assert!(matches!(
+            connection.receive(&mut buf).await,
+            Err(EmbeddedIoError::Closed)
+        ));
+        assert!(matches!(
+            connection.receive_ready(&mut buf).await,
+            Err(EmbeddedIoError::Closed)
+        ));
+        assert!(matches!(
+            connection.receive_available(&mut buf).await,
+            Err(EmbeddedIoError::Closed)
+        ));
+    }
+}
@@ -0,0 +1,1235 @@
+use heapless::Vec;
+
+use crate::{
+    client::{ClientError, ClientReceivedEvent, ConnectionSettings},
+    client_state::{
+        Acknowledgement, ClientState, ClientStateReceiveEvent, ClientStateSubscriptions,
+        PendingCounts,
+    },
+    codec::{
+        mqtt_reader::{MqttBufReader, MqttReader},
+        mqtt_writer::{MqttBufWriter, MqttWriter},
+        write,
+    },
+    data::{
+        encoded_topic::EncodedTopic, property::PublishProperty,
+        quality_of_service::QualityOfService, subscription_options::SubscriptionOptions,
+    },
+    diagnostics::{report_received, ClientDiagnosticEvent, Diagnostics},
+    error::{PacketReadError, PacketWriteError},
+    managed_client::{connect_packet, EventFilter},
+    packets::{
+        connect::{Connect, Will},
+        disconnect::Disconnect,
+        packet::Packet,
+        packets_iter::encoded_packet_len,
+        pingreq::Pingreq,
+        publish_template::PublishTemplate,
+        server_packet::ServerPacket,
+        subscribe::SubscriptionRequest,
+    },
+    timing::{ConnectionTimer, ConnectionTimerSettings},
+    watchdog::Watchdog,
+};
+
+/// Encoded packets waiting to be transmitted
+struct Transmit<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    /// True if a packet has been put since this was last cleared
+    queued: bool,
+}
+
+impl Transmit<'_> {
+    /// Encode a packet after any data already waiting. If the packet doesn't
+    /// fit, nothing is added, and [PacketWriteError::Overflow] is produced.
+    fn put<W>(&mut self, packet: &W) -> Result<(), PacketWriteError>
+    where
+        W: Packet + write::Write,
+    {
+        let mut writer = MqttBufWriter::new(&mut self.buf[self.len..]);
+        writer.put(packet)?;
+        self.len += writer.position();
+        self.queued = true;
+        Ok(())
+    }
+}
+
+/// If `error` means the server has violated the protocol, and the client is
+/// connected, encode a Disconnect with the appropriate reason code (see
+/// [ClientError::disconnect_reason_code]) if there is space, so the server
+/// knows why the connection is closing. The error is returned unaltered.
+fn disconnect_on_protocol_violation<S: ClientState>(
+    client_state: &mut S,
+    tx: &mut Transmit<'_>,
+    error: ClientError,
+) -> ClientError {
+    if let Some(reason_code) = error.disconnect_reason_code() {
+        if client_state.disconnect().is_ok() {
+            let packet: Disconnect<'_, 0> = Disconnect::new(reason_code, Vec::new());
+            let _ = tx.put(&packet);
+        }
+    }
+    error
+}
+
+/// The result of [Engine::handle_bytes]
+#[derive(Debug, PartialEq)]
+pub struct Received<'a, const P: usize, const E: usize> {
+    /// The number of bytes taken from the data provided to [Engine::handle_bytes].
+    /// Any remaining bytes must be provided again in a later call.
+    pub consumed: usize,
+
+    /// The events produced by complete packets received from the server, in order
+    pub events: Vec<ClientReceivedEvent<'a, P>, E>,
+}
+
+/// A "sans-IO" MQTT client, implementing the protocol as a state machine with
+/// no IO, timers or async code, so that it can be driven from any environment,
+/// e.g. an RTIC task, a bare-metal superloop, or an unusual transport.
+///
+/// - Bytes received from the server are passed to [Engine::handle_bytes], which
+///   decodes any complete packets and returns the resulting events.
+/// - Operations like [Engine::publish] encode packets to send, and
+///   [Engine::poll_transmit] provides these as bytes to write to the server.
+/// - The current time, in milliseconds from an arbitrary fixed start point, is
+///   passed to each method that needs it, and [Engine::handle_timeout] should
+///   be called at or after [Engine::next_wakeup], to send pings and detect an
+///   unresponsive server.
+///
+/// Received packets are stored in the receive buffer, so it must be large enough
+/// for the largest expected packet, and operations produce
+/// [PacketWriteError::Overflow] if there is not space in the transmit buffer.
+/// `P` is the maximum number of properties in received packets, and `E`
+/// is the maximum number of events returned by each call to [Engine::handle_bytes].
+///
+/// As for [ClientNoQueue](crate::client::ClientNoQueue), with
+/// [ClientStateNoQueue](crate::client_state::ClientStateNoQueue) only one request
+/// may await a response at a time, see [Engine::waiting_for_responses].
+/// Re-authentication is not supported.
+pub struct Engine<'a, S, const P: usize, const E: usize = 1>
+where
+    S: ClientState,
+{
+    client_state: S,
+    timer: ConnectionTimer,
+
+    rx_buf: &'a mut [u8],
+    /// The number of bytes of data in `rx_buf`
+    rx_len: usize,
+    /// The number of bytes at the start of `rx_buf` holding packets that have
+    /// already been handled. These may still be borrowed by the events from
+    /// the last call to [Engine::handle_bytes], so are only removed on the next call.
+    rx_handled: usize,
+
+    tx: Transmit<'a>,
+    /// The time provided to the most recent call that had one, used as the
+    /// earliest time packets queued since then can have been sent
+    last_now: u64,
+
+    filter: EventFilter,
+    watchdog: Option<&'a mut dyn Watchdog>,
+    diagnostics: Option<&'a mut dyn Diagnostics>,
+}
+
+impl<'a, S, const P: usize, const E: usize> Engine<'a, S, P, E>
+where
+    S: ClientState,
+{
+    /// Create an engine using `rx_buf` for data received from the server,
+    /// and `tx_buf` for data to transmit
+    pub fn new(
+        rx_buf: &'a mut [u8],
+        tx_buf: &'a mut [u8],
+        timer_settings: ConnectionTimerSettings,
+        client_state: S,
+    ) -> Self {
+        Self {
+            client_state,
+            timer: ConnectionTimer::new(timer_settings),
+            rx_buf,
+            rx_len: 0,
+            rx_handled: 0,
+            tx: Transmit {
+                buf: tx_buf,
+                len: 0,
+                queued: false,
+            },
+            last_now: 0,
+            filter: EventFilter::default(),
+            watchdog: None,
+            diagnostics: None,
+        }
+    }
+
+    /// Set the [EventFilter] handling received events, replacing the
+    /// filter configured by the other `with_` methods
+    pub fn with_event_filter(mut self, filter: EventFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// See [EventFilter::with_payload_format_validation]. Disabled by default.
+    pub fn with_payload_format_validation(mut self, validate_payload_format: bool) -> Self {
+        self.filter = self
+            .filter
+            .with_payload_format_validation(validate_payload_format);
+        self
+    }
+
+    /// See [EventFilter::with_accept_downgraded_qos]. Disabled by default.
+    pub fn with_accept_downgraded_qos(mut self, accept_downgraded_qos: bool) -> Self {
+        self.filter = self
+            .filter
+            .with_accept_downgraded_qos(accept_downgraded_qos);
+        self
+    }
+
+    /// See [EventFilter::with_duplicate_detection]. Disabled by default.
+    pub fn with_duplicate_detection(mut self, detect_duplicates: bool) -> Self {
+        self.filter = self.filter.with_duplicate_detection(detect_duplicates);
+        self
+    }
+
+    /// Set whether subscriptions made by [Engine::subscribe] have the NoLocal
+    /// option set, see [EventFilter::with_no_local]. Disabled by default.
+    pub fn with_no_local(mut self, no_local: bool) -> Self {
+        self.filter = self.filter.with_no_local(no_local);
+        self
+    }
+
+    /// See [EventFilter::with_local_echo_suppression]. Disabled by default.
+    pub fn with_local_echo_suppression(mut self, suppress_local_echo: bool) -> Self {
+        self.filter = self.filter.with_local_echo_suppression(suppress_local_echo);
+        self
+    }
+
+    /// The [EventFilter] handling received events, e.g. to keep its
+    /// [EventFilter::recent_publishes] for a later connection resuming the session
+    pub fn event_filter(&self) -> &EventFilter {
+        &self.filter
+    }
+
+    /// Set whether pings are postponed while other packets are being sent, see
+    /// [ConnectionTimer::with_adaptive_keep_alive]. The engine is only told the
+    /// time by calls to [Engine::handle_timeout] and [Engine::handle_bytes], so
+    /// packets are treated as sent at the time of the previous of these calls.
+    /// Calling [Engine::handle_timeout] after queueing packets lets pings be
+    /// postponed for longer. Disabled by default.
+    pub fn with_adaptive_keep_alive(mut self, adaptive_keep_alive: bool) -> Self {
+        self.timer = self.timer.with_adaptive_keep_alive(adaptive_keep_alive);
+        self
+    }
+
+    /// Set a [Watchdog] to be fed each time a packet from the server is
+    /// successfully handled by [Engine::handle_bytes]
+    pub fn with_watchdog(mut self, watchdog: &'a mut dyn Watchdog) -> Self {
+        self.watchdog = Some(watchdog);
+        self
+    }
+
+    /// Set a [Diagnostics] hook to receive a [ClientDiagnosticEvent] for each
+    /// connect attempt, Connack and Suback result, and receive timeout
+    pub fn with_diagnostics(mut self, diagnostics: &'a mut dyn Diagnostics) -> Self {
+        self.diagnostics = Some(diagnostics);
+        self
+    }
+
+    fn report(&mut self, event: ClientDiagnosticEvent) {
+        if let Some(diagnostics) = self.diagnostics.as_deref_mut() {
+            diagnostics.event(event);
+        }
+    }
+
+    /// Record the time given to a method, postponing pings if packets have
+    /// been queued since the previous time
+    fn update_time(&mut self, now: u64) {
+        if self.tx.queued {
+            self.timer.packet_sent(self.last_now);
+            self.tx.queued = false;
+        }
+        self.last_now = now;
+    }
+
+    /// Start connecting to the server with [ConnectionSettings], and an optional
+    /// [Will]. The connection is complete when an [ClientReceivedEvent::Ack] is
+    /// produced by [Engine::handle_bytes].
+    pub fn connect<const W: usize>(
+        &mut self,
+        now: u64,
+        settings: &ConnectionSettings<'_>,
+        will: Option<Will<'_, W>>,
+    ) -> Result<(), ClientError> {
+        settings.check_client_id()?;
+        self.connect_with_packet(now, connect_packet(settings, will))
+    }
+
+    /// Start connecting to the server with a provided [Connect] packet
+    pub fn connect_with_packet<const PP: usize, const W: usize>(
+        &mut self,
+        now: u64,
+        packet: Connect<'_, PP, W>,
+    ) -> Result<(), ClientError> {
+        self.client_state.connect(&packet)?;
+        if let Err(e) = self.tx.put(&packet) {
+            self.client_state.error();
+            return Err(e.into());
+        }
+        self.report(ClientDiagnosticEvent::connect_attempt(&packet));
+        self.rx_len = 0;
+        self.rx_handled = 0;
+        self.filter.connecting(packet.clean_start());
+        self.timer.connect_sent(now);
+        self.tx.queued = false;
+        self.last_now = now;
+        Ok(())
+    }
+
+    /// Disconnect from the server. The Disconnect packet should be transmitted
+    /// before closing the connection.
+    pub fn disconnect(&mut self) -> Result<(), ClientError> {
+        let packet = self.client_state.disconnect()?;
+        if let Err(e) = self.tx.put(&packet) {
+            self.client_state.error();
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Disconnect from the server, setting a new session expiry interval, see
+    /// [Client::disconnect_with_session_expiry_interval](crate::client::Client::disconnect_with_session_expiry_interval)
+    pub fn disconnect_with_session_expiry_interval(
+        &mut self,
+        session_expiry_interval: u32,
+    ) -> Result<(), ClientError> {
+        let packet = self
+            .client_state
+            .disconnect_with_session_expiry_interval(session_expiry_interval)?;
+        if let Err(e) = self.tx.put(&packet) {
+            self.client_state.error();
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Publish a message with given payload to a given topic, with no properties
+    pub fn publish(
+        &mut self,
+        topic_name: &str,
+        payload: &[u8],
+        qos: QualityOfService,
+        retain: bool,
+    ) -> Result<(), ClientError> {
+        self.publish_with_properties::<0>(topic_name, payload, qos, retain, Vec::new())
+    }
+
+    /// Publish a message with given payload to a given topic, with properties
+    pub fn publish_with_properties<'b, const PP: usize>(
+        &mut self,
+        topic_name: &'b str,
+        payload: &'b [u8],
+        qos: QualityOfService,
+        retain: bool,
+        properties: Vec<PublishProperty<'b>, PP>,
+    ) -> Result<(), ClientError> {
+        let packet = self
+            .client_state
+            .publish_with_properties_packet(topic_name, payload, qos, retain, properties)?;
+        self.tx.put(&packet)?;
+        self.client_state.publish_update(&packet)?;
+        self.filter.published(topic_name, payload);
+        Ok(())
+    }
+
+    /// Publish a message to an [EncodedTopic], so that the topic name doesn't
+    /// need to be checked and encoded again
+    pub fn publish_to_encoded_topic<'b, const N: usize, const PP: usize>(
+        &mut self,
+        topic: &'b EncodedTopic<N>,
+        payload: &'b [u8],
+        qos: QualityOfService,
+        retain: bool,
+        properties: Vec<PublishProperty<'b>, PP>,
+    ) -> Result<(), ClientError> {
+        let packet = self
+            .client_state
+            .publish_with_properties_packet(topic.topic_name(), payload, qos, retain, properties)?
+            .with_encoded_topic(topic);
+        self.tx.put(&packet)?;
+        self.client_state.publish_update(&packet)?;
+        self.filter.published(topic.topic_name(), payload);
+        Ok(())
+    }
+
+    /// Publish a message using a [PublishTemplate] for the topic name and
+    /// properties, so that only the payload needs to be encoded
+    pub fn publish_template<const N: usize>(
+        &mut self,
+        template: &PublishTemplate<N>,
+        payload: &[u8],
+    ) -> Result<(), ClientError> {
+        let publish = self.client_state.publish_with_properties_packet::<0>(
+            template.topic_name(),
+            &[],
+            template.qos(),
+            template.retain(),
+            Vec::new(),
+        )?;
+        self.tx.put(&template.packet_for(payload, &publish))?;
+        self.client_state.publish_update(&publish)?;
+        self.filter.published(template.topic_name(), payload);
+        Ok(())
+    }
+
+    /// Subscribe to a topic, with the NoLocal option if enabled, see
+    /// [Engine::with_no_local]
+    pub fn subscribe(
+        &mut self,
+        topic_name: &str,
+        maximum_qos: QualityOfService,
+    ) -> Result<(), ClientError> {
+        let packet = self.client_state.subscribe_requests_packet::<0>(
+            self.filter.subscription_request(topic_name, maximum_qos),
+            Vec::new(),
+        )?;
+        self.tx.put(&packet)?;
+        self.client_state.subscribe_update(&packet)?;
+        Ok(())
+    }
+
+    /// Subscribe to a topic with [SubscriptionOptions]
+    pub fn subscribe_with_options(
+        &mut self,
+        topic_name: &str,
+        options: SubscriptionOptions,
+    ) -> Result<(), ClientError> {
+        let packet = self.client_state.subscribe_requests_packet::<0>(
+            SubscriptionRequest {
+                topic_name,
+                options,
+            },
+            Vec::new(),
+        )?;
+        self.tx.put(&packet)?;
+        self.client_state.subscribe_update(&packet)?;
+        Ok(())
+    }
+
+    /// Unsubscribe from a topic
+    pub fn unsubscribe(&mut self, topic_name: &str) -> Result<(), ClientError> {
+        let packet = self.client_state.unsubscribe_packet(topic_name)?;
+        self.tx.put(&packet)?;
+        self.client_state.unsubscribe_update(&packet)?;
+        Ok(())
+    }
+
+    /// Send pings as needed to keep the connection alive, and produce
+    /// [ClientError::ReceiveTimeoutServerUnresponsive] if the server has not
+    /// responded in time. Call this at or after [Engine::next_wakeup].
+    pub fn handle_timeout(&mut self, now: u64) -> Result<(), ClientError> {
+        self.update_time(now);
+        if let Err(e) = self.timer.check_receive_timeout(now) {
+            self.report(ClientDiagnosticEvent::Timeout {
+                server_unresponsive: true,
+            });
+            return Err(e);
+        }
+        if self.timer.ping_due(now) {
+            if self.client_state.pending_ping_count() > 0 {
+                self.timer.ping_deferred(now);
+            } else {
+                self.tx.put(&Pingreq::default())?;
+                self.client_state.send_ping()?;
+                self.timer.ping_sent(now);
+            }
+        }
+        Ok(())
+    }
+
+    /// The time at which [Engine::handle_timeout] should next be called,
+    /// or None if no timeout is active
+    pub fn next_wakeup(&self) -> Option<u64> {
+        self.timer.next_wakeup()
+    }
+
+    /// Copy data waiting to be transmitted to the server into `buf`, returning
+    /// the number of bytes copied. Repeat until this returns 0 to transmit
+    /// all waiting data.
+    pub fn poll_transmit(&mut self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.tx.len);
+        buf[..len].copy_from_slice(&self.tx.buf[..len]);
+        self.tx.buf.copy_within(len..self.tx.len, 0);
+        self.tx.len -= len;
+        len
+    }
+
+    /// The number of bytes waiting to be transmitted, see [Engine::poll_transmit]
+    pub fn transmit_len(&self) -> usize {
+        self.tx.len
+    }
+
+    /// Handle bytes received from the server at time `now`. As many bytes as
+    /// will fit are added to the receive buffer, and then each complete packet
+    /// received is handled, producing at most `E` events. Any response needed
+    /// (e.g. a Puback) is encoded to be transmitted.
+    /// Packets are left in the receive buffer if `E` events have already been
+    /// produced, or there is not enough space to transmit a response, so this
+    /// should be called again (with any bytes that were not consumed, or an
+    /// empty slice) while the last call produced `E` events, or
+    /// [Received::consumed] was less than the length of `data`.
+    /// On an error caused by the server violating the protocol, a Disconnect
+    /// packet with a reason code is encoded to be transmitted, see
+    /// [ClientError::disconnect_reason_code], and the connection should then
+    /// be closed.
+    pub fn handle_bytes(
+        &mut self,
+        now: u64,
+        data: &[u8],
+    ) -> Result<Received<'_, P, E>, ClientError> {
+        self.update_time(now);
+
+        // Remove packets handled by the previous call
+        self.rx_buf.copy_within(self.rx_handled..self.rx_len, 0);
+        self.rx_len -= self.rx_handled;
+        self.rx_handled = 0;
+
+        let consumed = data.len().min(self.rx_buf.len() - self.rx_len);
+        self.rx_buf[self.rx_len..self.rx_len + consumed].copy_from_slice(&data[..consumed]);
+        self.rx_len += consumed;
+
+        let client_state = &mut self.client_state;
+        let tx = &mut self.tx;
+        let timer = &mut self.timer;
+        let rx_buf = &*self.rx_buf;
+        let rx_len = self.rx_len;
+        let filter = &mut self.filter;
+        let mut watchdog = self.watchdog.as_deref_mut();
+        let mut diagnostics = self.diagnostics.as_deref_mut();
+        let mut position = 0;
+        let mut events = Vec::new();
+
+        while !events.is_full() {
+            let len = match encoded_packet_len(&rx_buf[position..rx_len]) {
+                Ok(Some(len)) if len > rx_buf.len() => {
+                    return Err(disconnect_on_protocol_violation(
+                        client_state,
+                        tx,
+                        PacketReadError::PacketTooLargeForBuffer.into(),
+                    ))
+                }
+                Ok(Some(len)) if position + len <= rx_len => len,
+                Ok(_) => break,
+                Err(e) => return Err(disconnect_on_protocol_violation(client_state, tx, e.into())),
+            };
+
+            let packet: ServerPacket<'_, P, 0> =
+                match MqttBufReader::new(&rx_buf[position..position + len]).get() {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        return Err(disconnect_on_protocol_violation(
+                            client_state,
+                            tx,
+                            ClientError::PacketRead(e),
+                        ))
+                    }
+                };
+            timer.packet_received(now, packet.packet_type());
+
+            // Leave the packet to be handled later if we can't transmit the response
+            let response = client_state
+                .receive_produce_response(&packet)
+                .map(|response| response.map(|response| tx.put(&response)));
+            match response {
+                Ok(None) | Ok(Some(Ok(()))) => {}
+                Ok(Some(Err(PacketWriteError::Overflow))) => break,
+                Ok(Some(Err(e))) => {
+                    return Err(disconnect_on_protocol_violation(client_state, tx, e.into()))
+                }
+                Err(e) => return Err(disconnect_on_protocol_violation(client_state, tx, e.into())),
+            }
+
+            if let Some(diagnostics) = diagnostics.as_deref_mut() {
+                report_received(diagnostics, &packet);
+            }
+            let event = client_state
+                .receive(packet)
+                .map_err(ClientError::from)
+                .and_then(|event| {
+                    // Pings start once connected
+                    if let ClientStateReceiveEvent::Ack(Acknowledgement::Connack {
+                        session_present,
+                    }) = event
+                    {
+                        filter.connected(session_present);
+                        timer.connected();
+                    }
+                    filter.receive(event)
+                });
+            match event {
+                Ok(event) => {
+                    // Duplicates and echoes of our own publishes produce no event
+                    if let Some(event) = event {
+                        // Can't fail, we checked events is not full
+                        let _ = events.push(event);
+                    }
+                    if let Some(watchdog) = watchdog.as_deref_mut() {
+                        watchdog.feed();
+                    }
+                }
+                Err(e) => return Err(disconnect_on_protocol_violation(client_state, tx, e)),
+            }
+            position += len;
+        }
+
+        self.rx_handled = position;
+        Ok(Received { consumed, events })
+    }
+
+    /// True if the engine is waiting for a response from the server. If this
+    /// is true, bytes from the server must be handled until it becomes false,
+    /// before any more requests needing a response (subscribe, unsubscribe,
+    /// publish with qos 1) can be made.
+    pub fn waiting_for_responses(&self) -> bool {
+        self.client_state.waiting_for_responses()
+    }
+
+    /// The number of requests sent to the server that are still awaiting
+    /// a response
+    pub fn pending_counts(&self) -> PendingCounts {
+        self.client_state.pending_counts()
+    }
+
+    /// The subscriptions the client believes are active, or None if not connected,
+    /// see [ClientState::subscriptions]
+    pub fn subscriptions(&self) -> Option<&ClientStateSubscriptions> {
+        self.client_state.subscriptions()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client_id::ClientIdError,
+        client_state::{ClientStateError, ClientStateNoQueue},
+        data::{
+            packet_identifier::PacketIdentifier,
+            reason_code::{ConnectReasonCode, PublishReasonCode, SubscribeReasonCode},
+        },
+    };
+
+    const SETTINGS: ConnectionTimerSettings = ConnectionTimerSettings {
+        receive_timeout_millis: 10_000,
+        ping_interval_millis: 2_000,
+        ping_retry_delay_millis: 100,
+    };
+
+    fn transmitted<S: ClientState, const P: usize, const E: usize>(
+        engine: &mut Engine<'_, S, P, E>,
+    ) -> std::vec::Vec<u8> {
+        let mut data = std::vec::Vec::new();
+        let mut buf = [0; 8];
+        loop {
+            let len = engine.poll_transmit(&mut buf);
+            if len == 0 {
+                return data;
+            }
+            data.extend_from_slice(&buf[..len]);
+        }
+    }
+
+    #[test]
+    fn engine_connects_pings_and_receives_publishes() {
+        let mut rx_buf = [0; 64];
+        let mut tx_buf = [0; 64];
+        let mut engine: Engine<'_, ClientStateNoQueue, 16, 4> = Engine::new(
+            &mut rx_buf,
+            &mut tx_buf,
+            SETTINGS,
+            ClientStateNoQueue::new(),
+        );
+
+        engine
+            .connect::<0>(0, &ConnectionSettings::unauthenticated("a"), None)
+            .unwrap();
+        assert_eq!(transmitted(&mut engine)[0], 0x10);
+        assert!(engine.waiting_for_responses());
+        assert_eq!(engine.next_wakeup(), Some(10_000));
+
+        // Connack split across two calls
+        {
+            let received = engine.handle_bytes(10, &[0x20, 0x03]).unwrap();
+            assert_eq!(received.consumed, 2);
+            assert!(received.events.is_empty());
+        }
+        {
+            let received = engine.handle_bytes(20, &[0x00, 0x00, 0x00]).unwrap();
+            assert_eq!(
+                received.events.as_slice(),
+                &[ClientReceivedEvent::Ack(Acknowledgement::Connack {
+                    session_present: false
+                })]
+            );
+        }
+        assert!(!engine.waiting_for_responses());
+        assert_eq!(engine.next_wakeup(), Some(2_000));
+
+        // Ping when due
+        engine.handle_timeout(1_000).unwrap();
+        assert!(transmitted(&mut engine).is_empty());
+        engine.handle_timeout(2_001).unwrap();
+        assert_eq!(transmitted(&mut engine), [0xc0, 0x00]);
+
+        // Pingresp, then a qos 1 publish to "t" with payload "p", needing a puback
+        {
+            let received = engine
+                .handle_bytes(
+                    2_100,
+                    &[
+                        0xd0, 0x00, 0x32, 0x07, 0x00, 0x01, b't', 0x00, 0x05, 0x00, b'p',
+                    ],
+                )
+                .unwrap();
+            assert_eq!(received.events.len(), 2);
+            assert_eq!(
+                received.events[0],
+                ClientReceivedEvent::Ack(Acknowledgement::Pingresp)
+            );
+            let ClientReceivedEvent::ApplicationMessage(message) = &received.events[1] else {
+                panic!("expected application message");
+            };
+            assert_eq!(message.topic_name, "t");
+            assert_eq!(message.payload, b"p");
+        }
+        assert_eq!(transmitted(&mut engine), [0x40, 0x02, 0x00, 0x05]);
+    }
+
+    #[test]
+    fn engine_disconnects_on_protocol_violation() {
+        let mut rx_buf = [0; 64];
+        let mut tx_buf = [0; 64];
+        let mut engine: Engine<'_, ClientStateNoQueue, 16> = Engine::new(
+            &mut rx_buf,
+            &mut tx_buf,
+            SETTINGS,
+            ClientStateNoQueue::new(),
+        );
+
+        engine
+            .connect::<0>(0, &ConnectionSettings::unauthenticated("a"), None)
+            .unwrap();
+        engine
+            .handle_bytes(10, &[0x20, 0x03, 0x00, 0x00, 0x00])
+            .unwrap();
+        transmitted(&mut engine);
+
+        // Puback with no matching publish
+        assert!(engine.handle_bytes(20, &[0x40, 0x02, 0x00, 0x01]).is_err());
+        assert_eq!(transmitted(&mut engine), [0xe0, 0x01, 0x82]);
+    }
+
+    #[test]
+    fn engine_disconnects_on_malformed_packet() {
+        let mut rx_buf = [0; 64];
+        let mut tx_buf = [0; 64];
+        let mut engine: Engine<'_, ClientStateNoQueue, 16> = Engine::new(
+            &mut rx_buf,
+            &mut tx_buf,
+            SETTINGS,
+            ClientStateNoQueue::new(),
+        );
+
+        engine
+            .connect::<0>(0, &ConnectionSettings::unauthenticated("a"), None)
+            .unwrap();
+        engine
+            .handle_bytes(10, &[0x20, 0x03, 0x00, 0x00, 0x00])
+            .unwrap();
+        transmitted(&mut engine);
+
+        // Publish with qos bits set to 3
+        assert_eq!(
+            engine.handle_bytes(20, &[0x36, 0x05, 0x00, 0x01, b't', 0x00, b'p']),
+            Err(ClientError::PacketRead(PacketReadError::InvalidPacketType))
+        );
+        assert_eq!(transmitted(&mut engine), [0xe0, 0x01, 0x81]);
+    }
+
+    #[test]
+    fn engine_disconnects_on_packet_too_large_for_buffer() {
+        let mut rx_buf = [0; 16];
+        let mut tx_buf = [0; 64];
+        let mut engine: Engine<'_, ClientStateNoQueue, 16> = Engine::new(
+            &mut rx_buf,
+            &mut tx_buf,
+            SETTINGS,
+            ClientStateNoQueue::new(),
+        );
+
+        engine
+            .connect::<0>(0, &ConnectionSettings::unauthenticated("a"), None)
+            .unwrap();
+        engine
+            .handle_bytes(10, &[0x20, 0x03, 0x00, 0x00, 0x00])
+            .unwrap();
+        transmitted(&mut engine);
+
+        // Publish with a remaining length of 64, more than the buffer holds
+        assert_eq!(
+            engine.handle_bytes(20, &[0x30, 0x40, 0x00, 0x01, b't']),
+            Err(ClientError::PacketRead(
+                PacketReadError::PacketTooLargeForBuffer
+            ))
+        );
+        assert_eq!(transmitted(&mut engine), [0xe0, 0x01, 0x95]);
+    }
+
+    #[cfg(all(feature = "client-codec", feature = "server-codec"))]
+    #[test]
+    fn engine_connects_with_settings_properties() {
+        use crate::{
+            codec::mqtt_reader::{MqttBufReader, MqttReader},
+            data::property::ConnectProperty,
+            packets::connect::Connect,
+        };
+
+        let mut rx_buf = [0; 64];
+        let mut tx_buf = [0; 64];
+        let mut engine: Engine<'_, ClientStateNoQueue, 16> = Engine::new(
+            &mut rx_buf,
+            &mut tx_buf,
+            SETTINGS,
+            ClientStateNoQueue::new(),
+        );
+
+        let settings = ConnectionSettings::unauthenticated("a")
+            .with_session_expiry_interval(3600)
+            .with_receive_maximum(core::num::NonZeroU16::new(8).unwrap())
+            .with_request_problem_information(false);
+        engine.connect::<0>(0, &settings, None).unwrap();
+        let data = transmitted(&mut engine);
+        let connect: Connect<'_, 4, 0> = MqttBufReader::new(&data).get().unwrap();
+        assert_eq!(
+            connect.properties.as_slice(),
+            &[
+                ConnectProperty::TopicAliasMaximum(0.into()),
+                ConnectProperty::SessionExpiryInterval(3600.into()),
+                ConnectProperty::ReceiveMaximum(8.into()),
+                ConnectProperty::RequestProblemInformation(0.into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn engine_disconnects_with_session_expiry_interval() {
+        let mut rx_buf = [0; 64];
+        let mut tx_buf = [0; 64];
+        let mut engine: Engine<'_, ClientStateNoQueue, 16> = Engine::new(
+            &mut rx_buf,
+            &mut tx_buf,
+            SETTINGS,
+            ClientStateNoQueue::new(),
+        );
+
+        engine
+            .connect::<0>(0, &ConnectionSettings::unauthenticated("a"), None)
+            .unwrap();
+        engine
+            .handle_bytes(10, &[0x20, 0x03, 0x00, 0x00, 0x00])
+            .unwrap();
+        transmitted(&mut engine);
+
+        // Session expiry interval can't be extended when connected without one
+        assert_eq!(
+            engine.disconnect_with_session_expiry_interval(60),
+            Err(ClientError::ClientState(
+                ClientStateError::SessionExpiryIntervalWasZero
+            ))
+        );
+        engine.disconnect_with_session_expiry_interval(0).unwrap();
+        assert_eq!(
+            transmitted(&mut engine),
+            [0xe0, 0x07, 0x00, 0x05, 0x11, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn engine_publishes_from_template() {
+        let mut rx_buf = [0; 64];
+        let mut tx_buf = [0; 64];
+        let mut engine: Engine<'_, ClientStateNoQueue, 16> = Engine::new(
+            &mut rx_buf,
+            &mut tx_buf,
+            SETTINGS,
+            ClientStateNoQueue::new(),
+        );
+
+        engine
+            .connect::<0>(0, &ConnectionSettings::unauthenticated("a"), None)
+            .unwrap();
+        engine
+            .handle_bytes(10, &[0x20, 0x03, 0x00, 0x00, 0x00])
+            .unwrap();
+        transmitted(&mut engine);
+
+        let template =
+            PublishTemplate::<8>::new::<0>("t", QualityOfService::Qos1, false, &Vec::new())
+                .unwrap();
+        engine.publish_template(&template, b"p").unwrap();
+        assert_eq!(
+            transmitted(&mut engine),
+            [0x32, 0x07, 0x00, 0x01, b't', 0x00, 0x01, 0x00, b'p']
+        );
+        assert_eq!(engine.pending_counts().publishes, 1);
+    }
+
+    #[test]
+    fn engine_postpones_pings_with_adaptive_keep_alive() {
+        let mut rx_buf = [0; 64];
+        let mut tx_buf = [0; 64];
+        let mut engine: Engine<'_, ClientStateNoQueue, 16> = Engine::new(
+            &mut rx_buf,
+            &mut tx_buf,
+            SETTINGS,
+            ClientStateNoQueue::new(),
+        )
+        .with_adaptive_keep_alive(true);
+
+        engine
+            .connect::<0>(0, &ConnectionSettings::unauthenticated("a"), None)
+            .unwrap();
+        engine
+            .handle_bytes(10, &[0x20, 0x03, 0x00, 0x00, 0x00])
+            .unwrap();
+        transmitted(&mut engine);
+        assert_eq!(engine.next_wakeup(), Some(2_000));
+
+        engine.handle_timeout(1_500).unwrap();
+        engine
+            .publish("t", b"p", QualityOfService::Qos0, false)
+            .unwrap();
+        transmitted(&mut engine);
+
+        // The publish was queued after the call at 1_500
+        engine.handle_timeout(1_600).unwrap();
+        assert_eq!(engine.next_wakeup(), Some(3_500));
+        engine.handle_timeout(2_100).unwrap();
+        assert!(transmitted(&mut engine).is_empty());
+    }
+
+    #[test]
+    fn engine_feeds_watchdog_for_handled_packets() {
+        let mut rx_buf = [0; 64];
+        let mut tx_buf = [0; 64];
+        let mut feeds = 0;
+        let mut watchdog = || feeds += 1;
+        let mut engine: Engine<'_, ClientStateNoQueue, 16> = Engine::new(
+            &mut rx_buf,
+            &mut tx_buf,
+            SETTINGS,
+            ClientStateNoQueue::new(),
+        )
+        .with_watchdog(&mut watchdog);
+
+        engine
+            .connect::<0>(0, &ConnectionSettings::unauthenticated("a"), None)
+            .unwrap();
+        engine
+            .handle_bytes(10, &[0x20, 0x03, 0x00, 0x00, 0x00])
+            .unwrap();
+        // Puback with no matching publish is not handled
+        assert!(engine.handle_bytes(20, &[0x40, 0x02, 0x00, 0x01]).is_err());
+        drop(engine);
+        assert_eq!(feeds, 1);
+    }
+
+    #[test]
+    fn engine_rejects_invalid_client_id_before_connecting() {
+        let mut rx_buf = [0; 64];
+        let mut tx_buf = [0; 64];
+        let mut engine: Engine<'_, ClientStateNoQueue, 16> = Engine::new(
+            &mut rx_buf,
+            &mut tx_buf,
+            SETTINGS,
+            ClientStateNoQueue::new(),
+        );
+
+        let settings =
+            ConnectionSettings::unauthenticated("sensor-01").with_validate_client_id(true);
+        assert_eq!(
+            engine.connect::<0>(0, &settings, None),
+            Err(ClientError::InvalidClientId(
+                ClientIdError::InvalidCharacter
+            ))
+        );
+        assert_eq!(engine.transmit_len(), 0);
+    }
+
+    #[test]
+    fn engine_acks_identify_acknowledged_publish() {
+        let mut rx_buf = [0; 64];
+        let mut tx_buf = [0; 64];
+        let mut engine: Engine<'_, ClientStateNoQueue, 16> = Engine::new(
+            &mut rx_buf,
+            &mut tx_buf,
+            SETTINGS,
+            ClientStateNoQueue::new(),
+        );
+
+        engine
+            .connect::<0>(0, &ConnectionSettings::unauthenticated("a"), None)
+            .unwrap();
+        engine
+            .handle_bytes(10, &[0x20, 0x03, 0x00, 0x00, 0x00])
+            .unwrap();
+        transmitted(&mut engine);
+        engine
+            .publish("t", b"p", QualityOfService::Qos1, false)
+            .unwrap();
+        let publish = transmitted(&mut engine);
+
+        let received = engine
+            .handle_bytes(20, &[0x40, 0x02, publish[5], publish[6]])
+            .unwrap();
+        assert_eq!(
+            received.events.as_slice(),
+            &[ClientReceivedEvent::Ack(Acknowledgement::Puback {
+                packet_identifier: PacketIdentifier(u16::from_be_bytes([publish[5], publish[6]])),
+                reason_code: PublishReasonCode::Success,
+            })]
+        );
+    }
+
+    #[test]
+    fn engine_accepts_downgraded_subscription_qos() {
+        let mut rx_buf = [0; 64];
+        let mut tx_buf = [0; 64];
+        let mut engine: Engine<'_, ClientStateNoQueue, 16> = Engine::new(
+            &mut rx_buf,
+            &mut tx_buf,
+            SETTINGS,
+            ClientStateNoQueue::new(),
+        )
+        .with_accept_downgraded_qos(true);
+
+        engine
+            .connect::<0>(0, &ConnectionSettings::unauthenticated("a"), None)
+            .unwrap();
+        engine
+            .handle_bytes(10, &[0x20, 0x03, 0x00, 0x00, 0x00])
+            .unwrap();
+        transmitted(&mut engine);
+        engine.subscribe("a/b", QualityOfService::Qos1).unwrap();
+        let subscribe = transmitted(&mut engine);
+
+        // Suback for the same packet identifier, granting qos 0
+        {
+            let suback = [0x90, 0x04, subscribe[2], subscribe[3], 0x00, 0x00];
+            let received = engine.handle_bytes(20, &suback).unwrap();
+            let packet_identifier =
+                PacketIdentifier(u16::from_be_bytes([subscribe[2], subscribe[3]]));
+            assert_eq!(
+                received.events.as_slice(),
+                &[ClientReceivedEvent::Ack(Acknowledgement::Suback {
+                    packet_identifier
+                })]
+            );
+        }
+        let subscription = engine.subscriptions().unwrap().subscription("a/b");
+        assert_eq!(
+            subscription.and_then(|s| s.granted_qos()),
+            Some(QualityOfService::Qos0)
+        );
+    }
+
+    #[test]
+    fn engine_keeps_subscriptions_when_reconnecting_with_session_present() {
+        let mut rx_buf = [0; 64];
+        let mut tx_buf = [0; 64];
+        let mut engine: Engine<'_, ClientStateNoQueue, 16> = Engine::new(
+            &mut rx_buf,
+            &mut tx_buf,
+            SETTINGS,
+            ClientStateNoQueue::new(),
+        );
+        let settings = ConnectionSettings::unauthenticated("a")
+            .with_clean_start(false)
+            .with_session_expiry_interval(60);
+
+        engine.connect::<0>(0, &settings, None).unwrap();
+        engine
+            .handle_bytes(10, &[0x20, 0x03, 0x00, 0x00, 0x00])
+            .unwrap();
+        transmitted(&mut engine);
+        engine.subscribe("t", QualityOfService::Qos0).unwrap();
+        let subscribe = transmitted(&mut engine);
+        engine
+            .handle_bytes(20, &[0x90, 0x04, subscribe[2], subscribe[3], 0x00, 0x00])
+            .unwrap();
+        assert!(engine.subscriptions().unwrap().is_subscribed("t"));
+
+        // Reconnect, and the server has the session
+        engine.disconnect().unwrap();
+        assert!(engine.subscriptions().is_none());
+        engine.connect::<0>(30, &settings, None).unwrap();
+        engine
+            .handle_bytes(40, &[0x20, 0x03, 0x01, 0x00, 0x00])
+            .unwrap();
+        assert!(engine.subscriptions().unwrap().is_subscribed("t"));
+
+        // Reconnect, and the server has no session
+        engine.disconnect().unwrap();
+        engine.connect::<0>(50, &settings, None).unwrap();
+        engine
+            .handle_bytes(60, &[0x20, 0x03, 0x00, 0x00, 0x00])
+            .unwrap();
+        assert!(!engine.subscriptions().unwrap().is_subscribed("t"));
+    }
+
+    #[test]
+    fn engine_sets_no_local_and_suppresses_local_echo() {
+        let mut rx_buf = [0; 64];
+        let mut tx_buf = [0; 64];
+        let mut engine: Engine<'_, ClientStateNoQueue, 16, 4> = Engine::new(
+            &mut rx_buf,
+            &mut tx_buf,
+            SETTINGS,
+            ClientStateNoQueue::new(),
+        )
+        .with_no_local(true)
+        .with_local_echo_suppression(true);
+
+        engine
+            .connect::<0>(0, &ConnectionSettings::unauthenticated("a"), None)
+            .unwrap();
+        engine
+            .handle_bytes(10, &[0x20, 0x03, 0x00, 0x00, 0x00])
+            .unwrap();
+        transmitted(&mut engine);
+
+        // Subscription options are the last byte, with NoLocal bit 2
+        engine.subscribe("t", QualityOfService::Qos0).unwrap();
+        let subscribe = transmitted(&mut engine);
+        assert_eq!(subscribe.last(), Some(&0x04));
+        engine
+            .handle_bytes(20, &[0x90, 0x04, subscribe[2], subscribe[3], 0x00, 0x00])
+            .unwrap();
+
+        // Our publish echoed back is dropped, but the same message again is not
+        engine
+            .publish("t", b"p", QualityOfService::Qos0, false)
+            .unwrap();
+        let publish = [0x30, 0x05, 0x00, 0x01, b't', 0x00, b'p'];
+        {
+            let received = engine.handle_bytes(30, &publish).unwrap();
+            assert!(received.events.is_empty());
+        }
+        {
+            let received = engine.handle_bytes(40, &publish).unwrap();
+            assert_eq!(received.events.len(), 1);
+        }
+    }
+
+    #[test]
+    fn engine_drops_redelivered_duplicate_publishes() {
+        let mut rx_buf = [0; 64];
+        let mut tx_buf = [0; 64];
+        let mut engine: Engine<'_, ClientStateNoQueue, 16, 4> = Engine::new(
+            &mut rx_buf,
+            &mut tx_buf,
+            SETTINGS,
+            ClientStateNoQueue::new(),
+        )
+        .with_duplicate_detection(true);
+
+        engine
+            .connect::<0>(0, &ConnectionSettings::unauthenticated("a"), None)
+            .unwrap();
+        engine
+            .handle_bytes(10, &[0x20, 0x03, 0x00, 0x00, 0x00])
+            .unwrap();
+        transmitted(&mut engine);
+
+        // Qos 1 publish with packet identifier 5, then redelivered with DUP set
+        {
+            let received = engine
+                .handle_bytes(20, &[0x32, 0x07, 0x00, 0x01, b't', 0x00, 0x05, 0x00, b'p'])
+                .unwrap();
+            assert_eq!(received.events.len(), 1);
+        }
+        assert_eq!(transmitted(&mut engine), [0x40, 0x02, 0x00, 0x05]);
+        {
+            let received = engine
+                .handle_bytes(30, &[0x3a, 0x07, 0x00, 0x01, b't', 0x00, 0x05, 0x00, b'p'])
+                .unwrap();
+            assert!(received.events.is_empty());
+        }
+        // The duplicate is still acknowledged
+        assert_eq!(transmitted(&mut engine), [0x40, 0x02, 0x00, 0x05]);
+        assert!(engine
+            .event_filter()
+            .recent_publishes()
+            .contains(PacketIdentifier(5)));
+    }
+
+    #[test]
+    fn engine_reports_diagnostic_events() {
+        let mut events = std::vec::Vec::new();
+        let mut diagnostics = |event: ClientDiagnosticEvent| events.push(event);
+        let mut rx_buf = [0; 64];
+        let mut tx_buf = [0; 64];
+        let mut engine: Engine<'_, ClientStateNoQueue, 16> = Engine::new(
+            &mut rx_buf,
+            &mut tx_buf,
+            SETTINGS,
+            ClientStateNoQueue::new(),
+        )
+        .with_diagnostics(&mut diagnostics);
+
+        engine
+            .connect::<0>(0, &ConnectionSettings::unauthenticated("a"), None)
+            .unwrap();
+        engine
+            .handle_bytes(10, &[0x20, 0x03, 0x00, 0x00, 0x00])
+            .unwrap();
+        transmitted(&mut engine);
+
+        engine.subscribe("t", QualityOfService::Qos1).unwrap();
+        let subscribe = transmitted(&mut engine);
+        engine
+            .handle_bytes(20, &[0x90, 0x04, subscribe[2], subscribe[3], 0x00, 0x01])
+            .unwrap();
+
+        assert_eq!(
+            engine.handle_timeout(10_100),
+            Err(ClientError::ReceiveTimeoutServerUnresponsive)
+        );
+        drop(engine);
+
+        assert_eq!(
+            events,
+            [
+                ClientDiagnosticEvent::ConnectAttempt {
+                    clean_start: true,
+                    keep_alive: 60,
+                },
+                ClientDiagnosticEvent::ConnackResult {
+                    reason_code: ConnectReasonCode::Success,
+                    session_present: false,
+                },
+                ClientDiagnosticEvent::SubackResult {
+                    packet_identifier: PacketIdentifier(u16::from_be_bytes([
+                        subscribe[2],
+                        subscribe[3]
+                    ])),
+                    reason_code: SubscribeReasonCode::GrantedQos1,
+                },
+                ClientDiagnosticEvent::Timeout {
+                    server_unresponsive: true,
+                },
+            ]
+        );
+        assert!(events[3].is_warning());
+        assert!(!events[2].is_warning());
+    }
+}
@@ -1,7 +1,83 @@
-use core::{
-    fmt::{Display, Formatter},
-    str::Utf8Error,
-};
+#[cfg(feature = "display")]
+use core::fmt::{Display, Formatter};
+use core::str::Utf8Error;
+
+use crate::data::{packet_type::PacketType, reason_code::DisconnectReasonCode};
+
+/// The kind of failure of the transport underlying a
+/// [Connection](crate::packet_client::Connection), so that applications can
+/// react appropriately, e.g. reconnecting immediately after the server closes
+/// the connection, but backing off after a timeout
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TransportErrorKind {
+    /// The connection was closed by the other end
+    Closed,
+
+    /// The connection was reset or aborted
+    Reset,
+
+    /// The transport timed out
+    TimedOut,
+
+    /// Any other failure
+    Other,
+}
+
+#[cfg(feature = "display")]
+impl Display for TransportErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Closed => write!(f, "Closed"),
+            Self::Reset => write!(f, "Reset"),
+            Self::TimedOut => write!(f, "TimedOut"),
+            Self::Other => write!(f, "Other"),
+        }
+    }
+}
+
+/// An error produced by the transport underlying a
+/// [Connection](crate::packet_client::Connection), which can be classified
+/// by [TransportErrorKind]
+pub trait TransportError: core::fmt::Debug {
+    fn kind(&self) -> TransportErrorKind;
+}
+
+impl TransportError for TransportErrorKind {
+    fn kind(&self) -> TransportErrorKind {
+        *self
+    }
+}
+
+#[cfg(feature = "std")]
+impl TransportError for std::io::Error {
+    fn kind(&self) -> TransportErrorKind {
+        use std::io::ErrorKind;
+        match self.kind() {
+            ErrorKind::UnexpectedEof | ErrorKind::BrokenPipe | ErrorKind::NotConnected => {
+                TransportErrorKind::Closed
+            }
+            ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted => TransportErrorKind::Reset,
+            // A read timeout on a blocking socket may produce WouldBlock
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => TransportErrorKind::TimedOut,
+            _ => TransportErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl TransportErrorKind {
+    /// Classify an error from `embedded-io`
+    pub fn from_embedded_io<E: embedded_io::Error>(error: &E) -> Self {
+        use embedded_io::ErrorKind;
+        match error.kind() {
+            ErrorKind::BrokenPipe | ErrorKind::NotConnected => Self::Closed,
+            ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted => Self::Reset,
+            ErrorKind::TimedOut => Self::TimedOut,
+            _ => Self::Other,
+        }
+    }
+}
 
 /// An error occurring while attempting to read/receive/decode an MQTT packet
 /// Can occur at multiple levels:
@@ -45,7 +121,11 @@ pub enum PacketReadError {
     /// Data was expected to be of a known packet type, but the first header byte did not match this
     IncorrectPacketType,
 
-    /// Data contained an unknown reason code
+    /// Data contained an unknown reason code. Note that reason codes in packets
+    /// decode to an `Unknown` value rather than producing this error, it is
+    /// only produced by explicit conversions, e.g. [ConnectReasonCode::try_from]
+    ///
+    /// [ConnectReasonCode::try_from]: crate::data::reason_code::ConnectReasonCode
     UnknownReasonCode,
 
     /// Data contained a u8 value that was expected to be 0 (false) or 1 (true), but was some other value
@@ -72,8 +152,9 @@ pub enum PacketReadError {
     /// decoded to a [PacketType], or contained invalid values for the "reserved" bits.
     InvalidPacketType,
 
-    /// Failure to receive via connection
-    ConnectionReceive,
+    /// Failure to receive via connection, with the kind of failure reported by
+    /// the [Connection](crate::packet_client::Connection)
+    Transport(TransportErrorKind),
 
     /// Packet was too large to place in provided buffer
     PacketTooLargeForBuffer,
@@ -116,6 +197,24 @@ pub enum PacketReadError {
 
     // Subscription options u8 values must not have reserved bits set to non-zero [MQTT-3.8.3-5]
     SubscriptionOptionsReservedBitsNonZero,
+
+    /// A property that may only be included once in a packet was included more than once
+    /// (Protocol Error). Only checked when the `validate-properties` feature is enabled.
+    DuplicateProperty,
+
+    /// A [crate::packets::publish::PayloadSink] failed to accept part of a streamed payload
+    PayloadSink,
+
+    /// A packet that only a server may receive (Connect, Subscribe, Unsubscribe or Pingreq)
+    /// was decoded as a [crate::packets::server_packet::ServerPacket] (Protocol Error)
+    ServerOnlyPacketType,
+
+    /// The packet could not be decoded, in a way not covered by a more specific error.
+    /// E.g. when skipping unexpected properties (see
+    /// [PropertiesIter::with_skip_unknown](crate::data::property::PropertiesIter::with_skip_unknown)),
+    /// a property identifier not defined by MQTT v5 has no known length, so the rest of the
+    /// packet can't be decoded (Malformed Packet)
+    MalformedPacket,
 }
 
 #[cfg(feature = "defmt")]
@@ -136,7 +235,7 @@ impl defmt::Format for PacketReadError {
             Self::UnsupportedProtocolVersion => defmt::write!(f, "UnsupportedProtocolVersion"),
             Self::TooManyRequests => defmt::write!(f, "TooManyRequests"),
             Self::InvalidPacketType => defmt::write!(f, "InvalidPacketType"),
-            Self::ConnectionReceive => defmt::write!(f, "ConnectionReceive"),
+            Self::Transport(k) => defmt::write!(f, "Transport({})", k),
             Self::PacketTooLargeForBuffer => defmt::write!(f, "PacketTooLargeForBuffer"),
             Self::UnexpectedPropertyIdentifier => defmt::write!(f, "UnexpectedPropertyIdentifier"),
             Self::InvalidRetainHandlingValue => defmt::write!(f, "InvalidRetainHandlingValue"),
@@ -159,6 +258,10 @@ impl defmt::Format for PacketReadError {
             Self::SubscriptionOptionsReservedBitsNonZero => {
                 defmt::write!(f, "SubscriptionOptionsReservedBitsNonZero")
             }
+            Self::DuplicateProperty => defmt::write!(f, "DuplicateProperty"),
+            Self::PayloadSink => defmt::write!(f, "PayloadSink"),
+            Self::ServerOnlyPacketType => defmt::write!(f, "ServerOnlyPacketType"),
+            Self::MalformedPacket => defmt::write!(f, "MalformedPacket"),
         }
     }
 }
@@ -169,6 +272,7 @@ impl From<Utf8Error> for PacketReadError {
     }
 }
 
+#[cfg(feature = "display")]
 impl Display for PacketReadError {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match self {
@@ -186,7 +290,7 @@ impl Display for PacketReadError {
             Self::UnsupportedProtocolVersion => write!(f, "UnsupportedProtocolVersion"),
             Self::TooManyRequests => write!(f, "TooManyRequests"),
             Self::InvalidPacketType => write!(f, "InvalidPacketType"),
-            Self::ConnectionReceive => write!(f, "ConnectionReceive"),
+            Self::Transport(k) => write!(f, "Transport({})", k),
             Self::PacketTooLargeForBuffer => write!(f, "PacketTooLargeForBuffer"),
             Self::UnexpectedPropertyIdentifier => write!(f, "UnexpectedPropertyIdentifier"),
             Self::InvalidRetainHandlingValue => write!(f, "InvalidRetainHandlingValue"),
@@ -205,6 +309,57 @@ impl Display for PacketReadError {
             Self::SubscriptionOptionsReservedBitsNonZero => {
                 write!(f, "ReservedBitsSetInSubscriptionOptions")
             }
+            Self::DuplicateProperty => write!(f, "DuplicateProperty"),
+            Self::PayloadSink => write!(f, "PayloadSink"),
+            Self::ServerOnlyPacketType => write!(f, "ServerOnlyPacketType"),
+            Self::MalformedPacket => write!(f, "MalformedPacket"),
+        }
+    }
+}
+
+impl PacketReadError {
+    /// True if the error may not recur if retried, i.e. it is a failure to receive
+    /// via the connection. Other errors indicate invalid data that would be expected
+    /// to recur.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Transport(_))
+    }
+
+    /// The reason code to use in a Disconnect packet sent to the other end of the
+    /// connection when this error occurs receiving a packet, or None if the error
+    /// is not caused by the received data, e.g. a failure to receive via the connection.
+    pub fn disconnect_reason_code(&self) -> Option<DisconnectReasonCode> {
+        match self {
+            Self::Transport(_) | Self::PayloadSink => None,
+            Self::PacketTooLargeForBuffer => Some(DisconnectReasonCode::PacketTooLarge),
+            // Valid packets that exceed this implementation's limits
+            Self::TooManyProperties | Self::TooManyRequests => {
+                Some(DisconnectReasonCode::ImplementationSpecificError)
+            }
+            Self::UnsupportedProtocolVersion
+            | Self::SubscribeWithoutValidSubscriptionRequest
+            | Self::SubackWithoutValidReasonCode
+            | Self::UnsubscribeWithoutValidSubscriptionRequest
+            | Self::UnsubackWithoutValidReasonCode
+            | Self::DuplicateProperty
+            | Self::ServerOnlyPacketType => Some(DisconnectReasonCode::ProtocolError),
+            Self::InsufficientData
+            | Self::InvalidUtf8
+            | Self::NullCharacterInString
+            | Self::InvalidVariableByteIntegerEncoding
+            | Self::IncorrectPacketType
+            | Self::UnknownReasonCode
+            | Self::InvalidBooleanValue
+            | Self::InvalidQosValue
+            | Self::InvalidPacketType
+            | Self::UnexpectedPropertyIdentifier
+            | Self::InvalidRetainHandlingValue
+            | Self::InvalidConnectFlags
+            | Self::IncorrectPacketLength
+            | Self::WillQosSpecifiedWithoutWill
+            | Self::WillRetainSpecifiedWithoutWill
+            | Self::SubscriptionOptionsReservedBitsNonZero
+            | Self::MalformedPacket => Some(DisconnectReasonCode::MalformedPacket),
         }
     }
 }
@@ -226,8 +381,18 @@ pub enum PacketWriteError {
     /// On attempt to put a string where the encoded form is too many bytes to encode
     StringTooLarge,
 
-    /// Failure to send via connection
-    ConnectionSend,
+    /// Failure to send via connection, with the kind of failure reported by
+    /// the [Connection](crate::packet_client::Connection)
+    Transport(TransportErrorKind),
+
+    /// On attempt to put a list of properties where a property that may only be
+    /// included once in a packet is present more than once. Only checked when
+    /// the `validate-properties` feature is enabled.
+    DuplicateProperty,
+
+    /// On attempt to put a payload from a [crate::packets::publish::PayloadSource] that
+    /// doesn't provide data consistent with its stated length
+    IncorrectPayloadLength,
 }
 
 #[cfg(feature = "defmt")]
@@ -239,11 +404,14 @@ impl defmt::Format for PacketWriteError {
             Self::VariableByteIntegerTooLarge => defmt::write!(f, "VariableByteIntegerTooLarge"),
             Self::DataTooLarge => defmt::write!(f, "DataTooLarge"),
             Self::StringTooLarge => defmt::write!(f, "StringTooLarge"),
-            Self::ConnectionSend => defmt::write!(f, "ConnectionSend"),
+            Self::Transport(k) => defmt::write!(f, "Transport({})", k),
+            Self::DuplicateProperty => defmt::write!(f, "DuplicateProperty"),
+            Self::IncorrectPayloadLength => defmt::write!(f, "IncorrectPayloadLength"),
         }
     }
 }
 
+#[cfg(feature = "display")]
 impl Display for PacketWriteError {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match self {
@@ -252,7 +420,80 @@ impl Display for PacketWriteError {
             Self::VariableByteIntegerTooLarge => write!(f, "VariableByteIntegerTooLarge"),
             Self::DataTooLarge => write!(f, "DataTooLarge"),
             Self::StringTooLarge => write!(f, "StringTooLarge"),
-            Self::ConnectionSend => write!(f, "ConnectionSend"),
+            Self::Transport(k) => write!(f, "Transport({})", k),
+            Self::DuplicateProperty => write!(f, "DuplicateProperty"),
+            Self::IncorrectPayloadLength => write!(f, "IncorrectPayloadLength"),
         }
     }
 }
+
+impl PacketWriteError {
+    /// True if the error may not recur if retried, i.e. it is a failure to send
+    /// via the connection. Other errors indicate data that cannot be encoded.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Transport(_))
+    }
+}
+
+/// A [PacketReadError] with context to help diagnose it, produced by
+/// [MqttBufReader::get_with_context](crate::codec::mqtt_reader::MqttBufReader::get_with_context)
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PacketReadErrorContext {
+    /// The error
+    pub error: PacketReadError,
+    /// The type of packet being decoded, if the first byte of data was a valid packet type
+    pub packet_type: Option<PacketType>,
+    /// The position in the data where decoding stopped, relative to the start of the packet
+    pub position: usize,
+}
+
+#[cfg(feature = "display")]
+impl Display for PacketReadErrorContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self.packet_type {
+            Some(packet_type) => write!(
+                f,
+                "{} at byte {} of {:?} packet",
+                self.error, self.position, packet_type
+            ),
+            None => write!(f, "{} at byte {}", self.error, self.position),
+        }
+    }
+}
+
+impl From<PacketReadErrorContext> for PacketReadError {
+    fn from(value: PacketReadErrorContext) -> Self {
+        value.error
+    }
+}
+
+/// A [PacketWriteError] with context to help diagnose it, produced by
+/// [MqttBufWriter::put_with_context](crate::codec::mqtt_writer::MqttBufWriter::put_with_context)
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PacketWriteErrorContext {
+    /// The error
+    pub error: PacketWriteError,
+    /// The type of packet being encoded
+    pub packet_type: PacketType,
+    /// The position in the buffer where encoding stopped, relative to the start of the packet
+    pub position: usize,
+}
+
+#[cfg(feature = "display")]
+impl Display for PacketWriteErrorContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} at byte {} of {:?} packet",
+            self.error, self.position, self.packet_type
+        )
+    }
+}
+
+impl From<PacketWriteErrorContext> for PacketWriteError {
+    fn from(value: PacketWriteErrorContext) -> Self {
+        value.error
+    }
+}
@@ -1,19 +1,55 @@
-#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+pub mod authenticator;
+pub mod bridge;
 pub mod client;
+pub mod client_id;
 pub mod client_state;
 pub mod codec;
 pub mod data;
+pub mod diagnostics;
+#[cfg(feature = "client-codec")]
+pub mod engine;
 pub mod error;
+#[cfg(feature = "client-codec")]
+pub mod managed_client;
 pub mod mqtt_manager;
+pub mod namespace;
 pub mod packet_client;
 pub mod packets;
+pub mod prelude;
+pub mod router;
+pub mod subscriptions;
+pub mod timing;
+pub mod value_cache;
+pub mod watchdog;
 
 #[cfg(feature = "tokio")]
 pub mod tokio;
 
+#[cfg(all(feature = "std", feature = "client-codec"))]
+pub mod blocking;
+
 #[cfg(feature = "embedded-io-async")]
 pub mod embedded_io_async;
 
+#[cfg(feature = "embedded-nal-async")]
+pub mod embedded_nal_async;
+
 #[cfg(feature = "embedded-hal-async")]
 pub mod embedded_hal_async;
+
+#[cfg(feature = "zeroize")]
+pub mod secret;
+
+#[cfg(feature = "sparkplug")]
+pub mod sparkplug;
+
+#[cfg(feature = "azure")]
+pub mod azure;
+
+#[cfg(feature = "mqtt-sn")]
+pub mod mqtt_sn;
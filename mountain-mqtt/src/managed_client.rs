@@ -0,0 +1,2044 @@
+use core::num::NonZeroU16;
+
+use heapless::Vec;
+
+use crate::{
+    authenticator::Authenticator,
+    client::{
+        BatchMessage, Client, ClientError, ClientNoQueueOptions, ClientOptions,
+        ClientReceivedEvent, ConnectionSettings, Delay, EventHandler, DUPLICATE_DETECTION_WINDOW,
+        LOCAL_ECHO_WINDOW,
+    },
+    client_state::{
+        Acknowledgement, ClientState, ClientStateReceiveEvent, ClientStateSubscriptions,
+        PendingCounts, PendingRequest,
+    },
+    codec::write,
+    data::{
+        encoded_topic::EncodedTopic,
+        packet_identifier::{PacketIdentifier, RecentPacketIdentifiers},
+        packet_type::PacketType,
+        property::{
+            AuthenticationData, ConnectProperty, PropertiesExt, Property, PropertyOverflow,
+            PublishProperty, SubscribeProperty,
+        },
+        quality_of_service::QualityOfService,
+        reason_code::AuthReasonCode,
+        recent_publishes::RecentPublishes,
+        subscription_options::SubscriptionOptions,
+        topic_filter::shared_subscription_filter,
+    },
+    diagnostics::{report_received, ClientDiagnosticEvent, Diagnostics},
+    error::{PacketReadError, PacketReadErrorContext, PacketWriteErrorContext},
+    packet_client::{Connection, PacketClient},
+    packets::{
+        connect::{Connect, Will},
+        disconnect::Disconnect,
+        packet::Packet,
+        puback::Puback,
+        publish::{PayloadSource, Publish},
+        publish_template::PublishTemplate,
+        server_packet::ServerPacket,
+        subscribe::{Subscribe, SubscriptionRequest},
+        PROPERTIES_DEFAULT,
+    },
+    timing::{Clock, ConnectionTimer, Health},
+    watchdog::Watchdog,
+};
+
+/// The maximum number of properties in a [Connect] packet produced by [connect_packet]
+pub const CONNECT_PACKET_PROPERTIES: usize = 4;
+
+/// Produce the [Connect] packet for [ConnectionSettings] and an optional [Will],
+/// as used by [Client::connect_with_will]. This uses clean start from the
+/// settings, and has a [TopicAliasMaximum](crate::data::property::TopicAliasMaximum) property
+/// from the settings, plus properties for any other settings that differ from
+/// the protocol defaults.
+pub fn connect_packet<'a, const W: usize>(
+    settings: &ConnectionSettings<'a>,
+    will: Option<Will<'a, W>>,
+) -> Connect<'a, CONNECT_PACKET_PROPERTIES, W> {
+    let mut properties = Vec::new();
+    // By setting maximum topic alias to 0, we prevent the server
+    // trying to use aliases, which we don't support. They are optional
+    // and only provide for reduced packet size, but would require storing
+    // topic names from the server for the length of the connection,
+    // which might be awkward without alloc.
+    properties
+        .push(ConnectProperty::TopicAliasMaximum(0.into()))
+        .unwrap();
+    if settings.session_expiry_interval() != 0 {
+        properties
+            .push(ConnectProperty::SessionExpiryInterval(
+                settings.session_expiry_interval().into(),
+            ))
+            .unwrap();
+    }
+    if settings.receive_maximum() != NonZeroU16::MAX {
+        properties
+            .push(ConnectProperty::ReceiveMaximum(
+                settings.receive_maximum().get().into(),
+            ))
+            .unwrap();
+    }
+    if !settings.request_problem_information() {
+        properties
+            .push(ConnectProperty::RequestProblemInformation(0.into()))
+            .unwrap();
+    }
+    Connect::new(
+        settings.keep_alive(),
+        *settings.username(),
+        *settings.password(),
+        settings.client_id(),
+        settings.clean_start(),
+        will,
+        properties,
+    )
+}
+
+/// Check a [Publish] received from the server can be passed on as an application
+/// message. The topic name must not be empty, since topic aliases are not
+/// supported, and if `validate_payload_format` is true, a payload with a
+/// payload format indicator showing UTF-8 data must be valid UTF-8.
+pub fn check_received_publish<const P: usize>(
+    publish: &Publish<'_, P>,
+    validate_payload_format: bool,
+) -> Result<(), ClientError> {
+    if publish.topic_name().is_empty() {
+        return Err(ClientError::EmptyTopicNameWithAliasesDisabled);
+    }
+    if validate_payload_format && !publish.payload_format_is_valid() {
+        return Err(ClientError::PayloadFormatInvalid);
+    }
+    Ok(())
+}
+
+/// If `accept_downgraded_qos` is true, treat [ClientReceivedEvent::SubscriptionGrantedBelowMaximumQos]
+/// as a successful [ClientReceivedEvent::Ack], otherwise return `event` unchanged.
+/// Note that the granted qos is recorded in the [SubscriptionRegistry](crate::subscriptions::SubscriptionRegistry)
+/// of a client state either way.
+pub fn accept_downgraded_qos<const P: usize>(
+    event: ClientReceivedEvent<'_, P>,
+    accept_downgraded_qos: bool,
+) -> ClientReceivedEvent<'_, P> {
+    match event {
+        ClientReceivedEvent::SubscriptionGrantedBelowMaximumQos {
+            packet_identifier, ..
+        } if accept_downgraded_qos => {
+            ClientReceivedEvent::Ack(Acknowledgement::Suback { packet_identifier })
+        }
+        event => event,
+    }
+}
+
+/// A request to subscribe to `topic_name` with default options, setting the
+/// NoLocal option if `no_local` is true, so that the server doesn't send
+/// messages published by this client. NoLocal is never set for a shared
+/// subscription, where it is a protocol error [MQTT-3.8.3-4].
+pub fn subscription_request(
+    topic_name: &str,
+    maximum_qos: QualityOfService,
+    no_local: bool,
+) -> SubscriptionRequest<'_> {
+    let no_local = no_local && shared_subscription_filter(topic_name).is_none();
+    SubscriptionRequest::with_options(
+        topic_name,
+        SubscriptionOptions::new(maximum_qos).with_no_local(no_local),
+    )
+}
+
+/// Convert a [ClientStateReceiveEvent] into a [ClientReceivedEvent], for a client
+/// that sends any required Puback itself, and does not support re-authentication.
+/// Received publishes are checked with [check_received_publish], and a Disconnect
+/// from the server produces [ClientError::Disconnected].
+pub fn received_event<'a, const P: usize>(
+    event: ClientStateReceiveEvent<'a, '_, P>,
+    validate_payload_format: bool,
+) -> Result<ClientReceivedEvent<'a, P>, ClientError> {
+    match event {
+        ClientStateReceiveEvent::Ack(acknowledgement) => {
+            Ok(ClientReceivedEvent::Ack(acknowledgement))
+        }
+
+        ClientStateReceiveEvent::Publish { publish }
+        | ClientStateReceiveEvent::PublishAndPuback { publish, puback: _ } => {
+            check_received_publish(&publish, validate_payload_format)?;
+            Ok(publish.into())
+        }
+
+        ClientStateReceiveEvent::SubscriptionGrantedBelowMaximumQos {
+            packet_identifier,
+            granted_qos,
+            maximum_qos,
+        } => Ok(ClientReceivedEvent::SubscriptionGrantedBelowMaximumQos {
+            packet_identifier,
+            granted_qos,
+            maximum_qos,
+        }),
+
+        ClientStateReceiveEvent::SubscriptionsPartiallyRejected { reason_codes } => {
+            Ok(ClientReceivedEvent::SubscriptionsPartiallyRejected { reason_codes })
+        }
+
+        ClientStateReceiveEvent::PublishedMessageHadNoMatchingSubscribers => {
+            Ok(ClientReceivedEvent::PublishedMessageHadNoMatchingSubscribers)
+        }
+
+        ClientStateReceiveEvent::NoSubscriptionExisted => {
+            Ok(ClientReceivedEvent::NoSubscriptionExisted)
+        }
+
+        ClientStateReceiveEvent::UnsubscriptionsPartiallyRejected { reason_codes } => {
+            Ok(ClientReceivedEvent::UnsubscriptionsPartiallyRejected { reason_codes })
+        }
+
+        // Re-authentication is not supported, so the state will not
+        // accept Auth packets, but we handle this for completeness
+        ClientStateReceiveEvent::Auth { auth: _ } => Err(ClientError::NoAuthenticator),
+
+        ClientStateReceiveEvent::Disconnect { disconnect } => {
+            Err(ClientError::Disconnected(*disconnect.reason_code()))
+        }
+    }
+}
+
+/// Handling of received events that is independent of how packets are sent
+/// and received, shared by [ManagedClient] and other clients built on a
+/// [ClientState] (e.g. with their own transport): checking received
+/// application messages, suppressing redelivered duplicates and echoes of our
+/// own publishes, accepting downgraded subscriptions, and setting NoLocal on
+/// subscriptions. All behaviours are disabled by default, see the corresponding
+/// methods of [ManagedClient] for details.
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    validate_payload_format: bool,
+    accept_downgraded_qos: bool,
+    detect_duplicates: bool,
+    recent_publishes: RecentPacketIdentifiers<DUPLICATE_DETECTION_WINDOW>,
+    no_local: bool,
+    suppress_local_echo: bool,
+    local_publishes: RecentPublishes<LOCAL_ECHO_WINDOW>,
+}
+
+impl EventFilter {
+    /// Create a filter with the behaviours enabled in `options`
+    pub fn new(options: &ClientOptions) -> Self {
+        Self::default()
+            .with_payload_format_validation(options.validate_payload_format())
+            .with_accept_downgraded_qos(options.accept_downgraded_qos())
+            .with_duplicate_detection(options.detect_duplicates())
+            .with_no_local(options.no_local())
+            .with_local_echo_suppression(options.suppress_local_echo())
+    }
+
+    /// See [ManagedClient::with_payload_format_validation]
+    pub fn with_payload_format_validation(mut self, validate_payload_format: bool) -> Self {
+        self.validate_payload_format = validate_payload_format;
+        self
+    }
+
+    /// See [ManagedClient::with_accept_downgraded_qos]
+    pub fn with_accept_downgraded_qos(mut self, accept_downgraded_qos: bool) -> Self {
+        self.accept_downgraded_qos = accept_downgraded_qos;
+        self
+    }
+
+    /// See [ManagedClient::with_duplicate_detection]
+    pub fn with_duplicate_detection(mut self, detect_duplicates: bool) -> Self {
+        self.detect_duplicates = detect_duplicates;
+        self
+    }
+
+    /// See [ManagedClient::with_no_local]
+    pub fn with_no_local(mut self, no_local: bool) -> Self {
+        self.no_local = no_local;
+        self
+    }
+
+    /// See [ManagedClient::with_local_echo_suppression]
+    pub fn with_local_echo_suppression(mut self, suppress_local_echo: bool) -> Self {
+        self.suppress_local_echo = suppress_local_echo;
+        self
+    }
+
+    /// A request to subscribe to `topic_name` with default options, setting
+    /// NoLocal if enabled, see [subscription_request]
+    pub fn subscription_request<'b>(
+        &self,
+        topic_name: &'b str,
+        maximum_qos: QualityOfService,
+    ) -> SubscriptionRequest<'b> {
+        subscription_request(topic_name, maximum_qos, self.no_local)
+    }
+
+    /// Record a message we are publishing, if suppressing local echoes
+    pub fn published(&mut self, topic_name: &str, payload: &[u8]) {
+        if self.suppress_local_echo {
+            self.local_publishes.record(topic_name, payload);
+        }
+    }
+
+    /// Start a new connection, forgetting any messages published on the
+    /// previous connection. Recently acknowledged messages are forgotten if
+    /// `clean_start` is true, otherwise they are kept until
+    /// [EventFilter::connected] shows whether the session is resumed.
+    pub fn connecting(&mut self, clean_start: bool) {
+        if clean_start {
+            self.recent_publishes.clear();
+        }
+        self.local_publishes.clear();
+    }
+
+    /// The server has acknowledged the connection. If `session_present` is
+    /// false there is no existing session, so any message received is new,
+    /// and recently acknowledged messages are forgotten. Otherwise they are
+    /// kept, so that messages redelivered in the resumed session are detected
+    /// as duplicates.
+    pub fn connected(&mut self, session_present: bool) {
+        if !session_present {
+            self.recent_publishes.clear();
+        }
+    }
+
+    /// The packet identifiers of recently acknowledged qos 1 messages, used
+    /// to detect duplicates, e.g. to be restored with
+    /// [EventFilter::with_recent_publishes] in a new client resuming the session
+    pub fn recent_publishes(&self) -> &RecentPacketIdentifiers<DUPLICATE_DETECTION_WINDOW> {
+        &self.recent_publishes
+    }
+
+    /// Restore the packet identifiers of recently acknowledged qos 1 messages,
+    /// see [EventFilter::recent_publishes]
+    pub fn with_recent_publishes(
+        mut self,
+        recent_publishes: RecentPacketIdentifiers<DUPLICATE_DETECTION_WINDOW>,
+    ) -> Self {
+        self.recent_publishes = recent_publishes;
+        self
+    }
+
+    /// Check a [Publish] received from the server, with the `packet_identifier`
+    /// of the Puback that will be sent for a qos 1 message. Produces true if the
+    /// message should be passed on to the application, or false if it is a
+    /// duplicate or local echo that should only be acknowledged.
+    /// Call [EventFilter::acknowledged] once the Puback is sent.
+    pub fn message<const P: usize>(
+        &mut self,
+        publish: &Publish<'_, P>,
+        packet_identifier: Option<PacketIdentifier>,
+    ) -> Result<bool, ClientError> {
+        let is_duplicate = self.detect_duplicates
+            && publish.duplicate()
+            && packet_identifier.is_some_and(|id| self.recent_publishes.contains(id));
+        // Duplicates are acknowledged again, but not handled
+        if is_duplicate {
+            return Ok(false);
+        }
+        check_received_publish(publish, self.validate_payload_format)?;
+        // Echoes of our own publishes are not handled
+        let is_local_echo = self.suppress_local_echo
+            && self
+                .local_publishes
+                .take(publish.topic_name(), publish.payload());
+        Ok(!is_local_echo)
+    }
+
+    /// Record the packet identifier of a qos 1 message that has been
+    /// acknowledged, if detecting duplicates
+    pub fn acknowledged(&mut self, packet_identifier: PacketIdentifier) {
+        if self.detect_duplicates {
+            self.recent_publishes.record(packet_identifier);
+        }
+    }
+
+    /// Apply [accept_downgraded_qos] if enabled
+    pub fn acknowledgement<'a, const P: usize>(
+        &self,
+        event: ClientReceivedEvent<'a, P>,
+    ) -> ClientReceivedEvent<'a, P> {
+        accept_downgraded_qos(event, self.accept_downgraded_qos)
+    }
+
+    /// Convert a [ClientStateReceiveEvent] into a [ClientReceivedEvent] as for
+    /// [received_event], for a client that has already sent any required Puback.
+    /// Produces None for a message that should not be passed on to the
+    /// application, see [EventFilter::message].
+    pub fn receive<'a, const P: usize>(
+        &mut self,
+        event: ClientStateReceiveEvent<'a, '_, P>,
+    ) -> Result<Option<ClientReceivedEvent<'a, P>>, ClientError> {
+        match event {
+            ClientStateReceiveEvent::Publish { publish } => {
+                Ok(self.message(&publish, None)?.then(|| publish.into()))
+            }
+            ClientStateReceiveEvent::PublishAndPuback { publish, puback } => {
+                let id = *puback.packet_identifier();
+                let handle = self.message(&publish, Some(id))?;
+                self.acknowledged(id);
+                Ok(handle.then(|| publish.into()))
+            }
+            event => Ok(Some(self.acknowledgement(received_event(
+                event,
+                self.validate_payload_format,
+            )?))),
+        }
+    }
+}
+
+/// The result of receiving a packet in [ManagedClient], before handling any event
+enum Received<const P: usize> {
+    /// No packet was ready
+    Nothing,
+    /// A packet was received, but produces no event
+    Handled,
+    /// A packet was received, producing an event that doesn't borrow the packet
+    Event(ClientReceivedEvent<'static, P>),
+    /// An application message was received, and is left in the buffer
+    Message,
+    /// A Disconnect was received, and is left in the buffer
+    Disconnect,
+}
+
+/// The event for an application message or Disconnect received from the server
+fn packet_event<const P: usize>(
+    packet: ServerPacket<'_, P, 0>,
+) -> Result<ClientReceivedEvent<'_, P>, ClientError> {
+    match packet {
+        ServerPacket::Publish(publish) => Ok(publish.into()),
+        ServerPacket::Disconnect(disconnect) => Ok(ClientReceivedEvent::Disconnected {
+            reason_code: *disconnect.reason_code(),
+            properties: disconnect.into_properties(),
+        }),
+        _ => Err(ClientError::PacketRead(
+            PacketReadError::IncorrectPacketType,
+        )),
+    }
+}
+
+/// An MQTT client that manages a connection to the server, sending packets
+/// over a [Connection] and tracking the protocol with a [ClientState].
+/// Each operation needing a response waits for it, polling the connection
+/// using the [Delay], and each received event is passed to an [EventHandler].
+/// Pings, the receive timeout and timeouts waiting for responses are measured
+/// with the [Clock], using a [ConnectionTimer].
+/// This is independent of any particular executor or network stack - these are
+/// provided by the [Connection], [Delay] and [Clock] implementations, see for example
+/// [crate::tokio] and [crate::embedded_io_async].
+pub struct ManagedClient<'a, C, D, T, S, F, const P: usize = PROPERTIES_DEFAULT>
+where
+    C: Connection,
+    D: Delay,
+    T: Clock,
+    S: ClientState,
+    F: EventHandler<P>,
+{
+    packet_client: PacketClient<'a, C>,
+    client_state: S,
+    delay: D,
+    clock: T,
+    timer: ConnectionTimer,
+    options: ClientNoQueueOptions,
+    event_handler: F,
+    filter: EventFilter,
+    authenticator: Option<&'a mut dyn Authenticator>,
+    watchdog: Option<&'a mut dyn Watchdog>,
+    diagnostics: Option<&'a mut dyn Diagnostics>,
+    deferred_puback: Option<Puback<'static, P>>,
+    continue_authentication: bool,
+}
+
+impl<'a, C, D, T, S, F, const P: usize> ManagedClient<'a, C, D, T, S, F, P>
+where
+    C: Connection,
+    D: Delay,
+    T: Clock,
+    S: ClientState,
+    F: EventHandler<P>,
+{
+    /// Create a client with a new [ClientState], using `buf` to encode
+    /// and decode packets, `delay` between polls and `clock` to measure time.
+    /// `options` may be a [ClientOptions], or just the [ClientNoQueueOptions]
+    /// timeouts to use default behaviours.
+    pub fn new(
+        connection: C,
+        buf: &'a mut [u8],
+        delay: D,
+        clock: T,
+        options: impl Into<ClientOptions>,
+        event_handler: F,
+    ) -> Self
+    where
+        S: Default,
+    {
+        Self::new_with_client_state(
+            connection,
+            buf,
+            delay,
+            clock,
+            options,
+            S::default(),
+            event_handler,
+        )
+    }
+
+    /// Create a client using the provided [ClientState], which must
+    /// not yet be connected
+    pub fn new_with_client_state(
+        connection: C,
+        buf: &'a mut [u8],
+        delay: D,
+        clock: T,
+        options: impl Into<ClientOptions>,
+        client_state: S,
+        event_handler: F,
+    ) -> Self {
+        let options: ClientOptions = options.into();
+        let packet_client = PacketClient::new(connection, buf)
+            .with_discard_oversized_packets(options.discard_oversized_packets())
+            .with_skip_unknown_properties(options.skip_unknown_properties())
+            .with_property_overflow(options.property_overflow());
+        Self {
+            packet_client,
+            client_state,
+            delay,
+            clock,
+            timer: ConnectionTimer::new(options.timeouts().connection_timer_settings()),
+            options: *options.timeouts(),
+            event_handler,
+            filter: EventFilter::new(&options),
+            authenticator: None,
+            watchdog: None,
+            diagnostics: None,
+            deferred_puback: None,
+            continue_authentication: false,
+        }
+    }
+
+    /// Set the [Authenticator] used to respond to the server during
+    /// re-authentication, see [ManagedClient::reauthenticate]
+    pub fn with_authenticator(mut self, authenticator: &'a mut dyn Authenticator) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    /// Set a [Watchdog] to be fed each time a packet from the server is
+    /// successfully handled
+    pub fn with_watchdog(mut self, watchdog: &'a mut dyn Watchdog) -> Self {
+        self.watchdog = Some(watchdog);
+        self
+    }
+
+    /// Set a [Diagnostics] hook to receive a [ClientDiagnosticEvent] for
+    /// each connect attempt, Connack and Suback result, retransmission and
+    /// timeout
+    pub fn with_diagnostics(mut self, diagnostics: &'a mut dyn Diagnostics) -> Self {
+        self.diagnostics = Some(diagnostics);
+        self
+    }
+
+    /// Set whether received application messages with a payload format
+    /// indicator showing UTF-8 data are checked for valid UTF-8. If enabled,
+    /// invalid messages are not passed to the event handler, and instead
+    /// produce [ClientError::PayloadFormatInvalid]. Disabled by default.
+    pub fn with_payload_format_validation(mut self, validate_payload_format: bool) -> Self {
+        self.filter = self
+            .filter
+            .with_payload_format_validation(validate_payload_format);
+        self
+    }
+
+    /// Set whether a subscription granted with a lower qos than requested is
+    /// treated as successful, passing [ClientReceivedEvent::Ack] to the event
+    /// handler rather than [ClientReceivedEvent::SubscriptionGrantedBelowMaximumQos].
+    /// The granted qos is still recorded in the client state's subscription
+    /// registry. Disabled by default.
+    pub fn with_accept_downgraded_qos(mut self, accept_downgraded_qos: bool) -> Self {
+        self.filter = self
+            .filter
+            .with_accept_downgraded_qos(accept_downgraded_qos);
+        self
+    }
+
+    /// Set whether redelivered qos 1 application messages are suppressed.
+    /// If the server doesn't receive our Puback (e.g. because the connection
+    /// dropped) it may deliver a message again with the DUP flag set. If enabled,
+    /// a DUP message is acknowledged but not passed to the event handler if it
+    /// has the packet identifier of one of the last [DUPLICATE_DETECTION_WINDOW]
+    /// qos 1 messages acknowledged in this session. Disabled by default.
+    /// These are kept when reconnecting without clean start (see
+    /// [ConnectionSettings::with_clean_start]) if the server resumes the session,
+    /// and can be passed to a new client with [ManagedClient::recent_publishes]
+    /// and [ManagedClient::with_recent_publishes].
+    /// Note that the server may legitimately reuse a packet identifier once a
+    /// message is acknowledged, so this can only detect redeliveries that
+    /// arrive before the identifier is reused.
+    pub fn with_duplicate_detection(mut self, detect_duplicates: bool) -> Self {
+        self.filter = self.filter.with_duplicate_detection(detect_duplicates);
+        self
+    }
+
+    /// The packet identifiers of recently acknowledged qos 1 messages, used for
+    /// duplicate detection, e.g. to pass to [ManagedClient::with_recent_publishes]
+    /// for a new client resuming the same session after the connection drops
+    pub fn recent_publishes(&self) -> &RecentPacketIdentifiers<DUPLICATE_DETECTION_WINDOW> {
+        self.filter.recent_publishes()
+    }
+
+    /// Restore the packet identifiers of recently acknowledged qos 1 messages
+    /// from [ManagedClient::recent_publishes] of a previous client, so that
+    /// messages redelivered when the session is resumed are detected as
+    /// duplicates. These are forgotten if the client connects with clean start,
+    /// or the server has no existing session.
+    pub fn with_recent_publishes(
+        mut self,
+        recent_publishes: RecentPacketIdentifiers<DUPLICATE_DETECTION_WINDOW>,
+    ) -> Self {
+        self.filter = self.filter.with_recent_publishes(recent_publishes);
+        self
+    }
+
+    /// Set whether subscriptions made without explicit [SubscriptionOptions]
+    /// (e.g. by [Client::subscribe]) have the NoLocal option set, so the server
+    /// doesn't send messages published by this client back to it, preventing
+    /// feedback loops when publishing and subscribing to the same topics.
+    /// See [subscription_request]. Disabled by default.
+    pub fn with_no_local(mut self, no_local: bool) -> Self {
+        self.filter = self.filter.with_no_local(no_local);
+        self
+    }
+
+    /// Set whether received application messages that look like echoes of our
+    /// own publishes are suppressed, for servers that don't support NoLocal.
+    /// If enabled, a received message with the same topic name and payload as
+    /// one of the last [LOCAL_ECHO_WINDOW] messages published on this connection
+    /// is acknowledged but not passed to the event handler, and each published
+    /// message suppresses at most one received message. Disabled by default.
+    /// Note that an identical message published by another client may be
+    /// suppressed, and messages published with [ManagedClient::publish_streaming]
+    /// are not recorded.
+    pub fn with_local_echo_suppression(mut self, suppress_local_echo: bool) -> Self {
+        self.filter = self.filter.with_local_echo_suppression(suppress_local_echo);
+        self
+    }
+
+    /// Receive packets to `rx`, separately from the buffer used to send packets,
+    /// so that when the connection delivers several packets in one read, they
+    /// are each handled without receiving more data, see
+    /// [PacketClient::with_receive_buffer]
+    pub fn with_receive_buffer(mut self, rx: &'a mut [u8]) -> Self {
+        self.packet_client = self.packet_client.with_receive_buffer(rx);
+        self
+    }
+
+    /// Set whether a received packet that is too large for the client's buffer
+    /// is received and discarded before the client disconnects, see
+    /// [PacketClient::with_discard_oversized_packets]. Disabled by default.
+    pub fn with_discard_oversized_packets(mut self, discard_oversized_packets: bool) -> Self {
+        self.packet_client = self
+            .packet_client
+            .with_discard_oversized_packets(discard_oversized_packets);
+        self
+    }
+
+    /// Set whether received packets are decoded skipping any properties that
+    /// are not expected for the packet type, see
+    /// [PacketClient::with_skip_unknown_properties]. Disabled by default.
+    pub fn with_skip_unknown_properties(mut self, skip_unknown_properties: bool) -> Self {
+        self.packet_client = self
+            .packet_client
+            .with_skip_unknown_properties(skip_unknown_properties);
+        self
+    }
+
+    /// Set how received packets with more properties than `P` are handled, see
+    /// [PacketClient::with_property_overflow]. Defaults to [PropertyOverflow::Fail].
+    pub fn with_property_overflow(mut self, property_overflow: PropertyOverflow) -> Self {
+        self.packet_client = self.packet_client.with_property_overflow(property_overflow);
+        self
+    }
+
+    /// The number of properties dropped from the last packet received, when
+    /// using [PropertyOverflow::Count], see [PacketClient::dropped_properties]
+    pub fn dropped_properties(&self) -> usize {
+        self.packet_client.dropped_properties()
+    }
+
+    /// Context for the error decoding the last packet received, see
+    /// [PacketClient::read_error_context]
+    pub fn read_error_context(&self) -> Option<PacketReadErrorContext> {
+        self.packet_client.read_error_context()
+    }
+
+    /// Context for the error encoding the last packet sent, see
+    /// [PacketClient::write_error_context]
+    pub fn write_error_context(&self) -> Option<PacketWriteErrorContext> {
+        self.packet_client.write_error_context()
+    }
+
+    /// The error from the connection that caused the most recent
+    /// [ClientError::Transport], see [PacketClient::transport_error]
+    pub fn transport_error(&self) -> Option<&C::Error> {
+        self.packet_client.transport_error()
+    }
+
+    /// Produce an error carrying the error from the connection that caused
+    /// `error`, if it is a [ClientError::Transport], e.g.
+    /// `client.poll(true).await.map_err(|e| client.full_error(e))`.
+    /// This takes the error from the connection, see [ClientError::with_transport_error]
+    pub fn full_error(&mut self, error: ClientError) -> ClientError<C::Error> {
+        match error {
+            ClientError::Transport(_) => {
+                error.with_transport_error(self.packet_client.take_transport_error())
+            }
+            error => error.with_transport_error(None),
+        }
+    }
+
+    /// The number of requests sent to the server that are still awaiting
+    /// a response, e.g. for monitoring connection health
+    pub fn pending_counts(&self) -> PendingCounts {
+        self.client_state.pending_counts()
+    }
+
+    /// Produce a [Health] snapshot, with the time since the last packet was
+    /// received from the server, the time left before the server is considered
+    /// unresponsive, and counts of requests still awaiting a response
+    pub fn health(&self) -> Health {
+        self.timer
+            .health(self.clock.now_millis(), self.client_state.pending_counts())
+    }
+
+    /// The requests sent to the server that are still awaiting a response,
+    /// with their packet identifiers, see [ClientState::pending_requests]
+    pub fn pending_requests(&self) -> impl Iterator<Item = PendingRequest> + '_ {
+        self.client_state.pending_requests()
+    }
+
+    /// The subscriptions the client believes are active, or None if not connected,
+    /// see [ClientState::subscriptions]
+    pub fn subscriptions(&self) -> Option<&ClientStateSubscriptions> {
+        self.client_state.subscriptions()
+    }
+
+    /// True if the client believes there is an active subscription with exactly
+    /// the given topic filter
+    pub fn is_subscribed(&self, topic_filter: &str) -> bool {
+        self.subscriptions()
+            .is_some_and(|s| s.is_subscribed(topic_filter))
+    }
+
+    /// Publish a message with a payload provided in chunks by a [PayloadSource],
+    /// so that the payload may be larger than the client's buffer (e.g. a
+    /// log streamed from flash). Apart from the payload, this is the same as
+    /// [Client::publish_with_properties].
+    pub async fn publish_streaming<'b, L, const PP: usize>(
+        &'b mut self,
+        topic_name: &'b str,
+        payload: L,
+        qos: QualityOfService,
+        retain: bool,
+        properties: Vec<PublishProperty<'b>, PP>,
+    ) -> Result<(), ClientError>
+    where
+        L: PayloadSource,
+    {
+        let packet = self
+            .client_state
+            .publish_with_properties(topic_name, &[], qos, retain, properties)?
+            .with_payload_source(payload);
+        match self.packet_client.send_streaming_publish(&packet).await {
+            Ok(()) => {
+                self.wait_for_responses(self.options.ack_timeout_millis())
+                    .await
+            }
+            Err(e) => {
+                self.client_state.error();
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Publish a message using a [PublishTemplate] for the topic name and
+    /// properties, so that only the payload needs to be encoded for each message.
+    /// Apart from this, this is the same as [Client::publish_with_properties].
+    pub async fn publish_template<const N: usize>(
+        &mut self,
+        template: &PublishTemplate<N>,
+        payload: &[u8],
+    ) -> Result<(), ClientError> {
+        let publish = self.client_state.publish_with_properties::<0>(
+            template.topic_name(),
+            &[],
+            template.qos(),
+            template.retain(),
+            Vec::new(),
+        )?;
+        self.filter.published(template.topic_name(), payload);
+        self.send_wait_for_responses(
+            template.packet_for(payload, &publish),
+            self.options.ack_timeout_millis(),
+        )
+        .await
+    }
+
+    /// Publish a message to an [EncodedTopic], so that the topic name doesn't
+    /// need to be checked and encoded for each message. Apart from this, this is
+    /// the same as [Client::publish_with_properties].
+    pub async fn publish_to_encoded_topic<'b, const N: usize, const PP: usize>(
+        &'b mut self,
+        topic: &'b EncodedTopic<N>,
+        payload: &'b [u8],
+        qos: QualityOfService,
+        retain: bool,
+        properties: Vec<PublishProperty<'b>, PP>,
+    ) -> Result<(), ClientError> {
+        let packet = self
+            .client_state
+            .publish_with_properties(topic.topic_name(), payload, qos, retain, properties)?
+            .with_encoded_topic(topic);
+        self.filter.published(topic.topic_name(), payload);
+        self.send_wait_for_responses(packet, self.options.ack_timeout_millis())
+            .await
+    }
+
+    /// Publish a message, waiting up to `ack_timeout_millis` for it to be acknowledged
+    /// rather than the ack timeout from the client's [ClientNoQueueOptions]. Apart
+    /// from this, this is the same as [Client::publish_with_properties].
+    /// If this produces [ClientError::TimeoutOnResponsePacket] the client remains
+    /// connected, see [ManagedClient::wait_for_acks].
+    pub async fn publish_with_ack_timeout<'b, const PP: usize>(
+        &'b mut self,
+        topic_name: &'b str,
+        payload: &'b [u8],
+        qos: QualityOfService,
+        retain: bool,
+        properties: Vec<PublishProperty<'b>, PP>,
+        ack_timeout_millis: u32,
+    ) -> Result<(), ClientError> {
+        let packet = self
+            .client_state
+            .publish_with_properties(topic_name, payload, qos, retain, properties)?;
+        self.filter.published(topic_name, payload);
+        self.send_wait_for_responses(packet, ack_timeout_millis)
+            .await
+    }
+
+    /// Subscribe to a topic, waiting up to `ack_timeout_millis` for the subscription
+    /// to be acknowledged rather than the ack timeout from the client's
+    /// [ClientNoQueueOptions]. Apart from this, this is the same as
+    /// [Client::subscribe_with_properties].
+    /// If this produces [ClientError::TimeoutOnResponsePacket] the client remains
+    /// connected, see [ManagedClient::wait_for_acks].
+    pub async fn subscribe_with_ack_timeout<'b, const PP: usize>(
+        &'b mut self,
+        topic_name: &'b str,
+        maximum_qos: QualityOfService,
+        properties: Vec<SubscribeProperty<'b>, PP>,
+        ack_timeout_millis: u32,
+    ) -> Result<(), ClientError> {
+        let packet = self.subscribe_packet(topic_name, maximum_qos, properties)?;
+        self.send_wait_for_responses(packet, ack_timeout_millis)
+            .await
+    }
+
+    /// Receive a packet if one is available, or wait for one if `wait` is true,
+    /// as for [Client::poll], but return the resulting event rather than passing
+    /// it to the client's [EventHandler], for applications that prefer to loop
+    /// and match on events. Produces `Ok(None)` if no packet was available, or
+    /// the packet produced no event, e.g. a suppressed duplicate message.
+    /// Since the event may borrow the client's buffer, the Puback for a qos 1
+    /// message is sent on the next call to `poll_event` or [Client::poll], or while
+    /// waiting for a response to another request, so the client should be polled
+    /// regularly to acknowledge messages.
+    /// A Disconnect from the server produces [ClientReceivedEvent::Disconnected],
+    /// after which the client is no longer connected.
+    pub async fn poll_event(
+        &mut self,
+        wait: bool,
+    ) -> Result<Option<ClientReceivedEvent<'_, P>>, ClientError> {
+        self.maintain_connection().await?;
+        self.send_deferred().await?;
+
+        let received = match self.receive_for_event(wait).await {
+            Ok(received) => received,
+            Err(e) => return Err(self.disconnect_on_protocol_violation(e).await),
+        };
+        if matches!(
+            received,
+            Received::Handled | Received::Event(_) | Received::Message
+        ) {
+            if let Some(watchdog) = self.watchdog.as_deref_mut() {
+                watchdog.feed();
+            }
+        }
+
+        match received {
+            Received::Nothing | Received::Handled => Ok(None),
+            Received::Event(event) => Ok(Some(event)),
+            Received::Message | Received::Disconnect => {
+                let packet = self.packet_client.last_received_server_packet()?;
+                Ok(Some(packet_event(packet)?))
+            }
+        }
+    }
+
+    /// Wait up to `timeout_millis` for any outstanding acknowledgement, e.g. after a
+    /// publish or subscribe has produced [ClientError::TimeoutOnResponsePacket].
+    /// That error leaves the client connected and still waiting for the
+    /// acknowledgement, shown by [ManagedClient::pending_requests], so a slow
+    /// acknowledgement can be given longer to arrive rather than reconnecting.
+    /// No further requests can be made while one is outstanding.
+    pub async fn wait_for_acks(&mut self, timeout_millis: u32) -> Result<(), ClientError> {
+        self.wait_for_responses(timeout_millis).await
+    }
+
+    /// Publish a batch of messages with quality of service 0, encoding as many
+    /// as will fit in the client's buffer back-to-back and sending them in a
+    /// single write to the connection, see [PacketClient::send_batch]. This
+    /// reduces per-packet overhead when sending a burst of telemetry.
+    /// Each message must fit in the buffer on its own. If an error occurs,
+    /// some earlier messages in the batch may have been sent.
+    pub async fn publish_batch(
+        &mut self,
+        messages: &[BatchMessage<'_>],
+    ) -> Result<(), ClientError> {
+        let client_state = &mut self.client_state;
+        let packets = messages.iter().map(|m| {
+            client_state
+                .publish(m.topic_name, m.payload, QualityOfService::Qos0, m.retain)
+                .map_err(ClientError::from)
+        });
+        let result = self.packet_client.send_batch(packets).await;
+
+        // Only messages that were sent can be echoed back
+        for m in &messages[0..self.packet_client.batch_sent()] {
+            self.filter.published(m.topic_name, m.payload);
+        }
+
+        // Errors from the client state are produced before a packet is sent,
+        // any other error means packets may not have been sent
+        if let Err(e) = &result {
+            if !matches!(e, ClientError::ClientState(_)) {
+                self.client_state.error();
+            }
+        }
+        result
+    }
+
+    /// Re-authenticate with the server while connected, e.g. to refresh a token
+    /// before it expires, without reconnecting. This sends an Auth packet with
+    /// [AuthReasonCode::ReAuthenticate] and `auth_data`, then responds to any
+    /// Auth packets from the server using the client's [Authenticator], until the
+    /// server completes re-authentication, producing [ClientReceivedEvent::Reauthenticated].
+    /// The connection must have been made with an
+    /// [AuthenticationMethod](crate::data::property::AuthenticationMethod) property
+    /// matching [Authenticator::method]. If the server rejects re-authentication
+    /// it will disconnect, producing [ClientError::Disconnected].
+    /// Auth packets from the server have authentication method and data properties,
+    /// so the client must have a property capacity `P` of at least 2.
+    pub async fn reauthenticate(&mut self, auth_data: &[u8]) -> Result<(), ClientError> {
+        let authenticator = self
+            .authenticator
+            .as_deref()
+            .ok_or(ClientError::NoAuthenticator)?;
+        let packet = self
+            .client_state
+            .reauthenticate(authenticator.method(), auth_data)?;
+        match self.packet_client.send(packet).await {
+            Ok(()) => {
+                self.wait_for_responses(self.options.ack_timeout_millis())
+                    .await
+            }
+            Err(e) => {
+                self.client_state.error();
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Produce a packet to subscribe to a topic with default options, see
+    /// [ManagedClient::with_no_local], and update state
+    fn subscribe_packet<'b, const PP: usize>(
+        &mut self,
+        topic_name: &'b str,
+        maximum_qos: QualityOfService,
+        properties: Vec<SubscribeProperty<'b>, PP>,
+    ) -> Result<Subscribe<'b, PP, 0>, ClientError> {
+        let packet = self.client_state.subscribe_with_properties_packet(
+            self.filter.subscription_request(topic_name, maximum_qos),
+            Vec::new(),
+            properties,
+        )?;
+        self.client_state.subscribe_update(&packet)?;
+        Ok(packet)
+    }
+
+    /// Respond to an Auth packet from the server, using the response data
+    /// prepared by the [Authenticator]
+    async fn send_continue_authentication(&mut self) -> Result<(), ClientError> {
+        let authenticator = self
+            .authenticator
+            .as_deref()
+            .ok_or(ClientError::NoAuthenticator)?;
+        let packet = self
+            .client_state
+            .continue_authentication(authenticator.method(), authenticator.response_data())?;
+        if let Err(e) = self.packet_client.send(packet).await {
+            self.client_state.error();
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// The options used for timeouts
+    pub fn options(&self) -> &ClientNoQueueOptions {
+        &self.options
+    }
+
+    /// Borrow the event handler, e.g. to inspect events it has stored
+    pub fn event_handler(&self) -> &F {
+        &self.event_handler
+    }
+
+    /// Mutably borrow the event handler, e.g. to take events it has stored
+    pub fn event_handler_mut(&mut self) -> &mut F {
+        &mut self.event_handler
+    }
+
+    /// Poll until the client state is no longer waiting for responses, producing
+    /// [ClientError::TimeoutOnResponsePacket] if this takes longer than `timeout_millis`,
+    /// or [ClientError::ReceiveTimeoutServerUnresponsive] if the server stops
+    /// responding, see [ManagedClient::maintain_connection].
+    async fn wait_for_responses(&mut self, timeout_millis: u32) -> Result<(), ClientError> {
+        let poll_interval_micros = self.options.poll_interval_micros().max(1);
+        let timeout_at = self.clock.now_millis() + timeout_millis as u64;
+
+        while self.client_state.waiting_for_responses() {
+            let received = self.poll(false).await?;
+
+            if self.client_state.waiting_for_responses() && self.clock.now_millis() > timeout_at {
+                self.report(ClientDiagnosticEvent::Timeout {
+                    server_unresponsive: false,
+                });
+                return Err(ClientError::TimeoutOnResponsePacket);
+            }
+
+            // Further packets may be ready immediately
+            if !received {
+                self.delay.delay_us(poll_interval_micros).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// While connecting or connected, produce [ClientError::ReceiveTimeoutServerUnresponsive]
+    /// if the server has not responded to a connect or ping for the receive timeout,
+    /// otherwise send a ping if one is due, see [ConnectionTimer].
+    /// A ping is not sent while a previous ping is awaiting a response, instead
+    /// it is retried after the poll interval.
+    async fn maintain_connection(&mut self) -> Result<(), ClientError> {
+        if self.client_state.keep_alive().is_none() && !self.client_state.waiting_for_responses() {
+            return Ok(());
+        }
+
+        let now = self.clock.now_millis();
+        if let Err(e) = self.timer.check_receive_timeout(now) {
+            self.report(ClientDiagnosticEvent::Timeout {
+                server_unresponsive: true,
+            });
+            return Err(e);
+        }
+
+        if self.timer.ping_due(now) {
+            if self.client_state.pending_ping_count() > 0 {
+                self.timer.ping_deferred(now);
+            } else {
+                let packet = self.client_state.send_ping()?;
+                self.send(packet).await?;
+                self.timer.ping_sent(now);
+            }
+        }
+        Ok(())
+    }
+
+    fn report(&mut self, event: ClientDiagnosticEvent) {
+        if let Some(diagnostics) = self.diagnostics.as_deref_mut() {
+            diagnostics.event(event);
+        }
+    }
+
+    async fn send_wait_for_responses<PW>(
+        &mut self,
+        packet: PW,
+        timeout_millis: u32,
+    ) -> Result<(), ClientError>
+    where
+        PW: Packet + write::Write,
+    {
+        match self.packet_client.send(packet).await {
+            Ok(()) => {
+                self.wait_for_responses(timeout_millis).await?;
+                Ok(())
+            }
+            Err(e) => {
+                self.client_state.error();
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn send<PW>(&mut self, packet: PW) -> Result<(), ClientError>
+    where
+        PW: Packet + write::Write,
+    {
+        let r = self.packet_client.send(packet).await;
+        if r.is_err() {
+            self.client_state.error();
+        }
+        r?;
+        Ok(())
+    }
+
+    /// Receive and handle a packet if one is available, or wait for one if
+    /// `wait` is true, see [Client::poll]
+    async fn receive_and_handle(&mut self, wait: bool) -> Result<bool, ClientError> {
+        self.maintain_connection().await?;
+        self.send_deferred().await?;
+
+        let mut disconnected = None;
+        let result = match self.receive_for_event(wait).await? {
+            Received::Nothing => return Ok(false),
+            Received::Handled => Ok(()),
+            Received::Event(event) => self.event_handler.handle_event(event).await,
+            Received::Message => {
+                let packet = self.packet_client.last_received_server_packet::<P, 0>()?;
+                self.event_handler.handle_event(packet_event(packet)?).await
+            }
+            Received::Disconnect => {
+                let packet = self.packet_client.last_received_server_packet::<P, 0>()?;
+                let event = packet_event(packet)?;
+                if let ClientReceivedEvent::Disconnected { reason_code, .. } = &event {
+                    disconnected = Some(*reason_code);
+                }
+                self.event_handler.handle_event(event).await
+            }
+        };
+
+        // Messages that are not handled are not acknowledged
+        if let Err(e) = result {
+            self.deferred_puback = None;
+            return Err(e.into());
+        }
+        if let Some(reason_code) = disconnected {
+            return Err(ClientError::Disconnected(reason_code));
+        }
+
+        // Send any resulting packets, no need to wait for responses
+        self.send_deferred().await?;
+        if let Some(watchdog) = self.watchdog.as_deref_mut() {
+            watchdog.feed();
+        }
+
+        Ok(true)
+    }
+
+    /// Receive a packet if one is available, or wait for one if `wait` is true,
+    /// and update the client state. The result doesn't borrow the client, so
+    /// any Puback or Auth packet to send in response is deferred until
+    /// [ManagedClient::send_deferred], and an application message or Disconnect
+    /// is left in the buffer to be decoded again with
+    /// [PacketClient::last_received_server_packet].
+    async fn receive_for_event(&mut self, wait: bool) -> Result<Received<P>, ClientError> {
+        // Note we allow 0 will properties and additional subscriptions, since we
+        // shouldn't receive any messages using these, since we are a client.
+        let packet: Option<ServerPacket<'_, P, 0>> = if wait {
+            Some(self.packet_client.receive_server_packet().await?)
+        } else {
+            self.packet_client.receive_server_packet_if_ready().await?
+        };
+
+        let Some(packet) = packet else {
+            return Ok(Received::Nothing);
+        };
+
+        if let Some(diagnostics) = self.diagnostics.as_deref_mut() {
+            report_received(diagnostics, &packet);
+        }
+        self.timer
+            .packet_received(self.clock.now_millis(), packet.packet_type());
+        let event = self.client_state.receive(packet)?;
+
+        match event {
+            ClientStateReceiveEvent::Ack(acknowledgement) => {
+                if let Acknowledgement::Connack { session_present } = acknowledgement {
+                    self.filter.connected(session_present);
+                    self.timer.connected();
+                }
+                Ok(Received::Event(ClientReceivedEvent::Ack(acknowledgement)))
+            }
+
+            ClientStateReceiveEvent::Publish { publish } => {
+                if self.filter.message(&publish, None)? {
+                    Ok(Received::Message)
+                } else {
+                    Ok(Received::Handled)
+                }
+            }
+
+            ClientStateReceiveEvent::PublishAndPuback { publish, puback } => {
+                let id = *puback.packet_identifier();
+                let received = if self.filter.message(&publish, Some(id))? {
+                    Received::Message
+                } else {
+                    Received::Handled
+                };
+                // Recorded as acknowledged in send_deferred
+                self.deferred_puback = Some(puback);
+                Ok(received)
+            }
+
+            ClientStateReceiveEvent::SubscriptionGrantedBelowMaximumQos {
+                packet_identifier,
+                granted_qos,
+                maximum_qos,
+            } => Ok(Received::Event(self.filter.acknowledgement(
+                ClientReceivedEvent::SubscriptionGrantedBelowMaximumQos {
+                    packet_identifier,
+                    granted_qos,
+                    maximum_qos,
+                },
+            ))),
+
+            ClientStateReceiveEvent::SubscriptionsPartiallyRejected { reason_codes } => {
+                Ok(Received::Event(
+                    ClientReceivedEvent::SubscriptionsPartiallyRejected { reason_codes },
+                ))
+            }
+
+            ClientStateReceiveEvent::PublishedMessageHadNoMatchingSubscribers => Ok(
+                Received::Event(ClientReceivedEvent::PublishedMessageHadNoMatchingSubscribers),
+            ),
+
+            ClientStateReceiveEvent::NoSubscriptionExisted => {
+                Ok(Received::Event(ClientReceivedEvent::NoSubscriptionExisted))
+            }
+
+            ClientStateReceiveEvent::UnsubscriptionsPartiallyRejected { reason_codes } => {
+                Ok(Received::Event(
+                    ClientReceivedEvent::UnsubscriptionsPartiallyRejected { reason_codes },
+                ))
+            }
+
+            ClientStateReceiveEvent::Auth { auth } => {
+                let authenticator = self
+                    .authenticator
+                    .as_deref_mut()
+                    .ok_or(ClientError::NoAuthenticator)?;
+                let server_data = auth
+                    .properties()
+                    .get_property::<AuthenticationData>()
+                    .map(|p| p.value());
+                if auth.reason_code() == &AuthReasonCode::ContinueAuthentication {
+                    authenticator.continue_authentication(server_data)?;
+                    self.continue_authentication = true;
+                    Ok(Received::Handled)
+                } else {
+                    authenticator.authenticated(server_data)?;
+                    Ok(Received::Event(ClientReceivedEvent::Reauthenticated))
+                }
+            }
+
+            ClientStateReceiveEvent::Disconnect { disconnect: _ } => Ok(Received::Disconnect),
+        }
+    }
+
+    /// Send any Puback or Auth packet deferred by [ManagedClient::receive_for_event],
+    /// recording the packet identifier of an acknowledged message for duplicate
+    /// detection if needed
+    async fn send_deferred(&mut self) -> Result<(), ClientError> {
+        if let Some(puback) = self.deferred_puback.take() {
+            let id = *puback.packet_identifier();
+            self.send(puback).await?;
+            self.filter.acknowledged(id);
+        }
+        if core::mem::take(&mut self.continue_authentication) {
+            self.send_continue_authentication().await?;
+        }
+        Ok(())
+    }
+
+    /// If an error handling received data means the server has violated the
+    /// protocol, make a best-effort attempt to send a Disconnect with the
+    /// appropriate reason code (see [ClientError::disconnect_reason_code]) if
+    /// connected, so the server knows why the connection is closing. The
+    /// error is returned unaltered, even if the Disconnect can't be sent.
+    async fn disconnect_on_protocol_violation(&mut self, error: ClientError) -> ClientError {
+        if let Some(reason_code) = error.disconnect_reason_code() {
+            if self.client_state.disconnect().is_ok() {
+                let packet: Disconnect<'_, 0> = Disconnect::new(reason_code, Vec::new());
+                if self.send(packet).await.is_ok() && self.packet_client.flush().await.is_err() {
+                    self.client_state.error();
+                }
+            }
+        }
+        error
+    }
+}
+
+impl<'a, C, D, T, S, F, const P: usize> Client<'a> for ManagedClient<'a, C, D, T, S, F, P>
+where
+    C: Connection,
+    D: Delay,
+    T: Clock,
+    S: ClientState,
+    F: EventHandler<P>,
+{
+    async fn connect_with_will<const W: usize>(
+        &mut self,
+        settings: &ConnectionSettings<'_>,
+        will: Option<Will<'_, W>>,
+    ) -> Result<(), ClientError> {
+        settings.check_client_id()?;
+        self.connect_with_packet(connect_packet(settings, will))
+            .await
+    }
+
+    async fn connect_with_packet<const PP: usize, const W: usize>(
+        &mut self,
+        packet: Connect<'_, PP, W>,
+    ) -> Result<(), ClientError> {
+        self.client_state.connect(&packet)?;
+        self.filter.connecting(packet.clean_start());
+        self.deferred_puback = None;
+        self.continue_authentication = false;
+        self.report(ClientDiagnosticEvent::connect_attempt(&packet));
+        self.timer.connect_sent(self.clock.now_millis());
+        self.send_wait_for_responses(packet, self.options.connect_timeout_millis())
+            .await
+    }
+    async fn connect(&mut self, settings: &ConnectionSettings<'_>) -> Result<(), ClientError> {
+        self.connect_with_will::<0>(settings, None).await
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ClientError> {
+        let packet = self.client_state.disconnect()?;
+        self.send(packet).await?;
+        // Make sure the disconnect has been sent before the connection is dropped
+        self.packet_client.flush().await?;
+        Ok(())
+    }
+
+    async fn disconnect_with_session_expiry_interval(
+        &mut self,
+        session_expiry_interval: u32,
+    ) -> Result<(), ClientError> {
+        let packet = self
+            .client_state
+            .disconnect_with_session_expiry_interval(session_expiry_interval)?;
+        self.send(packet).await?;
+        self.packet_client.flush().await?;
+        Ok(())
+    }
+
+    async fn publish_with_properties<'b, const PP: usize>(
+        &'b mut self,
+        topic_name: &'b str,
+        payload: &'b [u8],
+        qos: QualityOfService,
+        retain: bool,
+        properties: Vec<PublishProperty<'b>, PP>,
+    ) -> Result<(), ClientError> {
+        let packet = self
+            .client_state
+            .publish_with_properties(topic_name, payload, qos, retain, properties)?;
+        self.filter.published(topic_name, payload);
+        self.send_wait_for_responses(packet, self.options.ack_timeout_millis())
+            .await
+    }
+
+    async fn subscribe_with_options<'b>(
+        &'b mut self,
+        topic_name: &'b str,
+        options: SubscriptionOptions,
+    ) -> Result<(), ClientError> {
+        let packet = self
+            .client_state
+            .subscribe_with_options(topic_name, options)?;
+        self.send_wait_for_responses(packet, self.options.ack_timeout_millis())
+            .await
+    }
+
+    async fn subscribe_with_properties<'b, const PP: usize>(
+        &'b mut self,
+        topic_name: &'b str,
+        maximum_qos: QualityOfService,
+        properties: Vec<SubscribeProperty<'b>, PP>,
+    ) -> Result<(), ClientError> {
+        let packet = self.subscribe_packet(topic_name, maximum_qos, properties)?;
+        self.send_wait_for_responses(packet, self.options.ack_timeout_millis())
+            .await
+    }
+
+    async fn unsubscribe<'b>(&'b mut self, topic_name: &'b str) -> Result<(), ClientError> {
+        let packet = self.client_state.unsubscribe(topic_name)?;
+        self.send_wait_for_responses(packet, self.options.ack_timeout_millis())
+            .await
+    }
+
+    async fn send_ping(&mut self) -> Result<(), ClientError> {
+        let retransmit = self.client_state.pending_ping_count() > 0;
+        let packet = self.client_state.send_ping()?;
+        if retransmit {
+            self.report(ClientDiagnosticEvent::Retransmit {
+                packet_type: PacketType::Pingreq,
+            });
+        }
+        self.send(packet).await?;
+        self.timer.ping_sent(self.clock.now_millis());
+        Ok(())
+    }
+
+    fn pending_ping_count(&self) -> u32 {
+        self.client_state.pending_ping_count()
+    }
+
+    fn keep_alive(&self) -> Option<u16> {
+        self.client_state.keep_alive()
+    }
+
+    async fn poll(&mut self, wait: bool) -> Result<bool, ClientError> {
+        match self.receive_and_handle(wait).await {
+            Err(e) => Err(self.disconnect_on_protocol_violation(e).await),
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{cell::RefCell, time::Duration};
+    use std::{collections::VecDeque, string::String};
+
+    use super::*;
+    use crate::{
+        authenticator::AuthenticatorError, client::EventHandlerError,
+        client_state::ClientStateNoQueue, data::reason_code::DisconnectReasonCode,
+        error::TransportErrorKind,
+    };
+
+    /// Data from the server waiting to be received, and data sent to the server
+    #[derive(Default)]
+    struct Pipe {
+        incoming: VecDeque<u8>,
+        sent: std::vec::Vec<u8>,
+        /// If set, the number of sends that succeed before sends fail
+        sends_before_failure: Option<usize>,
+        /// The time in microseconds, advanced only by delays
+        now_micros: u64,
+    }
+
+    impl Pipe {
+        fn server_sends(&mut self, data: &[u8]) {
+            self.incoming.extend(data);
+        }
+
+        fn take_sent(&mut self) -> std::vec::Vec<u8> {
+            core::mem::take(&mut self.sent)
+        }
+    }
+
+    /// An in-memory [Connection] using a [Pipe]. Running out of data part way
+    /// through a packet behaves like a closed connection.
+    struct MemoryConnection<'p>(&'p RefCell<Pipe>);
+
+    impl Connection for MemoryConnection<'_> {
+        type Error = TransportErrorKind;
+
+        async fn send(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+            let mut pipe = self.0.borrow_mut();
+            match pipe.sends_before_failure {
+                Some(0) => return Err(TransportErrorKind::Reset),
+                Some(n) => pipe.sends_before_failure = Some(n - 1),
+                None => {}
+            }
+            pipe.sent.extend_from_slice(buf);
+            Ok(())
+        }
+
+        async fn receive(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+            let mut pipe = self.0.borrow_mut();
+            if pipe.incoming.len() < buf.len() {
+                return Err(TransportErrorKind::Closed);
+            }
+            for b in buf.iter_mut() {
+                *b = pipe.incoming.pop_front().unwrap();
+            }
+            Ok(())
+        }
+
+        async fn receive_if_ready(&mut self, buf: &mut [u8]) -> Result<bool, Self::Error> {
+            if self.0.borrow().incoming.is_empty() {
+                return Ok(false);
+            }
+            self.receive(buf).await?;
+            Ok(true)
+        }
+
+        async fn receive_available(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let mut pipe = self.0.borrow_mut();
+            if pipe.incoming.is_empty() {
+                return Err(TransportErrorKind::Closed);
+            }
+            let len = buf.len().min(pipe.incoming.len());
+            for b in buf[0..len].iter_mut() {
+                *b = pipe.incoming.pop_front().unwrap();
+            }
+            Ok(len)
+        }
+    }
+
+    /// A [Delay] and [Clock] using the time in a [Pipe], so that time passes
+    /// only while the client is delaying
+    #[derive(Clone, Copy)]
+    struct PipeTime<'p>(&'p RefCell<Pipe>);
+
+    impl Delay for PipeTime<'_> {
+        async fn delay_us(&mut self, us: u32) {
+            self.0.borrow_mut().now_micros += us as u64;
+        }
+    }
+
+    impl Clock for PipeTime<'_> {
+        fn now_millis(&self) -> u64 {
+            self.0.borrow().now_micros / 1000
+        }
+    }
+
+    /// Records the topic name and payload of each application message,
+    /// and the number of other events
+    #[derive(Default)]
+    struct Events {
+        messages: std::vec::Vec<(String, std::vec::Vec<u8>)>,
+        reauthenticated: usize,
+        others: usize,
+    }
+
+    impl EventHandler<16> for Events {
+        async fn handle_event(
+            &mut self,
+            event: ClientReceivedEvent<'_, 16>,
+        ) -> Result<(), EventHandlerError> {
+            match event {
+                ClientReceivedEvent::ApplicationMessage(message) => self
+                    .messages
+                    .push((message.topic_name.into(), message.payload.into())),
+                ClientReceivedEvent::Reauthenticated => self.reauthenticated += 1,
+                _ => self.others += 1,
+            }
+            Ok(())
+        }
+    }
+
+    type TestClient<'a, 'p> = ManagedClient<
+        'a,
+        MemoryConnection<'p>,
+        PipeTime<'p>,
+        PipeTime<'p>,
+        ClientStateNoQueue,
+        Events,
+        16,
+    >;
+
+    const CONNACK: [u8; 5] = [0x20, 0x03, 0x00, 0x00, 0x00];
+
+    fn client<'a, 'p>(
+        pipe: &'p RefCell<Pipe>,
+        buf: &'a mut [u8],
+        options: ClientOptions,
+    ) -> TestClient<'a, 'p> {
+        ManagedClient::new(
+            MemoryConnection(pipe),
+            buf,
+            PipeTime(pipe),
+            PipeTime(pipe),
+            options,
+            Events::default(),
+        )
+    }
+
+    fn options() -> ClientOptions {
+        ClientOptions::new(
+            ClientNoQueueOptions::new(100)
+                .with_receive_timeout(1000)
+                .with_poll_interval(1000),
+        )
+    }
+
+    async fn connect(client: &mut TestClient<'_, '_>, pipe: &RefCell<Pipe>) {
+        pipe.borrow_mut().server_sends(&CONNACK);
+        client
+            .connect(&ConnectionSettings::unauthenticated("a"))
+            .await
+            .unwrap();
+        assert_eq!(pipe.borrow_mut().take_sent()[0], 0x10);
+    }
+
+    #[tokio::test]
+    async fn managed_client_publishes_and_subscribes_waiting_for_acks() {
+        let pipe = RefCell::new(Pipe::default());
+        let mut buf = [0; 256];
+        let mut client = client(&pipe, &mut buf, options().with_no_local(true));
+        connect(&mut client, &pipe).await;
+        assert_eq!(client.event_handler().others, 1);
+
+        // Qos 1 publish with packet identifier 1, acknowledged by the server
+        pipe.borrow_mut().server_sends(&[0x40, 0x02, 0x00, 0x01]);
+        client
+            .publish("t", b"p", QualityOfService::Qos1, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            pipe.borrow_mut().take_sent(),
+            [0x32, 0x07, 0x00, 0x01, b't', 0x00, 0x01, 0x00, b'p']
+        );
+        assert_eq!(client.pending_counts(), PendingCounts::default());
+
+        // Subscribe with packet identifier 2 and NoLocal, granted qos 1
+        pipe.borrow_mut()
+            .server_sends(&[0x90, 0x04, 0x00, 0x02, 0x00, 0x01]);
+        client.subscribe("t", QualityOfService::Qos1).await.unwrap();
+        let subscribe = pipe.borrow_mut().take_sent();
+        assert_eq!(subscribe[0], 0x82);
+        assert_eq!(subscribe.last(), Some(&0x05));
+        assert!(client.is_subscribed("t"));
+        assert_eq!(client.event_handler().others, 3);
+    }
+
+    #[tokio::test]
+    async fn managed_client_handles_packets_received_together_from_receive_buffer() {
+        let pipe = RefCell::new(Pipe::default());
+        let mut buf = [0; 256];
+        let mut rx_buf = [0; 256];
+        let mut client = client(&pipe, &mut buf, options()).with_receive_buffer(&mut rx_buf);
+        connect(&mut client, &pipe).await;
+
+        // Two qos 0 messages, received in one read
+        pipe.borrow_mut().server_sends(&[
+            0x30, 0x05, 0x00, 0x01, b't', 0x00, b'a', 0x30, 0x05, 0x00, 0x01, b't', 0x00, b'b',
+        ]);
+        assert!(client.poll(true).await.unwrap());
+        assert!(pipe.borrow().incoming.is_empty());
+
+        // The second message is handled without receiving any more data
+        assert!(client.poll(false).await.unwrap());
+        assert!(!client.poll(false).await.unwrap());
+        assert_eq!(
+            client.event_handler().messages,
+            [("t".into(), b"a".to_vec()), ("t".into(), b"b".to_vec())]
+        );
+    }
+
+    #[tokio::test]
+    async fn managed_client_acknowledges_messages_and_drops_duplicates() {
+        let pipe = RefCell::new(Pipe::default());
+        let mut buf = [0; 256];
+        let mut client = client(&pipe, &mut buf, options().with_duplicate_detection(true));
+        connect(&mut client, &pipe).await;
+
+        // Nothing to receive
+        assert!(!client.poll(false).await.unwrap());
+
+        // Qos 1 message with packet identifier 5, then redelivered with DUP set
+        let message = [0x32, 0x07, 0x00, 0x01, b't', 0x00, 0x05, 0x00, b'p'];
+        let mut duplicate = message;
+        duplicate[0] |= 0x08;
+
+        pipe.borrow_mut().server_sends(&message);
+        assert!(client.poll(false).await.unwrap());
+        assert_eq!(pipe.borrow_mut().take_sent(), [0x40, 0x02, 0x00, 0x05]);
+
+        pipe.borrow_mut().server_sends(&duplicate);
+        assert!(client.poll(false).await.unwrap());
+        assert_eq!(pipe.borrow_mut().take_sent(), [0x40, 0x02, 0x00, 0x05]);
+
+        assert_eq!(
+            client.event_handler().messages,
+            [(String::from("t"), b"p".to_vec())]
+        );
+    }
+
+    #[tokio::test]
+    async fn managed_client_suppresses_local_echoes() {
+        let pipe = RefCell::new(Pipe::default());
+        let mut buf = [0; 256];
+        let mut client = client(&pipe, &mut buf, options().with_local_echo_suppression(true));
+        connect(&mut client, &pipe).await;
+
+        client
+            .publish("t", b"p", QualityOfService::Qos0, false)
+            .await
+            .unwrap();
+        pipe.borrow_mut().take_sent();
+
+        // The echo of our publish is dropped, but the same message again is not
+        let message = [0x30, 0x05, 0x00, 0x01, b't', 0x00, b'p'];
+        pipe.borrow_mut().server_sends(&message);
+        assert!(client.poll(false).await.unwrap());
+        assert!(client.event_handler().messages.is_empty());
+        pipe.borrow_mut().server_sends(&message);
+        assert!(client.poll(false).await.unwrap());
+        assert_eq!(client.event_handler().messages.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn managed_client_publish_batch_records_only_sent_messages() {
+        let pipe = RefCell::new(Pipe::default());
+        // Room for the connect packet, or two of the publishes below
+        let mut buf = [0; 32];
+        let mut client = client(&pipe, &mut buf, options().with_local_echo_suppression(true));
+        connect(&mut client, &pipe).await;
+
+        // The first two messages are sent together, then sending the third fails
+        pipe.borrow_mut().sends_before_failure = Some(1);
+        let messages = [
+            BatchMessage::new("a", b"0123456789", false),
+            BatchMessage::new("b", b"0123456789", false),
+            BatchMessage::new("c", b"0123456789", false),
+        ];
+        assert_eq!(
+            client.publish_batch(&messages).await,
+            Err(ClientError::Transport(TransportErrorKind::Reset))
+        );
+        assert_eq!(pipe.borrow_mut().take_sent().len(), 32);
+        assert!(client.filter.local_publishes.take("a", b"0123456789"));
+        assert!(client.filter.local_publishes.take("b", b"0123456789"));
+        assert!(!client.filter.local_publishes.take("c", b"0123456789"));
+
+        // The failure leaves the client state errored
+        assert!(matches!(
+            client
+                .publish("t", b"p", QualityOfService::Qos0, false)
+                .await,
+            Err(ClientError::ClientState(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn managed_client_publish_batch_errors_state_when_connection_fails() {
+        let pipe = RefCell::new(Pipe::default());
+        let mut buf = [0; 256];
+        let mut client = client(&pipe, &mut buf, options().with_local_echo_suppression(true));
+        connect(&mut client, &pipe).await;
+
+        pipe.borrow_mut().sends_before_failure = Some(0);
+        assert_eq!(
+            client
+                .publish_batch(&[BatchMessage::new("t", b"p", false)])
+                .await,
+            Err(ClientError::Transport(TransportErrorKind::Reset))
+        );
+        assert!(!client.filter.local_publishes.take("t", b"p"));
+        assert!(matches!(
+            client
+                .publish("t", b"p", QualityOfService::Qos0, false)
+                .await,
+            Err(ClientError::ClientState(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn managed_client_times_out_waiting_for_ack() {
+        let pipe = RefCell::new(Pipe::default());
+        let mut buf = [0; 256];
+        let mut client = client(&pipe, &mut buf, options());
+        connect(&mut client, &pipe).await;
+
+        assert_eq!(
+            client
+                .publish("t", b"p", QualityOfService::Qos1, false)
+                .await,
+            Err(ClientError::TimeoutOnResponsePacket)
+        );
+    }
+
+    #[tokio::test]
+    async fn managed_client_pings_and_detects_unresponsive_server() {
+        let pipe = RefCell::new(Pipe::default());
+        let mut buf = [0; 256];
+        let mut client = client(&pipe, &mut buf, options());
+        connect(&mut client, &pipe).await;
+
+        // Pings are sent at half the receive timeout
+        pipe.borrow_mut().now_micros = 500_000;
+        assert_eq!(client.poll(false).await, Ok(false));
+        assert!(pipe.borrow_mut().take_sent().is_empty());
+        pipe.borrow_mut().now_micros = 501_000;
+        assert_eq!(client.poll(false).await, Ok(false));
+        assert_eq!(pipe.borrow_mut().take_sent(), [0xc0, 0x00]);
+
+        // Pingresp resets the receive timeout
+        pipe.borrow_mut().server_sends(&[0xd0, 0x00]);
+        pipe.borrow_mut().now_micros = 900_000;
+        assert_eq!(client.poll(false).await, Ok(true));
+        assert_eq!(client.pending_ping_count(), 0);
+
+        pipe.borrow_mut().now_micros = 1_002_000;
+        assert_eq!(client.poll(false).await, Ok(false));
+        assert_eq!(pipe.borrow_mut().take_sent(), [0xc0, 0x00]);
+
+        // No further ping is sent while one is awaiting a response
+        pipe.borrow_mut().now_micros = 1_900_000;
+        assert_eq!(client.poll(false).await, Ok(false));
+        assert!(pipe.borrow_mut().take_sent().is_empty());
+
+        pipe.borrow_mut().now_micros = 1_901_000;
+        assert_eq!(
+            client.poll(false).await,
+            Err(ClientError::ReceiveTimeoutServerUnresponsive)
+        );
+    }
+
+    #[tokio::test]
+    async fn managed_client_health_shows_responses_and_pending_requests() {
+        let pipe = RefCell::new(Pipe::default());
+        let mut buf = [0; 256];
+        let mut client = client(&pipe, &mut buf, options());
+        assert_eq!(
+            client.health(),
+            Health {
+                since_last_received: None,
+                until_receive_timeout: None,
+                pending: PendingCounts::default(),
+            }
+        );
+
+        pipe.borrow_mut().now_micros = 100_000;
+        connect(&mut client, &pipe).await;
+        pipe.borrow_mut().now_micros = 400_000;
+        client.send_ping().await.unwrap();
+        assert_eq!(
+            client.health(),
+            Health {
+                since_last_received: Some(Duration::from_millis(300)),
+                until_receive_timeout: Some(Duration::from_millis(700)),
+                pending: PendingCounts {
+                    pings: 1,
+                    ..Default::default()
+                },
+            }
+        );
+
+        // Pingresp
+        pipe.borrow_mut().server_sends(&[0xd0, 0x00]);
+        pipe.borrow_mut().now_micros = 450_000;
+        assert_eq!(client.poll(false).await, Ok(true));
+        assert_eq!(
+            client.health(),
+            Health {
+                since_last_received: Some(Duration::ZERO),
+                until_receive_timeout: Some(Duration::from_millis(1000)),
+                pending: PendingCounts::default(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn managed_client_reports_server_disconnect() {
+        let pipe = RefCell::new(Pipe::default());
+        let mut buf = [0; 256];
+        let mut client = client(&pipe, &mut buf, options());
+        connect(&mut client, &pipe).await;
+
+        // Disconnect with reason code Server shutting down
+        pipe.borrow_mut().server_sends(&[0xe0, 0x01, 0x8b]);
+        assert_eq!(
+            client.poll(false).await,
+            Err(ClientError::Disconnected(
+                DisconnectReasonCode::ServerShuttingDown
+            ))
+        );
+        assert_eq!(client.event_handler().others, 2);
+        assert!(pipe.borrow_mut().take_sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn managed_client_disconnects_with_malformed_packet() {
+        let pipe = RefCell::new(Pipe::default());
+        let mut buf = [0; 256];
+        let mut client = client(&pipe, &mut buf, options());
+        connect(&mut client, &pipe).await;
+
+        // Publish with qos bits set to 3
+        pipe.borrow_mut()
+            .server_sends(&[0x36, 0x07, 0x00, 0x01, b't', 0x00, 0x05, 0x00, b'p']);
+        assert_eq!(
+            client.poll(false).await,
+            Err(ClientError::PacketRead(PacketReadError::InvalidPacketType))
+        );
+        assert_eq!(pipe.borrow_mut().take_sent(), [0xe0, 0x01, 0x81]);
+    }
+
+    #[tokio::test]
+    async fn managed_client_provides_full_transport_error() {
+        let pipe = RefCell::new(Pipe::default());
+        let mut buf = [0; 256];
+        let mut client = client(&pipe, &mut buf, options());
+        connect(&mut client, &pipe).await;
+
+        // The connection closes while waiting for a packet
+        let error = client.poll(true).await.unwrap_err();
+        assert_eq!(error, ClientError::Transport(TransportErrorKind::Closed));
+        assert_eq!(
+            client.full_error(error),
+            ClientError::Transport(TransportErrorKind::Closed)
+        );
+
+        // The error from the connection has been taken
+        assert_eq!(
+            client.full_error(error),
+            ClientError::PacketRead(PacketReadError::Transport(TransportErrorKind::Closed))
+        );
+        assert_eq!(
+            client.full_error(ClientError::WouldBlock),
+            ClientError::WouldBlock
+        );
+    }
+
+    #[tokio::test]
+    async fn managed_client_disconnects_with_protocol_error_on_unexpected_puback() {
+        let pipe = RefCell::new(Pipe::default());
+        let mut buf = [0; 256];
+        let mut client = client(&pipe, &mut buf, options());
+        connect(&mut client, &pipe).await;
+
+        // Puback with no matching publish
+        pipe.borrow_mut().server_sends(&[0x40, 0x02, 0x00, 0x01]);
+        assert!(client.poll(false).await.is_err());
+        assert_eq!(pipe.borrow_mut().take_sent(), [0xe0, 0x01, 0x82]);
+    }
+
+    #[tokio::test]
+    async fn managed_client_disconnects_with_topic_alias_invalid_on_empty_topic_name() {
+        let pipe = RefCell::new(Pipe::default());
+        let mut buf = [0; 256];
+        let mut client = client(&pipe, &mut buf, options());
+        connect(&mut client, &pipe).await;
+
+        // Publish with an empty topic name and no topic alias
+        pipe.borrow_mut()
+            .server_sends(&[0x30, 0x04, 0x00, 0x00, 0x00, b'p']);
+        assert_eq!(
+            client.poll(false).await,
+            Err(ClientError::EmptyTopicNameWithAliasesDisabled)
+        );
+        assert_eq!(pipe.borrow_mut().take_sent(), [0xe0, 0x01, 0x94]);
+    }
+
+    #[tokio::test]
+    async fn managed_client_disconnects_with_packet_too_large() {
+        let pipe = RefCell::new(Pipe::default());
+        let mut buf = [0; 32];
+        let mut client = client(&pipe, &mut buf, options());
+        connect(&mut client, &pipe).await;
+
+        // Publish with a remaining length of 64, more than the buffer holds
+        let mut publish = vec![0x30, 0x40, 0x00, 0x01, b't'];
+        publish.resize(66, b'p');
+        pipe.borrow_mut().server_sends(&publish);
+        assert_eq!(
+            client.poll(false).await,
+            Err(ClientError::PacketRead(
+                PacketReadError::PacketTooLargeForBuffer
+            ))
+        );
+        assert_eq!(pipe.borrow_mut().take_sent(), [0xe0, 0x01, 0x95]);
+    }
+
+    /// Responds to the server with fixed data, recording the final server data
+    #[derive(Default)]
+    struct TestAuthenticator {
+        server_data: Option<std::vec::Vec<u8>>,
+    }
+
+    impl Authenticator for TestAuthenticator {
+        fn method(&self) -> &str {
+            "m"
+        }
+
+        fn continue_authentication(
+            &mut self,
+            _server_data: Option<&[u8]>,
+        ) -> Result<(), AuthenticatorError> {
+            Ok(())
+        }
+
+        fn response_data(&self) -> &[u8] {
+            b"r"
+        }
+
+        fn authenticated(&mut self, server_data: Option<&[u8]>) -> Result<(), AuthenticatorError> {
+            self.server_data = server_data.map(|d| d.to_vec());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn managed_client_reauthenticates_with_authenticator() {
+        let pipe = RefCell::new(Pipe::default());
+        let mut authenticator = TestAuthenticator::default();
+        let mut buf = [0; 256];
+        let mut client = client(&pipe, &mut buf, options()).with_authenticator(&mut authenticator);
+
+        let mut properties: Vec<ConnectProperty<'_>, 1> = Vec::new();
+        properties
+            .push(ConnectProperty::AuthenticationMethod("m".into()))
+            .unwrap();
+        pipe.borrow_mut().server_sends(&CONNACK);
+        client
+            .connect_with_packet::<1, 0>(Connect::new(60, None, None, "a", true, None, properties))
+            .await
+            .unwrap();
+        pipe.borrow_mut().take_sent();
+
+        // The server continues authentication with data "s", then completes it with data "t"
+        pipe.borrow_mut().server_sends(&[
+            0xf0, 0x0a, 0x18, 0x08, 0x15, 0x00, 0x01, b'm', 0x16, 0x00, 0x01, b's',
+        ]);
+        pipe.borrow_mut().server_sends(&[
+            0xf0, 0x0a, 0x00, 0x08, 0x15, 0x00, 0x01, b'm', 0x16, 0x00, 0x01, b't',
+        ]);
+        client.reauthenticate(&[]).await.unwrap();
+
+        // Re-authenticate with no data, so no AuthenticationData property,
+        // then respond with the authenticator's data
+        assert_eq!(
+            pipe.borrow_mut().take_sent(),
+            [
+                0xf0, 0x06, 0x19, 0x04, 0x15, 0x00, 0x01, b'm', 0xf0, 0x0a, 0x18, 0x08, 0x15, 0x00,
+                0x01, b'm', 0x16, 0x00, 0x01, b'r'
+            ]
+        );
+        assert_eq!(client.event_handler().reauthenticated, 1);
+        assert_eq!(client.event_handler().others, 1);
+        drop(client);
+        assert_eq!(authenticator.server_data, Some(b"t".to_vec()));
+    }
+
+    #[test]
+    fn connect_packet_advertises_receive_maximum_only_if_set() {
+        let settings = ConnectionSettings::unauthenticated("a");
+        let packet = connect_packet::<0>(&settings, None);
+        assert!(!packet
+            .properties
+            .iter()
+            .any(|p| matches!(p, ConnectProperty::ReceiveMaximum(_))));
+
+        let settings = settings.with_receive_maximum(NonZeroU16::MIN);
+        let packet = connect_packet::<0>(&settings, None);
+        assert!(packet
+            .properties
+            .contains(&ConnectProperty::ReceiveMaximum(1.into())));
+    }
+}
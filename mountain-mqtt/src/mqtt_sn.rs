@@ -0,0 +1,1340 @@
+//! Support for MQTT-SN (MQTT for Sensor Networks) v1.2, for devices on UDP,
+//! 802.15.4, LoRa or other datagram links that can't maintain a TCP connection
+//! to an MQTT server, and instead talk to an MQTT-SN gateway:
+//!
+//! - Encoding and decoding of the messages used by a client, see [Message]
+//! - A client state machine, tracking the connection, the outstanding request,
+//!   and the topic ids registered with the gateway, see [SnClientState]
+//!
+//! As with [ClientState](crate::client_state::ClientState), the state machine
+//! does no IO itself - it produces the messages to send, and handles the
+//! messages received, so can be used with any datagram transport. Each
+//! datagram holds exactly one message.
+//!
+//! Topic names are long compared to the payloads sent over constrained links,
+//! so MQTT-SN publishes to a two byte topic id instead. A client registers a
+//! topic name with [SnClientState::register] to get its id, or uses a
+//! predefined topic id or two character short topic name agreed with the
+//! gateway in advance. Gateway discovery, wills, sleeping clients and qos -1
+//! are not supported.
+
+#[cfg(feature = "display")]
+use core::fmt::{Display, Formatter};
+
+use heapless::{String, Vec};
+
+use crate::{
+    codec::{
+        mqtt_reader::{self, MqttBufReader, MqttReader},
+        mqtt_writer::{self, MqttWriter},
+        write::Write,
+    },
+    data::quality_of_service::QualityOfService,
+    error::{PacketReadError, PacketWriteError},
+};
+
+/// The protocol id in an MQTT-SN Connect message
+pub const PROTOCOL_ID: u8 = 0x01;
+
+/// The largest message length that can be encoded with a one byte length field
+const SHORT_LENGTH_MAX: usize = 255;
+
+/// The first byte of a three byte length field
+const LONG_LENGTH_MARKER: u8 = 0x01;
+
+const DUP_SHIFT: u8 = 7;
+const QOS_SHIFT: u8 = 5;
+const RETAIN_SHIFT: u8 = 4;
+const CLEAN_SESSION_SHIFT: u8 = 2;
+const QOS_MASK: u8 = 0b11;
+const TOPIC_ID_TYPE_MASK: u8 = 0b11;
+
+/// The type of an MQTT-SN message, from section 5.2.2 of the MQTT-SN v1.2
+/// specification. Only the types used by a client without wills are supported.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MsgType {
+    Connect = 0x04,
+    Connack = 0x05,
+    Register = 0x0A,
+    Regack = 0x0B,
+    Publish = 0x0C,
+    Puback = 0x0D,
+    Subscribe = 0x12,
+    Suback = 0x13,
+    Unsubscribe = 0x14,
+    Unsuback = 0x15,
+    Pingreq = 0x16,
+    Pingresp = 0x17,
+    Disconnect = 0x18,
+}
+
+impl TryFrom<u8> for MsgType {
+    type Error = PacketReadError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x04 => Ok(Self::Connect),
+            0x05 => Ok(Self::Connack),
+            0x0A => Ok(Self::Register),
+            0x0B => Ok(Self::Regack),
+            0x0C => Ok(Self::Publish),
+            0x0D => Ok(Self::Puback),
+            0x12 => Ok(Self::Subscribe),
+            0x13 => Ok(Self::Suback),
+            0x14 => Ok(Self::Unsubscribe),
+            0x15 => Ok(Self::Unsuback),
+            0x16 => Ok(Self::Pingreq),
+            0x17 => Ok(Self::Pingresp),
+            0x18 => Ok(Self::Disconnect),
+            _ => Err(PacketReadError::InvalidPacketType),
+        }
+    }
+}
+
+/// The return code in Connack, Regack, Puback and Suback messages
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReturnCode {
+    Accepted,
+    RejectedCongestion,
+    RejectedInvalidTopicId,
+    RejectedNotSupported,
+    Unknown(u8),
+}
+
+impl From<u8> for ReturnCode {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => Self::Accepted,
+            0x01 => Self::RejectedCongestion,
+            0x02 => Self::RejectedInvalidTopicId,
+            0x03 => Self::RejectedNotSupported,
+            value => Self::Unknown(value),
+        }
+    }
+}
+
+impl From<ReturnCode> for u8 {
+    fn from(value: ReturnCode) -> Self {
+        match value {
+            ReturnCode::Accepted => 0x00,
+            ReturnCode::RejectedCongestion => 0x01,
+            ReturnCode::RejectedInvalidTopicId => 0x02,
+            ReturnCode::RejectedNotSupported => 0x03,
+            ReturnCode::Unknown(value) => value,
+        }
+    }
+}
+
+#[cfg(feature = "display")]
+impl Display for ReturnCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Accepted => write!(f, "Accepted"),
+            Self::RejectedCongestion => write!(f, "RejectedCongestion"),
+            Self::RejectedInvalidTopicId => write!(f, "RejectedInvalidTopicId"),
+            Self::RejectedNotSupported => write!(f, "RejectedNotSupported"),
+            Self::Unknown(value) => write!(f, "Unknown({})", value),
+        }
+    }
+}
+
+/// The topic of a Publish message
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TopicId {
+    /// A topic id registered with the gateway by a Register message
+    Normal(u16),
+
+    /// A topic id agreed with the gateway in advance
+    Predefined(u16),
+
+    /// A topic name of exactly two characters
+    Short([u8; 2]),
+}
+
+impl TopicId {
+    fn id_type(&self) -> u8 {
+        match self {
+            Self::Normal(_) => 0b00,
+            Self::Predefined(_) => 0b01,
+            Self::Short(_) => 0b10,
+        }
+    }
+
+    fn put<'w, W: MqttWriter<'w>>(&self, writer: &mut W) -> mqtt_writer::Result<()> {
+        match self {
+            Self::Normal(id) | Self::Predefined(id) => writer.put_u16(*id),
+            Self::Short(name) => writer.put_slice(name),
+        }
+    }
+
+    fn get<'a, R: MqttReader<'a>>(id_type: u8, reader: &mut R) -> mqtt_reader::Result<Self> {
+        match id_type {
+            0b00 => Ok(Self::Normal(reader.get_u16()?)),
+            0b01 => Ok(Self::Predefined(reader.get_u16()?)),
+            0b10 => {
+                let name = reader.get_slice(2)?;
+                Ok(Self::Short([name[0], name[1]]))
+            }
+            _ => Err(PacketReadError::IncorrectPacketType),
+        }
+    }
+}
+
+/// The topic of a Subscribe or Unsubscribe message
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SubscribeTopic<'a> {
+    /// A topic filter, which may contain wildcards
+    Name(&'a str),
+
+    /// A topic id agreed with the gateway in advance
+    Predefined(u16),
+
+    /// A topic name of exactly two characters
+    Short([u8; 2]),
+}
+
+impl SubscribeTopic<'_> {
+    fn id_type(&self) -> u8 {
+        match self {
+            Self::Name(_) => 0b00,
+            Self::Predefined(_) => 0b01,
+            Self::Short(_) => 0b10,
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        match self {
+            Self::Name(name) => name.len(),
+            Self::Predefined(_) | Self::Short(_) => 2,
+        }
+    }
+
+    fn put<'w, W: MqttWriter<'w>>(&self, writer: &mut W) -> mqtt_writer::Result<()> {
+        match self {
+            Self::Name(name) => writer.put_slice(name.as_bytes()),
+            Self::Predefined(id) => writer.put_u16(*id),
+            Self::Short(name) => writer.put_slice(name),
+        }
+    }
+}
+
+/// An MQTT-SN message
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Message<'a> {
+    Connect {
+        clean_session: bool,
+        duration: u16,
+        client_id: &'a str,
+    },
+    Connack {
+        return_code: ReturnCode,
+    },
+    Register {
+        topic_id: u16,
+        msg_id: u16,
+        topic_name: &'a str,
+    },
+    Regack {
+        topic_id: u16,
+        msg_id: u16,
+        return_code: ReturnCode,
+    },
+    Publish {
+        dup: bool,
+        qos: QualityOfService,
+        retain: bool,
+        topic_id: TopicId,
+        msg_id: u16,
+        data: &'a [u8],
+    },
+    Puback {
+        topic_id: u16,
+        msg_id: u16,
+        return_code: ReturnCode,
+    },
+    Subscribe {
+        dup: bool,
+        qos: QualityOfService,
+        msg_id: u16,
+        topic: SubscribeTopic<'a>,
+    },
+    Suback {
+        qos: QualityOfService,
+        topic_id: u16,
+        msg_id: u16,
+        return_code: ReturnCode,
+    },
+    Unsubscribe {
+        msg_id: u16,
+        topic: SubscribeTopic<'a>,
+    },
+    Unsuback {
+        msg_id: u16,
+    },
+    Pingreq,
+    Pingresp,
+    Disconnect {
+        duration: Option<u16>,
+    },
+}
+
+fn flags(dup: bool, qos: QualityOfService, retain: bool, topic_id_type: u8) -> u8 {
+    ((dup as u8) << DUP_SHIFT)
+        | ((qos as u8) << QOS_SHIFT)
+        | ((retain as u8) << RETAIN_SHIFT)
+        | topic_id_type
+}
+
+fn qos_from_flags(flags: u8) -> mqtt_reader::Result<QualityOfService> {
+    match (flags >> QOS_SHIFT) & QOS_MASK {
+        0 => Ok(QualityOfService::Qos0),
+        1 => Ok(QualityOfService::Qos1),
+        2 => Ok(QualityOfService::Qos2),
+        // Qos -1 is not supported
+        _ => Err(PacketReadError::InvalidQosValue),
+    }
+}
+
+fn get_str<'a, R: MqttReader<'a>>(reader: &mut R, len: usize) -> mqtt_reader::Result<&'a str> {
+    core::str::from_utf8(reader.get_slice(len)?).map_err(|_| PacketReadError::InvalidUtf8)
+}
+
+impl<'a> Message<'a> {
+    pub fn msg_type(&self) -> MsgType {
+        match self {
+            Self::Connect { .. } => MsgType::Connect,
+            Self::Connack { .. } => MsgType::Connack,
+            Self::Register { .. } => MsgType::Register,
+            Self::Regack { .. } => MsgType::Regack,
+            Self::Publish { .. } => MsgType::Publish,
+            Self::Puback { .. } => MsgType::Puback,
+            Self::Subscribe { .. } => MsgType::Subscribe,
+            Self::Suback { .. } => MsgType::Suback,
+            Self::Unsubscribe { .. } => MsgType::Unsubscribe,
+            Self::Unsuback { .. } => MsgType::Unsuback,
+            Self::Pingreq => MsgType::Pingreq,
+            Self::Pingresp => MsgType::Pingresp,
+            Self::Disconnect { .. } => MsgType::Disconnect,
+        }
+    }
+
+    /// The length of the message after the length and message type fields
+    fn body_len(&self) -> usize {
+        match self {
+            Self::Connect { client_id, .. } => 4 + client_id.len(),
+            Self::Connack { .. } => 1,
+            Self::Register { topic_name, .. } => 4 + topic_name.len(),
+            Self::Regack { .. } | Self::Puback { .. } => 5,
+            Self::Publish { data, .. } => 5 + data.len(),
+            Self::Subscribe { topic, .. } | Self::Unsubscribe { topic, .. } => {
+                3 + topic.encoded_len()
+            }
+            Self::Suback { .. } => 6,
+            Self::Unsuback { .. } => 2,
+            Self::Pingreq | Self::Pingresp => 0,
+            Self::Disconnect { duration } => {
+                if duration.is_some() {
+                    2
+                } else {
+                    0
+                }
+            }
+        }
+    }
+
+    /// The total encoded length of the message, including the length field
+    pub fn encoded_len(&self) -> usize {
+        let len = 2 + self.body_len();
+        if len > SHORT_LENGTH_MAX {
+            len + 2
+        } else {
+            len
+        }
+    }
+
+    /// Decode a message from `buf`, which must contain exactly one message,
+    /// e.g. the contents of a datagram
+    pub fn read(buf: &'a [u8]) -> Result<Self, PacketReadError> {
+        let mut reader = MqttBufReader::new(buf);
+        let len = match reader.get_u8()? {
+            LONG_LENGTH_MARKER => reader.get_u16()? as usize,
+            len => len as usize,
+        };
+        if len != buf.len() {
+            return Err(PacketReadError::IncorrectPacketLength);
+        }
+        let msg_type = MsgType::try_from(reader.get_u8()?)?;
+        let body_len = len - reader.position();
+
+        let message = match msg_type {
+            MsgType::Connect => {
+                let flags = reader.get_u8()?;
+                if reader.get_u8()? != PROTOCOL_ID {
+                    return Err(PacketReadError::UnsupportedProtocolVersion);
+                }
+                let duration = reader.get_u16()?;
+                let client_id = get_str(&mut reader, body_len.saturating_sub(4))?;
+                Self::Connect {
+                    clean_session: flags & (1 << CLEAN_SESSION_SHIFT) != 0,
+                    duration,
+                    client_id,
+                }
+            }
+            MsgType::Connack => Self::Connack {
+                return_code: reader.get_u8()?.into(),
+            },
+            MsgType::Register => {
+                let topic_id = reader.get_u16()?;
+                let msg_id = reader.get_u16()?;
+                let topic_name = get_str(&mut reader, body_len.saturating_sub(4))?;
+                Self::Register {
+                    topic_id,
+                    msg_id,
+                    topic_name,
+                }
+            }
+            MsgType::Regack => Self::Regack {
+                topic_id: reader.get_u16()?,
+                msg_id: reader.get_u16()?,
+                return_code: reader.get_u8()?.into(),
+            },
+            MsgType::Publish => {
+                let flags = reader.get_u8()?;
+                let topic_id = TopicId::get(flags & TOPIC_ID_TYPE_MASK, &mut reader)?;
+                let msg_id = reader.get_u16()?;
+                let data = reader.get_slice(body_len.saturating_sub(5))?;
+                Self::Publish {
+                    dup: flags & (1 << DUP_SHIFT) != 0,
+                    qos: qos_from_flags(flags)?,
+                    retain: flags & (1 << RETAIN_SHIFT) != 0,
+                    topic_id,
+                    msg_id,
+                    data,
+                }
+            }
+            MsgType::Puback => Self::Puback {
+                topic_id: reader.get_u16()?,
+                msg_id: reader.get_u16()?,
+                return_code: reader.get_u8()?.into(),
+            },
+            MsgType::Subscribe | MsgType::Unsubscribe => {
+                let flags = reader.get_u8()?;
+                let msg_id = reader.get_u16()?;
+                let topic = match flags & TOPIC_ID_TYPE_MASK {
+                    0b00 => SubscribeTopic::Name(get_str(&mut reader, body_len.saturating_sub(3))?),
+                    id_type => match TopicId::get(id_type, &mut reader)? {
+                        TopicId::Short(name) => SubscribeTopic::Short(name),
+                        TopicId::Predefined(id) | TopicId::Normal(id) => {
+                            SubscribeTopic::Predefined(id)
+                        }
+                    },
+                };
+                if msg_type == MsgType::Subscribe {
+                    Self::Subscribe {
+                        dup: flags & (1 << DUP_SHIFT) != 0,
+                        qos: qos_from_flags(flags)?,
+                        msg_id,
+                        topic,
+                    }
+                } else {
+                    Self::Unsubscribe { msg_id, topic }
+                }
+            }
+            MsgType::Suback => Self::Suback {
+                qos: qos_from_flags(reader.get_u8()?)?,
+                topic_id: reader.get_u16()?,
+                msg_id: reader.get_u16()?,
+                return_code: reader.get_u8()?.into(),
+            },
+            MsgType::Unsuback => Self::Unsuback {
+                msg_id: reader.get_u16()?,
+            },
+            MsgType::Pingreq => {
+                // Skip the client id of a sleeping client, which is not supported
+                reader.get_slice(body_len)?;
+                Self::Pingreq
+            }
+            MsgType::Pingresp => Self::Pingresp,
+            MsgType::Disconnect => Self::Disconnect {
+                duration: if body_len >= 2 {
+                    Some(reader.get_u16()?)
+                } else {
+                    None
+                },
+            },
+        };
+
+        if reader.position() != len {
+            return Err(PacketReadError::IncorrectPacketLength);
+        }
+        Ok(message)
+    }
+}
+
+impl Write for Message<'_> {
+    fn write<'w, W: MqttWriter<'w>>(&self, writer: &mut W) -> mqtt_writer::Result<()> {
+        let len = self.encoded_len();
+        if len > SHORT_LENGTH_MAX {
+            if len > u16::MAX as usize {
+                return Err(PacketWriteError::DataTooLarge);
+            }
+            writer.put_u8(LONG_LENGTH_MARKER)?;
+            writer.put_u16(len as u16)?;
+        } else {
+            writer.put_u8(len as u8)?;
+        }
+        writer.put_u8(self.msg_type() as u8)?;
+
+        match self {
+            Self::Connect {
+                clean_session,
+                duration,
+                client_id,
+            } => {
+                writer.put_u8((*clean_session as u8) << CLEAN_SESSION_SHIFT)?;
+                writer.put_u8(PROTOCOL_ID)?;
+                writer.put_u16(*duration)?;
+                writer.put_slice(client_id.as_bytes())
+            }
+            Self::Connack { return_code } => writer.put_u8((*return_code).into()),
+            Self::Register {
+                topic_id,
+                msg_id,
+                topic_name,
+            } => {
+                writer.put_u16(*topic_id)?;
+                writer.put_u16(*msg_id)?;
+                writer.put_slice(topic_name.as_bytes())
+            }
+            Self::Regack {
+                topic_id,
+                msg_id,
+                return_code,
+            }
+            | Self::Puback {
+                topic_id,
+                msg_id,
+                return_code,
+            } => {
+                writer.put_u16(*topic_id)?;
+                writer.put_u16(*msg_id)?;
+                writer.put_u8((*return_code).into())
+            }
+            Self::Publish {
+                dup,
+                qos,
+                retain,
+                topic_id,
+                msg_id,
+                data,
+            } => {
+                writer.put_u8(flags(*dup, *qos, *retain, topic_id.id_type()))?;
+                topic_id.put(writer)?;
+                writer.put_u16(*msg_id)?;
+                writer.put_slice(data)
+            }
+            Self::Subscribe {
+                dup,
+                qos,
+                msg_id,
+                topic,
+            } => {
+                writer.put_u8(flags(*dup, *qos, false, topic.id_type()))?;
+                writer.put_u16(*msg_id)?;
+                topic.put(writer)
+            }
+            Self::Suback {
+                qos,
+                topic_id,
+                msg_id,
+                return_code,
+            } => {
+                writer.put_u8(flags(false, *qos, false, 0))?;
+                writer.put_u16(*topic_id)?;
+                writer.put_u16(*msg_id)?;
+                writer.put_u8((*return_code).into())
+            }
+            Self::Unsubscribe { msg_id, topic } => {
+                writer.put_u8(topic.id_type())?;
+                writer.put_u16(*msg_id)?;
+                topic.put(writer)
+            }
+            Self::Unsuback { msg_id } => writer.put_u16(*msg_id),
+            Self::Pingreq | Self::Pingresp => Ok(()),
+            Self::Disconnect { duration } => match duration {
+                Some(duration) => writer.put_u16(*duration),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+/// Errors produced by [SnClientState]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SnClientError {
+    /// A request was made when not connected
+    NotConnected,
+
+    /// A request was made while waiting for the response to a previous request
+    NotIdle,
+
+    /// The gateway rejected a request, or the connection
+    Rejected(ReturnCode),
+
+    /// A message was received that was not expected in the current state
+    UnexpectedMessage(MsgType),
+
+    /// A response was received with a message id that doesn't match the request
+    UnexpectedMsgId,
+
+    /// A publish was requested to a topic name that has not been registered
+    TopicNotRegistered,
+
+    /// A topic name was too long to store in the topic registry
+    TopicNameTooLong,
+
+    /// A topic id could not be stored since the topic registry is full
+    TopicRegistryFull,
+
+    /// The gateway disconnected
+    Disconnected,
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for SnClientError {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::NotConnected => defmt::write!(f, "NotConnected"),
+            Self::NotIdle => defmt::write!(f, "NotIdle"),
+            Self::Rejected(r) => defmt::write!(f, "Rejected({})", r),
+            Self::UnexpectedMessage(t) => defmt::write!(f, "UnexpectedMessage({})", t),
+            Self::UnexpectedMsgId => defmt::write!(f, "UnexpectedMsgId"),
+            Self::TopicNotRegistered => defmt::write!(f, "TopicNotRegistered"),
+            Self::TopicNameTooLong => defmt::write!(f, "TopicNameTooLong"),
+            Self::TopicRegistryFull => defmt::write!(f, "TopicRegistryFull"),
+            Self::Disconnected => defmt::write!(f, "Disconnected"),
+        }
+    }
+}
+
+#[cfg(feature = "display")]
+impl Display for SnClientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NotConnected => write!(f, "NotConnected"),
+            Self::NotIdle => write!(f, "NotIdle"),
+            Self::Rejected(r) => write!(f, "Rejected({})", r),
+            Self::UnexpectedMessage(t) => write!(f, "UnexpectedMessage({:?})", t),
+            Self::UnexpectedMsgId => write!(f, "UnexpectedMsgId"),
+            Self::TopicNotRegistered => write!(f, "TopicNotRegistered"),
+            Self::TopicNameTooLong => write!(f, "TopicNameTooLong"),
+            Self::TopicRegistryFull => write!(f, "TopicRegistryFull"),
+            Self::Disconnected => write!(f, "Disconnected"),
+        }
+    }
+}
+
+/// The result of [SnClientState::receive]
+#[derive(Debug, PartialEq)]
+pub enum SnReceiveEvent<'a> {
+    /// The response to the outstanding request was received
+    Ack,
+
+    /// A subscription was granted, with the topic id the gateway will use
+    /// when publishing to it (0 for a topic filter with wildcards, in which
+    /// case the gateway registers each matching topic name before publishing)
+    Subscribed {
+        topic_id: u16,
+        qos: QualityOfService,
+    },
+
+    /// A published message was received, and if `response` is present it
+    /// must be sent to acknowledge it. The topic name of a [TopicId::Normal]
+    /// can be found with [SnClientState::topic_name]
+    Publish {
+        topic_id: TopicId,
+        qos: QualityOfService,
+        retain: bool,
+        data: &'a [u8],
+        response: Option<Message<'static>>,
+    },
+
+    /// The gateway registered a topic name that it will publish to, which has
+    /// been stored, and the `response` must be sent to acknowledge it
+    Register { response: Message<'static> },
+
+    /// The gateway responded to a ping
+    Pingresp,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum SnWaiting<const T: usize> {
+    None,
+    ForConnack,
+    ForRegack {
+        msg_id: u16,
+        topic_name: String<T>,
+    },
+    ForPuback {
+        msg_id: u16,
+    },
+    ForSuback {
+        msg_id: u16,
+        topic_name: Option<String<T>>,
+    },
+    ForUnsuback {
+        msg_id: u16,
+    },
+}
+
+/// The client side of an MQTT-SN connection to a gateway. Tracks whether the
+/// client is connected, the single request that may be outstanding (as for
+/// [ClientStateNoQueue](crate::client_state::ClientStateNoQueue)), and the
+/// ids of up to `N` topic names of up to `T` bytes, registered either by
+/// the client or the gateway.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SnClientState<const N: usize, const T: usize> {
+    connected: bool,
+    waiting: SnWaiting<T>,
+    last_msg_id: u16,
+    pending_ping_count: u32,
+    topics: Vec<(u16, String<T>), N>,
+}
+
+impl<const N: usize, const T: usize> Default for SnClientState<N, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const T: usize> SnClientState<N, T> {
+    pub fn new() -> Self {
+        Self {
+            connected: false,
+            waiting: SnWaiting::None,
+            last_msg_id: 0,
+            pending_ping_count: 0,
+            topics: Vec::new(),
+        }
+    }
+
+    pub fn connected(&self) -> bool {
+        self.connected
+    }
+
+    /// True if a request has been sent, and its response not yet received
+    pub fn waiting_for_responses(&self) -> bool {
+        self.waiting != SnWaiting::None
+    }
+
+    /// The number of pings sent without a response yet being received
+    pub fn pending_ping_count(&self) -> u32 {
+        self.pending_ping_count
+    }
+
+    /// The id registered for `topic_name`, if any
+    pub fn topic_id(&self, topic_name: &str) -> Option<u16> {
+        self.topics
+            .iter()
+            .find(|(_, name)| name == topic_name)
+            .map(|(id, _)| *id)
+    }
+
+    /// The topic name registered for `topic_id`, if any
+    pub fn topic_name(&self, topic_id: u16) -> Option<&str> {
+        self.topics
+            .iter()
+            .find(|(id, _)| *id == topic_id)
+            .map(|(_, name)| name.as_str())
+    }
+
+    fn next_msg_id(&mut self) -> u16 {
+        // Message ids can't be 0, which is used for qos 0 publishes
+        self.last_msg_id = self.last_msg_id.checked_add(1).unwrap_or(1);
+        self.last_msg_id
+    }
+
+    fn check_idle(&self) -> Result<(), SnClientError> {
+        if !self.connected {
+            Err(SnClientError::NotConnected)
+        } else if self.waiting_for_responses() {
+            Err(SnClientError::NotIdle)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn topic_string(topic_name: &str) -> Result<String<T>, SnClientError> {
+        let mut s = String::new();
+        s.push_str(topic_name)
+            .map_err(|_| SnClientError::TopicNameTooLong)?;
+        Ok(s)
+    }
+
+    fn record_topic(&mut self, topic_id: u16, topic_name: String<T>) -> Result<(), SnClientError> {
+        if let Some(entry) = self.topics.iter_mut().find(|(_, name)| *name == topic_name) {
+            entry.0 = topic_id;
+            return Ok(());
+        }
+        self.topics
+            .push((topic_id, topic_name))
+            .map_err(|_| SnClientError::TopicRegistryFull)
+    }
+
+    /// Produce a Connect message, and wait for the gateway's Connack.
+    /// `duration` is the keep alive interval in seconds. Topics registered on
+    /// a previous connection are kept unless `clean_session` is true.
+    pub fn connect<'a>(
+        &mut self,
+        client_id: &'a str,
+        clean_session: bool,
+        duration: u16,
+    ) -> Result<Message<'a>, SnClientError> {
+        if self.connected || self.waiting_for_responses() {
+            return Err(SnClientError::NotIdle);
+        }
+        if clean_session {
+            self.topics.clear();
+        }
+        self.waiting = SnWaiting::ForConnack;
+        self.pending_ping_count = 0;
+        Ok(Message::Connect {
+            clean_session,
+            duration,
+            client_id,
+        })
+    }
+
+    /// Produce a Register message to request a topic id for `topic_name`,
+    /// which is stored when the gateway's Regack is received
+    pub fn register<'a>(&mut self, topic_name: &'a str) -> Result<Message<'a>, SnClientError> {
+        self.check_idle()?;
+        let stored = Self::topic_string(topic_name)?;
+        let msg_id = self.next_msg_id();
+        self.waiting = SnWaiting::ForRegack {
+            msg_id,
+            topic_name: stored,
+        };
+        Ok(Message::Register {
+            topic_id: 0,
+            msg_id,
+            topic_name,
+        })
+    }
+
+    /// Produce a Publish message to `topic_id`. For qos 1 the client then
+    /// waits for the gateway's Puback. Qos 2 is not supported.
+    pub fn publish<'a>(
+        &mut self,
+        topic_id: TopicId,
+        data: &'a [u8],
+        qos: QualityOfService,
+        retain: bool,
+    ) -> Result<Message<'a>, SnClientError> {
+        self.check_idle()?;
+        let msg_id = match qos {
+            QualityOfService::Qos0 => 0,
+            QualityOfService::Qos1 => {
+                let msg_id = self.next_msg_id();
+                self.waiting = SnWaiting::ForPuback { msg_id };
+                msg_id
+            }
+            QualityOfService::Qos2 => {
+                return Err(SnClientError::Rejected(ReturnCode::RejectedNotSupported))
+            }
+        };
+        Ok(Message::Publish {
+            dup: false,
+            qos,
+            retain,
+            topic_id,
+            msg_id,
+            data,
+        })
+    }
+
+    /// Produce a Publish message to a topic name previously registered with
+    /// [SnClientState::register], see [SnClientState::publish]
+    pub fn publish_to<'a>(
+        &mut self,
+        topic_name: &str,
+        data: &'a [u8],
+        qos: QualityOfService,
+        retain: bool,
+    ) -> Result<Message<'a>, SnClientError> {
+        let topic_id = self
+            .topic_id(topic_name)
+            .ok_or(SnClientError::TopicNotRegistered)?;
+        self.publish(TopicId::Normal(topic_id), data, qos, retain)
+    }
+
+    /// Produce a Subscribe message, and wait for the gateway's Suback. If
+    /// subscribing to a topic name without wildcards, the topic id granted
+    /// by the gateway is stored.
+    pub fn subscribe<'a>(
+        &mut self,
+        topic: SubscribeTopic<'a>,
+        qos: QualityOfService,
+    ) -> Result<Message<'a>, SnClientError> {
+        self.check_idle()?;
+        let topic_name = match topic {
+            SubscribeTopic::Name(name) if !name.contains(['+', '#']) => {
+                Some(Self::topic_string(name)?)
+            }
+            _ => None,
+        };
+        let msg_id = self.next_msg_id();
+        self.waiting = SnWaiting::ForSuback { msg_id, topic_name };
+        Ok(Message::Subscribe {
+            dup: false,
+            qos,
+            msg_id,
+            topic,
+        })
+    }
+
+    /// Produce an Unsubscribe message, and wait for the gateway's Unsuback
+    pub fn unsubscribe<'a>(
+        &mut self,
+        topic: SubscribeTopic<'a>,
+    ) -> Result<Message<'a>, SnClientError> {
+        self.check_idle()?;
+        let msg_id = self.next_msg_id();
+        self.waiting = SnWaiting::ForUnsuback { msg_id };
+        Ok(Message::Unsubscribe { msg_id, topic })
+    }
+
+    /// Produce a Pingreq message. This can be sent while waiting for the
+    /// response to another request.
+    pub fn send_ping(&mut self) -> Result<Message<'static>, SnClientError> {
+        if !self.connected {
+            return Err(SnClientError::NotConnected);
+        }
+        self.pending_ping_count += 1;
+        Ok(Message::Pingreq)
+    }
+
+    /// Produce a Disconnect message, after which the client is disconnected
+    pub fn disconnect(&mut self) -> Result<Message<'static>, SnClientError> {
+        if !self.connected {
+            return Err(SnClientError::NotConnected);
+        }
+        self.error();
+        Ok(Message::Disconnect { duration: None })
+    }
+
+    /// Update state after an error sending or receiving messages, after
+    /// which the client is disconnected. Registered topics are kept, so they
+    /// can be reused if the next connection does not use a clean session.
+    pub fn error(&mut self) {
+        self.connected = false;
+        self.waiting = SnWaiting::None;
+        self.pending_ping_count = 0;
+    }
+
+    /// Update state based on a message received from the gateway, producing
+    /// an event, which may contain a message that must be sent in response
+    pub fn receive<'a>(
+        &mut self,
+        message: Message<'a>,
+    ) -> Result<SnReceiveEvent<'a>, SnClientError> {
+        let unexpected = SnClientError::UnexpectedMessage(message.msg_type());
+        if !self.connected {
+            return match (&self.waiting, message) {
+                (SnWaiting::ForConnack, Message::Connack { return_code }) => {
+                    self.waiting = SnWaiting::None;
+                    if return_code == ReturnCode::Accepted {
+                        self.connected = true;
+                        Ok(SnReceiveEvent::Ack)
+                    } else {
+                        Err(SnClientError::Rejected(return_code))
+                    }
+                }
+                _ => Err(unexpected),
+            };
+        }
+
+        match message {
+            Message::Regack {
+                topic_id,
+                msg_id,
+                return_code,
+            } => {
+                let SnWaiting::ForRegack {
+                    msg_id: expected,
+                    topic_name,
+                } = core::mem::replace(&mut self.waiting, SnWaiting::None)
+                else {
+                    return Err(unexpected);
+                };
+                if msg_id != expected {
+                    return Err(SnClientError::UnexpectedMsgId);
+                }
+                if return_code != ReturnCode::Accepted {
+                    return Err(SnClientError::Rejected(return_code));
+                }
+                self.record_topic(topic_id, topic_name)?;
+                Ok(SnReceiveEvent::Ack)
+            }
+            Message::Puback {
+                msg_id,
+                return_code,
+                ..
+            } => {
+                let SnWaiting::ForPuback { msg_id: expected } = self.waiting else {
+                    return Err(unexpected);
+                };
+                if msg_id != expected {
+                    return Err(SnClientError::UnexpectedMsgId);
+                }
+                self.waiting = SnWaiting::None;
+                if return_code != ReturnCode::Accepted {
+                    return Err(SnClientError::Rejected(return_code));
+                }
+                Ok(SnReceiveEvent::Ack)
+            }
+            Message::Suback {
+                qos,
+                topic_id,
+                msg_id,
+                return_code,
+            } => {
+                let SnWaiting::ForSuback {
+                    msg_id: expected,
+                    topic_name,
+                } = core::mem::replace(&mut self.waiting, SnWaiting::None)
+                else {
+                    return Err(unexpected);
+                };
+                if msg_id != expected {
+                    return Err(SnClientError::UnexpectedMsgId);
+                }
+                if return_code != ReturnCode::Accepted {
+                    return Err(SnClientError::Rejected(return_code));
+                }
+                if let Some(topic_name) = topic_name {
+                    if topic_id != 0 {
+                        self.record_topic(topic_id, topic_name)?;
+                    }
+                }
+                Ok(SnReceiveEvent::Subscribed { topic_id, qos })
+            }
+            Message::Unsuback { msg_id } => {
+                let SnWaiting::ForUnsuback { msg_id: expected } = self.waiting else {
+                    return Err(unexpected);
+                };
+                if msg_id != expected {
+                    return Err(SnClientError::UnexpectedMsgId);
+                }
+                self.waiting = SnWaiting::None;
+                Ok(SnReceiveEvent::Ack)
+            }
+            Message::Register {
+                topic_id,
+                msg_id,
+                topic_name,
+            } => {
+                let return_code = match Self::topic_string(topic_name)
+                    .and_then(|name| self.record_topic(topic_id, name))
+                {
+                    Ok(()) => ReturnCode::Accepted,
+                    Err(_) => ReturnCode::RejectedCongestion,
+                };
+                Ok(SnReceiveEvent::Register {
+                    response: Message::Regack {
+                        topic_id,
+                        msg_id,
+                        return_code,
+                    },
+                })
+            }
+            Message::Publish {
+                qos,
+                retain,
+                topic_id,
+                msg_id,
+                data,
+                ..
+            } => {
+                let response = match (qos, topic_id) {
+                    (QualityOfService::Qos0, _) => None,
+                    (QualityOfService::Qos1, TopicId::Normal(id))
+                        if self.topic_name(id).is_none() =>
+                    {
+                        Some(Message::Puback {
+                            topic_id: id,
+                            msg_id,
+                            return_code: ReturnCode::RejectedInvalidTopicId,
+                        })
+                    }
+                    (QualityOfService::Qos1, topic_id) => Some(Message::Puback {
+                        topic_id: match topic_id {
+                            TopicId::Normal(id) | TopicId::Predefined(id) => id,
+                            TopicId::Short(name) => u16::from_be_bytes(name),
+                        },
+                        msg_id,
+                        return_code: ReturnCode::Accepted,
+                    }),
+                    (QualityOfService::Qos2, _) => return Err(unexpected),
+                };
+                Ok(SnReceiveEvent::Publish {
+                    topic_id,
+                    qos,
+                    retain,
+                    data,
+                    response,
+                })
+            }
+            Message::Pingresp => {
+                if self.pending_ping_count == 0 {
+                    return Err(unexpected);
+                }
+                self.pending_ping_count -= 1;
+                Ok(SnReceiveEvent::Pingresp)
+            }
+            Message::Disconnect { .. } => {
+                self.error();
+                Err(SnClientError::Disconnected)
+            }
+            _ => Err(unexpected),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::mqtt_writer::MqttBufWriter;
+
+    fn encode<'b>(message: &Message<'_>, buf: &'b mut [u8]) -> &'b [u8] {
+        let mut writer = MqttBufWriter::new(buf);
+        writer.put(message).unwrap();
+        let len = writer.position();
+        assert_eq!(len, message.encoded_len());
+        &buf[..len]
+    }
+
+    #[test]
+    fn messages_encode_and_decode() {
+        let mut buf = [0; 64];
+        let cases: [(Message<'_>, &[u8]); 5] = [
+            (
+                Message::Connect {
+                    clean_session: true,
+                    duration: 60,
+                    client_id: "ab",
+                },
+                &[0x08, 0x04, 0x04, 0x01, 0x00, 0x3c, b'a', b'b'],
+            ),
+            (
+                Message::Register {
+                    topic_id: 0,
+                    msg_id: 1,
+                    topic_name: "t/a",
+                },
+                &[0x09, 0x0a, 0x00, 0x00, 0x00, 0x01, b't', b'/', b'a'],
+            ),
+            (
+                Message::Publish {
+                    dup: false,
+                    qos: QualityOfService::Qos1,
+                    retain: true,
+                    topic_id: TopicId::Normal(5),
+                    msg_id: 2,
+                    data: b"on",
+                },
+                &[0x09, 0x0c, 0x30, 0x00, 0x05, 0x00, 0x02, b'o', b'n'],
+            ),
+            (
+                Message::Subscribe {
+                    dup: false,
+                    qos: QualityOfService::Qos0,
+                    msg_id: 3,
+                    topic: SubscribeTopic::Short(*b"ab"),
+                },
+                &[0x07, 0x12, 0x02, 0x00, 0x03, b'a', b'b'],
+            ),
+            (Message::Disconnect { duration: None }, &[0x02, 0x18]),
+        ];
+        for (message, encoded) in cases {
+            assert_eq!(encode(&message, &mut buf), encoded);
+            assert_eq!(Message::read(encoded), Ok(message));
+        }
+    }
+
+    #[test]
+    fn long_messages_use_three_byte_length() {
+        let data = [0x55; 300];
+        let message = Message::Publish {
+            dup: false,
+            qos: QualityOfService::Qos0,
+            retain: false,
+            topic_id: TopicId::Predefined(1),
+            msg_id: 0,
+            data: &data,
+        };
+        let mut buf = [0; 320];
+        let encoded = encode(&message, &mut buf);
+        assert_eq!(encoded[0..4], [0x01, 0x01, 0x35, 0x0c]);
+        assert_eq!(Message::read(encoded), Ok(message));
+    }
+
+    #[test]
+    fn incorrect_length_is_rejected() {
+        assert_eq!(
+            Message::read(&[0x03, 0x18]),
+            Err(PacketReadError::IncorrectPacketLength)
+        );
+        assert_eq!(
+            Message::read(&[0x02, 0x03]),
+            Err(PacketReadError::InvalidPacketType)
+        );
+    }
+
+    fn connected() -> SnClientState<4, 16> {
+        let mut state = SnClientState::new();
+        state.connect("ab", true, 60).unwrap();
+        assert_eq!(
+            state.receive(Message::Connack {
+                return_code: ReturnCode::Accepted
+            }),
+            Ok(SnReceiveEvent::Ack)
+        );
+        state
+    }
+
+    #[test]
+    fn client_registers_and_publishes() {
+        let mut state = connected();
+
+        let Message::Register { msg_id, .. } = state.register("t/a").unwrap() else {
+            panic!("expected register");
+        };
+        assert_eq!(
+            state.publish(TopicId::Predefined(1), b"on", QualityOfService::Qos0, false),
+            Err(SnClientError::NotIdle)
+        );
+        state
+            .receive(Message::Regack {
+                topic_id: 7,
+                msg_id,
+                return_code: ReturnCode::Accepted,
+            })
+            .unwrap();
+        assert_eq!(state.topic_id("t/a"), Some(7));
+
+        let Message::Publish {
+            topic_id, msg_id, ..
+        } = state
+            .publish_to("t/a", b"on", QualityOfService::Qos1, false)
+            .unwrap()
+        else {
+            panic!("expected publish");
+        };
+        assert_eq!(topic_id, TopicId::Normal(7));
+        assert_eq!(
+            state.receive(Message::Puback {
+                topic_id: 7,
+                msg_id: msg_id + 1,
+                return_code: ReturnCode::Accepted
+            }),
+            Err(SnClientError::UnexpectedMsgId)
+        );
+        assert!(state.waiting_for_responses());
+        assert_eq!(
+            state.receive(Message::Puback {
+                topic_id: 7,
+                msg_id,
+                return_code: ReturnCode::Accepted
+            }),
+            Ok(SnReceiveEvent::Ack)
+        );
+        assert!(!state.waiting_for_responses());
+        assert_eq!(
+            state.publish_to("t/b", b"on", QualityOfService::Qos0, false),
+            Err(SnClientError::TopicNotRegistered)
+        );
+    }
+
+    #[test]
+    fn client_handles_gateway_register_and_publish() {
+        let mut state = connected();
+
+        let msg_id = match state
+            .subscribe(SubscribeTopic::Name("t/#"), QualityOfService::Qos1)
+            .unwrap()
+        {
+            Message::Subscribe { msg_id, .. } => msg_id,
+            _ => panic!("expected subscribe"),
+        };
+        assert_eq!(
+            state.receive(Message::Suback {
+                qos: QualityOfService::Qos1,
+                topic_id: 0,
+                msg_id,
+                return_code: ReturnCode::Accepted
+            }),
+            Ok(SnReceiveEvent::Subscribed {
+                topic_id: 0,
+                qos: QualityOfService::Qos1
+            })
+        );
+
+        assert_eq!(
+            state.receive(Message::Register {
+                topic_id: 9,
+                msg_id: 100,
+                topic_name: "t/b",
+            }),
+            Ok(SnReceiveEvent::Register {
+                response: Message::Regack {
+                    topic_id: 9,
+                    msg_id: 100,
+                    return_code: ReturnCode::Accepted
+                }
+            })
+        );
+        assert_eq!(state.topic_name(9), Some("t/b"));
+
+        assert_eq!(
+            state.receive(Message::Publish {
+                dup: false,
+                qos: QualityOfService::Qos1,
+                retain: false,
+                topic_id: TopicId::Normal(9),
+                msg_id: 101,
+                data: b"off",
+            }),
+            Ok(SnReceiveEvent::Publish {
+                topic_id: TopicId::Normal(9),
+                qos: QualityOfService::Qos1,
+                retain: false,
+                data: b"off",
+                response: Some(Message::Puback {
+                    topic_id: 9,
+                    msg_id: 101,
+                    return_code: ReturnCode::Accepted
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn rejected_connection_is_an_error() {
+        let mut state: SnClientState<4, 16> = SnClientState::new();
+        state.connect("ab", true, 60).unwrap();
+        assert_eq!(
+            state.receive(Message::Connack {
+                return_code: ReturnCode::RejectedCongestion
+            }),
+            Err(SnClientError::Rejected(ReturnCode::RejectedCongestion))
+        );
+        assert!(!state.connected());
+        assert_eq!(state.send_ping(), Err(SnClientError::NotConnected));
+    }
+}
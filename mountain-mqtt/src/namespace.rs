@@ -0,0 +1,287 @@
+use heapless::{String, Vec};
+
+use crate::{
+    client::{
+        Client, ClientError, ClientReceivedEvent, ConnectionSettings, EventHandler,
+        EventHandlerError,
+    },
+    data::{
+        property::{PublishProperty, SubscribeProperty},
+        quality_of_service::QualityOfService,
+        subscription_options::SubscriptionOptions,
+        topic_filter::{SHARED_SUBSCRIPTION_PREFIX, TOPIC_LEVEL_SEPARATOR},
+    },
+    packets::connect::{Connect, Will},
+};
+
+/// Write `topic` with `prefix` added into `buf`, producing
+/// [ClientError::TopicNameTooLong] if it won't fit. For a shared subscription
+/// filter `$share/{ShareName}/{filter}` the prefix is added to `{filter}`.
+pub fn namespaced_topic<const T: usize>(
+    buf: &mut String<T>,
+    prefix: &str,
+    topic: &str,
+) -> Result<(), ClientError> {
+    buf.clear();
+    let topic = match topic
+        .strip_prefix(SHARED_SUBSCRIPTION_PREFIX)
+        .and_then(|rest| rest.split_once(TOPIC_LEVEL_SEPARATOR))
+    {
+        Some((share_name, filter)) => {
+            buf.push_str(SHARED_SUBSCRIPTION_PREFIX)
+                .and_then(|_| buf.push_str(share_name))
+                .and_then(|_| buf.push(TOPIC_LEVEL_SEPARATOR))
+                .map_err(|_| ClientError::TopicNameTooLong)?;
+            filter
+        }
+        None => topic,
+    };
+    buf.push_str(prefix)
+        .and_then(|_| buf.push_str(topic))
+        .map_err(|_| ClientError::TopicNameTooLong)
+}
+
+/// A [Client] wrapping another client, adding a namespace prefix (e.g.
+/// `fleet/device123/`) to the topic names and filters used to publish,
+/// subscribe and unsubscribe, and to the topic name of any [Will]. Prefixed
+/// topics are built in a buffer of `T` bytes, producing
+/// [ClientError::TopicNameTooLong] if they won't fit.
+///
+/// Received messages are passed to the event handler of the wrapped client,
+/// so to strip the prefix from their topic names, that event handler should be
+/// wrapped in a [NamespaceHandler] with the same prefix. A [Connect] packet
+/// provided to [Client::connect_with_packet] is passed on unchanged.
+pub struct NamespacedClient<'p, C, const T: usize> {
+    client: C,
+    prefix: &'p str,
+    topic: String<T>,
+}
+
+impl<'p, C, const T: usize> NamespacedClient<'p, C, T> {
+    pub fn new(client: C, prefix: &'p str) -> Self {
+        Self {
+            client,
+            prefix,
+            topic: String::new(),
+        }
+    }
+
+    pub fn prefix(&self) -> &'p str {
+        self.prefix
+    }
+
+    pub fn client(&self) -> &C {
+        &self.client
+    }
+
+    pub fn client_mut(&mut self) -> &mut C {
+        &mut self.client
+    }
+
+    /// Consume the wrapper, returning the wrapped client
+    pub fn into_inner(self) -> C {
+        self.client
+    }
+}
+
+impl<'a, 'p, C, const T: usize> Client<'a> for NamespacedClient<'p, C, T>
+where
+    C: Client<'a>,
+{
+    async fn connect(&mut self, settings: &ConnectionSettings<'_>) -> Result<(), ClientError> {
+        self.client.connect(settings).await
+    }
+
+    async fn connect_with_will<const W: usize>(
+        &mut self,
+        settings: &ConnectionSettings<'_>,
+        will: Option<Will<'_, W>>,
+    ) -> Result<(), ClientError> {
+        match will {
+            Some(will) => {
+                namespaced_topic(&mut self.topic, self.prefix, will.topic_name())?;
+                let will = will.with_topic_name(&self.topic);
+                self.client.connect_with_will(settings, Some(will)).await
+            }
+            None => self.client.connect_with_will(settings, will).await,
+        }
+    }
+
+    async fn connect_with_packet<const PP: usize, const W: usize>(
+        &mut self,
+        packet: Connect<'_, PP, W>,
+    ) -> Result<(), ClientError> {
+        self.client.connect_with_packet(packet).await
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ClientError> {
+        self.client.disconnect().await
+    }
+
+    async fn disconnect_with_session_expiry_interval(
+        &mut self,
+        session_expiry_interval: u32,
+    ) -> Result<(), ClientError> {
+        self.client
+            .disconnect_with_session_expiry_interval(session_expiry_interval)
+            .await
+    }
+
+    async fn send_ping(&mut self) -> Result<(), ClientError> {
+        self.client.send_ping().await
+    }
+
+    fn pending_ping_count(&self) -> u32 {
+        self.client.pending_ping_count()
+    }
+
+    fn keep_alive(&self) -> Option<u16> {
+        self.client.keep_alive()
+    }
+
+    async fn poll(&mut self, wait: bool) -> Result<bool, ClientError> {
+        self.client.poll(wait).await
+    }
+
+    async fn subscribe_with_options<'b>(
+        &'b mut self,
+        topic_name: &'b str,
+        options: SubscriptionOptions,
+    ) -> Result<(), ClientError> {
+        namespaced_topic(&mut self.topic, self.prefix, topic_name)?;
+        self.client
+            .subscribe_with_options(&self.topic, options)
+            .await
+    }
+
+    async fn subscribe_with_properties<'b, const P: usize>(
+        &'b mut self,
+        topic_name: &'b str,
+        maximum_qos: QualityOfService,
+        properties: Vec<SubscribeProperty<'b>, P>,
+    ) -> Result<(), ClientError> {
+        namespaced_topic(&mut self.topic, self.prefix, topic_name)?;
+        self.client
+            .subscribe_with_properties(&self.topic, maximum_qos, properties)
+            .await
+    }
+
+    async fn unsubscribe<'b>(&'b mut self, topic_name: &'b str) -> Result<(), ClientError> {
+        namespaced_topic(&mut self.topic, self.prefix, topic_name)?;
+        self.client.unsubscribe(&self.topic).await
+    }
+
+    async fn publish_with_properties<'b, const P: usize>(
+        &'b mut self,
+        topic_name: &'b str,
+        payload: &'b [u8],
+        qos: QualityOfService,
+        retain: bool,
+        properties: Vec<PublishProperty<'b>, P>,
+    ) -> Result<(), ClientError> {
+        namespaced_topic(&mut self.topic, self.prefix, topic_name)?;
+        self.client
+            .publish_with_properties(&self.topic, payload, qos, retain, properties)
+            .await
+    }
+}
+
+/// An [EventHandler] wrapping another handler, removing a namespace prefix
+/// from the topic names of received application messages before passing them
+/// on, for use with a [NamespacedClient]. Messages with topic names that don't
+/// start with the prefix are passed on unchanged, and other events are passed
+/// on as they are.
+pub struct NamespaceHandler<'p, F> {
+    prefix: &'p str,
+    handler: F,
+}
+
+impl<'p, F> NamespaceHandler<'p, F> {
+    pub fn new(prefix: &'p str, handler: F) -> Self {
+        Self { prefix, handler }
+    }
+
+    pub fn handler(&self) -> &F {
+        &self.handler
+    }
+
+    pub fn handler_mut(&mut self) -> &mut F {
+        &mut self.handler
+    }
+}
+
+impl<F, const P: usize> EventHandler<P> for NamespaceHandler<'_, F>
+where
+    F: EventHandler<P>,
+{
+    async fn handle_event(
+        &mut self,
+        event: ClientReceivedEvent<'_, P>,
+    ) -> Result<(), EventHandlerError> {
+        let event = match event {
+            ClientReceivedEvent::ApplicationMessage(mut message) => {
+                if let Some(topic_name) = message.topic_name.strip_prefix(self.prefix) {
+                    message.topic_name = topic_name;
+                }
+                ClientReceivedEvent::ApplicationMessage(message)
+            }
+            event => event,
+        };
+        self.handler.handle_event(event).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::publish::ApplicationMessage;
+
+    #[test]
+    fn topics_are_prefixed() {
+        let mut buf = String::<32>::new();
+        namespaced_topic(&mut buf, "fleet/d1/", "sensors/+").unwrap();
+        assert_eq!(buf, "fleet/d1/sensors/+");
+        namespaced_topic(&mut buf, "fleet/d1/", "$share/group/sensors/#").unwrap();
+        assert_eq!(buf, "$share/group/fleet/d1/sensors/#");
+        assert_eq!(
+            namespaced_topic(&mut String::<12>::new(), "fleet/d1/", "sensors"),
+            Err(ClientError::TopicNameTooLong)
+        );
+    }
+
+    #[derive(Default)]
+    struct Topics(std::vec::Vec<std::string::String>);
+
+    impl EventHandler<0> for Topics {
+        async fn handle_event(
+            &mut self,
+            event: ClientReceivedEvent<'_, 0>,
+        ) -> Result<(), EventHandlerError> {
+            if let ClientReceivedEvent::ApplicationMessage(message) = event {
+                self.0.push(message.topic_name.into());
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn handler_strips_prefix() {
+        let mut handler = NamespaceHandler::new("fleet/d1/", Topics::default());
+        for topic_name in ["fleet/d1/sensors/a", "other/b"] {
+            handler
+                .handle_event(ClientReceivedEvent::ApplicationMessage(
+                    ApplicationMessage {
+                        topic_name,
+                        payload: &[],
+                        qos: QualityOfService::Qos0,
+                        retain: false,
+                        properties: Vec::new(),
+                        encoded_properties: &[],
+                    },
+                ))
+                .await
+                .unwrap();
+        }
+        assert_eq!(handler.handler().0, ["sensors/a", "other/b"]);
+    }
+}
@@ -1,21 +1,58 @@
+#[cfg(any(feature = "client-codec", feature = "zeroize"))]
+use crate::data::packet_type::PacketType;
+#[cfg(all(feature = "client-codec", feature = "server-codec"))]
+use crate::packets::{
+    packet::PacketRead,
+    packet_generic::PacketGeneric,
+    packets_iter::PacketsIter,
+    publish::{is_valid_publish_first_header_byte, PayloadSink},
+};
+#[cfg(feature = "client-codec")]
 use crate::{
     codec::{
         mqtt_reader::{MqttBufReader, MqttReader},
-        mqtt_writer::{MqttBufWriter, MqttWriter},
+        read::Read,
+    },
+    packets::server_packet::ServerPacket,
+};
+use crate::{
+    codec::{
+        mqtt_writer::{MqttBufWriter, MqttLenWriter, MqttWriter},
         write,
     },
-    data::packet_type::PacketType,
-    error::{PacketReadError, PacketWriteError},
-    packets::{packet::Packet, packet_generic::PacketGeneric},
+    data::{property::PropertyOverflow, DATA_MAX_LEN, VARIABLE_BYTE_INTEGER_MAX_LEN},
+    error::{
+        PacketReadError, PacketReadErrorContext, PacketWriteError, PacketWriteErrorContext,
+        TransportError,
+    },
+    packets::{
+        packet::Packet,
+        packets_iter::encoded_packet_len,
+        publish::{PayloadSource, Publish, StreamingPublish},
+    },
 };
 
 #[allow(async_fn_in_trait)]
 pub trait Connection {
-    // Send and flush all data in `buf`
-    async fn send(&mut self, buf: &[u8]) -> Result<(), PacketWriteError>;
+    /// The error produced when the underlying transport fails. This is
+    /// classified by [TransportError::kind] to produce
+    /// [PacketReadError::Transport] or [PacketWriteError::Transport], and the
+    /// error itself is available from [PacketClient::take_transport_error],
+    /// e.g. to produce a [ClientError::Transport](crate::client::ClientError::Transport)
+    /// carrying it.
+    type Error: TransportError;
+
+    // Send all data in `buf`. Data may be buffered until [Connection::flush] is called
+    async fn send(&mut self, buf: &[u8]) -> Result<(), Self::Error>;
+
+    /// Flush any data buffered by [Connection::send], waiting until it has been sent.
+    /// Connections that don't buffer data can use the default, which does nothing.
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
 
     // Receive into buffer, waiting to fill it. This may need to await more data.
-    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), PacketReadError>;
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), Self::Error>;
 
     /// If no data at all is ready, then return immediately with `Ok(false)`,
     /// leaving the underlying stream and `buf` unaltered.
@@ -38,12 +75,187 @@ pub trait Connection {
     /// since we only need something like embedded-async's `ReadReady` trait, or
     /// tokio's `TCPStream.try_read`
     /// More sophisticated approaches are definitely possible.
-    async fn receive_if_ready(&mut self, buf: &mut [u8]) -> Result<bool, PacketReadError>;
+    async fn receive_if_ready(&mut self, buf: &mut [u8]) -> Result<bool, Self::Error>;
+
+    /// Receive whatever data is ready into `buf` without waiting for more, and
+    /// return the number of bytes received, which is 0 if no data is ready.
+    /// This is used to receive packets a piece at a time when polling, so that
+    /// a slow connection doesn't stall the client part way through a packet.
+    /// The default implementation receives at most a single byte using
+    /// [Connection::receive_if_ready], connections that can read all ready
+    /// data at once without waiting should override this.
+    async fn receive_ready(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.receive_if_ready(&mut buf[0..1]).await? {
+            Ok(1)
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Receive whatever data is available into `buf`, waiting until at least
+    /// one byte is available, and return the number of bytes received. This
+    /// may include several packets, and end part way through a packet.
+    /// The default implementation receives a single byte using [Connection::receive],
+    /// connections that can read all available data at once should override this.
+    async fn receive_available(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.receive(&mut buf[0..1]).await?;
+        Ok(1)
+    }
+}
+
+/// Wraps a [Connection] for use by [PacketClient], converting errors to
+/// [PacketReadError::Transport] and [PacketWriteError::Transport], and
+/// keeping the last error produced by the connection
+struct TransportConnection<C: Connection> {
+    inner: C,
+    error: Option<C::Error>,
 }
 
-pub struct PacketClient<'a, C> {
-    connection: C,
+impl<C: Connection> TransportConnection<C> {
+    fn receive_failed(&mut self, error: C::Error) -> PacketReadError {
+        let kind = error.kind();
+        self.error = Some(error);
+        PacketReadError::Transport(kind)
+    }
+
+    fn send_failed(&mut self, error: C::Error) -> PacketWriteError {
+        let kind = error.kind();
+        self.error = Some(error);
+        PacketWriteError::Transport(kind)
+    }
+
+    async fn send(&mut self, buf: &[u8]) -> Result<(), PacketWriteError> {
+        let result = self.inner.send(buf).await;
+        result.map_err(|e| self.send_failed(e))
+    }
+
+    async fn flush(&mut self) -> Result<(), PacketWriteError> {
+        let result = self.inner.flush().await;
+        result.map_err(|e| self.send_failed(e))
+    }
+
+    #[cfg(feature = "client-codec")]
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), PacketReadError> {
+        let result = self.inner.receive(buf).await;
+        result.map_err(|e| self.receive_failed(e))
+    }
+
+    #[cfg(feature = "client-codec")]
+    async fn receive_ready(&mut self, buf: &mut [u8]) -> Result<usize, PacketReadError> {
+        let result = self.inner.receive_ready(buf).await;
+        result.map_err(|e| self.receive_failed(e))
+    }
+
+    async fn receive_available(&mut self, buf: &mut [u8]) -> Result<usize, PacketReadError> {
+        let result = self.inner.receive_available(buf).await;
+        result.map_err(|e| self.receive_failed(e))
+    }
+}
+
+/// A buffer for receiving data that may contain several packets, for use with
+/// [PacketClient::receive_available]. Complete packets are decoded in bulk with
+/// [ReceiveBuffer::packets], then [ReceiveBuffer::consume] removes them, leaving
+/// any incomplete packet at the start of the buffer to be completed by the next
+/// receive. This is separate from the buffer used by [PacketClient] for sending.
+/// A [PacketClient] can also decode packets from its own receive buffer, see
+/// [PacketClient::with_receive_buffer].
+pub struct ReceiveBuffer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    skip_unknown_properties: bool,
+    property_overflow: PropertyOverflow,
+}
+
+impl<'a> ReceiveBuffer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            buf,
+            len: 0,
+            skip_unknown_properties: false,
+            property_overflow: PropertyOverflow::Fail,
+        }
+    }
+
+    /// Set whether packets are decoded skipping any properties that are not
+    /// expected for the packet type, see [PacketClient::with_skip_unknown_properties]
+    pub fn with_skip_unknown_properties(mut self, skip_unknown_properties: bool) -> Self {
+        self.skip_unknown_properties = skip_unknown_properties;
+        self
+    }
+
+    /// Set how packets with more properties than the packet's property capacity
+    /// are handled, see [PacketClient::with_property_overflow]
+    pub fn with_property_overflow(mut self, property_overflow: PropertyOverflow) -> Self {
+        self.property_overflow = property_overflow;
+        self
+    }
+
+    /// The data received and not yet consumed
+    pub fn data(&self) -> &[u8] {
+        &self.buf[0..self.len]
+    }
+
+    /// Iterate the complete packets in the buffer. Afterwards, call
+    /// [ReceiveBuffer::consume] with [PacketsIter::consumed] to remove them.
+    #[cfg(all(feature = "client-codec", feature = "server-codec"))]
+    pub fn packets<const P: usize, const W: usize, const S: usize>(
+        &self,
+    ) -> PacketsIter<'_, P, W, S> {
+        PacketsIter::new(self.data())
+            .with_skip_unknown_properties(self.skip_unknown_properties)
+            .with_property_overflow(self.property_overflow)
+    }
+
+    /// Remove `len` bytes from the start of the buffer, moving any remaining
+    /// data to the start
+    pub fn consume(&mut self, len: usize) {
+        let len = len.min(self.len);
+        self.buf.copy_within(len..self.len, 0);
+        self.len -= len;
+    }
+}
+
+/// A packet received by [PacketClient::receive_streaming]
+#[cfg(all(feature = "client-codec", feature = "server-codec"))]
+#[derive(Debug, PartialEq)]
+pub enum StreamingReceived<'a, const P: usize, const W: usize, const S: usize> {
+    /// A packet that fit in the buffer, received as normal
+    Packet(PacketGeneric<'a, P, W, S>),
+
+    /// A publish packet that was too large for the buffer. The payload
+    /// has been passed to the [PayloadSink], so the payload of `publish`
+    /// is empty, all other contents are present.
+    StreamedPublish {
+        publish: Publish<'a, P>,
+        payload_len: usize,
+    },
+}
+
+pub struct PacketClient<'a, C>
+where
+    C: Connection,
+{
+    connection: TransportConnection<C>,
     buf: &'a mut [u8],
+    flush_after_send: bool,
+    discard_oversized_packets: bool,
+    skip_unknown_properties: bool,
+    property_overflow: PropertyOverflow,
+    dropped_properties: usize,
+    read_error_context: Option<PacketReadErrorContext>,
+    write_error_context: Option<PacketWriteErrorContext>,
+    received_len: usize,
+    partial_len: usize,
+    batch_sent: usize,
+    rx: Option<ReceiveBuffer<'a>>,
+    #[cfg(feature = "client-codec")]
+    rx_received_len: usize,
 }
 
 // struct PositionBuf<'a> {}
@@ -53,53 +265,756 @@ where
     C: Connection,
 {
     pub fn new(connection: C, buf: &'a mut [u8]) -> Self {
-        Self { connection, buf }
+        Self {
+            connection: TransportConnection {
+                inner: connection,
+                error: None,
+            },
+            buf,
+            flush_after_send: true,
+            discard_oversized_packets: false,
+            skip_unknown_properties: false,
+            property_overflow: PropertyOverflow::Fail,
+            dropped_properties: 0,
+            read_error_context: None,
+            write_error_context: None,
+            received_len: 0,
+            partial_len: 0,
+            batch_sent: 0,
+            rx: None,
+            #[cfg(feature = "client-codec")]
+            rx_received_len: 0,
+        }
+    }
+
+    /// The error from the connection that caused the most recent
+    /// [PacketReadError::Transport] or [PacketWriteError::Transport], if any
+    pub fn transport_error(&self) -> Option<&C::Error> {
+        self.connection.error.as_ref()
+    }
+
+    /// Take the error from the connection that caused the most recent
+    /// [PacketReadError::Transport] or [PacketWriteError::Transport], if any,
+    /// leaving None
+    pub fn take_transport_error(&mut self) -> Option<C::Error> {
+        self.connection.error.take()
+    }
+
+    /// The number of packets from the start of the most recent batch that were
+    /// sent to the connection, see [PacketClient::send_batch]. If the batch
+    /// failed, later packets were not sent.
+    pub fn batch_sent(&self) -> usize {
+        self.batch_sent
+    }
+
+    /// Set whether the connection is flushed after sending each packet, see
+    /// [Connection::flush]. Enabled by default. If disabled, [PacketClient::flush]
+    /// should be called when packets need to have been sent, e.g. after disconnecting.
+    pub fn with_flush_after_send(mut self, flush_after_send: bool) -> Self {
+        self.flush_after_send = flush_after_send;
+        self
+    }
+
+    /// Receive packets to `rx`, separately from the buffer used to send packets.
+    /// Data is then received with [Connection::receive_available] or
+    /// [Connection::receive_ready], reading as much as is available, so when the
+    /// transport delivers several packets in one read, each is decoded in turn
+    /// from `rx` without receiving more data or copying. Received packets must
+    /// fit in `rx`, and [PacketClient::receive_streaming] can't be used.
+    pub fn with_receive_buffer(mut self, rx: &'a mut [u8]) -> Self {
+        self.rx = Some(ReceiveBuffer::new(rx));
+        self
+    }
+
+    /// Set whether a received packet that is too large for the buffer is
+    /// received and discarded, before failing with
+    /// [PacketReadError::PacketTooLargeForBuffer]. This leaves the connection
+    /// at the start of the next packet, and avoids closing a connection with
+    /// unread data, which for TCP may cause a reset that discards a Disconnect
+    /// we have just sent. Note that the whole packet must be received, so a
+    /// very large packet may take some time. Disabled by default, in which
+    /// case the rest of the packet is left unread.
+    pub fn with_discard_oversized_packets(mut self, discard_oversized_packets: bool) -> Self {
+        self.discard_oversized_packets = discard_oversized_packets;
+        self
+    }
+
+    /// Set whether received packets are decoded skipping any properties that are
+    /// not expected for the packet type, rather than failing with
+    /// [PacketReadError::UnexpectedPropertyIdentifier], to interoperate with servers
+    /// that send such properties. See
+    /// [PropertiesIter::with_skip_unknown](crate::data::property::PropertiesIter::with_skip_unknown).
+    /// Disabled by default.
+    pub fn with_skip_unknown_properties(mut self, skip_unknown_properties: bool) -> Self {
+        self.skip_unknown_properties = skip_unknown_properties;
+        self
+    }
+
+    /// Set how received packets with more properties than the packet's property
+    /// capacity are handled, see [PropertyOverflow]. With [PropertyOverflow::Count],
+    /// the number of properties dropped is available from
+    /// [PacketClient::dropped_properties]. Defaults to [PropertyOverflow::Fail].
+    pub fn with_property_overflow(mut self, property_overflow: PropertyOverflow) -> Self {
+        self.property_overflow = property_overflow;
+        self
+    }
+
+    /// The number of properties dropped from the last packet received, when
+    /// using [PropertyOverflow::Count]
+    pub fn dropped_properties(&self) -> usize {
+        self.dropped_properties
+    }
+
+    /// Context for the error decoding the last packet received, giving the
+    /// packet type and the byte position where decoding failed, or None if
+    /// it was decoded successfully. This is useful for diagnosing errors like
+    /// [PacketReadError::InsufficientData] from logs.
+    pub fn read_error_context(&self) -> Option<PacketReadErrorContext> {
+        self.read_error_context
+    }
+
+    /// Context for the error encoding the last packet sent with
+    /// [PacketClient::send], or None if it was encoded successfully
+    pub fn write_error_context(&self) -> Option<PacketWriteErrorContext> {
+        self.write_error_context
+    }
+
+    /// Flush any data buffered by the connection, see [Connection::flush]
+    pub async fn flush(&mut self) -> Result<(), PacketWriteError> {
+        self.connection.flush().await
+    }
+
+    async fn flush_if_needed(&mut self) -> Result<(), PacketWriteError> {
+        if self.flush_after_send {
+            self.flush().await
+        } else {
+            Ok(())
+        }
     }
 
     pub async fn send<P>(&mut self, packet: P) -> Result<(), PacketWriteError>
     where
         P: Packet + write::Write,
     {
+        // Connect and Auth packets may contain a password or authentication data,
+        // so wipe the buffer after sending them, whether or not this succeeds
+        #[cfg(feature = "zeroize")]
+        let wipe = matches!(packet.packet_type(), PacketType::Connect | PacketType::Auth);
+
+        let result = self.encode_and_send(&packet).await;
+
+        #[cfg(feature = "zeroize")]
+        if wipe {
+            zeroize::Zeroize::zeroize(&mut self.buf[self.partial_len..]);
+        }
+
+        result
+    }
+
+    async fn encode_and_send<P>(&mut self, packet: &P) -> Result<(), PacketWriteError>
+    where
+        P: Packet + write::Write,
+    {
+        self.received_len = 0;
+        let buf = &mut self.buf[self.partial_len..];
+        let result = {
+            let mut r = MqttBufWriter::new(&mut *buf);
+            r.put_with_context(packet).map(|_| r.position())
+        };
+        self.write_error_context = result.err();
+        let len = result?;
+        self.connection.send(&buf[0..len]).await?;
+        self.flush_if_needed().await
+    }
+
+    /// Send a batch of packets, encoding as many as will fit in the buffer
+    /// back-to-back and sending them with a single [Connection::send], repeating
+    /// until all packets are sent. This reduces the overhead of sending many small
+    /// packets, e.g. a burst of quality of service 0 publishes. Each packet must
+    /// fit in the buffer on its own. If enabled, the connection is flushed once,
+    /// after the whole batch is sent.
+    /// Packets are taken from `packets` as they are encoded, and the first error
+    /// stops the batch - in this case, packets earlier in the batch may or may
+    /// not have been sent, see [PacketClient::batch_sent].
+    pub async fn send_batch<P, E, I>(&mut self, packets: I) -> Result<(), E>
+    where
+        P: Packet + write::Write,
+        E: From<PacketWriteError>,
+        I: IntoIterator<Item = Result<P, E>>,
+    {
+        self.received_len = 0;
+        self.batch_sent = 0;
+        #[cfg(feature = "zeroize")]
+        let mut wipe = false;
+
+        let buf = &mut self.buf[self.partial_len..];
+        let mut len = 0;
+        // The number of packets encoded in the buffer, waiting to be sent
+        let mut buffered = 0;
+        let mut result = Ok(());
+        for packet in packets {
+            let packet = match packet {
+                Ok(packet) => packet,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            };
+
+            #[cfg(feature = "zeroize")]
+            {
+                wipe |= matches!(packet.packet_type(), PacketType::Connect | PacketType::Auth);
+            }
+
+            let mut lw = MqttLenWriter::new();
+            if let Err(e) = lw.put(&packet) {
+                result = Err(e.into());
+                break;
+            }
+            let packet_len = lw.position();
+            if len + packet_len > buf.len() && len > 0 {
+                if let Err(e) = self.connection.send(&buf[0..len]).await {
+                    result = Err(e.into());
+                    break;
+                }
+                self.batch_sent += buffered;
+                len = 0;
+                buffered = 0;
+            }
+
+            let mut r = MqttBufWriter::new(&mut buf[len..]);
+            if let Err(e) = r.put(&packet) {
+                result = Err(e.into());
+                break;
+            }
+            len += r.position();
+            buffered += 1;
+        }
+
+        if result.is_ok() && len > 0 {
+            result = self.connection.send(&buf[0..len]).await.map_err(E::from);
+            if result.is_ok() {
+                self.batch_sent += buffered;
+            }
+        }
+        if result.is_ok() {
+            result = self.flush_if_needed().await.map_err(E::from);
+        }
+
+        #[cfg(feature = "zeroize")]
+        if wipe {
+            zeroize::Zeroize::zeroize(&mut self.buf[self.partial_len..]);
+        }
+
+        result
+    }
+
+    /// Send a [StreamingPublish] packet, where the packet may be larger than
+    /// the buffer. The headers are put to the buffer, then it is filled with
+    /// chunks of the payload and sent, as many times as needed to send the
+    /// whole payload. The headers must fit in the buffer.
+    pub async fn send_streaming_publish<L, const P: usize>(
+        &mut self,
+        packet: &StreamingPublish<'_, L, P>,
+    ) -> Result<(), PacketWriteError>
+    where
+        L: PayloadSource,
+    {
+        self.received_len = 0;
+        let buf = &mut self.buf[self.partial_len..];
+        let mut len = {
+            let mut r = MqttBufWriter::new(&mut *buf);
+            packet.put_headers(&mut r)?;
+            r.position()
+        };
+
+        let payload_len = packet.payload().payload_len();
+        let mut offset = 0;
+        while offset < payload_len {
+            if len == buf.len() {
+                self.connection.send(&buf[0..len]).await?;
+                len = 0;
+            }
+            let mut r = MqttBufWriter::new(&mut buf[len..]);
+            let max_len = r.remaining();
+            let written = packet.put_payload_chunk(offset, max_len, &mut r)?;
+            offset += written;
+            len += written;
+        }
+
+        if len > 0 {
+            self.connection.send(&buf[0..len]).await?;
+        }
+        self.flush_if_needed().await
+    }
+
+    /// Send a [Publish] packet without copying the topic name or payload to the buffer.
+    /// The fixed header and topic name length are written to a small stack buffer and
+    /// sent, then the topic name is sent directly, then the packet identifier and
+    /// properties are written to the buffer and sent, and finally the payload is
+    /// sent directly. This is intended for quality of service 0 publishes with large
+    /// payloads, but note that the packet is sent with multiple calls to
+    /// [Connection::send], so this may be slower for small packets.
+    pub async fn send_publish_direct<const P: usize>(
+        &mut self,
+        packet: &Publish<'_, P>,
+    ) -> Result<(), PacketWriteError> {
+        self.received_len = 0;
+        let topic_name = packet.topic_name();
+        let payload = packet.payload();
+
+        // Check topic name as for `MqttWriter::put_str`
+        if topic_name.len() > DATA_MAX_LEN {
+            return Err(PacketWriteError::StringTooLarge);
+        } else if topic_name.contains('\0') {
+            return Err(PacketWriteError::NullCharacterInString);
+        }
+
+        // Length of variable header and payload
+        let remaining_length = {
+            let mut lw = MqttLenWriter::new();
+            lw.put_str(topic_name)?;
+            packet.put_variable_header_after_topic_name(&mut lw)?;
+            lw.position() + payload.len()
+        };
+
+        // Fixed header, then topic name length
+        let mut header = [0u8; 1 + VARIABLE_BYTE_INTEGER_MAX_LEN + 2];
+        let header_len = {
+            let mut r = MqttBufWriter::new(&mut header);
+            r.put_u8(packet.fixed_header_first_byte())?;
+            r.put_variable_u32(remaining_length as u32)?;
+            r.put_u16(topic_name.len() as u16)?;
+            r.position()
+        };
+        self.connection.send(&header[0..header_len]).await?;
+        self.connection.send(topic_name.as_bytes()).await?;
+
+        // Packet identifier and properties
+        let buf = &mut self.buf[self.partial_len..];
         let len = {
-            let mut r = MqttBufWriter::new(self.buf);
-            r.put(&packet)?;
+            let mut r = MqttBufWriter::new(&mut *buf);
+            packet.put_variable_header_after_topic_name(&mut r)?;
             r.position()
         };
-        self.connection.send(&self.buf[0..len]).await
+        self.connection.send(&buf[0..len]).await?;
+
+        if !payload.is_empty() {
+            self.connection.send(payload).await?;
+        }
+
+        self.flush_if_needed().await
     }
 
+    #[cfg(all(feature = "client-codec", feature = "server-codec"))]
     pub async fn receive<const P: usize, const W: usize, const S: usize>(
         &mut self,
     ) -> Result<PacketGeneric<'_, P, W, S>, PacketReadError> {
-        // First, try to read one byte with blocking
-        self.connection.receive(&mut self.buf[0..1]).await?;
-
-        // Then the rest of the packet
-        self.receive_rest_of_packet().await
+        self.receive_decoded().await
     }
 
+    /// Receive a packet if one is ready, without waiting for data, returning
+    /// `Ok(None)` if no complete packet is available yet. Data for a packet is
+    /// received as it becomes ready, using [Connection::receive_ready], so a
+    /// packet may be received over several calls. The partly received packet is
+    /// kept at the start of the buffer, and packets sent in the meantime use the
+    /// rest of the buffer. Any other receive also completes a partly received packet.
+    #[cfg(all(feature = "client-codec", feature = "server-codec"))]
     pub async fn receive_if_ready<const P: usize, const W: usize, const S: usize>(
         &mut self,
     ) -> Result<Option<PacketGeneric<'_, P, W, S>>, PacketReadError> {
-        // First, try to read one byte without blocking - if this returns false, no packet is ready
-        // and we can return immediately to avoid blocking
-        let packet_started = self
+        self.receive_decoded_if_ready().await
+    }
+
+    /// Wait to receive a packet sent by a server, as for [PacketClient::receive].
+    /// Decoding to a [ServerPacket] rather than a [PacketGeneric] uses less stack
+    /// and code in clients, but fails with [PacketReadError::ServerOnlyPacketType]
+    /// if a packet that only a server may receive is received.
+    #[cfg(feature = "client-codec")]
+    pub async fn receive_server_packet<const P: usize, const S: usize>(
+        &mut self,
+    ) -> Result<ServerPacket<'_, P, S>, PacketReadError> {
+        self.receive_decoded().await
+    }
+
+    /// Receive a packet sent by a server if one is ready, as for
+    /// [PacketClient::receive_if_ready], see [PacketClient::receive_server_packet]
+    #[cfg(feature = "client-codec")]
+    pub async fn receive_server_packet_if_ready<const P: usize, const S: usize>(
+        &mut self,
+    ) -> Result<Option<ServerPacket<'_, P, S>>, PacketReadError> {
+        self.receive_decoded_if_ready().await
+    }
+
+    /// Decode the packet last received by [PacketClient::receive_server_packet]
+    /// or [PacketClient::receive_server_packet_if_ready] again from the buffer,
+    /// without receiving any data. This allows a received packet to be returned
+    /// after other processing that needed to mutably borrow the client.
+    /// The packet is only available until the buffer is next used to send or
+    /// receive, after which this fails with [PacketReadError::InsufficientData].
+    #[cfg(feature = "client-codec")]
+    pub fn last_received_server_packet<const P: usize, const S: usize>(
+        &self,
+    ) -> Result<ServerPacket<'_, P, S>, PacketReadError> {
+        let mut packet_reader = MqttBufReader::new(self.received_data(self.received_len))
+            .with_skip_unknown_properties(self.skip_unknown_properties)
+            .with_property_overflow(self.property_overflow);
+        Ok(packet_reader.get_with_context()?)
+    }
+
+    #[cfg(feature = "client-codec")]
+    async fn receive_decoded<'b, T>(&'b mut self) -> Result<T, PacketReadError>
+    where
+        T: Read<'b>,
+    {
+        self.received_len = 0;
+        let len = loop {
+            if let Some(len) = self.receive_next(true).await? {
+                break len;
+            }
+        };
+        self.decode_received(len)
+    }
+
+    #[cfg(feature = "client-codec")]
+    async fn receive_decoded_if_ready<'b, T>(&'b mut self) -> Result<Option<T>, PacketReadError>
+    where
+        T: Read<'b>,
+    {
+        self.received_len = 0;
+        match self.receive_next(false).await? {
+            Some(len) => Ok(Some(self.decode_received(len)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Receive data for the next packet, using the receive buffer if there is
+    /// one, and return the length of the packet once it is complete, as for
+    /// [PacketClient::receive_packet_data]
+    #[cfg(feature = "client-codec")]
+    async fn receive_next(&mut self, wait: bool) -> Result<Option<usize>, PacketReadError> {
+        if self.rx.is_some() {
+            self.receive_buffered(wait).await
+        } else {
+            self.receive_packet_data(wait).await
+        }
+    }
+
+    /// Remove the packet last received from the start of the receive buffer,
+    /// then receive as much data as is available until the buffer starts with
+    /// a complete packet, and return its length. This only receives data when
+    /// the buffer doesn't already hold a complete packet. If `wait` is false,
+    /// only data that is already ready is received, and if there is no complete
+    /// packet this returns `Ok(None)`.
+    #[cfg(feature = "client-codec")]
+    async fn receive_buffered(&mut self, wait: bool) -> Result<Option<usize>, PacketReadError> {
+        let Some(rx) = self.rx.as_mut() else {
+            return Ok(None);
+        };
+        rx.consume(core::mem::take(&mut self.rx_received_len));
+        loop {
+            match encoded_packet_len(rx.data())? {
+                Some(len) if len <= rx.len => {
+                    self.rx_received_len = len;
+                    return Ok(Some(len));
+                }
+                Some(len) if len > rx.buf.len() => {
+                    return Err(PacketReadError::PacketTooLargeForBuffer)
+                }
+                _ if rx.len == rx.buf.len() => {
+                    return Err(PacketReadError::PacketTooLargeForBuffer)
+                }
+                _ => {}
+            }
+
+            let received = if wait {
+                self.connection
+                    .receive_available(&mut rx.buf[rx.len..])
+                    .await?
+            } else {
+                self.connection.receive_ready(&mut rx.buf[rx.len..]).await?
+            };
+            if received == 0 {
+                return Ok(None);
+            }
+            rx.len += received;
+        }
+    }
+
+    /// The first `len` bytes of received data, from the receive buffer if
+    /// there is one
+    #[cfg(feature = "client-codec")]
+    fn received_data(&self, len: usize) -> &[u8] {
+        match &self.rx {
+            Some(rx) => &rx.buf[0..len],
+            None => &self.buf[0..len],
+        }
+    }
+
+    /// Receive data for the next packet to the start of the buffer, continuing
+    /// any packet partly received by an earlier call, and return the length of
+    /// the packet once it is complete. If `wait` is false, only data that is
+    /// already ready is received, using [Connection::receive_ready], and if the
+    /// packet is not yet complete this returns `Ok(None)`, keeping the data
+    /// received so far for the next call. The fixed header is received a byte
+    /// at a time, so that no data after the end of the packet is received.
+    #[cfg(feature = "client-codec")]
+    async fn receive_packet_data(&mut self, wait: bool) -> Result<Option<usize>, PacketReadError> {
+        let result = self.receive_packet_data_to_end(wait).await;
+        // The partial packet is finished with unless we are still receiving it
+        if !matches!(result, Ok(None)) {
+            self.partial_len = 0;
+        }
+        result
+    }
+
+    #[cfg(feature = "client-codec")]
+    async fn receive_packet_data_to_end(
+        &mut self,
+        wait: bool,
+    ) -> Result<Option<usize>, PacketReadError> {
+        loop {
+            // Receive the next byte of the fixed header, or the rest of the packet
+            let end = match fixed_header(&self.buf[0..self.partial_len])? {
+                None => self.partial_len + 1,
+                Some((header_len, remaining_length)) => {
+                    let end = header_len + remaining_length;
+                    if end > self.buf.len() {
+                        return Err(self.packet_too_large(remaining_length).await);
+                    }
+                    if self.partial_len == end {
+                        return Ok(Some(end));
+                    }
+                    end
+                }
+            };
+            if end > self.buf.len() {
+                return Err(PacketReadError::PacketTooLargeForBuffer);
+            }
+
+            let received = if wait {
+                self.connection
+                    .receive(&mut self.buf[self.partial_len..end])
+                    .await?;
+                end - self.partial_len
+            } else {
+                self.connection
+                    .receive_ready(&mut self.buf[self.partial_len..end])
+                    .await?
+            };
+            if received == 0 {
+                return Ok(None);
+            }
+            self.partial_len += received;
+        }
+    }
+
+    /// Decode a received packet of `len` bytes at the start of the buffer, or
+    /// of the receive buffer if there is one
+    #[cfg(feature = "client-codec")]
+    fn decode_received<'b, T>(&'b mut self, len: usize) -> Result<T, PacketReadError>
+    where
+        T: Read<'b>,
+    {
+        let data = match &self.rx {
+            Some(rx) => &rx.buf[0..len],
+            None => &self.buf[0..len],
+        };
+        let mut packet_reader = MqttBufReader::new(data)
+            .with_skip_unknown_properties(self.skip_unknown_properties)
+            .with_property_overflow(self.property_overflow);
+        self.dropped_properties = 0;
+        let result = packet_reader.get_with_context();
+        self.dropped_properties = packet_reader.dropped_properties();
+        self.read_error_context = result.as_ref().err().copied();
+        if result.is_ok() {
+            self.received_len = len;
+        }
+        Ok(result?)
+    }
+
+    /// Wait until data is available, then receive as much as is available and
+    /// fits in `rx`, using [Connection::receive_available], returning the number
+    /// of bytes received. When the transport delivers several packets in one read,
+    /// they can then all be decoded from `rx` without further copying, see
+    /// [ReceiveBuffer::packets].
+    /// If `rx` is already full, nothing is received, and this returns `Ok(0)`
+    /// if `rx` starts with a complete packet, which must be consumed before
+    /// receiving more, or otherwise fails with
+    /// [PacketReadError::PacketTooLargeForBuffer], since `rx` then holds the
+    /// start of a single packet that can't fit.
+    pub async fn receive_available(
+        &mut self,
+        rx: &mut ReceiveBuffer<'_>,
+    ) -> Result<usize, PacketReadError> {
+        if rx.len == rx.buf.len() {
+            return match encoded_packet_len(rx.data())? {
+                Some(len) if len <= rx.len => Ok(0),
+                _ => Err(PacketReadError::PacketTooLargeForBuffer),
+            };
+        }
+        let received = self
             .connection
-            .receive_if_ready(&mut self.buf[0..1])
+            .receive_available(&mut rx.buf[rx.len..])
             .await?;
+        rx.len += received;
+        Ok(received)
+    }
 
-        if !packet_started {
-            return Ok(None);
+    /// Wait to receive a packet, as for [PacketClient::receive]. However if a publish
+    /// packet is too large for the buffer, instead of failing, the headers are
+    /// received to the buffer and decoded, then the payload is received in chunks
+    /// using the remaining space in the buffer, and each chunk is passed to `sink`.
+    /// The headers of the publish packet must fit in the buffer, with at least one
+    /// byte to spare.
+    #[cfg(all(feature = "client-codec", feature = "server-codec"))]
+    pub async fn receive_streaming<const P: usize, const W: usize, const S: usize, K>(
+        &mut self,
+        sink: &mut K,
+    ) -> Result<StreamingReceived<'_, P, W, S>, PacketReadError>
+    where
+        K: PayloadSink,
+    {
+        self.received_len = 0;
+
+        // Finish any packet partly received by an earlier call, this must fit in the buffer
+        if self.partial_len > 0 {
+            let len = loop {
+                if let Some(len) = self.receive_packet_data(true).await? {
+                    break len;
+                }
+            };
+            return Ok(StreamingReceived::Packet(self.decode_received(len)?));
         }
 
-        // We have packet, and its first byte is in our buffer, so receive the rest of the packet
-        let packet = self.receive_rest_of_packet().await?;
-        Ok(Some(packet))
+        self.connection.receive(&mut self.buf[0..1]).await?;
+        let (mut position, remaining_length) = self.receive_fixed_header().await?;
+
+        // If packet fits, receive it as normal
+        if position + remaining_length <= self.buf.len() {
+            let packet = self.receive_packet(position, remaining_length).await?;
+            return Ok(StreamingReceived::Packet(packet));
+        }
+
+        let first_header_byte = self.buf[0];
+        if !is_valid_publish_first_header_byte(first_header_byte) {
+            return Err(self.packet_too_large(remaining_length).await);
+        }
+        let header_end = position;
+
+        // Topic name, and packet identifier if qos is 1 or 2
+        self.receive_to_buffer(position, 2).await?;
+        let topic_len = u16::from_be_bytes([self.buf[position], self.buf[position + 1]]) as usize;
+        position += 2;
+        let packet_identifier_len = if first_header_byte & 0x06 == 0 { 0 } else { 2 };
+        self.receive_to_buffer(position, topic_len + packet_identifier_len)
+            .await?;
+        position += topic_len + packet_identifier_len;
+
+        // Property list length, as variable byte integer of up to 4 bytes
+        let properties_len_start = position;
+        loop {
+            self.receive_to_buffer(position, 1).await?;
+            position += 1;
+            if self.buf[position - 1] & 128 == 0 {
+                break;
+            } else if position - properties_len_start == 4 {
+                return Err(PacketReadError::InvalidVariableByteIntegerEncoding);
+            }
+        }
+        let properties_len = {
+            let mut r = MqttBufReader::new(&self.buf[properties_len_start..position]);
+            r.get_variable_u32()?
+        } as usize;
+
+        // Property list
+        self.receive_to_buffer(position, properties_len).await?;
+        position += properties_len;
+
+        let variable_header_len = position - header_end;
+        if variable_header_len > remaining_length {
+            return Err(PacketReadError::IncorrectPacketLength);
+        }
+        let payload_len = remaining_length - variable_header_len;
+
+        let (headers, chunk_buf) = self.buf.split_at_mut(position);
+        if chunk_buf.is_empty() {
+            return Err(PacketReadError::PacketTooLargeForBuffer);
+        }
+
+        // Decode the headers, with an empty payload
+        let mut r = MqttBufReader::new(&headers[header_end..])
+            .with_skip_unknown_properties(self.skip_unknown_properties)
+            .with_property_overflow(self.property_overflow);
+        self.dropped_properties = 0;
+        let result = Publish::get_variable_header_and_payload(
+            &mut r,
+            first_header_byte,
+            variable_header_len,
+        )
+        .map_err(|error| PacketReadErrorContext {
+            error,
+            packet_type: Some(PacketType::Publish),
+            position: header_end + r.position(),
+        });
+        self.dropped_properties = r.dropped_properties();
+        self.read_error_context = result.as_ref().err().copied();
+        let publish = result?;
+
+        // Stream the payload to the sink
+        let mut offset = 0;
+        while offset < payload_len {
+            let chunk_len = chunk_buf.len().min(payload_len - offset);
+            let chunk = &mut chunk_buf[0..chunk_len];
+            self.connection.receive(chunk).await?;
+            sink.write_chunk(offset, chunk)?;
+            offset += chunk_len;
+        }
+
+        Ok(StreamingReceived::StreamedPublish {
+            publish,
+            payload_len,
+        })
     }
 
-    async fn receive_rest_of_packet<const P: usize, const W: usize, const S: usize>(
+    /// Receive `len` bytes to the buffer at `position`, failing with
+    /// [PacketReadError::PacketTooLargeForBuffer] if they will not fit
+    #[cfg(all(feature = "client-codec", feature = "server-codec"))]
+    async fn receive_to_buffer(
         &mut self,
-    ) -> Result<PacketGeneric<'_, P, W, S>, PacketReadError> {
+        position: usize,
+        len: usize,
+    ) -> Result<(), PacketReadError> {
+        if position + len > self.buf.len() {
+            return Err(PacketReadError::PacketTooLargeForBuffer);
+        }
+        self.connection
+            .receive(&mut self.buf[position..position + len])
+            .await
+    }
+
+    /// Handle a packet with `remaining_length` bytes after the fixed header
+    /// that is too large for the buffer, discarding those bytes if enabled,
+    /// and returning the error to produce
+    #[cfg(feature = "client-codec")]
+    async fn packet_too_large(&mut self, remaining_length: usize) -> PacketReadError {
+        if self.discard_oversized_packets {
+            let mut remaining = remaining_length;
+            while remaining > 0 {
+                let chunk_len = self.buf.len().min(remaining);
+                if let Err(e) = self.connection.receive(&mut self.buf[0..chunk_len]).await {
+                    return e;
+                }
+                remaining -= chunk_len;
+            }
+        }
+        PacketReadError::PacketTooLargeForBuffer
+    }
+
+    /// Receive the rest of the fixed header, after the first byte, returning
+    /// the position after the header, and the remaining length of the packet
+    #[cfg(all(feature = "client-codec", feature = "server-codec"))]
+    async fn receive_fixed_header(&mut self) -> Result<(usize, usize), PacketReadError> {
         let mut position: usize = 1;
 
         // Check first header byte is valid, if not we can error early without
@@ -138,11 +1053,20 @@ where
             r.get_variable_u32()?
         } as usize;
 
-        // If packet will not fit in buffer, error
-        if position + remaining_length > self.buf.len() {
-            return Err(PacketReadError::PacketTooLargeForBuffer);
-        }
+        Ok((position, remaining_length))
+    }
 
+    /// Receive the rest of a packet that fits in the buffer, after the fixed
+    /// header ending at `position`, and decode it
+    #[cfg(all(feature = "client-codec", feature = "server-codec"))]
+    async fn receive_packet<'b, T>(
+        &'b mut self,
+        mut position: usize,
+        remaining_length: usize,
+    ) -> Result<T, PacketReadError>
+    where
+        T: Read<'b>,
+    {
         // Read the rest of the packet
         self.connection
             .receive(&mut self.buf[position..position + remaining_length])
@@ -150,15 +1074,39 @@ where
         position += remaining_length;
 
         // We can now decode the packet from the buffer
-        let packet_buf = &mut self.buf[0..position];
-        let mut packet_reader = MqttBufReader::new(packet_buf);
-        let packet_generic = packet_reader.get()?;
+        self.decode_received(position)
+    }
+}
 
-        Ok(packet_generic)
+/// Parse the fixed header at the start of `data`, returning the length of the
+/// header and the remaining length of the packet, or `None` if more data is
+/// needed to complete the header
+#[cfg(feature = "client-codec")]
+fn fixed_header(data: &[u8]) -> Result<Option<(usize, usize)>, PacketReadError> {
+    let Some(first_header_byte) = data.first() else {
+        return Ok(None);
+    };
+
+    // Check first header byte is valid, if not we can error early without
+    // trying to read the rest of a packet
+    if !PacketType::is_valid_first_header_byte(*first_header_byte) {
+        return Err(PacketReadError::InvalidPacketType);
+    }
+
+    // Remaining length is encoded as a variable u32 of up to 4 bytes
+    let length_bytes = &data[1..data.len().min(5)];
+    match length_bytes.iter().position(|b| b & 128 == 0) {
+        Some(last) => {
+            let mut r = MqttBufReader::new(&length_bytes[0..=last]);
+            let remaining_length = r.get_variable_u32()? as usize;
+            Ok(Some((last + 2, remaining_length)))
+        }
+        None if length_bytes.len() == 4 => Err(PacketReadError::InvalidVariableByteIntegerEncoding),
+        None => Ok(None),
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "client-codec", feature = "server-codec"))]
 mod tests {
     use super::*;
     use crate::{
@@ -168,6 +1116,7 @@ mod tests {
             property::{ConnectProperty, SubscribeProperty},
             quality_of_service::QualityOfService,
         },
+        error::TransportErrorKind,
         packets::{
             connect::Connect,
             pingreq::Pingreq,
@@ -233,32 +1182,73 @@ mod tests {
     struct BufferConnection<'a> {
         reader: MqttBufReader<'a>,
         writer: MqttBufWriter<'a>,
+        sends: usize,
+        flushes: usize,
+        ready: usize,
     }
 
     impl<'a> BufferConnection<'a> {
         pub fn new(read_buf: &'a [u8], write_buf: &'a mut [u8]) -> Self {
             let reader = MqttBufReader::new(read_buf);
             let writer = MqttBufWriter::new(write_buf);
-            BufferConnection { reader, writer }
+            BufferConnection {
+                reader,
+                writer,
+                sends: 0,
+                flushes: 0,
+                ready: usize::MAX,
+            }
         }
     }
 
     impl Connection for BufferConnection<'_> {
-        async fn send(&mut self, buf: &[u8]) -> Result<(), PacketWriteError> {
-            self.writer.put_slice(buf)
+        // Running out of data or space behaves like a closed connection
+        type Error = TransportErrorKind;
+
+        async fn send(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+            self.sends += 1;
+            self.writer
+                .put_slice(buf)
+                .map_err(|_| TransportErrorKind::Closed)
+        }
+
+        async fn flush(&mut self) -> Result<(), Self::Error> {
+            self.flushes += 1;
+            Ok(())
         }
 
-        async fn receive(&mut self, buf: &mut [u8]) -> Result<(), PacketReadError> {
-            let slice = self.reader.get_slice(buf.len())?;
+        async fn receive(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+            let slice = self
+                .reader
+                .get_slice(buf.len())
+                .map_err(|_| TransportErrorKind::Closed)?;
             buf.copy_from_slice(slice);
             Ok(())
         }
 
-        async fn receive_if_ready(&mut self, buf: &mut [u8]) -> Result<bool, PacketReadError> {
+        async fn receive_if_ready(&mut self, buf: &mut [u8]) -> Result<bool, Self::Error> {
             // Assume data is always ready, will error on underflow as required for tests
             self.receive(buf).await?;
             Ok(true)
         }
+
+        async fn receive_ready(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            // Deliver up to `ready` bytes, to simulate data arriving slowly
+            let len = buf.len().min(self.reader.remaining()).min(self.ready);
+            self.ready -= len;
+            self.receive(&mut buf[0..len]).await?;
+            Ok(len)
+        }
+
+        async fn receive_available(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            // Deliver up to 3 bytes per read, so packets are split across reads
+            let len = buf.len().min(self.reader.remaining()).min(3);
+            if len == 0 {
+                return Err(TransportErrorKind::Closed);
+            }
+            self.receive(&mut buf[0..len]).await?;
+            Ok(len)
+        }
     }
 
     async fn decode(data: &[u8], packet_generic: PacketGeneric<'_, 16, 16, 16>) {
@@ -296,9 +1286,19 @@ mod tests {
         let mut buf = [0; 1024];
         let mut client = PacketClient::new(connection, &mut buf);
 
-        let packet: Result<Option<PacketGeneric<'_, 16, 16, 16>>, PacketReadError> =
-            client.receive_if_ready().await;
-        assert_eq!(packet, Err(PacketReadError::IncorrectPacketLength));
+        {
+            let packet: Result<Option<PacketGeneric<'_, 16, 16, 16>>, PacketReadError> =
+                client.receive_if_ready().await;
+            assert_eq!(packet, Err(PacketReadError::IncorrectPacketLength));
+        }
+        assert_eq!(
+            client.read_error_context(),
+            Some(PacketReadErrorContext {
+                error: PacketReadError::IncorrectPacketLength,
+                packet_type: Some(PacketType::Connect),
+                position: 18,
+            })
+        );
     }
 
     #[tokio::test]
@@ -341,6 +1341,194 @@ mod tests {
         encode(example_connect_packet(), &ENCODED_CONNECT).await;
     }
 
+    #[tokio::test]
+    async fn streaming_publish_is_sent_in_chunks_larger_than_buffer() {
+        use crate::data::packet_identifier::PublishPacketIdentifier;
+        use crate::packets::publish::Publish;
+
+        let payload: [u8; 300] = core::array::from_fn(|i| i as u8);
+        let packet: Publish<'_, 0> = Publish::new(
+            false,
+            false,
+            "test/topic",
+            PublishPacketIdentifier::None,
+            &payload,
+            Vec::new(),
+        );
+
+        let mut expected = [0; 1024];
+        let expected_len = {
+            let mut r = MqttBufWriter::new(&mut expected);
+            r.put(&packet).unwrap();
+            r.position()
+        };
+
+        let read_buf = [];
+        let mut write_buf = [0; 1024];
+        let connection = BufferConnection::new(&read_buf, &mut write_buf);
+
+        // Buffer is much smaller than the packet, but large enough for headers
+        let mut buf = [0; 32];
+        let mut client = PacketClient::new(connection, &mut buf);
+        let streaming = packet.with_payload_source(payload.as_slice());
+        client.send_streaming_publish(&streaming).await.unwrap();
+
+        assert_eq!(&write_buf[0..expected_len], &expected[0..expected_len]);
+    }
+
+    #[tokio::test]
+    async fn connection_is_flushed_after_send_unless_disabled() {
+        let read_buf = [];
+        let mut write_buf = [0; 1024];
+        let connection = BufferConnection::new(&read_buf, &mut write_buf);
+        let mut buf = [0; 1024];
+        let mut client = PacketClient::new(connection, &mut buf);
+        client.send(Pingreq::default()).await.unwrap();
+        assert_eq!(client.connection.inner.flushes, 1);
+
+        let mut client = client.with_flush_after_send(false);
+        client.send(Pingreq::default()).await.unwrap();
+        assert_eq!(client.connection.inner.flushes, 1);
+        client.flush().await.unwrap();
+        assert_eq!(client.connection.inner.flushes, 2);
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[tokio::test]
+    async fn buffer_is_wiped_after_sending_connect() {
+        let read_buf = [];
+        let mut write_buf = [0; 1024];
+        let connection = BufferConnection::new(&read_buf, &mut write_buf);
+        let mut buf = [0; 1024];
+        let mut client = PacketClient::new(connection, &mut buf);
+
+        client.send(Pingreq::default()).await.unwrap();
+        assert_eq!(client.buf[0..2], ENCODED_PINGRESP);
+
+        let packet: Connect<'_, 0, 0> = Connect::new(
+            60,
+            Some("user"),
+            Some(b"password"),
+            "id",
+            true,
+            None,
+            Vec::new(),
+        );
+        client.send(packet).await.unwrap();
+        assert!(client.buf.iter().all(|b| *b == 0));
+    }
+
+    #[tokio::test]
+    async fn publish_sent_directly_matches_encoding() {
+        use crate::data::packet_identifier::PublishPacketIdentifier;
+        use crate::data::property::PublishProperty;
+
+        let payload: [u8; 300] = core::array::from_fn(|i| i as u8);
+        let mut properties = Vec::<PublishProperty<'_>, 1>::new();
+        properties
+            .push(PublishProperty::MessageExpiryInterval(60.into()))
+            .unwrap();
+        let packet = Publish::new(
+            false,
+            true,
+            "test/topic",
+            PublishPacketIdentifier::None,
+            &payload,
+            properties,
+        );
+
+        let mut expected = [0; 1024];
+        let expected_len = {
+            let mut r = MqttBufWriter::new(&mut expected);
+            r.put(&packet).unwrap();
+            r.position()
+        };
+
+        let read_buf = [];
+        let mut write_buf = [0; 1024];
+        let connection = BufferConnection::new(&read_buf, &mut write_buf);
+
+        // Buffer only needs to hold the properties
+        let mut buf = [0; 16];
+        let mut client = PacketClient::new(connection, &mut buf);
+        client.send_publish_direct(&packet).await.unwrap();
+
+        assert_eq!(&write_buf[0..expected_len], &expected[0..expected_len]);
+    }
+
+    #[tokio::test]
+    async fn large_publish_payload_is_received_in_chunks_to_sink() {
+        use crate::data::packet_identifier::PublishPacketIdentifier;
+        use crate::data::property::PublishProperty;
+
+        let payload: [u8; 300] = core::array::from_fn(|i| i as u8);
+        let mut properties = Vec::<PublishProperty<'_>, 1>::new();
+        properties
+            .push(PublishProperty::MessageExpiryInterval(60.into()))
+            .unwrap();
+        let packet = Publish::new(
+            false,
+            false,
+            "test/topic",
+            PublishPacketIdentifier::Qos1(PacketIdentifier(7)),
+            &payload,
+            properties,
+        );
+
+        let mut encoded = [0; 1024];
+        let encoded_len = {
+            let mut r = MqttBufWriter::new(&mut encoded);
+            r.put(&packet).unwrap();
+            r.position()
+        };
+
+        let mut write_buf = [];
+        let connection = BufferConnection::new(&encoded[0..encoded_len], &mut write_buf);
+        let mut buf = [0; 64];
+        let mut client = PacketClient::new(connection, &mut buf);
+
+        let mut received = [0u8; 300];
+        let mut chunks = 0;
+        let mut sink = |offset: usize, chunk: &[u8]| {
+            received[offset..offset + chunk.len()].copy_from_slice(chunk);
+            chunks += 1;
+            Ok(())
+        };
+        let result: StreamingReceived<'_, 1, 0, 0> =
+            client.receive_streaming(&mut sink).await.unwrap();
+
+        match result {
+            StreamingReceived::StreamedPublish {
+                publish,
+                payload_len,
+            } => {
+                assert_eq!(payload_len, 300);
+                assert_eq!(publish.topic_name(), "test/topic");
+                assert_eq!(publish.properties(), packet.properties());
+                assert!(publish.payload().is_empty());
+            }
+            _ => panic!("Expected streamed publish"),
+        }
+        assert!(chunks > 1);
+        assert_eq!(received, payload);
+    }
+
+    #[tokio::test]
+    async fn small_packets_are_received_normally_when_streaming() {
+        let mut write_buf = [];
+        let connection = BufferConnection::new(&ENCODED_SUBSCRIBE, &mut write_buf);
+        let mut buf = [0; 64];
+        let mut client = PacketClient::new(connection, &mut buf);
+
+        let mut sink = |_offset: usize, _chunk: &[u8]| Err(PacketReadError::PayloadSink);
+        let result: StreamingReceived<'_, 16, 16, 16> =
+            client.receive_streaming(&mut sink).await.unwrap();
+        assert_eq!(
+            result,
+            StreamingReceived::Packet(PacketGeneric::Subscribe(example_subscribe_packet()))
+        );
+    }
+
     #[tokio::test]
     async fn decode_fails_on_invalid_packet_type() {
         let mut write_buf = [];
@@ -355,6 +1543,27 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn receive_fails_with_transport_error_when_connection_closes() {
+        let mut write_buf = [];
+        let connection = BufferConnection::new(&[0xD0], &mut write_buf);
+
+        let mut buf = [0; 1024];
+        let mut client = PacketClient::new(connection, &mut buf);
+        assert_eq!(client.transport_error(), None);
+
+        assert_eq!(
+            client.receive::<16, 16, 16>().await,
+            Err(PacketReadError::Transport(TransportErrorKind::Closed))
+        );
+        assert_eq!(client.transport_error(), Some(&TransportErrorKind::Closed));
+        assert_eq!(
+            client.take_transport_error(),
+            Some(TransportErrorKind::Closed)
+        );
+        assert_eq!(client.transport_error(), None);
+    }
+
     #[tokio::test]
     async fn decode_fails_on_invalid_length_encoding() {
         let mut write_buf = [];
@@ -382,4 +1591,241 @@ mod tests {
             Err(PacketReadError::PacketTooLargeForBuffer)
         );
     }
+
+    #[tokio::test]
+    async fn oversized_packet_is_discarded_if_enabled() {
+        let mut read_buf = [0; 20];
+        read_buf[0..2].copy_from_slice(&ENCODED_IMPLIES_PACKET_LENGTH_18);
+        read_buf[18..20].copy_from_slice(&ENCODED_PINGRESP);
+        let mut write_buf = [];
+        let connection = BufferConnection::new(&read_buf, &mut write_buf);
+
+        let mut buf = [0; 5];
+        let mut client =
+            PacketClient::new(connection, &mut buf).with_discard_oversized_packets(true);
+
+        assert_eq!(
+            client.receive::<16, 16, 16>().await,
+            Err(PacketReadError::PacketTooLargeForBuffer)
+        );
+        assert_eq!(
+            client.receive::<16, 16, 16>().await,
+            Ok(PacketGeneric::Pingreq(Pingreq::default()))
+        );
+    }
+
+    #[tokio::test]
+    async fn available_packets_are_decoded_in_bulk() {
+        use crate::packets::pingresp::Pingresp;
+
+        // Pingreq, Pingresp, Pingreq, received 3 bytes at a time
+        let read_buf = [0xC0, 0x00, 0xD0, 0x00, 0xC0, 0x00];
+        let mut write_buf = [];
+        let connection = BufferConnection::new(&read_buf, &mut write_buf);
+        let mut buf = [0; 16];
+        let mut client = PacketClient::new(connection, &mut buf);
+        let mut rx_buf = [0; 16];
+        let mut rx = ReceiveBuffer::new(&mut rx_buf);
+
+        assert_eq!(client.receive_available(&mut rx).await, Ok(3));
+        let consumed = {
+            let mut packets = rx.packets::<0, 0, 0>();
+            assert_eq!(
+                packets.next(),
+                Some(Ok(PacketGeneric::Pingreq(Pingreq::default())))
+            );
+            assert_eq!(packets.next(), None);
+            packets.consumed()
+        };
+        rx.consume(consumed);
+        assert_eq!(rx.data(), &[0xD0]);
+
+        assert_eq!(client.receive_available(&mut rx).await, Ok(3));
+        let consumed = {
+            let mut packets = rx.packets::<0, 0, 0>();
+            assert_eq!(
+                packets.next(),
+                Some(Ok(PacketGeneric::Pingresp(Pingresp::default())))
+            );
+            assert_eq!(
+                packets.next(),
+                Some(Ok(PacketGeneric::Pingreq(Pingreq::default())))
+            );
+            assert_eq!(packets.next(), None);
+            packets.consumed()
+        };
+        rx.consume(consumed);
+        assert!(rx.data().is_empty());
+    }
+
+    #[tokio::test]
+    async fn receive_available_fails_only_when_full_with_a_single_incomplete_packet() {
+        // Pingreq, Pingreq, then a publish too large for the buffer
+        let read_buf = [0xC0, 0x00, 0xC0, 0x00, 0x30, 0x05, 0x00, 0x01, b'a'];
+        let mut write_buf = [];
+        let connection = BufferConnection::new(&read_buf, &mut write_buf);
+        let mut buf = [0; 16];
+        let mut client = PacketClient::new(connection, &mut buf);
+        let mut rx_buf = [0; 4];
+        let mut rx = ReceiveBuffer::new(&mut rx_buf);
+
+        assert_eq!(client.receive_available(&mut rx).await, Ok(3));
+        assert_eq!(client.receive_available(&mut rx).await, Ok(1));
+
+        // The full buffer holds complete packets, so nothing is received
+        assert_eq!(client.receive_available(&mut rx).await, Ok(0));
+        rx.consume(4);
+
+        assert_eq!(client.receive_available(&mut rx).await, Ok(3));
+        assert_eq!(client.receive_available(&mut rx).await, Ok(1));
+        assert_eq!(
+            client.receive_available(&mut rx).await,
+            Err(PacketReadError::PacketTooLargeForBuffer)
+        );
+    }
+
+    #[tokio::test]
+    async fn packets_are_received_from_receive_buffer() {
+        use crate::packets::pingresp::Pingresp;
+
+        // Pingresp, Pingreq, Pingresp, received 3 bytes at a time
+        let read_buf = [0xD0, 0x00, 0xC0, 0x00, 0xD0, 0x00];
+        let mut write_buf = [0; 2];
+        let connection = BufferConnection::new(&read_buf, &mut write_buf);
+        let mut buf = [0; 16];
+        let mut rx_buf = [0; 16];
+        let mut client = PacketClient::new(connection, &mut buf).with_receive_buffer(&mut rx_buf);
+
+        assert_eq!(
+            client.receive::<0, 0, 0>().await,
+            Ok(PacketGeneric::Pingresp(Pingresp::default()))
+        );
+
+        // Sending doesn't disturb the received data
+        client.send(Pingreq::default()).await.unwrap();
+        assert_eq!(client.rx.as_ref().unwrap().data(), &[0xD0, 0x00, 0xC0]);
+
+        assert_eq!(
+            client.receive_if_ready::<0, 0, 0>().await,
+            Ok(Some(PacketGeneric::Pingreq(Pingreq::default())))
+        );
+        assert_eq!(
+            client.receive::<0, 0, 0>().await,
+            Ok(PacketGeneric::Pingresp(Pingresp::default()))
+        );
+        assert_eq!(
+            client.last_received_server_packet::<0, 0>(),
+            Ok(ServerPacket::Pingresp(Pingresp::default()))
+        );
+        assert_eq!(client.connection.inner.sends, 1);
+    }
+
+    #[tokio::test]
+    async fn partial_packets_are_received_across_polls() {
+        use crate::{
+            data::packet_identifier::PublishPacketIdentifier,
+            packets::{pingresp::Pingresp, publish::Publish},
+        };
+
+        // Qos 0 publish to "a" with payload "b", then Pingresp
+        let read_buf = [0x30, 0x05, 0x00, 0x01, b'a', 0x00, b'b', 0xD0, 0x00];
+        let mut write_buf = [0; 2];
+        let mut connection = BufferConnection::new(&read_buf, &mut write_buf);
+        connection.ready = 1;
+        let mut buf = [0; 16];
+        let mut client = PacketClient::new(connection, &mut buf);
+
+        // First header byte, then the length and part of the packet
+        assert_eq!(
+            client.receive_server_packet_if_ready::<0, 0>().await,
+            Ok(None)
+        );
+        client.connection.inner.ready = 3;
+        assert_eq!(
+            client.receive_server_packet_if_ready::<0, 0>().await,
+            Ok(None)
+        );
+
+        // Sending doesn't disturb the partly received packet
+        client.send(Pingreq::default()).await.unwrap();
+
+        // Rest of the publish is received, without receiving the Pingresp
+        client.connection.inner.ready = usize::MAX;
+        let expected: Publish<'_, 0> = Publish::new(
+            false,
+            false,
+            "a",
+            PublishPacketIdentifier::None,
+            b"b",
+            Vec::new(),
+        );
+        assert_eq!(
+            client.receive_server_packet_if_ready::<0, 0>().await,
+            Ok(Some(ServerPacket::Publish(expected)))
+        );
+        assert_eq!(
+            client.receive_server_packet_if_ready::<0, 0>().await,
+            Ok(Some(ServerPacket::Pingresp(Pingresp::default())))
+        );
+
+        assert_eq!(write_buf, [0xC0, 0x00]);
+    }
+
+    #[tokio::test]
+    async fn batch_is_sent_with_one_send_per_full_buffer() {
+        use crate::data::packet_identifier::PublishPacketIdentifier;
+        use crate::packets::publish::Publish;
+
+        // Each publish is encoded as 10 bytes
+        let publish = |payload: &'static [u8]| -> Result<Publish<'static, 0>, PacketWriteError> {
+            Ok(Publish::new(
+                false,
+                false,
+                "a/b",
+                PublishPacketIdentifier::None,
+                payload,
+                Vec::new(),
+            ))
+        };
+        let mut expected = [0u8; 40];
+        for (i, payload) in [b"p0", b"p1", b"p2", b"p3"].iter().enumerate() {
+            let mut r = MqttBufWriter::new(&mut expected[i * 10..]);
+            r.put(&publish(*payload).unwrap()).unwrap();
+            assert_eq!(r.position(), 10);
+        }
+
+        let read_buf = [];
+        let mut write_buf = [0; 128];
+        let connection = BufferConnection::new(&read_buf, &mut write_buf);
+        let mut buf = [0; 32];
+        let mut client = PacketClient::new(connection, &mut buf);
+        client
+            .send_batch([b"p0", b"p1", b"p2"].into_iter().map(|p| publish(p)))
+            .await
+            .unwrap();
+        assert_eq!(client.connection.inner.sends, 1);
+        assert_eq!(client.connection.inner.flushes, 1);
+        assert_eq!(client.batch_sent(), 3);
+
+        client
+            .send_batch([b"p0", b"p1", b"p2", b"p3"].into_iter().map(|p| publish(p)))
+            .await
+            .unwrap();
+        assert_eq!(client.connection.inner.sends, 3);
+        assert_eq!(client.connection.inner.flushes, 2);
+        assert_eq!(client.batch_sent(), 4);
+
+        assert_eq!(
+            client
+                .send_batch([publish(b"p0"), Err(PacketWriteError::Overflow)])
+                .await,
+            Err(PacketWriteError::Overflow)
+        );
+        assert_eq!(client.connection.inner.sends, 3);
+        assert_eq!(client.batch_sent(), 0);
+
+        // First batch fits in one send, second is split after three publishes
+        assert_eq!(write_buf[0..30], expected[0..30]);
+        assert_eq!(write_buf[30..70], expected[0..40]);
+    }
 }
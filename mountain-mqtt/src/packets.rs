@@ -4,14 +4,31 @@ pub mod connect;
 pub mod disconnect;
 pub mod packet;
 pub mod packet_generic;
+pub mod packets_iter;
 pub mod pingreq;
 pub mod pingresp;
 pub mod puback;
 pub mod pubcomp;
 pub mod publish;
+pub mod publish_template;
 pub mod pubrec;
 pub mod pubrel;
+pub mod server_packet;
+pub mod size;
 pub mod suback;
 pub mod subscribe;
 pub mod unsuback;
 pub mod unsubscribe;
+
+/// The default maximum number of properties in a packet, used when the `P`
+/// parameter of a packet type is omitted, e.g. `Publish<'a>`
+pub const PROPERTIES_DEFAULT: usize = 16;
+
+/// The default maximum number of will properties in a [connect::Connect]
+/// packet, used when the `W` parameter is omitted
+pub const WILL_PROPERTIES_DEFAULT: usize = 16;
+
+/// The default maximum number of additional subscription requests (after the
+/// mandatory first request) in subscription packets, used when the `S`
+/// parameter is omitted
+pub const SUBSCRIPTION_REQUESTS_DEFAULT: usize = 16;
@@ -8,10 +8,11 @@ use crate::{
     },
     data::reason_code::AuthReasonCode,
 };
+use crate::packets::PROPERTIES_DEFAULT;
 use heapless::Vec;
 
 #[derive(Debug, PartialEq)]
-pub struct Auth<'a, const P: usize> {
+pub struct Auth<'a, const P: usize = PROPERTIES_DEFAULT> {
     reason_code: AuthReasonCode,
     properties: Vec<AuthProperty<'a>, P>,
 }
@@ -26,6 +27,14 @@ impl<'a, const P: usize> Auth<'a, P> {
             properties,
         }
     }
+
+    pub fn reason_code(&self) -> &AuthReasonCode {
+        &self.reason_code
+    }
+
+    pub fn properties(&self) -> &Vec<AuthProperty<'a>, P> {
+        &self.properties
+    }
 }
 
 impl<const P: usize> Packet for Auth<'_, P> {
@@ -1,15 +1,20 @@
-use super::packet::{Packet, PacketRead, PacketWrite};
-use crate::codec::{
-    mqtt_reader::{self, MqttReader},
-    mqtt_writer::{self, MqttWriter},
-};
+use super::packet::Packet;
+#[cfg(feature = "client-codec")]
+use super::packet::PacketRead;
+#[cfg(feature = "server-codec")]
+use super::packet::PacketWrite;
+#[cfg(feature = "client-codec")]
+use crate::codec::mqtt_reader::{self, MqttReader};
+#[cfg(feature = "server-codec")]
+use crate::codec::mqtt_writer::{self, MqttWriter};
 use crate::data::{
     packet_type::PacketType, property::ConnackProperty, reason_code::ConnectReasonCode,
 };
+use crate::packets::PROPERTIES_DEFAULT;
 use heapless::Vec;
 
 #[derive(Debug, PartialEq)]
-pub struct Connack<'a, const P: usize> {
+pub struct Connack<'a, const P: usize = PROPERTIES_DEFAULT> {
     session_present: bool,
     reason_code: ConnectReasonCode,
     properties: Vec<ConnackProperty<'a>, P>,
@@ -45,6 +50,7 @@ impl<const P: usize> Packet for Connack<'_, P> {
     }
 }
 
+#[cfg(feature = "server-codec")]
 impl<const P: usize> PacketWrite for Connack<'_, P> {
     fn put_variable_header_and_payload<'w, W: MqttWriter<'w>>(
         &self,
@@ -56,7 +62,7 @@ impl<const P: usize> PacketWrite for Connack<'_, P> {
         // Note this byte is technically "connack_flags", but only contains one bit of data, which
         // is encoded the same way as a bool-zero-one used for other data
         writer.put_bool_zero_one(self.session_present)?; // 3.2.2.1 Connect Acknowledge Flags
-        writer.put_u8(self.reason_code as u8)?; // 3.2.2.2 Connect Reason Code
+        writer.put_u8(self.reason_code.value())?; // 3.2.2.2 Connect Reason Code
 
         // Write the properties vec (3.2.2.3)
         writer.put_variable_u32_delimited_vec(&self.properties)?;
@@ -67,6 +73,7 @@ impl<const P: usize> PacketWrite for Connack<'_, P> {
     }
 }
 
+#[cfg(feature = "client-codec")]
 impl<'a, const P: usize> PacketRead<'a> for Connack<'a, P> {
     fn get_variable_header_and_payload<R: MqttReader<'a>>(
         reader: &mut R,
@@ -92,7 +99,7 @@ impl<'a, const P: usize> PacketRead<'a> for Connack<'a, P> {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "client-codec", feature = "server-codec"))]
 mod tests {
     use crate::codec::{
         mqtt_reader::MqttBufReader, mqtt_writer::MqttBufWriter, read::Read, write::Write,
@@ -114,7 +121,7 @@ mod tests {
         0x20,
         0x06,
         0x01,
-        ConnectReasonCode::ServerMoved as u8,
+        ConnectReasonCode::ServerMoved.value(),
         0x03,
         0x21,
         0x00,
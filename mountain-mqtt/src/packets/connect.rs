@@ -1,17 +1,25 @@
-use super::packet::{
-    Packet, PacketRead, PacketWrite, KEEP_ALIVE_DEFAULT, PROTOCOL_NAME, PROTOCOL_VERSION_5,
-};
+#[cfg(feature = "server-codec")]
+use super::packet::PacketRead;
+#[cfg(feature = "client-codec")]
+use super::packet::PacketWrite;
+use super::packet::{Packet, KEEP_ALIVE_DEFAULT};
+#[cfg(any(feature = "client-codec", feature = "server-codec"))]
+use super::packet::{PROTOCOL_NAME, PROTOCOL_VERSION_5};
+use crate::client_id::{validate_connect_client_id, ClientIdError};
+#[cfg(feature = "client-codec")]
 use crate::codec::mqtt_writer::{self, MqttWriter};
 use crate::data::{
     packet_type::PacketType,
     property::{ConnectProperty, WillProperty},
     quality_of_service::QualityOfService,
 };
+#[cfg(feature = "server-codec")]
 use crate::error::PacketReadError;
+use crate::packets::{PROPERTIES_DEFAULT, WILL_PROPERTIES_DEFAULT};
 use heapless::Vec;
 
 #[derive(Debug, PartialEq)]
-pub struct Will<'a, const P: usize> {
+pub struct Will<'a, const P: usize = PROPERTIES_DEFAULT> {
     qos: QualityOfService,
     retain: bool,
     topic_name: &'a str,
@@ -35,18 +43,39 @@ impl<'a, const P: usize> Will<'a, P> {
             properties,
         }
     }
+
+    pub fn topic_name(&self) -> &'a str {
+        self.topic_name
+    }
+
+    /// Replace the topic name the will is published to
+    pub fn with_topic_name(mut self, topic_name: &'a str) -> Self {
+        self.topic_name = topic_name;
+        self
+    }
 }
 
+#[cfg(any(feature = "client-codec", feature = "server-codec"))]
 const CLEAN_START_BIT: u8 = 1 << 1;
+#[cfg(any(feature = "client-codec", feature = "server-codec"))]
 const WILL_PRESENT_BIT: u8 = 1 << 2;
+#[cfg(any(feature = "client-codec", feature = "server-codec"))]
 const WILL_QOS_SHIFT: i32 = 3;
+#[cfg(feature = "server-codec")]
 const WILL_QOS_MASK: u8 = 0x03;
+#[cfg(any(feature = "client-codec", feature = "server-codec"))]
 const WILL_RETAIN_BIT: u8 = 1 << 5;
+#[cfg(any(feature = "client-codec", feature = "server-codec"))]
 const PASSWORD_PRESENT_BIT: u8 = 1 << 6;
+#[cfg(any(feature = "client-codec", feature = "server-codec"))]
 const USERNAME_PRESENT_BIT: u8 = 1 << 7;
 
 #[derive(Debug, PartialEq)]
-pub struct Connect<'a, const P: usize, const W: usize> {
+pub struct Connect<
+    'a,
+    const P: usize = PROPERTIES_DEFAULT,
+    const W: usize = WILL_PROPERTIES_DEFAULT,
+> {
     keep_alive: u16,
     username: Option<&'a str>,
     password: Option<&'a [u8]>,
@@ -109,6 +138,7 @@ impl<'a, const P: usize, const W: usize> Connect<'a, P, W> {
         }
     }
 
+    #[cfg(feature = "client-codec")]
     fn connect_flags(&self) -> u8 {
         let mut flags = 0u8;
         // Note bit 0 is reserved, must be left as 0 (MQTT-3.1.2-2)
@@ -138,6 +168,16 @@ impl<'a, const P: usize, const W: usize> Connect<'a, P, W> {
     pub fn clean_start(&self) -> bool {
         self.clean_start
     }
+
+    pub fn client_id(&self) -> &'a str {
+        self.client_id
+    }
+
+    /// Check that the client identifier will be accepted by every server,
+    /// see [validate_connect_client_id]
+    pub fn validate_client_id(&self) -> Result<(), ClientIdError> {
+        validate_connect_client_id(self.client_id, self.clean_start)
+    }
 }
 
 impl<const P: usize, const W: usize> Packet for Connect<'_, P, W> {
@@ -146,6 +186,7 @@ impl<const P: usize, const W: usize> Packet for Connect<'_, P, W> {
     }
 }
 
+#[cfg(feature = "client-codec")]
 impl<const P: usize, const W: usize> PacketWrite for Connect<'_, P, W> {
     fn put_variable_header_and_payload<'w, Writer: MqttWriter<'w>>(
         &self,
@@ -187,6 +228,7 @@ impl<const P: usize, const W: usize> PacketWrite for Connect<'_, P, W> {
     }
 }
 
+#[cfg(feature = "server-codec")]
 impl<'a, const P: usize, const W: usize> PacketRead<'a> for Connect<'a, P, W> {
     fn get_variable_header_and_payload<R: crate::codec::mqtt_reader::MqttReader<'a>>(
         reader: &mut R,
@@ -287,7 +329,7 @@ impl<'a, const P: usize, const W: usize> PacketRead<'a> for Connect<'a, P, W> {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "client-codec", feature = "server-codec"))]
 mod tests {
     use crate::codec::{
         mqtt_reader::{MqttBufReader, MqttReader},
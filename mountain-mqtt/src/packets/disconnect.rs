@@ -4,12 +4,15 @@ use crate::codec::{
     mqtt_writer::{self, MqttWriter},
 };
 use crate::data::{
-    packet_type::PacketType, property::DisconnectProperty, reason_code::DisconnectReasonCode,
+    packet_type::PacketType,
+    property::{DisconnectProperty, SessionExpiryInterval},
+    reason_code::DisconnectReasonCode,
 };
+use crate::packets::PROPERTIES_DEFAULT;
 use heapless::Vec;
 
 #[derive(Debug, PartialEq)]
-pub struct Disconnect<'a, const P: usize> {
+pub struct Disconnect<'a, const P: usize = PROPERTIES_DEFAULT> {
     reason_code: DisconnectReasonCode,
     properties: Vec<DisconnectProperty<'a>, P>,
 }
@@ -31,6 +34,28 @@ impl<'a, const P: usize> Disconnect<'a, P> {
     pub fn properties(&self) -> &Vec<DisconnectProperty<'a>, P> {
         &self.properties
     }
+    pub fn into_properties(self) -> Vec<DisconnectProperty<'a>, P> {
+        self.properties
+    }
+}
+
+impl Disconnect<'_, 1> {
+    /// A Disconnect with a [SessionExpiryInterval] property, to change how long
+    /// the server keeps the session after the connection closes, e.g. 0 to end
+    /// the session now. This can only be non-zero if the Connect packet had a
+    /// non-zero session expiry interval [MQTT-3.14.2-2].
+    pub fn with_session_expiry_interval(
+        reason_code: DisconnectReasonCode,
+        session_expiry_interval: u32,
+    ) -> Self {
+        let mut properties = Vec::new();
+        properties
+            .push(DisconnectProperty::SessionExpiryInterval(
+                SessionExpiryInterval::new(session_expiry_interval),
+            ))
+            .unwrap();
+        Self::new(reason_code, properties)
+    }
 }
 
 impl Default for Disconnect<'_, 0> {
@@ -1,8 +1,10 @@
+#[cfg(feature = "client-codec")]
+use crate::codec::mqtt_reader::MqttBufReader;
 use crate::data::packet_type::PacketType;
 use crate::{
     codec::{
         mqtt_reader::{self, MqttReader},
-        mqtt_writer::{self, MqttLenWriter, MqttWriter},
+        mqtt_writer::{self, MqttBufWriter, MqttWriter},
         read::Read,
         write::Write,
     },
@@ -25,19 +27,27 @@ pub trait PacketWrite: Packet {
         &self,
         writer: &mut W,
     ) -> mqtt_writer::Result<()>;
+
+    /// Encode the whole packet to the start of `buf`, returning the number of
+    /// bytes written. Fails with [PacketWriteError::Overflow] if the packet
+    /// doesn't fit in `buf`.
+    ///
+    /// [PacketWriteError::Overflow]: crate::error::PacketWriteError::Overflow
+    fn encode(&self, buf: &mut [u8]) -> mqtt_writer::Result<usize>
+    where
+        Self: Sized,
+    {
+        let mut writer = MqttBufWriter::new(buf);
+        writer.put(self)?;
+        Ok(writer.position())
+    }
 }
 
 impl<P: PacketWrite> Write for P {
     fn write<'w, W: MqttWriter<'w>>(&self, writer: &mut W) -> mqtt_writer::Result<()> {
-        // Find length of variable header, and payload
-        let mut lw = MqttLenWriter::new();
-        self.put_variable_header_and_payload(&mut lw)?;
-        let remaining_length = lw.position();
-
         // fixed header including length, then variable header and payload
         writer.put_u8(self.fixed_header_first_byte())?;
-        writer.put_variable_u32(remaining_length as u32)?;
-        self.put_variable_header_and_payload(writer)?;
+        writer.put_variable_u32_delimited_packet(self)?;
 
         Ok(())
     }
@@ -84,9 +94,9 @@ impl<'a, P: PacketRead<'a>> Read<'a> for P {
             remaining_length,
         )?;
 
-        // Check that packet type is as expected, so `PacketRead` implementation
-        // doesn't have to
-        let packet_type = PacketType::try_from(first_header_byte)?;
+        // Check that packet type is as expected, and any fixed flags are valid,
+        // so `PacketRead` implementation doesn't have to
+        let packet_type = PacketType::from_first_header_byte(first_header_byte)?;
         if packet_type != packet.packet_type() {
             return Err(PacketReadError::IncorrectPacketType);
         }
@@ -99,3 +109,18 @@ impl<'a, P: PacketRead<'a>> Read<'a> for P {
         }
     }
 }
+
+/// Decode a packet (or other data) from `data`, which must contain exactly
+/// the encoded packet, failing with [PacketReadError::IncorrectPacketLength]
+/// if there is any data after the packet. Used for `TryFrom<&[u8]>`
+/// implementations on packets.
+#[cfg(feature = "client-codec")]
+pub(crate) fn decode_exact<'a, T: Read<'a>>(data: &'a [u8]) -> mqtt_reader::Result<T> {
+    let mut reader = MqttBufReader::new(data);
+    let packet = reader.get()?;
+    if reader.remaining() == 0 {
+        Ok(packet)
+    } else {
+        Err(PacketReadError::IncorrectPacketLength)
+    }
+}
@@ -1,26 +1,16 @@
+use crate::data::packet_type::PacketType;
+use crate::packets::{PROPERTIES_DEFAULT, SUBSCRIPTION_REQUESTS_DEFAULT, WILL_PROPERTIES_DEFAULT};
+#[cfg(all(feature = "client-codec", feature = "server-codec"))]
 use crate::{
-    codec::{mqtt_reader, mqtt_writer, read::Read, write::Write},
-    data::packet_type::PacketType,
+    codec::{mqtt_reader, mqtt_writer, read::Read},
     error::PacketReadError,
-    packets::packet::PacketWrite,
+    packets::packet::{decode_exact, PacketRead, PacketWrite},
 };
 
 use super::{
-    auth::Auth,
-    connack::Connack,
-    connect::Connect,
-    disconnect::Disconnect,
-    packet::{Packet, PacketRead},
-    pingreq::Pingreq,
-    pingresp::Pingresp,
-    puback::Puback,
-    pubcomp::Pubcomp,
-    publish::Publish,
-    pubrec::Pubrec,
-    pubrel::Pubrel,
-    suback::Suback,
-    subscribe::Subscribe,
-    unsuback::Unsuback,
+    auth::Auth, connack::Connack, connect::Connect, disconnect::Disconnect, packet::Packet,
+    pingreq::Pingreq, pingresp::Pingresp, puback::Puback, pubcomp::Pubcomp, publish::Publish,
+    pubrec::Pubrec, pubrel::Pubrel, suback::Suback, subscribe::Subscribe, unsuback::Unsuback,
     unsubscribe::Unsubscribe,
 };
 
@@ -31,8 +21,15 @@ use super::{
 /// `W` is the maximum number of properties in a will packet.
 /// `S` is the maximum number of _additional_ subscription requests
 /// after the mandatory request.
+/// Encoding and decoding require both the `client-codec` and `server-codec`
+/// features.
 #[derive(Debug, PartialEq)]
-pub enum PacketGeneric<'a, const P: usize, const W: usize, const S: usize> {
+pub enum PacketGeneric<
+    'a,
+    const P: usize = PROPERTIES_DEFAULT,
+    const W: usize = WILL_PROPERTIES_DEFAULT,
+    const S: usize = SUBSCRIPTION_REQUESTS_DEFAULT,
+> {
     Connect(Connect<'a, P, W>),
     Connack(Connack<'a, P>),
     Publish(Publish<'a, P>),
@@ -50,31 +47,45 @@ pub enum PacketGeneric<'a, const P: usize, const W: usize, const S: usize> {
     Auth(Auth<'a, P>),
 }
 
+/// A [PacketGeneric] with the default maximum numbers of properties and
+/// subscription requests, see [PROPERTIES_DEFAULT], [WILL_PROPERTIES_DEFAULT]
+/// and [SUBSCRIPTION_REQUESTS_DEFAULT]. This is useful in expressions, where
+/// omitted parameters are inferred rather than using their defaults.
+pub type PacketGenericDefault<'a> = PacketGeneric<'a>;
+
+#[cfg(all(feature = "client-codec", feature = "server-codec"))]
 impl<const P: usize, const W: usize, const S: usize> PacketWrite for PacketGeneric<'_, P, W, S> {
     fn put_variable_header_and_payload<'w, WRITER: mqtt_writer::MqttWriter<'w>>(
         &self,
         writer: &mut WRITER,
     ) -> mqtt_writer::Result<()> {
         match self {
-            PacketGeneric::Connect(connect) => connect.write(writer),
-            PacketGeneric::Connack(connack) => connack.write(writer),
-            PacketGeneric::Publish(publish) => publish.write(writer),
-            PacketGeneric::Puback(puback) => puback.write(writer),
-            PacketGeneric::Pubrec(pubrec) => pubrec.write(writer),
-            PacketGeneric::Pubrel(pubrel) => pubrel.write(writer),
-            PacketGeneric::Pubcomp(pubcomp) => pubcomp.write(writer),
-            PacketGeneric::Subscribe(subscribe) => subscribe.write(writer),
-            PacketGeneric::Suback(suback) => suback.write(writer),
-            PacketGeneric::Unsubscribe(unsubscribe) => unsubscribe.write(writer),
-            PacketGeneric::Unsuback(unsuback) => unsuback.write(writer),
-            PacketGeneric::Pingreq(pingreq) => pingreq.write(writer),
-            PacketGeneric::Pingresp(pingresp) => pingresp.write(writer),
-            PacketGeneric::Disconnect(disconnect) => disconnect.write(writer),
-            PacketGeneric::Auth(auth) => auth.write(writer),
+            PacketGeneric::Connect(connect) => connect.put_variable_header_and_payload(writer),
+            PacketGeneric::Connack(connack) => connack.put_variable_header_and_payload(writer),
+            PacketGeneric::Publish(publish) => publish.put_variable_header_and_payload(writer),
+            PacketGeneric::Puback(puback) => puback.put_variable_header_and_payload(writer),
+            PacketGeneric::Pubrec(pubrec) => pubrec.put_variable_header_and_payload(writer),
+            PacketGeneric::Pubrel(pubrel) => pubrel.put_variable_header_and_payload(writer),
+            PacketGeneric::Pubcomp(pubcomp) => pubcomp.put_variable_header_and_payload(writer),
+            PacketGeneric::Subscribe(subscribe) => {
+                subscribe.put_variable_header_and_payload(writer)
+            }
+            PacketGeneric::Suback(suback) => suback.put_variable_header_and_payload(writer),
+            PacketGeneric::Unsubscribe(unsubscribe) => {
+                unsubscribe.put_variable_header_and_payload(writer)
+            }
+            PacketGeneric::Unsuback(unsuback) => unsuback.put_variable_header_and_payload(writer),
+            PacketGeneric::Pingreq(pingreq) => pingreq.put_variable_header_and_payload(writer),
+            PacketGeneric::Pingresp(pingresp) => pingresp.put_variable_header_and_payload(writer),
+            PacketGeneric::Disconnect(disconnect) => {
+                disconnect.put_variable_header_and_payload(writer)
+            }
+            PacketGeneric::Auth(auth) => auth.put_variable_header_and_payload(writer),
         }
     }
 }
 
+#[cfg(all(feature = "client-codec", feature = "server-codec"))]
 impl<'a, const P: usize, const W: usize, const S: usize> Read<'a> for PacketGeneric<'a, P, W, S> {
     fn read<R: crate::codec::mqtt_reader::MqttReader<'a>>(
         reader: &mut R,
@@ -84,8 +95,8 @@ impl<'a, const P: usize, const W: usize, const S: usize> Read<'a> for PacketGene
     {
         let first_header_byte = reader.get_u8()?;
 
-        // Check that packet type is valid
-        let packet_type = PacketType::try_from(first_header_byte)?;
+        // Check that packet type and any fixed flags are valid
+        let packet_type = PacketType::from_first_header_byte(first_header_byte)?;
 
         let len = reader.get_variable_u32()? as usize;
         let packet_end_position = reader.position() + len;
@@ -177,6 +188,18 @@ impl<'a, const P: usize, const W: usize, const S: usize> Read<'a> for PacketGene
     }
 }
 
+/// Decode a packet from `data`, which must contain exactly one encoded packet
+#[cfg(all(feature = "client-codec", feature = "server-codec"))]
+impl<'a, const P: usize, const W: usize, const S: usize> TryFrom<&'a [u8]>
+    for PacketGeneric<'a, P, W, S>
+{
+    type Error = PacketReadError;
+
+    fn try_from(data: &'a [u8]) -> Result<Self, Self::Error> {
+        decode_exact(data)
+    }
+}
+
 impl<const P: usize, const W: usize, const S: usize> Packet for PacketGeneric<'_, P, W, S> {
     fn packet_type(&self) -> PacketType {
         match self {
@@ -197,4 +220,55 @@ impl<const P: usize, const W: usize, const S: usize> Packet for PacketGeneric<'_
             PacketGeneric::Auth(_) => PacketType::Auth,
         }
     }
+
+    // Use the first byte of the contained packet, which may include flags,
+    // e.g. the qos of a publish packet
+    fn fixed_header_first_byte(&self) -> u8 {
+        match self {
+            PacketGeneric::Connect(packet) => packet.fixed_header_first_byte(),
+            PacketGeneric::Connack(packet) => packet.fixed_header_first_byte(),
+            PacketGeneric::Publish(packet) => packet.fixed_header_first_byte(),
+            PacketGeneric::Puback(packet) => packet.fixed_header_first_byte(),
+            PacketGeneric::Pubrec(packet) => packet.fixed_header_first_byte(),
+            PacketGeneric::Pubrel(packet) => packet.fixed_header_first_byte(),
+            PacketGeneric::Pubcomp(packet) => packet.fixed_header_first_byte(),
+            PacketGeneric::Subscribe(packet) => packet.fixed_header_first_byte(),
+            PacketGeneric::Suback(packet) => packet.fixed_header_first_byte(),
+            PacketGeneric::Unsubscribe(packet) => packet.fixed_header_first_byte(),
+            PacketGeneric::Unsuback(packet) => packet.fixed_header_first_byte(),
+            PacketGeneric::Pingreq(packet) => packet.fixed_header_first_byte(),
+            PacketGeneric::Pingresp(packet) => packet.fixed_header_first_byte(),
+            PacketGeneric::Disconnect(packet) => packet.fixed_header_first_byte(),
+            PacketGeneric::Auth(packet) => packet.fixed_header_first_byte(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "client-codec", feature = "server-codec"))]
+mod tests {
+    use super::*;
+    use crate::data::packet_identifier::{PacketIdentifier, PublishPacketIdentifier};
+    use heapless::Vec;
+
+    #[test]
+    fn encode_and_try_from_round_trip() {
+        let packet: PacketGeneric<'_> = PacketGeneric::Publish(Publish::new(
+            false,
+            true,
+            "a",
+            PublishPacketIdentifier::Qos1(PacketIdentifier(1)),
+            b"b",
+            Vec::new(),
+        ));
+
+        let mut buf = [0; 16];
+        let len = packet.encode(&mut buf).unwrap();
+
+        // Qos 1 and retain flags are encoded in the first header byte
+        assert_eq!(
+            &buf[0..len],
+            [0x33, 0x07, 0x00, 0x01, b'a', 0x00, 0x01, 0x00, b'b']
+        );
+        assert_eq!(PacketGenericDefault::try_from(&buf[0..len]), Ok(packet));
+    }
 }
@@ -0,0 +1,184 @@
+use crate::{
+    codec::mqtt_reader::{MqttBufReader, MqttReader},
+    data::packet_type::PacketType,
+    error::PacketReadError,
+};
+
+#[cfg(all(feature = "client-codec", feature = "server-codec"))]
+use super::packet_generic::PacketGeneric;
+#[cfg(all(feature = "client-codec", feature = "server-codec"))]
+use crate::data::property::PropertyOverflow;
+
+/// Find the total encoded length of the packet at the start of `buf`, from
+/// its fixed header, or None if `buf` does not yet contain the whole fixed header
+pub fn encoded_packet_len(buf: &[u8]) -> Result<Option<usize>, PacketReadError> {
+    let Some(first) = buf.first() else {
+        return Ok(None);
+    };
+    if !PacketType::is_valid_first_header_byte(*first) {
+        return Err(PacketReadError::InvalidPacketType);
+    }
+
+    // The remaining length is encoded in up to 4 bytes after the first byte
+    for length_len in 1..=4 {
+        match buf.get(length_len) {
+            None => return Ok(None),
+            Some(byte) if byte & 128 == 0 => {
+                let remaining_length =
+                    MqttBufReader::new(&buf[1..=length_len]).get_variable_u32()?;
+                return Ok(Some(1 + length_len + remaining_length as usize));
+            }
+            Some(_) => {}
+        }
+    }
+    Err(PacketReadError::InvalidVariableByteIntegerEncoding)
+}
+
+/// Iterates the complete packets encoded back-to-back in a buffer, for
+/// example data from a transport that may deliver several packets, and the
+/// start of another, in one read. Iteration ends at the first incomplete
+/// packet, which can then be found with [PacketsIter::remainder] and kept
+/// until the rest of it is received. If a packet can't be decoded, the
+/// error is produced and iteration ends.
+/// Requires the `client-codec` and `server-codec` features.
+#[cfg(all(feature = "client-codec", feature = "server-codec"))]
+pub struct PacketsIter<'a, const P: usize, const W: usize, const S: usize> {
+    buf: &'a [u8],
+    position: usize,
+    skip_unknown_properties: bool,
+    property_overflow: PropertyOverflow,
+    dropped_properties: usize,
+    failed: bool,
+}
+
+#[cfg(all(feature = "client-codec", feature = "server-codec"))]
+impl<'a, const P: usize, const W: usize, const S: usize> PacketsIter<'a, P, W, S> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self {
+            buf,
+            position: 0,
+            skip_unknown_properties: false,
+            property_overflow: PropertyOverflow::Fail,
+            dropped_properties: 0,
+            failed: false,
+        }
+    }
+
+    /// Set whether packets are decoded skipping any properties that are not
+    /// expected for the packet type, see [MqttBufReader::with_skip_unknown_properties]
+    pub fn with_skip_unknown_properties(mut self, skip_unknown_properties: bool) -> Self {
+        self.skip_unknown_properties = skip_unknown_properties;
+        self
+    }
+
+    /// Set how packets with more properties than the packet's property capacity
+    /// are handled, see [MqttBufReader::with_property_overflow]
+    pub fn with_property_overflow(mut self, property_overflow: PropertyOverflow) -> Self {
+        self.property_overflow = property_overflow;
+        self
+    }
+
+    /// The number of properties dropped from the last packet produced, when
+    /// using [PropertyOverflow::Count]
+    pub fn dropped_properties(&self) -> usize {
+        self.dropped_properties
+    }
+
+    /// The number of bytes at the start of the buffer used by the packets
+    /// produced so far
+    pub fn consumed(&self) -> usize {
+        self.position
+    }
+
+    /// The bytes of the buffer after the packets produced so far. Once
+    /// iteration has ended without an error, this is the start of an
+    /// incomplete packet, or empty.
+    pub fn remainder(&self) -> &'a [u8] {
+        &self.buf[self.position..]
+    }
+}
+
+#[cfg(all(feature = "client-codec", feature = "server-codec"))]
+impl<'a, const P: usize, const W: usize, const S: usize> Iterator for PacketsIter<'a, P, W, S> {
+    type Item = Result<PacketGeneric<'a, P, W, S>, PacketReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let remainder = self.remainder();
+        let result = match encoded_packet_len(remainder) {
+            Ok(Some(len)) if len <= remainder.len() => {
+                let mut reader = MqttBufReader::new(&remainder[0..len])
+                    .with_skip_unknown_properties(self.skip_unknown_properties)
+                    .with_property_overflow(self.property_overflow);
+                let packet = reader.get();
+                self.dropped_properties = reader.dropped_properties();
+                packet.map(|packet| (packet, len))
+            }
+            Ok(_) => return None,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok((packet, len)) => {
+                self.position += len;
+                Some(Ok(packet))
+            }
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "client-codec", feature = "server-codec"))]
+mod tests {
+    use super::*;
+    use crate::packets::{pingreq::Pingreq, pingresp::Pingresp};
+
+    // Pingreq, Pingresp, then the first byte of a Pingreq
+    const ENCODED: [u8; 5] = [0xC0, 0x00, 0xD0, 0x00, 0xC0];
+
+    #[test]
+    fn complete_packets_are_produced_leaving_partial_packet() {
+        let mut packets = PacketsIter::<'_, 0, 0, 0>::new(&ENCODED);
+        assert_eq!(
+            packets.next(),
+            Some(Ok(PacketGeneric::Pingreq(Pingreq::default())))
+        );
+        assert_eq!(
+            packets.next(),
+            Some(Ok(PacketGeneric::Pingresp(Pingresp::default())))
+        );
+        assert_eq!(packets.next(), None);
+        assert_eq!(packets.consumed(), 4);
+        assert_eq!(packets.remainder(), &[0xC0]);
+    }
+
+    #[test]
+    fn iteration_ends_after_error() {
+        let encoded = [0xC0, 0x00, 0xC1, 0x00, 0xC0, 0x00];
+        let mut packets = PacketsIter::<'_, 0, 0, 0>::new(&encoded);
+        assert!(matches!(packets.next(), Some(Ok(_))));
+        assert_eq!(
+            packets.next(),
+            Some(Err(PacketReadError::InvalidPacketType))
+        );
+        assert_eq!(packets.next(), None);
+        assert_eq!(packets.consumed(), 2);
+    }
+
+    #[test]
+    fn encoded_packet_len_needs_whole_fixed_header() {
+        assert_eq!(encoded_packet_len(&[]), Ok(None));
+        assert_eq!(encoded_packet_len(&[0x30, 0x80]), Ok(None));
+        assert_eq!(encoded_packet_len(&[0x30, 0x80, 0x01]), Ok(Some(131)));
+        assert_eq!(
+            encoded_packet_len(&[0x30, 0x80, 0x80, 0x80, 0x80]),
+            Err(PacketReadError::InvalidVariableByteIntegerEncoding)
+        );
+    }
+}
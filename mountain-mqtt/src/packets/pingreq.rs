@@ -1,10 +1,14 @@
-use super::packet::{Packet, PacketRead, PacketWrite};
+use super::packet::Packet;
+#[cfg(feature = "server-codec")]
+use super::packet::PacketRead;
+#[cfg(feature = "client-codec")]
+use super::packet::PacketWrite;
+#[cfg(feature = "client-codec")]
+use crate::codec::mqtt_writer::{self, MqttWriter};
 use crate::data::packet_type::PacketType;
+#[cfg(feature = "server-codec")]
 use crate::{
-    codec::{
-        mqtt_reader::{self, MqttReader},
-        mqtt_writer::{self, MqttWriter},
-    },
+    codec::mqtt_reader::{self, MqttReader},
     error::PacketReadError,
 };
 
@@ -29,6 +33,7 @@ impl Packet for Pingreq {
     }
 }
 
+#[cfg(feature = "client-codec")]
 impl PacketWrite for Pingreq {
     fn put_variable_header_and_payload<'w, W: MqttWriter<'w>>(
         &self,
@@ -39,6 +44,7 @@ impl PacketWrite for Pingreq {
     }
 }
 
+#[cfg(feature = "server-codec")]
 impl<'a> PacketRead<'a> for Pingreq {
     fn get_variable_header_and_payload<R: MqttReader<'a>>(
         _reader: &mut R,
@@ -57,7 +63,7 @@ impl<'a> PacketRead<'a> for Pingreq {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "client-codec", feature = "server-codec"))]
 mod tests {
     use crate::codec::{
         mqtt_reader::MqttBufReader, mqtt_writer::MqttBufWriter, read::Read, write::Write,
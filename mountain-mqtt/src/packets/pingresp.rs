@@ -1,10 +1,14 @@
-use super::packet::{Packet, PacketRead, PacketWrite};
+use super::packet::Packet;
+#[cfg(feature = "client-codec")]
+use super::packet::PacketRead;
+#[cfg(feature = "server-codec")]
+use super::packet::PacketWrite;
+#[cfg(feature = "server-codec")]
+use crate::codec::mqtt_writer::{self, MqttWriter};
 use crate::data::packet_type::PacketType;
+#[cfg(feature = "client-codec")]
 use crate::{
-    codec::{
-        mqtt_reader::{self, MqttReader},
-        mqtt_writer::{self, MqttWriter},
-    },
+    codec::mqtt_reader::{self, MqttReader},
     error::PacketReadError,
 };
 
@@ -29,6 +33,7 @@ impl Packet for Pingresp {
     }
 }
 
+#[cfg(feature = "server-codec")]
 impl PacketWrite for Pingresp {
     fn put_variable_header_and_payload<'w, W: MqttWriter<'w>>(
         &self,
@@ -39,6 +44,7 @@ impl PacketWrite for Pingresp {
     }
 }
 
+#[cfg(feature = "client-codec")]
 impl<'a> PacketRead<'a> for Pingresp {
     fn get_variable_header_and_payload<R: MqttReader<'a>>(
         _reader: &mut R,
@@ -57,7 +63,7 @@ impl<'a> PacketRead<'a> for Pingresp {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "client-codec", feature = "server-codec"))]
 mod tests {
     use crate::codec::{
         mqtt_reader::MqttBufReader, mqtt_writer::MqttBufWriter, read::Read, write::Write,
@@ -7,10 +7,11 @@ use crate::data::{
     packet_identifier::PacketIdentifier, packet_type::PacketType, property::PubackProperty,
     reason_code::PublishReasonCode,
 };
+use crate::packets::PROPERTIES_DEFAULT;
 use heapless::Vec;
 
 #[derive(Debug, PartialEq)]
-pub struct Puback<'a, const P: usize> {
+pub struct Puback<'a, const P: usize = PROPERTIES_DEFAULT> {
     packet_identifier: PacketIdentifier,
     reason_code: PublishReasonCode,
     properties: Vec<PubackProperty<'a>, P>,
@@ -7,10 +7,11 @@ use crate::data::{
     packet_identifier::PacketIdentifier, packet_type::PacketType, property::PubcompProperty,
     reason_code::PubrelReasonCode,
 };
+use crate::packets::PROPERTIES_DEFAULT;
 use heapless::Vec;
 
 #[derive(Debug, PartialEq)]
-pub struct Pubcomp<'a, const P: usize> {
+pub struct Pubcomp<'a, const P: usize = PROPERTIES_DEFAULT> {
     packet_identifier: PacketIdentifier,
     // Note that the pubcomp reason codes match the pubrel ones, so we
     // reuse them, to match general approach of naming reason codes after
@@ -1,19 +1,30 @@
 use super::packet::{Packet, PacketRead, PacketWrite};
+use crate::data::VARIABLE_BYTE_INTEGER_MAX_VALUE;
 use crate::data::{
+    encoded_topic::EncodedTopic,
     packet_identifier::{PacketIdentifier, PublishPacketIdentifier},
     packet_type::PacketType,
-    property::PublishProperty,
+    property::{
+        ContentType, CorrelationData, MessageExpiryInterval, PayloadFormatIndicator, PropertiesExt,
+        PropertiesIter, Property, PublishProperty, ResponseTopic, SubscriptionIdentifier,
+        UserProperty,
+    },
+    string_pair::StringPair,
 };
-use crate::error::PacketReadError;
+use crate::error::{PacketReadError, PacketWriteError};
+use crate::packets::PROPERTIES_DEFAULT;
 use crate::{
     codec::{
         mqtt_reader::{self, MqttReader},
-        mqtt_writer::{self, MqttWriter},
+        mqtt_writer::{self, MqttLenWriter, MqttWriter},
     },
     data::quality_of_service::QualityOfService,
 };
 use heapless::Vec;
 
+#[cfg(feature = "alloc")]
+use alloc::{string::String, vec::Vec as AllocVec};
+
 const RETAIN_SHIFT: i32 = 0;
 const QOS_SHIFT: i32 = 1;
 const QOS_MASK: u8 = 0x03;
@@ -27,12 +38,106 @@ pub fn is_valid_publish_first_header_byte(encoded: u8) -> bool {
 
 /// Contains the parts of a [Publish] packet relevant to the application.
 #[derive(Debug, PartialEq)]
-pub struct ApplicationMessage<'a, const P: usize> {
+pub struct ApplicationMessage<'a, const P: usize = PROPERTIES_DEFAULT> {
     pub topic_name: &'a str,
     pub payload: &'a [u8],
     pub qos: QualityOfService,
+    /// See [ApplicationMessage::retain]
     pub retain: bool,
     pub properties: Vec<PublishProperty<'a>, P>,
+    /// The encoded property list, if the message was received, otherwise empty.
+    /// See [ApplicationMessage::properties_iter]
+    pub encoded_properties: &'a [u8],
+}
+
+/// True if the properties contain a [PayloadFormatIndicator] for UTF-8 data
+fn is_utf8_indicated<const P: usize>(properties: &Vec<PublishProperty<'_>, P>) -> bool {
+    properties
+        .get_property::<PayloadFormatIndicator>()
+        .is_some_and(|p| p.value() == PayloadFormatIndicator::UTF8)
+}
+
+/// False if the payload is indicated as UTF-8, but is not valid UTF-8
+fn is_payload_format_valid<const P: usize>(
+    payload: &[u8],
+    properties: &Vec<PublishProperty<'_>, P>,
+) -> bool {
+    !is_utf8_indicated(properties) || core::str::from_utf8(payload).is_ok()
+}
+
+impl<'a, const P: usize> ApplicationMessage<'a, P> {
+    /// Lazily decode all properties of a received message, including any that
+    /// were not stored in [ApplicationMessage::properties] (e.g. if `P` is 0).
+    /// Empty for a message that was not received.
+    /// If the message was decoded while skipping unknown properties, use
+    /// [PropertiesIter::with_skip_unknown] to skip them here as well.
+    pub fn properties_iter(&self) -> PropertiesIter<'a, PublishProperty<'a>> {
+        PropertiesIter::new(self.encoded_properties)
+    }
+
+    /// True if the message has a [PayloadFormatIndicator] showing the
+    /// payload is UTF-8 encoded character data
+    pub fn payload_is_utf8(&self) -> bool {
+        is_utf8_indicated(&self.properties)
+    }
+
+    /// False if the message has a [PayloadFormatIndicator] showing the
+    /// payload is UTF-8, but the payload is not valid UTF-8
+    pub fn payload_format_is_valid(&self) -> bool {
+        is_payload_format_valid(self.payload, &self.properties)
+    }
+
+    /// The content type of the payload, if a [PublishProperty::ContentType] is present
+    pub fn content_type(&self) -> Option<&'a str> {
+        self.properties
+            .get_property::<ContentType>()
+            .map(|p| p.value())
+    }
+
+    /// The topic for a response to this message, if a [PublishProperty::ResponseTopic] is present
+    pub fn response_topic(&self) -> Option<&'a str> {
+        self.properties
+            .get_property::<ResponseTopic>()
+            .map(|p| p.value())
+    }
+
+    /// The correlation data for a request/response, if a [PublishProperty::CorrelationData] is present
+    pub fn correlation_data(&self) -> Option<&'a [u8]> {
+        self.properties
+            .get_property::<CorrelationData>()
+            .map(|p| p.value())
+    }
+
+    /// The message expiry interval in seconds, if a [PublishProperty::MessageExpiryInterval]
+    /// is present
+    pub fn message_expiry(&self) -> Option<u32> {
+        self.properties
+            .get_property::<MessageExpiryInterval>()
+            .map(|p| p.value())
+    }
+
+    /// The user properties of the message, in the order they were received
+    pub fn user_properties(&self) -> impl Iterator<Item = StringPair<'a>> + '_ {
+        self.properties
+            .get_properties::<UserProperty>()
+            .map(|p| p.value())
+    }
+
+    /// The identifiers of the subscriptions matched by the message, if the
+    /// subscriptions used a [PublishProperty::SubscriptionIdentifier]
+    pub fn subscription_identifiers(&self) -> impl Iterator<Item = u32> + '_ {
+        self.properties
+            .get_properties::<SubscriptionIdentifier>()
+            .map(|p| p.value())
+    }
+
+    /// True if the message has the retain flag set. Unless the subscription
+    /// used "retain as published", the server only sets this for messages
+    /// sent because they were retained (e.g. when subscribing), so this
+    /// distinguishes retained state from live messages.
+    pub fn retain(&self) -> bool {
+        self.retain
+    }
 }
 
 #[cfg(feature = "defmt")]
@@ -58,18 +163,123 @@ impl<'a, const P: usize> From<Publish<'a, P>> for ApplicationMessage<'a, P> {
             qos: p.qos(),
             payload: p.payload,
             properties: p.properties,
+            encoded_properties: p.encoded_properties,
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub struct Publish<'a, const P: usize> {
+/// An owned copy of an [ApplicationMessage], see [ApplicationMessage::into_owned].
+/// This doesn't borrow the receive buffer, so can be sent over channels or
+/// stored.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnedApplicationMessage {
+    pub topic_name: String,
+    pub payload: AllocVec<u8>,
+    pub qos: QualityOfService,
+    /// See [ApplicationMessage::retain]
+    pub retain: bool,
+    /// The encoded property list, if the message was received, otherwise empty.
+    /// See [OwnedApplicationMessage::properties_iter]
+    pub encoded_properties: AllocVec<u8>,
+}
+
+#[cfg(feature = "alloc")]
+impl OwnedApplicationMessage {
+    /// Lazily decode all properties of the message, as for
+    /// [ApplicationMessage::properties_iter]
+    pub fn properties_iter(&self) -> PropertiesIter<'_, PublishProperty<'_>> {
+        PropertiesIter::new(&self.encoded_properties)
+    }
+
+    /// Borrow the message as an [ApplicationMessage], decoding up to `P`
+    /// properties. This fails with [PacketReadError::TooManyProperties] if the
+    /// message has more than `P` properties (unless `P` is 0).
+    pub fn as_message<const P: usize>(&self) -> Result<ApplicationMessage<'_, P>, PacketReadError> {
+        // The properties were checked when the message was received, so any
+        // unknown properties left here were skipped then, and are skipped again
+        let mut properties = Vec::new();
+        self.properties_iter()
+            .with_skip_unknown(true)
+            .collect_into(&mut properties)?;
+        Ok(ApplicationMessage {
+            topic_name: &self.topic_name,
+            payload: &self.payload,
+            qos: self.qos,
+            retain: self.retain,
+            properties,
+            encoded_properties: &self.encoded_properties,
+        })
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<const P: usize> ApplicationMessage<'_, P> {
+    /// Copy the message into an [OwnedApplicationMessage], which doesn't
+    /// borrow the receive buffer. Properties are kept in their encoded form,
+    /// so as for [ApplicationMessage::properties_iter] they are only kept for
+    /// a received message.
+    pub fn into_owned(self) -> OwnedApplicationMessage {
+        OwnedApplicationMessage {
+            topic_name: self.topic_name.into(),
+            payload: self.payload.into(),
+            qos: self.qos,
+            retain: self.retain,
+            encoded_properties: self.encoded_properties.into(),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl From<OwnedPublish> for OwnedApplicationMessage {
+    fn from(p: OwnedPublish) -> Self {
+        OwnedApplicationMessage {
+            qos: p.qos(),
+            topic_name: p.topic_name,
+            payload: p.payload,
+            retain: p.retain,
+            encoded_properties: p.encoded_properties,
+        }
+    }
+}
+
+#[cfg(all(feature = "alloc", feature = "defmt"))]
+impl defmt::Format for OwnedApplicationMessage {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "OwnedApplicationMessage({},{},{},{})",
+            self.topic_name.as_str(),
+            self.payload.as_slice(),
+            self.qos,
+            self.retain,
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct Publish<'a, const P: usize = PROPERTIES_DEFAULT> {
     duplicate: bool,
     retain: bool,
     topic_name: &'a str,
     publish_packet_identifier: PublishPacketIdentifier,
     payload: &'a [u8],
     properties: Vec<PublishProperty<'a>, P>,
+    encoded_properties: &'a [u8],
+    encoded_topic_name: Option<&'a [u8]>,
+}
+
+// Encoded properties and topic name are not compared, since they just provide
+// a different view of the same properties and topic name
+impl<const P: usize> PartialEq for Publish<'_, P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.duplicate == other.duplicate
+            && self.retain == other.retain
+            && self.topic_name == other.topic_name
+            && self.publish_packet_identifier == other.publish_packet_identifier
+            && self.payload == other.payload
+            && self.properties == other.properties
+    }
 }
 
 impl<'a, const P: usize> Publish<'a, P> {
@@ -88,9 +298,20 @@ impl<'a, const P: usize> Publish<'a, P> {
             publish_packet_identifier: packet_identifier,
             payload,
             properties,
+            encoded_properties: &[],
+            encoded_topic_name: None,
         }
     }
 
+    /// Use an [EncodedTopic] as the topic name, replacing the current topic name.
+    /// The topic name is then put when encoding the packet without being
+    /// checked or encoded again.
+    pub fn with_encoded_topic<const N: usize>(mut self, topic: &'a EncodedTopic<N>) -> Self {
+        self.topic_name = topic.topic_name();
+        self.encoded_topic_name = Some(topic.encoded());
+        self
+    }
+
     pub fn duplicate(&self) -> bool {
         self.duplicate
     }
@@ -118,6 +339,27 @@ impl<'a, const P: usize> Publish<'a, P> {
     pub fn properties(&self) -> &Vec<PublishProperty<'a>, P> {
         &self.properties
     }
+
+    /// Lazily decode all properties of a received packet, including any that
+    /// were not stored in [Publish::properties] (e.g. if `P` is 0).
+    /// Empty for a packet that was not received.
+    /// If the packet was decoded while skipping unknown properties, use
+    /// [PropertiesIter::with_skip_unknown] to skip them here as well.
+    pub fn properties_iter(&self) -> PropertiesIter<'a, PublishProperty<'a>> {
+        PropertiesIter::new(self.encoded_properties)
+    }
+
+    /// True if the packet has a [PayloadFormatIndicator] showing the
+    /// payload is UTF-8 encoded character data
+    pub fn payload_is_utf8(&self) -> bool {
+        is_utf8_indicated(&self.properties)
+    }
+
+    /// False if the packet has a [PayloadFormatIndicator] showing the
+    /// payload is UTF-8, but the payload is not valid UTF-8
+    pub fn payload_format_is_valid(&self) -> bool {
+        is_payload_format_valid(self.payload, &self.properties)
+    }
 }
 
 impl<const P: usize> Packet for Publish<'_, P> {
@@ -138,15 +380,24 @@ impl<const P: usize> Packet for Publish<'_, P> {
     }
 }
 
-impl<const P: usize> PacketWrite for Publish<'_, P> {
-    fn put_variable_header_and_payload<'w, W: MqttWriter<'w>>(
+impl<const P: usize> Publish<'_, P> {
+    fn put_variable_header<'w, W: MqttWriter<'w>>(
         &self,
         writer: &mut W,
     ) -> mqtt_writer::Result<()> {
-        // Variable header:
+        match self.encoded_topic_name {
+            Some(encoded) => writer.put_slice(encoded)?,
+            None => writer.put_str(self.topic_name)?,
+        }
+        self.put_variable_header_after_topic_name(writer)
+    }
 
-        // Write the fixed parts of the variable header
-        writer.put_str(self.topic_name)?;
+    /// Put the parts of the variable header after the topic name, i.e. the
+    /// packet identifier if any, and the properties
+    pub(crate) fn put_variable_header_after_topic_name<'w, W: MqttWriter<'w>>(
+        &self,
+        writer: &mut W,
+    ) -> mqtt_writer::Result<()> {
         match &self.publish_packet_identifier {
             PublishPacketIdentifier::None => {} // Qos0, no packet identifier
             PublishPacketIdentifier::Qos1(id) => writer.put_u16(id.0)?,
@@ -154,7 +405,17 @@ impl<const P: usize> PacketWrite for Publish<'_, P> {
         }
 
         // Write the properties vec (3.3.2.3)
-        writer.put_variable_u32_delimited_vec(&self.properties)?;
+        writer.put_variable_u32_delimited_vec(&self.properties)
+    }
+}
+
+impl<const P: usize> PacketWrite for Publish<'_, P> {
+    fn put_variable_header_and_payload<'w, W: MqttWriter<'w>>(
+        &self,
+        writer: &mut W,
+    ) -> mqtt_writer::Result<()> {
+        // Variable header:
+        self.put_variable_header(writer)?;
 
         // Payload - note that we put a raw slice rather than use `put_binary_data`, since
         // the payload has no delimiting length, the length is what's left of the packet
@@ -165,6 +426,176 @@ impl<const P: usize> PacketWrite for Publish<'_, P> {
     }
 }
 
+/// Provides the payload of a [StreamingPublish] packet, in chunks.
+/// This allows publishing a payload that is too large to fit in memory,
+/// e.g. data read from flash, by writing it a chunk at a time, see
+/// [crate::packet_client::PacketClient::send_streaming_publish]
+pub trait PayloadSource {
+    /// The total length of the payload in bytes
+    fn payload_len(&self) -> usize;
+
+    /// Put the next chunk of the payload to `writer`, starting `offset` bytes into the
+    /// payload, and returning the number of bytes put. This must put at least 1 and
+    /// at most `max_len` bytes, and must not put data beyond the end of the payload.
+    fn write_chunk<'w, W: MqttWriter<'w>>(
+        &self,
+        offset: usize,
+        max_len: usize,
+        writer: &mut W,
+    ) -> mqtt_writer::Result<usize>;
+}
+
+impl PayloadSource for &[u8] {
+    fn payload_len(&self) -> usize {
+        self.len()
+    }
+
+    fn write_chunk<'w, W: MqttWriter<'w>>(
+        &self,
+        offset: usize,
+        max_len: usize,
+        writer: &mut W,
+    ) -> mqtt_writer::Result<usize> {
+        let end = self.len().min(offset.saturating_add(max_len));
+        let chunk = self.get(offset..end).unwrap_or(&[]);
+        writer.put_slice(chunk)?;
+        Ok(chunk.len())
+    }
+}
+
+/// Accepts the payload of a received publish packet in chunks, allowing
+/// payloads too large for the receive buffer to be processed, e.g. written
+/// to flash, see [crate::packet_client::PacketClient::receive_streaming].
+/// This is implemented for closures accepting `(offset, chunk)`.
+pub trait PayloadSink {
+    /// Accept the next chunk of the payload, starting `offset` bytes into the
+    /// payload. Chunks are provided in order. Errors should normally be
+    /// [PacketReadError::PayloadSink].
+    fn write_chunk(&mut self, offset: usize, chunk: &[u8]) -> Result<(), PacketReadError>;
+}
+
+impl<F> PayloadSink for F
+where
+    F: FnMut(usize, &[u8]) -> Result<(), PacketReadError>,
+{
+    fn write_chunk(&mut self, offset: usize, chunk: &[u8]) -> Result<(), PacketReadError> {
+        self(offset, chunk)
+    }
+}
+
+/// A publish packet with a payload provided by a [PayloadSource]. The other
+/// contents of the packet are from a [Publish], see [Publish::with_payload_source]
+#[derive(Debug, PartialEq)]
+pub struct StreamingPublish<'a, L: PayloadSource, const P: usize> {
+    header: Publish<'a, P>,
+    payload: L,
+}
+
+impl<'a, const P: usize> Publish<'a, P> {
+    /// Use the contents of this packet, other than the payload, to make a
+    /// [StreamingPublish] with the payload provided by `payload`
+    pub fn with_payload_source<L: PayloadSource>(self, payload: L) -> StreamingPublish<'a, L, P> {
+        StreamingPublish {
+            header: self,
+            payload,
+        }
+    }
+}
+
+impl<'a, L: PayloadSource, const P: usize> StreamingPublish<'a, L, P> {
+    /// The contents of the packet other than the payload. The payload of
+    /// this [Publish] is not used
+    pub fn header(&self) -> &Publish<'a, P> {
+        &self.header
+    }
+
+    pub fn payload(&self) -> &L {
+        &self.payload
+    }
+
+    /// Put the fixed header and variable header of the packet, leaving
+    /// the payload to be written, e.g. with [StreamingPublish::put_payload_chunk]
+    pub fn put_headers<'w, W: MqttWriter<'w>>(&self, writer: &mut W) -> mqtt_writer::Result<()> {
+        let mut lw = MqttLenWriter::new();
+        self.header.put_variable_header(&mut lw)?;
+        let remaining_length = lw.position() + self.payload.payload_len();
+        if remaining_length > VARIABLE_BYTE_INTEGER_MAX_VALUE as usize {
+            return Err(PacketWriteError::VariableByteIntegerTooLarge);
+        }
+
+        writer.put_u8(self.fixed_header_first_byte())?;
+        writer.put_variable_u32(remaining_length as u32)?;
+        self.header.put_variable_header(writer)
+    }
+
+    /// Put the next chunk of the payload, starting at `offset`, checking that the
+    /// [PayloadSource] provides valid chunks, see [PayloadSource::write_chunk].
+    /// Returns the number of bytes put, or [PacketWriteError::IncorrectPayloadLength]
+    /// if the payload source makes no progress, overruns, or returns a length
+    /// different to the number of bytes it put.
+    pub fn put_payload_chunk<'w, W: MqttWriter<'w>>(
+        &self,
+        offset: usize,
+        max_len: usize,
+        writer: &mut W,
+    ) -> mqtt_writer::Result<usize> {
+        let max_len = max_len.min(self.payload.payload_len().saturating_sub(offset));
+        let mut counter = CountingWriter {
+            inner: writer,
+            count: 0,
+        };
+        let reported = self.payload.write_chunk(offset, max_len, &mut counter)?;
+        let written = counter.count;
+        if written == 0 || written > max_len || reported != written {
+            Err(PacketWriteError::IncorrectPayloadLength)
+        } else {
+            Ok(written)
+        }
+    }
+}
+
+/// Counts the bytes put to a writer, so that chunks from a [PayloadSource]
+/// are measured by what was actually put, rather than the length returned
+struct CountingWriter<'c, W> {
+    inner: &'c mut W,
+    count: usize,
+}
+
+impl<'w, W: MqttWriter<'w>> MqttWriter<'w> for CountingWriter<'_, W> {
+    fn put_slice(&mut self, slice: &[u8]) -> mqtt_writer::Result<()> {
+        self.inner.put_slice(slice)?;
+        self.count += slice.len();
+        Ok(())
+    }
+}
+
+impl<L: PayloadSource, const P: usize> Packet for StreamingPublish<'_, L, P> {
+    fn packet_type(&self) -> PacketType {
+        PacketType::Publish
+    }
+
+    fn fixed_header_first_byte(&self) -> u8 {
+        self.header.fixed_header_first_byte()
+    }
+}
+
+impl<L: PayloadSource, const P: usize> PacketWrite for StreamingPublish<'_, L, P> {
+    fn put_variable_header_and_payload<'w, W: MqttWriter<'w>>(
+        &self,
+        writer: &mut W,
+    ) -> mqtt_writer::Result<()> {
+        self.header.put_variable_header(writer)?;
+
+        let payload_len = self.payload.payload_len();
+        let mut offset = 0;
+        while offset < payload_len {
+            offset += self.put_payload_chunk(offset, payload_len - offset, writer)?;
+        }
+
+        Ok(())
+    }
+}
+
 impl<'a, const P: usize> PacketRead<'a> for Publish<'a, P> {
     fn get_variable_header_and_payload<R: MqttReader<'a>>(
         reader: &mut R,
@@ -191,8 +622,10 @@ impl<'a, const P: usize> PacketRead<'a> for Publish<'a, P> {
             _ => return Err(PacketReadError::InvalidQosValue),
         };
 
+        let properties_iter = reader.get_properties_iter()?;
+        let encoded_properties = properties_iter.encoded();
         let mut properties = Vec::new();
-        reader.get_property_list(&mut properties)?;
+        properties_iter.collect_into(&mut properties)?;
 
         // We expect there to be 0 or more bytes left in data,
         // if so this is all the payload, if not we have a malformed packet
@@ -204,7 +637,7 @@ impl<'a, const P: usize> PacketRead<'a> for Publish<'a, P> {
             let payload_len = payload_end_position - position;
             let payload = reader.get_slice(payload_len)?;
 
-            let packet: Publish<'a, P> = Publish::new(
+            let mut packet: Publish<'a, P> = Publish::new(
                 duplicate,
                 retain,
                 topic_name,
@@ -212,12 +645,90 @@ impl<'a, const P: usize> PacketRead<'a> for Publish<'a, P> {
                 payload,
                 properties,
             );
+            packet.encoded_properties = encoded_properties;
 
             Ok(packet)
         }
     }
 }
 
+/// An owned copy of a [Publish] packet, see [Publish::into_owned]
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnedPublish {
+    duplicate: bool,
+    retain: bool,
+    topic_name: String,
+    publish_packet_identifier: PublishPacketIdentifier,
+    payload: AllocVec<u8>,
+    encoded_properties: AllocVec<u8>,
+}
+
+#[cfg(feature = "alloc")]
+impl OwnedPublish {
+    pub fn duplicate(&self) -> bool {
+        self.duplicate
+    }
+    pub fn retain(&self) -> bool {
+        self.retain
+    }
+    pub fn topic_name(&self) -> &str {
+        &self.topic_name
+    }
+    pub fn publish_packet_identifier(&self) -> &PublishPacketIdentifier {
+        &self.publish_packet_identifier
+    }
+    pub fn qos(&self) -> QualityOfService {
+        self.publish_packet_identifier.qos()
+    }
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Lazily decode all properties of the packet, as for [Publish::properties_iter]
+    pub fn properties_iter(&self) -> PropertiesIter<'_, PublishProperty<'_>> {
+        PropertiesIter::new(&self.encoded_properties)
+    }
+
+    /// Borrow the packet as a [Publish], decoding up to `P` properties.
+    /// This fails with [PacketReadError::TooManyProperties] if the packet
+    /// has more than `P` properties (unless `P` is 0).
+    pub fn as_publish<const P: usize>(&self) -> Result<Publish<'_, P>, PacketReadError> {
+        // See OwnedApplicationMessage::as_message
+        let mut properties = Vec::new();
+        self.properties_iter()
+            .with_skip_unknown(true)
+            .collect_into(&mut properties)?;
+        let mut packet = Publish::new(
+            self.duplicate,
+            self.retain,
+            &self.topic_name,
+            self.publish_packet_identifier,
+            &self.payload,
+            properties,
+        );
+        packet.encoded_properties = &self.encoded_properties;
+        Ok(packet)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<const P: usize> Publish<'_, P> {
+    /// Copy the packet into an [OwnedPublish], which doesn't borrow the
+    /// receive buffer. Properties are kept in their encoded form, so as for
+    /// [Publish::properties_iter] they are only kept for a received packet.
+    pub fn into_owned(self) -> OwnedPublish {
+        OwnedPublish {
+            duplicate: self.duplicate,
+            retain: self.retain,
+            topic_name: self.topic_name.into(),
+            publish_packet_identifier: self.publish_packet_identifier,
+            payload: self.payload.into(),
+            encoded_properties: self.encoded_properties.into(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::codec::{
@@ -299,6 +810,30 @@ mod tests {
         encode_example(true, true, &EXAMPLE_DATA_RETAIN_DUPLICATE);
     }
 
+    #[test]
+    fn encode_example_with_encoded_topic() {
+        // Topic name is replaced by encoded topic
+        let topic = EncodedTopic::<8>::new("test").unwrap();
+        let packet = Publish::new(
+            false,
+            false,
+            "other",
+            PublishPacketIdentifier::Qos1(PacketIdentifier(23432)),
+            &EXAMPLE_PAYLOAD,
+            example_packet(false, false).properties,
+        )
+        .with_encoded_topic(&topic);
+        assert_eq!(packet, example_packet(false, false));
+
+        let mut buf = [0; EXAMPLE_LEN];
+        let len = {
+            let mut r = MqttBufWriter::new(&mut buf);
+            packet.write(&mut r).unwrap();
+            r.position()
+        };
+        assert_eq!(&buf[0..len], &EXAMPLE_DATA);
+    }
+
     #[test]
     fn decode_example() {
         let mut r = MqttBufReader::new(&EXAMPLE_DATA);
@@ -314,10 +849,280 @@ mod tests {
         assert_eq!(Publish::read(&mut r).unwrap(), example_packet(true, true));
     }
 
+    /// Provides a payload of incrementing bytes, a few bytes at a time
+    struct CountingPayload(usize);
+
+    impl PayloadSource for CountingPayload {
+        fn payload_len(&self) -> usize {
+            self.0
+        }
+
+        fn write_chunk<'w, W: MqttWriter<'w>>(
+            &self,
+            offset: usize,
+            max_len: usize,
+            writer: &mut W,
+        ) -> mqtt_writer::Result<usize> {
+            let len = max_len.min(3);
+            for i in offset..offset + len {
+                writer.put_u8(i as u8)?;
+            }
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn streaming_publish_encodes_as_publish() {
+        let payload: [u8; 200] = core::array::from_fn(|i| i as u8);
+        let mut buf = [0; 512];
+        let len = {
+            let mut r = MqttBufWriter::new(&mut buf);
+            example_packet(false, true)
+                .with_payload_source(CountingPayload(200))
+                .write(&mut r)
+                .unwrap();
+            r.position()
+        };
+
+        let mut r = MqttBufReader::new(&buf[0..len]);
+        let packet: Publish<'_, 2> = Publish::read(&mut r).unwrap();
+        assert_eq!(packet.payload(), &payload);
+        assert_eq!(
+            packet.properties(),
+            example_packet(false, true).properties()
+        );
+        assert!(packet.retain());
+    }
+
+    #[test]
+    fn streaming_publish_errors_on_payload_source_making_no_progress() {
+        struct StalledPayload;
+        impl PayloadSource for StalledPayload {
+            fn payload_len(&self) -> usize {
+                10
+            }
+
+            fn write_chunk<'w, W: MqttWriter<'w>>(
+                &self,
+                _offset: usize,
+                _max_len: usize,
+                _writer: &mut W,
+            ) -> mqtt_writer::Result<usize> {
+                Ok(0)
+            }
+        }
+
+        let mut buf = [0; 512];
+        let mut r = MqttBufWriter::new(&mut buf);
+        assert_eq!(
+            example_packet(false, false)
+                .with_payload_source(StalledPayload)
+                .write(&mut r),
+            Err(PacketWriteError::IncorrectPayloadLength)
+        );
+    }
+
+    #[test]
+    fn streaming_publish_errors_on_payload_source_misreporting_length() {
+        /// Puts `put` bytes for each chunk, but reports `reported` bytes
+        struct MisreportingPayload {
+            put: usize,
+            reported: usize,
+        }
+        impl PayloadSource for MisreportingPayload {
+            fn payload_len(&self) -> usize {
+                10
+            }
+
+            fn write_chunk<'w, W: MqttWriter<'w>>(
+                &self,
+                _offset: usize,
+                _max_len: usize,
+                writer: &mut W,
+            ) -> mqtt_writer::Result<usize> {
+                for _ in 0..self.put {
+                    writer.put_u8(0)?;
+                }
+                Ok(self.reported)
+            }
+        }
+
+        for (put, reported) in [(3, 1), (0, 2)] {
+            let mut buf = [0; 512];
+            let mut r = MqttBufWriter::new(&mut buf);
+            assert_eq!(
+                example_packet(false, false)
+                    .with_payload_source(MisreportingPayload { put, reported })
+                    .write(&mut r),
+                Err(PacketWriteError::IncorrectPayloadLength)
+            );
+        }
+    }
+
+    #[test]
+    fn decode_without_property_capacity_and_iterate_properties() {
+        let mut r = MqttBufReader::new(&EXAMPLE_DATA);
+        let packet: Publish<'_, 0> = Publish::read(&mut r).unwrap();
+        assert!(packet.properties().is_empty());
+        assert_eq!(packet.payload(), &EXAMPLE_PAYLOAD);
+
+        let properties: Vec<PublishProperty<'_>, 2> =
+            packet.properties_iter().collect::<Result<_, _>>().unwrap();
+        assert_eq!(&properties, example_packet(false, false).properties());
+
+        let message: ApplicationMessage<'_, 0> = packet.into();
+        assert_eq!(
+            message
+                .properties_iter()
+                .filter_map(|p| match p {
+                    Ok(PublishProperty::MessageExpiryInterval(expiry)) => Some(expiry.value()),
+                    _ => None,
+                })
+                .next(),
+            Some(45678)
+        );
+
+        // Packets that were not received have no encoded properties
+        assert_eq!(example_packet(false, false).properties_iter().count(), 0);
+    }
+
     #[test]
     fn decode_errors_on_data_with_invalid_length_that_excludes_payload() {
         let mut r = MqttBufReader::new(&EXAMPLE_DATA_INCORRECT_PACKET_LENGTH);
         let packet: Result<Publish<'_, 16>, PacketReadError> = Publish::read(&mut r);
         assert_eq!(packet, Err(PacketReadError::IncorrectPacketLength));
     }
+
+    #[test]
+    fn payload_format_is_validated_when_utf8_indicated() {
+        let packet = example_packet(false, false);
+        assert!(packet.payload_is_utf8());
+        assert!(packet.payload_format_is_valid());
+
+        let invalid_payload = [0x48, 0xFF];
+        let mut properties = Vec::new();
+        properties
+            .push(PublishProperty::PayloadFormatIndicator(
+                PayloadFormatIndicator::utf8(),
+            ))
+            .unwrap();
+        let packet: Publish<'_, 1> = Publish::new(
+            false,
+            false,
+            "test",
+            PublishPacketIdentifier::None,
+            &invalid_payload,
+            properties,
+        );
+        assert!(!packet.payload_format_is_valid());
+
+        let message: ApplicationMessage<'_, 1> = packet.into();
+        assert!(message.payload_is_utf8());
+        assert!(!message.payload_format_is_valid());
+
+        let packet: Publish<'_, 0> = Publish::new(
+            false,
+            false,
+            "test",
+            PublishPacketIdentifier::None,
+            &invalid_payload,
+            Vec::new(),
+        );
+        assert!(!packet.payload_is_utf8());
+        assert!(packet.payload_format_is_valid());
+    }
+
+    #[test]
+    fn application_message_property_accessors() {
+        let mut properties = Vec::new();
+        properties
+            .push(PublishProperty::ContentType("application/json".into()))
+            .unwrap();
+        properties
+            .push(PublishProperty::ResponseTopic("response".into()))
+            .unwrap();
+        properties
+            .push(PublishProperty::CorrelationData(
+                [1, 2, 3].as_slice().into(),
+            ))
+            .unwrap();
+        properties
+            .push(PublishProperty::MessageExpiryInterval(60.into()))
+            .unwrap();
+        properties
+            .push(PublishProperty::UserProperty(
+                StringPair::new("a", "1").into(),
+            ))
+            .unwrap();
+        properties
+            .push(PublishProperty::UserProperty(
+                StringPair::new("b", "2").into(),
+            ))
+            .unwrap();
+        let message: ApplicationMessage<'_, 6> = ApplicationMessage {
+            topic_name: "test",
+            payload: &EXAMPLE_PAYLOAD,
+            qos: QualityOfService::Qos0,
+            retain: false,
+            properties,
+            encoded_properties: &[],
+        };
+
+        assert_eq!(message.content_type(), Some("application/json"));
+        assert_eq!(message.response_topic(), Some("response"));
+        assert_eq!(message.correlation_data(), Some([1, 2, 3].as_slice()));
+        assert_eq!(message.message_expiry(), Some(60));
+        let user_properties: Vec<StringPair<'_>, 2> = message.user_properties().collect();
+        assert_eq!(
+            user_properties.as_slice(),
+            &[StringPair::new("a", "1"), StringPair::new("b", "2")]
+        );
+        assert_eq!(message.subscription_identifiers().count(), 0);
+
+        let message: ApplicationMessage<'_, 2> = example_packet(false, false).into();
+        assert_eq!(message.content_type(), None);
+        assert_eq!(message.message_expiry(), Some(45678));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn into_owned_outlives_receive_buffer() {
+        let owned = {
+            let buf = EXAMPLE_DATA;
+            let mut r = MqttBufReader::new(&buf);
+            let packet: Publish<'_, 2> = Publish::read(&mut r).unwrap();
+            packet.into_owned()
+        };
+        assert_eq!(owned.topic_name(), "test");
+        assert_eq!(owned.payload(), &EXAMPLE_PAYLOAD);
+        assert_eq!(owned.qos(), QualityOfService::Qos1);
+        assert_eq!(owned.properties_iter().count(), 2);
+        assert_eq!(
+            owned.as_publish::<2>().unwrap(),
+            example_packet(false, false)
+        );
+        assert_eq!(
+            owned.as_publish::<1>(),
+            Err(PacketReadError::TooManyProperties)
+        );
+
+        let message = OwnedApplicationMessage::from(owned);
+        let borrowed = message.as_message::<2>().unwrap();
+        assert_eq!(borrowed.topic_name, "test");
+        assert!(borrowed.payload_is_utf8());
+        assert_eq!(borrowed.message_expiry(), Some(45678));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn application_message_into_owned() {
+        let mut r = MqttBufReader::new(&EXAMPLE_DATA_RETAIN);
+        let packet: Publish<'_, 0> = Publish::read(&mut r).unwrap();
+        let message = ApplicationMessage::from(packet).into_owned();
+        assert_eq!(message.topic_name, "test");
+        assert_eq!(message.payload, EXAMPLE_PAYLOAD);
+        assert!(message.retain);
+        // Properties are kept even if they weren't stored when decoding
+        assert_eq!(message.properties_iter().count(), 2);
+    }
 }
@@ -0,0 +1,219 @@
+use super::packet::{Packet, PacketWrite};
+use super::publish::Publish;
+use crate::codec::mqtt_writer::{self, MqttBufWriter, MqttWriter};
+use crate::data::{
+    packet_identifier::PublishPacketIdentifier, packet_type::PacketType, property::PublishProperty,
+    quality_of_service::QualityOfService,
+};
+use heapless::Vec;
+
+const RETAIN_SHIFT: i32 = 0;
+const QOS_SHIFT: i32 = 1;
+
+/// The parts of a publish packet that stay the same between messages, i.e.
+/// the topic name, properties, quality of service and retain flag, encoded
+/// once when the template is made. Each message then only needs its payload
+/// (and packet identifier for qos 1 or 2) to be encoded, e.g. when
+/// publishing telemetry to the same topic many times a second.
+/// `N` is the capacity for the encoded topic name and properties.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PublishTemplate<const N: usize> {
+    qos: QualityOfService,
+    retain: bool,
+    /// The encoded topic name, followed by the encoded properties
+    encoded: [u8; N],
+    topic_name_len: usize,
+    len: usize,
+}
+
+impl<const N: usize> PublishTemplate<N> {
+    /// Encode the parts of a publish packet other than the payload and packet
+    /// identifier. Fails if the topic name or properties can't be encoded,
+    /// or don't fit in `N` bytes.
+    pub fn new<const P: usize>(
+        topic_name: &str,
+        qos: QualityOfService,
+        retain: bool,
+        properties: &Vec<PublishProperty<'_>, P>,
+    ) -> mqtt_writer::Result<Self> {
+        let mut encoded = [0; N];
+        let (topic_name_len, len) = {
+            let mut writer = MqttBufWriter::new(&mut encoded);
+            writer.put_str(topic_name)?;
+            let topic_name_len = writer.position();
+            writer.put_variable_u32_delimited_vec(properties)?;
+            (topic_name_len, writer.position())
+        };
+        Ok(Self {
+            qos,
+            retain,
+            encoded,
+            topic_name_len,
+            len,
+        })
+    }
+
+    pub fn qos(&self) -> QualityOfService {
+        self.qos
+    }
+
+    pub fn retain(&self) -> bool {
+        self.retain
+    }
+
+    pub fn topic_name(&self) -> &str {
+        // Skip the two byte length, the rest was encoded from a str
+        core::str::from_utf8(&self.encoded[2..self.topic_name_len]).unwrap_or("")
+    }
+
+    /// Produce a packet from the template with the given payload. The packet
+    /// identifier must match the template's quality of service, e.g. as
+    /// produced for a [Publish] by a [ClientState](crate::client_state::ClientState),
+    /// see [PublishTemplate::packet_for].
+    pub fn packet<'a>(
+        &'a self,
+        payload: &'a [u8],
+        packet_identifier: PublishPacketIdentifier,
+    ) -> TemplatePublish<'a, N> {
+        TemplatePublish {
+            template: self,
+            packet_identifier,
+            payload,
+        }
+    }
+
+    /// Produce a packet from the template with the given payload, using the packet
+    /// identifier of `publish`, which should have been produced for the template's
+    /// topic name and quality of service
+    pub fn packet_for<'a, const P: usize>(
+        &'a self,
+        payload: &'a [u8],
+        publish: &Publish<'_, P>,
+    ) -> TemplatePublish<'a, N> {
+        self.packet(payload, *publish.publish_packet_identifier())
+    }
+}
+
+/// A publish packet made from a [PublishTemplate] and a payload
+#[derive(Debug, PartialEq)]
+pub struct TemplatePublish<'a, const N: usize> {
+    template: &'a PublishTemplate<N>,
+    packet_identifier: PublishPacketIdentifier,
+    payload: &'a [u8],
+}
+
+impl<const N: usize> TemplatePublish<'_, N> {
+    pub fn payload(&self) -> &[u8] {
+        self.payload
+    }
+
+    pub fn publish_packet_identifier(&self) -> &PublishPacketIdentifier {
+        &self.packet_identifier
+    }
+}
+
+impl<const N: usize> Packet for TemplatePublish<'_, N> {
+    fn packet_type(&self) -> PacketType {
+        PacketType::Publish
+    }
+
+    fn fixed_header_first_byte(&self) -> u8 {
+        let mut b: u8 = self.packet_type().into();
+        if self.template.retain {
+            b |= 1 << RETAIN_SHIFT;
+        }
+        b |= (self.packet_identifier.qos() as u8) << QOS_SHIFT;
+        b
+    }
+}
+
+impl<const N: usize> PacketWrite for TemplatePublish<'_, N> {
+    fn put_variable_header_and_payload<'w, W: MqttWriter<'w>>(
+        &self,
+        writer: &mut W,
+    ) -> mqtt_writer::Result<()> {
+        let encoded = &self.template.encoded;
+        writer.put_slice(&encoded[0..self.template.topic_name_len])?;
+        match &self.packet_identifier {
+            PublishPacketIdentifier::None => {}
+            PublishPacketIdentifier::Qos1(id) => writer.put_u16(id.0)?,
+            PublishPacketIdentifier::Qos2(id) => writer.put_u16(id.0)?,
+        }
+        writer.put_slice(&encoded[self.template.topic_name_len..self.template.len])?;
+        writer.put_slice(self.payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codec::write::Write,
+        data::{
+            packet_identifier::PacketIdentifier,
+            property::{MessageExpiryInterval, UserProperty},
+            string_pair::StringPair,
+        },
+        error::PacketWriteError,
+    };
+
+    fn encode<P: Write>(packet: &P, buf: &mut [u8]) -> usize {
+        let mut writer = MqttBufWriter::new(buf);
+        packet.write(&mut writer).unwrap();
+        writer.position()
+    }
+
+    fn example_properties<'a>() -> Vec<PublishProperty<'a>, 2> {
+        let mut properties = Vec::new();
+        properties
+            .push(PublishProperty::MessageExpiryInterval(
+                MessageExpiryInterval::new(60),
+            ))
+            .unwrap();
+        properties
+            .push(PublishProperty::UserProperty(UserProperty::new(
+                StringPair::new("unit", "C"),
+            )))
+            .unwrap();
+        properties
+    }
+
+    #[test]
+    fn template_packet_encodes_as_publish() {
+        let template = PublishTemplate::<32>::new(
+            "sensor/temp",
+            QualityOfService::Qos1,
+            true,
+            &example_properties(),
+        )
+        .unwrap();
+        assert_eq!(template.topic_name(), "sensor/temp");
+
+        for (payload, id) in [(b"21.5".as_slice(), 1), (b"21.75".as_slice(), 2)] {
+            let id = PublishPacketIdentifier::Qos1(PacketIdentifier(id));
+            let publish = Publish::new(
+                false,
+                true,
+                "sensor/temp",
+                id,
+                payload,
+                example_properties(),
+            );
+
+            let mut expected = [0; 64];
+            let expected_len = encode(&publish, &mut expected);
+            let mut actual = [0; 64];
+            let actual_len = encode(&template.packet_for(payload, &publish), &mut actual);
+            assert_eq!(actual[0..actual_len], expected[0..expected_len]);
+        }
+    }
+
+    #[test]
+    fn template_must_fit_capacity() {
+        let properties: Vec<PublishProperty<'_>, 0> = Vec::new();
+        assert_eq!(
+            PublishTemplate::<8>::new("sensor/temp", QualityOfService::Qos0, false, &properties),
+            Err(PacketWriteError::Overflow)
+        );
+    }
+}
@@ -8,10 +8,11 @@ use crate::data::{
     reason_code::PublishReasonCode,
 };
 
+use crate::packets::PROPERTIES_DEFAULT;
 use heapless::Vec;
 
 #[derive(Debug, PartialEq)]
-pub struct Pubrec<'a, const P: usize> {
+pub struct Pubrec<'a, const P: usize = PROPERTIES_DEFAULT> {
     packet_identifier: PacketIdentifier,
     reason_code: PublishReasonCode,
     properties: Vec<PubrecProperty<'a>, P>,
@@ -8,10 +8,11 @@ use crate::data::{
     reason_code::PubrelReasonCode,
 };
 
+use crate::packets::PROPERTIES_DEFAULT;
 use heapless::Vec;
 
 #[derive(Debug, PartialEq)]
-pub struct Pubrel<'a, const P: usize> {
+pub struct Pubrel<'a, const P: usize = PROPERTIES_DEFAULT> {
     packet_identifier: PacketIdentifier,
     reason_code: PubrelReasonCode,
     properties: Vec<PubrelProperty<'a>, P>,
@@ -107,6 +108,7 @@ mod tests {
     use crate::{
         codec::{mqtt_reader::MqttBufReader, mqtt_writer::MqttBufWriter, write::Write},
         data::string_pair::StringPair,
+        error::PacketReadError,
     };
 
     use super::*;
@@ -216,4 +218,22 @@ mod tests {
     fn encode_and_decode_example_user_property() {
         encode_decode_and_check(&example_packet_user_property(), &EXAMPLE_DATA_USER_PROPERTY);
     }
+
+    #[test]
+    fn decode_fails_with_invalid_fixed_header_flags() {
+        // Pubrel must have flags 0b0010 [MQTT-3.6.1-1]
+        let mut encoded = EXAMPLE_DATA_LENGTH2;
+        encoded[0] = 0x60;
+        let mut r = MqttBufReader::new(&encoded);
+        let read_packet: Result<Pubrel<'_, 0>, _> = r.get();
+        assert_eq!(read_packet, Err(PacketReadError::InvalidPacketType));
+
+        #[cfg(all(feature = "client-codec", feature = "server-codec"))]
+        {
+            use crate::packets::packet_generic::PacketGeneric;
+            let mut r = MqttBufReader::new(&encoded);
+            let read_packet: Result<PacketGeneric<'_, 0, 0, 0>, _> = r.get();
+            assert_eq!(read_packet, Err(PacketReadError::InvalidPacketType));
+        }
+    }
 }
@@ -0,0 +1,330 @@
+use crate::packets::{PROPERTIES_DEFAULT, SUBSCRIPTION_REQUESTS_DEFAULT};
+#[cfg(feature = "client-codec")]
+use crate::{
+    codec::{mqtt_reader, read::Read},
+    packets::packet::{decode_exact, PacketRead},
+};
+#[cfg(feature = "server-codec")]
+use crate::{
+    codec::mqtt_writer,
+    packets::packet::PacketWrite,
+};
+use crate::{data::packet_type::PacketType, error::PacketReadError};
+
+use super::{
+    auth::Auth, connack::Connack, disconnect::Disconnect, packet::Packet,
+    packet_generic::PacketGeneric, pingresp::Pingresp, puback::Puback, pubcomp::Pubcomp,
+    publish::Publish, pubrec::Pubrec, pubrel::Pubrel, suback::Suback, unsuback::Unsuback,
+};
+
+/// A packet that may be sent by a server, and so received by a client.
+/// This is a subset of [PacketGeneric], without the Connect, Subscribe,
+/// Unsubscribe and Pingreq packets that only a server receives. This makes
+/// it smaller, and avoids generating code to decode and handle those packets
+/// in clients, which is significant on small embedded targets.
+/// `P` is the maximum number of properties in a packet.
+/// `S` is the maximum number of _additional_ reason codes in a Suback or
+/// Unsuback packet, after the mandatory reason code.
+/// Decoding a packet that only a server receives fails with
+/// [PacketReadError::ServerOnlyPacketType].
+#[derive(Debug, PartialEq)]
+pub enum ServerPacket<
+    'a,
+    const P: usize = PROPERTIES_DEFAULT,
+    const S: usize = SUBSCRIPTION_REQUESTS_DEFAULT,
+> {
+    Connack(Connack<'a, P>),
+    Publish(Publish<'a, P>),
+    Puback(Puback<'a, P>),
+    Pubrec(Pubrec<'a, P>),
+    Pubrel(Pubrel<'a, P>),
+    Pubcomp(Pubcomp<'a, P>),
+    Suback(Suback<'a, P, S>),
+    Unsuback(Unsuback<'a, P, S>),
+    Pingresp(Pingresp),
+    Disconnect(Disconnect<'a, P>),
+    Auth(Auth<'a, P>),
+}
+
+/// A [ServerPacket] with the default maximum numbers of properties and
+/// reason codes, see [PROPERTIES_DEFAULT] and [SUBSCRIPTION_REQUESTS_DEFAULT]
+pub type ServerPacketDefault<'a> = ServerPacket<'a>;
+
+#[cfg(feature = "server-codec")]
+impl<const P: usize, const S: usize> PacketWrite for ServerPacket<'_, P, S> {
+    fn put_variable_header_and_payload<'w, WRITER: mqtt_writer::MqttWriter<'w>>(
+        &self,
+        writer: &mut WRITER,
+    ) -> mqtt_writer::Result<()> {
+        match self {
+            ServerPacket::Connack(connack) => connack.put_variable_header_and_payload(writer),
+            ServerPacket::Publish(publish) => publish.put_variable_header_and_payload(writer),
+            ServerPacket::Puback(puback) => puback.put_variable_header_and_payload(writer),
+            ServerPacket::Pubrec(pubrec) => pubrec.put_variable_header_and_payload(writer),
+            ServerPacket::Pubrel(pubrel) => pubrel.put_variable_header_and_payload(writer),
+            ServerPacket::Pubcomp(pubcomp) => pubcomp.put_variable_header_and_payload(writer),
+            ServerPacket::Suback(suback) => suback.put_variable_header_and_payload(writer),
+            ServerPacket::Unsuback(unsuback) => unsuback.put_variable_header_and_payload(writer),
+            ServerPacket::Pingresp(pingresp) => pingresp.put_variable_header_and_payload(writer),
+            ServerPacket::Disconnect(disconnect) => {
+                disconnect.put_variable_header_and_payload(writer)
+            }
+            ServerPacket::Auth(auth) => auth.put_variable_header_and_payload(writer),
+        }
+    }
+}
+
+#[cfg(feature = "client-codec")]
+impl<'a, const P: usize, const S: usize> Read<'a> for ServerPacket<'a, P, S> {
+    fn read<R: crate::codec::mqtt_reader::MqttReader<'a>>(
+        reader: &mut R,
+    ) -> mqtt_reader::Result<Self>
+    where
+        Self: Sized,
+    {
+        let first_header_byte = reader.get_u8()?;
+
+        // Check that packet type and any fixed flags are valid
+        let packet_type = PacketType::from_first_header_byte(first_header_byte)?;
+
+        let len = reader.get_variable_u32()? as usize;
+        let packet_end_position = reader.position() + len;
+
+        let server_packet = match packet_type {
+            PacketType::Connack => {
+                let packet =
+                    PacketRead::get_variable_header_and_payload(reader, first_header_byte, len)?;
+                ServerPacket::Connack(packet)
+            }
+            PacketType::Publish => {
+                let packet =
+                    PacketRead::get_variable_header_and_payload(reader, first_header_byte, len)?;
+                ServerPacket::Publish(packet)
+            }
+            PacketType::Puback => {
+                let packet =
+                    PacketRead::get_variable_header_and_payload(reader, first_header_byte, len)?;
+                ServerPacket::Puback(packet)
+            }
+            PacketType::Pubrec => {
+                let packet =
+                    PacketRead::get_variable_header_and_payload(reader, first_header_byte, len)?;
+                ServerPacket::Pubrec(packet)
+            }
+            PacketType::Pubrel => {
+                let packet =
+                    PacketRead::get_variable_header_and_payload(reader, first_header_byte, len)?;
+                ServerPacket::Pubrel(packet)
+            }
+            PacketType::Pubcomp => {
+                let packet =
+                    PacketRead::get_variable_header_and_payload(reader, first_header_byte, len)?;
+                ServerPacket::Pubcomp(packet)
+            }
+            PacketType::Suback => {
+                let packet =
+                    PacketRead::get_variable_header_and_payload(reader, first_header_byte, len)?;
+                ServerPacket::Suback(packet)
+            }
+            PacketType::Unsuback => {
+                let packet =
+                    PacketRead::get_variable_header_and_payload(reader, first_header_byte, len)?;
+                ServerPacket::Unsuback(packet)
+            }
+            PacketType::Pingresp => {
+                let packet =
+                    PacketRead::get_variable_header_and_payload(reader, first_header_byte, len)?;
+                ServerPacket::Pingresp(packet)
+            }
+            PacketType::Disconnect => {
+                let packet =
+                    PacketRead::get_variable_header_and_payload(reader, first_header_byte, len)?;
+                ServerPacket::Disconnect(packet)
+            }
+            PacketType::Auth => {
+                let packet =
+                    PacketRead::get_variable_header_and_payload(reader, first_header_byte, len)?;
+                ServerPacket::Auth(packet)
+            }
+            PacketType::Connect
+            | PacketType::Subscribe
+            | PacketType::Unsubscribe
+            | PacketType::Pingreq => return Err(PacketReadError::ServerOnlyPacketType),
+        };
+
+        // Check remaining length was correct
+        if reader.position() == packet_end_position {
+            Ok(server_packet)
+        } else {
+            Err(PacketReadError::IncorrectPacketLength)
+        }
+    }
+}
+
+/// Decode a packet from `data`, which must contain exactly one encoded packet
+#[cfg(feature = "client-codec")]
+impl<'a, const P: usize, const S: usize> TryFrom<&'a [u8]> for ServerPacket<'a, P, S> {
+    type Error = PacketReadError;
+
+    fn try_from(data: &'a [u8]) -> Result<Self, Self::Error> {
+        decode_exact(data)
+    }
+}
+
+impl<const P: usize, const S: usize> Packet for ServerPacket<'_, P, S> {
+    fn packet_type(&self) -> PacketType {
+        match self {
+            ServerPacket::Connack(_) => PacketType::Connack,
+            ServerPacket::Publish(_) => PacketType::Publish,
+            ServerPacket::Puback(_) => PacketType::Puback,
+            ServerPacket::Pubrec(_) => PacketType::Pubrec,
+            ServerPacket::Pubrel(_) => PacketType::Pubrel,
+            ServerPacket::Pubcomp(_) => PacketType::Pubcomp,
+            ServerPacket::Suback(_) => PacketType::Suback,
+            ServerPacket::Unsuback(_) => PacketType::Unsuback,
+            ServerPacket::Pingresp(_) => PacketType::Pingresp,
+            ServerPacket::Disconnect(_) => PacketType::Disconnect,
+            ServerPacket::Auth(_) => PacketType::Auth,
+        }
+    }
+
+    // Use the first byte of the contained packet, which may include flags,
+    // e.g. the qos of a publish packet
+    fn fixed_header_first_byte(&self) -> u8 {
+        match self {
+            ServerPacket::Connack(packet) => packet.fixed_header_first_byte(),
+            ServerPacket::Publish(packet) => packet.fixed_header_first_byte(),
+            ServerPacket::Puback(packet) => packet.fixed_header_first_byte(),
+            ServerPacket::Pubrec(packet) => packet.fixed_header_first_byte(),
+            ServerPacket::Pubrel(packet) => packet.fixed_header_first_byte(),
+            ServerPacket::Pubcomp(packet) => packet.fixed_header_first_byte(),
+            ServerPacket::Suback(packet) => packet.fixed_header_first_byte(),
+            ServerPacket::Unsuback(packet) => packet.fixed_header_first_byte(),
+            ServerPacket::Pingresp(packet) => packet.fixed_header_first_byte(),
+            ServerPacket::Disconnect(packet) => packet.fixed_header_first_byte(),
+            ServerPacket::Auth(packet) => packet.fixed_header_first_byte(),
+        }
+    }
+}
+
+impl<'a, const P: usize, const W: usize, const S: usize> From<ServerPacket<'a, P, S>>
+    for PacketGeneric<'a, P, W, S>
+{
+    fn from(value: ServerPacket<'a, P, S>) -> Self {
+        match value {
+            ServerPacket::Connack(packet) => PacketGeneric::Connack(packet),
+            ServerPacket::Publish(packet) => PacketGeneric::Publish(packet),
+            ServerPacket::Puback(packet) => PacketGeneric::Puback(packet),
+            ServerPacket::Pubrec(packet) => PacketGeneric::Pubrec(packet),
+            ServerPacket::Pubrel(packet) => PacketGeneric::Pubrel(packet),
+            ServerPacket::Pubcomp(packet) => PacketGeneric::Pubcomp(packet),
+            ServerPacket::Suback(packet) => PacketGeneric::Suback(packet),
+            ServerPacket::Unsuback(packet) => PacketGeneric::Unsuback(packet),
+            ServerPacket::Pingresp(packet) => PacketGeneric::Pingresp(packet),
+            ServerPacket::Disconnect(packet) => PacketGeneric::Disconnect(packet),
+            ServerPacket::Auth(packet) => PacketGeneric::Auth(packet),
+        }
+    }
+}
+
+impl<'a, const P: usize, const W: usize, const S: usize> TryFrom<PacketGeneric<'a, P, W, S>>
+    for ServerPacket<'a, P, S>
+{
+    type Error = PacketReadError;
+
+    fn try_from(value: PacketGeneric<'a, P, W, S>) -> Result<Self, Self::Error> {
+        match value {
+            PacketGeneric::Connack(packet) => Ok(ServerPacket::Connack(packet)),
+            PacketGeneric::Publish(packet) => Ok(ServerPacket::Publish(packet)),
+            PacketGeneric::Puback(packet) => Ok(ServerPacket::Puback(packet)),
+            PacketGeneric::Pubrec(packet) => Ok(ServerPacket::Pubrec(packet)),
+            PacketGeneric::Pubrel(packet) => Ok(ServerPacket::Pubrel(packet)),
+            PacketGeneric::Pubcomp(packet) => Ok(ServerPacket::Pubcomp(packet)),
+            PacketGeneric::Suback(packet) => Ok(ServerPacket::Suback(packet)),
+            PacketGeneric::Unsuback(packet) => Ok(ServerPacket::Unsuback(packet)),
+            PacketGeneric::Pingresp(packet) => Ok(ServerPacket::Pingresp(packet)),
+            PacketGeneric::Disconnect(packet) => Ok(ServerPacket::Disconnect(packet)),
+            PacketGeneric::Auth(packet) => Ok(ServerPacket::Auth(packet)),
+            PacketGeneric::Connect(_)
+            | PacketGeneric::Subscribe(_)
+            | PacketGeneric::Unsubscribe(_)
+            | PacketGeneric::Pingreq(_) => Err(PacketReadError::ServerOnlyPacketType),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "server-codec")]
+    use crate::error::PacketWriteError;
+    use crate::{
+        codec::mqtt_reader::{MqttBufReader, MqttReader},
+        packets::packet_generic::PacketGenericDefault,
+    };
+
+    const ENCODED_PINGREQ: [u8; 2] = [0xC0, 0x00];
+    const ENCODED_PINGRESP: [u8; 2] = [0xD0, 0x00];
+
+    #[test]
+    fn decode_packet_sent_by_server() {
+        let mut r = MqttBufReader::new(&ENCODED_PINGRESP);
+        let packet: ServerPacket<'_, 0, 0> = r.get().unwrap();
+        assert_eq!(packet, ServerPacket::Pingresp(Pingresp::default()));
+        assert_eq!(
+            PacketGeneric::<'_, 0, 0, 0>::from(packet),
+            PacketGeneric::Pingresp(Pingresp::default())
+        );
+    }
+
+    #[test]
+    fn decode_packet_with_default_parameters() {
+        let mut r = MqttBufReader::new(&ENCODED_PINGRESP);
+        let packet: ServerPacket<'_> = r.get().unwrap();
+        assert_eq!(
+            PacketGenericDefault::from(packet),
+            PacketGeneric::Pingresp(Pingresp::default())
+        );
+    }
+
+    #[test]
+    fn try_from_decodes_exactly_one_packet() {
+        let packet = ServerPacketDefault::try_from(&ENCODED_PINGRESP[..]);
+        assert_eq!(packet, Ok(ServerPacket::Pingresp(Pingresp::default())));
+
+        let packet = ServerPacketDefault::try_from(&[0xD0, 0x00, 0xD0][..]);
+        assert_eq!(packet, Err(PacketReadError::IncorrectPacketLength));
+
+        let packet = ServerPacketDefault::try_from(&[0xD0][..]);
+        assert_eq!(packet, Err(PacketReadError::InsufficientData));
+    }
+
+    #[cfg(feature = "server-codec")]
+    #[test]
+    fn encode_and_try_from_round_trip() {
+        let packet: ServerPacket<'_> = ServerPacket::Pingresp(Pingresp::default());
+
+        let mut buf = [0; 1];
+        assert_eq!(packet.encode(&mut buf), Err(PacketWriteError::Overflow));
+
+        let mut buf = [0; 2];
+        assert_eq!(packet.encode(&mut buf), Ok(2));
+        assert_eq!(buf, ENCODED_PINGRESP);
+        assert_eq!(ServerPacketDefault::try_from(&buf[..]), Ok(packet));
+    }
+
+    #[test]
+    fn decode_fails_for_packet_only_server_receives() {
+        let mut r = MqttBufReader::new(&ENCODED_PINGREQ);
+        let packet: Result<ServerPacket<'_, 0, 0>, _> = r.get();
+        assert_eq!(packet, Err(PacketReadError::ServerOnlyPacketType));
+    }
+
+    #[test]
+    fn server_packet_is_smaller_than_packet_generic() {
+        assert!(
+            core::mem::size_of::<ServerPacket<'_, 8, 0>>()
+                < core::mem::size_of::<PacketGeneric<'_, 8, 0, 0>>()
+        );
+    }
+}
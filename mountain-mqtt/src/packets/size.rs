@@ -0,0 +1,338 @@
+//! `const fn` helpers to calculate the encoded size of packets, so that
+//! buffers can be sized exactly at compile time, for example:
+//!
+//! ```
+//! use mountain_mqtt::packets::size::{connect_len, numeric_properties_len};
+//!
+//! // A connect packet with a 32 byte client id, 16 byte username and password,
+//! // no will, and up to 2 numeric properties (e.g. session expiry interval
+//! // and topic alias maximum)
+//! const CONNECT_LEN: usize = connect_len(32, Some(16), Some(16), numeric_properties_len(2), None);
+//! let buffer = [0u8; CONNECT_LEN];
+//! ```
+//!
+//! Sizes of properties are given as the total encoded length of all properties
+//! in the list, which can be found using the property helpers, e.g.
+//! `numeric_properties_len(2) + string_property_len(16)`.
+
+use crate::data::VARIABLE_BYTE_INTEGER_MAX_LEN;
+
+use super::packet::PROTOCOL_NAME;
+
+/// The maximum encoded length of a property with a numeric value, including
+/// its identifier. This is the largest value size (4 bytes for a four byte
+/// integer or variable byte integer) plus the identifier (1 byte)
+pub const NUMERIC_PROPERTY_MAX_LEN: usize = 5;
+
+/// The number of bytes needed to encode `n` as a variable byte integer.
+/// Values too large to encode give [VARIABLE_BYTE_INTEGER_MAX_LEN]
+pub const fn variable_byte_integer_len(n: usize) -> usize {
+    if n < 128 {
+        1
+    } else if n < 16_384 {
+        2
+    } else if n < 2_097_152 {
+        3
+    } else {
+        VARIABLE_BYTE_INTEGER_MAX_LEN
+    }
+}
+
+/// The encoded length of a string of `len` bytes (2 byte length then data)
+pub const fn string_len(len: usize) -> usize {
+    2 + len
+}
+
+/// The encoded length of binary data of `len` bytes (2 byte length then data)
+pub const fn binary_data_len(len: usize) -> usize {
+    2 + len
+}
+
+/// The maximum encoded length of `count` numeric properties (e.g.
+/// [crate::data::property::SessionExpiryInterval]), see [NUMERIC_PROPERTY_MAX_LEN]
+pub const fn numeric_properties_len(count: usize) -> usize {
+    count * NUMERIC_PROPERTY_MAX_LEN
+}
+
+/// The encoded length of a string property (e.g. [crate::data::property::ContentType])
+/// with a value of `len` bytes
+pub const fn string_property_len(len: usize) -> usize {
+    1 + string_len(len)
+}
+
+/// The encoded length of a binary data property (e.g.
+/// [crate::data::property::CorrelationData]) with a value of `len` bytes
+pub const fn binary_data_property_len(len: usize) -> usize {
+    1 + binary_data_len(len)
+}
+
+/// The encoded length of a [crate::data::property::UserProperty] with a name of
+/// `name_len` bytes and value of `value_len` bytes
+pub const fn user_property_len(name_len: usize, value_len: usize) -> usize {
+    1 + string_len(name_len) + string_len(value_len)
+}
+
+/// The encoded length of a property list with properties of `properties_len`
+/// bytes in total, including the leading length of the list
+pub const fn property_list_len(properties_len: usize) -> usize {
+    variable_byte_integer_len(properties_len) + properties_len
+}
+
+/// The encoded length of a whole packet, with a variable header and payload
+/// of `remaining_len` bytes, including the fixed header
+pub const fn packet_len(remaining_len: usize) -> usize {
+    1 + variable_byte_integer_len(remaining_len) + remaining_len
+}
+
+/// The sizes of the parts of a [crate::packets::connect::Will], for [connect_len]
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WillLen {
+    pub topic_name_len: usize,
+    pub payload_len: usize,
+    pub properties_len: usize,
+}
+
+/// The encoded length of a [crate::packets::connect::Connect] packet.
+/// Username and password lengths are None if they are not present, and
+/// properties lengths are the total length of all properties in the list.
+pub const fn connect_len(
+    client_id_len: usize,
+    username_len: Option<usize>,
+    password_len: Option<usize>,
+    properties_len: usize,
+    will: Option<WillLen>,
+) -> usize {
+    // Protocol name, version, connect flags, keep alive
+    let mut len = string_len(PROTOCOL_NAME.len()) + 1 + 1 + 2;
+    len += property_list_len(properties_len);
+    len += string_len(client_id_len);
+    if let Some(will) = will {
+        len += property_list_len(will.properties_len)
+            + string_len(will.topic_name_len)
+            + binary_data_len(will.payload_len);
+    }
+    if let Some(username_len) = username_len {
+        len += string_len(username_len);
+    }
+    if let Some(password_len) = password_len {
+        len += binary_data_len(password_len);
+    }
+    packet_len(len)
+}
+
+/// The encoded length of a [crate::packets::subscribe::Subscribe] packet,
+/// with the given lengths of topic names of each subscription request,
+/// and total length of properties.
+pub const fn subscribe_len(topic_name_lens: &[usize], properties_len: usize) -> usize {
+    // Packet identifier
+    let mut len = 2 + property_list_len(properties_len);
+    let mut i = 0;
+    while i < topic_name_lens.len() {
+        // Topic name then subscription options byte
+        len += string_len(topic_name_lens[i]) + 1;
+        i += 1;
+    }
+    packet_len(len)
+}
+
+/// The encoded length of a [crate::packets::unsubscribe::Unsubscribe] packet,
+/// with the given lengths of topic names, and total length of properties.
+pub const fn unsubscribe_len(topic_name_lens: &[usize], properties_len: usize) -> usize {
+    // Packet identifier
+    let mut len = 2 + property_list_len(properties_len);
+    let mut i = 0;
+    while i < topic_name_lens.len() {
+        len += string_len(topic_name_lens[i]);
+        i += 1;
+    }
+    packet_len(len)
+}
+
+/// The encoded length of a [crate::packets::publish::Publish] packet, with a packet
+/// identifier for quality of service 1 or 2 if `has_packet_identifier` is true.
+pub const fn publish_len(
+    topic_name_len: usize,
+    payload_len: usize,
+    has_packet_identifier: bool,
+    properties_len: usize,
+) -> usize {
+    let mut len = string_len(topic_name_len) + property_list_len(properties_len) + payload_len;
+    if has_packet_identifier {
+        len += 2;
+    }
+    packet_len(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::Vec;
+
+    use super::*;
+    use crate::{
+        codec::mqtt_writer::{MqttBufWriter, MqttWriter},
+        data::{
+            packet_identifier::{PacketIdentifier, PublishPacketIdentifier},
+            property::{
+                ConnectProperty, ContentType, MaximumPacketSize, PublishProperty,
+                SessionExpiryInterval, SubscribeProperty, SubscriptionIdentifier, UserProperty,
+                WillDelayInterval, WillProperty,
+            },
+            quality_of_service::QualityOfService,
+            string_pair::StringPair,
+        },
+        packets::{
+            connect::{Connect, Will},
+            publish::Publish,
+            subscribe::{Subscribe, SubscriptionRequest},
+            unsubscribe::Unsubscribe,
+        },
+    };
+
+    fn encoded_len<T: crate::codec::write::Write>(packet: &T) -> usize {
+        let mut buf = [0u8; 1024];
+        let mut writer = MqttBufWriter::new(&mut buf);
+        writer.put(packet).unwrap();
+        writer.position()
+    }
+
+    #[test]
+    fn variable_byte_integer_len_matches_encoding() {
+        for n in [
+            0,
+            127,
+            128,
+            16_383,
+            16_384,
+            2_097_151,
+            2_097_152,
+            268_435_455,
+        ] {
+            let mut buf = [0u8; 4];
+            let mut writer = MqttBufWriter::new(&mut buf);
+            writer.put_variable_u32(n as u32).unwrap();
+            assert_eq!(variable_byte_integer_len(n), writer.position());
+        }
+    }
+
+    #[test]
+    fn connect_len_matches_encoding() {
+        let mut properties = Vec::<ConnectProperty<'_>, 2>::new();
+        properties
+            .push(ConnectProperty::SessionExpiryInterval(
+                SessionExpiryInterval::new(u32::MAX),
+            ))
+            .unwrap();
+        properties
+            .push(ConnectProperty::MaximumPacketSize(MaximumPacketSize::new(
+                1024,
+            )))
+            .unwrap();
+        let packet: Connect<'_, 2, 0> = Connect::new(
+            60,
+            Some("user"),
+            Some(b"secret"),
+            "client-id",
+            true,
+            None,
+            properties,
+        );
+        assert_eq!(
+            encoded_len(&packet),
+            connect_len(9, Some(4), Some(6), numeric_properties_len(2), None)
+        );
+
+        let mut will_properties = Vec::<WillProperty<'_>, 1>::new();
+        will_properties
+            .push(WillProperty::WillDelayInterval(WillDelayInterval::new(30)))
+            .unwrap();
+        let will = Will::new(
+            QualityOfService::Qos1,
+            true,
+            "will/topic",
+            &[0u8; 200],
+            will_properties,
+        );
+        let packet: Connect<'_, 0, 1> =
+            Connect::new(60, None, None, "client-id", true, Some(will), Vec::new());
+        assert_eq!(
+            encoded_len(&packet),
+            connect_len(
+                9,
+                None,
+                None,
+                0,
+                Some(WillLen {
+                    topic_name_len: 10,
+                    payload_len: 200,
+                    properties_len: numeric_properties_len(1),
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn subscribe_and_unsubscribe_len_match_encoding() {
+        let mut other_requests = Vec::<SubscriptionRequest<'_>, 1>::new();
+        other_requests
+            .push(SubscriptionRequest::new(
+                "b/longer/topic",
+                QualityOfService::Qos0,
+            ))
+            .unwrap();
+        let mut properties = Vec::new();
+        // Use the largest identifier, so the property has its maximum length
+        properties
+            .push(SubscribeProperty::SubscriptionIdentifier(
+                SubscriptionIdentifier::new(268_435_455),
+            ))
+            .unwrap();
+        let packet: Subscribe<'_, 1, 1> = Subscribe::new(
+            PacketIdentifier(1),
+            SubscriptionRequest::new("a/topic", QualityOfService::Qos1),
+            other_requests,
+            properties,
+        );
+        assert_eq!(
+            encoded_len(&packet),
+            subscribe_len(&[7, 14], numeric_properties_len(1))
+        );
+
+        let mut other_topics = Vec::<&str, 1>::new();
+        other_topics.push("b/longer/topic").unwrap();
+        let packet: Unsubscribe<'_, 0, 1> =
+            Unsubscribe::new(PacketIdentifier(1), "a/topic", other_topics, Vec::new());
+        assert_eq!(encoded_len(&packet), unsubscribe_len(&[7, 14], 0));
+    }
+
+    #[test]
+    fn publish_len_matches_encoding() {
+        let mut properties = Vec::<PublishProperty<'_>, 2>::new();
+        properties
+            .push(PublishProperty::ContentType(ContentType::new("text/plain")))
+            .unwrap();
+        properties
+            .push(PublishProperty::UserProperty(UserProperty::new(
+                StringPair::new("name", "value"),
+            )))
+            .unwrap();
+        let payload = [0u8; 500];
+        let packet = Publish::new(
+            false,
+            false,
+            "a/topic",
+            PublishPacketIdentifier::Qos1(PacketIdentifier(1)),
+            &payload,
+            properties,
+        );
+        assert_eq!(
+            encoded_len(&packet),
+            publish_len(
+                7,
+                500,
+                true,
+                string_property_len(10) + user_property_len(4, 5)
+            )
+        );
+    }
+}
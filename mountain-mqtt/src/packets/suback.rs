@@ -1,19 +1,28 @@
-use super::packet::{Packet, PacketRead, PacketWrite};
+use super::packet::Packet;
+#[cfg(feature = "client-codec")]
+use super::packet::PacketRead;
+#[cfg(feature = "server-codec")]
+use super::packet::PacketWrite;
+#[cfg(feature = "server-codec")]
+use crate::codec::mqtt_writer::{self, MqttWriter};
 use crate::data::{
     packet_identifier::PacketIdentifier, packet_type::PacketType, property::SubackProperty,
     reason_code::SubscribeReasonCode,
 };
+use crate::packets::{PROPERTIES_DEFAULT, SUBSCRIPTION_REQUESTS_DEFAULT};
+#[cfg(feature = "client-codec")]
 use crate::{
-    codec::{
-        mqtt_reader::{self, MqttReader},
-        mqtt_writer::{self, MqttWriter},
-    },
+    codec::mqtt_reader::{self, MqttReader},
     error::PacketReadError,
 };
 use heapless::Vec;
 
 #[derive(Debug, PartialEq)]
-pub struct Suback<'a, const P: usize, const S: usize> {
+pub struct Suback<
+    'a,
+    const P: usize = PROPERTIES_DEFAULT,
+    const S: usize = SUBSCRIPTION_REQUESTS_DEFAULT,
+> {
     packet_identifier: PacketIdentifier,
     first_reason_code: SubscribeReasonCode,
     other_reason_codes: Vec<SubscribeReasonCode, S>,
@@ -44,6 +53,11 @@ impl<'a, const P: usize, const S: usize> Suback<'a, P, S> {
     pub fn other_reason_codes(&self) -> &Vec<SubscribeReasonCode, S> {
         &self.other_reason_codes
     }
+
+    /// Iterate over all reason codes, in order
+    pub fn reason_codes(&self) -> impl Iterator<Item = &SubscribeReasonCode> {
+        core::iter::once(&self.first_reason_code).chain(self.other_reason_codes.iter())
+    }
     pub fn properties(&self) -> &Vec<SubackProperty<'a>, P> {
         &self.properties
     }
@@ -55,6 +69,7 @@ impl<const P: usize, const S: usize> Packet for Suback<'_, P, S> {
     }
 }
 
+#[cfg(feature = "server-codec")]
 impl<const P: usize, const S: usize> PacketWrite for Suback<'_, P, S> {
     fn put_variable_header_and_payload<'w, W: MqttWriter<'w>>(
         &self,
@@ -75,6 +90,7 @@ impl<const P: usize, const S: usize> PacketWrite for Suback<'_, P, S> {
     }
 }
 
+#[cfg(feature = "client-codec")]
 impl<'a, const P: usize, const S: usize> PacketRead<'a> for Suback<'a, P, S> {
     fn get_variable_header_and_payload<R: MqttReader<'a>>(
         reader: &mut R,
@@ -120,7 +136,7 @@ impl<'a, const P: usize, const S: usize> PacketRead<'a> for Suback<'a, P, S> {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "client-codec", feature = "server-codec"))]
 mod tests {
     use crate::codec::{
         mqtt_reader::MqttBufReader, mqtt_writer::MqttBufWriter, read::Read, write::Write,
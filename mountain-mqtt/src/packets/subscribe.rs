@@ -1,20 +1,23 @@
-use super::packet::{Packet, PacketRead, PacketWrite};
+use super::packet::Packet;
+#[cfg(feature = "server-codec")]
+use super::packet::PacketRead;
+#[cfg(feature = "client-codec")]
+use super::packet::PacketWrite;
+#[cfg(feature = "client-codec")]
+use crate::codec::mqtt_writer::{self, MqttWriter};
 use crate::data::{
-    packet_identifier::PacketIdentifier,
-    packet_type::PacketType,
-    property::SubscribeProperty,
-    quality_of_service::QualityOfService,
-    subscription_options::{RetainHandling, SubscriptionOptions},
+    packet_identifier::PacketIdentifier, packet_type::PacketType, property::SubscribeProperty,
+    quality_of_service::QualityOfService, subscription_options::SubscriptionOptions,
 };
+use crate::packets::{PROPERTIES_DEFAULT, SUBSCRIPTION_REQUESTS_DEFAULT};
+#[cfg(feature = "server-codec")]
 use crate::{
     codec::{
         mqtt_reader::{self, MqttReader},
-        mqtt_writer::{self, MqttWriter},
         read::Read,
     },
     error::PacketReadError,
 };
-
 use heapless::Vec;
 
 #[derive(Debug, PartialEq)]
@@ -25,18 +28,21 @@ pub struct SubscriptionRequest<'a> {
 
 impl<'a> SubscriptionRequest<'a> {
     pub fn new(topic_name: &'a str, maximum_qos: QualityOfService) -> SubscriptionRequest<'a> {
+        Self::with_options(topic_name, SubscriptionOptions::new(maximum_qos))
+    }
+
+    pub fn with_options(
+        topic_name: &'a str,
+        options: SubscriptionOptions,
+    ) -> SubscriptionRequest<'a> {
         SubscriptionRequest {
             topic_name,
-            options: SubscriptionOptions {
-                maximum_qos,
-                no_local: false,
-                retain_as_published: false,
-                retain_handling: RetainHandling::SendOnSubscribe,
-            },
+            options,
         }
     }
 }
 
+#[cfg(feature = "server-codec")]
 impl<'a> Read<'a> for SubscriptionRequest<'a> {
     fn read<R: MqttReader<'a>>(reader: &mut R) -> mqtt_reader::Result<Self>
     where
@@ -47,7 +53,11 @@ impl<'a> Read<'a> for SubscriptionRequest<'a> {
 }
 
 #[derive(Debug, PartialEq)]
-pub struct Subscribe<'a, const P: usize, const S: usize> {
+pub struct Subscribe<
+    'a,
+    const P: usize = PROPERTIES_DEFAULT,
+    const S: usize = SUBSCRIPTION_REQUESTS_DEFAULT,
+> {
     packet_identifier: PacketIdentifier,
     first_request: SubscriptionRequest<'a>,
     other_requests: Vec<SubscriptionRequest<'a>, S>,
@@ -86,6 +96,11 @@ impl<'a, const P: usize, const S: usize> Subscribe<'a, P, S> {
     pub fn request_count(&self) -> usize {
         self.other_requests.len() + 1
     }
+
+    /// Iterate over all subscription requests, in order
+    pub fn requests(&self) -> impl Iterator<Item = &SubscriptionRequest<'a>> {
+        core::iter::once(&self.first_request).chain(self.other_requests.iter())
+    }
 }
 
 impl<const P: usize, const S: usize> Packet for Subscribe<'_, P, S> {
@@ -94,6 +109,7 @@ impl<const P: usize, const S: usize> Packet for Subscribe<'_, P, S> {
     }
 }
 
+#[cfg(feature = "client-codec")]
 impl<const P: usize, const S: usize> PacketWrite for Subscribe<'_, P, S> {
     fn put_variable_header_and_payload<'w, W: MqttWriter<'w>>(
         &self,
@@ -114,6 +130,7 @@ impl<const P: usize, const S: usize> PacketWrite for Subscribe<'_, P, S> {
     }
 }
 
+#[cfg(feature = "server-codec")]
 impl<'a, const P: usize, const S: usize> PacketRead<'a> for Subscribe<'a, P, S> {
     fn get_variable_header_and_payload<R: MqttReader<'a>>(
         reader: &mut R,
@@ -153,13 +170,16 @@ impl<'a, const P: usize, const S: usize> PacketRead<'a> for Subscribe<'a, P, S>
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "client-codec", feature = "server-codec"))]
 mod tests {
-    use crate::codec::{
-        mqtt_reader::MqttBufReader,
-        mqtt_writer::{MqttBufWriter, MqttLenWriter},
-        read::Read,
-        write::Write,
+    use crate::{
+        codec::{
+            mqtt_reader::MqttBufReader,
+            mqtt_writer::{MqttBufWriter, MqttLenWriter},
+            read::Read,
+            write::Write,
+        },
+        data::subscription_options::RetainHandling,
     };
 
     use super::*;
@@ -249,6 +269,24 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn subscription_options_builder_matches_fields() {
+        let o = SubscriptionOptions::new(QualityOfService::Qos1)
+            .with_retain_handling(RetainHandling::DoNotSend)
+            .with_no_local(true)
+            .with_retain_as_published(true);
+        assert_eq!(
+            o,
+            SubscriptionOptions {
+                maximum_qos: QualityOfService::Qos1,
+                no_local: true,
+                retain_as_published: true,
+                retain_handling: RetainHandling::DoNotSend,
+            }
+        );
+        assert_eq!(u8::from(&o), 0b0010_1101);
+    }
+
     #[test]
     fn mqtt_buf_reader_can_get_subscription_options() -> mqtt_reader::Result<()> {
         for (o, encoded) in SUBSCRIPTION_OPTIONS_CASES.iter() {
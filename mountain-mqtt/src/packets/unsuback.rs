@@ -1,19 +1,28 @@
-use super::packet::{Packet, PacketRead, PacketWrite};
+use super::packet::Packet;
+#[cfg(feature = "client-codec")]
+use super::packet::PacketRead;
+#[cfg(feature = "server-codec")]
+use super::packet::PacketWrite;
+#[cfg(feature = "server-codec")]
+use crate::codec::mqtt_writer::{self, MqttWriter};
 use crate::data::{
     packet_identifier::PacketIdentifier, packet_type::PacketType, property::UnsubackProperty,
     reason_code::UnsubscribeReasonCode,
 };
+#[cfg(feature = "client-codec")]
 use crate::{
-    codec::{
-        mqtt_reader::{self, MqttReader},
-        mqtt_writer::{self, MqttWriter},
-    },
+    codec::mqtt_reader::{self, MqttReader},
     error::PacketReadError,
 };
+use crate::packets::{PROPERTIES_DEFAULT, SUBSCRIPTION_REQUESTS_DEFAULT};
 use heapless::Vec;
 
 #[derive(Debug, PartialEq)]
-pub struct Unsuback<'a, const P: usize, const S: usize> {
+pub struct Unsuback<
+    'a,
+    const P: usize = PROPERTIES_DEFAULT,
+    const S: usize = SUBSCRIPTION_REQUESTS_DEFAULT,
+> {
     packet_identifier: PacketIdentifier,
     first_reason_code: UnsubscribeReasonCode,
     other_reason_codes: Vec<UnsubscribeReasonCode, S>,
@@ -44,6 +53,11 @@ impl<'a, const P: usize, const S: usize> Unsuback<'a, P, S> {
     pub fn other_reason_codes(&self) -> &Vec<UnsubscribeReasonCode, S> {
         &self.other_reason_codes
     }
+
+    /// Iterate over all reason codes, in order
+    pub fn reason_codes(&self) -> impl Iterator<Item = &UnsubscribeReasonCode> {
+        core::iter::once(&self.first_reason_code).chain(self.other_reason_codes.iter())
+    }
     pub fn properties(&self) -> &Vec<UnsubackProperty<'a>, P> {
         &self.properties
     }
@@ -57,6 +71,7 @@ impl<const P: usize, const S: usize> Packet
     }
 }
 
+#[cfg(feature = "server-codec")]
 impl<const P: usize, const S: usize> PacketWrite
     for Unsuback<'_, P, S>
 {
@@ -79,6 +94,7 @@ impl<const P: usize, const S: usize> PacketWrite
     }
 }
 
+#[cfg(feature = "client-codec")]
 impl<'a, const P: usize, const S: usize> PacketRead<'a>
     for Unsuback<'a, P, S>
 {
@@ -126,7 +142,7 @@ impl<'a, const P: usize, const S: usize> PacketRead<'a>
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "client-codec", feature = "server-codec"))]
 mod tests {
     use crate::codec::{
         mqtt_reader::MqttBufReader, mqtt_writer::MqttBufWriter, read::Read, write::Write,
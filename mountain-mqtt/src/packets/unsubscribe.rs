@@ -1,18 +1,27 @@
-use super::packet::{Packet, PacketRead, PacketWrite};
+use super::packet::Packet;
+#[cfg(feature = "server-codec")]
+use super::packet::PacketRead;
+#[cfg(feature = "client-codec")]
+use super::packet::PacketWrite;
+#[cfg(feature = "client-codec")]
+use crate::codec::mqtt_writer::{self, MqttWriter};
 use crate::data::{
     packet_identifier::PacketIdentifier, packet_type::PacketType, property::UnsubscribeProperty,
 };
+#[cfg(feature = "server-codec")]
 use crate::{
-    codec::{
-        mqtt_reader::{self, MqttReader},
-        mqtt_writer::{self, MqttWriter},
-    },
+    codec::mqtt_reader::{self, MqttReader},
     error::PacketReadError,
 };
+use crate::packets::{PROPERTIES_DEFAULT, SUBSCRIPTION_REQUESTS_DEFAULT};
 use heapless::Vec;
 
 #[derive(Debug, PartialEq)]
-pub struct Unsubscribe<'a, const P: usize, const S: usize> {
+pub struct Unsubscribe<
+    'a,
+    const P: usize = PROPERTIES_DEFAULT,
+    const S: usize = SUBSCRIPTION_REQUESTS_DEFAULT,
+> {
     packet_identifier: PacketIdentifier,
     first_request: &'a str,
     other_requests: Vec<&'a str, S>,
@@ -39,6 +48,11 @@ impl<'a, const P: usize, const S: usize>
     pub fn packet_identifier(&self) -> &PacketIdentifier {
         &self.packet_identifier
     }
+
+    /// Iterate over all topic names (filters) to unsubscribe from, in order
+    pub fn topic_names(&self) -> impl Iterator<Item = &'a str> + '_ {
+        core::iter::once(self.first_request).chain(self.other_requests.iter().copied())
+    }
 }
 
 impl<const P: usize, const S: usize> Packet
@@ -49,6 +63,7 @@ impl<const P: usize, const S: usize> Packet
     }
 }
 
+#[cfg(feature = "client-codec")]
 impl<const P: usize, const S: usize> PacketWrite
     for Unsubscribe<'_, P, S>
 {
@@ -71,6 +86,7 @@ impl<const P: usize, const S: usize> PacketWrite
     }
 }
 
+#[cfg(feature = "server-codec")]
 impl<'a, const P: usize, const S: usize> PacketRead<'a>
     for Unsubscribe<'a, P, S>
 {
@@ -113,7 +129,7 @@ impl<'a, const P: usize, const S: usize> PacketRead<'a>
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "client-codec", feature = "server-codec"))]
 mod tests {
     use crate::{
         codec::{mqtt_reader::MqttBufReader, mqtt_writer::MqttBufWriter, read::Read, write::Write},
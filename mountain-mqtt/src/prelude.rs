@@ -0,0 +1,47 @@
+//! The traits and types needed for most uses of the crate, so that they can
+//! be imported with `use mountain_mqtt::prelude::*;`
+
+pub use crate::{
+    client::{
+        Client, ClientError, ClientNoQueueOptions, ClientReceivedEvent, ConnectionSettings, Delay,
+        EventHandler, EventHandlerError,
+    },
+    codec::{
+        mqtt_reader::{MqttBufReader, MqttReader},
+        mqtt_writer::{MqttBufWriter, MqttWriter},
+        read::Read,
+        write::Write,
+    },
+    data::{
+        quality_of_service::QualityOfService,
+        reason_code::{DisconnectReasonCode, SubscribeReasonCode},
+    },
+    error::{PacketReadError, PacketWriteError, TransportErrorKind},
+    packet_client::{Connection, PacketClient},
+    packets::{
+        connect::{Connect, Will},
+        packet::{Packet, PacketWrite},
+        packet_generic::{PacketGeneric, PacketGenericDefault},
+        publish::{ApplicationMessage, Publish},
+        server_packet::{ServerPacket, ServerPacketDefault},
+        PROPERTIES_DEFAULT, SUBSCRIPTION_REQUESTS_DEFAULT, WILL_PROPERTIES_DEFAULT,
+    },
+};
+
+#[cfg(feature = "alloc")]
+pub use crate::packets::publish::{OwnedApplicationMessage, OwnedPublish};
+
+#[cfg(feature = "client-codec")]
+pub use crate::{client::ClientNoQueue, managed_client::ManagedClient};
+
+#[cfg(feature = "tokio")]
+pub use crate::tokio::{ConnectionTcpStream, TokioDelay};
+
+#[cfg(all(feature = "tokio", feature = "client-codec"))]
+pub use crate::tokio::client_tcp;
+
+#[cfg(feature = "embedded-io-async")]
+pub use crate::embedded_io_async::ConnectionEmbedded;
+
+#[cfg(feature = "embedded-hal-async")]
+pub use crate::embedded_hal_async::DelayEmbedded;
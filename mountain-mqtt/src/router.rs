@@ -0,0 +1,206 @@
+use heapless::Vec;
+
+use crate::{
+    client::{ClientReceivedEvent, EventHandler, EventHandlerError},
+    data::topic_filter::topic_filter_matches,
+    packets::publish::ApplicationMessage,
+};
+
+/// A handler for application messages routed by a [Router]
+pub type RouteHandler<'a, const P: usize> =
+    dyn FnMut(&ApplicationMessage<'_, P>) -> Result<(), EventHandlerError> + 'a;
+
+/// A topic filter, and the handler for application messages with topic
+/// names matching that filter
+pub struct Route<'a, const P: usize> {
+    topic_filter: &'a str,
+    handler: &'a mut RouteHandler<'a, P>,
+}
+
+impl<'a, const P: usize> Route<'a, P> {
+    pub fn new(topic_filter: &'a str, handler: &'a mut RouteHandler<'a, P>) -> Self {
+        Self {
+            topic_filter,
+            handler,
+        }
+    }
+
+    pub fn topic_filter(&self) -> &'a str {
+        self.topic_filter
+    }
+}
+
+/// An [EventHandler] dispatching each received application message to the
+/// handler of the first of up to `H` [Route]s with a topic filter matching
+/// the message's topic name, in the order the routes were added.
+/// Topic filters are matched using the MQTT wildcard rules, see
+/// [topic_filter_matches].
+///
+/// By default, an application message that doesn't match any route produces
+/// [EventHandlerError::UnexpectedApplicationMessageTopic]. Events other than
+/// application messages are ignored - to handle these, implement an
+/// [EventHandler] that uses [Router::route] for application messages.
+pub struct Router<'a, const P: usize, const H: usize> {
+    routes: Vec<Route<'a, P>, H>,
+    ignore_unmatched: bool,
+}
+
+impl<'a, const P: usize, const H: usize> Router<'a, P, H> {
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            ignore_unmatched: false,
+        }
+    }
+
+    /// Set whether application messages that don't match any route are
+    /// ignored, rather than producing an error. Disabled by default.
+    pub fn with_ignore_unmatched(mut self, ignore_unmatched: bool) -> Self {
+        self.ignore_unmatched = ignore_unmatched;
+        self
+    }
+
+    /// Add a route for application messages with topic names matching `topic_filter`.
+    /// If the router already has `H` routes, the route is returned as an error.
+    pub fn add_route(
+        &mut self,
+        topic_filter: &'a str,
+        handler: &'a mut RouteHandler<'a, P>,
+    ) -> Result<(), Route<'a, P>> {
+        self.routes.push(Route::new(topic_filter, handler))
+    }
+
+    pub fn routes(&self) -> &[Route<'a, P>] {
+        &self.routes
+    }
+
+    /// Dispatch an application message to the first matching route.
+    /// On success, returns true if a route matched, false otherwise.
+    pub fn route(
+        &mut self,
+        message: &ApplicationMessage<'_, P>,
+    ) -> Result<bool, EventHandlerError> {
+        match self
+            .routes
+            .iter_mut()
+            .find(|route| topic_filter_matches(route.topic_filter, message.topic_name))
+        {
+            Some(route) => {
+                (route.handler)(message)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+impl<const P: usize, const H: usize> Default for Router<'_, P, H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const P: usize, const H: usize> EventHandler<P> for Router<'_, P, H> {
+    async fn handle_event(
+        &mut self,
+        event: ClientReceivedEvent<'_, P>,
+    ) -> Result<(), EventHandlerError> {
+        if let ClientReceivedEvent::ApplicationMessage(message) = event {
+            if !self.route(&message)? && !self.ignore_unmatched {
+                return Err(EventHandlerError::UnexpectedApplicationMessageTopic);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client_state::Acknowledgement, data::quality_of_service::QualityOfService};
+
+    fn message(topic_name: &str) -> ClientReceivedEvent<'_, 0> {
+        ClientReceivedEvent::ApplicationMessage(ApplicationMessage {
+            topic_name,
+            payload: &[],
+            qos: QualityOfService::Qos0,
+            retain: false,
+            properties: Vec::new(),
+            encoded_properties: &[],
+        })
+    }
+
+    #[tokio::test]
+    async fn messages_are_routed_to_first_matching_handler() {
+        let mut temperatures = 0;
+        let mut sensors = 0;
+        let mut commands = 0;
+        let mut temperature_handler = |_: &ApplicationMessage<'_, 0>| {
+            temperatures += 1;
+            Ok(())
+        };
+        let mut sensor_handler = |_: &ApplicationMessage<'_, 0>| {
+            sensors += 1;
+            Ok(())
+        };
+        let mut command_handler = |message: &ApplicationMessage<'_, 0>| {
+            if message.topic_name.ends_with("invalid") {
+                Err(EventHandlerError::InvalidApplicationMessage)
+            } else {
+                commands += 1;
+                Ok(())
+            }
+        };
+
+        {
+            let mut router = Router::<'_, 0, 3>::new();
+            assert!(router
+                .add_route("sensors/temperature", &mut temperature_handler)
+                .is_ok());
+            assert!(router.add_route("sensors/#", &mut sensor_handler).is_ok());
+            assert!(router.add_route("commands/+", &mut command_handler).is_ok());
+
+            router
+                .handle_event(message("sensors/temperature"))
+                .await
+                .unwrap();
+            router
+                .handle_event(message("sensors/humidity"))
+                .await
+                .unwrap();
+            router
+                .handle_event(message("commands/reset"))
+                .await
+                .unwrap();
+            router
+                .handle_event(ClientReceivedEvent::Ack(Acknowledgement::Pingresp))
+                .await
+                .unwrap();
+            assert_eq!(
+                router.handle_event(message("commands/invalid")).await,
+                Err(EventHandlerError::InvalidApplicationMessage)
+            );
+            assert_eq!(
+                router.handle_event(message("other")).await,
+                Err(EventHandlerError::UnexpectedApplicationMessageTopic)
+            );
+
+            let mut router = router.with_ignore_unmatched(true);
+            router.handle_event(message("other")).await.unwrap();
+        }
+
+        assert_eq!(temperatures, 1);
+        assert_eq!(sensors, 1);
+        assert_eq!(commands, 1);
+    }
+
+    #[test]
+    fn full_router_returns_route() {
+        let mut handler = |_: &ApplicationMessage<'_, 0>| Ok(());
+        let mut other_handler = |_: &ApplicationMessage<'_, 0>| Ok(());
+        let mut router = Router::<'_, 0, 1>::new();
+        assert!(router.add_route("a", &mut handler).is_ok());
+        let route = router.add_route("b", &mut other_handler).err().unwrap();
+        assert_eq!(route.topic_filter(), "b");
+    }
+}
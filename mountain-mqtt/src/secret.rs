@@ -0,0 +1,97 @@
+//! Wiping of credentials (e.g. passwords and authentication tokens) from memory
+//! after use, enabled by the `zeroize` feature, using the
+//! [zeroize](https://crates.io/crates/zeroize) crate.
+//!
+//! With this feature, [PacketClient](crate::packet_client::PacketClient) wipes its
+//! buffer after sending a Connect or Auth packet, since the encoded packet may
+//! contain a password or authentication data. Credentials passed to
+//! [ConnectionSettings](crate::client::ConnectionSettings) or
+//! [Connect](crate::packets::connect::Connect) are borrowed, so can be held in a
+//! [SecretBuf] that is wiped when dropped, or explicitly with [SecretBuf::clear].
+
+use heapless::Vec;
+use zeroize::Zeroize;
+
+/// A buffer of up to `N` bytes holding a secret, e.g. a password or token,
+/// which is wiped when cleared or dropped.
+pub struct SecretBuf<const N: usize> {
+    data: Vec<u8, N>,
+}
+
+impl<const N: usize> SecretBuf<N> {
+    pub fn new() -> Self {
+        Self { data: Vec::new() }
+    }
+
+    /// Create a buffer holding a copy of `secret`, or None if it is longer than `N`
+    pub fn from_slice(secret: &[u8]) -> Option<Self> {
+        let mut buf = Self::new();
+        buf.data.extend_from_slice(secret).ok()?;
+        Some(buf)
+    }
+
+    /// Create a buffer holding a copy of a string `secret`, or None if it is longer than `N`
+    pub fn from_string(secret: &str) -> Option<Self> {
+        Self::from_slice(secret.as_bytes())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The secret as a string, or None if it is not valid utf8
+    pub fn as_str(&self) -> Option<&str> {
+        core::str::from_utf8(&self.data).ok()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Wipe the secret, leaving the buffer empty
+    pub fn clear(&mut self) {
+        self.data.as_mut_slice().zeroize();
+        self.data.clear();
+    }
+}
+
+impl<const N: usize> Default for SecretBuf<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Zeroize for SecretBuf<N> {
+    fn zeroize(&mut self) {
+        self.clear();
+    }
+}
+
+impl<const N: usize> zeroize::ZeroizeOnDrop for SecretBuf<N> {}
+
+impl<const N: usize> Drop for SecretBuf<N> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<const N: usize> core::fmt::Debug for SecretBuf<N> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "SecretBuf({} bytes)", self.data.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_is_cleared_and_not_shown_in_debug() {
+        let mut secret = SecretBuf::<16>::from_string("password").unwrap();
+        assert_eq!(secret.as_str(), Some("password"));
+        assert_eq!(format!("{:?}", secret), "SecretBuf(8 bytes)");
+        secret.clear();
+        assert!(secret.is_empty());
+        assert!(SecretBuf::<4>::from_string("password").is_none());
+    }
+}
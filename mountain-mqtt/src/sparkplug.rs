@@ -0,0 +1,878 @@
+//! Support for the Sparkplug B specification, used to integrate edge nodes
+//! with industrial SCADA infrastructure:
+//!
+//! - Topic names in the `spBv1.0` namespace, see [sparkplug_topic]
+//! - Birth and death sequence numbers, see [SparkplugSequence]
+//! - Encoding and decoding of the protobuf payload with scalar metrics,
+//!   see [PayloadWriter] and [PayloadReader]
+//! - The node death certificate sent as the Will, see [node_death_payload]
+//!   and [node_death_will]
+//!
+//! For each connection, an edge node calls [SparkplugSequence::start_session]
+//! to get the `bdSeq` for the session, connects with a Will containing a node
+//! death certificate with that `bdSeq`, then publishes a node birth certificate
+//! with the same `bdSeq` metric and a sequence number of 0, followed by data
+//! messages using [SparkplugSequence::next_seq].
+
+#[cfg(feature = "display")]
+use core::fmt::{Display, Formatter};
+
+use heapless::Vec;
+
+use crate::{
+    data::{
+        quality_of_service::QualityOfService,
+        topic_buf::{TopicBuf, TopicBufError},
+    },
+    packets::connect::Will,
+};
+
+/// The first level of all Sparkplug B topic names
+pub const SPARKPLUG_B_NAMESPACE: &str = "spBv1.0";
+
+/// The metric containing the birth/death sequence number, in node birth and
+/// death certificates
+pub const BD_SEQ_METRIC: &str = "bdSeq";
+
+/// The metric that a host application sets to true in a node command, to
+/// request that the edge node publishes its birth certificates again
+pub const REBIRTH_METRIC: &str = "Node Control/Rebirth";
+
+/// Errors building Sparkplug B topics or payloads
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SparkplugError {
+    /// A topic name could not be built
+    Topic(TopicBufError),
+
+    /// A device id was given for a node message type, or was missing for
+    /// a device message type
+    InvalidDeviceId,
+
+    /// An encoded payload would not fit in the buffer
+    Overflow,
+
+    /// A payload is not valid protobuf, or does not match the Sparkplug B schema
+    MalformedPayload,
+
+    /// A metric has a data type not supported by [MetricValue], or a null value
+    UnsupportedDataType(u32),
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for SparkplugError {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Topic(e) => defmt::write!(f, "Topic({})", e),
+            Self::InvalidDeviceId => defmt::write!(f, "InvalidDeviceId"),
+            Self::Overflow => defmt::write!(f, "Overflow"),
+            Self::MalformedPayload => defmt::write!(f, "MalformedPayload"),
+            Self::UnsupportedDataType(d) => defmt::write!(f, "UnsupportedDataType({})", d),
+        }
+    }
+}
+
+#[cfg(feature = "display")]
+impl Display for SparkplugError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Topic(e) => write!(f, "Topic({})", e),
+            Self::InvalidDeviceId => write!(f, "InvalidDeviceId"),
+            Self::Overflow => write!(f, "Overflow"),
+            Self::MalformedPayload => write!(f, "MalformedPayload"),
+            Self::UnsupportedDataType(d) => write!(f, "UnsupportedDataType({})", d),
+        }
+    }
+}
+
+impl From<TopicBufError> for SparkplugError {
+    fn from(value: TopicBufError) -> Self {
+        SparkplugError::Topic(value)
+    }
+}
+
+/// The type of a Sparkplug B message, forming the second level of the topic name
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MessageType {
+    NodeBirth,
+    NodeDeath,
+    NodeData,
+    NodeCommand,
+    DeviceBirth,
+    DeviceDeath,
+    DeviceData,
+    DeviceCommand,
+}
+
+impl MessageType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageType::NodeBirth => "NBIRTH",
+            MessageType::NodeDeath => "NDEATH",
+            MessageType::NodeData => "NDATA",
+            MessageType::NodeCommand => "NCMD",
+            MessageType::DeviceBirth => "DBIRTH",
+            MessageType::DeviceDeath => "DDEATH",
+            MessageType::DeviceData => "DDATA",
+            MessageType::DeviceCommand => "DCMD",
+        }
+    }
+
+    /// True for message types relating to a device attached to an edge node,
+    /// which have the device id as the last topic level
+    pub fn is_device(&self) -> bool {
+        matches!(
+            self,
+            MessageType::DeviceBirth
+                | MessageType::DeviceDeath
+                | MessageType::DeviceData
+                | MessageType::DeviceCommand
+        )
+    }
+}
+
+/// Build a Sparkplug B topic name,
+/// `spBv1.0/{group_id}/{message_type}/{edge_node_id}[/{device_id}]`.
+/// A device id must be provided for device message types, and only for
+/// those message types.
+pub fn sparkplug_topic<const N: usize>(
+    group_id: &str,
+    message_type: MessageType,
+    edge_node_id: &str,
+    device_id: Option<&str>,
+) -> Result<TopicBuf<N>, SparkplugError> {
+    if message_type.is_device() != device_id.is_some() {
+        return Err(SparkplugError::InvalidDeviceId);
+    }
+    let mut topic = TopicBuf::new();
+    topic.push_segment(SPARKPLUG_B_NAMESPACE)?;
+    topic.push_segment(group_id)?;
+    topic.push_segment(message_type.as_str())?;
+    topic.push_segment(edge_node_id)?;
+    if let Some(device_id) = device_id {
+        topic.push_segment(device_id)?;
+    }
+    Ok(topic)
+}
+
+/// Build the topic name used by a host application to publish its state,
+/// `spBv1.0/STATE/{host_id}`
+pub fn state_topic<const N: usize>(host_id: &str) -> Result<TopicBuf<N>, SparkplugError> {
+    let mut topic = TopicBuf::new();
+    topic.push_segment(SPARKPLUG_B_NAMESPACE)?;
+    topic.push_segment("STATE")?;
+    topic.push_segment(host_id)?;
+    Ok(topic)
+}
+
+/// Tracks the birth/death sequence number (`bdSeq`), which increments for
+/// each connection of an edge node, and the message sequence number (`seq`),
+/// which is 0 for the node birth certificate then increments for each message.
+/// Both are in the range 0 to 255, wrapping back to 0.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SparkplugSequence {
+    bd_seq: u8,
+    next_bd_seq: u8,
+    next_seq: u8,
+}
+
+impl SparkplugSequence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the `bdSeq` for the next session, e.g. if this has been persisted
+    /// across restarts of the edge node
+    pub fn with_next_bd_seq(mut self, next_bd_seq: u8) -> Self {
+        self.next_bd_seq = next_bd_seq;
+        self
+    }
+
+    /// Start a new session, before connecting. Returns the `bdSeq` to use in
+    /// the node death certificate and node birth certificate for the session,
+    /// and resets `seq` so that the next message (the node birth certificate)
+    /// has sequence number 0.
+    pub fn start_session(&mut self) -> u64 {
+        self.bd_seq = self.next_bd_seq;
+        self.next_bd_seq = self.next_bd_seq.wrapping_add(1);
+        self.next_seq = 0;
+        self.bd_seq as u64
+    }
+
+    /// The `bdSeq` of the current session
+    pub fn bd_seq(&self) -> u64 {
+        self.bd_seq as u64
+    }
+
+    /// The `bdSeq` that will be used for the next session
+    pub fn next_bd_seq(&self) -> u64 {
+        self.next_bd_seq as u64
+    }
+
+    /// Produce the sequence number for the next birth or data message
+    pub fn next_seq(&mut self) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        seq as u64
+    }
+}
+
+/// The value of a metric, with the Sparkplug B data type implied by the variant
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MetricValue<'a> {
+    Int8(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    UInt8(u8),
+    UInt16(u16),
+    UInt32(u32),
+    UInt64(u64),
+    Float(f32),
+    Double(f64),
+    Boolean(bool),
+    String(&'a str),
+}
+
+const DATA_TYPE_INT8: u32 = 1;
+const DATA_TYPE_INT16: u32 = 2;
+const DATA_TYPE_INT32: u32 = 3;
+const DATA_TYPE_INT64: u32 = 4;
+const DATA_TYPE_UINT8: u32 = 5;
+const DATA_TYPE_UINT16: u32 = 6;
+const DATA_TYPE_UINT32: u32 = 7;
+const DATA_TYPE_UINT64: u32 = 8;
+const DATA_TYPE_FLOAT: u32 = 9;
+const DATA_TYPE_DOUBLE: u32 = 10;
+const DATA_TYPE_BOOLEAN: u32 = 11;
+const DATA_TYPE_STRING: u32 = 12;
+
+impl MetricValue<'_> {
+    /// The Sparkplug B data type number
+    pub fn data_type(&self) -> u32 {
+        match self {
+            MetricValue::Int8(_) => DATA_TYPE_INT8,
+            MetricValue::Int16(_) => DATA_TYPE_INT16,
+            MetricValue::Int32(_) => DATA_TYPE_INT32,
+            MetricValue::Int64(_) => DATA_TYPE_INT64,
+            MetricValue::UInt8(_) => DATA_TYPE_UINT8,
+            MetricValue::UInt16(_) => DATA_TYPE_UINT16,
+            MetricValue::UInt32(_) => DATA_TYPE_UINT32,
+            MetricValue::UInt64(_) => DATA_TYPE_UINT64,
+            MetricValue::Float(_) => DATA_TYPE_FLOAT,
+            MetricValue::Double(_) => DATA_TYPE_DOUBLE,
+            MetricValue::Boolean(_) => DATA_TYPE_BOOLEAN,
+            MetricValue::String(_) => DATA_TYPE_STRING,
+        }
+    }
+}
+
+/// A metric in a Sparkplug B payload. After a metric has been sent in a birth
+/// certificate with both a name and an alias, later messages may use just the alias.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Metric<'a> {
+    pub name: Option<&'a str>,
+    pub alias: Option<u64>,
+    pub timestamp: Option<u64>,
+    pub value: MetricValue<'a>,
+}
+
+impl<'a> Metric<'a> {
+    pub fn new(name: &'a str, value: MetricValue<'a>) -> Self {
+        Self {
+            name: Some(name),
+            alias: None,
+            timestamp: None,
+            value,
+        }
+    }
+
+    pub fn with_alias(mut self, alias: u64) -> Self {
+        self.alias = Some(alias);
+        self
+    }
+
+    /// Set the timestamp, in milliseconds since the Unix epoch
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// True if this is a node command metric requesting a rebirth
+    pub fn is_rebirth_request(&self) -> bool {
+        self.name == Some(REBIRTH_METRIC) && self.value == MetricValue::Boolean(true)
+    }
+}
+
+// Protobuf field numbers from the Sparkplug B schema
+const PAYLOAD_TIMESTAMP: u32 = 1;
+const PAYLOAD_METRICS: u32 = 2;
+const PAYLOAD_SEQ: u32 = 3;
+
+const METRIC_NAME: u32 = 1;
+const METRIC_ALIAS: u32 = 2;
+const METRIC_TIMESTAMP: u32 = 3;
+const METRIC_DATATYPE: u32 = 4;
+const METRIC_IS_NULL: u32 = 7;
+const METRIC_INT_VALUE: u32 = 10;
+const METRIC_LONG_VALUE: u32 = 11;
+const METRIC_FLOAT_VALUE: u32 = 12;
+const METRIC_DOUBLE_VALUE: u32 = 13;
+const METRIC_BOOLEAN_VALUE: u32 = 14;
+const METRIC_STRING_VALUE: u32 = 15;
+
+// Protobuf wire types
+const WIRE_VARINT: u32 = 0;
+const WIRE_FIXED64: u32 = 1;
+const WIRE_LEN: u32 = 2;
+const WIRE_FIXED32: u32 = 5;
+
+/// Destination for encoded protobuf data, allowing the length of a metric
+/// to be calculated before writing it
+trait ProtoSink {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), SparkplugError>;
+
+    fn put_varint(&mut self, mut value: u64) -> Result<(), SparkplugError> {
+        while value >= 0x80 {
+            self.put(&[(value as u8) | 0x80])?;
+            value >>= 7;
+        }
+        self.put(&[value as u8])
+    }
+
+    fn put_tag(&mut self, field: u32, wire_type: u32) -> Result<(), SparkplugError> {
+        self.put_varint(((field << 3) | wire_type) as u64)
+    }
+
+    fn put_varint_field(&mut self, field: u32, value: u64) -> Result<(), SparkplugError> {
+        self.put_tag(field, WIRE_VARINT)?;
+        self.put_varint(value)
+    }
+
+    fn put_len_field(&mut self, field: u32, data: &[u8]) -> Result<(), SparkplugError> {
+        self.put_tag(field, WIRE_LEN)?;
+        self.put_varint(data.len() as u64)?;
+        self.put(data)
+    }
+}
+
+struct LenCounter(usize);
+
+impl ProtoSink for LenCounter {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), SparkplugError> {
+        self.0 += bytes.len();
+        Ok(())
+    }
+}
+
+fn put_metric_fields<S: ProtoSink>(
+    sink: &mut S,
+    metric: &Metric<'_>,
+) -> Result<(), SparkplugError> {
+    if let Some(name) = metric.name {
+        sink.put_len_field(METRIC_NAME, name.as_bytes())?;
+    }
+    if let Some(alias) = metric.alias {
+        sink.put_varint_field(METRIC_ALIAS, alias)?;
+    }
+    if let Some(timestamp) = metric.timestamp {
+        sink.put_varint_field(METRIC_TIMESTAMP, timestamp)?;
+    }
+    sink.put_varint_field(METRIC_DATATYPE, metric.value.data_type() as u64)?;
+
+    // Signed integers are sign extended to the width of the field
+    match metric.value {
+        MetricValue::Int8(v) => sink.put_varint_field(METRIC_INT_VALUE, v as i32 as u32 as u64),
+        MetricValue::Int16(v) => sink.put_varint_field(METRIC_INT_VALUE, v as i32 as u32 as u64),
+        MetricValue::Int32(v) => sink.put_varint_field(METRIC_INT_VALUE, v as u32 as u64),
+        MetricValue::Int64(v) => sink.put_varint_field(METRIC_LONG_VALUE, v as u64),
+        MetricValue::UInt8(v) => sink.put_varint_field(METRIC_INT_VALUE, v as u64),
+        MetricValue::UInt16(v) => sink.put_varint_field(METRIC_INT_VALUE, v as u64),
+        MetricValue::UInt32(v) => sink.put_varint_field(METRIC_INT_VALUE, v as u64),
+        MetricValue::UInt64(v) => sink.put_varint_field(METRIC_LONG_VALUE, v),
+        MetricValue::Float(v) => {
+            sink.put_tag(METRIC_FLOAT_VALUE, WIRE_FIXED32)?;
+            sink.put(&v.to_le_bytes())
+        }
+        MetricValue::Double(v) => {
+            sink.put_tag(METRIC_DOUBLE_VALUE, WIRE_FIXED64)?;
+            sink.put(&v.to_le_bytes())
+        }
+        MetricValue::Boolean(v) => sink.put_varint_field(METRIC_BOOLEAN_VALUE, v as u64),
+        MetricValue::String(v) => sink.put_len_field(METRIC_STRING_VALUE, v.as_bytes()),
+    }
+}
+
+/// Encodes a Sparkplug B payload into a buffer. If an error is returned,
+/// the payload is left unchanged.
+pub struct PayloadWriter<'a> {
+    buf: &'a mut [u8],
+    position: usize,
+}
+
+impl ProtoSink for PayloadWriter<'_> {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), SparkplugError> {
+        let end = self.position + bytes.len();
+        if end > self.buf.len() {
+            return Err(SparkplugError::Overflow);
+        }
+        self.buf[self.position..end].copy_from_slice(bytes);
+        self.position = end;
+        Ok(())
+    }
+}
+
+impl<'a> PayloadWriter<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, position: 0 }
+    }
+
+    fn restoring_on_error(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<(), SparkplugError>,
+    ) -> Result<(), SparkplugError> {
+        let position = self.position;
+        let result = f(self);
+        if result.is_err() {
+            self.position = position;
+        }
+        result
+    }
+
+    /// Add the payload timestamp, in milliseconds since the Unix epoch
+    pub fn put_timestamp(&mut self, timestamp: u64) -> Result<(), SparkplugError> {
+        self.restoring_on_error(|w| w.put_varint_field(PAYLOAD_TIMESTAMP, timestamp))
+    }
+
+    /// Add the sequence number, see [SparkplugSequence::next_seq]
+    pub fn put_seq(&mut self, seq: u64) -> Result<(), SparkplugError> {
+        self.restoring_on_error(|w| w.put_varint_field(PAYLOAD_SEQ, seq))
+    }
+
+    pub fn put_metric(&mut self, metric: &Metric<'_>) -> Result<(), SparkplugError> {
+        let mut len = LenCounter(0);
+        put_metric_fields(&mut len, metric)?;
+        self.restoring_on_error(|w| {
+            w.put_tag(PAYLOAD_METRICS, WIRE_LEN)?;
+            w.put_varint(len.0 as u64)?;
+            put_metric_fields(w, metric)
+        })
+    }
+
+    /// The encoded payload
+    pub fn finish(self) -> &'a [u8] {
+        &self.buf[0..self.position]
+    }
+}
+
+/// Encode a node death certificate payload, containing just the [BD_SEQ_METRIC]
+/// metric for the session
+pub fn node_death_payload(buf: &mut [u8], bd_seq: u64) -> Result<&[u8], SparkplugError> {
+    let mut writer = PayloadWriter::new(buf);
+    writer.put_metric(&Metric::new(BD_SEQ_METRIC, MetricValue::UInt64(bd_seq)))?;
+    Ok(writer.finish())
+}
+
+/// Produce the Will for an edge node, publishing the node death certificate
+/// at [QualityOfService::Qos1] without retain, as required by Sparkplug B.
+/// `topic_name` should be the [MessageType::NodeDeath] topic, and `payload`
+/// from [node_death_payload].
+pub fn node_death_will<'a, const W: usize>(topic_name: &'a str, payload: &'a [u8]) -> Will<'a, W> {
+    Will::new(
+        QualityOfService::Qos1,
+        false,
+        topic_name,
+        payload,
+        Vec::new(),
+    )
+}
+
+/// Reads protobuf data
+struct ProtoReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ProtoReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn get(&mut self, len: usize) -> Result<&'a [u8], SparkplugError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or(SparkplugError::MalformedPayload)?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn get_varint(&mut self) -> Result<u64, SparkplugError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.get(1)?[0];
+            value |= ((b & 0x7F) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(SparkplugError::MalformedPayload)
+    }
+
+    fn get_tag(&mut self) -> Result<(u32, u32), SparkplugError> {
+        let tag = self.get_varint()?;
+        let tag = u32::try_from(tag).map_err(|_| SparkplugError::MalformedPayload)?;
+        Ok((tag >> 3, tag & 0x07))
+    }
+
+    fn get_len_delimited(&mut self) -> Result<&'a [u8], SparkplugError> {
+        let len = self.get_varint()?;
+        let len = usize::try_from(len).map_err(|_| SparkplugError::MalformedPayload)?;
+        self.get(len)
+    }
+
+    fn get_fixed<const N: usize>(&mut self) -> Result<[u8; N], SparkplugError> {
+        let mut bytes = [0u8; N];
+        bytes.copy_from_slice(self.get(N)?);
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, wire_type: u32) -> Result<(), SparkplugError> {
+        match wire_type {
+            WIRE_VARINT => self.get_varint().map(|_| ()),
+            WIRE_FIXED64 => self.get(8).map(|_| ()),
+            WIRE_LEN => self.get_len_delimited().map(|_| ()),
+            WIRE_FIXED32 => self.get(4).map(|_| ()),
+            _ => Err(SparkplugError::MalformedPayload),
+        }
+    }
+}
+
+fn get_str(bytes: &[u8]) -> Result<&str, SparkplugError> {
+    core::str::from_utf8(bytes).map_err(|_| SparkplugError::MalformedPayload)
+}
+
+fn expect_wire_type(wire_type: u32, expected: u32) -> Result<(), SparkplugError> {
+    if wire_type == expected {
+        Ok(())
+    } else {
+        Err(SparkplugError::MalformedPayload)
+    }
+}
+
+/// The value fields of a metric, before interpreting using the data type
+#[derive(Default)]
+struct RawValue<'a> {
+    int_value: Option<u64>,
+    float_value: Option<f32>,
+    double_value: Option<f64>,
+    string_value: Option<&'a str>,
+}
+
+fn read_metric(data: &[u8]) -> Result<Metric<'_>, SparkplugError> {
+    let mut reader = ProtoReader::new(data);
+    let mut name = None;
+    let mut alias = None;
+    let mut timestamp = None;
+    let mut data_type = None;
+    let mut is_null = false;
+    let mut raw = RawValue::default();
+
+    while !reader.is_empty() {
+        let (field, wire_type) = reader.get_tag()?;
+        match field {
+            METRIC_NAME => {
+                expect_wire_type(wire_type, WIRE_LEN)?;
+                name = Some(get_str(reader.get_len_delimited()?)?);
+            }
+            METRIC_ALIAS | METRIC_TIMESTAMP | METRIC_DATATYPE | METRIC_IS_NULL
+            | METRIC_INT_VALUE | METRIC_LONG_VALUE | METRIC_BOOLEAN_VALUE => {
+                expect_wire_type(wire_type, WIRE_VARINT)?;
+                let value = reader.get_varint()?;
+                match field {
+                    METRIC_ALIAS => alias = Some(value),
+                    METRIC_TIMESTAMP => timestamp = Some(value),
+                    METRIC_DATATYPE => data_type = Some(value as u32),
+                    METRIC_IS_NULL => is_null = value != 0,
+                    _ => raw.int_value = Some(value),
+                }
+            }
+            METRIC_FLOAT_VALUE => {
+                expect_wire_type(wire_type, WIRE_FIXED32)?;
+                raw.float_value = Some(f32::from_le_bytes(reader.get_fixed()?));
+            }
+            METRIC_DOUBLE_VALUE => {
+                expect_wire_type(wire_type, WIRE_FIXED64)?;
+                raw.double_value = Some(f64::from_le_bytes(reader.get_fixed()?));
+            }
+            METRIC_STRING_VALUE => {
+                expect_wire_type(wire_type, WIRE_LEN)?;
+                raw.string_value = Some(get_str(reader.get_len_delimited()?)?);
+            }
+            _ => reader.skip(wire_type)?,
+        }
+    }
+
+    let data_type = data_type.ok_or(SparkplugError::MalformedPayload)?;
+    if is_null {
+        return Err(SparkplugError::UnsupportedDataType(data_type));
+    }
+
+    // Signed integers are truncated, so values that were either sign extended
+    // or zero extended are decoded correctly
+    let int = || raw.int_value.ok_or(SparkplugError::MalformedPayload);
+    let value = match data_type {
+        DATA_TYPE_INT8 => MetricValue::Int8(int()? as i8),
+        DATA_TYPE_INT16 => MetricValue::Int16(int()? as i16),
+        DATA_TYPE_INT32 => MetricValue::Int32(int()? as i32),
+        DATA_TYPE_INT64 => MetricValue::Int64(int()? as i64),
+        DATA_TYPE_UINT8 => MetricValue::UInt8(int()? as u8),
+        DATA_TYPE_UINT16 => MetricValue::UInt16(int()? as u16),
+        DATA_TYPE_UINT32 => MetricValue::UInt32(int()? as u32),
+        DATA_TYPE_UINT64 => MetricValue::UInt64(int()?),
+        DATA_TYPE_FLOAT => {
+            MetricValue::Float(raw.float_value.ok_or(SparkplugError::MalformedPayload)?)
+        }
+        DATA_TYPE_DOUBLE => {
+            MetricValue::Double(raw.double_value.ok_or(SparkplugError::MalformedPayload)?)
+        }
+        DATA_TYPE_BOOLEAN => MetricValue::Boolean(int()? != 0),
+        DATA_TYPE_STRING => {
+            MetricValue::String(raw.string_value.ok_or(SparkplugError::MalformedPayload)?)
+        }
+        _ => return Err(SparkplugError::UnsupportedDataType(data_type)),
+    };
+
+    Ok(Metric {
+        name,
+        alias,
+        timestamp,
+        value,
+    })
+}
+
+/// Decodes a Sparkplug B payload, e.g. from a node command. The timestamp
+/// and sequence number are read immediately, and metrics are decoded as they
+/// are iterated using [PayloadReader::metrics]. Fields not supported by
+/// [Metric] (e.g. metadata and properties) are skipped.
+pub struct PayloadReader<'a> {
+    data: &'a [u8],
+    timestamp: Option<u64>,
+    seq: Option<u64>,
+}
+
+impl<'a> PayloadReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, SparkplugError> {
+        let mut reader = ProtoReader::new(data);
+        let mut timestamp = None;
+        let mut seq = None;
+        while !reader.is_empty() {
+            let (field, wire_type) = reader.get_tag()?;
+            match field {
+                PAYLOAD_TIMESTAMP => {
+                    expect_wire_type(wire_type, WIRE_VARINT)?;
+                    timestamp = Some(reader.get_varint()?);
+                }
+                PAYLOAD_SEQ => {
+                    expect_wire_type(wire_type, WIRE_VARINT)?;
+                    seq = Some(reader.get_varint()?);
+                }
+                _ => reader.skip(wire_type)?,
+            }
+        }
+        Ok(Self {
+            data,
+            timestamp,
+            seq,
+        })
+    }
+
+    /// The payload timestamp, in milliseconds since the Unix epoch
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    pub fn seq(&self) -> Option<u64> {
+        self.seq
+    }
+
+    /// Iterate the metrics in the payload, in order
+    pub fn metrics(&self) -> Metrics<'a> {
+        Metrics {
+            reader: ProtoReader::new(self.data),
+        }
+    }
+}
+
+/// Iterator over the metrics of a [PayloadReader]
+pub struct Metrics<'a> {
+    reader: ProtoReader<'a>,
+}
+
+impl<'a> Iterator for Metrics<'a> {
+    type Item = Result<Metric<'a>, SparkplugError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Structure of the payload was checked by PayloadReader::new,
+        // so only metrics need to be handled here
+        while !self.reader.is_empty() {
+            let (field, wire_type) = self.reader.get_tag().ok()?;
+            if field == PAYLOAD_METRICS && wire_type == WIRE_LEN {
+                return Some(self.reader.get_len_delimited().and_then(read_metric));
+            }
+            self.reader.skip(wire_type).ok()?;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topics_are_built_in_namespace() {
+        let topic: TopicBuf<64> =
+            sparkplug_topic("plant1", MessageType::NodeBirth, "edge1", None).unwrap();
+        assert_eq!(topic.as_str(), "spBv1.0/plant1/NBIRTH/edge1");
+
+        let topic: TopicBuf<64> =
+            sparkplug_topic("plant1", MessageType::DeviceData, "edge1", Some("pump")).unwrap();
+        assert_eq!(topic.as_str(), "spBv1.0/plant1/DDATA/edge1/pump");
+
+        let topic: TopicBuf<64> = state_topic("scada").unwrap();
+        assert_eq!(topic.as_str(), "spBv1.0/STATE/scada");
+
+        assert_eq!(
+            sparkplug_topic::<64>("plant1", MessageType::DeviceData, "edge1", None),
+            Err(SparkplugError::InvalidDeviceId)
+        );
+        assert_eq!(
+            sparkplug_topic::<64>("plant1", MessageType::NodeData, "edge1", Some("pump")),
+            Err(SparkplugError::InvalidDeviceId)
+        );
+        assert_eq!(
+            sparkplug_topic::<64>("plant/1", MessageType::NodeData, "edge1", None),
+            Err(SparkplugError::Topic(TopicBufError::SeparatorInSegment))
+        );
+    }
+
+    #[test]
+    fn sequence_numbers_reset_and_wrap() {
+        let mut sequence = SparkplugSequence::new().with_next_bd_seq(255);
+        assert_eq!(sequence.start_session(), 255);
+        assert_eq!(sequence.next_seq(), 0);
+        assert_eq!(sequence.next_seq(), 1);
+
+        assert_eq!(sequence.start_session(), 0);
+        assert_eq!(sequence.bd_seq(), 0);
+        assert_eq!(sequence.next_bd_seq(), 1);
+        assert_eq!(sequence.next_seq(), 0);
+        for _ in 1..256 {
+            sequence.next_seq();
+        }
+        assert_eq!(sequence.next_seq(), 0);
+    }
+
+    #[test]
+    fn node_death_payload_is_encoded() {
+        let mut buf = [0u8; 32];
+        let payload = node_death_payload(&mut buf, 3).unwrap();
+        assert_eq!(
+            payload,
+            [
+                0x12, 0x0B, // metrics, length 11
+                0x0A, 0x05, b'b', b'd', b'S', b'e', b'q', // name
+                0x20, 0x08, // datatype UInt64
+                0x58, 0x03, // long_value
+            ]
+        );
+
+        let will: Will<'_, 0> = node_death_will("spBv1.0/g/NDEATH/e", payload);
+        assert_eq!(
+            will,
+            Will::new(
+                QualityOfService::Qos1,
+                false,
+                "spBv1.0/g/NDEATH/e",
+                payload,
+                Vec::new()
+            )
+        );
+    }
+
+    #[test]
+    fn payload_round_trips() {
+        let metrics = [
+            Metric::new(BD_SEQ_METRIC, MetricValue::UInt64(7)),
+            Metric::new("i8", MetricValue::Int8(-1)).with_alias(1),
+            Metric::new("i16", MetricValue::Int16(-300)),
+            Metric::new("i32", MetricValue::Int32(i32::MIN)),
+            Metric::new("i64", MetricValue::Int64(-5)).with_timestamp(1_700_000_000_000),
+            Metric::new("u8", MetricValue::UInt8(200)),
+            Metric::new("u16", MetricValue::UInt16(60_000)),
+            Metric::new("u32", MetricValue::UInt32(u32::MAX)),
+            Metric::new("f", MetricValue::Float(1.5)),
+            Metric::new("d", MetricValue::Double(-2.25)),
+            Metric::new(REBIRTH_METRIC, MetricValue::Boolean(true)),
+            Metric::new("s", MetricValue::String("running")),
+        ];
+
+        let mut buf = [0u8; 256];
+        let mut writer = PayloadWriter::new(&mut buf);
+        writer.put_timestamp(1_700_000_000_001).unwrap();
+        for metric in metrics.iter() {
+            writer.put_metric(metric).unwrap();
+        }
+        writer.put_seq(42).unwrap();
+        let payload = writer.finish();
+
+        let reader = PayloadReader::new(payload).unwrap();
+        assert_eq!(reader.timestamp(), Some(1_700_000_000_001));
+        assert_eq!(reader.seq(), Some(42));
+        let mut count = 0;
+        for (decoded, metric) in reader.metrics().zip(metrics.iter()) {
+            assert_eq!(decoded, Ok(*metric));
+            count += 1;
+        }
+        assert_eq!(count, metrics.len());
+        assert!(reader
+            .metrics()
+            .any(|metric| metric.is_ok_and(|m| m.is_rebirth_request())));
+    }
+
+    #[test]
+    fn writer_overflow_leaves_payload_unchanged() {
+        let mut buf = [0u8; 8];
+        let mut writer = PayloadWriter::new(&mut buf);
+        writer.put_seq(1).unwrap();
+        assert_eq!(
+            writer.put_metric(&Metric::new("name", MetricValue::Boolean(true))),
+            Err(SparkplugError::Overflow)
+        );
+        assert_eq!(writer.finish(), [0x18, 0x01]);
+    }
+
+    #[test]
+    fn malformed_payloads_are_rejected() {
+        // Truncated length delimited metric
+        assert!(matches!(
+            PayloadReader::new(&[0x12, 0x05, 0x0A]),
+            Err(SparkplugError::MalformedPayload)
+        ));
+
+        // Metric with unsupported data type (DateTime)
+        let payload = [0x12, 0x04, 0x20, 0x0D, 0x58, 0x01];
+        let reader = PayloadReader::new(&payload).unwrap();
+        assert_eq!(
+            reader.metrics().next(),
+            Some(Err(SparkplugError::UnsupportedDataType(13)))
+        );
+    }
+}
@@ -0,0 +1,400 @@
+use heapless::{String, Vec};
+
+use crate::{
+    data::{
+        packet_identifier::PacketIdentifier,
+        quality_of_service::QualityOfService,
+        reason_code::{SubscribeReasonCode, UnsubscribeReasonCode},
+        subscription_options::SubscriptionOptions,
+    },
+    packets::{suback::Suback, subscribe::Subscribe, unsuback::Unsuback, unsubscribe::Unsubscribe},
+};
+
+/// The quality of service granted by a successful [SubscribeReasonCode],
+/// or None for an error
+pub fn granted_qos(reason_code: &SubscribeReasonCode) -> Option<QualityOfService> {
+    match reason_code {
+        SubscribeReasonCode::Success => Some(QualityOfService::Qos0),
+        SubscribeReasonCode::GrantedQos1 => Some(QualityOfService::Qos1),
+        SubscribeReasonCode::GrantedQos2 => Some(QualityOfService::Qos2),
+        _ => None,
+    }
+}
+
+/// A subscription tracked by a [SubscriptionRegistry], with a topic filter
+/// of up to `T` bytes
+#[derive(Debug, PartialEq, Clone)]
+pub struct Subscription<const T: usize> {
+    topic_filter: String<T>,
+    options: SubscriptionOptions,
+
+    /// The reason code from the server's suback, None if the subscription
+    /// has been requested but not yet acknowledged
+    reason_code: Option<SubscribeReasonCode>,
+
+    /// The packet identifier of a subscribe packet for this topic filter that
+    /// has not yet been acknowledged, the index of this topic filter in that
+    /// packet, and the requested options
+    pending_subscribe: Option<(PacketIdentifier, usize, SubscriptionOptions)>,
+
+    /// The packet identifier of an unsubscribe packet including this topic
+    /// filter that has not yet been acknowledged, and the index of this
+    /// topic filter in that packet
+    pending_unsubscribe: Option<(PacketIdentifier, usize)>,
+}
+
+impl<const T: usize> Subscription<T> {
+    pub fn topic_filter(&self) -> &str {
+        &self.topic_filter
+    }
+
+    /// The options requested for the subscription
+    pub fn options(&self) -> &SubscriptionOptions {
+        &self.options
+    }
+
+    /// The reason code the server acknowledged the subscription with,
+    /// or None if it is not yet acknowledged
+    pub fn reason_code(&self) -> Option<SubscribeReasonCode> {
+        self.reason_code
+    }
+
+    /// The quality of service granted by the server, if the subscription
+    /// has been acknowledged
+    pub fn granted_qos(&self) -> Option<QualityOfService> {
+        self.reason_code.as_ref().and_then(granted_qos)
+    }
+
+    /// True if the server has acknowledged the subscription
+    pub fn is_active(&self) -> bool {
+        self.granted_qos().is_some()
+    }
+}
+
+/// Tracks up to `N` subscriptions the client believes are active, from the
+/// subscribe and unsubscribe packets sent, and the suback and unsuback packets
+/// received. Topic filters of up to `T` bytes can be tracked.
+///
+/// Subscribing to a topic filter that is already subscribed replaces the existing
+/// subscription, as for the server. If a subscription can't be tracked, since the
+/// registry is full or the topic filter is too long, it is skipped, and
+/// [SubscriptionRegistry::is_complete] becomes false, so that tracking subscriptions
+/// never prevents the client from subscribing.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SubscriptionRegistry<const N: usize, const T: usize> {
+    subscriptions: Vec<Subscription<T>, N>,
+    complete: bool,
+}
+
+impl<const N: usize, const T: usize> SubscriptionRegistry<N, T> {
+    pub fn new() -> Self {
+        Self {
+            subscriptions: Vec::new(),
+            complete: true,
+        }
+    }
+
+    /// Iterate over the active subscriptions, i.e. those acknowledged by the server
+    pub fn subscriptions(&self) -> impl Iterator<Item = &Subscription<T>> {
+        self.subscriptions.iter().filter(|s| s.is_active())
+    }
+
+    /// The active subscription with exactly the given topic filter, if any.
+    /// Note this compares filters, it doesn't match a topic name against filters.
+    pub fn subscription(&self, topic_filter: &str) -> Option<&Subscription<T>> {
+        self.subscriptions()
+            .find(|s| s.topic_filter.as_str() == topic_filter)
+    }
+
+    /// True if there is an active subscription with exactly the given topic filter
+    pub fn is_subscribed(&self, topic_filter: &str) -> bool {
+        self.subscription(topic_filter).is_some()
+    }
+
+    /// False if any subscription could not be tracked since the registry was full,
+    /// or the topic filter was too long, so that [SubscriptionRegistry::subscriptions]
+    /// may be missing subscriptions
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Remove all subscriptions, e.g. when starting a new session
+    pub fn clear(&mut self) {
+        self.subscriptions.clear();
+        self.complete = true;
+    }
+
+    /// Update the registry when the server accepts a connection. Without a session
+    /// present the server has no subscriptions, so the registry is cleared. With a
+    /// session present, acknowledged subscriptions are kept, and any requests left
+    /// pending by an earlier connection are dropped, since they will not be
+    /// acknowledged on this connection.
+    pub fn connected(&mut self, session_present: bool) {
+        if !session_present {
+            self.clear();
+            return;
+        }
+        self.subscriptions.retain_mut(|subscription| {
+            subscription.pending_subscribe = None;
+            subscription.pending_unsubscribe = None;
+            subscription.is_active()
+        });
+    }
+
+    /// Update the registry after sending a subscribe packet
+    pub fn subscribe_sent<const P: usize, const S: usize>(&mut self, packet: &Subscribe<'_, P, S>) {
+        let id = *packet.packet_identifier();
+        for (index, request) in packet.requests().enumerate() {
+            let pending = Some((id, index, request.options));
+            if let Some(existing) = self.find_mut(request.topic_name) {
+                existing.pending_subscribe = pending;
+                continue;
+            }
+
+            let Ok(topic_filter) = request.topic_name.try_into() else {
+                self.complete = false;
+                continue;
+            };
+            let subscription = Subscription {
+                topic_filter,
+                options: request.options,
+                reason_code: None,
+                pending_subscribe: pending,
+                pending_unsubscribe: None,
+            };
+            if self.subscriptions.push(subscription).is_err() {
+                self.complete = false;
+            }
+        }
+    }
+
+    /// Update the registry after receiving a suback packet. Reason codes are
+    /// matched to the topic filters of the subscribe packet with the same packet
+    /// identifier, in order.
+    pub fn suback_received<const P: usize, const S: usize>(&mut self, packet: &Suback<'_, P, S>) {
+        let id = *packet.packet_identifier();
+        self.subscriptions.retain_mut(|subscription| {
+            let Some((pending_id, index, options)) = subscription.pending_subscribe else {
+                return true;
+            };
+            if pending_id != id {
+                return true;
+            }
+            subscription.pending_subscribe = None;
+            match packet.reason_codes().nth(index) {
+                Some(reason_code) if granted_qos(reason_code).is_some() => {
+                    subscription.options = options;
+                    subscription.reason_code = Some(*reason_code);
+                    true
+                }
+                // Failed, keep any existing subscription this was replacing
+                _ => subscription.is_active(),
+            }
+        });
+    }
+
+    /// Update the registry after sending an unsubscribe packet
+    pub fn unsubscribe_sent<const P: usize, const S: usize>(
+        &mut self,
+        packet: &Unsubscribe<'_, P, S>,
+    ) {
+        let id = *packet.packet_identifier();
+        for (index, topic_filter) in packet.topic_names().enumerate() {
+            if let Some(subscription) = self.find_mut(topic_filter) {
+                subscription.pending_unsubscribe = Some((id, index));
+            }
+        }
+    }
+
+    /// Update the registry after receiving an unsuback packet. Subscriptions are
+    /// removed when the server acknowledges the unsubscription, or reports that no
+    /// such subscription existed.
+    pub fn unsuback_received<const P: usize, const S: usize>(
+        &mut self,
+        packet: &Unsuback<'_, P, S>,
+    ) {
+        let id = *packet.packet_identifier();
+        self.subscriptions.retain_mut(|subscription| {
+            let Some((pending_id, index)) = subscription.pending_unsubscribe else {
+                return true;
+            };
+            if pending_id != id {
+                return true;
+            }
+            subscription.pending_unsubscribe = None;
+            !matches!(
+                packet.reason_codes().nth(index),
+                Some(UnsubscribeReasonCode::Success | UnsubscribeReasonCode::NoSubscriptionExisted)
+            )
+        });
+    }
+
+    fn find_mut(&mut self, topic_filter: &str) -> Option<&mut Subscription<T>> {
+        self.subscriptions
+            .iter_mut()
+            .find(|s| s.topic_filter.as_str() == topic_filter)
+    }
+}
+
+impl<const N: usize, const T: usize> Default for SubscriptionRegistry<N, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::subscription_options::RetainHandling, packets::subscribe::SubscriptionRequest,
+    };
+
+    fn subscribe<'a>(id: u16, requests: &[(&'a str, SubscriptionOptions)]) -> Subscribe<'a, 0, 2> {
+        let mut other_requests = Vec::new();
+        for (topic_name, options) in requests[1..].iter() {
+            other_requests
+                .push(SubscriptionRequest::with_options(topic_name, *options))
+                .unwrap();
+        }
+        let (topic_name, options) = requests[0];
+        Subscribe::new(
+            PacketIdentifier(id),
+            SubscriptionRequest::with_options(topic_name, options),
+            other_requests,
+            Vec::new(),
+        )
+    }
+
+    fn qos0() -> SubscriptionOptions {
+        SubscriptionOptions::new(QualityOfService::Qos0)
+    }
+
+    fn qos1() -> SubscriptionOptions {
+        SubscriptionOptions::new(QualityOfService::Qos1)
+    }
+
+    fn suback(id: u16, reason_codes: &[SubscribeReasonCode]) -> Suback<'static, 0, 2> {
+        Suback::new(
+            PacketIdentifier(id),
+            reason_codes[0],
+            Vec::from_slice(&reason_codes[1..]).unwrap(),
+            Vec::new(),
+        )
+    }
+
+    #[test]
+    fn subscriptions_are_active_once_acknowledged() {
+        let mut registry = SubscriptionRegistry::<4, 16>::new();
+        registry.subscribe_sent(&subscribe(
+            1,
+            &[("a/+", qos1()), ("b/#", qos1()), ("c", qos0())],
+        ));
+        assert!(!registry.is_subscribed("a/+"));
+        assert_eq!(registry.subscriptions().count(), 0);
+
+        registry.suback_received(&suback(
+            1,
+            &[
+                SubscribeReasonCode::Success,
+                SubscribeReasonCode::NotAuthorized,
+                SubscribeReasonCode::Success,
+            ],
+        ));
+        assert!(registry.is_subscribed("a/+"));
+        assert!(!registry.is_subscribed("b/#"));
+        assert!(registry.is_subscribed("c"));
+        let subscription = registry.subscription("a/+").unwrap();
+        assert_eq!(subscription.granted_qos(), Some(QualityOfService::Qos0));
+        assert_eq!(subscription.options().maximum_qos, QualityOfService::Qos1);
+        assert!(registry.is_complete());
+    }
+
+    #[test]
+    fn resubscribing_replaces_options_and_failure_keeps_existing() {
+        let mut registry = SubscriptionRegistry::<4, 16>::new();
+        registry.subscribe_sent(&subscribe(1, &[("a", qos1())]));
+        registry.suback_received(&suback(1, &[SubscribeReasonCode::GrantedQos1]));
+
+        let options = SubscriptionOptions::new(QualityOfService::Qos0)
+            .with_retain_handling(RetainHandling::DoNotSend);
+        // Resubscribe in a different position to the existing subscription
+        registry.subscribe_sent(&subscribe(2, &[("b", qos0()), ("a", options)]));
+        registry.suback_received(&suback(
+            2,
+            &[
+                SubscribeReasonCode::NotAuthorized,
+                SubscribeReasonCode::Success,
+            ],
+        ));
+        assert_eq!(registry.subscriptions().count(), 1);
+        assert_eq!(registry.subscription("a").unwrap().options(), &options);
+
+        registry.subscribe_sent(&subscribe(3, &[("a", qos1())]));
+        registry.suback_received(&suback(3, &[SubscribeReasonCode::QuotaExceeded]));
+        assert_eq!(registry.subscription("a").unwrap().options(), &options);
+    }
+
+    #[test]
+    fn unsubscribing_removes_subscriptions() {
+        let mut registry = SubscriptionRegistry::<4, 16>::new();
+        registry.subscribe_sent(&subscribe(
+            1,
+            &[("a", qos0()), ("b", qos0()), ("c", qos0())],
+        ));
+        registry.suback_received(&suback(1, &[SubscribeReasonCode::Success; 3]));
+
+        let mut other_topics = Vec::<&str, 2>::new();
+        other_topics.push("b").unwrap();
+        other_topics.push("c").unwrap();
+        registry.unsubscribe_sent(&Unsubscribe::<'_, 0, 2>::new(
+            PacketIdentifier(2),
+            "a",
+            other_topics,
+            Vec::new(),
+        ));
+        let mut other_reason_codes = Vec::<UnsubscribeReasonCode, 2>::new();
+        other_reason_codes
+            .push(UnsubscribeReasonCode::NotAuthorized)
+            .unwrap();
+        other_reason_codes
+            .push(UnsubscribeReasonCode::NoSubscriptionExisted)
+            .unwrap();
+        registry.unsuback_received(&Unsuback::<'_, 0, 2>::new(
+            PacketIdentifier(2),
+            UnsubscribeReasonCode::Success,
+            other_reason_codes,
+            Vec::new(),
+        ));
+
+        assert!(!registry.is_subscribed("a"));
+        assert!(registry.is_subscribed("b"));
+        assert!(!registry.is_subscribed("c"));
+    }
+
+    #[test]
+    fn connecting_keeps_subscriptions_only_with_session_present() {
+        let mut registry = SubscriptionRegistry::<4, 16>::new();
+        registry.subscribe_sent(&subscribe(1, &[("a", qos0())]));
+        registry.suback_received(&suback(1, &[SubscribeReasonCode::Success]));
+        registry.subscribe_sent(&subscribe(2, &[("b", qos0())]));
+
+        // The pending subscription is dropped, but "a" is kept
+        registry.connected(true);
+        registry.suback_received(&suback(2, &[SubscribeReasonCode::Success]));
+        assert!(registry.is_subscribed("a"));
+        assert!(!registry.is_subscribed("b"));
+
+        registry.connected(false);
+        assert_eq!(registry.subscriptions().count(), 0);
+    }
+
+    #[test]
+    fn untracked_subscriptions_make_registry_incomplete() {
+        let mut registry = SubscriptionRegistry::<1, 4>::new();
+        registry.subscribe_sent(&subscribe(1, &[("a", qos0()), ("b", qos0())]));
+        assert!(!registry.is_complete());
+        registry.clear();
+        registry.subscribe_sent(&subscribe(1, &[("too/long", qos0())]));
+        assert!(!registry.is_complete());
+    }
+}
@@ -0,0 +1,375 @@
+use core::time::Duration;
+
+use crate::{client::ClientError, client_state::PendingCounts, data::packet_type::PacketType};
+
+/// A monotonic source of time, for use in timing pings and timeouts.
+/// Times are in milliseconds, from an arbitrary fixed start point, which
+/// must be the same for a [Clock] and any [Timer] used with it.
+pub trait Clock {
+    /// The current time in milliseconds
+    fn now_millis(&self) -> u64;
+}
+
+/// A shared reference to a [Clock] is also a [Clock], e.g. so that a
+/// client can use a clock that is also used elsewhere
+impl<T: Clock + ?Sized> Clock for &T {
+    fn now_millis(&self) -> u64 {
+        (**self).now_millis()
+    }
+}
+
+/// Waits until a time given by a [Clock]
+#[allow(async_fn_in_trait)]
+pub trait Timer {
+    /// Wait until the given time in milliseconds, returning immediately
+    /// if the time has already passed
+    async fn sleep_until_millis(&mut self, at_millis: u64);
+}
+
+/// Settings for a [ConnectionTimer], in milliseconds
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ConnectionTimerSettings {
+    /// The maximum time between the responses we use to check the server
+    /// is responsive (connack and pingresp), before we consider it unresponsive,
+    /// leading to a [ClientError::ReceiveTimeoutServerUnresponsive]
+    pub receive_timeout_millis: u64,
+
+    /// The time between pings sent to the server to keep the
+    /// connection alive
+    pub ping_interval_millis: u64,
+
+    /// If a ping is due while a previous ping has not had a response,
+    /// we delay this additional time before trying again
+    pub ping_retry_delay_millis: u64,
+}
+
+/// A snapshot of the health of a connection, see [ConnectionTimer::health].
+/// This can be used to distinguish a connection that is idle but healthy
+/// from one that is about to time out.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Health {
+    /// Time since the last packet was received from the server, or None
+    /// if no packet has been received yet
+    pub since_last_received: Option<Duration>,
+
+    /// Time remaining before the server will be considered unresponsive,
+    /// or None if the receive timeout is not active
+    pub until_receive_timeout: Option<Duration>,
+
+    /// Requests sent to the server that have not yet had a response
+    pub pending: PendingCounts,
+}
+
+/// Tracks when pings should be sent to the server, and whether the server
+/// has become unresponsive, based on times from a [Clock].
+/// This holds no clock itself, so can be used from both sync and async code, with
+/// the caller providing the current time to each method.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ConnectionTimer {
+    settings: ConnectionTimerSettings,
+
+    /// The end of the timeout for received packets (specifically connack and pingresp)
+    /// from the server.
+    /// This is initially None. It is initialised when a connection is started (by
+    /// sending a connect packet), and then updated whenever a relevant packet is
+    /// received.
+    /// If we ever reach this time, then the server has not replied for too long,
+    /// and is unresponsive.
+    receive_timeout_at: Option<u64>,
+
+    /// The scheduled time for sending the next ping.
+    /// This is initially None. It is initialised when a connection is made,
+    /// and then updated whenever we attempt to send a ping.
+    ping_at: Option<u64>,
+
+    /// When we sent the connect packet to start connection
+    connection_start: Option<u64>,
+
+    /// When we last received a packet from the server
+    last_received_at: Option<u64>,
+
+    /// If true, pings are postponed while other packets are being sent,
+    /// see [ConnectionTimer::with_adaptive_keep_alive]
+    adaptive_keep_alive: bool,
+}
+
+impl ConnectionTimer {
+    pub fn new(settings: ConnectionTimerSettings) -> Self {
+        Self {
+            settings,
+            receive_timeout_at: None,
+            ping_at: None,
+            connection_start: None,
+            last_received_at: None,
+            adaptive_keep_alive: false,
+        }
+    }
+
+    /// Set whether pings are postponed when other packets are sent, since any
+    /// packet keeps the connection alive [MQTT-3.1.2-20]. This reduces the
+    /// number of pings (and so e.g. radio wakeups) when publishing regularly.
+    /// Responses to our requests (puback, suback and unsuback) then also reset
+    /// the receive timeout, and pings are still sent in time to get a response
+    /// before the receive timeout, so that an unresponsive server is detected.
+    /// Disabled by default.
+    pub fn with_adaptive_keep_alive(mut self, adaptive_keep_alive: bool) -> Self {
+        self.adaptive_keep_alive = adaptive_keep_alive;
+        self
+    }
+
+    /// Record that a connect packet was sent at `now`. We are expecting a server
+    /// reply (the connack), so this starts the receive timeout. We don't start
+    /// pinging until we are connected.
+    pub fn connect_sent(&mut self, now: u64) {
+        self.connection_start = Some(now);
+        self.ping_at = None;
+        self.receive_timeout_at = Some(now + self.settings.receive_timeout_millis);
+    }
+
+    /// Record that the connection was acknowledged. Pings are scheduled starting
+    /// from when we sent the connect packet, since this is the last time we sent a packet.
+    pub fn connected(&mut self) {
+        self.ping_at = self
+            .connection_start
+            .map(|start| start + self.settings.ping_interval_millis);
+    }
+
+    /// Record that a packet of the given type was received at `now`.
+    /// We reset the receive timeout on connack and pingresp only.
+    /// We use the pings as our means of detecting any issue with packets getting
+    /// through on either outgoing or incoming channels. If we included other packet
+    /// types then even if pings were not being sent outgoing, we might not timeout
+    /// since the server could still be sending publish packets regularly.
+    /// Connack is safe to use since we'll only ever get one in response to our connect
+    /// packet, which essentially acts as our first ping.
+    /// With adaptive keep alive, responses to other requests also reset the
+    /// receive timeout, since these show packets are getting through in both
+    /// directions in the same way.
+    pub fn packet_received(&mut self, now: u64, packet_type: PacketType) {
+        self.last_received_at = Some(now);
+        let is_response = match packet_type {
+            PacketType::Connack | PacketType::Pingresp => true,
+            PacketType::Puback | PacketType::Suback | PacketType::Unsuback => {
+                self.adaptive_keep_alive
+            }
+            _ => false,
+        };
+        if is_response {
+            self.receive_timeout_at = Some(now + self.settings.receive_timeout_millis);
+        }
+    }
+
+    /// Record that a packet other than a ping was sent at `now`. With adaptive
+    /// keep alive, this postpones the next ping to a ping interval after `now`,
+    /// but no later than a ping interval before the receive timeout.
+    /// Otherwise this has no effect.
+    pub fn packet_sent(&mut self, now: u64) {
+        if !self.adaptive_keep_alive {
+            return;
+        }
+        if let Some(ping_at) = self.ping_at {
+            let mut postponed = now + self.settings.ping_interval_millis;
+            if let Some(receive_timeout_at) = self.receive_timeout_at {
+                postponed = postponed
+                    .min(receive_timeout_at.saturating_sub(self.settings.ping_interval_millis));
+            }
+            self.ping_at = Some(ping_at.max(postponed));
+        }
+    }
+
+    /// True if a ping is due at `now`
+    pub fn ping_due(&self, now: u64) -> bool {
+        self.ping_at.is_some_and(|ping_at| ping_at < now)
+    }
+
+    /// True if a ping will be due before `at`, e.g. so that a ping can be sent
+    /// early, while already awake, rather than waking again just to send it
+    pub fn ping_due_before(&self, at: u64) -> bool {
+        self.ping_at.is_some_and(|ping_at| ping_at < at)
+    }
+
+    /// Record that a ping was sent at `now`, scheduling the next one
+    pub fn ping_sent(&mut self, now: u64) {
+        self.ping_at = Some(now + self.settings.ping_interval_millis);
+    }
+
+    /// Record that a ping was due at `now`, but could not be sent since a previous
+    /// ping has not had a response, scheduling a retry
+    pub fn ping_deferred(&mut self, now: u64) {
+        self.ping_at = Some(now + self.settings.ping_retry_delay_millis);
+    }
+
+    /// Produce [ClientError::ReceiveTimeoutServerUnresponsive] if the
+    /// receive timeout has been reached at `now`
+    pub fn check_receive_timeout(&self, now: u64) -> Result<(), ClientError> {
+        match self.receive_timeout_at {
+            Some(receive_timeout_at) if receive_timeout_at < now => {
+                Err(ClientError::ReceiveTimeoutServerUnresponsive)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The time the next ping is due, if pings are active
+    pub fn ping_at(&self) -> Option<u64> {
+        self.ping_at
+    }
+
+    /// The time the server will be considered unresponsive, if the receive
+    /// timeout is active
+    pub fn receive_timeout_at(&self) -> Option<u64> {
+        self.receive_timeout_at
+    }
+
+    /// The time we last received a packet from the server, if any
+    pub fn last_received_at(&self) -> Option<u64> {
+        self.last_received_at
+    }
+
+    /// Produce a [Health] snapshot at `now`, with the `pending` requests
+    /// awaiting a response, e.g. from [ClientState::pending_counts](crate::client_state::ClientState::pending_counts)
+    pub fn health(&self, now: u64, pending: PendingCounts) -> Health {
+        Health {
+            since_last_received: self
+                .last_received_at
+                .map(|at| Duration::from_millis(now.saturating_sub(at))),
+            until_receive_timeout: self
+                .receive_timeout_at
+                .map(|at| Duration::from_millis(at.saturating_sub(now))),
+            pending,
+        }
+    }
+
+    /// The earliest time at which the timer needs attention, i.e. a ping is due
+    /// or the receive timeout would be reached
+    pub fn next_wakeup(&self) -> Option<u64> {
+        match (self.ping_at, self.receive_timeout_at) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: ConnectionTimerSettings = ConnectionTimerSettings {
+        receive_timeout_millis: 10_000,
+        ping_interval_millis: 2_000,
+        ping_retry_delay_millis: 100,
+    };
+
+    #[test]
+    fn pings_start_after_connection() {
+        let mut timer = ConnectionTimer::new(SETTINGS);
+        assert_eq!(timer.next_wakeup(), None);
+
+        timer.connect_sent(1_000);
+        assert_eq!(timer.ping_at(), None);
+        assert_eq!(timer.receive_timeout_at(), Some(11_000));
+
+        timer.packet_received(1_500, PacketType::Connack);
+        timer.connected();
+        assert_eq!(timer.ping_at(), Some(3_000));
+        assert_eq!(timer.receive_timeout_at(), Some(11_500));
+        assert_eq!(timer.next_wakeup(), Some(3_000));
+        assert_eq!(timer.last_received_at(), Some(1_500));
+
+        assert!(!timer.ping_due(3_000));
+        assert!(timer.ping_due(3_001));
+        assert!(timer.ping_due_before(3_001));
+        assert!(!timer.ping_due_before(3_000));
+        timer.ping_deferred(3_001);
+        assert_eq!(timer.ping_at(), Some(3_101));
+        timer.ping_sent(3_200);
+        assert_eq!(timer.ping_at(), Some(5_200));
+    }
+
+    #[test]
+    fn receive_timeout_is_only_reset_by_connack_and_pingresp() {
+        let mut timer = ConnectionTimer::new(SETTINGS);
+        timer.connect_sent(0);
+        timer.packet_received(5_000, PacketType::Publish);
+        assert_eq!(timer.receive_timeout_at(), Some(10_000));
+        assert_eq!(timer.check_receive_timeout(10_000), Ok(()));
+        assert_eq!(
+            timer.check_receive_timeout(10_001),
+            Err(ClientError::ReceiveTimeoutServerUnresponsive)
+        );
+
+        timer.packet_received(6_000, PacketType::Pingresp);
+        assert_eq!(timer.check_receive_timeout(10_001), Ok(()));
+    }
+
+    #[test]
+    fn adaptive_keep_alive_postpones_pings() {
+        let mut timer = ConnectionTimer::new(SETTINGS).with_adaptive_keep_alive(true);
+        timer.connect_sent(0);
+        timer.packet_received(100, PacketType::Connack);
+        timer.connected();
+        assert_eq!(timer.ping_at(), Some(2_000));
+
+        timer.packet_sent(1_500);
+        assert_eq!(timer.ping_at(), Some(3_500));
+
+        // Pings are not postponed past a ping interval before the receive timeout
+        timer.packet_sent(7_000);
+        assert_eq!(timer.ping_at(), Some(8_100));
+
+        // A puback resets the receive timeout, allowing further postponing
+        timer.packet_received(7_100, PacketType::Puback);
+        assert_eq!(timer.receive_timeout_at(), Some(17_100));
+        timer.packet_sent(7_200);
+        assert_eq!(timer.ping_at(), Some(9_200));
+    }
+
+    #[test]
+    fn health_shows_time_since_received_and_until_timeout() {
+        let mut timer = ConnectionTimer::new(SETTINGS);
+        let pending = PendingCounts {
+            pings: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            timer.health(500, pending),
+            Health {
+                since_last_received: None,
+                until_receive_timeout: None,
+                pending,
+            }
+        );
+
+        timer.connect_sent(1_000);
+        timer.packet_received(1_500, PacketType::Connack);
+        timer.packet_received(4_000, PacketType::Publish);
+        assert_eq!(
+            timer.health(4_250, pending),
+            Health {
+                since_last_received: Some(Duration::from_millis(250)),
+                until_receive_timeout: Some(Duration::from_millis(7_250)),
+                pending,
+            }
+        );
+
+        // Past the receive timeout, no time remains
+        assert_eq!(
+            timer.health(12_000, pending).until_receive_timeout,
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn pings_are_not_postponed_by_default() {
+        let mut timer = ConnectionTimer::new(SETTINGS);
+        timer.connect_sent(0);
+        timer.packet_received(100, PacketType::Connack);
+        timer.connected();
+        timer.packet_sent(1_500);
+        assert_eq!(timer.ping_at(), Some(2_000));
+        timer.packet_received(1_600, PacketType::Puback);
+        assert_eq!(timer.receive_timeout_at(), Some(10_100));
+    }
+}
@@ -1,15 +1,22 @@
+#[cfg(feature = "client-codec")]
 use core::net::Ipv4Addr;
-use std::time::Duration;
+use core::net::SocketAddr;
+use std::{string::String, time::Duration, vec::Vec};
 
 use tokio::{
     io::{self, AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
+    net::{TcpSocket, TcpStream},
+    sync::{mpsc, oneshot},
+    time::MissedTickBehavior,
 };
 
+#[cfg(feature = "client-codec")]
+use crate::client::{ClientNoQueue, ClientNoQueueOptions, EventHandler};
 use crate::{
-    client::{ClientNoQueue, Delay, EventHandler},
-    error::{PacketReadError, PacketWriteError},
+    client::{Client, ClientError, Delay},
+    data::quality_of_service::QualityOfService,
     packet_client::Connection,
+    timing::{Clock, Timer},
 };
 
 #[derive(Clone)]
@@ -21,6 +28,106 @@ impl Delay for TokioDelay {
     }
 }
 
+/// A [Clock] and [Timer] using tokio time, with times measured from when
+/// this was created
+#[derive(Clone)]
+pub struct TokioTime {
+    start: tokio::time::Instant,
+}
+
+impl TokioTime {
+    pub fn new() -> Self {
+        Self {
+            start: tokio::time::Instant::now(),
+        }
+    }
+}
+
+impl Default for TokioTime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for TokioTime {
+    fn now_millis(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+}
+
+impl Timer for TokioTime {
+    async fn sleep_until_millis(&mut self, at_millis: u64) {
+        tokio::time::sleep_until(self.start + Duration::from_millis(at_millis)).await;
+    }
+}
+
+/// Socket options for a [ConnectionTcpStream], see [ConnectionTcpStream::connect].
+/// By default all options are left at the operating system defaults.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct TcpOptions {
+    nodelay: Option<bool>,
+    keepalive: Option<bool>,
+    linger: Option<Option<Duration>>,
+}
+
+impl TcpOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set `TCP_NODELAY`. When true, small packets are sent immediately rather
+    /// than being delayed to combine them into larger segments (Nagle's algorithm),
+    /// reducing latency at the cost of more segments being sent.
+    pub fn with_nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Set `SO_KEEPALIVE`. When true, the operating system sends TCP keepalive
+    /// probes on an idle connection, using its default intervals. This detects
+    /// dead connections beneath the MQTT keep alive.
+    pub fn with_keepalive(mut self, keepalive: bool) -> Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Set `SO_LINGER`. Note that a linger duration other than `Some(Duration::ZERO)`
+    /// can cause the thread dropping the connection to block until data is sent,
+    /// or the duration elapses.
+    pub fn with_linger(mut self, linger: Option<Duration>) -> Self {
+        self.linger = Some(linger);
+        self
+    }
+
+    pub fn nodelay(&self) -> Option<bool> {
+        self.nodelay
+    }
+
+    pub fn keepalive(&self) -> Option<bool> {
+        self.keepalive
+    }
+
+    pub fn linger(&self) -> Option<Option<Duration>> {
+        self.linger
+    }
+
+    fn apply(&self, socket: &TcpSocket) -> io::Result<()> {
+        if let Some(nodelay) = self.nodelay {
+            socket.set_nodelay(nodelay)?;
+        }
+        if let Some(keepalive) = self.keepalive {
+            socket.set_keepalive(keepalive)?;
+        }
+        if let Some(linger) = self.linger {
+            // Deprecated since linger can block on drop, this is documented
+            // on [TcpOptions::with_linger]
+            #[allow(deprecated)]
+            socket.set_linger(linger)?;
+        }
+        Ok(())
+    }
+}
+
 pub struct ConnectionTcpStream {
     inner: TcpStream,
 }
@@ -31,6 +138,30 @@ impl ConnectionTcpStream {
         ConnectionTcpStream { inner }
     }
 
+    /// Connect to the given address, with the given socket options
+    pub async fn connect(addr: SocketAddr, options: TcpOptions) -> io::Result<Self> {
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        options.apply(&socket)?;
+        let inner = socket.connect(addr).await?;
+        Ok(Self::new(inner))
+    }
+
+    /// Set `TCP_NODELAY` on the stream, see [TcpOptions::with_nodelay]
+    pub fn with_nodelay(self, nodelay: bool) -> io::Result<Self> {
+        self.inner.set_nodelay(nodelay)?;
+        Ok(self)
+    }
+
+    /// Set `SO_LINGER` on the stream, see [TcpOptions::with_linger]
+    pub fn with_linger(self, linger: Option<Duration>) -> io::Result<Self> {
+        #[allow(deprecated)]
+        self.inner.set_linger(linger)?;
+        Ok(self)
+    }
+
     /// Consume the adapter, returning the inner object.
     pub fn into_inner(self) -> TcpStream {
         self.inner
@@ -48,28 +179,28 @@ impl ConnectionTcpStream {
 }
 
 impl Connection for ConnectionTcpStream {
-    async fn send(&mut self, buf: &[u8]) -> Result<(), PacketWriteError> {
-        self.inner
-            .write_all(buf)
-            .await
-            .map_err(|_| PacketWriteError::ConnectionSend)
+    type Error = io::Error;
+
+    async fn send(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.inner.write_all(buf).await
     }
 
-    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), PacketReadError> {
-        self.inner
-            .read_exact(buf)
-            .await
-            .map_err(|_| PacketReadError::ConnectionReceive)?;
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush().await
+    }
+
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.inner.read_exact(buf).await?;
         Ok(())
     }
 
-    async fn receive_if_ready(&mut self, buf: &mut [u8]) -> Result<bool, PacketReadError> {
+    async fn receive_if_ready(&mut self, buf: &mut [u8]) -> Result<bool, Self::Error> {
         // Try to read data, this may fail with `WouldBlock`
         // if no data is available
         match self.inner.try_read(buf) {
             // If length is 0, the stream's read half is closed, this is a read error
             // since no data will be read in future
-            Ok(0) => Err(PacketReadError::ConnectionReceive),
+            Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
 
             // We have read some bytes, we may need to perform more reads to finish
             Ok(n) => {
@@ -85,18 +216,43 @@ impl Connection for ConnectionTcpStream {
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
 
             // Any other error is a real receive error
-            Err(_e) => Err(PacketReadError::ConnectionReceive),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn receive_ready(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.inner.try_read(buf) {
+            // The stream's read half is closed, no data will be read in future
+            Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => Ok(n),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn receive_available(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.inner.read(buf).await {
+            // The stream's read half is closed, no data will be read in future
+            Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+            result => result,
         }
     }
 }
 
+#[cfg(feature = "client-codec")]
 pub async fn client_tcp<F, const P: usize>(
     ip: Ipv4Addr,
     port: u16,
-    timeout_millis: u32,
+    options: ClientNoQueueOptions,
     buf: &mut [u8],
     event_handler: F,
-) -> ClientNoQueue<'_, ConnectionTcpStream, TokioDelay, F, P>
+) -> ClientNoQueue<'_, ConnectionTcpStream, TokioDelay, TokioTime, F, P>
 where
     F: EventHandler<P>,
 {
@@ -105,5 +261,679 @@ where
     let connection = ConnectionTcpStream::new(tcp_stream);
 
     let delay = TokioDelay;
-    ClientNoQueue::new(connection, buf, delay, timeout_millis, event_handler)
+    ClientNoQueue::new(
+        connection,
+        buf,
+        delay,
+        TokioTime::new(),
+        options,
+        event_handler,
+    )
+}
+
+/// The default interval between polls of a client by a [ClientRunner]
+pub const CLIENT_RUNNER_POLL_INTERVAL_DEFAULT: Duration = Duration::from_millis(10);
+
+/// A request from a [ClientHandle] to its [ClientRunner]
+enum Command {
+    Publish {
+        topic_name: String,
+        payload: Vec<u8>,
+        qos: QualityOfService,
+        retain: bool,
+    },
+    Subscribe {
+        topic_name: String,
+        maximum_qos: QualityOfService,
+    },
+    Unsubscribe {
+        topic_name: String,
+    },
+    SendPing,
+    Disconnect,
+}
+
+/// Create a [ClientHandle] that can be cloned and used from any number of tasks,
+/// and the [ClientRunner] that owns `client` and performs their requests.
+/// Up to `capacity` requests can be queued for the runner before callers wait.
+pub fn client_handle<C>(client: C, capacity: usize) -> (ClientHandle, ClientRunner<C>) {
+    let (commands, receiver) = mpsc::channel(capacity);
+    let runner = ClientRunner {
+        client,
+        commands: receiver,
+        poll_interval: CLIENT_RUNNER_POLL_INTERVAL_DEFAULT,
+    };
+    (ClientHandle { commands }, runner)
+}
+
+/// A handle to a connected [Client] that is owned by a [ClientRunner], so
+/// that it can be shared between tasks, e.g. to publish from request handlers
+/// in a web service. The handle is `Send + Sync` and cheap to clone, requests
+/// are sent to the runner over a channel and performed in order, each call
+/// completing when the client has performed the request (e.g. for a qos 1
+/// publish, once the server has acknowledged it).
+/// If the runner has stopped, requests fail with [ClientError::ClientStopped].
+#[derive(Debug, Clone)]
+pub struct ClientHandle {
+    commands: mpsc::Sender<(Command, oneshot::Sender<Result<(), ClientError>>)>,
+}
+
+impl ClientHandle {
+    async fn request(&self, command: Command) -> Result<(), ClientError> {
+        let (response, result) = oneshot::channel();
+        self.commands
+            .send((command, response))
+            .await
+            .map_err(|_| ClientError::ClientStopped)?;
+        result.await.map_err(|_| ClientError::ClientStopped)?
+    }
+
+    /// Publish a message, see [Client::publish]
+    pub async fn publish(
+        &self,
+        topic_name: &str,
+        payload: &[u8],
+        qos: QualityOfService,
+        retain: bool,
+    ) -> Result<(), ClientError> {
+        self.request(Command::Publish {
+            topic_name: topic_name.into(),
+            payload: payload.into(),
+            qos,
+            retain,
+        })
+        .await
+    }
+
+    /// Subscribe to a topic, see [Client::subscribe]
+    pub async fn subscribe(
+        &self,
+        topic_name: &str,
+        maximum_qos: QualityOfService,
+    ) -> Result<(), ClientError> {
+        self.request(Command::Subscribe {
+            topic_name: topic_name.into(),
+            maximum_qos,
+        })
+        .await
+    }
+
+    /// Unsubscribe from a topic, see [Client::unsubscribe]
+    pub async fn unsubscribe(&self, topic_name: &str) -> Result<(), ClientError> {
+        self.request(Command::Unsubscribe {
+            topic_name: topic_name.into(),
+        })
+        .await
+    }
+
+    /// Send a ping, see [Client::send_ping]
+    pub async fn send_ping(&self) -> Result<(), ClientError> {
+        self.request(Command::SendPing).await
+    }
+
+    /// Disconnect the client, see [Client::disconnect]. The runner then stops,
+    /// so later requests fail with [ClientError::ClientStopped].
+    pub async fn disconnect(&self) -> Result<(), ClientError> {
+        self.request(Command::Disconnect).await
+    }
+}
+
+/// Owns a [Client] and performs requests from its [ClientHandle]s, polling
+/// the client for received packets between requests, see [client_handle].
+/// Since clients hold a reference to their buffer, and may hold references to
+/// other non-`Send` state (e.g. an [Authenticator](crate::authenticator::Authenticator)),
+/// the runner will often not be `Send`, in which case it should be run in the
+/// task that created the client, e.g. by awaiting [ClientRunner::run] in `main`
+/// after spawning tasks using the handle, or using `tokio::select!` to run it
+/// alongside other work.
+pub struct ClientRunner<C> {
+    client: C,
+    commands: mpsc::Receiver<(Command, oneshot::Sender<Result<(), ClientError>>)>,
+    poll_interval: Duration,
+}
+
+impl<C> ClientRunner<C> {
+    /// Set the interval between polls of the client for received packets,
+    /// defaults to [CLIENT_RUNNER_POLL_INTERVAL_DEFAULT]
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Run the client, performing requests from handles and polling for received
+    /// packets, until a handle requests a disconnect, or all handles are dropped,
+    /// producing `Ok(())`. An error polling the client stops the runner, producing
+    /// that error. An error performing a request is returned to the handle that
+    /// made the request, and the runner continues.
+    /// While no requests are sent, the runner pings the server every half
+    /// [Client::keep_alive] to keep the connection alive. If a ping has still
+    /// not been answered when the next is due, the runner stops with
+    /// [ClientError::ReceiveTimeoutServerUnresponsive].
+    pub async fn run<'a>(mut self) -> Result<(), ClientError>
+    where
+        C: Client<'a>,
+    {
+        let mut poll = tokio::time::interval(self.poll_interval);
+        poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut last_sent = tokio::time::Instant::now();
+
+        loop {
+            let ping_at = self
+                .client
+                .keep_alive()
+                .filter(|keep_alive| *keep_alive > 0)
+                .map(|keep_alive| last_sent + Duration::from_millis(keep_alive as u64 * 500));
+
+            tokio::select! {
+                command = self.commands.recv() => {
+                    let Some((command, response)) = command else {
+                        return Ok(());
+                    };
+                    let disconnect = matches!(command, Command::Disconnect);
+                    let result = perform(&mut self.client, command).await;
+                    last_sent = tokio::time::Instant::now();
+                    let _ = response.send(result);
+                    if disconnect {
+                        return Ok(());
+                    }
+                }
+                _ = poll.tick() => {
+                    while self.client.poll(false).await? {}
+                }
+                _ = sleep_until(ping_at) => {
+                    if self.client.pending_ping_count() > 0 {
+                        return Err(ClientError::ReceiveTimeoutServerUnresponsive);
+                    }
+                    self.client.send_ping().await?;
+                    last_sent = tokio::time::Instant::now();
+                }
+            }
+        }
+    }
+
+    /// Consume the runner, returning the client
+    pub fn into_inner(self) -> C {
+        self.client
+    }
+}
+
+/// Sleep until `at`, or forever if it is None
+async fn sleep_until(at: Option<tokio::time::Instant>) {
+    match at {
+        Some(at) => tokio::time::sleep_until(at).await,
+        None => core::future::pending().await,
+    }
+}
+
+async fn perform<'a, C: Client<'a>>(client: &mut C, command: Command) -> Result<(), ClientError> {
+    match command {
+        Command::Publish {
+            topic_name,
+            payload,
+            qos,
+            retain,
+        } => client.publish(&topic_name, &payload, qos, retain).await,
+        Command::Subscribe {
+            topic_name,
+            maximum_qos,
+        } => client.subscribe(&topic_name, maximum_qos).await,
+        Command::Unsubscribe { topic_name } => client.unsubscribe(&topic_name).await,
+        Command::SendPing => client.send_ping().await,
+        Command::Disconnect => client.disconnect().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{
+        client::{ClientReceivedEvent, ConnectionSettings, EventHandlerError},
+        client_state::ClientStateNoQueue,
+        managed_client::ManagedClient,
+    };
+
+    struct IgnoreEvents;
+
+    impl EventHandler<16> for IgnoreEvents {
+        async fn handle_event(
+            &mut self,
+            _event: ClientReceivedEvent<'_, 16>,
+        ) -> Result<(), EventHandlerError> {
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct CountMessages(usize);
+
+    impl EventHandler<16> for CountMessages {
+        async fn handle_event(
+            &mut self,
+            event: ClientReceivedEvent<'_, 16>,
+        ) -> Result<(), EventHandlerError> {
+            if let ClientReceivedEvent::ApplicationMessage(_) = event {
+                self.0 += 1;
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn handle_publishes_from_another_task() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut header = [0u8; 2];
+
+            // Connect, respond with Connack
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header[0], 0x10);
+            let mut rest = std::vec![0u8; header[1] as usize];
+            stream.read_exact(&mut rest).await.unwrap();
+            stream
+                .write_all(&[0x20, 0x03, 0x00, 0x00, 0x00])
+                .await
+                .unwrap();
+
+            // Qos 0 publish to "a" with payload "b"
+            let mut publish = [0u8; 7];
+            stream.read_exact(&mut publish).await.unwrap();
+            assert_eq!(publish, [0x30, 0x05, 0x00, 0x01, b'a', 0x00, b'b']);
+
+            // Disconnect
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header, [0xe0, 0x00]);
+        });
+
+        let connection = ConnectionTcpStream::connect(addr, TcpOptions::new())
+            .await
+            .unwrap();
+        let mut buf = [0; 1024];
+        let mut client: ClientNoQueue<'_, _, _, _, _, 16> = ClientNoQueue::new(
+            connection,
+            &mut buf,
+            TokioDelay,
+            TokioTime::new(),
+            ClientNoQueueOptions::new(5000),
+            IgnoreEvents,
+        );
+        client
+            .connect(&ConnectionSettings::unauthenticated("shared"))
+            .await
+            .unwrap();
+
+        let (handle, runner) = client_handle(client, 4);
+        let publisher = tokio::spawn(async move {
+            handle
+                .publish("a", b"b", QualityOfService::Qos0, false)
+                .await
+                .unwrap();
+            handle.disconnect().await.unwrap();
+            assert_eq!(handle.send_ping().await, Err(ClientError::ClientStopped));
+        });
+
+        runner.run().await.unwrap();
+        publisher.await.unwrap();
+        server.await.unwrap();
+    }
+
+    /// Delays for ten times as long as requested
+    struct SlowDelay;
+
+    impl Delay for SlowDelay {
+        async fn delay_us(&mut self, us: u32) {
+            tokio::time::sleep(Duration::from_micros(us as u64 * 10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn ack_timeout_measured_with_clock() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut header = [0u8; 2];
+
+            // Connect, respond with Connack
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header[0], 0x10);
+            let mut rest = std::vec![0u8; header[1] as usize];
+            stream.read_exact(&mut rest).await.unwrap();
+            stream
+                .write_all(&[0x20, 0x03, 0x00, 0x00, 0x00])
+                .await
+                .unwrap();
+
+            // Qos 1 publish, never acknowledged
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header[0], 0x32);
+            let mut rest = std::vec![0u8; header[1] as usize];
+            stream.read_exact(&mut rest).await.unwrap();
+            stream
+        });
+
+        let connection = ConnectionTcpStream::connect(addr, TcpOptions::new())
+            .await
+            .unwrap();
+        let mut buf = [0; 1024];
+        // Adding up poll intervals would take ten times the ack timeout
+        let mut client: ManagedClient<'_, _, _, _, ClientStateNoQueue, _, 16> = ManagedClient::new(
+            connection,
+            &mut buf,
+            SlowDelay,
+            TokioTime::new(),
+            ClientNoQueueOptions::new(5000),
+            IgnoreEvents,
+        );
+        client
+            .connect(&ConnectionSettings::unauthenticated("clock"))
+            .await
+            .unwrap();
+
+        let start = tokio::time::Instant::now();
+        let result = client
+            .publish_with_ack_timeout(
+                "a",
+                b"b",
+                QualityOfService::Qos1,
+                false,
+                heapless::Vec::<_, 0>::new(),
+                200,
+            )
+            .await;
+        let elapsed = start.elapsed();
+        assert_eq!(result, Err(ClientError::TimeoutOnResponsePacket));
+        assert!(elapsed >= Duration::from_millis(200));
+        assert!(elapsed < Duration::from_millis(1000));
+
+        drop(server.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn runner_pings_when_idle() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (pinged, ping_received) = oneshot::channel();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut header = [0u8; 2];
+
+            // Connect, respond with Connack with a server keep alive of 1 second
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header[0], 0x10);
+            let mut rest = std::vec![0u8; header[1] as usize];
+            stream.read_exact(&mut rest).await.unwrap();
+            stream
+                .write_all(&[0x20, 0x06, 0x00, 0x00, 0x03, 0x13, 0x00, 0x01])
+                .await
+                .unwrap();
+
+            // Pingreq, respond with Pingresp
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header, [0xc0, 0x00]);
+            stream.write_all(&[0xd0, 0x00]).await.unwrap();
+            pinged.send(()).unwrap();
+
+            // Disconnect
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header, [0xe0, 0x00]);
+        });
+
+        let connection = ConnectionTcpStream::connect(addr, TcpOptions::new())
+            .await
+            .unwrap();
+        let mut buf = [0; 1024];
+        let mut client: ClientNoQueue<'_, _, _, _, _, 16> = ClientNoQueue::new(
+            connection,
+            &mut buf,
+            TokioDelay,
+            TokioTime::new(),
+            ClientNoQueueOptions::new(5000),
+            IgnoreEvents,
+        );
+        client
+            .connect(&ConnectionSettings::unauthenticated("idle"))
+            .await
+            .unwrap();
+        assert_eq!(client.keep_alive(), Some(1));
+
+        // The runner is idle until the server has received a ping
+        let (handle, runner) = client_handle(client, 4);
+        let disconnecter = tokio::spawn(async move {
+            ping_received.await.unwrap();
+            handle.disconnect().await.unwrap();
+        });
+
+        tokio::time::timeout(Duration::from_secs(5), runner.run())
+            .await
+            .expect("Runner should ping while idle")
+            .unwrap();
+        disconnecter.await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn poll_event_returns_message_and_acknowledges_on_next_poll() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut header = [0u8; 2];
+
+            // Connect, respond with Connack
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header[0], 0x10);
+            let mut rest = std::vec![0u8; header[1] as usize];
+            stream.read_exact(&mut rest).await.unwrap();
+            stream
+                .write_all(&[0x20, 0x03, 0x00, 0x00, 0x00])
+                .await
+                .unwrap();
+
+            // Qos 1 publish to "a" with payload "b", packet identifier 1
+            stream
+                .write_all(&[0x32, 0x07, 0x00, 0x01, b'a', 0x00, 0x01, 0x00, b'b'])
+                .await
+                .unwrap();
+
+            // Puback for packet identifier 1
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header[0], 0x40);
+            let mut rest = std::vec![0u8; header[1] as usize];
+            stream.read_exact(&mut rest).await.unwrap();
+            assert_eq!(rest[0..2], [0x00, 0x01]);
+
+            // Disconnect
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header, [0xe0, 0x00]);
+        });
+
+        let connection = ConnectionTcpStream::connect(addr, TcpOptions::new())
+            .await
+            .unwrap();
+        let mut buf = [0; 1024];
+        let mut client: ClientNoQueue<'_, _, _, _, _, 16> = ClientNoQueue::new(
+            connection,
+            &mut buf,
+            TokioDelay,
+            TokioTime::new(),
+            ClientNoQueueOptions::new(5000),
+            IgnoreEvents,
+        );
+        client
+            .connect(&ConnectionSettings::unauthenticated("pull"))
+            .await
+            .unwrap();
+
+        match client.poll_event(true).await.unwrap() {
+            Some(ClientReceivedEvent::ApplicationMessage(message)) => {
+                assert_eq!(message.topic_name, "a");
+                assert_eq!(message.payload, b"b");
+                assert_eq!(message.qos, QualityOfService::Qos1);
+            }
+            event => panic!("Expected application message, got {:?}", event),
+        }
+
+        // Sends the deferred Puback
+        assert_eq!(client.poll_event(false).await.unwrap(), None);
+        client.disconnect().await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn duplicate_detected_after_reconnect_with_session_present() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let mut header = [0u8; 2];
+
+            // First connection, with clean start
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header[0], 0x10);
+            let mut rest = std::vec![0u8; header[1] as usize];
+            stream.read_exact(&mut rest).await.unwrap();
+            assert_eq!(rest[7] & 0x02, 0x02);
+            stream
+                .write_all(&[0x20, 0x03, 0x00, 0x00, 0x00])
+                .await
+                .unwrap();
+
+            // Qos 1 publish to "a" with payload "b", packet identifier 1
+            stream
+                .write_all(&[0x32, 0x07, 0x00, 0x01, b'a', 0x00, 0x01, 0x00, b'b'])
+                .await
+                .unwrap();
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header[0], 0x40);
+            let mut rest = std::vec![0u8; header[1] as usize];
+            stream.read_exact(&mut rest).await.unwrap();
+
+            // Drop the connection, as if the Puback was lost
+            drop(stream);
+
+            // Second connection, without clean start, resuming the session
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header[0], 0x10);
+            let mut rest = std::vec![0u8; header[1] as usize];
+            stream.read_exact(&mut rest).await.unwrap();
+            assert_eq!(rest[7] & 0x02, 0x00);
+            stream
+                .write_all(&[0x20, 0x03, 0x01, 0x00, 0x00])
+                .await
+                .unwrap();
+
+            // Redeliver the publish with the DUP flag set
+            stream
+                .write_all(&[0x3a, 0x07, 0x00, 0x01, b'a', 0x00, 0x01, 0x00, b'b'])
+                .await
+                .unwrap();
+
+            // Still acknowledged
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header[0], 0x40);
+            let mut rest = std::vec![0u8; header[1] as usize];
+            stream.read_exact(&mut rest).await.unwrap();
+            assert_eq!(rest[0..2], [0x00, 0x01]);
+
+            // Disconnect
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header, [0xe0, 0x00]);
+        });
+
+        let settings =
+            ConnectionSettings::unauthenticated("resume").with_session_expiry_interval(60);
+
+        let connection = ConnectionTcpStream::connect(addr, TcpOptions::new())
+            .await
+            .unwrap();
+        let mut buf = [0; 1024];
+        let mut client: ClientNoQueue<'_, _, _, _, _, 16> = ClientNoQueue::new(
+            connection,
+            &mut buf,
+            TokioDelay,
+            TokioTime::new(),
+            ClientNoQueueOptions::new(5000),
+            CountMessages::default(),
+        )
+        .with_duplicate_detection(true);
+        client.connect(&settings).await.unwrap();
+        assert!(client.poll(true).await.unwrap());
+        assert_eq!(client.event_handler().0, 1);
+        let recent_publishes = client.recent_publishes().clone();
+        drop(client);
+
+        let connection = ConnectionTcpStream::connect(addr, TcpOptions::new())
+            .await
+            .unwrap();
+        let mut buf = [0; 1024];
+        let mut client: ClientNoQueue<'_, _, _, _, _, 16> = ClientNoQueue::new(
+            connection,
+            &mut buf,
+            TokioDelay,
+            TokioTime::new(),
+            ClientNoQueueOptions::new(5000),
+            CountMessages::default(),
+        )
+        .with_duplicate_detection(true)
+        .with_recent_publishes(recent_publishes);
+        client
+            .connect(&settings.with_clean_start(false))
+            .await
+            .unwrap();
+
+        // The redelivered message is acknowledged, but not handled
+        assert!(client.poll(true).await.unwrap());
+        assert_eq!(client.event_handler().0, 0);
+        client.disconnect().await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn tcp_options_are_applied_to_socket() {
+        let options = TcpOptions::new()
+            .with_nodelay(true)
+            .with_keepalive(true)
+            .with_linger(Some(Duration::ZERO));
+
+        // Keepalive is only readable from the socket, before connecting
+        let socket = TcpSocket::new_v4().unwrap();
+        options.apply(&socket).unwrap();
+        assert!(socket.nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(socket.linger().unwrap(), Some(Duration::ZERO));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+
+        let connection = ConnectionTcpStream::connect(addr, options).await.unwrap();
+        assert!(connection.inner().nodelay().unwrap());
+        assert_eq!(connection.inner().linger().unwrap(), Some(Duration::ZERO));
+        accept.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn default_tcp_options_leave_socket_unchanged() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap() });
+
+        let connection = ConnectionTcpStream::connect(addr, TcpOptions::new())
+            .await
+            .unwrap();
+        assert!(!connection.inner().nodelay().unwrap());
+        let connection = connection.with_nodelay(true).unwrap();
+        assert!(connection.inner().nodelay().unwrap());
+        accept.await.unwrap();
+    }
 }
@@ -0,0 +1,250 @@
+use heapless::{String, Vec};
+
+use crate::{
+    client::{ClientReceivedEvent, EventHandler, EventHandlerError},
+    data::topic_filter::topic_filter_matches,
+    packets::publish::ApplicationMessage,
+};
+
+/// The last payload received on a topic, held by a [ValueCache], with a
+/// topic name of up to `T` bytes and payload of up to `N` bytes
+#[derive(Debug, PartialEq, Clone)]
+pub struct CachedValue<const T: usize, const N: usize> {
+    pub topic_name: String<T>,
+    pub payload: Vec<u8, N>,
+    /// True if the value was received as a retained message, i.e. it was
+    /// published before the subscription was made
+    pub retain: bool,
+}
+
+/// Caches the last payload received on each topic name matching any of up to
+/// `F` topic filters, for up to `E` topic names, so that the current state of
+/// a few topics can be read with [ValueCache::last_value] without the
+/// application keeping its own copies.
+///
+/// A message that can't be cached, since its topic name or payload is too
+/// large or `E` topic names are already cached, produces
+/// [EventHandlerError::Overflow]. A retained message with an empty payload
+/// clears the retained message on the server, so it also removes the cached
+/// value. Events other than application messages are ignored - to handle
+/// these, implement an [EventHandler] that uses [ValueCache::update] for
+/// application messages.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ValueCache<'a, const F: usize, const E: usize, const T: usize, const N: usize> {
+    topic_filters: Vec<&'a str, F>,
+    values: Vec<CachedValue<T, N>, E>,
+}
+
+impl<'a, const F: usize, const E: usize, const T: usize, const N: usize>
+    ValueCache<'a, F, E, T, N>
+{
+    pub fn new() -> Self {
+        Self {
+            topic_filters: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+
+    /// Cache values for topic names matching `topic_filter`.
+    /// If the cache already has `F` filters, the filter is returned as an error.
+    pub fn add_filter(&mut self, topic_filter: &'a str) -> Result<(), &'a str> {
+        self.topic_filters.push(topic_filter)
+    }
+
+    pub fn topic_filters(&self) -> &[&'a str] {
+        &self.topic_filters
+    }
+
+    /// True if values are cached for the topic name
+    pub fn matches(&self, topic_name: &str) -> bool {
+        self.topic_filters
+            .iter()
+            .any(|topic_filter| topic_filter_matches(topic_filter, topic_name))
+    }
+
+    /// The last payload received on the topic name, if any
+    pub fn last_value(&self, topic_name: &str) -> Option<&[u8]> {
+        self.get(topic_name).map(|value| value.payload.as_slice())
+    }
+
+    /// The last value received on the topic name, if any
+    pub fn get(&self, topic_name: &str) -> Option<&CachedValue<T, N>> {
+        self.values
+            .iter()
+            .find(|value| value.topic_name == topic_name)
+    }
+
+    /// The cached values, in the order their topic names were first received
+    pub fn values(&self) -> &[CachedValue<T, N>] {
+        &self.values
+    }
+
+    /// Remove the cached value for the topic name, returning it if present
+    pub fn remove(&mut self, topic_name: &str) -> Option<CachedValue<T, N>> {
+        let index = self
+            .values
+            .iter()
+            .position(|value| value.topic_name == topic_name)?;
+        Some(self.values.remove(index))
+    }
+
+    /// Remove all cached values, e.g. when reconnecting without a session,
+    /// so that only values received on the new connection are kept
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Cache the payload of an application message, if its topic name matches
+    /// a filter. On success, returns true if the topic name matched, false otherwise.
+    pub fn update<const P: usize>(
+        &mut self,
+        message: &ApplicationMessage<'_, P>,
+    ) -> Result<bool, EventHandlerError> {
+        if !self.matches(message.topic_name) {
+            return Ok(false);
+        }
+
+        if message.retain && message.payload.is_empty() {
+            self.remove(message.topic_name);
+            return Ok(true);
+        }
+
+        let payload = Vec::from_slice(message.payload).map_err(|_| EventHandlerError::Overflow)?;
+        match self
+            .values
+            .iter_mut()
+            .find(|value| value.topic_name == message.topic_name)
+        {
+            Some(value) => {
+                value.payload = payload;
+                value.retain = message.retain;
+            }
+            None => {
+                let value = CachedValue {
+                    topic_name: message
+                        .topic_name
+                        .try_into()
+                        .map_err(|_| EventHandlerError::Overflow)?,
+                    payload,
+                    retain: message.retain,
+                };
+                self.values
+                    .push(value)
+                    .map_err(|_| EventHandlerError::Overflow)?;
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<const F: usize, const E: usize, const T: usize, const N: usize> Default
+    for ValueCache<'_, F, E, T, N>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const F: usize, const E: usize, const T: usize, const N: usize, const P: usize> EventHandler<P>
+    for ValueCache<'_, F, E, T, N>
+{
+    async fn handle_event(
+        &mut self,
+        event: ClientReceivedEvent<'_, P>,
+    ) -> Result<(), EventHandlerError> {
+        if let ClientReceivedEvent::ApplicationMessage(message) = event {
+            self.update(&message)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client_state::Acknowledgement, data::quality_of_service::QualityOfService};
+
+    fn message<'a>(
+        topic_name: &'a str,
+        payload: &'a [u8],
+        retain: bool,
+    ) -> ClientReceivedEvent<'a, 0> {
+        ClientReceivedEvent::ApplicationMessage(ApplicationMessage {
+            topic_name,
+            payload,
+            qos: QualityOfService::Qos0,
+            retain,
+            properties: Vec::new(),
+            encoded_properties: &[],
+        })
+    }
+
+    #[tokio::test]
+    async fn last_value_is_cached_for_matching_topics() {
+        let mut cache = ValueCache::<'_, 2, 2, 16, 8>::new();
+        assert!(cache.add_filter("sensors/+").is_ok());
+        assert!(cache.add_filter("mode").is_ok());
+        assert_eq!(cache.add_filter("other"), Err("other"));
+
+        cache
+            .handle_event(message("sensors/a", b"1", true))
+            .await
+            .unwrap();
+        cache
+            .handle_event(message("sensors/a", b"2", false))
+            .await
+            .unwrap();
+        cache
+            .handle_event(message("unmatched", b"3", false))
+            .await
+            .unwrap();
+        cache
+            .handle_event(ClientReceivedEvent::<'_, 0>::Ack(Acknowledgement::Pingresp))
+            .await
+            .unwrap();
+        cache
+            .handle_event(message("mode", b"auto", true))
+            .await
+            .unwrap();
+
+        assert_eq!(cache.last_value("sensors/a"), Some(b"2".as_slice()));
+        assert_eq!(cache.get("sensors/a").map(|v| v.retain), Some(false));
+        assert_eq!(cache.last_value("mode"), Some(b"auto".as_slice()));
+        assert_eq!(cache.last_value("unmatched"), None);
+        assert_eq!(cache.len(), 2);
+
+        // A retained message with an empty payload removes the value
+        cache
+            .handle_event(message("mode", b"", true))
+            .await
+            .unwrap();
+        assert_eq!(cache.last_value("mode"), None);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn values_that_do_not_fit_overflow() {
+        let mut cache = ValueCache::<'_, 1, 1, 16, 4>::new();
+        assert!(cache.add_filter("#").is_ok());
+
+        assert_eq!(
+            cache.handle_event(message("a", b"too long", false)).await,
+            Err(EventHandlerError::Overflow)
+        );
+        cache.handle_event(message("a", b"1", false)).await.unwrap();
+        assert_eq!(
+            cache.handle_event(message("b", b"1", false)).await,
+            Err(EventHandlerError::Overflow)
+        );
+        assert_eq!(cache.last_value("a"), Some(b"1".as_slice()));
+        assert_eq!(cache.last_value("b"), None);
+    }
+}
@@ -0,0 +1,20 @@
+/// Notified by a client each time it successfully processes a packet received
+/// from the server, e.g. to feed a hardware watchdog only while the MQTT
+/// connection is actually working, rather than whenever the task loop runs.
+/// Note that with no traffic from the server, only ping responses are
+/// received, so the watchdog timeout should be longer than the ping interval.
+/// This is implemented for closures taking no arguments.
+pub trait Watchdog {
+    /// Called after a received packet has been handled without error,
+    /// including sending any response to the server
+    fn feed(&mut self);
+}
+
+impl<F> Watchdog for F
+where
+    F: FnMut(),
+{
+    fn feed(&mut self) {
+        self()
+    }
+}
@@ -1,5 +1,8 @@
 use mountain_mqtt::{
-    client::{Client, ClientReceivedEvent, ConnectionSettings, EventHandler, EventHandlerError},
+    client::{
+        Client, ClientNoQueueOptions, ClientReceivedEvent, ConnectionSettings, EventHandler,
+        EventHandlerError,
+    },
     data::quality_of_service::QualityOfService,
     tokio::client_tcp,
 };
@@ -31,7 +34,7 @@ impl<const P: usize> EventHandler<P> for SenderEventHandler {
 async fn client_connect_subscribe_and_publish() {
     let ip = core::net::Ipv4Addr::new(127, 0, 0, 1);
     let port = 1883;
-    let timeout_millis = 5000;
+    let options = ClientNoQueueOptions::new(5000);
     let mut buf = [0; 1024];
 
     let (message_tx, mut message_rx) = mpsc::channel(32);
@@ -39,7 +42,7 @@ async fn client_connect_subscribe_and_publish() {
     let handler = SenderEventHandler { sender: message_tx };
 
     let mut client =
-        client_tcp::<SenderEventHandler, 16>(ip, port, timeout_millis, &mut buf, handler).await;
+        client_tcp::<SenderEventHandler, 16>(ip, port, options, &mut buf, handler).await;
 
     const CLIENT_ID: &str = "mountain-mqtt-test-client-client_connect_subscribe_and_publish";
     const TOPIC_NAME: &str = "mountain-mqtt-test-topic-client_connect_subscribe_and_publish";